@@ -0,0 +1,240 @@
+//! Shared fixtures for the benchmarks: an in-memory file system with a fixed
+//! shape and helpers to build RPC contexts and call records.
+
+#![allow(dead_code)]
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use nfs_mamont::protocol::rpc;
+use nfs_mamont::vfs::{self, Capabilities, DirEntry, ReadDirResult};
+use nfs_mamont::xdr::nfs3::{
+    fattr3, fileid3, filename3, ftype3, nfspath3, nfsstat3, nfstime3, sattr3, specdata3,
+};
+use nfs_mamont::xdr::{self, Serialize};
+
+/// File id of the root directory
+pub const ROOT: fileid3 = 1;
+
+/// Read-only file system with a root directory holding `entries` files
+pub struct BenchFs {
+    /// Number of files in the root directory, with ids `ROOT + 1..=ROOT + entries`
+    pub entries: u64,
+}
+
+/// Attributes reported for every object
+pub fn attr(fileid: fileid3) -> fattr3 {
+    let time = nfstime3 { seconds: 1_700_000_000, nseconds: 0 };
+    fattr3 {
+        ftype: if fileid == ROOT { ftype3::NF3DIR } else { ftype3::NF3REG },
+        mode: 0o644,
+        nlink: 1,
+        uid: 1000,
+        gid: 1000,
+        size: 4096,
+        used: 4096,
+        rdev: specdata3::default(),
+        fsid: 1,
+        fileid,
+        atime: time,
+        mtime: time,
+        ctime: time,
+    }
+}
+
+#[async_trait]
+impl vfs::NFSFileSystem for BenchFs {
+    fn generation(&self) -> u64 {
+        1
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::ReadOnly
+    }
+
+    fn root_dir(&self) -> fileid3 {
+        ROOT
+    }
+
+    async fn lookup(&self, _dirid: fileid3, _filename: &filename3) -> Result<fileid3, nfsstat3> {
+        Err(nfsstat3::NFS3ERR_NOENT)
+    }
+
+    async fn getattr(&self, id: fileid3) -> Result<fattr3, nfsstat3> {
+        Ok(attr(id))
+    }
+
+    async fn setattr(&self, _id: fileid3, _setattr: sattr3) -> Result<fattr3, nfsstat3> {
+        Err(nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn read(
+        &self,
+        _id: fileid3,
+        _offset: u64,
+        count: u32,
+    ) -> Result<(Vec<u8>, bool), nfsstat3> {
+        Ok((vec![0; count as usize], true))
+    }
+
+    async fn write(&self, _id: fileid3, _offset: u64, _data: &[u8]) -> Result<fattr3, nfsstat3> {
+        Err(nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn create(
+        &self,
+        _dirid: fileid3,
+        _filename: &filename3,
+        _attr: sattr3,
+    ) -> Result<(fileid3, fattr3), nfsstat3> {
+        Err(nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn create_exclusive(
+        &self,
+        _dirid: fileid3,
+        _filename: &filename3,
+    ) -> Result<fileid3, nfsstat3> {
+        Err(nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn mkdir(
+        &self,
+        _dirid: fileid3,
+        _dirname: &filename3,
+    ) -> Result<(fileid3, fattr3), nfsstat3> {
+        Err(nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn remove(&self, _dirid: fileid3, _filename: &filename3) -> Result<(), nfsstat3> {
+        Err(nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn rename(
+        &self,
+        _from_dirid: fileid3,
+        _from_filename: &filename3,
+        _to_dirid: fileid3,
+        _to_filename: &filename3,
+    ) -> Result<(), nfsstat3> {
+        Err(nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn readdir(
+        &self,
+        dirid: fileid3,
+        start_after: fileid3,
+        max_entries: usize,
+    ) -> Result<ReadDirResult, nfsstat3> {
+        if dirid != ROOT {
+            return Err(nfsstat3::NFS3ERR_NOTDIR);
+        }
+        let first = start_after.max(ROOT) + 1;
+        let last = ROOT + self.entries;
+        let entries = (first..=last)
+            .take(max_entries)
+            .map(|fileid| DirEntry {
+                fileid,
+                name: format!("file-{fileid:08}").into_bytes().into(),
+                attr: attr(fileid),
+            })
+            .collect::<Vec<_>>();
+        let end = entries.last().is_none_or(|e| e.fileid == last);
+        Ok(ReadDirResult { entries, end })
+    }
+
+    async fn symlink(
+        &self,
+        _dirid: fileid3,
+        _linkname: &filename3,
+        _symlink: &nfspath3,
+        _attr: &sattr3,
+    ) -> Result<(fileid3, fattr3), nfsstat3> {
+        Err(nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn readlink(&self, _id: fileid3) -> Result<nfspath3, nfsstat3> {
+        Err(nfsstat3::NFS3ERR_INVAL)
+    }
+
+    async fn link(
+        &self,
+        _file_id: fileid3,
+        _link_dir_id: fileid3,
+        _link_name: &filename3,
+    ) -> Result<fattr3, nfsstat3> {
+        Err(nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn mknod(
+        &self,
+        _dir_id: fileid3,
+        _name: &filename3,
+        _ftype: ftype3,
+        _specdata: specdata3,
+        _attrs: &sattr3,
+    ) -> Result<(fileid3, fattr3), nfsstat3> {
+        Err(nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn commit(
+        &self,
+        _file_id: fileid3,
+        _offset: u64,
+        _count: u32,
+    ) -> Result<fattr3, nfsstat3> {
+        Err(nfsstat3::NFS3ERR_ROFS)
+    }
+}
+
+/// Builds a context serving `fs`
+///
+/// The transaction tracker keeps no completed transactions, so benchmarks can
+/// reuse transaction ids without hitting retransmission detection.
+pub fn context(fs: BenchFs) -> rpc::Context {
+    rpc::Context {
+        local_port: 2049,
+        client_addr: ([127, 0, 0, 1], 700).into(),
+        transaction_tracker: Arc::new(rpc::TransactionTracker::new(Duration::ZERO)),
+        ..rpc::Context::new(Arc::new(fs))
+    }
+}
+
+/// Builds an `NFSv3` call record with `AUTH_UNIX` credentials
+///
+/// # Parameters
+/// * `xid` - Transaction id
+/// * `proc` - Procedure number
+/// * `args` - Procedure arguments
+///
+/// # Returns
+/// * `Vec<u8>` - The serialized call, without record marking
+pub fn nfs_call(xid: u32, proc: u32, args: &impl Serialize) -> Vec<u8> {
+    let mut cred = Vec::new();
+    xdr::rpc::auth_unix {
+        stamp: 0,
+        machinename: b"bench".to_vec(),
+        uid: 1000,
+        gid: 1000,
+        gids: vec![1000],
+    }
+    .serialize(&mut cred)
+    .unwrap();
+    let msg = xdr::rpc::rpc_msg {
+        xid,
+        body: xdr::rpc::rpc_body::CALL(xdr::rpc::call_body {
+            rpcvers: 2,
+            prog: xdr::nfs3::PROGRAM,
+            vers: xdr::nfs3::VERSION,
+            proc,
+            cred: xdr::rpc::opaque_auth { flavor: xdr::rpc::auth_flavor::AUTH_UNIX, body: cred },
+            verf: xdr::rpc::opaque_auth::default(),
+        }),
+    };
+    let mut out = Vec::new();
+    msg.serialize(&mut out).unwrap();
+    args.serialize(&mut out).unwrap();
+    out
+}
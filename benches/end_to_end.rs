@@ -0,0 +1,72 @@
+//! Request/response throughput through the full connection pipeline, minus
+//! the socket: framed calls are fed to a [`rpc::SocketMessageHandler`] and
+//! replies are collected from its channel.
+
+mod common;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use tokio::io::AsyncWriteExt;
+
+use nfs_mamont::protocol::rpc;
+use nfs_mamont::vfs::NFSFileSystem;
+use nfs_mamont::xdr::nfs3::file::READ3args;
+
+use common::{BenchFs, ROOT};
+
+/// Calls pipelined per iteration
+const BATCH: u64 = 64;
+/// Procedure number of `GETATTR`
+const NFSPROC3_GETATTR: u32 = 1;
+/// Procedure number of `READ`
+const NFSPROC3_READ: u32 = 6;
+
+/// Record-marks each call as a single fragment and concatenates them
+fn framed(calls: impl Iterator<Item = Vec<u8>>) -> Vec<u8> {
+    let mut out = Vec::new();
+    for call in calls {
+        out.extend_from_slice(&(call.len() as u32 | 1 << 31).to_be_bytes());
+        out.extend_from_slice(&call);
+    }
+    out
+}
+
+/// Pushes `input` through a fresh handler and waits for all `BATCH` replies
+async fn round_trip(context: &rpc::Context, input: &[u8]) {
+    let (mut handler, mut socksend, mut msgrecv) = rpc::SocketMessageHandler::new(context);
+    let writer = async {
+        socksend.write_all(input).await.unwrap();
+    };
+    let reader = async {
+        for _ in 0..BATCH {
+            handler.read().await.unwrap();
+        }
+        for _ in 0..BATCH {
+            msgrecv.recv().await.unwrap().unwrap();
+        }
+    };
+    tokio::join!(writer, reader);
+}
+
+fn end_to_end(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap();
+    let fs = BenchFs { entries: 1 };
+    let file = fs.id_to_fh(ROOT + 1);
+    let context = common::context(fs);
+
+    let getattr =
+        framed((0..BATCH as u32).map(|xid| common::nfs_call(xid, NFSPROC3_GETATTR, &file)));
+    let read = framed((0..BATCH as u32).map(|xid| {
+        let args = READ3args { file: file.clone(), offset: 0, count: 32 << 10 };
+        common::nfs_call(xid, NFSPROC3_READ, &args)
+    }));
+
+    let mut group = c.benchmark_group("end_to_end");
+    group.throughput(Throughput::Elements(BATCH));
+    for (name, input) in [("getattr", &getattr), ("read_32k", &read)] {
+        group.bench_function(name, |b| b.iter(|| runtime.block_on(round_trip(&context, input))));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, end_to_end);
+criterion_main!(benches);
@@ -0,0 +1,57 @@
+//! Record marking: splitting replies into fragments and reassembling calls.
+
+use std::io::Cursor;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use nfs_mamont::protocol::rpc;
+
+/// Record sizes from a bare `GETATTR` reply up to a 1 MiB `READ` reply
+const SIZES: [usize; 3] = [128, 64 << 10, 1 << 20];
+
+/// Fragment size used when reassembling multi-fragment records
+const FRAGMENT: usize = 8 << 10;
+
+/// Record-marks `data` as fragments of at most `fragment` bytes
+fn frame(data: &[u8], fragment: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / fragment * 4 + 4);
+    let chunks = data.chunks(fragment).count();
+    for (i, chunk) in data.chunks(fragment).enumerate() {
+        let last = if i + 1 == chunks { 1 << 31 } else { 0 };
+        out.extend_from_slice(&(chunk.len() as u32 | last).to_be_bytes());
+        out.extend_from_slice(chunk);
+    }
+    out
+}
+
+fn framing(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+
+    let mut group = c.benchmark_group("framing");
+    for size in SIZES {
+        let record = vec![0x5a; size];
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("write", size), &record, |b, record| {
+            let mut output = Vec::with_capacity(size + 4);
+            b.iter(|| {
+                output.clear();
+                runtime.block_on(rpc::write_fragment(&mut output, black_box(record))).unwrap()
+            })
+        });
+        let framed = frame(&record, FRAGMENT);
+        group.bench_with_input(BenchmarkId::new("read", size), &framed, |b, framed| {
+            let mut record = Vec::with_capacity(size);
+            b.iter(|| {
+                record.clear();
+                let mut input = Cursor::new(black_box(&framed[..]));
+                runtime.block_on(async {
+                    while !rpc::read_fragment(&mut input, &mut record).await.unwrap() {}
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, framing);
+criterion_main!(benches);
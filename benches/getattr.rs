@@ -0,0 +1,42 @@
+//! `GETATTR` throughput through the fast path and through the generic dispatcher.
+
+mod common;
+
+use std::io::Cursor;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use nfs_mamont::protocol::rpc;
+use nfs_mamont::vfs::NFSFileSystem;
+
+use common::{BenchFs, ROOT};
+
+fn getattr(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let fs = BenchFs { entries: 0 };
+    let handle = fs.id_to_fh(ROOT);
+    let context = common::context(fs);
+    let call = common::nfs_call(1, 1, &handle);
+    let mut output = Vec::with_capacity(256);
+
+    let mut group = c.benchmark_group("getattr");
+    group.bench_function("fast_path", |b| {
+        b.iter(|| {
+            output.clear();
+            runtime
+                .block_on(rpc::process_message(black_box(&call), &mut output, context.clone()))
+                .unwrap()
+        })
+    });
+    group.bench_function("generic", |b| {
+        b.iter(|| {
+            output.clear();
+            let mut input = Cursor::new(black_box(&call[..]));
+            runtime.block_on(rpc::handle_rpc(&mut input, &mut output, context.clone())).unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, getattr);
+criterion_main!(benches);
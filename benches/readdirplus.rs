@@ -0,0 +1,48 @@
+//! `READDIRPLUS` reply encoding for a directory of 10k entries returned in a
+//! single reply.
+
+mod common;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+use nfs_mamont::protocol::rpc;
+use nfs_mamont::vfs::NFSFileSystem;
+use nfs_mamont::xdr::nfs3::{self, dir::READDIRPLUS3args};
+
+use common::{BenchFs, ROOT};
+
+/// Number of entries in the listed directory
+const ENTRIES: u64 = 10_000;
+/// Procedure number of `READDIRPLUS`
+const NFSPROC3_READDIRPLUS: u32 = 17;
+
+fn readdirplus(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let fs = BenchFs { entries: ENTRIES };
+    let args = READDIRPLUS3args {
+        dir: fs.id_to_fh(ROOT),
+        cookie: 0,
+        cookieverf: nfs3::cookieverf3::default(),
+        // large enough for every entry to fit into one reply
+        dircount: 1 << 20,
+        maxcount: 4 << 20,
+    };
+    let context = common::context(fs);
+    let call = common::nfs_call(1, NFSPROC3_READDIRPLUS, &args);
+    let mut output = Vec::with_capacity(4 << 20);
+
+    let mut group = c.benchmark_group("readdirplus");
+    group.throughput(Throughput::Elements(ENTRIES));
+    group.bench_function("10k_entries", |b| {
+        b.iter(|| {
+            output.clear();
+            runtime
+                .block_on(rpc::process_message(black_box(&call), &mut output, context.clone()))
+                .unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, readdirplus);
+criterion_main!(benches);
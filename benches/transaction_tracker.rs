@@ -0,0 +1,62 @@
+//! Duplicate request detection under concurrent clients, one lock versus shards.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use nfs_mamont::protocol::rpc::{
+    RetransmissionPolicy, TransactionTracker, TransactionTrackerConfig,
+};
+
+/// Number of threads issuing calls concurrently, each as a different client
+const CLIENTS: usize = 8;
+
+/// Calls issued by every client per iteration
+const CALLS: u32 = 1000;
+
+fn tracker(shards: usize) -> TransactionTracker {
+    TransactionTracker::with_config(TransactionTrackerConfig {
+        retention_period: Duration::from_secs(60),
+        capacity: 4096,
+        policy: RetransmissionPolicy::All,
+        shards,
+    })
+}
+
+fn transaction_tracker(c: &mut Criterion) {
+    let mut group = c.benchmark_group("transaction_tracker");
+    group.throughput(Throughput::Elements(CLIENTS as u64 * u64::from(CALLS)));
+    for shards in [1, 16] {
+        let tracker = tracker(shards);
+        let mut xid = 0;
+        group.bench_with_input(BenchmarkId::new("concurrent", shards), &tracker, |b, tracker| {
+            b.iter(|| {
+                xid += CALLS;
+                std::thread::scope(|scope| {
+                    for client in 0..CLIENTS {
+                        scope.spawn(move || {
+                            let addr = SocketAddr::from(([10, 0, 0, client as u8], 700));
+                            for call in xid - CALLS..xid {
+                                tracker.is_retransmission(call, addr);
+                                tracker.mark_processed(call, addr);
+                            }
+                        });
+                    }
+                })
+            })
+        });
+        let stats = tracker.lock_stats();
+        println!(
+            "{} shard(s): {} of {} lock acquisitions contended ({:.1}%)",
+            stats.shards,
+            stats.contended,
+            stats.acquisitions,
+            stats.contention_rate() * 100.0
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, transaction_tracker);
+criterion_main!(benches);
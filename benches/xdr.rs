@@ -0,0 +1,101 @@
+//! XDR encoding and decoding of the largest structures the server handles:
+//! `READ` replies carrying a full megabyte and `READDIRPLUS` entry lists.
+
+mod common;
+
+use std::io::Cursor;
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+use nfs_mamont::vfs::NFSFileSystem;
+use nfs_mamont::xdr::nfs3::{self, dir::entryplus3, file::READ3resok};
+use nfs_mamont::xdr::{deserialize, Serialize};
+
+use common::{BenchFs, ROOT};
+
+/// Number of entries in the encoded directory listing
+const ENTRIES: u64 = 10_000;
+/// Payload size of the encoded `READ` reply
+const READ_SIZE: usize = 1 << 20;
+
+fn read_reply() -> READ3resok {
+    READ3resok {
+        file_attributes: nfs3::post_op_attr::Some(common::attr(ROOT + 1)),
+        count: READ_SIZE as u32,
+        eof: false,
+        data: vec![0xa5; READ_SIZE],
+    }
+}
+
+fn entries(fs: &BenchFs) -> Vec<entryplus3> {
+    (ROOT + 1..=ROOT + ENTRIES)
+        .map(|fileid| entryplus3 {
+            fileid,
+            name: format!("file-{fileid:08}").into_bytes().into(),
+            cookie: fileid,
+            name_attributes: nfs3::post_op_attr::Some(common::attr(fileid)),
+            name_handle: nfs3::post_op_fh3::Some(fs.id_to_fh(fileid)),
+        })
+        .collect()
+}
+
+/// Encodes `entries` the way a `READDIRPLUS` reply lays them out: each entry
+/// preceded by `true`, and the list terminated by `false`
+fn serialize_list(entries: &[entryplus3], output: &mut Vec<u8>) {
+    for entry in entries {
+        true.serialize(output).unwrap();
+        entry.serialize(output).unwrap();
+    }
+    false.serialize(output).unwrap();
+}
+
+fn deserialize_list(input: &[u8]) -> Vec<entryplus3> {
+    let mut input = Cursor::new(input);
+    let mut entries = Vec::new();
+    while deserialize::<bool>(&mut input).unwrap() {
+        entries.push(deserialize::<entryplus3>(&mut input).unwrap());
+    }
+    entries
+}
+
+fn read3resok(c: &mut Criterion) {
+    let reply = read_reply();
+    let mut encoded = Vec::new();
+    reply.serialize(&mut encoded).unwrap();
+
+    let mut group = c.benchmark_group("xdr/read3resok");
+    group.throughput(Throughput::Bytes(encoded.len() as u64));
+    group.bench_function("serialize", |b| {
+        b.iter_batched_ref(
+            || Vec::with_capacity(encoded.len()),
+            |output| black_box(&reply).serialize(output).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("deserialize", |b| {
+        b.iter(|| deserialize::<READ3resok>(&mut Cursor::new(black_box(&encoded[..]))).unwrap())
+    });
+    group.finish();
+}
+
+fn entryplus3_list(c: &mut Criterion) {
+    let fs = BenchFs { entries: ENTRIES };
+    let list = entries(&fs);
+    let mut encoded = Vec::new();
+    serialize_list(&list, &mut encoded);
+
+    let mut group = c.benchmark_group("xdr/entryplus3_list");
+    group.throughput(Throughput::Elements(ENTRIES));
+    group.bench_function("serialize", |b| {
+        b.iter_batched_ref(
+            || Vec::with_capacity(encoded.len()),
+            |output| serialize_list(black_box(&list), output),
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("deserialize", |b| b.iter(|| deserialize_list(black_box(&encoded))));
+    group.finish();
+}
+
+criterion_group!(benches, read3resok, entryplus3_list);
+criterion_main!(benches);
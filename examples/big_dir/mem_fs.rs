@@ -0,0 +1,282 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use nfs_mamont::vfs::{self, DirEntry, ReadDirResult};
+use nfs_mamont::xdr::nfs3;
+
+/// File ID of the root directory
+pub const ROOT: nfs3::fileid3 = 1;
+
+/// File or directory of a [`MemFs`]
+struct Inode {
+    attr: nfs3::fattr3,
+    /// Name of the inode in its parent directory
+    name: Vec<u8>,
+    /// Entries of a directory by name, so listings come in name order
+    entries: Option<BTreeMap<Vec<u8>, nfs3::fileid3>>,
+}
+
+/// Inodes of a [`MemFs`], the inode with file ID `n` at index `n - 1`
+struct State {
+    inodes: Vec<Inode>,
+}
+
+impl State {
+    /// Returns an inode
+    fn get(&self, id: nfs3::fileid3) -> Result<&Inode, nfs3::nfsstat3> {
+        let index = id.checked_sub(1).ok_or(nfs3::nfsstat3::NFS3ERR_STALE)?;
+        self.inodes.get(index as usize).ok_or(nfs3::nfsstat3::NFS3ERR_STALE)
+    }
+
+    /// Returns the entries of a directory
+    fn entries(
+        &self,
+        dirid: nfs3::fileid3,
+    ) -> Result<&BTreeMap<Vec<u8>, nfs3::fileid3>, nfs3::nfsstat3> {
+        self.get(dirid)?.entries.as_ref().ok_or(nfs3::nfsstat3::NFS3ERR_NOTDIR)
+    }
+
+    /// Adds an empty file or directory named `name` to a directory
+    fn add(
+        &mut self,
+        dirid: nfs3::fileid3,
+        name: &[u8],
+        ftype: nfs3::ftype3,
+    ) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        if self.entries(dirid)?.contains_key(name) {
+            return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
+        }
+        let id = self.inodes.len() as nfs3::fileid3 + 1;
+        let directory = matches!(ftype, nfs3::ftype3::NF3DIR);
+        let attr = nfs3::fattr3 {
+            ftype,
+            mode: if directory { 0o755 } else { 0o644 },
+            nlink: if directory { 2 } else { 1 },
+            fileid: id,
+            ..Default::default()
+        };
+        let entries = directory.then(BTreeMap::new);
+        self.inodes.push(Inode { attr, name: name.to_vec(), entries });
+        let parent = &mut self.inodes[dirid as usize - 1];
+        parent.entries.as_mut().unwrap().insert(name.to_vec(), id);
+        Ok(id)
+    }
+}
+
+/// File system held in memory, made to list directories of millions of
+/// entries quickly
+///
+/// Files are empty and cannot be written. Lookups and listings take
+/// logarithmic time in the size of the directory: entries are listed in name
+/// order and a listing resumes after the name of the file ID in the cookie.
+pub struct MemFs {
+    state: Mutex<State>,
+}
+
+impl Default for MemFs {
+    fn default() -> Self {
+        let root = nfs3::fattr3 {
+            ftype: nfs3::ftype3::NF3DIR,
+            mode: 0o755,
+            nlink: 2,
+            fileid: ROOT,
+            ..Default::default()
+        };
+        let root = Inode { attr: root, name: Vec::new(), entries: Some(BTreeMap::new()) };
+        MemFs { state: Mutex::new(State { inodes: vec![root] }) }
+    }
+}
+
+impl MemFs {
+    /// Creates a directory named `name` in the root directory holding `count`
+    /// empty files
+    ///
+    /// Files are created in an order unrelated to their names, so the file IDs
+    /// of a listing are not ascending.
+    pub fn add_directory(&self, name: &str, count: u64) -> nfs3::fileid3 {
+        let mut state = self.state.lock().unwrap();
+        let dirid = state.add(ROOT, name.as_bytes(), nfs3::ftype3::NF3DIR).unwrap();
+        // 1_000_003 is prime, so unless `count` is a multiple of it the
+        // sequence visits every index once
+        for i in 0..count {
+            let index = i * 1_000_003 % count;
+            let name = format!("file-{index:010}");
+            state.add(dirid, name.as_bytes(), nfs3::ftype3::NF3REG).unwrap();
+        }
+        dirid
+    }
+}
+
+#[async_trait]
+impl vfs::NFSFileSystem for MemFs {
+    fn generation(&self) -> u64 {
+        1
+    }
+
+    fn capabilities(&self) -> vfs::Capabilities {
+        vfs::Capabilities::WRITE
+    }
+
+    fn root_dir(&self) -> nfs3::fileid3 {
+        ROOT
+    }
+
+    async fn lookup(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        let state = self.state.lock().unwrap();
+        state.entries(dirid)?.get(&filename.0).copied().ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)
+    }
+
+    async fn getattr(&self, id: nfs3::fileid3) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        Ok(self.state.lock().unwrap().get(id)?.attr)
+    }
+
+    async fn setattr(
+        &self,
+        _id: nfs3::fileid3,
+        _setattr: nfs3::sattr3,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_NOTSUPP)
+    }
+
+    async fn read(
+        &self,
+        id: nfs3::fileid3,
+        _offset: u64,
+        _count: u32,
+    ) -> Result<(Vec<u8>, bool), nfs3::nfsstat3> {
+        self.state.lock().unwrap().get(id)?;
+        Ok((Vec::new(), true))
+    }
+
+    async fn write(
+        &self,
+        _id: nfs3::fileid3,
+        _offset: u64,
+        _data: &[u8],
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_NOTSUPP)
+    }
+
+    async fn create(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        _attr: nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        let mut state = self.state.lock().unwrap();
+        let id = state.add(dirid, &filename.0, nfs3::ftype3::NF3REG)?;
+        Ok((id, state.get(id)?.attr))
+    }
+
+    async fn create_exclusive(
+        &self,
+        _dirid: nfs3::fileid3,
+        _filename: &nfs3::filename3,
+    ) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_NOTSUPP)
+    }
+
+    async fn mkdir(
+        &self,
+        dirid: nfs3::fileid3,
+        dirname: &nfs3::filename3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        let mut state = self.state.lock().unwrap();
+        let id = state.add(dirid, &dirname.0, nfs3::ftype3::NF3DIR)?;
+        Ok((id, state.get(id)?.attr))
+    }
+
+    async fn remove(
+        &self,
+        _dirid: nfs3::fileid3,
+        _filename: &nfs3::filename3,
+    ) -> Result<(), nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_NOTSUPP)
+    }
+
+    async fn rename(
+        &self,
+        _from_dirid: nfs3::fileid3,
+        _from_filename: &nfs3::filename3,
+        _to_dirid: nfs3::fileid3,
+        _to_filename: &nfs3::filename3,
+    ) -> Result<(), nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_NOTSUPP)
+    }
+
+    /// Lists entries in name order, after the entry with file ID `start_after`
+    async fn readdir(
+        &self,
+        dirid: nfs3::fileid3,
+        start_after: nfs3::fileid3,
+        max_entries: usize,
+    ) -> Result<ReadDirResult, nfs3::nfsstat3> {
+        let state = self.state.lock().unwrap();
+        let entries = state.entries(dirid)?;
+        let start = match start_after {
+            0 => Bound::Unbounded,
+            id => {
+                let name = &state.get(id).map_err(|_| nfs3::nfsstat3::NFS3ERR_BAD_COOKIE)?.name;
+                Bound::Excluded(name.clone())
+            }
+        };
+        let mut listed = entries.range((start, Bound::Unbounded));
+        let mut result = ReadDirResult::default();
+        for (name, &fileid) in listed.by_ref().take(max_entries) {
+            let attr = state.get(fileid)?.attr;
+            result.entries.push(DirEntry { fileid, name: name.clone().into(), attr });
+        }
+        result.end = listed.next().is_none();
+        Ok(result)
+    }
+
+    async fn symlink(
+        &self,
+        _dirid: nfs3::fileid3,
+        _linkname: &nfs3::filename3,
+        _symlink: &nfs3::nfspath3,
+        _attr: &nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_NOTSUPP)
+    }
+
+    async fn readlink(&self, _id: nfs3::fileid3) -> Result<nfs3::nfspath3, nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_INVAL)
+    }
+
+    async fn link(
+        &self,
+        _file_id: nfs3::fileid3,
+        _link_dir_id: nfs3::fileid3,
+        _link_name: &nfs3::filename3,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_NOTSUPP)
+    }
+
+    async fn mknod(
+        &self,
+        _dir_id: nfs3::fileid3,
+        _name: &nfs3::filename3,
+        _ftype: nfs3::ftype3,
+        _specdata: nfs3::specdata3,
+        _attrs: &nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_NOTSUPP)
+    }
+
+    async fn commit(
+        &self,
+        id: nfs3::fileid3,
+        _offset: u64,
+        _count: u32,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        Ok(self.state.lock().unwrap().get(id)?.attr)
+    }
+}
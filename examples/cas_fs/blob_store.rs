@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+
+use sha2::{Digest as _, Sha256};
+
+/// SHA-256 digest addressing a blob
+pub type Digest = [u8; 32];
+
+/// Stored blob with the number of file chunks referring to it
+#[derive(Debug)]
+struct Blob {
+    /// Contents of the blob
+    data: Vec<u8>,
+    /// Number of references; the blob is deleted when it drops to zero
+    refs: u64,
+}
+
+/// In-memory content-addressed blob store with reference counting.
+/// Equal contents are stored once, no matter how many files hold them.
+#[derive(Debug, Default)]
+pub struct BlobStore {
+    /// Blobs by digest of their contents
+    blobs: HashMap<Digest, Blob>,
+    /// Total size of all stored blobs
+    stored_bytes: u64,
+}
+
+impl BlobStore {
+    /// Stores `data` and adds a reference to it, returning its digest.
+    /// Data that is already stored only gains a reference.
+    pub fn put(&mut self, data: &[u8]) -> Digest {
+        let digest: Digest = Sha256::digest(data).into();
+        let blob =
+            self.blobs.entry(digest).or_insert_with(|| Blob { data: data.to_vec(), refs: 0 });
+        if blob.refs == 0 {
+            self.stored_bytes += data.len() as u64;
+        }
+        blob.refs += 1;
+        digest
+    }
+
+    /// Returns the contents of a blob
+    pub fn get(&self, digest: &Digest) -> Option<&[u8]> {
+        self.blobs.get(digest).map(|blob| &blob.data[..])
+    }
+
+    /// Drops a reference to a blob, deleting it when no reference is left
+    pub fn release(&mut self, digest: &Digest) {
+        if let Some(blob) = self.blobs.get_mut(digest) {
+            blob.refs -= 1;
+            if blob.refs == 0 {
+                self.stored_bytes -= blob.data.len() as u64;
+                self.blobs.remove(digest);
+            }
+        }
+    }
+
+    /// Returns the number of blobs and their total size
+    pub fn usage(&self) -> (usize, u64) {
+        (self.blobs.len(), self.stored_bytes)
+    }
+}
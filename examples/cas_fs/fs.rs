@@ -0,0 +1,582 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use tracing::debug;
+
+use nfs_mamont::clock::{RequestedTimes, SystemClock};
+use nfs_mamont::vfs;
+use nfs_mamont::xdr::nfs3;
+
+use crate::blob_store::{BlobStore, Digest};
+
+/// Size of the chunks file contents are split into before hashing
+const CHUNK_SIZE: u64 = 16 * 1024;
+/// File ID of the root directory
+const ROOT_ID: nfs3::fileid3 = 1;
+
+/// Contents of a regular file
+#[derive(Debug, Default)]
+struct FileData {
+    /// Committed chunks; `None` for a chunk of zeros
+    chunks: Vec<Option<Digest>>,
+    /// Chunks written since the last `COMMIT`, by index
+    dirty: BTreeMap<u64, Vec<u8>>,
+}
+
+/// Kind-specific part of an inode
+#[derive(Debug)]
+enum Kind {
+    /// Regular file
+    File(FileData),
+    /// Directory with its entries sorted by name
+    Directory { parent: nfs3::fileid3, entries: BTreeMap<Vec<u8>, nfs3::fileid3> },
+    /// Symbolic link with its target
+    Symlink(Vec<u8>),
+}
+
+/// File, directory or symbolic link
+#[derive(Debug)]
+struct Inode {
+    /// Attributes reported to clients
+    attr: nfs3::fattr3,
+    /// Contents
+    kind: Kind,
+}
+
+/// Inodes and the blob store holding file contents
+#[derive(Debug)]
+struct State {
+    /// Inodes by file ID
+    inodes: HashMap<nfs3::fileid3, Inode>,
+    /// Next file ID to hand out. IDs are never reused, so a file keeps its ID
+    /// across renames and hard links and a stale handle never reaches another file.
+    next_id: nfs3::fileid3,
+    /// Store of committed file chunks
+    store: BlobStore,
+}
+
+/// In-memory file system storing file contents as content-addressed chunks.
+///
+/// Files are split into chunks of [`CHUNK_SIZE`] bytes that are stored by their
+/// SHA-256 digest, so files and hard links sharing contents share storage.
+/// Writes land in per-file dirty chunks and reach the blob store on `COMMIT`.
+#[derive(Debug)]
+pub struct CasFS {
+    /// All state, behind one lock
+    state: Mutex<State>,
+    /// Generation number derived from the startup time
+    generation: u64,
+}
+
+impl Default for CasFS {
+    /// Creates a file system holding an empty root directory
+    fn default() -> CasFS {
+        let root = Inode {
+            attr: make_attr(nfs3::ftype3::NF3DIR, ROOT_ID, 0o777),
+            kind: Kind::Directory { parent: ROOT_ID, entries: BTreeMap::new() },
+        };
+        let state = State {
+            inodes: HashMap::from([(ROOT_ID, root)]),
+            next_id: ROOT_ID + 1,
+            store: BlobStore::default(),
+        };
+        let now = SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis();
+        CasFS { state: Mutex::new(state), generation: now as u64 }
+    }
+}
+
+/// Returns the current time
+fn now() -> nfs3::nfstime3 {
+    let d = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
+    nfs3::nfstime3 { seconds: d.as_secs() as u32, nseconds: d.subsec_nanos() }
+}
+
+/// Creates the attributes of a new inode
+fn make_attr(ftype: nfs3::ftype3, id: nfs3::fileid3, mode: u32) -> nfs3::fattr3 {
+    let now = now();
+    nfs3::fattr3 {
+        ftype,
+        mode,
+        nlink: 1,
+        uid: 507,
+        gid: 507,
+        size: 0,
+        used: 0,
+        rdev: nfs3::specdata3::default(),
+        fsid: 0,
+        fileid: id,
+        atime: now,
+        mtime: now,
+        ctime: now,
+    }
+}
+
+/// Returns the length of chunk `index` of a file of `size` bytes
+fn chunk_len(size: u64, index: u64) -> usize {
+    size.saturating_sub(index * CHUNK_SIZE).min(CHUNK_SIZE) as usize
+}
+
+impl FileData {
+    /// Returns the current contents of chunk `index` of a file of `size` bytes
+    fn chunk(&self, store: &BlobStore, index: u64, size: u64) -> Vec<u8> {
+        let mut chunk = match self.dirty.get(&index) {
+            Some(chunk) => chunk.clone(),
+            None => self
+                .chunks
+                .get(index as usize)
+                .copied()
+                .flatten()
+                .and_then(|digest| store.get(&digest))
+                .map(<[u8]>::to_vec)
+                .unwrap_or_default(),
+        };
+        chunk.resize(chunk_len(size, index), 0);
+        chunk
+    }
+
+    /// Drops the references of all committed chunks
+    fn release(&mut self, store: &mut BlobStore) {
+        for digest in self.chunks.drain(..).flatten() {
+            store.release(&digest);
+        }
+        self.dirty.clear();
+    }
+}
+
+impl State {
+    /// Returns an inode
+    fn inode(&self, id: nfs3::fileid3) -> Result<&Inode, nfs3::nfsstat3> {
+        self.inodes.get(&id).ok_or(nfs3::nfsstat3::NFS3ERR_STALE)
+    }
+
+    /// Returns an inode for modification
+    fn inode_mut(&mut self, id: nfs3::fileid3) -> Result<&mut Inode, nfs3::nfsstat3> {
+        self.inodes.get_mut(&id).ok_or(nfs3::nfsstat3::NFS3ERR_STALE)
+    }
+
+    /// Returns the entries of a directory
+    fn entries(
+        &mut self,
+        dirid: nfs3::fileid3,
+    ) -> Result<&mut BTreeMap<Vec<u8>, nfs3::fileid3>, nfs3::nfsstat3> {
+        match &mut self.inode_mut(dirid)?.kind {
+            Kind::Directory { entries, .. } => Ok(entries),
+            _ => Err(nfs3::nfsstat3::NFS3ERR_NOTDIR),
+        }
+    }
+
+    /// Updates the modification time of a directory after its entries changed
+    fn touch(&mut self, dirid: nfs3::fileid3) {
+        if let Some(dir) = self.inodes.get_mut(&dirid) {
+            dir.attr.mtime = now();
+            dir.attr.ctime = dir.attr.mtime;
+        }
+    }
+
+    /// Creates an inode and links it into a directory
+    fn add(
+        &mut self,
+        dirid: nfs3::fileid3,
+        name: &nfs3::filename3,
+        ftype: nfs3::ftype3,
+        kind: Kind,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        let id = self.next_id;
+        let entries = self.entries(dirid)?;
+        if entries.contains_key(&name[..]) {
+            return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
+        }
+        entries.insert(name.to_vec(), id);
+        let mode = if matches!(ftype, nfs3::ftype3::NF3DIR) { 0o777 } else { 0o755 };
+        let mut attr = make_attr(ftype, id, mode);
+        if let Kind::Symlink(target) = &kind {
+            attr.size = target.len() as u64;
+        }
+        self.inodes.insert(id, Inode { attr, kind });
+        self.next_id += 1;
+        self.touch(dirid);
+        Ok((id, attr))
+    }
+
+    /// Drops a link to an inode, deleting it and releasing its chunks when it
+    /// was the last one
+    fn unlink(&mut self, id: nfs3::fileid3) {
+        let Some(inode) = self.inodes.get_mut(&id) else {
+            return;
+        };
+        inode.attr.nlink -= 1;
+        inode.attr.ctime = now();
+        if inode.attr.nlink == 0 {
+            if let Some(Inode { kind: Kind::File(mut data), .. }) = self.inodes.remove(&id) {
+                data.release(&mut self.store);
+            }
+        }
+    }
+
+    /// Writes data into the dirty chunks of a file
+    fn write(
+        &mut self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        let inode = self.inodes.get_mut(&id).ok_or(nfs3::nfsstat3::NFS3ERR_STALE)?;
+        let Kind::File(file) = &mut inode.kind else {
+            return Err(nfs3::nfsstat3::NFS3ERR_INVAL);
+        };
+        if !data.is_empty() {
+            let end = offset + data.len() as u64;
+            let size = inode.attr.size.max(end);
+            for index in offset / CHUNK_SIZE..=(end - 1) / CHUNK_SIZE {
+                let mut chunk = file.chunk(&self.store, index, inode.attr.size);
+                chunk.resize(chunk_len(size, index), 0);
+                let start = index * CHUNK_SIZE;
+                let from = offset.max(start);
+                let to = end.min(start + CHUNK_SIZE);
+                chunk[(from - start) as usize..(to - start) as usize]
+                    .copy_from_slice(&data[(from - offset) as usize..(to - offset) as usize]);
+                file.dirty.insert(index, chunk);
+            }
+            inode.attr.size = size;
+            inode.attr.mtime = now();
+            inode.attr.ctime = inode.attr.mtime;
+        }
+        Ok(inode.attr)
+    }
+
+    /// Changes the size of a file
+    fn truncate(&mut self, id: nfs3::fileid3, size: u64) -> Result<(), nfs3::nfsstat3> {
+        let inode = self.inodes.get_mut(&id).ok_or(nfs3::nfsstat3::NFS3ERR_STALE)?;
+        let Kind::File(file) = &mut inode.kind else {
+            return Err(nfs3::nfsstat3::NFS3ERR_INVAL);
+        };
+        if size < inode.attr.size {
+            let chunks = size.div_ceil(CHUNK_SIZE);
+            if size % CHUNK_SIZE != 0 {
+                // keep the cut off bytes from reappearing if the file grows again
+                let last = file.chunk(&self.store, chunks - 1, size);
+                file.dirty.insert(chunks - 1, last);
+            }
+            file.dirty.retain(|&index, _| index < chunks);
+            if file.chunks.len() as u64 > chunks {
+                for digest in file.chunks.drain(chunks as usize..).flatten() {
+                    self.store.release(&digest);
+                }
+            }
+        }
+        inode.attr.size = size;
+        inode.attr.mtime = now();
+        inode.attr.ctime = inode.attr.mtime;
+        Ok(())
+    }
+
+    /// Moves the dirty chunks of a file into the blob store
+    fn commit(&mut self, id: nfs3::fileid3) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        let inode = self.inodes.get_mut(&id).ok_or(nfs3::nfsstat3::NFS3ERR_STALE)?;
+        let Kind::File(file) = &mut inode.kind else {
+            return Ok(inode.attr);
+        };
+        let size = inode.attr.size;
+        for (index, chunk) in std::mem::take(&mut file.dirty) {
+            let index = index as usize;
+            if index >= file.chunks.len() {
+                file.chunks.resize(index + 1, None);
+            }
+            let digest = if chunk.iter().all(|&byte| byte == 0) {
+                None
+            } else {
+                Some(self.store.put(&chunk))
+            };
+            if let Some(old) = std::mem::replace(&mut file.chunks[index], digest) {
+                self.store.release(&old);
+            }
+        }
+        file.chunks.truncate(size.div_ceil(CHUNK_SIZE) as usize);
+        let stored: u64 = file.chunks.iter().flatten().count() as u64 * CHUNK_SIZE;
+        inode.attr.used = stored.min(size);
+        let (blobs, bytes) = self.store.usage();
+        debug!("committed file {}; store holds {} blobs, {} bytes", id, blobs, bytes);
+        Ok(inode.attr)
+    }
+}
+
+/// Implementation of the NFSFileSystem trait for CasFS.
+#[async_trait]
+impl vfs::NFSFileSystem for CasFS {
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn root_dir(&self) -> nfs3::fileid3 {
+        ROOT_ID
+    }
+
+    fn capabilities(&self) -> vfs::Capabilities {
+        vfs::Capabilities::ReadWrite
+    }
+
+    async fn lookup(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        let state = self.state.lock().unwrap();
+        let Kind::Directory { parent, entries } = &state.inode(dirid)?.kind else {
+            return Err(nfs3::nfsstat3::NFS3ERR_NOTDIR);
+        };
+        match &filename[..] {
+            b"." => Ok(dirid),
+            b".." => Ok(*parent),
+            name => entries.get(name).copied().ok_or(nfs3::nfsstat3::NFS3ERR_NOENT),
+        }
+    }
+
+    async fn getattr(&self, id: nfs3::fileid3) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        Ok(self.state.lock().unwrap().inode(id)?.attr)
+    }
+
+    async fn setattr(
+        &self,
+        id: nfs3::fileid3,
+        setattr: nfs3::sattr3,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(size) = setattr.size {
+            state.truncate(id, size)?;
+        }
+        let attr = &mut state.inode_mut(id)?.attr;
+        let times = RequestedTimes::resolve(&setattr, &SystemClock);
+        attr.atime = times.atime.unwrap_or(attr.atime);
+        attr.mtime = times.mtime.unwrap_or(attr.mtime);
+        if let Some(mode) = setattr.mode {
+            attr.mode = mode;
+        }
+        if let Some(uid) = setattr.uid {
+            attr.uid = uid;
+        }
+        if let Some(gid) = setattr.gid {
+            attr.gid = gid;
+        }
+        attr.ctime = now();
+        Ok(*attr)
+    }
+
+    async fn read(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        count: u32,
+    ) -> Result<(Vec<u8>, bool), nfs3::nfsstat3> {
+        let state = self.state.lock().unwrap();
+        let inode = state.inode(id)?;
+        let file = match &inode.kind {
+            Kind::File(file) => file,
+            Kind::Directory { .. } => return Err(nfs3::nfsstat3::NFS3ERR_ISDIR),
+            Kind::Symlink(_) => return Err(nfs3::nfsstat3::NFS3ERR_INVAL),
+        };
+        let size = inode.attr.size;
+        if offset >= size || count == 0 {
+            return Ok((Vec::new(), offset >= size));
+        }
+        let end = (offset + u64::from(count)).min(size);
+        let mut data = Vec::with_capacity((end - offset) as usize);
+        for index in offset / CHUNK_SIZE..=(end - 1) / CHUNK_SIZE {
+            let chunk = file.chunk(&state.store, index, size);
+            let start = index * CHUNK_SIZE;
+            let from = (offset.max(start) - start) as usize;
+            let to = (end.min(start + CHUNK_SIZE) - start) as usize;
+            data.extend_from_slice(&chunk[from..to]);
+        }
+        Ok((data, end >= size))
+    }
+
+    async fn write(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        self.state.lock().unwrap().write(id, offset, data)
+    }
+
+    async fn create(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        attr: nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        let (id, _) = {
+            let mut state = self.state.lock().unwrap();
+            let kind = Kind::File(FileData::default());
+            state.add(dirid, filename, nfs3::ftype3::NF3REG, kind)?
+        };
+        Ok((id, self.setattr(id, attr).await?))
+    }
+
+    async fn create_exclusive(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        let mut state = self.state.lock().unwrap();
+        let kind = Kind::File(FileData::default());
+        Ok(state.add(dirid, filename, nfs3::ftype3::NF3REG, kind)?.0)
+    }
+
+    async fn mkdir(
+        &self,
+        dirid: nfs3::fileid3,
+        dirname: &nfs3::filename3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        let mut state = self.state.lock().unwrap();
+        let kind = Kind::Directory { parent: dirid, entries: BTreeMap::new() };
+        state.add(dirid, dirname, nfs3::ftype3::NF3DIR, kind)
+    }
+
+    async fn remove(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> Result<(), nfs3::nfsstat3> {
+        let mut state = self.state.lock().unwrap();
+        let id = *state.entries(dirid)?.get(&filename[..]).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?;
+        if let Kind::Directory { entries, .. } = &state.inode(id)?.kind {
+            if !entries.is_empty() {
+                return Err(nfs3::nfsstat3::NFS3ERR_NOTEMPTY);
+            }
+        }
+        state.entries(dirid)?.remove(&filename[..]);
+        state.touch(dirid);
+        state.unlink(id);
+        Ok(())
+    }
+
+    async fn rename(
+        &self,
+        from_dirid: nfs3::fileid3,
+        from_filename: &nfs3::filename3,
+        to_dirid: nfs3::fileid3,
+        to_filename: &nfs3::filename3,
+    ) -> Result<(), nfs3::nfsstat3> {
+        let mut state = self.state.lock().unwrap();
+        let id = *state
+            .entries(from_dirid)?
+            .get(&from_filename[..])
+            .ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?;
+        if let Some(&replaced) = state.entries(to_dirid)?.get(&to_filename[..]) {
+            if replaced == id {
+                return Ok(());
+            }
+            if let Kind::Directory { entries, .. } = &state.inode(replaced)?.kind {
+                if !entries.is_empty() {
+                    return Err(nfs3::nfsstat3::NFS3ERR_NOTEMPTY);
+                }
+            }
+            state.unlink(replaced);
+        }
+        state.entries(from_dirid)?.remove(&from_filename[..]);
+        state.entries(to_dirid)?.insert(to_filename.to_vec(), id);
+        if let Kind::Directory { parent, .. } = &mut state.inode_mut(id)?.kind {
+            *parent = to_dirid;
+        }
+        state.touch(from_dirid);
+        state.touch(to_dirid);
+        Ok(())
+    }
+
+    /// Hard links put one file ID under several names of a directory, so
+    /// listings resume by position rather than by file ID.
+    fn readdir_cookie_kind(&self) -> vfs::ReadDirCookieKind {
+        vfs::ReadDirCookieKind::Index
+    }
+
+    async fn readdir_with_cookie(
+        &self,
+        dirid: nfs3::fileid3,
+        cookie: vfs::ReadDirCookie,
+        max_entries: usize,
+    ) -> Result<vfs::ReadDirResult, nfs3::nfsstat3> {
+        let state = self.state.lock().unwrap();
+        let Kind::Directory { entries, .. } = &state.inode(dirid)?.kind else {
+            return Err(nfs3::nfsstat3::NFS3ERR_NOTDIR);
+        };
+        let skip = match cookie {
+            vfs::ReadDirCookie::Start => 0,
+            vfs::ReadDirCookie::Index(index) => index as usize,
+            vfs::ReadDirCookie::AfterFileId(_) | vfs::ReadDirCookie::Opaque(_) => {
+                return Err(nfs3::nfsstat3::NFS3ERR_BAD_COOKIE)
+            }
+        };
+        let mut result = vfs::ReadDirResult::default();
+        for (name, &fileid) in entries.iter().skip(skip).take(max_entries) {
+            let attr = state.inode(fileid)?.attr;
+            result.entries.push(vfs::DirEntry { fileid, name: name.clone().into(), attr });
+        }
+        result.end = skip + result.entries.len() >= entries.len();
+        Ok(result)
+    }
+
+    async fn symlink(
+        &self,
+        dirid: nfs3::fileid3,
+        linkname: &nfs3::filename3,
+        symlink: &nfs3::nfspath3,
+        _attr: &nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        let mut state = self.state.lock().unwrap();
+        let kind = Kind::Symlink(symlink.to_vec());
+        state.add(dirid, linkname, nfs3::ftype3::NF3LNK, kind)
+    }
+
+    async fn readlink(&self, id: nfs3::fileid3) -> Result<nfs3::nfspath3, nfs3::nfsstat3> {
+        match &self.state.lock().unwrap().inode(id)?.kind {
+            Kind::Symlink(target) => Ok(target.clone().into()),
+            _ => Err(nfs3::nfsstat3::NFS3ERR_INVAL),
+        }
+    }
+
+    async fn link(
+        &self,
+        file_id: nfs3::fileid3,
+        link_dir_id: nfs3::fileid3,
+        link_name: &nfs3::filename3,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        let mut state = self.state.lock().unwrap();
+        if let Kind::Directory { .. } = state.inode(file_id)?.kind {
+            return Err(nfs3::nfsstat3::NFS3ERR_ISDIR);
+        }
+        let entries = state.entries(link_dir_id)?;
+        if entries.contains_key(&link_name[..]) {
+            return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
+        }
+        entries.insert(link_name.to_vec(), file_id);
+        state.touch(link_dir_id);
+        let attr = &mut state.inode_mut(file_id)?.attr;
+        attr.nlink += 1;
+        attr.ctime = now();
+        Ok(*attr)
+    }
+
+    async fn mknod(
+        &self,
+        _dir_id: nfs3::fileid3,
+        _name: &nfs3::filename3,
+        _ftype: nfs3::ftype3,
+        _specdata: nfs3::specdata3,
+        _attrs: &nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_NOTSUPP)
+    }
+
+    /// Stores the dirty chunks of the whole file, whatever range is committed.
+    async fn commit(
+        &self,
+        file_id: nfs3::fileid3,
+        _offset: u64,
+        _count: u32,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        self.state.lock().unwrap().commit(file_id)
+    }
+}
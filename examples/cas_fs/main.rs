@@ -0,0 +1,29 @@
+use nfs_mamont::protocol::nfs::v3::WriteCoalescerConfig;
+use nfs_mamont::tcp::{NFSTcp, NFSTcpListener};
+
+/// Content-addressed store of file chunks
+mod blob_store;
+/// Implements the file system on top of the blob store
+mod fs;
+
+/// Port number on which the NFS server will listen
+const HOSTPORT: u32 = 11111;
+
+/// NFS server storing file contents in a deduplicating, content-addressed blob
+/// store. Write coalescing is enabled so `UNSTABLE` writes are acknowledged as
+/// such and their data reaches the store on the client's `COMMIT`.
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::DEBUG)
+        .with_writer(std::io::stderr)
+        .init();
+
+    println!("Starting NFS server on 0.0.0.0:{HOSTPORT}");
+    println!("You can mount it with: sudo mount -o proto=tcp,port={HOSTPORT},mountport={HOSTPORT},nolock,addr=127.0.0.1 127.0.0.1:/ /mnt/nfs");
+
+    let mut listener =
+        NFSTcpListener::bind(&format!("0.0.0.0:{HOSTPORT}"), fs::CasFS::default()).await.unwrap();
+    listener.with_write_coalescing(WriteCoalescerConfig::default());
+    listener.handle_forever().await.unwrap();
+}
@@ -0,0 +1,34 @@
+use nfs_mamont::tcp::{NFSTcp, NFSTcpListener};
+
+/// Implements the compressing file system wrapper
+mod compressed;
+/// In-memory file system of the demo example, used as the underlying store
+#[path = "../demo_fs/fs.rs"]
+mod fs;
+/// Defines the storage representation for file system entries
+#[path = "../demo_fs/fs_contents.rs"]
+mod fs_contents;
+/// Defines the structure for file system entry metadata and content
+#[path = "../demo_fs/fs_entry.rs"]
+mod fs_entry;
+
+/// Port number on which the NFS server will listen
+const HOSTPORT: u32 = 11111;
+
+/// NFS server storing file contents zstd-compressed in an in-memory file system.
+/// Clients see the uncompressed contents; the stored size is reported as the
+/// space used by a file.
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::DEBUG)
+        .with_writer(std::io::stderr)
+        .init();
+
+    println!("Starting NFS server on 0.0.0.0:{HOSTPORT}");
+    println!("You can mount it with: sudo mount -o proto=tcp,port={HOSTPORT},mountport={HOSTPORT},nolock,addr=127.0.0.1 127.0.0.1:/ /mnt/nfs");
+
+    let fs = compressed::CompressedFs::new(fs::DemoFS::default());
+    let listener = NFSTcpListener::bind(&format!("0.0.0.0:{HOSTPORT}"), fs).await.unwrap();
+    listener.handle_forever().await.unwrap();
+}
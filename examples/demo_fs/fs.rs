@@ -1,9 +1,10 @@
-use std::sync::{Arc, Mutex, RwLock};
+use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
 
 use async_trait::async_trait;
 
-use nfs_mamont::vfs;
+use nfs_mamont::clock::{Clock, RequestedTimes, SystemClock};
+use nfs_mamont::vfs::{self, links::LinkCounts};
 use nfs_mamont::xdr::nfs3;
 
 use crate::fs_contents::FSContents;
@@ -13,32 +14,200 @@ use crate::fs_entry::{make_dir, make_file, FSEntry};
 /// Provides a simple in-memory file system that supports basic NFS operations.
 #[derive(Debug)]
 pub struct DemoFS {
-    /// Vector of all file system entries, protected by a mutex for concurrent access
-    fs: Mutex<Vec<FSEntry>>,
+    /// All file system entries, protected by a mutex for concurrent access
+    fs: Mutex<Entries>,
     /// File ID of the root directory
     rootdir: nfs3::fileid3,
     generation: u64,
+    /// Whether this is a snapshot, which clients cannot change
+    read_only: bool,
+}
+
+/// File system entries indexed by file ID, with the link counts of files
+///
+/// Entries are shared with the snapshots taken since their last change and
+/// copied on their next change.
+#[derive(Debug, Clone)]
+struct Entries {
+    /// Entry of every file ID, `None` once the last name of a file is removed
+    entries: Vec<Option<Arc<FSEntry>>>,
+    /// Number of names of every file that is not a directory
+    links: LinkCounts,
+    /// Source of the timestamps of changed files
+    clock: Arc<dyn Clock>,
+}
+
+impl Entries {
+    /// Returns the current time
+    fn now(&self) -> nfs3::nfstime3 {
+        self.clock.now_nfstime()
+    }
+
+    /// Returns the entry of a file
+    fn get(&self, id: nfs3::fileid3) -> Result<&FSEntry, nfs3::nfsstat3> {
+        match self.entries.get(id as usize) {
+            Some(Some(entry)) => Ok(entry),
+            Some(None) => Err(nfs3::nfsstat3::NFS3ERR_STALE),
+            None => Err(nfs3::nfsstat3::NFS3ERR_NOENT),
+        }
+    }
+
+    /// Returns the entry of a file for modification, copying it first if a
+    /// snapshot shares it
+    fn get_mut(&mut self, id: nfs3::fileid3) -> Result<&mut FSEntry, nfs3::nfsstat3> {
+        match self.entries.get_mut(id as usize) {
+            Some(Some(entry)) => Ok(Arc::make_mut(entry)),
+            Some(None) => Err(nfs3::nfsstat3::NFS3ERR_STALE),
+            None => Err(nfs3::nfsstat3::NFS3ERR_NOENT),
+        }
+    }
+
+    /// Returns the names and file IDs in a directory
+    fn dir(
+        &self,
+        dirid: nfs3::fileid3,
+    ) -> Result<&Vec<(nfs3::filename3, nfs3::fileid3)>, nfs3::nfsstat3> {
+        match &self.get(dirid)?.contents {
+            FSContents::Directory(dir) => Ok(dir),
+            FSContents::File(_) => Err(nfs3::nfsstat3::NFS3ERR_NOTDIR),
+        }
+    }
+
+    /// Returns the file ID of a name in a directory, if the name exists
+    fn find(
+        &self,
+        dirid: nfs3::fileid3,
+        name: &nfs3::filename3,
+    ) -> Result<Option<nfs3::fileid3>, nfs3::nfsstat3> {
+        Ok(self.dir(dirid)?.iter().find(|(n, _)| n[..] == name[..]).map(|(_, id)| *id))
+    }
+
+    /// Returns whether a file is a directory
+    fn is_dir(&self, id: nfs3::fileid3) -> bool {
+        self.get(id).is_ok_and(|entry| matches!(entry.contents, FSContents::Directory(_)))
+    }
+
+    /// Adds a new file and returns its ID
+    fn push(&mut self, make: impl FnOnce(nfs3::fileid3) -> FSEntry) -> nfs3::fileid3 {
+        let id = self.entries.len() as nfs3::fileid3;
+        self.entries.push(Some(Arc::new(make(id))));
+        id
+    }
+
+    /// Adds a name for a file to a directory and updates the link counts
+    fn add_name(&mut self, dirid: nfs3::fileid3, name: &nfs3::filename3, id: nfs3::fileid3) {
+        if !self.is_dir(id) {
+            let nlink = self.links.link(id);
+            if let Ok(entry) = self.get_mut(id) {
+                entry.attr.nlink = nlink;
+            }
+        }
+        if let Ok(FSContents::Directory(dir)) = self.get_mut(dirid).map(|e| &mut e.contents) {
+            dir.push((name.clone(), id));
+        }
+        self.dir_changed(dirid);
+    }
+
+    /// Removes a name from a directory and updates the link counts,
+    /// dropping the file once it has no names left
+    fn remove_name(&mut self, dirid: nfs3::fileid3, name: &nfs3::filename3) {
+        let Ok(Some(id)) = self.find(dirid, name) else {
+            return;
+        };
+        if let Ok(FSContents::Directory(dir)) = self.get_mut(dirid).map(|e| &mut e.contents) {
+            dir.retain(|(n, _)| n[..] != name[..]);
+        }
+        if self.is_dir(id) {
+            self.entries[id as usize] = None;
+        } else {
+            match self.links.unlink(id) {
+                0 => self.entries[id as usize] = None,
+                nlink => {
+                    let now = self.now();
+                    if let Ok(entry) = self.get_mut(id) {
+                        entry.attr.nlink = nlink;
+                        entry.attr.ctime = now;
+                    }
+                }
+            }
+        }
+        self.dir_changed(dirid);
+    }
+
+    /// Updates the modification time and link count of a changed directory
+    fn dir_changed(&mut self, dirid: nfs3::fileid3) {
+        let Ok(dir) = self.dir(dirid) else {
+            return;
+        };
+        let subdirectories = dir.iter().filter(|(_, id)| self.is_dir(*id)).count();
+        let now = self.now();
+        if let Ok(entry) = self.get_mut(dirid) {
+            entry.attr.nlink = vfs::links::dir_nlink(subdirectories);
+            entry.attr.mtime = now;
+            entry.attr.ctime = entry.attr.mtime;
+        }
+    }
+
+    /// Returns whether `id` is `ancestor` or one of its descendants
+    fn is_within(&self, mut id: nfs3::fileid3, ancestor: nfs3::fileid3) -> bool {
+        loop {
+            if id == ancestor {
+                return true;
+            }
+            match self.get(id) {
+                Ok(entry) if entry.parent != id => id = entry.parent,
+                _ => return false,
+            }
+        }
+    }
 }
 
 impl Default for DemoFS {
+    /// Creates a new DemoFS with just the root directory, using the system clock.
+    fn default() -> DemoFS {
+        DemoFS::with_clock(Arc::new(SystemClock))
+    }
+}
+
+impl DemoFS {
     /// Creates a new DemoFS with just the root directory.
     ///
     /// Initializes an empty file system with only the special entry at index 0
-    /// and the root directory at index 1.
-    fn default() -> DemoFS {
+    /// and the root directory at index 1. Timestamps of changed files are taken
+    /// from `clock`.
+    pub fn with_clock(clock: Arc<dyn Clock>) -> DemoFS {
         // Create only the root directory without additional files and folders
         let entries = vec![
-            make_file("", 0, 0, &[]), // fileid 0 is special
-            make_dir(
-                "/",
-                1,      // current id. Must match position in entries
-                1,      // parent id
-                vec![], // empty list of children
-            ),
+            None, // fileid 0 is special
+            Some(Arc::new(make_dir(
+                1, // current id. Must match position in entries
+                1, // parent id
+            ))),
         ];
 
         let now = SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis();
-        DemoFS { fs: Mutex::new(entries), rootdir: 1, generation: now as u64 }
+        let fs = Entries { entries, links: LinkCounts::new(), clock };
+        DemoFS { fs: Mutex::new(fs), rootdir: 1, generation: now as u64, read_only: false }
+    }
+
+    /// Returns a read-only copy of the current state of the file system.
+    ///
+    /// The copy shares all entries with this file system and takes no more
+    /// time than copying the list of file IDs; an entry is copied only once
+    /// either side changes it. It can be served by a listener of its own to
+    /// publish the current state, while clients keep changing the original.
+    /// File IDs stay the same, but handles of one are not valid for the other.
+    // unused by the examples wrapping this file system
+    #[allow(dead_code)]
+    pub fn snapshot(&self) -> DemoFS {
+        let fs = self.fs.lock().unwrap().clone();
+        let now = SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos();
+        DemoFS {
+            fs: Mutex::new(fs),
+            rootdir: self.rootdir,
+            generation: now as u64,
+            read_only: true,
+        }
     }
 }
 
@@ -58,9 +227,13 @@ impl vfs::NFSFileSystem for DemoFS {
     }
 
     /// Returns the capabilities of this file system.
-    /// This demo supports both read and write operations.
+    /// This demo supports both read and write operations, snapshots only reads.
     fn capabilities(&self) -> vfs::Capabilities {
-        vfs::Capabilities::ReadWrite
+        if self.read_only {
+            vfs::Capabilities::ReadOnly
+        } else {
+            vfs::Capabilities::ReadWrite
+        }
     }
 
     /// Writes data to a file at the specified offset.
@@ -75,16 +248,15 @@ impl vfs::NFSFileSystem for DemoFS {
             let mut fs = self.fs.lock().unwrap();
 
             // Get file entry and verify it's a file
-            let entry = fs.get_mut(id as usize).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?;
+            let entry = fs.get_mut(id)?;
 
-            let shared_bytes = match &mut entry.contents {
+            let bytes = match &mut entry.contents {
                 FSContents::File(bytes) => bytes,
-                _ => return Err(nfs3::nfsstat3::NFS3ERR_IO),
+                _ => return Err(nfs3::nfsstat3::NFS3ERR_ISDIR),
             };
 
             let new_size = {
                 // Write data to file
-                let mut bytes = shared_bytes.write().unwrap();
                 let offset = offset as usize;
 
                 // Resize if needed and copy data
@@ -96,16 +268,9 @@ impl vfs::NFSFileSystem for DemoFS {
                 bytes.len() as u64
             };
 
-            // Update size for all entries sharing this file
-            let shared_ptr = Arc::as_ptr(shared_bytes);
-            for entry in fs.iter_mut() {
-                if let FSContents::File(b) = &entry.contents {
-                    if Arc::as_ptr(b) == shared_ptr {
-                        entry.attr.size = new_size;
-                        entry.attr.used = new_size;
-                    }
-                }
-            }
+            // Hard links share the entry, so every name sees the new size
+            entry.attr.size = new_size;
+            entry.attr.used = new_size;
         }
 
         self.getattr(id).await
@@ -119,16 +284,13 @@ impl vfs::NFSFileSystem for DemoFS {
         filename: &nfs3::filename3,
         _attr: nfs3::sattr3,
     ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
-        let newid: nfs3::fileid3;
-        {
-            let mut fs = self.fs.lock().unwrap();
-            newid = fs.len() as nfs3::fileid3;
-            fs.push(make_file(std::str::from_utf8(filename).unwrap(), newid, dirid, "".as_bytes()));
-            if let FSContents::Directory(dir) = &mut fs[dirid as usize].contents {
-                dir.push(newid);
-            }
+        let mut fs = self.fs.lock().unwrap();
+        if fs.find(dirid, filename)?.is_some() {
+            return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
         }
-        Ok((newid, self.getattr(newid).await.unwrap()))
+        let newid = fs.push(|id| make_file(id, &[]));
+        fs.add_name(dirid, filename, newid);
+        Ok((newid, fs.get(newid)?.attr))
     }
 
     /// Creates a file exclusively (not supported in this demo).
@@ -148,34 +310,22 @@ impl vfs::NFSFileSystem for DemoFS {
         filename: &nfs3::filename3,
     ) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
         let fs = self.fs.lock().unwrap();
-        let entry = fs.get(dirid as usize).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?;
-        if let FSContents::File(_) = entry.contents {
-            return Err(nfs3::nfsstat3::NFS3ERR_NOTDIR);
-        } else if let FSContents::Directory(dir) = &entry.contents {
-            // if looking for dir/. its the current directory
-            if filename[..] == [b'.'] {
-                return Ok(dirid);
-            }
-            // if looking for dir/.. its the parent directory
-            if filename[..] == [b'.', b'.'] {
-                return Ok(entry.parent);
-            }
-            for i in dir {
-                if let Some(f) = fs.get(*i as usize) {
-                    if f.name[..] == filename[..] {
-                        return Ok(*i);
-                    }
-                }
-            }
+        let found = fs.find(dirid, filename)?;
+        // if looking for dir/. its the current directory
+        if filename[..] == [b'.'] {
+            return Ok(dirid);
         }
-        Err(nfs3::nfsstat3::NFS3ERR_NOENT)
+        // if looking for dir/.. its the parent directory
+        if filename[..] == [b'.', b'.'] {
+            return Ok(fs.get(dirid)?.parent);
+        }
+        found.ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)
     }
 
     /// Gets the attributes of a file system entry.
     async fn getattr(&self, id: nfs3::fileid3) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
         let fs = self.fs.lock().unwrap();
-        let entry = fs.get(id as usize).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?;
-        Ok(entry.attr)
+        Ok(fs.get(id)?.attr)
     }
 
     /// Sets attributes for a file system entry.
@@ -186,51 +336,26 @@ impl vfs::NFSFileSystem for DemoFS {
         setattr: nfs3::sattr3,
     ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
         let mut fs = self.fs.lock().unwrap();
-        let entry = fs.get_mut(id as usize).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?;
-        match setattr.atime {
-            nfs3::set_atime::DONT_CHANGE => {}
-            nfs3::set_atime::SET_TO_CLIENT_TIME(c) => {
-                entry.attr.atime = c;
-            }
-            nfs3::set_atime::SET_TO_SERVER_TIME => {
-                let d = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
-                entry.attr.atime.seconds = d.as_secs() as u32;
-                entry.attr.atime.nseconds = d.subsec_nanos();
-            }
-        };
-        match setattr.mtime {
-            nfs3::set_mtime::DONT_CHANGE => {}
-            nfs3::set_mtime::SET_TO_CLIENT_TIME(c) => {
-                entry.attr.mtime = c;
-            }
-            nfs3::set_mtime::SET_TO_SERVER_TIME => {
-                let d = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
-                entry.attr.mtime.seconds = d.as_secs() as u32;
-                entry.attr.mtime.nseconds = d.subsec_nanos();
-            }
-        };
-        match setattr.uid {
-            nfs3::set_uid3::Some(u) => {
-                entry.attr.uid = u;
-            }
-            nfs3::set_uid3::None => {}
+        let times = RequestedTimes::resolve(&setattr, fs.clock.as_ref());
+        let entry = fs.get_mut(id)?;
+        if let Some(atime) = times.atime {
+            entry.attr.atime = atime;
         }
-        match setattr.gid {
-            nfs3::set_gid3::Some(u) => {
-                entry.attr.gid = u;
-            }
-            nfs3::set_gid3::None => {}
-        }
-        match setattr.size {
-            nfs3::set_size3::Some(s) => {
-                entry.attr.size = s;
-                entry.attr.used = s;
-                if let FSContents::File(shared_bytes) = &mut entry.contents {
-                    let mut bytes = shared_bytes.write().unwrap();
-                    bytes.resize(s as usize, 0);
-                }
+        if let Some(mtime) = times.mtime {
+            entry.attr.mtime = mtime;
+        }
+        if let nfs3::set_uid3::Some(u) = setattr.uid {
+            entry.attr.uid = u;
+        }
+        if let nfs3::set_gid3::Some(u) = setattr.gid {
+            entry.attr.gid = u;
+        }
+        if let nfs3::set_size3::Some(s) = setattr.size {
+            entry.attr.size = s;
+            entry.attr.used = s;
+            if let FSContents::File(bytes) = &mut entry.contents {
+                bytes.resize(s as usize, 0);
             }
-            nfs3::set_size3::None => {}
         }
         Ok(entry.attr)
     }
@@ -244,12 +369,10 @@ impl vfs::NFSFileSystem for DemoFS {
         count: u32,
     ) -> Result<(Vec<u8>, bool), nfs3::nfsstat3> {
         let fs = self.fs.lock().unwrap();
-        let entry = fs.get(id as usize).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?;
+        let entry = fs.get(id)?;
         if let FSContents::Directory(_) = entry.contents {
             return Err(nfs3::nfsstat3::NFS3ERR_ISDIR);
-        } else if let FSContents::File(shared_bytes) = &entry.contents {
-            let bytes = shared_bytes.read().unwrap();
-
+        } else if let FSContents::File(bytes) = &entry.contents {
             let mut start = offset as usize;
             let mut end = offset as usize + count as usize;
             let eof = end >= bytes.len();
@@ -264,100 +387,49 @@ impl vfs::NFSFileSystem for DemoFS {
         Err(nfs3::nfsstat3::NFS3ERR_NOENT)
     }
 
-    /// Reads directory entries, starting after the specified entry ID.
+    /// Lists directories with cookies derived from entry names, as file IDs are
+    /// not unique within a directory that holds several links to one file.
+    fn readdir_cookie_kind(&self) -> vfs::ReadDirCookieKind {
+        vfs::ReadDirCookieKind::Opaque
+    }
+
+    /// Reads directory entries, starting after the entry of the specified cookie.
     /// Returns a list of directory entries and an indicator if there are more entries.
-    async fn readdir(
+    async fn readdir_with_cookie(
         &self,
         dirid: nfs3::fileid3,
-        start_after: nfs3::fileid3,
+        cookie: vfs::ReadDirCookie,
         max_entries: usize,
     ) -> Result<vfs::ReadDirResult, nfs3::nfsstat3> {
         let fs = self.fs.lock().unwrap();
-        let entry = fs.get(dirid as usize).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?;
-        if let FSContents::File(_) = entry.contents {
-            return Err(nfs3::nfsstat3::NFS3ERR_NOTDIR);
-        } else if let FSContents::Directory(dir) = &entry.contents {
-            let mut ret = vfs::ReadDirResult { entries: Vec::new(), end: false };
-            let mut start_index = 0;
-            if start_after > 0 {
-                if let Some(pos) = dir.iter().position(|&r| r == start_after) {
-                    start_index = pos + 1;
-                } else {
-                    return Err(nfs3::nfsstat3::NFS3ERR_BAD_COOKIE);
-                }
-            }
-            let remaining_length = dir.len() - start_index;
-
-            for i in dir[start_index..].iter() {
-                ret.entries.push(vfs::DirEntry {
-                    fileid: *i,
-                    name: fs[(*i) as usize].name.clone(),
-                    attr: fs[(*i) as usize].attr,
-                });
-                if ret.entries.len() >= max_entries {
-                    break;
-                }
-            }
-            if ret.entries.len() == remaining_length {
-                ret.end = true;
-            }
-            return Ok(ret);
-        }
-        Err(nfs3::nfsstat3::NFS3ERR_NOENT)
+        let mut entries = Vec::new();
+        for (name, id) in fs.dir(dirid)? {
+            let attr = fs.get(*id)?.attr;
+            entries.push(vfs::DirEntry { fileid: *id, name: name.clone(), attr });
+        }
+        vfs::readdir::page_by_cookie(entries, cookie, max_entries, |entry| {
+            vfs::readdir::name_cookie(&entry.name)
+        })
     }
 
     /// Removes a file or empty directory from a directory.
+    /// The file itself is dropped once its last name is removed.
     async fn remove(
         &self,
         dirid: nfs3::fileid3,
         filename: &nfs3::filename3,
     ) -> Result<(), nfs3::nfsstat3> {
         let mut fs = self.fs.lock().unwrap();
-        let dir_entry = fs.get(dirid as usize).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?;
-
-        if let FSContents::File(_) = dir_entry.contents {
-            return Err(nfs3::nfsstat3::NFS3ERR_NOTDIR);
-        }
-
-        // Find the file in the directory
-        let file_id = {
-            if let FSContents::Directory(dir) = &fs[dirid as usize].contents {
-                let mut file_id = None;
-                for &id in dir {
-                    if let Some(file) = fs.get(id as usize) {
-                        if file.name[..] == filename[..] {
-                            file_id = Some(id);
-                            break;
-                        }
-                    }
-                }
-                file_id.ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?
-            } else {
-                return Err(nfs3::nfsstat3::NFS3ERR_NOTDIR);
-            }
-        };
-
-        // Check that it's not a directory
-        if let FSContents::Directory(_) = fs[file_id as usize].contents {
-            // If trying to remove a directory using remove, return an error
-            return Err(nfs3::nfsstat3::NFS3ERR_ISDIR);
-        }
-
-        // Remove the file from the directory list
-        if let FSContents::Directory(dir) = &mut fs[dirid as usize].contents {
-            dir.retain(|&id| id != file_id);
+        let file_id = fs.find(dirid, filename)?.ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?;
+        if fs.is_dir(file_id) && !fs.dir(file_id)?.is_empty() {
+            return Err(nfs3::nfsstat3::NFS3ERR_NOTEMPTY);
         }
-
-        // Mark the file as deleted (in a real FS, we would completely remove it)
-        // In our simple implementation, we just clear the name and contents
-        fs[file_id as usize].name = Vec::new().into();
-        fs[file_id as usize].contents = FSContents::File(Arc::new(RwLock::new(Vec::new())));
-
+        fs.remove_name(dirid, filename);
         Ok(())
     }
 
     /// Renames a file or directory from one location to another.
-    /// Handles various edge cases like moving between directories.
+    /// An existing target is replaced, following the POSIX rules.
     async fn rename(
         &self,
         from_dirid: nfs3::fileid3,
@@ -366,75 +438,42 @@ impl vfs::NFSFileSystem for DemoFS {
         to_filename: &nfs3::filename3,
     ) -> Result<(), nfs3::nfsstat3> {
         let mut fs = self.fs.lock().unwrap();
-
-        // Find the file in the source directory
-        let file_id = {
-            if let FSContents::Directory(dir) = &fs[from_dirid as usize].contents {
-                let mut file_id = None;
-                for &id in dir {
-                    if let Some(file) = fs.get(id as usize) {
-                        if file.name[..] == from_filename[..] {
-                            file_id = Some(id);
-                            break;
-                        }
-                    }
+        let file_id = fs.find(from_dirid, from_filename)?.ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?;
+        let target = fs.find(to_dirid, to_filename)?;
+        // Renaming a name onto another name of the same file does nothing
+        if target == Some(file_id) {
+            return Ok(());
+        }
+        let moves_dir = fs.is_dir(file_id);
+        if moves_dir && fs.is_within(to_dirid, file_id) {
+            return Err(nfs3::nfsstat3::NFS3ERR_INVAL);
+        }
+        if let Some(target) = target {
+            match (moves_dir, fs.is_dir(target)) {
+                (true, false) => return Err(nfs3::nfsstat3::NFS3ERR_NOTDIR),
+                (false, true) => return Err(nfs3::nfsstat3::NFS3ERR_ISDIR),
+                (true, true) if !fs.dir(target)?.is_empty() => {
+                    return Err(nfs3::nfsstat3::NFS3ERR_NOTEMPTY)
                 }
-                file_id.ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?
-            } else {
-                return Err(nfs3::nfsstat3::NFS3ERR_NOTDIR);
+                _ => fs.remove_name(to_dirid, to_filename),
             }
-        };
-
-        // Check that the target directory exists
-        if !fs
-            .get(to_dirid as usize)
-            .is_some_and(|entry| matches!(entry.contents, FSContents::Directory(_)))
-        {
-            return Err(nfs3::nfsstat3::NFS3ERR_NOTDIR);
         }
 
-        // Find ID of the file to remove (if it exists)
-        let to_remove_id = if let FSContents::Directory(dir) = &fs[to_dirid as usize].contents {
-            let mut to_remove = None;
-            for &id in dir {
-                if let Some(file) = fs.get(id as usize) {
-                    if file.name[..] == to_filename[..] {
-                        to_remove = Some(id);
-                        break;
-                    }
-                }
-            }
-            to_remove
-        } else {
-            None
-        };
-
-        // If the file exists, remove it from the directory
-        if let Some(id) = to_remove_id {
-            if let FSContents::Directory(dir) = &mut fs[to_dirid as usize].contents {
-                dir.retain(|&x| x != id);
-            }
+        // Move the name without changing the link count of the file
+        if let FSContents::Directory(dir) = &mut fs.get_mut(from_dirid)?.contents {
+            dir.retain(|(n, _)| n[..] != from_filename[..]);
         }
-
-        // If this is a move between directories
-        if from_dirid != to_dirid {
-            // Remove from the old directory
-            if let FSContents::Directory(dir) = &mut fs[from_dirid as usize].contents {
-                dir.retain(|&id| id != file_id);
-            }
-
-            // Add to the new directory
-            if let FSContents::Directory(dir) = &mut fs[to_dirid as usize].contents {
-                dir.push(file_id);
-            }
-
-            // Update the file's parent
-            fs[file_id as usize].parent = to_dirid;
+        if let FSContents::Directory(dir) = &mut fs.get_mut(to_dirid)?.contents {
+            dir.push((to_filename.clone(), file_id));
         }
-
-        // Update the file name
-        fs[file_id as usize].name = to_filename.to_vec().into();
-
+        let now = fs.now();
+        let entry = fs.get_mut(file_id)?;
+        if moves_dir {
+            entry.parent = to_dirid;
+        }
+        entry.attr.ctime = now;
+        fs.dir_changed(from_dirid);
+        fs.dir_changed(to_dirid);
         Ok(())
     }
 
@@ -446,40 +485,17 @@ impl vfs::NFSFileSystem for DemoFS {
     ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
         let mut fs = self.fs.lock().unwrap();
 
-        // Check that the parent directory exists
-        if !fs
-            .get(dirid as usize)
-            .is_some_and(|entry| matches!(entry.contents, FSContents::Directory(_)))
-        {
-            return Err(nfs3::nfsstat3::NFS3ERR_NOTDIR);
+        // Check that the name doesn't already exist
+        if fs.find(dirid, dirname)?.is_some() {
+            return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
         }
 
-        // Check that a directory with this name doesn't already exist
-        if let FSContents::Directory(dir) = &fs[dirid as usize].contents {
-            if dir
-                .iter()
-                .any(|&id| fs.get(id as usize).is_some_and(|file| file.name[..] == dirname[..]))
-            {
-                return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
-            }
-        }
-
-        // Create a new directory
-        let newid = fs.len() as nfs3::fileid3;
-        fs.push(make_dir(std::str::from_utf8(dirname).unwrap(), newid, dirid, Vec::new()));
-
-        // Add the new directory to the parent
-        if let FSContents::Directory(dir) = &mut fs[dirid as usize].contents {
-            dir.push(newid);
-        }
-
-        // Update the parent directory's modification time
-        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
-        fs[dirid as usize].attr.mtime.seconds = now.as_secs() as u32;
-        fs[dirid as usize].attr.mtime.nseconds = now.subsec_nanos();
+        // Create a new directory and add it to the parent
+        let newid = fs.push(|id| make_dir(id, dirid));
+        fs.add_name(dirid, dirname, newid);
 
         // Return the ID and attributes of the new directory
-        Ok((newid, fs[newid as usize].attr))
+        Ok((newid, fs.get(newid)?.attr))
     }
 
     /// Creates a symbolic link pointing to the specified path.
@@ -494,35 +510,21 @@ impl vfs::NFSFileSystem for DemoFS {
         // with contents representing the path the link points to
         let mut fs = self.fs.lock().unwrap();
 
-        // Check that the parent directory exists
-        if !fs
-            .get(dirid as usize)
-            .is_some_and(|entry| matches!(entry.contents, FSContents::Directory(_)))
-        {
-            return Err(nfs3::nfsstat3::NFS3ERR_NOTDIR);
+        // Check that the name doesn't already exist
+        if fs.find(dirid, linkname)?.is_some() {
+            return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
         }
 
         // Create a new file but mark its type as a symbolic link
-        let newid = fs.len() as nfs3::fileid3;
-        let mut entry = make_file(std::str::from_utf8(linkname).unwrap(), newid, dirid, symlink);
-
-        // Change type to symbolic link
-        entry.attr.ftype = nfs3::ftype3::NF3LNK;
-
-        fs.push(entry);
-
-        // Add the new file to the parent directory
-        if let FSContents::Directory(dir) = &mut fs[dirid as usize].contents {
-            dir.push(newid);
-        }
-
-        // Update the parent directory's modification time
-        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
-        fs[dirid as usize].attr.mtime.seconds = now.as_secs() as u32;
-        fs[dirid as usize].attr.mtime.nseconds = now.subsec_nanos();
+        let newid = fs.push(|id| {
+            let mut entry = make_file(id, symlink);
+            entry.attr.ftype = nfs3::ftype3::NF3LNK;
+            entry
+        });
+        fs.add_name(dirid, linkname, newid);
 
         // Return the ID and attributes of the new file
-        Ok((newid, fs[newid as usize].attr))
+        Ok((newid, fs.get(newid)?.attr))
     }
 
     /// Reads the target of a symbolic link.
@@ -530,14 +532,13 @@ impl vfs::NFSFileSystem for DemoFS {
         let fs = self.fs.lock().unwrap();
 
         // Check that the file exists
-        let entry = fs.get(id as usize).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?;
+        let entry = fs.get(id)?;
 
         // Use matching instead of comparing with ftype3::NF3LNK
         match entry.attr.ftype {
             nfs3::ftype3::NF3LNK => {
                 // Get the symbolic link content
-                if let FSContents::File(shared_bytes) = &entry.contents {
-                    let bytes = shared_bytes.read().unwrap();
+                if let FSContents::File(bytes) = &entry.contents {
                     // Convert Vec<u8> to nfspath3
                     return Ok(bytes.to_vec().into());
                 }
@@ -548,6 +549,7 @@ impl vfs::NFSFileSystem for DemoFS {
     }
 
     /// Creates a hard link to an existing file.
+    /// The new name refers to the same file ID, whose link count is raised.
     async fn link(
         &self,
         file_id: nfs3::fileid3,
@@ -556,58 +558,24 @@ impl vfs::NFSFileSystem for DemoFS {
     ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
         let mut fs = self.fs.lock().unwrap();
 
-        // Check that the source file exists
-        let source_file = fs.get(file_id as usize).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?;
-
         // Check that the source is a file, not a directory
-        if let FSContents::Directory(_) = source_file.contents {
+        fs.get(file_id)?;
+        if fs.is_dir(file_id) {
             return Err(nfs3::nfsstat3::NFS3ERR_ISDIR);
         }
 
-        // Check that the target directory exists
-        if !fs
-            .get(target_dir_id as usize)
-            .is_some_and(|entry| matches!(entry.contents, FSContents::Directory(_)))
-        {
-            return Err(nfs3::nfsstat3::NFS3ERR_NOTDIR);
-        }
-
         // Check if a file with the same name already exists in the target directory
-        if let FSContents::Directory(dir) = &fs[target_dir_id as usize].contents {
-            if dir
-                .iter()
-                .any(|&id| fs.get(id as usize).is_some_and(|file| file.name[..] == link_name[..]))
-            {
-                return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
-            }
+        if fs.find(target_dir_id, link_name)?.is_some() {
+            return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
         }
 
-        // Create a new entry for the hard link
-        let newid = fs.len() as nfs3::fileid3;
-
-        let new_entry = FSEntry {
-            id: newid,
-            name: link_name.to_vec().into(),
-            parent: target_dir_id,
-            attr: source_file.attr,
-            contents: source_file.contents.clone(),
-        };
-
-        // Add the new entry to the filesystem
-        fs.push(new_entry);
+        fs.add_name(target_dir_id, link_name, file_id);
+        let now = fs.now();
+        let entry = fs.get_mut(file_id)?;
+        entry.attr.ctime = now;
 
-        // Add the new entry to the target directory
-        if let FSContents::Directory(dir) = &mut fs[target_dir_id as usize].contents {
-            dir.push(newid);
-        }
-
-        // Update the link count of the original file
-        if let Some(entry) = fs.get_mut(file_id as usize) {
-            entry.attr.nlink += 1;
-        }
-
-        // Return the attributes of the new link
-        Ok(fs[newid as usize].attr)
+        // Return the attributes of the linked file
+        Ok(entry.attr)
     }
 
     /// Creates a special device node file.
@@ -621,51 +589,38 @@ impl vfs::NFSFileSystem for DemoFS {
     ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
         let mut fs = self.fs.lock().unwrap();
 
-        // Check that the parent directory exists
-        if !fs
-            .get(dir_id as usize)
-            .is_some_and(|entry| matches!(entry.contents, FSContents::Directory(_)))
-        {
-            return Err(nfs3::nfsstat3::NFS3ERR_NOTDIR);
-        }
-
         // Check if a file with the same name already exists
-        if let FSContents::Directory(dir) = &fs[dir_id as usize].contents {
-            if dir
-                .iter()
-                .any(|&id| fs.get(id as usize).is_some_and(|file| file.name[..] == name[..]))
-            {
-                return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
-            }
+        if fs.find(dir_id, name)?.is_some() {
+            return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
         }
 
         // Create a new entry based on the type
-        let newid = fs.len() as nfs3::fileid3;
+        let newid = fs.entries.len() as nfs3::fileid3;
         let mut entry;
 
         match type_ {
             nfs3::ftype3::NF3REG => {
                 // Regular file
-                entry = make_file(std::str::from_utf8(name).unwrap(), newid, dir_id, &[]);
+                entry = make_file(newid, &[]);
             }
             nfs3::ftype3::NF3DIR => {
                 // Directory
-                entry = make_dir(std::str::from_utf8(name).unwrap(), newid, dir_id, Vec::new());
+                entry = make_dir(newid, dir_id);
             }
             nfs3::ftype3::NF3BLK | nfs3::ftype3::NF3CHR => {
                 // Block or character device
-                entry = make_file(std::str::from_utf8(name).unwrap(), newid, dir_id, &[]);
+                entry = make_file(newid, &[]);
                 entry.attr.ftype = type_;
                 entry.attr.rdev = device_spec;
             }
             nfs3::ftype3::NF3FIFO => {
                 // Named pipe
-                entry = make_file(std::str::from_utf8(name).unwrap(), newid, dir_id, &[]);
+                entry = make_file(newid, &[]);
                 entry.attr.ftype = type_;
             }
             nfs3::ftype3::NF3SOCK => {
                 // Socket
-                entry = make_file(std::str::from_utf8(name).unwrap(), newid, dir_id, &[]);
+                entry = make_file(newid, &[]);
                 entry.attr.ftype = type_;
             }
             _ => {
@@ -687,21 +642,12 @@ impl vfs::NFSFileSystem for DemoFS {
             entry.attr.gid = gid;
         }
 
-        // Add the new entry to the filesystem
-        fs.push(entry);
-
-        // Add the new entry to the parent directory
-        if let FSContents::Directory(dir) = &mut fs[dir_id as usize].contents {
-            dir.push(newid);
-        }
-
-        // Update the parent directory's modification time
-        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
-        fs[dir_id as usize].attr.mtime.seconds = now.as_secs() as u32;
-        fs[dir_id as usize].attr.mtime.nseconds = now.subsec_nanos();
+        // Add the new entry to the filesystem and the parent directory
+        fs.push(|_| entry);
+        fs.add_name(dir_id, name, newid);
 
         // Return the ID and attributes of the new entry
-        Ok((newid, fs[newid as usize].attr))
+        Ok((newid, fs.get(newid)?.attr))
     }
 
     /// Commits any pending writes to stable storage.
@@ -715,17 +661,50 @@ impl vfs::NFSFileSystem for DemoFS {
         // In a real filesystem, this would ensure that the data written
         // to the file is committed to persistent storage.
         // For this demo, we'll just update the file's modification time
-        // and return the attributes.
+        // and return the attributes. Snapshots are left unchanged.
+        if self.read_only {
+            return self.getattr(id).await;
+        }
 
         let mut fs = self.fs.lock().unwrap();
-        let entry = fs.get_mut(id as usize).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?;
+        let now = fs.now();
+        let entry = fs.get_mut(id)?;
 
         // Update the file's modification time
-        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
-        entry.attr.mtime.seconds = now.as_secs() as u32;
-        entry.attr.mtime.nseconds = now.subsec_nanos();
+        entry.attr.mtime = now;
 
         // Return the updated attributes
         Ok(entry.attr)
     }
+
+    /// Reserves space by extending the file with zeroes up to the end of the range.
+    async fn allocate(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        length: u64,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        let size = self.getattr(id).await?.size;
+        let end = offset.saturating_add(length);
+        if end <= size {
+            return self.getattr(id).await;
+        }
+        self.write(id, size, &vec![0; (end - size) as usize]).await
+    }
+
+    /// Zeroes the part of the range that lies inside the file.
+    /// The in-memory contents have no holes, so the file size is kept as is.
+    async fn deallocate(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        length: u64,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        let size = self.getattr(id).await?.size;
+        let end = offset.saturating_add(length).min(size);
+        if offset >= end {
+            return self.getattr(id).await;
+        }
+        self.write(id, offset, &vec![0; (end - offset) as usize]).await
+    }
 }
@@ -1,13 +1,12 @@
-use std::sync::{Arc, RwLock};
-
 use nfs_mamont::xdr::nfs3;
 
 /// Storage representation for file system entries.
 /// Used to represent either file data or directory listings.
 #[derive(Debug, Clone)]
 pub enum FSContents {
-    /// Contains link to file data as a byte vector
-    File(Arc<RwLock<Vec<u8>>>),
-    /// Contains a list of file IDs for directory entries
-    Directory(Vec<nfs3::fileid3>),
+    /// Contains the file data as a byte vector
+    File(Vec<u8>),
+    /// Contains the name and file ID of every directory entry.
+    /// Hard links appear as several names with the same file ID.
+    Directory(Vec<(nfs3::filename3, nfs3::fileid3)>),
 }
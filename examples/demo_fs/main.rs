@@ -1,3 +1,8 @@
+use std::sync::Arc;
+
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use nfs_mamont::config::Config;
 use nfs_mamont::tcp::{NFSTcp, NFSTcpListener};
 
 /// Implements the core file system functionality
@@ -12,6 +17,11 @@ const HOSTPORT: u32 = 11111;
 
 /// Demo NFS server implementation using the nfs-mamont library.
 /// Shows how to create a simple in-memory file system that supports NFS operations.
+///
+/// With `--config <file>`, serves every export of the configuration file with
+/// a file system of its own instead. With `--publish <port>`, also serves a
+/// read-only snapshot of the file system on `port`, taken anew whenever a line
+/// is entered.
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt()
@@ -19,6 +29,18 @@ async fn main() {
         .with_writer(std::io::stderr)
         .init();
 
+    let args: Vec<String> = std::env::args().collect();
+    if let [_, flag, value] = &args[..] {
+        match flag.as_str() {
+            "--config" => {
+                serve_config(&Config::load(value).unwrap_or_else(|e| panic!("{value}: {e}"))).await
+            }
+            "--publish" => publish(value).await,
+            _ => panic!("usage: demofs [--config <file> | --publish <port>]"),
+        }
+        return;
+    }
+
     println!("Starting NFS server on 0.0.0.0:{HOSTPORT}");
     println!("You can mount it with: sudo mount -o proto=tcp,port={HOSTPORT},mountport={HOSTPORT},nolock,addr=127.0.0.1 127.0.0.1:/ /mnt/nfs");
 
@@ -26,3 +48,42 @@ async fn main() {
         NFSTcpListener::bind(&format!("0.0.0.0:{HOSTPORT}"), fs::DemoFS::default()).await.unwrap();
     listener.handle_forever().await.unwrap();
 }
+
+/// Serves every export of a configuration, each with an empty file system
+async fn serve_config(config: &Config) {
+    config.install_redaction();
+    let mut servers = tokio::task::JoinSet::new();
+    for export in &config.exports {
+        let mut listener =
+            NFSTcpListener::bind(&export.bind.to_string(), fs::DemoFS::default()).await.unwrap();
+        export.apply(&mut listener);
+        println!("Serving {} on {}", export.name, export.bind);
+        servers.spawn(async move { listener.handle_forever().await });
+    }
+    while let Some(result) = servers.join_next().await {
+        result.unwrap().unwrap();
+    }
+}
+
+/// Serves a file system, and a snapshot of it on `port` that is replaced by a
+/// new one whenever a line is read from the standard input
+async fn publish(port: &str) {
+    let fs = Arc::new(fs::DemoFS::default());
+    let live = NFSTcpListener::bind_dyn(&format!("0.0.0.0:{HOSTPORT}"), fs.clone()).await.unwrap();
+    tokio::spawn(async move { live.handle_forever().await });
+    println!("Serving on 0.0.0.0:{HOSTPORT}, press Enter to publish its state on port {port}");
+
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut published: Option<tokio::task::JoinHandle<_>> = None;
+    while let Ok(Some(_)) = lines.next_line().await {
+        // stop accepting connections to the previous snapshot to free the port
+        if let Some(task) = published.take() {
+            task.abort();
+            let _ = task.await;
+        }
+        let snapshot =
+            NFSTcpListener::bind(&format!("0.0.0.0:{port}"), fs.snapshot()).await.unwrap();
+        published = Some(tokio::spawn(async move { snapshot.handle_forever().await }));
+        println!("Published the current state on port {port}");
+    }
+}
@@ -0,0 +1,412 @@
+//! File system wrapper storing file contents encrypted.
+//!
+//! The contents of a regular file are split into chunks of [`CHUNK_SIZE`] bytes,
+//! each sealed with AES-256-GCM under a random nonce. A sealed chunk is stored as
+//! the nonce, the ciphertext and the tag, so chunk `i` starts at a fixed offset
+//! of `i` times [`SEALED_CHUNK_SIZE`] and the plaintext size follows from the
+//! stored size alone. The index of a chunk is authenticated with it, so chunks
+//! cannot be reordered within a file without failing decryption.
+//!
+//! Writes re-seal every chunk they touch, reading the chunk first if the write
+//! covers it only partly. Writes past the end of a file seal the zeros of the
+//! gap, so sparse files take their full size in the store.
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use async_trait::async_trait;
+use tokio::sync::RwLock;
+use tracing::warn;
+
+use nfs_mamont::vfs;
+use nfs_mamont::xdr::nfs3;
+
+/// Plaintext size of a chunk
+const CHUNK_SIZE: u64 = 4096;
+/// Length of the nonce stored before a chunk
+const NONCE_LEN: u64 = 12;
+/// Length of the authentication tag stored after a chunk
+const TAG_LEN: u64 = 16;
+/// Stored bytes per chunk besides the plaintext
+const OVERHEAD: u64 = NONCE_LEN + TAG_LEN;
+/// Stored size of a full chunk
+const SEALED_CHUNK_SIZE: u64 = CHUNK_SIZE + OVERHEAD;
+
+/// Generates a random 256-bit key
+pub fn generate_key() -> [u8; 32] {
+    Aes256Gcm::generate_key(OsRng).into()
+}
+
+/// Wraps a file system, encrypting the contents of its regular files
+pub struct EncryptedFs<F> {
+    /// File system holding the encrypted contents
+    inner: F,
+    /// Cipher of all chunks
+    cipher: Aes256Gcm,
+    /// Serializes writes against each other and against reads
+    lock: RwLock<()>,
+}
+
+impl<F: vfs::NFSFileSystem + Send + Sync> EncryptedFs<F> {
+    /// Wraps `inner`, which must be empty or hold files written with the same key
+    pub fn new(inner: F, key: &[u8; 32]) -> Self {
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+        Self { inner, cipher, lock: RwLock::new(()) }
+    }
+
+    /// Seals chunk `index` of a file
+    fn seal(&self, index: u64, plaintext: &[u8]) -> Result<Vec<u8>, nfs3::nfsstat3> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let aad = index.to_be_bytes();
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, Payload { msg: plaintext, aad: &aad })
+            .map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    /// Opens sealed chunk `index` of a file
+    fn open(&self, index: u64, sealed: &[u8]) -> Result<Vec<u8>, nfs3::nfsstat3> {
+        if (sealed.len() as u64) <= OVERHEAD {
+            return Err(nfs3::nfsstat3::NFS3ERR_IO);
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN as usize);
+        let aad = index.to_be_bytes();
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: &aad })
+            .map_err(|_| {
+                warn!("chunk {} failed authentication", index);
+                nfs3::nfsstat3::NFS3ERR_IO
+            })
+    }
+
+    /// Reads and opens chunks `first..=last` of a file of `size` plaintext bytes
+    async fn read_chunks(
+        &self,
+        id: nfs3::fileid3,
+        size: u64,
+        first: u64,
+        last: u64,
+    ) -> Result<Vec<Vec<u8>>, nfs3::nfsstat3> {
+        let start = first * SEALED_CHUNK_SIZE;
+        let end = ((last + 1) * SEALED_CHUNK_SIZE).min(sealed_size(size));
+        let (sealed, _) = self.inner.read(id, start, (end - start) as u32).await?;
+        if sealed.len() as u64 != end - start {
+            return Err(nfs3::nfsstat3::NFS3ERR_IO);
+        }
+        sealed
+            .chunks(SEALED_CHUNK_SIZE as usize)
+            .zip(first..)
+            .map(|(chunk, index)| self.open(index, chunk))
+            .collect()
+    }
+
+    /// Writes `data` at `offset` into a file of `size` plaintext bytes
+    ///
+    /// Seals the zeros between the old end of the file and `offset`, so `data`
+    /// may be empty to extend a file.
+    async fn update(
+        &self,
+        id: nfs3::fileid3,
+        size: u64,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(), nfs3::nfsstat3> {
+        let end = offset + data.len() as u64;
+        let new_size = size.max(end);
+        if new_size == size && data.is_empty() {
+            return Ok(());
+        }
+        let first = offset.min(size) / CHUNK_SIZE;
+        let last = (end - 1) / CHUNK_SIZE;
+        let mut sealed = Vec::new();
+        for index in first..=last {
+            let start = index * CHUNK_SIZE;
+            let len = chunk_len(new_size, index);
+            let mut chunk = if start < size && (offset > start || end < start + len) {
+                self.read_chunks(id, size, index, index).await?.remove(0)
+            } else {
+                Vec::new()
+            };
+            chunk.resize(len as usize, 0);
+            let from = offset.max(start);
+            let to = end.min(start + len);
+            if from < to {
+                chunk[(from - start) as usize..(to - start) as usize]
+                    .copy_from_slice(&data[(from - offset) as usize..(to - offset) as usize]);
+            }
+            sealed.extend_from_slice(&self.seal(index, &chunk)?);
+        }
+        self.inner.write(id, first * SEALED_CHUNK_SIZE, &sealed).await?;
+        Ok(())
+    }
+
+    /// Changes the plaintext size of a file of `old_size` bytes
+    async fn truncate(
+        &self,
+        id: nfs3::fileid3,
+        old_size: u64,
+        size: u64,
+    ) -> Result<(), nfs3::nfsstat3> {
+        if size >= old_size {
+            return self.update(id, old_size, size, &[]).await;
+        }
+        if size % CHUNK_SIZE != 0 {
+            let index = size / CHUNK_SIZE;
+            let mut chunk = self.read_chunks(id, old_size, index, index).await?.remove(0);
+            chunk.truncate(chunk_len(size, index) as usize);
+            self.inner.write(id, index * SEALED_CHUNK_SIZE, &self.seal(index, &chunk)?).await?;
+        }
+        let truncate = nfs3::sattr3 { size: Some(sealed_size(size)), ..Default::default() };
+        self.inner.setattr(id, truncate).await?;
+        Ok(())
+    }
+}
+
+/// Turns attributes of a stored file into those of the plaintext file
+fn translate(mut attr: nfs3::fattr3) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+    if matches!(attr.ftype, nfs3::ftype3::NF3REG) {
+        attr.used = attr.size;
+        attr.size = plaintext_size(attr.size)?;
+    }
+    Ok(attr)
+}
+
+/// Returns the plaintext size of a stored file
+fn plaintext_size(sealed: u64) -> Result<u64, nfs3::nfsstat3> {
+    let rest = sealed % SEALED_CHUNK_SIZE;
+    if rest != 0 && rest <= OVERHEAD {
+        return Err(nfs3::nfsstat3::NFS3ERR_IO);
+    }
+    Ok(sealed / SEALED_CHUNK_SIZE * CHUNK_SIZE + rest.saturating_sub(OVERHEAD))
+}
+
+/// Returns the stored size of a file of `size` plaintext bytes
+fn sealed_size(size: u64) -> u64 {
+    let rest = size % CHUNK_SIZE;
+    size / CHUNK_SIZE * SEALED_CHUNK_SIZE + if rest == 0 { 0 } else { rest + OVERHEAD }
+}
+
+/// Returns the plaintext length of chunk `index` of a file of `size` bytes
+fn chunk_len(size: u64, index: u64) -> u64 {
+    size.saturating_sub(index * CHUNK_SIZE).min(CHUNK_SIZE)
+}
+
+#[async_trait]
+impl<F: vfs::NFSFileSystem + Send + Sync> vfs::NFSFileSystem for EncryptedFs<F> {
+    fn generation(&self) -> u64 {
+        self.inner.generation()
+    }
+
+    fn root_dir(&self) -> nfs3::fileid3 {
+        self.inner.root_dir()
+    }
+
+    fn capabilities(&self) -> vfs::Capabilities {
+        self.inner.capabilities()
+    }
+
+    async fn lookup(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        self.inner.lookup(dirid, filename).await
+    }
+
+    async fn getattr(&self, id: nfs3::fileid3) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        translate(self.inner.getattr(id).await?)
+    }
+
+    async fn setattr(
+        &self,
+        id: nfs3::fileid3,
+        setattr: nfs3::sattr3,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        let _guard = self.lock.write().await;
+        let attr = self.inner.getattr(id).await?;
+        let setattr = match setattr.size {
+            Some(size) if matches!(attr.ftype, nfs3::ftype3::NF3REG) => {
+                self.truncate(id, plaintext_size(attr.size)?, size).await?;
+                nfs3::sattr3 { size: None, ..setattr }
+            }
+            _ => setattr,
+        };
+        translate(self.inner.setattr(id, setattr).await?)
+    }
+
+    async fn read(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        count: u32,
+    ) -> Result<(Vec<u8>, bool), nfs3::nfsstat3> {
+        let _guard = self.lock.read().await;
+        let attr = self.inner.getattr(id).await?;
+        if !matches!(attr.ftype, nfs3::ftype3::NF3REG) {
+            return self.inner.read(id, offset, count).await;
+        }
+        let size = plaintext_size(attr.size)?;
+        if offset >= size || count == 0 {
+            return Ok((Vec::new(), offset >= size));
+        }
+        let end = (offset + u64::from(count)).min(size);
+        let first = offset / CHUNK_SIZE;
+        let chunks = self.read_chunks(id, size, first, (end - 1) / CHUNK_SIZE).await?;
+        let mut data: Vec<u8> = chunks.concat();
+        let skip = (offset - first * CHUNK_SIZE) as usize;
+        data.truncate(skip + (end - offset) as usize);
+        data.drain(..skip);
+        Ok((data, end >= size))
+    }
+
+    async fn write(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        let _guard = self.lock.write().await;
+        let attr = self.inner.getattr(id).await?;
+        if !matches!(attr.ftype, nfs3::ftype3::NF3REG) {
+            return self.inner.write(id, offset, data).await;
+        }
+        if !data.is_empty() {
+            self.update(id, plaintext_size(attr.size)?, offset, data).await?;
+        }
+        translate(self.inner.getattr(id).await?)
+    }
+
+    async fn create(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        attr: nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        let _guard = self.lock.write().await;
+        let (id, created) =
+            self.inner.create(dirid, filename, nfs3::sattr3 { size: None, ..attr }).await?;
+        if let Some(size) = attr.size {
+            self.truncate(id, plaintext_size(created.size)?, size).await?;
+        }
+        Ok((id, translate(self.inner.getattr(id).await?)?))
+    }
+
+    async fn create_exclusive(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        self.inner.create_exclusive(dirid, filename).await
+    }
+
+    async fn mkdir(
+        &self,
+        dirid: nfs3::fileid3,
+        dirname: &nfs3::filename3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        self.inner.mkdir(dirid, dirname).await
+    }
+
+    async fn remove(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> Result<(), nfs3::nfsstat3> {
+        self.inner.remove(dirid, filename).await
+    }
+
+    async fn rename(
+        &self,
+        from_dirid: nfs3::fileid3,
+        from_filename: &nfs3::filename3,
+        to_dirid: nfs3::fileid3,
+        to_filename: &nfs3::filename3,
+    ) -> Result<(), nfs3::nfsstat3> {
+        self.inner.rename(from_dirid, from_filename, to_dirid, to_filename).await
+    }
+
+    fn readdir_cookie_kind(&self) -> vfs::ReadDirCookieKind {
+        self.inner.readdir_cookie_kind()
+    }
+
+    fn readdir_has_attributes(&self) -> bool {
+        self.inner.readdir_has_attributes()
+    }
+
+    async fn readdir_with_cookie(
+        &self,
+        dirid: nfs3::fileid3,
+        cookie: vfs::ReadDirCookie,
+        max_entries: usize,
+    ) -> Result<vfs::ReadDirResult, nfs3::nfsstat3> {
+        let mut result = self.inner.readdir_with_cookie(dirid, cookie, max_entries).await?;
+        for entry in &mut result.entries {
+            entry.attr = translate(entry.attr)?;
+        }
+        Ok(result)
+    }
+
+    async fn symlink(
+        &self,
+        dirid: nfs3::fileid3,
+        linkname: &nfs3::filename3,
+        symlink: &nfs3::nfspath3,
+        attr: &nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        self.inner.symlink(dirid, linkname, symlink, attr).await
+    }
+
+    async fn readlink(&self, id: nfs3::fileid3) -> Result<nfs3::nfspath3, nfs3::nfsstat3> {
+        self.inner.readlink(id).await
+    }
+
+    async fn link(
+        &self,
+        file_id: nfs3::fileid3,
+        link_dir_id: nfs3::fileid3,
+        link_name: &nfs3::filename3,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        translate(self.inner.link(file_id, link_dir_id, link_name).await?)
+    }
+
+    async fn mknod(
+        &self,
+        dir_id: nfs3::fileid3,
+        name: &nfs3::filename3,
+        ftype: nfs3::ftype3,
+        specdata: nfs3::specdata3,
+        attrs: &nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        self.inner.mknod(dir_id, name, ftype, specdata, attrs).await
+    }
+
+    async fn commit(
+        &self,
+        file_id: nfs3::fileid3,
+        offset: u64,
+        count: u32,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        // cover the sealed chunks holding the plaintext range
+        let first = offset / CHUNK_SIZE;
+        let (offset, count) = match count {
+            0 => (first * SEALED_CHUNK_SIZE, 0),
+            _ => {
+                let last = (offset + u64::from(count) - 1) / CHUNK_SIZE;
+                let len = (last + 1 - first) * SEALED_CHUNK_SIZE;
+                (first * SEALED_CHUNK_SIZE, u32::try_from(len).unwrap_or(0))
+            }
+        };
+        translate(self.inner.commit(file_id, offset, count).await?)
+    }
+
+    async fn fsinfo(
+        &self,
+        root_fileid: nfs3::fileid3,
+    ) -> Result<nfs3::fs::fsinfo3, nfs3::nfsstat3> {
+        let mut info = self.inner.fsinfo(root_fileid).await?;
+        info.obj_attributes = self.getattr(root_fileid).await.ok();
+        Ok(info)
+    }
+}
@@ -0,0 +1,51 @@
+use nfs_mamont::tcp::{NFSTcp, NFSTcpListener};
+
+/// Implements the encrypting file system wrapper
+mod encrypted;
+/// In-memory file system of the demo example, used as the underlying store
+#[path = "../demo_fs/fs.rs"]
+mod fs;
+/// Defines the storage representation for file system entries
+#[path = "../demo_fs/fs_contents.rs"]
+mod fs_contents;
+/// Defines the structure for file system entry metadata and content
+#[path = "../demo_fs/fs_entry.rs"]
+mod fs_entry;
+
+/// Port number on which the NFS server will listen
+const HOSTPORT: u32 = 11111;
+
+/// NFS server storing file contents AES-256-GCM encrypted in an in-memory file
+/// system. The key is read as 64 hex digits from `NFS_MAMONT_KEY`; without it, a
+/// random key is used for the lifetime of the process.
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::DEBUG)
+        .with_writer(std::io::stderr)
+        .init();
+
+    println!("Starting NFS server on 0.0.0.0:{HOSTPORT}");
+    println!("You can mount it with: sudo mount -o proto=tcp,port={HOSTPORT},mountport={HOSTPORT},nolock,addr=127.0.0.1 127.0.0.1:/ /mnt/nfs");
+
+    let key = match std::env::var("NFS_MAMONT_KEY") {
+        Ok(hex) => parse_key(&hex).expect("NFS_MAMONT_KEY must be 64 hex digits"),
+        Err(_) => encrypted::generate_key(),
+    };
+    let fs = encrypted::EncryptedFs::new(fs::DemoFS::default(), &key);
+    let listener = NFSTcpListener::bind(&format!("0.0.0.0:{HOSTPORT}"), fs).await.unwrap();
+    listener.handle_forever().await.unwrap();
+}
+
+/// Parses a 256-bit key written as hex digits
+fn parse_key(hex: &str) -> Option<[u8; 32]> {
+    let hex = hex.trim().as_bytes();
+    if hex.len() != 64 {
+        return None;
+    }
+    let mut key = [0; 32];
+    for (byte, digits) in key.iter_mut().zip(hex.chunks_exact(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+    }
+    Some(key)
+}
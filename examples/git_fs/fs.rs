@@ -0,0 +1,366 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use git2::{ObjectType, Oid, Repository, Tree, TreeEntry};
+
+use nfs_mamont::vfs;
+use nfs_mamont::xdr::nfs3;
+
+/// File ID of the root directory
+const ROOT_ID: nfs3::fileid3 = 1;
+/// Git file mode of trees
+const MODE_TREE: i32 = 0o040000;
+/// Git file mode of symbolic links
+const MODE_LINK: i32 = 0o120000;
+/// Git file mode of submodule commits
+const MODE_COMMIT: i32 = 0o160000;
+/// Git file mode of executable blobs
+const MODE_EXECUTABLE: i32 = 0o100755;
+
+/// File, directory or symbolic link of the exported tree
+#[derive(Debug)]
+struct Node {
+    /// Object holding the contents; trees list directories, blobs hold file
+    /// contents and link targets
+    oid: Oid,
+    /// Type of the node as reported to clients
+    ftype: nfs3::ftype3,
+    /// Permission bits
+    mode: u32,
+    /// Size of the blob, 0 for directories
+    size: u64,
+    /// Directory the node was found in
+    parent: nfs3::fileid3,
+}
+
+/// Repository and the nodes found so far
+struct State {
+    /// Repository the objects are read from
+    repo: Repository,
+    /// Nodes by file ID minus one
+    nodes: Vec<Node>,
+    /// File IDs by parent directory and name
+    ids: HashMap<(nfs3::fileid3, Vec<u8>), nfs3::fileid3>,
+}
+
+/// Read-only file system exposing the tree of a git commit.
+///
+/// Trees become directories, blobs become files and symbolic links, and
+/// submodules become empty directories. Git objects have no inode numbers, so
+/// file IDs are handed out as clients discover entries, keyed by directory and
+/// name; an entry keeps its ID for the lifetime of the server. Listings resume
+/// by position in the tree, so paging through a large directory never scans it
+/// from the start.
+pub struct GitFS {
+    /// Repository and nodes, behind one lock as a repository is not `Sync`
+    state: Mutex<State>,
+    /// Commit time, reported as the times of every node
+    time: nfs3::nfstime3,
+    /// Generation number derived from the startup time
+    generation: u64,
+}
+
+impl GitFS {
+    /// Exports the tree of `rev`, such as a branch name or a commit hash
+    pub fn open(path: &str, rev: &str) -> Result<GitFS, git2::Error> {
+        let repo = Repository::open(path)?;
+        let commit = repo.revparse_single(rev)?.peel_to_commit()?;
+        let root = Node {
+            oid: commit.tree_id(),
+            ftype: nfs3::ftype3::NF3DIR,
+            mode: 0o555,
+            size: 0,
+            parent: ROOT_ID,
+        };
+        let time = nfs3::nfstime3 { seconds: commit.time().seconds() as u32, nseconds: 0 };
+        drop(commit);
+        let now = SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis();
+        let state = State { repo, nodes: vec![root], ids: HashMap::new() };
+        Ok(GitFS { state: Mutex::new(state), time, generation: now as u64 })
+    }
+
+    /// Returns the attributes of a node
+    fn attr(&self, id: nfs3::fileid3, node: &Node) -> nfs3::fattr3 {
+        nfs3::fattr3 {
+            ftype: node.ftype,
+            mode: node.mode,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            size: node.size,
+            used: node.size,
+            rdev: nfs3::specdata3::default(),
+            fsid: 0,
+            fileid: id,
+            atime: self.time,
+            mtime: self.time,
+            ctime: self.time,
+        }
+    }
+}
+
+/// Maps errors of the repository to NFS errors
+fn git_error(err: git2::Error) -> nfs3::nfsstat3 {
+    match err.code() {
+        git2::ErrorCode::NotFound => nfs3::nfsstat3::NFS3ERR_NOENT,
+        _ => nfs3::nfsstat3::NFS3ERR_IO,
+    }
+}
+
+impl State {
+    /// Returns the node of a file ID
+    fn node(&self, id: nfs3::fileid3) -> Result<&Node, nfs3::nfsstat3> {
+        let index = id.checked_sub(1).ok_or(nfs3::nfsstat3::NFS3ERR_STALE)?;
+        self.nodes.get(index as usize).ok_or(nfs3::nfsstat3::NFS3ERR_STALE)
+    }
+
+    /// Returns the file ID of an entry of directory `dirid`, assigning one on
+    /// first sight
+    fn child(
+        nodes: &mut Vec<Node>,
+        ids: &mut HashMap<(nfs3::fileid3, Vec<u8>), nfs3::fileid3>,
+        repo: &Repository,
+        dirid: nfs3::fileid3,
+        entry: &TreeEntry<'_>,
+    ) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        let key = (dirid, entry.name_bytes().to_vec());
+        if let Some(&id) = ids.get(&key) {
+            return Ok(id);
+        }
+        let oid = entry.id();
+        let (ftype, mode) = match entry.filemode() {
+            MODE_TREE | MODE_COMMIT => (nfs3::ftype3::NF3DIR, 0o555),
+            MODE_LINK => (nfs3::ftype3::NF3LNK, 0o777),
+            MODE_EXECUTABLE => (nfs3::ftype3::NF3REG, 0o555),
+            _ => (nfs3::ftype3::NF3REG, 0o444),
+        };
+        let size = match entry.kind() {
+            Some(ObjectType::Blob) => {
+                repo.odb().and_then(|odb| odb.read_header(oid)).map_err(git_error)?.0 as u64
+            }
+            _ => 0,
+        };
+        // submodule commits are not in this repository, so they list as empty directories
+        let oid = if entry.filemode() == MODE_COMMIT { Oid::zero() } else { oid };
+        nodes.push(Node { oid, ftype, mode, size, parent: dirid });
+        let id = nodes.len() as nfs3::fileid3;
+        ids.insert(key, id);
+        Ok(id)
+    }
+}
+
+/// Returns the tree listing a directory, `None` for submodules
+fn find_tree(repo: &Repository, oid: Oid) -> Result<Option<Tree<'_>>, nfs3::nfsstat3> {
+    if oid.is_zero() {
+        return Ok(None);
+    }
+    repo.find_tree(oid).map(Some).map_err(git_error)
+}
+
+#[async_trait]
+impl vfs::NFSFileSystem for GitFS {
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn root_dir(&self) -> nfs3::fileid3 {
+        ROOT_ID
+    }
+
+    fn capabilities(&self) -> vfs::Capabilities {
+        vfs::Capabilities::ReadOnly
+    }
+
+    async fn lookup(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        let mut state = self.state.lock().unwrap();
+        let dir = state.node(dirid)?;
+        if !matches!(dir.ftype, nfs3::ftype3::NF3DIR) {
+            return Err(nfs3::nfsstat3::NFS3ERR_NOTDIR);
+        }
+        match &filename[..] {
+            b"." => return Ok(dirid),
+            b".." => return Ok(dir.parent),
+            _ => {}
+        }
+        let oid = dir.oid;
+        let State { repo, nodes, ids } = &mut *state;
+        let tree = find_tree(repo, oid)?.ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?;
+        let entry = tree.get_name_bytes(filename).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?;
+        State::child(nodes, ids, repo, dirid, &entry)
+    }
+
+    async fn getattr(&self, id: nfs3::fileid3) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        Ok(self.attr(id, self.state.lock().unwrap().node(id)?))
+    }
+
+    async fn setattr(
+        &self,
+        _id: nfs3::fileid3,
+        _setattr: nfs3::sattr3,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn read(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        count: u32,
+    ) -> Result<(Vec<u8>, bool), nfs3::nfsstat3> {
+        let state = self.state.lock().unwrap();
+        let node = state.node(id)?;
+        if !matches!(node.ftype, nfs3::ftype3::NF3REG) {
+            return Err(nfs3::nfsstat3::NFS3ERR_ISDIR);
+        }
+        let blob = state.repo.find_blob(node.oid).map_err(git_error)?;
+        let content = blob.content();
+        let start = (offset as usize).min(content.len());
+        let end = (offset as usize).saturating_add(count as usize).min(content.len());
+        Ok((content[start..end].to_vec(), end >= content.len()))
+    }
+
+    async fn write(
+        &self,
+        _id: nfs3::fileid3,
+        _offset: u64,
+        _data: &[u8],
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn create(
+        &self,
+        _dirid: nfs3::fileid3,
+        _filename: &nfs3::filename3,
+        _attr: nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn create_exclusive(
+        &self,
+        _dirid: nfs3::fileid3,
+        _filename: &nfs3::filename3,
+    ) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn mkdir(
+        &self,
+        _dirid: nfs3::fileid3,
+        _dirname: &nfs3::filename3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn remove(
+        &self,
+        _dirid: nfs3::fileid3,
+        _filename: &nfs3::filename3,
+    ) -> Result<(), nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn rename(
+        &self,
+        _from_dirid: nfs3::fileid3,
+        _from_filename: &nfs3::filename3,
+        _to_dirid: nfs3::fileid3,
+        _to_filename: &nfs3::filename3,
+    ) -> Result<(), nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    fn readdir_cookie_kind(&self) -> vfs::ReadDirCookieKind {
+        vfs::ReadDirCookieKind::Index
+    }
+
+    async fn readdir_with_cookie(
+        &self,
+        dirid: nfs3::fileid3,
+        cookie: vfs::ReadDirCookie,
+        max_entries: usize,
+    ) -> Result<vfs::ReadDirResult, nfs3::nfsstat3> {
+        let mut state = self.state.lock().unwrap();
+        let dir = state.node(dirid)?;
+        if !matches!(dir.ftype, nfs3::ftype3::NF3DIR) {
+            return Err(nfs3::nfsstat3::NFS3ERR_NOTDIR);
+        }
+        let oid = dir.oid;
+        let skip = match cookie {
+            vfs::ReadDirCookie::Start => 0,
+            vfs::ReadDirCookie::Index(index) => index as usize,
+            vfs::ReadDirCookie::AfterFileId(_) | vfs::ReadDirCookie::Opaque(_) => {
+                return Err(nfs3::nfsstat3::NFS3ERR_BAD_COOKIE)
+            }
+        };
+        let State { repo, nodes, ids } = &mut *state;
+        let Some(tree) = find_tree(repo, oid)? else {
+            return Ok(vfs::ReadDirResult { entries: Vec::new(), end: true });
+        };
+        let mut ret = vfs::ReadDirResult::default();
+        for index in skip..tree.len().min(skip.saturating_add(max_entries)) {
+            let entry = tree.get(index).ok_or(nfs3::nfsstat3::NFS3ERR_IO)?;
+            let fileid = State::child(nodes, ids, repo, dirid, &entry)?;
+            let attr = self.attr(fileid, &nodes[fileid as usize - 1]);
+            ret.entries.push(vfs::DirEntry { fileid, name: entry.name_bytes().into(), attr });
+        }
+        ret.end = skip + ret.entries.len() >= tree.len();
+        Ok(ret)
+    }
+
+    async fn symlink(
+        &self,
+        _dirid: nfs3::fileid3,
+        _linkname: &nfs3::filename3,
+        _symlink: &nfs3::nfspath3,
+        _attr: &nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn readlink(&self, id: nfs3::fileid3) -> Result<nfs3::nfspath3, nfs3::nfsstat3> {
+        let state = self.state.lock().unwrap();
+        let node = state.node(id)?;
+        if !matches!(node.ftype, nfs3::ftype3::NF3LNK) {
+            return Err(nfs3::nfsstat3::NFS3ERR_INVAL);
+        }
+        let blob = state.repo.find_blob(node.oid).map_err(git_error)?;
+        Ok(blob.content().into())
+    }
+
+    async fn link(
+        &self,
+        _file_id: nfs3::fileid3,
+        _link_dir_id: nfs3::fileid3,
+        _link_name: &nfs3::filename3,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn mknod(
+        &self,
+        _dir_id: nfs3::fileid3,
+        _name: &nfs3::filename3,
+        _ftype: nfs3::ftype3,
+        _specdata: nfs3::specdata3,
+        _attrs: &nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn commit(
+        &self,
+        _file_id: nfs3::fileid3,
+        _offset: u64,
+        _count: u32,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_ROFS)
+    }
+}
@@ -0,0 +1,28 @@
+use nfs_mamont::tcp::{NFSTcp, NFSTcpListener};
+
+/// Implements the file system over a git tree
+mod fs;
+
+/// Port number on which the NFS server will listen
+const HOSTPORT: u32 = 11111;
+
+/// NFS server exporting the tree of a git commit read-only.
+/// Takes the repository path and optionally a revision, `HEAD` by default.
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::DEBUG)
+        .with_writer(std::io::stderr)
+        .init();
+
+    let mut args = std::env::args().skip(1);
+    let path = args.next().expect("must supply a repository path");
+    let rev = args.next().unwrap_or_else(|| "HEAD".to_string());
+    let fs = fs::GitFS::open(&path, &rev).expect("cannot open the revision");
+
+    println!("Exporting {rev} of {path} on 0.0.0.0:{HOSTPORT}");
+    println!("You can mount it with: sudo mount -o ro,proto=tcp,port={HOSTPORT},mountport={HOSTPORT},nolock,addr=127.0.0.1 127.0.0.1:/ /mnt/nfs");
+
+    let listener = NFSTcpListener::bind(&format!("0.0.0.0:{HOSTPORT}"), fs).await.unwrap();
+    listener.handle_forever().await.unwrap();
+}
@@ -1,5 +1,6 @@
 use std::{io, path::Path};
 
+use nfs_mamont::fs_util::nfsstat_from_io_error;
 use nfs_mamont::xdr::nfs3::nfsstat3;
 
 /// Result type for NFS operations
@@ -13,7 +14,7 @@ pub trait ResultExt<T> {
 
 impl<T> ResultExt<T> for Result<T, io::Error> {
     fn or_nfs_error(self) -> NFSResult<T> {
-        self.map_err(|_| nfsstat3::NFS3ERR_IO)
+        self.map_err(|e| nfsstat_from_io_error(&e))
     }
 }
 
@@ -10,7 +10,9 @@ use tokio::fs::{self, File, OpenOptions};
 use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 use tracing::debug;
 
-use nfs_mamont::fs_util::{file_setattr, metadata_to_fattr3, path_setattr};
+use nfs_mamont::fs_util::{
+    self, fattr3_from_metadata, file_setattr, nfsstat_from_io_error, path_setattr,
+};
 use nfs_mamont::vfs;
 use nfs_mamont::xdr::nfs3;
 
@@ -53,11 +55,11 @@ impl MirrorFS {
                 if exists_no_traverse(&path) {
                     return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
                 }
-                fs::create_dir(&path).await.map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
+                fs::create_dir(&path).await.map_err(|e| nfsstat_from_io_error(&e))?;
             }
             CreateFSObject::File(setattr) => {
                 debug!("create {:?}", path);
-                let file = std::fs::File::create(&path).map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
+                let file = std::fs::File::create(&path).map_err(|e| nfsstat_from_io_error(&e))?;
                 let _ = file_setattr(&file, setattr).await;
             }
             CreateFSObject::Exclusive => {
@@ -75,7 +77,7 @@ impl MirrorFS {
                 }
                 fs::symlink(OsStr::from_bytes(target), &path)
                     .await
-                    .map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
+                    .map_err(|e| nfsstat_from_io_error(&e))?;
                 // we do not set attributes on symlinks
             }
         }
@@ -85,7 +87,7 @@ impl MirrorFS {
         let sym = fsmap.intern.intern(objectname_osstr).unwrap();
         let mut name = ent.name.clone();
         name.push(sym);
-        let meta = path.symlink_metadata().map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
+        let meta = path.symlink_metadata().map_err(|e| nfsstat_from_io_error(&e))?;
         let fileid = fsmap.create_entry(&name, meta.clone()).await;
 
         // update the children list
@@ -94,7 +96,7 @@ impl MirrorFS {
         {
             children.insert(fileid);
         }
-        Ok((fileid, metadata_to_fattr3(fileid, &meta)))
+        Ok((fileid, fattr3_from_metadata(fileid, &meta)))
     }
 }
 
@@ -175,9 +177,9 @@ impl vfs::NFSFileSystem for MirrorFS {
         if end > len {
             end = len;
         }
-        f.seek(SeekFrom::Start(start)).await.or(Err(nfs3::nfsstat3::NFS3ERR_IO))?;
+        f.seek(SeekFrom::Start(start)).await.map_err(|e| nfsstat_from_io_error(&e))?;
         let mut buf = vec![0; (end - start) as usize];
-        f.read_exact(&mut buf).await.or(Err(nfs3::nfsstat3::NFS3ERR_IO))?;
+        f.read_exact(&mut buf).await.map_err(|e| nfsstat_from_io_error(&e))?;
         Ok((buf, eof))
     }
 
@@ -234,21 +236,50 @@ impl vfs::NFSFileSystem for MirrorFS {
 
     /// Sets attributes of a file
     async fn setattr(&self, id: nfs3::fileid3, setattr: nfs3::sattr3) -> NFSResult<nfs3::fattr3> {
-        let mut fsmap = self.fsmap.lock().await;
+        self.setattr_wcc(id, setattr).await.map(|(_, attr)| attr)
+    }
+
+    /// The attributes before a change are taken from the file itself
+    fn reports_pre_op_attrs(&self) -> bool {
+        true
+    }
+
+    /// Sets attributes of a file, reporting the ones it had before
+    async fn setattr_wcc(
+        &self,
+        id: nfs3::fileid3,
+        setattr: nfs3::sattr3,
+    ) -> NFSResult<(Option<nfs3::wcc_attr>, nfs3::fattr3)> {
+        let fsmap = self.fsmap.lock().await;
         let entry = fsmap.find_entry(id)?;
         let path = fsmap.sym_to_path(&entry.name).await;
+        // the map is not held while the file system is changed, which may block
+        drop(fsmap);
+        let before =
+            path.symlink_metadata().ok().map(|meta| fattr3_from_metadata(id, &meta).into());
         path_setattr(&path, &setattr).await?;
 
         // I have to lookup a second time to update
-        let metadata = path.symlink_metadata().or(Err(nfs3::nfsstat3::NFS3ERR_IO))?;
+        let metadata = path.symlink_metadata().map_err(|e| nfsstat_from_io_error(&e))?;
+        let mut fsmap = self.fsmap.lock().await;
         if let Ok(entry) = fsmap.find_entry_mut(id) {
-            entry.fsmeta = metadata_to_fattr3(id, &metadata);
+            entry.fsmeta = fattr3_from_metadata(id, &metadata);
         }
-        Ok(metadata_to_fattr3(id, &metadata))
+        Ok((before, fattr3_from_metadata(id, &metadata)))
     }
 
     /// Writes data to a file
     async fn write(&self, id: nfs3::fileid3, offset: u64, data: &[u8]) -> NFSResult<nfs3::fattr3> {
+        self.write_wcc(id, offset, data).await.map(|(_, attr, _)| attr)
+    }
+
+    /// Writes data to a file, reporting the attributes it had before
+    async fn write_wcc(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+    ) -> NFSResult<(Option<nfs3::wcc_attr>, nfs3::fattr3, u32)> {
         let fsmap = self.fsmap.lock().await;
         let ent = fsmap.find_entry(id)?;
         let path = fsmap.sym_to_path(&ent.name).await;
@@ -258,22 +289,23 @@ impl vfs::NFSFileSystem for MirrorFS {
             OpenOptions::new().write(true).create(true).truncate(false).open(&path).await.map_err(
                 |e| {
                     debug!("Unable to open {:?}", e);
-                    nfs3::nfsstat3::NFS3ERR_IO
+                    nfsstat_from_io_error(&e)
                 },
             )?;
+        let before = f.metadata().await.ok().map(|meta| fattr3_from_metadata(id, &meta).into());
         f.seek(SeekFrom::Start(offset)).await.map_err(|e| {
             debug!("Unable to seek {:?}", e);
-            nfs3::nfsstat3::NFS3ERR_IO
+            nfsstat_from_io_error(&e)
         })?;
         f.write_all(data).await.map_err(|e| {
             debug!("Unable to write {:?}", e);
-            nfs3::nfsstat3::NFS3ERR_IO
+            nfsstat_from_io_error(&e)
         })?;
         debug!("write to {:?} {:?} {:?}", path, offset, data.len());
         let _ = f.flush().await;
         let _ = f.sync_all().await;
-        let meta = f.metadata().await.or(Err(nfs3::nfsstat3::NFS3ERR_IO))?;
-        Ok(metadata_to_fattr3(id, &meta))
+        let meta = f.metadata().await.map_err(|e| nfsstat_from_io_error(&e))?;
+        Ok((before, fattr3_from_metadata(id, &meta), data.len() as u32))
     }
 
     /// Creates a file in a directory
@@ -303,9 +335,9 @@ impl vfs::NFSFileSystem for MirrorFS {
         path.push(OsStr::from_bytes(filename));
         if let Ok(meta) = path.symlink_metadata() {
             if meta.is_dir() {
-                fs::remove_dir(&path).await.map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
+                fs::remove_dir(&path).await.map_err(|e| nfsstat_from_io_error(&e))?;
             } else {
-                fs::remove_file(&path).await.map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
+                fs::remove_file(&path).await.map_err(|e| nfsstat_from_io_error(&e))?;
             }
 
             let filesym = fsmap.intern.intern(OsStr::from_bytes(filename).to_os_string()).unwrap();
@@ -359,7 +391,7 @@ impl vfs::NFSFileSystem for MirrorFS {
             return Err(nfs3::nfsstat3::NFS3ERR_NOENT);
         }
         debug!("Rename {:?} to {:?}", from_path, to_path);
-        fs::rename(&from_path, &to_path).await.map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
+        fs::rename(&from_path, &to_path).await.map_err(|e| nfsstat_from_io_error(&e))?;
 
         let oldsym = fsmap.intern.intern(OsStr::from_bytes(from_filename).to_os_string()).unwrap();
         let newsym = fsmap.intern.intern(OsStr::from_bytes(to_filename).to_os_string()).unwrap();
@@ -460,13 +492,13 @@ impl vfs::NFSFileSystem for MirrorFS {
         }
 
         // Create the hard link
-        fs::hard_link(&source_path, &target_path).await.map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
+        fs::hard_link(&source_path, &target_path).await.map_err(|e| nfsstat_from_io_error(&e))?;
 
         // Update the directory listing
         let sym = fsmap.intern.intern(link_name_osstr).unwrap();
         let mut name = dir_entry.name.clone();
         name.push(sym);
-        let meta = target_path.symlink_metadata().map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
+        let meta = target_path.symlink_metadata().map_err(|e| nfsstat_from_io_error(&e))?;
         let new_fileid = fsmap.create_entry(&name, meta.clone()).await;
 
         // Update the children list
@@ -486,7 +518,7 @@ impl vfs::NFSFileSystem for MirrorFS {
         dir_id: nfs3::fileid3,
         name: &nfs3::filename3,
         ftype: nfs3::ftype3,
-        _specdata: nfs3::specdata3,
+        specdata: nfs3::specdata3,
         attrs: &nfs3::sattr3,
     ) -> NFSResult<(nfs3::fileid3, nfs3::fattr3)> {
         let mut fsmap = self.fsmap.lock().await;
@@ -500,150 +532,23 @@ impl vfs::NFSFileSystem for MirrorFS {
             return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
         }
 
-        // Create the special file based on its type
-        match ftype {
-            nfs3::ftype3::NF3CHR => {
-                // Character device
-                let mode = match attrs.mode {
-                    nfs3::set_mode3::Some(m) => m,
-                    _ => 0o666,
-                };
-
-                // Create a regular file as a placeholder
-                fs::OpenOptions::new()
-                    .write(true)
-                    .truncate(true)
-                    .create(true)
-                    .open(&path)
-                    .await
-                    .map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
-
-                #[cfg(unix)]
-                {
-                    use std::os::unix::fs::PermissionsExt;
-                    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode))
-                        .map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
-
-                    // Set ownership if provided
-                    if let nfs3::set_uid3::Some(uid) = attrs.uid {
-                        if let nfs3::set_gid3::Some(gid) = attrs.gid {
-                            std::os::unix::fs::chown(&path, Some(uid), Some(gid))
-                                .map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
-                        }
-                    }
-                }
-            }
-            nfs3::ftype3::NF3BLK => {
-                // Block device
-                let mode = match attrs.mode {
-                    nfs3::set_mode3::Some(m) => m,
-                    _ => 0o666,
-                };
-
-                // Create a regular file as a placeholder
-                fs::OpenOptions::new()
-                    .write(true)
-                    .truncate(true)
-                    .create(true)
-                    .open(&path)
-                    .await
-                    .map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
-
-                #[cfg(unix)]
-                {
-                    use std::os::unix::fs::PermissionsExt;
-                    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode))
-                        .map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
-
-                    // Set ownership if provided
-                    if let nfs3::set_uid3::Some(uid) = attrs.uid {
-                        if let nfs3::set_gid3::Some(gid) = attrs.gid {
-                            std::os::unix::fs::chown(&path, Some(uid), Some(gid))
-                                .map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
-                        }
-                    }
-                }
-            }
-            nfs3::ftype3::NF3FIFO => {
-                // Named pipe (FIFO)
-                let mode = match attrs.mode {
-                    nfs3::set_mode3::Some(m) => m,
-                    _ => 0o666,
-                };
-
-                #[cfg(unix)]
-                {
-                    use std::os::unix::fs::PermissionsExt;
-                    // Create a regular file as a placeholder since mkfifo is not available
-                    fs::OpenOptions::new()
-                        .write(true)
-                        .create(true)
-                        .truncate(true)
-                        .open(&path)
-                        .await
-                        .map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
-
-                    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode))
-                        .map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
-
-                    // Set ownership if provided
-                    if let nfs3::set_uid3::Some(uid) = attrs.uid {
-                        if let nfs3::set_gid3::Some(gid) = attrs.gid {
-                            std::os::unix::fs::chown(&path, Some(uid), Some(gid))
-                                .map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
-                        }
-                    }
-                }
-                #[cfg(not(unix))]
-                {
-                    return Err(nfsstat3::NFS3ERR_NOTSUPP);
-                }
-            }
-            nfs3::ftype3::NF3SOCK => {
-                // Socket
-                let mode = match attrs.mode {
-                    nfs3::set_mode3::Some(m) => m,
-                    _ => 0o666,
-                };
-
-                #[cfg(unix)]
-                {
-                    use std::os::unix::fs::PermissionsExt;
-                    // Create a regular file as a placeholder since mksock is not available
-                    fs::OpenOptions::new()
-                        .write(true)
-                        .create(true)
-                        .truncate(true)
-                        .open(&path)
-                        .await
-                        .map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
-
-                    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode))
-                        .map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
-
-                    // Set ownership if provided
-                    if let nfs3::set_uid3::Some(uid) = attrs.uid {
-                        if let nfs3::set_gid3::Some(gid) = attrs.gid {
-                            std::os::unix::fs::chown(&path, Some(uid), Some(gid))
-                                .map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
-                        }
-                    }
-                }
-                #[cfg(not(unix))]
-                {
-                    return Err(nfsstat3::NFS3ERR_NOTSUPP);
-                }
-            }
-            _ => {
-                return Err(nfs3::nfsstat3::NFS3ERR_BADTYPE);
-            }
+        let mode = match attrs.mode {
+            nfs3::set_mode3::Some(m) => m,
+            _ => 0o666,
+        };
+        fs_util::mknod(&path, ftype, specdata, mode)?;
+
+        // Set ownership if provided, the owner and group independently
+        if attrs.uid.is_some() || attrs.gid.is_some() {
+            std::os::unix::fs::chown(&path, attrs.uid, attrs.gid)
+                .map_err(|e| nfsstat_from_io_error(&e))?;
         }
 
         // Update the directory listing
         let sym = fsmap.intern.intern(name_osstr).unwrap();
         let mut full_name = dir_entry.name.clone();
         full_name.push(sym);
-        let meta = path.symlink_metadata().map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
+        let meta = path.symlink_metadata().map_err(|e| nfsstat_from_io_error(&e))?;
         let fileid = fsmap.create_entry(&full_name, meta.clone()).await;
 
         // Update the children list
@@ -654,7 +559,7 @@ impl vfs::NFSFileSystem for MirrorFS {
         }
 
         // Return the file ID and attributes
-        Ok((fileid, metadata_to_fattr3(fileid, &meta)))
+        Ok((fileid, fattr3_from_metadata(fileid, &meta)))
     }
 
     /// Commits changes to a file
@@ -35,7 +35,8 @@ impl FSMap {
     /// Creates a new file system map with the given root path
     pub fn new(root: PathBuf) -> Self {
         // create root entry
-        let root_entry = FSEntry::new(Vec::new(), metadata_to_fattr3(1, &root.metadata().unwrap()));
+        let root_entry =
+            FSEntry::new(Vec::new(), fattr3_from_metadata(1, &root.metadata().unwrap()));
 
         Self {
             root,
@@ -119,8 +120,8 @@ impl FSMap {
             return Ok(RefreshResult::Delete);
         }
 
-        let meta = fs::symlink_metadata(&path).await.map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?;
-        let meta = metadata_to_fattr3(id, &meta);
+        let meta = fs::symlink_metadata(&path).await.map_err(|e| nfsstat_from_io_error(&e))?;
+        let meta = fattr3_from_metadata(id, &meta);
         if !fattr3_differ(&meta, &entry.fsmeta) {
             return Ok(RefreshResult::Noop);
         }
@@ -164,7 +165,7 @@ impl FSMap {
 
         if let Ok(mut listing) = fs::read_dir(&path).await {
             while let Some(entry) =
-                listing.next_entry().await.map_err(|_| nfs3::nfsstat3::NFS3ERR_IO)?
+                listing.next_entry().await.map_err(|e| nfsstat_from_io_error(&e))?
             {
                 let sym = self.intern.intern(entry.file_name()).unwrap();
                 cur_path.push(sym);
@@ -185,13 +186,13 @@ impl FSMap {
     pub async fn create_entry(&mut self, fullpath: &Vec<Symbol>, meta: Metadata) -> nfs3::fileid3 {
         let next_id = if let Some(chid) = self.path_to_id.get(fullpath) {
             if let Some(chent) = self.id_to_path.get_mut(chid) {
-                chent.fsmeta = metadata_to_fattr3(*chid, &meta);
+                chent.fsmeta = fattr3_from_metadata(*chid, &meta);
             }
             *chid
         } else {
             // path does not exist
             let next_id = self.next_fileid.fetch_add(1, Ordering::Relaxed);
-            let metafattr = metadata_to_fattr3(next_id, &meta);
+            let metafattr = fattr3_from_metadata(next_id, &meta);
             let new_entry = FSEntry::new(fullpath.clone(), metafattr);
             debug!("creating new entry {:?}: {:?}", next_id, meta);
             self.id_to_path.insert(next_id, new_entry);
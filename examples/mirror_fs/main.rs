@@ -1,5 +1,6 @@
 use std::path::PathBuf;
 
+use nfs_mamont::config::Config;
 use nfs_mamont::tcp::{NFSTcp, NFSTcpListener};
 
 const HOSTPORT: u32 = 11111;
@@ -15,6 +16,9 @@ pub mod fs_map;
 /// This function initializes the tracing subscriber, reads the directory path
 /// from command line arguments, creates a MirrorFS instance, and starts
 /// an NFS server on the specified port.
+///
+/// With `--config <file>`, mirrors the `path` of every export of the
+/// configuration file instead.
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt()
@@ -22,6 +26,13 @@ async fn main() {
         .with_writer(std::io::stderr)
         .init();
 
+    let args: Vec<String> = std::env::args().collect();
+    if let [_, flag, path] = &args[..] {
+        assert_eq!(flag, "--config", "usage: mirrorfs <directory> | mirrorfs --config <file>");
+        serve_config(&Config::load(path).unwrap_or_else(|e| panic!("{path}: {e}"))).await;
+        return;
+    }
+
     let path = std::env::args().nth(1).expect("must supply directory to mirror");
     let path = PathBuf::from(path);
 
@@ -29,3 +40,19 @@ async fn main() {
     let listener = NFSTcpListener::bind(&format!("127.0.0.1:{HOSTPORT}"), fs).await.unwrap();
     listener.handle_forever().await.unwrap();
 }
+
+/// Serves every export of a configuration, mirroring its `path`
+async fn serve_config(config: &Config) {
+    config.install_redaction();
+    let mut servers = tokio::task::JoinSet::new();
+    for export in &config.exports {
+        let path = export.path.clone().unwrap_or_else(|| panic!("{}: no path", export.name));
+        let addr = export.bind.to_string();
+        let mut listener = NFSTcpListener::bind(&addr, fs::MirrorFS::new(path)).await.unwrap();
+        export.apply(&mut listener);
+        servers.spawn(async move { listener.handle_forever().await });
+    }
+    while let Some(result) = servers.join_next().await {
+        result.unwrap().unwrap();
+    }
+}
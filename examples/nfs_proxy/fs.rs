@@ -0,0 +1,390 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use tracing::warn;
+
+use nfs_mamont::client::{Client, ClientError};
+use nfs_mamont::vfs::{self, NFSFileSystem as _};
+use nfs_mamont::xdr::nfs3;
+
+/// Largest READDIRPLUS reply requested from the upstream server
+const READDIR_MAXCOUNT: u32 = 64 * 1024;
+
+/// Upstream handles and directory positions learned so far
+#[derive(Default)]
+struct Handles {
+    /// Upstream file handle of each file ID seen
+    fhs: HashMap<nfs3::fileid3, nfs3::nfs_fh3>,
+    /// Upstream cookie and verifier continuing a listing after an entry,
+    /// keyed by directory and entry file ID
+    cookies: HashMap<(nfs3::fileid3, nfs3::fileid3), (nfs3::cookie3, nfs3::cookieverf3)>,
+}
+
+/// File system forwarding every operation to an upstream NFSv3 server.
+///
+/// File IDs are the upstream ones, so attributes pass through unchanged.
+/// Upstream handles are remembered when lookups and listings return them
+/// and are never evicted.
+pub struct ProxyFS {
+    /// Connection to the upstream server
+    client: Client,
+    /// File ID of the mounted upstream directory
+    root: nfs3::fileid3,
+    /// Handles and cookies learned from the upstream server
+    handles: Mutex<Handles>,
+    /// Generation number, changed on every start since handles are forgotten
+    generation: u64,
+}
+
+impl ProxyFS {
+    /// Re-exports the directory with handle `root` on the upstream server
+    pub async fn new(client: Client, root: nfs3::nfs_fh3) -> Result<ProxyFS, ClientError> {
+        let attr = client.getattr(&root).await?;
+        let mut handles = Handles::default();
+        handles.fhs.insert(attr.fileid, root);
+        let now = SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis();
+        Ok(ProxyFS {
+            client,
+            root: attr.fileid,
+            handles: Mutex::new(handles),
+            generation: now as u64,
+        })
+    }
+
+    /// Returns the upstream handle of a file ID
+    fn fh(&self, id: nfs3::fileid3) -> Result<nfs3::nfs_fh3, nfs3::nfsstat3> {
+        self.handles.lock().unwrap().fhs.get(&id).cloned().ok_or(nfs3::nfsstat3::NFS3ERR_STALE)
+    }
+
+    /// Records an upstream handle, fetching its attributes if the reply
+    /// carrying it had none
+    async fn learn(
+        &self,
+        fh: nfs3::nfs_fh3,
+        attr: nfs3::post_op_attr,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        let attr = match attr {
+            Some(attr) => attr,
+            None => self.client.getattr(&fh).await.map_err(status)?,
+        };
+        self.handles.lock().unwrap().fhs.insert(attr.fileid, fh);
+        Ok((attr.fileid, attr))
+    }
+
+    /// Records the result of a call creating `name` in `dir`
+    async fn learn_created(
+        &self,
+        dir: &nfs3::nfs_fh3,
+        name: &nfs3::filename3,
+        created: (nfs3::post_op_fh3, nfs3::post_op_attr),
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        match created {
+            (Some(fh), attr) => self.learn(fh, attr).await,
+            (None, _) => {
+                let (fh, attr) = self.client.lookup(dir, name).await.map_err(status)?;
+                self.learn(fh, attr).await
+            }
+        }
+    }
+
+    /// Returns the current attributes unless `attr` already holds them
+    async fn attr_or_fetch(
+        &self,
+        id: nfs3::fileid3,
+        attr: nfs3::post_op_attr,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        match attr {
+            Some(attr) => Ok(attr),
+            None => self.getattr(id).await,
+        }
+    }
+}
+
+/// Maps a failed upstream call to the status returned to the client
+fn status(err: ClientError) -> nfs3::nfsstat3 {
+    match err {
+        ClientError::Nfs(stat) => stat,
+        err => {
+            warn!("upstream call failed: {err}");
+            nfs3::nfsstat3::NFS3ERR_IO
+        }
+    }
+}
+
+#[async_trait]
+impl vfs::NFSFileSystem for ProxyFS {
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn capabilities(&self) -> vfs::Capabilities {
+        vfs::Capabilities::ReadWrite
+    }
+
+    fn root_dir(&self) -> nfs3::fileid3 {
+        self.root
+    }
+
+    async fn lookup(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        let dir = self.fh(dirid)?;
+        let (fh, attr) = self.client.lookup(&dir, filename).await.map_err(status)?;
+        Ok(self.learn(fh, attr).await?.0)
+    }
+
+    async fn getattr(&self, id: nfs3::fileid3) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        self.client.getattr(&self.fh(id)?).await.map_err(status)
+    }
+
+    async fn setattr(
+        &self,
+        id: nfs3::fileid3,
+        setattr: nfs3::sattr3,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        let attr = self.client.setattr(&self.fh(id)?, setattr).await.map_err(status)?;
+        self.attr_or_fetch(id, attr).await
+    }
+
+    async fn read(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        count: u32,
+    ) -> Result<(Vec<u8>, bool), nfs3::nfsstat3> {
+        let res = self.client.read(&self.fh(id)?, offset, count).await.map_err(status)?;
+        Ok((res.data, res.eof))
+    }
+
+    async fn write(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        let fh = self.fh(id)?;
+        let mut written = 0;
+        // The upstream server may accept fewer bytes than sent
+        while written < data.len() {
+            let res = self
+                .client
+                .write(
+                    &fh,
+                    offset + written as u64,
+                    &data[written..],
+                    nfs3::file::stable_how::UNSTABLE,
+                )
+                .await
+                .map_err(status)?;
+            if res.count == 0 {
+                return Err(nfs3::nfsstat3::NFS3ERR_IO);
+            }
+            written += res.count as usize;
+        }
+        self.getattr(id).await
+    }
+
+    async fn write_partial(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(nfs3::fattr3, u32), nfs3::nfsstat3> {
+        // A partial upstream write is passed on, the client sends the rest
+        let res = self
+            .client
+            .write(&self.fh(id)?, offset, data, nfs3::file::stable_how::UNSTABLE)
+            .await
+            .map_err(status)?;
+        if res.count == 0 && !data.is_empty() {
+            return Err(nfs3::nfsstat3::NFS3ERR_IO);
+        }
+        let attr = match res.file_wcc.after {
+            Some(attr) => attr,
+            None => self.getattr(id).await?,
+        };
+        Ok((attr, res.count.min(data.len() as u32)))
+    }
+
+    async fn create(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        attr: nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        let dir = self.fh(dirid)?;
+        let created = self.client.create(&dir, filename, attr, None).await.map_err(status)?;
+        self.learn_created(&dir, filename, created).await
+    }
+
+    async fn create_exclusive(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        let dir = self.fh(dirid)?;
+        let verf = Some(self.generation.to_le_bytes());
+        let created = self
+            .client
+            .create(&dir, filename, nfs3::sattr3::default(), verf)
+            .await
+            .map_err(status)?;
+        Ok(self.learn_created(&dir, filename, created).await?.0)
+    }
+
+    async fn mkdir(
+        &self,
+        dirid: nfs3::fileid3,
+        dirname: &nfs3::filename3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        let dir = self.fh(dirid)?;
+        let created =
+            self.client.mkdir(&dir, dirname, nfs3::sattr3::default()).await.map_err(status)?;
+        self.learn_created(&dir, dirname, created).await
+    }
+
+    async fn remove(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> Result<(), nfs3::nfsstat3> {
+        let dir = self.fh(dirid)?;
+        // NFS removes directories with a procedure of their own
+        let (fh, attr) = self.client.lookup(&dir, filename).await.map_err(status)?;
+        let (_, attr) = self.learn(fh, attr).await?;
+        if matches!(attr.ftype, nfs3::ftype3::NF3DIR) {
+            self.client.rmdir(&dir, filename).await.map_err(status)
+        } else {
+            self.client.remove(&dir, filename).await.map_err(status)
+        }
+    }
+
+    async fn rename(
+        &self,
+        from_dirid: nfs3::fileid3,
+        from_filename: &nfs3::filename3,
+        to_dirid: nfs3::fileid3,
+        to_filename: &nfs3::filename3,
+    ) -> Result<(), nfs3::nfsstat3> {
+        let from_dir = self.fh(from_dirid)?;
+        let to_dir = self.fh(to_dirid)?;
+        self.client.rename(&from_dir, from_filename, &to_dir, to_filename).await.map_err(status)
+    }
+
+    async fn readdir(
+        &self,
+        dirid: nfs3::fileid3,
+        start_after: nfs3::fileid3,
+        max_entries: usize,
+    ) -> Result<vfs::ReadDirResult, nfs3::nfsstat3> {
+        let dir = self.fh(dirid)?;
+        let known = self.handles.lock().unwrap().cookies.get(&(dirid, start_after)).copied();
+        // Without a remembered cookie the listing restarts and skips entries
+        // up to `start_after`
+        let (mut cookie, mut cookieverf, mut skipping) = match known {
+            Some((cookie, cookieverf)) => (cookie, cookieverf, false),
+            None => (0, nfs3::cookieverf3::default(), start_after != 0),
+        };
+        let mut ret = vfs::ReadDirResult { entries: Vec::new(), end: false };
+        loop {
+            let res = self
+                .client
+                .readdirplus(&dir, cookie, cookieverf, READDIR_MAXCOUNT)
+                .await
+                .map_err(status)?;
+            cookieverf = res.cookieverf;
+            let count = res.entries.len();
+            for (i, entry) in res.entries.into_iter().enumerate() {
+                cookie = entry.cookie;
+                if skipping {
+                    skipping = entry.fileid != start_after;
+                    continue;
+                }
+                if entry.name.0 == b"." || entry.name.0 == b".." {
+                    continue;
+                }
+                let fileid = match entry.name_handle {
+                    Some(fh) => self.learn(fh, entry.name_attributes).await?.0,
+                    None => self.lookup(dirid, &entry.name).await?,
+                };
+                let attr = self.attr_or_fetch(fileid, entry.name_attributes).await?;
+                self.handles.lock().unwrap().cookies.insert((dirid, fileid), (cookie, cookieverf));
+                ret.entries.push(vfs::DirEntry { fileid, name: entry.name, attr });
+                if ret.entries.len() >= max_entries {
+                    ret.end = res.eof && i + 1 == count;
+                    return Ok(ret);
+                }
+            }
+            if res.eof {
+                if skipping {
+                    return Err(nfs3::nfsstat3::NFS3ERR_BAD_COOKIE);
+                }
+                ret.end = true;
+                return Ok(ret);
+            }
+        }
+    }
+
+    async fn symlink(
+        &self,
+        dirid: nfs3::fileid3,
+        linkname: &nfs3::filename3,
+        symlink: &nfs3::nfspath3,
+        attr: &nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        let dir = self.fh(dirid)?;
+        let created = self.client.symlink(&dir, linkname, symlink, *attr).await.map_err(status)?;
+        self.learn_created(&dir, linkname, created).await
+    }
+
+    async fn readlink(&self, id: nfs3::fileid3) -> Result<nfs3::nfspath3, nfs3::nfsstat3> {
+        self.client.readlink(&self.fh(id)?).await.map_err(status)
+    }
+
+    async fn link(
+        &self,
+        file_id: nfs3::fileid3,
+        link_dir_id: nfs3::fileid3,
+        link_name: &nfs3::filename3,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        let fh = self.fh(file_id)?;
+        let dir = self.fh(link_dir_id)?;
+        let attr = self.client.link(&fh, &dir, link_name).await.map_err(status)?;
+        self.attr_or_fetch(file_id, attr).await
+    }
+
+    async fn mknod(
+        &self,
+        dir_id: nfs3::fileid3,
+        name: &nfs3::filename3,
+        ftype: nfs3::ftype3,
+        specdata: nfs3::specdata3,
+        attrs: &nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        let dir = self.fh(dir_id)?;
+        let created =
+            self.client.mknod(&dir, name, ftype, specdata, *attrs).await.map_err(status)?;
+        self.learn_created(&dir, name, created).await
+    }
+
+    async fn commit(
+        &self,
+        file_id: nfs3::fileid3,
+        offset: u64,
+        count: u32,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        let res = self.client.commit(&self.fh(file_id)?, offset, count).await.map_err(status)?;
+        self.attr_or_fetch(file_id, res.file_wcc.after).await
+    }
+
+    async fn fsinfo(
+        &self,
+        root_fileid: nfs3::fileid3,
+    ) -> Result<nfs3::fs::fsinfo3, nfs3::nfsstat3> {
+        self.client.fsinfo(&self.fh(root_fileid)?).await.map_err(status)
+    }
+}
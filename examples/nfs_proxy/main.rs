@@ -0,0 +1,47 @@
+use nfs_mamont::client;
+use nfs_mamont::tcp::{NFSTcp, NFSTcpListener};
+
+/// Implements the file system forwarding to the upstream server
+mod fs;
+
+/// Port number on which the NFS server will listen
+const HOSTPORT: u32 = 11111;
+
+/// NFS server re-exporting a directory of an upstream NFSv3 server.
+/// Takes the upstream address, the exported path and optionally the address
+/// of the upstream MOUNT service when it listens on another port.
+/// Calls are made with `AUTH_UNIX` credentials of `NFS_PROXY_UID` and
+/// `NFS_PROXY_GID`, root by default.
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::DEBUG)
+        .with_writer(std::io::stderr)
+        .init();
+
+    let mut args = std::env::args().skip(1);
+    let upstream = args.next().expect("must supply the upstream address");
+    let export = args.next().expect("must supply the upstream export path");
+    let mount_addr = args.next().unwrap_or_else(|| upstream.clone());
+
+    let id = |var: &str| std::env::var(var).map_or(0, |v| v.parse().expect("invalid id"));
+    let (uid, gid) = (id("NFS_PROXY_UID"), id("NFS_PROXY_GID"));
+
+    let mount_client = client::Client::connect(&mount_addr)
+        .await
+        .expect("cannot connect to the upstream MOUNT service")
+        .with_auth_unix(uid, gid, vec![gid]);
+    let root =
+        mount_client.mount(export.as_bytes()).await.expect("cannot mount the upstream export");
+    let nfs_client = client::Client::connect(&upstream)
+        .await
+        .expect("cannot connect to the upstream server")
+        .with_auth_unix(uid, gid, vec![gid]);
+    let fs = fs::ProxyFS::new(nfs_client, root).await.expect("cannot reach the upstream export");
+
+    println!("Re-exporting {upstream}:{export} on 0.0.0.0:{HOSTPORT}");
+    println!("You can mount it with: sudo mount -o proto=tcp,port={HOSTPORT},mountport={HOSTPORT},nolock,addr=127.0.0.1 127.0.0.1:/ /mnt/nfs");
+
+    let listener = NFSTcpListener::bind(&format!("0.0.0.0:{HOSTPORT}"), fs).await.unwrap();
+    listener.handle_forever().await.unwrap();
+}
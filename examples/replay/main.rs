@@ -0,0 +1,43 @@
+use std::io::BufReader;
+use std::sync::Arc;
+
+use nfs_mamont::protocol::rpc::{read_capture, Replay};
+use nfs_mamont::vfs::testing;
+
+/// In-memory file system of the demo example, replayed against
+#[path = "../demo_fs/fs.rs"]
+mod fs;
+/// Defines the storage representation for file system entries
+#[path = "../demo_fs/fs_contents.rs"]
+mod fs_contents;
+/// Defines the structure for file system entry metadata and content
+#[path = "../demo_fs/fs_entry.rs"]
+mod fs_entry;
+
+/// Replays a JSON capture of a demo file system server against a fresh one and
+/// lists the calls whose replies differ in status from the recorded ones.
+///
+/// Record a capture of the demo server with `NFSTcpListener::with_capture` and
+/// `CaptureFormat::Json`, then run `cargo run --example replay -- <capture>`.
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let [_, path] = &args[..] else {
+        panic!("usage: replay <capture.jsonl>");
+    };
+    let file = std::fs::File::open(path).unwrap_or_else(|e| panic!("{path}: {e}"));
+    let records = read_capture(BufReader::new(file)).unwrap();
+
+    let context = testing::context(Arc::new(fs::DemoFS::default()));
+    let calls = Replay::default().run(&records, &context).await.unwrap();
+    let mut diverged = 0;
+    for call in &calls {
+        let mark = if call.diverged() { "!" } else { " " };
+        println!(
+            "{mark} xid {:>6} (recorded {:>10}) program {} procedure {:>2}: status {:?}, recorded {:?}",
+            call.xid, call.recorded_xid, call.program, call.procedure, call.status, call.recorded_status
+        );
+        diverged += usize::from(call.diverged());
+    }
+    println!("{} calls replayed, {} diverged", calls.len(), diverged);
+}
@@ -0,0 +1,237 @@
+//! Command line server exporting a local directory over NFSv3.
+//!
+//! Lets the crate be tried without writing any Rust:
+//!
+//! ```text
+//! nfs-mamont serve [OPTIONS] <DIRECTORY>
+//! ```
+//!
+//! The directory is served by the backend of the `mirrorfs` example. Built
+//! with the `serve` feature only.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use nfs_mamont::config::{ExportConfig, Limits};
+use nfs_mamont::privileges::{self, Identity};
+use nfs_mamont::protocol::rpc::{Squash, SquashMode};
+use nfs_mamont::tcp::{NFSTcp, NFSTcpListener};
+
+#[path = "../../examples/mirror_fs/create_fs_object.rs"]
+pub mod create_fs_object;
+#[path = "../../examples/mirror_fs/error_handling.rs"]
+pub mod error_handling;
+#[path = "../../examples/mirror_fs/fs.rs"]
+pub mod fs;
+#[path = "../../examples/mirror_fs/fs_entry.rs"]
+pub mod fs_entry;
+#[path = "../../examples/mirror_fs/fs_map.rs"]
+pub mod fs_map;
+
+const USAGE: &str = "\
+Usage: nfs-mamont serve [OPTIONS] <DIRECTORY>
+
+Exports DIRECTORY over NFSv3. MOUNT is served on the same port as NFS.
+
+Options:
+  --bind <ADDR>          Address to listen on [default: 0.0.0.0]
+  --port <PORT>          Port to listen on [default: 11111]
+  --portmapper           Also serve the portmapper on port 111 if permitted
+  --export <NAME>        Path clients mount [default: /]
+  --read-only            Refuse all changes with NFS3ERR_ROFS
+  --no-readdirplus       Refuse READDIRPLUS so clients fall back to READDIR
+  --root-squash          Map root to the anonymous user
+  --all-squash           Map every user to the anonymous user
+  --anonuid <UID>        User ID of the anonymous user [default: 65534]
+  --anongid <GID>        Group ID of the anonymous user [default: 65534]
+  --user <USER[:GROUP]>  Switch to this user once the port is bound
+  --sandbox              Confine file access to DIRECTORY with Landlock (Linux)
+  --log <LEVEL>          One of error, warn, info, debug, trace [default: info]
+  -h, --help             Print this help";
+
+/// Settings given on the command line
+struct Options {
+    /// Directory to export
+    directory: PathBuf,
+    /// Address to listen on
+    bind: SocketAddr,
+    /// Whether the portmapper is served on port 111
+    portmapper: bool,
+    /// Path clients mount
+    export: String,
+    /// Whether changes are refused
+    read_only: bool,
+    /// Whether `READDIRPLUS` is served
+    readdirplus: bool,
+    /// Mapping of client identities
+    squash: Squash,
+    /// User to switch to after binding, if any
+    user: Option<Identity>,
+    /// Whether file access is confined to the directory
+    sandbox: bool,
+    /// Most verbose level logged
+    log: tracing::Level,
+}
+
+/// Parses the arguments following `serve`
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut directory = None;
+    let mut host = "0.0.0.0".to_string();
+    let mut port = 11111_u16;
+    let mut portmapper = false;
+    let mut export = "/".to_string();
+    let mut read_only = false;
+    let mut readdirplus = true;
+    let mut squash = Squash::default();
+    let mut user = None;
+    let mut sandbox = false;
+    let mut log = tracing::Level::INFO;
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{name} needs a value"));
+        match arg.as_str() {
+            "--bind" => host = value("--bind")?,
+            "--port" => port = value("--port")?.parse().map_err(|e| format!("--port: {e}"))?,
+            "--portmapper" => portmapper = true,
+            "--export" => export = value("--export")?,
+            "--read-only" => read_only = true,
+            "--no-readdirplus" => readdirplus = false,
+            "--root-squash" => squash.mode = SquashMode::Root,
+            "--all-squash" => squash.mode = SquashMode::All,
+            "--anonuid" => {
+                squash.anon_uid =
+                    value("--anonuid")?.parse().map_err(|e| format!("--anonuid: {e}"))?
+            }
+            "--anongid" => {
+                squash.anon_gid =
+                    value("--anongid")?.parse().map_err(|e| format!("--anongid: {e}"))?
+            }
+            "--user" => user = Some(value("--user")?.parse().map_err(|e| format!("--user: {e}"))?),
+            "--sandbox" => sandbox = true,
+            "--log" => log = value("--log")?.parse().map_err(|e| format!("--log: {e}"))?,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+            flag if flag.starts_with('-') => return Err(format!("unknown option {flag}")),
+            _ if directory.is_none() => directory = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument {arg}")),
+        }
+    }
+
+    let directory = directory.ok_or("no directory given")?;
+    let ip = host.parse().map_err(|e| format!("--bind: {e}"))?;
+    Ok(Options {
+        directory,
+        bind: SocketAddr::new(ip, port),
+        portmapper,
+        export,
+        read_only,
+        readdirplus,
+        squash,
+        user,
+        sandbox,
+        log,
+    })
+}
+
+/// Confines the file access of the process to the directory
+///
+/// Runs before the runtime starts, so that its threads are confined as well.
+#[cfg(target_os = "linux")]
+fn enter_sandbox(options: &Options) -> std::io::Result<()> {
+    use nfs_mamont::sandbox::{Access, Sandbox, SandboxStatus};
+
+    let access = if options.read_only { Access::ReadOnly } else { Access::ReadWrite };
+    match Sandbox::new().allow(&options.directory, access).enforce()? {
+        SandboxStatus::Enforced { .. } => Ok(()),
+        SandboxStatus::Unsupported => Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "--sandbox needs Landlock, which this kernel does not provide",
+        )),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn enter_sandbox(_: &Options) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "--sandbox needs Linux"))
+}
+
+/// Serves the directory until the listener fails
+async fn serve(options: Options) -> std::io::Result<()> {
+    let directory = options.directory.canonicalize()?;
+    if options.bind.port() < 1024 && !privileges::can_bind_privileged_ports() {
+        let msg = format!("port {} needs root or CAP_NET_BIND_SERVICE", options.bind.port());
+        return Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, msg));
+    }
+    let fs = fs::MirrorFS::new(directory.clone());
+    let mut listener = NFSTcpListener::bind(&options.bind.to_string(), fs).await?;
+    if options.portmapper {
+        let port = nfs_mamont::xdr::portmap::PMAP_PORT as u16;
+        if let Err(err) = listener.bind_portmapper(port).await {
+            tracing::warn!("not serving the portmapper: {err}");
+        }
+    }
+    if let Some(user) = options.user {
+        privileges::drop_privileges(user)?;
+    }
+    let export = ExportConfig {
+        name: options.export,
+        bind: options.bind,
+        path: Some(directory),
+        read_only: options.read_only,
+        clients: Vec::new(),
+        limits: Limits::default(),
+        readdirplus: options.readdirplus,
+        public: false,
+        ntfs: Default::default(),
+        creation: None,
+    };
+    export.apply(&mut listener);
+    listener.with_squash(options.squash);
+
+    tracing::info!(
+        "exporting {} as {} on {}{}",
+        options.directory.display(),
+        export.name,
+        options.bind,
+        if export.read_only { " (read-only)" } else { "" }
+    );
+    listener.handle_forever().await
+}
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let options = match args.next().as_deref() {
+        Some("serve") => parse_options(args),
+        Some("-h" | "--help") => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        _ => Err("expected the serve command".to_string()),
+    };
+    let options = match options {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("error: {err}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    tracing_subscriber::fmt().with_max_level(options.log).with_writer(std::io::stderr).init();
+    if options.sandbox {
+        if let Err(err) = enter_sandbox(&options) {
+            eprintln!("error: {err}");
+            return ExitCode::FAILURE;
+        }
+    }
+    let runtime = tokio::runtime::Runtime::new().expect("failed to start the runtime");
+    match runtime.block_on(serve(options)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,557 @@
+//! NFSv3 client for talking to remote servers.
+//!
+//! [`Client`] issues `MOUNT` and `NFS` calls over a TCP connection, encoding
+//! arguments and decoding results with the same XDR types the server uses.
+//! It is meant for tools and tests that need both directions from one crate,
+//! such as proxies re-exporting another server:
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), nfs_mamont::client::ClientError> {
+//! use nfs_mamont::client::Client;
+//!
+//! let client = Client::connect("127.0.0.1:11111").await?.with_auth_unix(1000, 1000, vec![]);
+//! let root = client.mount(b"/").await?;
+//! let (file, _) = client.lookup(&root, &b"hello.txt"[..].into()).await?;
+//! let data = client.read(&file, 0, 4096).await?.data;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Calls share one connection and are issued one at a time. Procedures that
+//! fail on the server return [`ClientError::Nfs`] with the status, leaving
+//! the rest of the reply unread.
+//!
+//! Against an nfs-mamont server offering it, [`Client::negotiate_compression`]
+//! has large replies sent compressed, see [`crate::protocol::rpc::ReplyCompression`].
+
+use std::fmt;
+use std::io::{self, Cursor};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+use crate::entropy::{Entropy, SystemEntropy};
+use crate::protocol::rpc::{self, read_fragment, write_fragment};
+use crate::xdr::mount::{self, mountstat3, MountProgram};
+use crate::xdr::nfs3::{self, NFSProgram};
+use crate::xdr::rpc::{
+    accept_body, auth_flavor, auth_unix, call_body, opaque_auth, reply_body, rpc_body, rpc_msg,
+};
+use crate::xdr::{deserialize, Deserialize, Serialize};
+
+/// Error of a call to a remote server
+#[derive(Debug)]
+pub enum ClientError {
+    /// The connection failed or the reply could not be decoded
+    Io(io::Error),
+    /// The server did not accept the RPC call
+    Rpc(String),
+    /// The MOUNT procedure failed
+    Mount(mountstat3),
+    /// The NFS procedure failed
+    Nfs(nfs3::nfsstat3),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::Io(err) => write!(f, "transport error: {err}"),
+            ClientError::Rpc(reason) => write!(f, "call rejected: {reason}"),
+            ClientError::Mount(stat) => write!(f, "mount failed: {stat:?}"),
+            ClientError::Nfs(stat) => write!(f, "NFS call failed: {stat:?}"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<io::Error> for ClientError {
+    fn from(err: io::Error) -> Self {
+        ClientError::Io(err)
+    }
+}
+
+/// Entries returned by one READDIR call
+#[derive(Debug, Default)]
+pub struct ReadDir {
+    /// Attributes of the listed directory
+    pub dir_attributes: nfs3::post_op_attr,
+    /// Verifier to send back with the next cookie
+    pub cookieverf: nfs3::cookieverf3,
+    /// Entries of this batch
+    pub entries: Vec<nfs3::dir::entry3>,
+    /// Whether the listing is complete
+    pub eof: bool,
+}
+
+/// Entries returned by one READDIRPLUS call
+#[derive(Debug, Default)]
+pub struct ReadDirPlus {
+    /// Attributes of the listed directory
+    pub dir_attributes: nfs3::post_op_attr,
+    /// Verifier to send back with the next cookie
+    pub cookieverf: nfs3::cookieverf3,
+    /// Entries of this batch
+    pub entries: Vec<nfs3::dir::entryplus3>,
+    /// Whether the listing is complete
+    pub eof: bool,
+}
+
+/// NFSv3 client speaking ONC RPC over a single TCP connection.
+/// Calls are serialized on the connection, one outstanding call at a time.
+pub struct Client {
+    /// Connection to the server
+    stream: Mutex<TcpStream>,
+    /// Transaction ID of the next call
+    xid: AtomicU32,
+    /// Credentials sent with every call
+    cred: opaque_auth,
+    /// Longest compressed reply accepted, uncompressed
+    max_record_length: usize,
+}
+
+impl Client {
+    /// Connects to a server, sending `AUTH_NULL` credentials
+    pub async fn connect(addr: &str) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        Ok(Client {
+            stream: Mutex::new(stream),
+            xid: AtomicU32::new(SystemEntropy::default().next_u32()),
+            cred: opaque_auth::default(),
+            max_record_length: rpc::DEFAULT_MAX_RECORD_LENGTH,
+        })
+    }
+
+    /// Accepts compressed replies of up to `len` bytes uncompressed
+    ///
+    /// The default is [`rpc::DEFAULT_MAX_RECORD_LENGTH`], the limit of the
+    /// server for records it receives. Compressed replies claiming to be longer
+    /// fail with an error of kind `InvalidData` before they are decompressed.
+    pub fn with_max_record_length(mut self, len: usize) -> Self {
+        self.max_record_length = len;
+        self
+    }
+
+    /// Draws the transaction ID of the next call from `entropy`
+    ///
+    /// Transaction IDs start at a random value by default, so that calls of an
+    /// earlier connection are not answered from the reply cache of the server.
+    pub fn with_entropy(self, entropy: &dyn Entropy) -> Self {
+        self.xid.store(entropy.next_u32(), Ordering::Relaxed);
+        self
+    }
+
+    /// Sends `AUTH_UNIX` credentials with the given user and groups
+    pub fn with_auth_unix(mut self, uid: u32, gid: u32, gids: Vec<u32>) -> Self {
+        let cred = auth_unix { stamp: 0, machinename: b"nfs-mamont".to_vec(), uid, gid, gids };
+        let mut body = Vec::new();
+        cred.serialize(&mut body).expect("serializing to memory cannot fail");
+        self.cred = opaque_auth { flavor: auth_flavor::AUTH_UNIX, body };
+        self
+    }
+
+    /// Issues a call and returns the procedure-specific part of the reply
+    async fn call(
+        &self,
+        prog: u32,
+        vers: u32,
+        proc: u32,
+        args: &[u8],
+    ) -> Result<Cursor<Vec<u8>>, ClientError> {
+        let xid = self.xid.fetch_add(1, Ordering::Relaxed);
+        let msg = rpc_msg {
+            xid,
+            body: rpc_body::CALL(call_body {
+                rpcvers: 2,
+                prog,
+                vers,
+                proc,
+                cred: self.cred.clone(),
+                verf: opaque_auth::default(),
+            }),
+        };
+        let mut request = Vec::with_capacity(args.len() + 128);
+        msg.serialize(&mut request)?;
+        request.extend_from_slice(args);
+
+        let mut stream = self.stream.lock().await;
+        write_fragment(&mut *stream, &request).await.map_err(io::Error::other)?;
+        loop {
+            let mut reply = Vec::new();
+            while !read_fragment(&mut *stream, &mut reply).await.map_err(io::Error::other)? {}
+            let mut reply = Cursor::new(rpc::decompress_record(reply, self.max_record_length)?);
+            let msg = deserialize::<rpc_msg>(&mut reply)?;
+            // Skip stale replies to calls abandoned by a cancelled caller
+            if msg.xid != xid {
+                continue;
+            }
+            return match msg.body {
+                rpc_body::REPLY(reply_body::MSG_ACCEPTED(accepted)) => match accepted.reply_data {
+                    accept_body::SUCCESS => Ok(reply),
+                    other => Err(ClientError::Rpc(format!("{other:?}"))),
+                },
+                rpc_body::REPLY(reply_body::MSG_DENIED(rejected)) => {
+                    Err(ClientError::Rpc(format!("{rejected:?}")))
+                }
+                rpc_body::CALL(_) => Err(ClientError::Rpc("received a call".to_string())),
+            };
+        }
+    }
+
+    /// Asks the server to compress large replies from now on
+    ///
+    /// Should be called before other calls, as replies to calls already under
+    /// way may arrive compressed or not.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<rpc::Algorithm>, ClientError>` - The algorithm the
+    ///   server chose, `None` if it compresses nothing, e.g. because it does
+    ///   not know the extension
+    pub async fn negotiate_compression(&self) -> Result<Option<rpc::Algorithm>, ClientError> {
+        let mut args = Vec::new();
+        let offered: Vec<u32> = rpc::Algorithm::supported().iter().map(|a| *a as u32).collect();
+        offered.serialize(&mut args)?;
+        let (prog, vers) = (rpc::COMPRESSION_PROGRAM, rpc::COMPRESSION_VERSION);
+        match self.call(prog, vers, rpc::COMPRESSION_PROC_NEGOTIATE, &args).await {
+            Ok(mut reply) => Ok(rpc::Algorithm::from_id(deserialize::<u32>(&mut reply)?)),
+            // servers without the extension do not know the program
+            Err(ClientError::Rpc(_)) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Issues an NFS call and checks the status of the reply
+    async fn nfs_call(
+        &self,
+        proc: NFSProgram,
+        args: &[u8],
+    ) -> Result<Cursor<Vec<u8>>, ClientError> {
+        let mut reply = self.call(nfs3::PROGRAM, nfs3::VERSION, proc as u32, args).await?;
+        let mut stat = nfs3::nfsstat3::NFS3_OK;
+        stat.deserialize(&mut reply)?;
+        match stat {
+            nfs3::nfsstat3::NFS3_OK => Ok(reply),
+            stat => Err(ClientError::Nfs(stat)),
+        }
+    }
+
+    /// Mounts an exported path, returning the handle of its root directory
+    pub async fn mount(&self, path: &[u8]) -> Result<nfs3::nfs_fh3, ClientError> {
+        let mut args = Vec::new();
+        path.serialize(&mut args)?;
+        let proc = MountProgram::MOUNTPROC3_MNT as u32;
+        let mut reply = self.call(mount::PROGRAM, mount::VERSION, proc, &args).await?;
+        let mut stat = mountstat3::MNT3_OK;
+        stat.deserialize(&mut reply)?;
+        if !matches!(stat, mountstat3::MNT3_OK) {
+            return Err(ClientError::Mount(stat));
+        }
+        Ok(nfs3::nfs_fh3 { data: deserialize(&mut reply)? })
+    }
+
+    /// GETATTR: returns the attributes of an object
+    pub async fn getattr(&self, fh: &nfs3::nfs_fh3) -> Result<nfs3::fattr3, ClientError> {
+        let mut args = Vec::new();
+        fh.serialize(&mut args)?;
+        let mut reply = self.nfs_call(NFSProgram::NFSPROC3_GETATTR, &args).await?;
+        Ok(deserialize(&mut reply)?)
+    }
+
+    /// SETATTR: changes the attributes of an object, returning the new ones
+    pub async fn setattr(
+        &self,
+        fh: &nfs3::nfs_fh3,
+        attr: nfs3::sattr3,
+    ) -> Result<nfs3::post_op_attr, ClientError> {
+        let args = nfs3::SETATTR3args { object: fh.clone(), new_attribute: attr, guard: None };
+        let mut buf = Vec::new();
+        args.serialize(&mut buf)?;
+        let mut reply = self.nfs_call(NFSProgram::NFSPROC3_SETATTR, &buf).await?;
+        Ok(deserialize::<nfs3::wcc_data>(&mut reply)?.after)
+    }
+
+    /// LOOKUP: resolves a name in a directory
+    pub async fn lookup(
+        &self,
+        dir: &nfs3::nfs_fh3,
+        name: &nfs3::filename3,
+    ) -> Result<(nfs3::nfs_fh3, nfs3::post_op_attr), ClientError> {
+        let mut args = Vec::new();
+        diropargs(dir, name).serialize(&mut args)?;
+        let mut reply = self.nfs_call(NFSProgram::NFSPROC3_LOOKUP, &args).await?;
+        let fh = deserialize(&mut reply)?;
+        let attr = deserialize(&mut reply)?;
+        Ok((fh, attr))
+    }
+
+    /// READLINK: returns the target of a symbolic link
+    pub async fn readlink(&self, fh: &nfs3::nfs_fh3) -> Result<nfs3::nfspath3, ClientError> {
+        let mut args = Vec::new();
+        fh.serialize(&mut args)?;
+        let mut reply = self.nfs_call(NFSProgram::NFSPROC3_READLINK, &args).await?;
+        deserialize::<nfs3::post_op_attr>(&mut reply)?;
+        Ok(deserialize(&mut reply)?)
+    }
+
+    /// READ: reads up to `count` bytes at `offset`
+    pub async fn read(
+        &self,
+        fh: &nfs3::nfs_fh3,
+        offset: u64,
+        count: u32,
+    ) -> Result<nfs3::file::READ3resok, ClientError> {
+        let mut args = Vec::new();
+        nfs3::file::READ3args { file: fh.clone(), offset, count }.serialize(&mut args)?;
+        let mut reply = self.nfs_call(NFSProgram::NFSPROC3_READ, &args).await?;
+        Ok(deserialize(&mut reply)?)
+    }
+
+    /// WRITE: writes `data` at `offset`
+    pub async fn write(
+        &self,
+        fh: &nfs3::nfs_fh3,
+        offset: u64,
+        data: &[u8],
+        stable: nfs3::file::stable_how,
+    ) -> Result<nfs3::file::WRITE3resok, ClientError> {
+        let args = nfs3::file::WRITE3args {
+            file: fh.clone(),
+            offset,
+            count: data.len() as u32,
+            stable: stable as u32,
+            data: data.to_vec(),
+        };
+        let mut buf = Vec::with_capacity(data.len() + 128);
+        args.serialize(&mut buf)?;
+        let mut reply = self.nfs_call(NFSProgram::NFSPROC3_WRITE, &buf).await?;
+        Ok(deserialize(&mut reply)?)
+    }
+
+    /// CREATE: creates a regular file, `UNCHECKED` unless `verf` asks for
+    /// an exclusive create
+    pub async fn create(
+        &self,
+        dir: &nfs3::nfs_fh3,
+        name: &nfs3::filename3,
+        attr: nfs3::sattr3,
+        verf: Option<nfs3::createverf3>,
+    ) -> Result<(nfs3::post_op_fh3, nfs3::post_op_attr), ClientError> {
+        let mut args = Vec::new();
+        diropargs(dir, name).serialize(&mut args)?;
+        match verf {
+            Some(verf) => {
+                nfs3::createmode3::EXCLUSIVE.serialize(&mut args)?;
+                verf.serialize(&mut args)?;
+            }
+            None => {
+                nfs3::createmode3::UNCHECKED.serialize(&mut args)?;
+                attr.serialize(&mut args)?;
+            }
+        }
+        let reply = self.nfs_call(NFSProgram::NFSPROC3_CREATE, &args).await?;
+        created(reply)
+    }
+
+    /// MKDIR: creates a directory
+    pub async fn mkdir(
+        &self,
+        dir: &nfs3::nfs_fh3,
+        name: &nfs3::filename3,
+        attr: nfs3::sattr3,
+    ) -> Result<(nfs3::post_op_fh3, nfs3::post_op_attr), ClientError> {
+        let args = nfs3::dir::MKDIR3args { dirops: diropargs(dir, name), attributes: attr };
+        let mut buf = Vec::new();
+        args.serialize(&mut buf)?;
+        let reply = self.nfs_call(NFSProgram::NFSPROC3_MKDIR, &buf).await?;
+        created(reply)
+    }
+
+    /// SYMLINK: creates a symbolic link pointing to `target`
+    pub async fn symlink(
+        &self,
+        dir: &nfs3::nfs_fh3,
+        name: &nfs3::filename3,
+        target: &nfs3::nfspath3,
+        attr: nfs3::sattr3,
+    ) -> Result<(nfs3::post_op_fh3, nfs3::post_op_attr), ClientError> {
+        let args = nfs3::dir::SYMLINK3args {
+            dirops: diropargs(dir, name),
+            symlink: nfs3::symlinkdata3 { symlink_attributes: attr, symlink_data: target.clone() },
+        };
+        let mut buf = Vec::new();
+        args.serialize(&mut buf)?;
+        let reply = self.nfs_call(NFSProgram::NFSPROC3_SYMLINK, &buf).await?;
+        created(reply)
+    }
+
+    /// MKNOD: creates a device, socket or FIFO
+    pub async fn mknod(
+        &self,
+        dir: &nfs3::nfs_fh3,
+        name: &nfs3::filename3,
+        ftype: nfs3::ftype3,
+        spec: nfs3::specdata3,
+        attr: nfs3::sattr3,
+    ) -> Result<(nfs3::post_op_fh3, nfs3::post_op_attr), ClientError> {
+        let mut args = Vec::new();
+        diropargs(dir, name).serialize(&mut args)?;
+        ftype.serialize(&mut args)?;
+        match ftype {
+            nfs3::ftype3::NF3CHR | nfs3::ftype3::NF3BLK => {
+                attr.serialize(&mut args)?;
+                spec.serialize(&mut args)?;
+            }
+            nfs3::ftype3::NF3SOCK | nfs3::ftype3::NF3FIFO => attr.serialize(&mut args)?,
+            _ => {}
+        }
+        let reply = self.nfs_call(NFSProgram::NFSPROC3_MKNOD, &args).await?;
+        created(reply)
+    }
+
+    /// REMOVE: removes a non-directory entry
+    pub async fn remove(
+        &self,
+        dir: &nfs3::nfs_fh3,
+        name: &nfs3::filename3,
+    ) -> Result<(), ClientError> {
+        let mut args = Vec::new();
+        diropargs(dir, name).serialize(&mut args)?;
+        self.nfs_call(NFSProgram::NFSPROC3_REMOVE, &args).await?;
+        Ok(())
+    }
+
+    /// RMDIR: removes an empty directory
+    pub async fn rmdir(
+        &self,
+        dir: &nfs3::nfs_fh3,
+        name: &nfs3::filename3,
+    ) -> Result<(), ClientError> {
+        let mut args = Vec::new();
+        diropargs(dir, name).serialize(&mut args)?;
+        self.nfs_call(NFSProgram::NFSPROC3_RMDIR, &args).await?;
+        Ok(())
+    }
+
+    /// RENAME: moves an entry, replacing any existing target
+    pub async fn rename(
+        &self,
+        from_dir: &nfs3::nfs_fh3,
+        from_name: &nfs3::filename3,
+        to_dir: &nfs3::nfs_fh3,
+        to_name: &nfs3::filename3,
+    ) -> Result<(), ClientError> {
+        let mut args = Vec::new();
+        diropargs(from_dir, from_name).serialize(&mut args)?;
+        diropargs(to_dir, to_name).serialize(&mut args)?;
+        self.nfs_call(NFSProgram::NFSPROC3_RENAME, &args).await?;
+        Ok(())
+    }
+
+    /// LINK: creates a hard link to `fh`, returning its new attributes
+    pub async fn link(
+        &self,
+        fh: &nfs3::nfs_fh3,
+        dir: &nfs3::nfs_fh3,
+        name: &nfs3::filename3,
+    ) -> Result<nfs3::post_op_attr, ClientError> {
+        let args = nfs3::file::LINK3args { file: fh.clone(), link: diropargs(dir, name) };
+        let mut buf = Vec::new();
+        args.serialize(&mut buf)?;
+        let mut reply = self.nfs_call(NFSProgram::NFSPROC3_LINK, &buf).await?;
+        Ok(deserialize(&mut reply)?)
+    }
+
+    /// READDIR: lists the names in a directory, starting after `cookie`
+    pub async fn readdir(
+        &self,
+        dir: &nfs3::nfs_fh3,
+        cookie: nfs3::cookie3,
+        cookieverf: nfs3::cookieverf3,
+        count: u32,
+    ) -> Result<ReadDir, ClientError> {
+        let args =
+            nfs3::dir::READDIR3args { dir: dir.clone(), cookie, cookieverf, dircount: count };
+        let mut buf = Vec::new();
+        args.serialize(&mut buf)?;
+        let mut reply = self.nfs_call(NFSProgram::NFSPROC3_READDIR, &buf).await?;
+        let mut res = ReadDir {
+            dir_attributes: deserialize(&mut reply)?,
+            cookieverf: deserialize(&mut reply)?,
+            ..Default::default()
+        };
+        while deserialize::<bool>(&mut reply)? {
+            res.entries.push(deserialize(&mut reply)?);
+        }
+        res.eof = deserialize(&mut reply)?;
+        Ok(res)
+    }
+
+    /// READDIRPLUS: lists a directory with attributes and handles, starting
+    /// after `cookie`
+    pub async fn readdirplus(
+        &self,
+        dir: &nfs3::nfs_fh3,
+        cookie: nfs3::cookie3,
+        cookieverf: nfs3::cookieverf3,
+        maxcount: u32,
+    ) -> Result<ReadDirPlus, ClientError> {
+        let args = nfs3::dir::READDIRPLUS3args {
+            dir: dir.clone(),
+            cookie,
+            cookieverf,
+            dircount: maxcount,
+            maxcount,
+        };
+        let mut buf = Vec::new();
+        args.serialize(&mut buf)?;
+        let mut reply = self.nfs_call(NFSProgram::NFSPROC3_READDIRPLUS, &buf).await?;
+        let mut res = ReadDirPlus {
+            dir_attributes: deserialize(&mut reply)?,
+            cookieverf: deserialize(&mut reply)?,
+            ..Default::default()
+        };
+        while deserialize::<bool>(&mut reply)? {
+            res.entries.push(deserialize(&mut reply)?);
+        }
+        res.eof = deserialize(&mut reply)?;
+        Ok(res)
+    }
+
+    /// COMMIT: flushes unstable writes to stable storage
+    pub async fn commit(
+        &self,
+        fh: &nfs3::nfs_fh3,
+        offset: u64,
+        count: u32,
+    ) -> Result<nfs3::file::COMMIT3resok, ClientError> {
+        let mut args = Vec::new();
+        nfs3::file::COMMIT3args { file: fh.clone(), offset, count }.serialize(&mut args)?;
+        let mut reply = self.nfs_call(NFSProgram::NFSPROC3_COMMIT, &args).await?;
+        Ok(deserialize(&mut reply)?)
+    }
+
+    /// FSINFO: returns the static properties of the file system
+    pub async fn fsinfo(&self, fh: &nfs3::nfs_fh3) -> Result<nfs3::fs::fsinfo3, ClientError> {
+        let mut args = Vec::new();
+        fh.serialize(&mut args)?;
+        let mut reply = self.nfs_call(NFSProgram::NFSPROC3_FSINFO, &args).await?;
+        Ok(deserialize(&mut reply)?)
+    }
+}
+
+/// Builds the arguments naming an entry of a directory
+fn diropargs(dir: &nfs3::nfs_fh3, name: &nfs3::filename3) -> nfs3::diropargs3 {
+    nfs3::diropargs3 { dir: dir.clone(), name: name.clone() }
+}
+
+/// Decodes the successful reply of a procedure creating an entry
+fn created(
+    mut reply: Cursor<Vec<u8>>,
+) -> Result<(nfs3::post_op_fh3, nfs3::post_op_attr), ClientError> {
+    let fh = deserialize(&mut reply)?;
+    let attr = deserialize(&mut reply)?;
+    Ok((fh, attr))
+}
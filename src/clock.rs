@@ -0,0 +1,150 @@
+//! Source of the current time.
+//!
+//! Handlers ask the [`Clock`] of their context for the time instead of calling
+//! [`SystemTime::now`], so `SET_TO_SERVER_TIME` resolves to the same clock on
+//! every connection, and tests can freeze time with a [`ManualClock`] to check
+//! timestamps exactly. Backends can hold a clock of their own for the same
+//! reason.
+//!
+//! [`RequestedTimes::resolve`] turns the times of attributes to set into
+//! concrete timestamps, so backends and [`crate::fs_util`] handle
+//! `SET_TO_SERVER_TIME` the same way instead of each picking a time of their
+//! own. The server resolves it with the clock of the context before calling
+//! the backend, so backends only see it when called directly.
+
+use std::fmt::Debug;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use crate::protocol::xdr::nfs3;
+
+/// Source of the current wall-clock time
+pub trait Clock: Debug + Send + Sync {
+    /// Returns the current time
+    fn now(&self) -> SystemTime;
+
+    /// Returns the current time as an NFS timestamp, clamped to its range
+    fn now_nfstime(&self) -> nfs3::nfstime3 {
+        self.now().into()
+    }
+}
+
+/// Access and modification times requested by attributes to set
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RequestedTimes {
+    /// New access time, `None` if left unchanged
+    pub atime: Option<nfs3::nfstime3>,
+    /// New modification time, `None` if left unchanged
+    pub mtime: Option<nfs3::nfstime3>,
+}
+
+impl RequestedTimes {
+    /// Resolves the times requested by `attr`, taking `SET_TO_SERVER_TIME`
+    /// from `clock`
+    ///
+    /// The clock is read once, so both times set to the server time are equal.
+    pub fn resolve(attr: &nfs3::sattr3, clock: &dyn Clock) -> Self {
+        let now = clock.now_nfstime();
+        let atime = match attr.atime {
+            nfs3::set_atime::DONT_CHANGE => None,
+            nfs3::set_atime::SET_TO_CLIENT_TIME(time) => Some(time),
+            nfs3::set_atime::SET_TO_SERVER_TIME => Some(now),
+        };
+        let mtime = match attr.mtime {
+            nfs3::set_mtime::DONT_CHANGE => None,
+            nfs3::set_mtime::SET_TO_CLIENT_TIME(time) => Some(time),
+            nfs3::set_mtime::SET_TO_SERVER_TIME => Some(now),
+        };
+        Self { atime, mtime }
+    }
+
+    /// Replaces `SET_TO_SERVER_TIME` in `attr` with the time of `clock`
+    pub fn resolve_in_place(attr: &mut nfs3::sattr3, clock: &dyn Clock) {
+        let times = Self::resolve(attr, clock);
+        if let Some(atime) = times.atime {
+            attr.atime = nfs3::set_atime::SET_TO_CLIENT_TIME(atime);
+        }
+        if let Some(mtime) = times.mtime {
+            attr.mtime = nfs3::set_mtime::SET_TO_CLIENT_TIME(mtime);
+        }
+    }
+}
+
+/// Clock returning the time of the operating system
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Clock that only moves when told to
+#[derive(Debug)]
+pub struct ManualClock {
+    /// The time returned by [`Clock::now`]
+    now: Mutex<SystemTime>,
+}
+
+impl ManualClock {
+    /// Creates a clock standing at `now`
+    pub fn new(now: SystemTime) -> Self {
+        Self { now: Mutex::new(now) }
+    }
+
+    /// Sets the clock to `now`
+    pub fn set(&self, now: SystemTime) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    /// Moves the clock forward by `by`
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().unwrap() += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use super::*;
+
+    #[test]
+    fn manual_clock_moves_on_request() {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(100));
+        let time = clock.now_nfstime();
+        assert_eq!((time.seconds, time.nseconds), (100, 0));
+        clock.advance(Duration::from_millis(1500));
+        let time = clock.now_nfstime();
+        assert_eq!((time.seconds, time.nseconds), (101, 500_000_000));
+        clock.set(UNIX_EPOCH);
+        assert_eq!(clock.now(), UNIX_EPOCH);
+    }
+
+    #[test]
+    fn resolves_server_time_from_the_clock() {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(100));
+        let client = nfs3::nfstime3 { seconds: 7, nseconds: 0 };
+        let mut attr = nfs3::sattr3 {
+            atime: nfs3::set_atime::SET_TO_SERVER_TIME,
+            mtime: nfs3::set_mtime::SET_TO_CLIENT_TIME(client),
+            ..Default::default()
+        };
+        let times = RequestedTimes::resolve(&attr, &clock);
+        assert_eq!(times.atime.map(|time| time.seconds), Some(100));
+        assert_eq!(times.mtime.map(|time| time.seconds), Some(7));
+        assert!(RequestedTimes::resolve(&nfs3::sattr3::default(), &clock).atime.is_none());
+
+        RequestedTimes::resolve_in_place(&mut attr, &clock);
+        clock.set(UNIX_EPOCH);
+        let times = RequestedTimes::resolve(&attr, &clock);
+        assert_eq!(times.atime.map(|time| time.seconds), Some(100));
+    }
+}
@@ -0,0 +1,424 @@
+//! Configuration files for servers built on the crate.
+//!
+//! A configuration file lists exports in TOML, much like `/etc/exports` lists
+//! them for the kernel server. Every export is served by a listener of its
+//! own, bound to its own address:
+//!
+//! ```toml
+//! [[export]]
+//! name = "/data"
+//! bind = "0.0.0.0:11111"
+//! path = "/srv/data"
+//! read_only = true
+//! clients = ["127.0.0.1", "192.168.0.0/16"]
+//! readdirplus = false
+//!
+//! [export.limits]
+//! max_dircount = 65536
+//! max_maxcount = 1048576
+//! max_file_size = 1073741824
+//! max_dir_entries = 10000
+//!
+//! [export.ntfs]
+//! streams = "hide"
+//! mount_points = "symlink"
+//!
+//! [export.creation]
+//! umask = 0o027
+//! owner = "caller"
+//!
+//! [redaction]
+//! names = "hash"
+//! hide_ids = true
+//! salt = "change me"
+//! ```
+//!
+//! `path` and `ntfs` are interpreted by the backend, e.g. the directory a
+//! mirroring backend serves, and may be left out by backends that need none. Without
+//! `clients` every client is served. [`ExportConfig::apply`] configures a
+//! listener accordingly. The optional `redaction` section applies to the logs
+//! of all exports and is installed with [`Config::install_redaction`].
+//!
+//! Available with the `config` feature, which is enabled by default.
+
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::protocol::nfs::v3::{CreationDefaults, ExportLimits, ReadDirPlusLimits};
+use crate::protocol::rpc::{self, CallInfo, Context, DispatchHook, HookDecision};
+use crate::protocol::xdr::nfs3;
+use crate::redaction::{self, RedactionPolicy};
+use crate::tcp::NFSTcpListener;
+use crate::vfs::ntfs::NtfsPolicy;
+use crate::vfs::NFSFileSystem;
+
+/// Contents of a configuration file
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Exports to serve, at least one
+    #[serde(rename = "export")]
+    pub exports: Vec<ExportConfig>,
+    /// What is left out of logs
+    #[serde(default)]
+    pub redaction: RedactionPolicy,
+}
+
+/// Configuration of a single export
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExportConfig {
+    /// Path clients mount, e.g. `/data`
+    #[serde(default = "default_export_name")]
+    pub name: String,
+    /// Address the listener of the export binds to
+    pub bind: SocketAddr,
+    /// Location of the exported data, interpreted by the backend
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+    /// Whether changes are refused with NFS3ERR_ROFS
+    #[serde(default)]
+    pub read_only: bool,
+    /// Clients allowed to use the export, all when empty
+    #[serde(default)]
+    pub clients: Vec<ClientMatch>,
+    /// Limits on request sizes and the data stored
+    #[serde(default)]
+    pub limits: Limits,
+    /// Whether `READDIRPLUS` is served, see
+    /// [`NFSTcpListener::with_readdirplus`]
+    #[serde(default = "default_readdirplus")]
+    pub readdirplus: bool,
+    /// Whether WebNFS clients may use the public file handle, see
+    /// [`NFSTcpListener::with_public_filehandle`]
+    #[serde(default)]
+    pub public: bool,
+    /// Presentation of NTFS streams and reparse points, for backends serving
+    /// NTFS
+    #[serde(default)]
+    pub ntfs: NtfsPolicy,
+    /// Mode and owner of new files the client sends none for, see
+    /// [`CreationDefaults`]; left to the backend when unset
+    #[serde(default)]
+    pub creation: Option<CreationDefaults>,
+}
+
+/// Limits on the sizes of requests of an export and the data stored in it
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limits {
+    /// Largest `READDIRPLUS` `dircount` honored
+    pub max_dircount: Option<u32>,
+    /// Largest `READDIRPLUS` `maxcount` honored
+    pub max_maxcount: Option<u32>,
+    /// Chunk size of streamed `READ` replies, not streamed when unset
+    pub read_stream_chunk: Option<u32>,
+    /// Largest size of a file, see [`ExportLimits`]
+    pub max_file_size: Option<u64>,
+    /// Largest number of entries of a directory
+    pub max_dir_entries: Option<u64>,
+    /// Largest number of files of the export
+    pub max_entries: Option<u64>,
+}
+
+/// Address or network of clients, such as `10.0.0.1` or `10.0.0.0/8`
+///
+/// IPv6 addresses may name the interface index of a link-local network, as in
+/// `fe80::%2/64`, to match only clients on that link.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct ClientMatch {
+    /// Network address
+    addr: IpAddr,
+    /// Number of leading bits of `addr` a client address must share
+    prefix_len: u8,
+    /// Interface index a client must have been reached on, any if `None`
+    scope_id: Option<u32>,
+}
+
+/// Error loading a configuration file
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The file could not be read
+    Io(io::Error),
+    /// The file is not valid TOML or does not match the expected structure
+    Parse(toml::de::Error),
+    /// The file is well-formed but describes an unusable configuration
+    Invalid(String),
+}
+
+/// Export name used when a configuration gives none
+fn default_export_name() -> String {
+    "/".to_string()
+}
+
+/// `READDIRPLUS` is served unless a configuration disables it
+fn default_readdirplus() -> bool {
+    true
+}
+
+impl Config {
+    /// Reads and validates a configuration file
+    pub fn load(path: impl AsRef<Path>) -> Result<Config, ConfigError> {
+        std::fs::read_to_string(path).map_err(ConfigError::Io)?.parse()
+    }
+
+    /// Installs the redaction policy of the configuration for all logs of the
+    /// process, see [`redaction::set_redaction_policy`]
+    pub fn install_redaction(&self) {
+        redaction::set_redaction_policy(self.redaction.clone());
+    }
+
+    /// Checks the parts of the configuration TOML cannot express
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.exports.is_empty() {
+            return Err(ConfigError::Invalid("no exports configured".to_string()));
+        }
+        for (index, export) in self.exports.iter().enumerate() {
+            if self.exports[..index].iter().any(|other| other.bind == export.bind) {
+                let msg = format!("more than one export binds to {}", export.bind);
+                return Err(ConfigError::Invalid(msg));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Config {
+    type Err = ConfigError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let config: Config = toml::from_str(text).map_err(ConfigError::Parse)?;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+impl ExportConfig {
+    /// Configures a listener to serve this export
+    ///
+    /// Sets the export name, size limits, limits of the data stored, defaults
+    /// of new files and whether `READDIRPLUS` is served, and installs a
+    /// dispatch hook refusing clients not listed in `clients` with
+    /// NFS3ERR_ACCES and, for read-only exports, changes with NFS3ERR_ROFS.
+    pub fn apply<T: NFSFileSystem + Send + Sync + ?Sized + 'static>(
+        &self,
+        listener: &mut NFSTcpListener<T>,
+    ) {
+        listener.with_export_name(&self.name);
+        if self.limits.max_dircount.is_some() || self.limits.max_maxcount.is_some() {
+            listener.with_readdirplus_limits(
+                self.limits.max_dircount.unwrap_or(ReadDirPlusLimits::DEFAULT_MAX_DIRCOUNT),
+                self.limits.max_maxcount.unwrap_or(ReadDirPlusLimits::DEFAULT_MAX_MAXCOUNT),
+            );
+        }
+        listener.with_read_streaming(self.limits.read_stream_chunk);
+        listener.with_export_limits(ExportLimits {
+            max_file_size: self.limits.max_file_size,
+            max_dir_entries: self.limits.max_dir_entries,
+            max_entries: self.limits.max_entries,
+        });
+        listener.with_creation_defaults(self.creation);
+        listener.with_readdirplus(self.readdirplus);
+        listener.with_public_filehandle(self.public);
+        if !self.clients.is_empty() || self.read_only {
+            listener.with_dispatch_hook(ExportAccess {
+                clients: self.clients.clone(),
+                read_only: self.read_only,
+            });
+        }
+    }
+}
+
+impl ClientMatch {
+    /// Returns whether a client address belongs to the matched addresses
+    pub fn matches(&self, client: SocketAddr) -> bool {
+        let scope_matches = match (self.scope_id, client) {
+            (None, _) => true,
+            (Some(scope_id), SocketAddr::V6(client)) => client.scope_id() == scope_id,
+            (Some(_), SocketAddr::V4(_)) => false,
+        };
+        scope_matches && rpc::in_network(client.ip(), self.addr, self.prefix_len)
+    }
+}
+
+impl FromStr for ClientMatch {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = match text.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (text, None),
+        };
+        let (addr, scope_id) = match addr.split_once('%') {
+            Some((addr, scope_id)) => (addr, Some(scope_id)),
+            None => (addr, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| format!("invalid client address {text:?}"))?;
+        let scope_id = match scope_id {
+            Some(_) if addr.is_ipv4() => return Err(format!("scope ID on IPv4 address {text:?}")),
+            Some(scope_id) => {
+                Some(scope_id.parse().map_err(|_| format!("invalid scope ID in {text:?}"))?)
+            }
+            None => None,
+        };
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(len) => len
+                .parse()
+                .ok()
+                .filter(|len| *len <= max_len)
+                .ok_or_else(|| format!("invalid prefix length in {text:?}"))?,
+            None => max_len,
+        };
+        Ok(ClientMatch { addr, prefix_len, scope_id })
+    }
+}
+
+impl TryFrom<String> for ClientMatch {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        text.parse()
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "cannot read configuration: {err}"),
+            ConfigError::Parse(err) => write!(f, "invalid configuration: {err}"),
+            ConfigError::Invalid(msg) => write!(f, "invalid configuration: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io(err) => Some(err),
+            ConfigError::Parse(err) => Some(err),
+            ConfigError::Invalid(_) => None,
+        }
+    }
+}
+
+/// Hook enforcing the client list and read-only flag of an export
+struct ExportAccess {
+    /// Clients allowed to use the export, all when empty
+    clients: Vec<ClientMatch>,
+    /// Whether changes are refused
+    read_only: bool,
+}
+
+/// `NFSv3` procedures that change the file system
+const CHANGING_PROCEDURES: [nfs3::NFSProgram; 11] = [
+    nfs3::NFSProgram::NFSPROC3_SETATTR,
+    nfs3::NFSProgram::NFSPROC3_WRITE,
+    nfs3::NFSProgram::NFSPROC3_CREATE,
+    nfs3::NFSProgram::NFSPROC3_MKDIR,
+    nfs3::NFSProgram::NFSPROC3_SYMLINK,
+    nfs3::NFSProgram::NFSPROC3_MKNOD,
+    nfs3::NFSProgram::NFSPROC3_REMOVE,
+    nfs3::NFSProgram::NFSPROC3_RMDIR,
+    nfs3::NFSProgram::NFSPROC3_RENAME,
+    nfs3::NFSProgram::NFSPROC3_LINK,
+    nfs3::NFSProgram::NFSPROC3_COMMIT,
+];
+
+impl DispatchHook for ExportAccess {
+    fn before(&self, call: &CallInfo, context: &Context, _args: &mut Vec<u8>) -> HookDecision {
+        let client_addr = context.client_addr;
+        if !self.clients.is_empty() && !self.clients.iter().any(|c| c.matches(client_addr)) {
+            return HookDecision::Fail(nfs3::nfsstat3::NFS3ERR_ACCES);
+        }
+        let changes = call.program == nfs3::PROGRAM
+            && CHANGING_PROCEDURES.iter().any(|proc| *proc as u32 == call.procedure);
+        if self.read_only && changes {
+            return HookDecision::Fail(nfs3::nfsstat3::NFS3ERR_ROFS);
+        }
+        HookDecision::Continue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_exports() {
+        let config: Config = r#"
+            [[export]]
+            bind = "127.0.0.1:11111"
+
+            [[export]]
+            name = "/data"
+            bind = "[::]:2049"
+            path = "/srv/data"
+            read_only = true
+            clients = ["10.0.0.0/8", "::1"]
+            limits = { max_dircount = 8192, max_dir_entries = 100 }
+            readdirplus = false
+            public = true
+            ntfs = { streams = "expose", other_reparse_points = "hide" }
+            creation = { umask = 0o077, owner = { fixed = { uid = 1000, gid = 100 } } }
+
+            [redaction]
+            names = "hide"
+            hide_ids = true
+        "#
+        .parse()
+        .unwrap();
+        assert_eq!(config.exports[0].name, "/");
+        assert!(config.exports[0].clients.is_empty());
+        let data = &config.exports[1];
+        assert_eq!(data.path.as_deref(), Some(Path::new("/srv/data")));
+        assert!(data.read_only);
+        assert!(config.exports[0].readdirplus && !data.readdirplus);
+        assert!(!config.exports[0].public && data.public);
+        assert_eq!(config.exports[0].ntfs, NtfsPolicy::default());
+        assert_eq!(data.ntfs.streams, crate::vfs::ntfs::StreamPolicy::Expose);
+        assert_eq!(data.ntfs.mount_points, crate::vfs::ntfs::ReparseAction::Symlink);
+        assert_eq!(data.ntfs.other_reparse_points, crate::vfs::ntfs::ReparseAction::Hide);
+        assert_eq!((data.limits.max_dircount, data.limits.max_maxcount), (Some(8192), None));
+        assert_eq!((data.limits.max_dir_entries, data.limits.max_file_size), (Some(100), None));
+        assert_eq!(config.exports[0].creation, None);
+        let creation = data.creation.unwrap();
+        assert_eq!((creation.umask, creation.dir_mode), (0o077, 0o777));
+        assert_eq!(
+            creation.owner,
+            crate::protocol::nfs::v3::DefaultOwner::Fixed { uid: 1000, gid: 100 }
+        );
+        assert_eq!(config.redaction.names, crate::redaction::NameRedaction::Hide);
+        assert!(config.redaction.hide_ids && config.redaction.salt.is_empty());
+
+        let client = |addr: &str| addr.parse::<SocketAddr>().unwrap();
+        assert!(data.clients[0].matches(client("[::ffff:10.1.2.3]:700")));
+        assert!(!data.clients[0].matches(client("11.0.0.1:700")));
+        assert!(data.clients[1].matches(client("[::1]:700")));
+
+        let link: ClientMatch = "fe80::%2/64".parse().unwrap();
+        assert!(link.matches(client("[fe80::1%2]:700")));
+        assert!(!link.matches(client("[fe80::1%3]:700")));
+        assert!(!link.matches(client("[fe81::1%2]:700")));
+        assert!("10.0.0.1%2".parse::<ClientMatch>().is_err());
+    }
+
+    #[test]
+    fn rejects_unusable_configurations() {
+        let invalid = |text: &str| text.parse::<Config>().unwrap_err().to_string();
+        assert!(invalid("export = []").contains("no exports"));
+        let twice = "[[export]]\nbind = \"127.0.0.1:1\"\n[[export]]\nbind = \"127.0.0.1:1\"";
+        assert!(invalid(twice).contains("more than one"));
+        assert!(invalid("[[export]]\nbind = \"127.0.0.1:1\"\nclients = [\"10.0.0.0/33\"]")
+            .contains("prefix length"));
+        assert!(invalid("[[export]]\nbind = \"127.0.0.1:1\"\nreadonly = true").contains("unknown"));
+        assert!(invalid("[[export]]\nbind = \"127.0.0.1:1\"\n[redaction]\nnames = \"scramble\"")
+            .contains("unknown variant"));
+    }
+}
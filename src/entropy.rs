@@ -0,0 +1,85 @@
+//! Source of the unpredictable values the server hands out.
+//!
+//! Handles for `AUTH_SHORT` credentials, the trace IDs of calls and the first
+//! transaction ID of a [`crate::client::Client`] are drawn from an [`Entropy`]
+//! instead of being made up where they are needed. They differ between runs by
+//! default, as they must, so that values of an earlier run are not mistaken
+//! for current ones. Tests replacing the entropy of the context with a
+//! [`SeededEntropy`] get the same values on every run, and so byte-identical
+//! replies. Backends can hold an entropy of their own for the same reason, e.g.
+//! for their generation numbers.
+
+use std::fmt::Debug;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Source of random 64-bit values
+pub trait Entropy: Debug + Send + Sync {
+    /// Returns the next value
+    fn next_u64(&self) -> u64;
+
+    /// Returns the next value truncated to 32 bits
+    fn next_u32(&self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+}
+
+/// Entropy differing between processes
+///
+/// Values are a keyed hash of a counter, with a key chosen at random when the
+/// instance is created, so they do not repeat within an instance.
+#[derive(Debug, Default)]
+pub struct SystemEntropy {
+    keys: RandomState,
+    counter: AtomicU64,
+}
+
+impl Entropy for SystemEntropy {
+    fn next_u64(&self) -> u64 {
+        self.keys.hash_one(self.counter.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// Entropy returning the same sequence for the same seed
+///
+/// The sequence is SplitMix64, which does not repeat a value before 2^64 draws.
+/// It is not suitable where values must be hard to guess.
+#[derive(Debug)]
+pub struct SeededEntropy {
+    state: AtomicU64,
+}
+
+impl SeededEntropy {
+    /// Creates an entropy starting the sequence of `seed`
+    pub fn new(seed: u64) -> Self {
+        Self { state: AtomicU64::new(seed) }
+    }
+}
+
+impl Entropy for SeededEntropy {
+    fn next_u64(&self) -> u64 {
+        let mut z = self.state.fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed);
+        z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_entropy_repeats_per_seed() {
+        let draw = |entropy: &dyn Entropy| (0..4).map(|_| entropy.next_u64()).collect::<Vec<_>>();
+        let first = draw(&SeededEntropy::new(7));
+        assert_eq!(first, draw(&SeededEntropy::new(7)));
+        assert_ne!(first, draw(&SeededEntropy::new(8)));
+        // first output of SplitMix64 seeded with 0
+        assert_eq!(SeededEntropy::new(0).next_u64(), 0xe220_a839_7b1d_cdaf);
+
+        let system = SystemEntropy::default();
+        assert_ne!(system.next_u64(), system.next_u64());
+    }
+}
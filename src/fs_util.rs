@@ -3,21 +3,28 @@
 //!
 //! This module contains functions for:
 //! - Converting between local file system metadata and NFS attributes
+//! - Converting I/O errors to NFS status codes
 //! - Safely checking file existence without traversing symlinks
 //! - Setting file attributes based on NFS `SETATTR` operations
 //! - Comparing file metadata for change detection
+//! - Creating FIFOs, sockets and device nodes for `MKNOD`
 
 use std::fs::Metadata;
-use std::fs::Permissions;
+use std::fs::{FileTimes, FileType, Permissions};
+use std::io;
+use std::time::SystemTime;
 
 #[cfg(unix)]
-use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
 use std::path::Path;
 
 use tokio::fs::OpenOptions;
 use tracing::debug;
 
+use crate::clock::{RequestedTimes, SystemClock};
 use crate::protocol::xdr::nfs3;
+use crate::redaction;
+use crate::vfs;
 
 /// Compares if file metadata has changed in a significant way
 ///
@@ -77,6 +84,196 @@ pub fn exists_no_traverse(path: &Path) -> bool {
     path.symlink_metadata().is_ok()
 }
 
+/// Converts an I/O error to the NFS status code reported to clients
+///
+/// Uses the OS error code where available, so e.g. `ENOSPC`, `EDQUOT` and
+/// `ENAMETOOLONG` become `NFS3ERR_NOSPC`, `NFS3ERR_DQUOT` and
+/// `NFS3ERR_NAMETOOLONG`, and falls back to the error kind otherwise. The
+/// mapper of the listener serving the current call, installed with
+/// [`crate::tcp::NFSTcpListener::with_error_mapper`], takes precedence.
+///
+/// # Arguments
+///
+/// * `err` - The error to convert
+///
+/// # Returns
+///
+/// The matching status code, `NFS3ERR_IO` if there is none
+pub fn nfsstat_from_io_error(err: &io::Error) -> nfs3::nfsstat3 {
+    vfs::map_error(err).unwrap_or(nfs3::nfsstat3::NFS3ERR_IO)
+}
+
+/// Converts a local file type to the NFS file type
+///
+/// # Arguments
+///
+/// * `file_type` - File type from the file's metadata
+///
+/// # Returns
+///
+/// The NFS file type; types without an NFS equivalent are reported as regular files
+pub fn ftype3_from_file_type(file_type: FileType) -> nfs3::ftype3 {
+    if file_type.is_dir() {
+        nfs3::ftype3::NF3DIR
+    } else if file_type.is_symlink() {
+        nfs3::ftype3::NF3LNK
+    } else if file_type.is_block_device() {
+        nfs3::ftype3::NF3BLK
+    } else if file_type.is_char_device() {
+        nfs3::ftype3::NF3CHR
+    } else if file_type.is_fifo() {
+        nfs3::ftype3::NF3FIFO
+    } else if file_type.is_socket() {
+        nfs3::ftype3::NF3SOCK
+    } else {
+        nfs3::ftype3::NF3REG
+    }
+}
+
+/// Splits a local device number into the major and minor numbers of `specdata3`
+///
+/// # Arguments
+///
+/// * `rdev` - Device number, e.g. from `MetadataExt::rdev`
+///
+/// # Returns
+///
+/// The major number in `specdata1` and the minor number in `specdata2`
+pub fn specdata3_from_rdev(rdev: u64) -> nfs3::specdata3 {
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    let (major, minor) = ((rdev >> 24) & 0xff, rdev & 0xff_ffff);
+    #[cfg(not(any(target_os = "macos", target_os = "ios")))]
+    let (major, minor) = (
+        ((rdev >> 32) & 0xffff_f000) | ((rdev >> 8) & 0xfff),
+        ((rdev >> 12) & 0xffff_ff00) | (rdev & 0xff),
+    );
+    nfs3::specdata3 { specdata1: major as u32, specdata2: minor as u32 }
+}
+
+/// Joins the major and minor numbers of `specdata3` into a local device number
+///
+/// This is the inverse of [`specdata3_from_rdev`].
+///
+/// # Arguments
+///
+/// * `specdata` - Major number in `specdata1` and minor number in `specdata2`
+///
+/// # Returns
+///
+/// The device number as used by `mknod(2)`
+pub fn rdev_from_specdata3(specdata: nfs3::specdata3) -> u64 {
+    let (major, minor) = (u64::from(specdata.specdata1), u64::from(specdata.specdata2));
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    let rdev = ((major & 0xff) << 24) | (minor & 0xff_ffff);
+    #[cfg(not(any(target_os = "macos", target_os = "ios")))]
+    let rdev = ((major & 0xffff_f000) << 32)
+        | ((major & 0xfff) << 8)
+        | ((minor & 0xffff_ff00) << 12)
+        | (minor & 0xff);
+    rdev
+}
+
+/// Creates a special file for `MKNOD`
+///
+/// FIFOs and sockets are created with `mkfifo(2)` and `mknod(2)` and need no
+/// privileges. Character and block devices need root (or `CAP_MKNOD`); for
+/// other users, or when the kernel refuses them, they fail with
+/// `NFS3ERR_NOTSUPP` instead of being faked, so clients see an honest error.
+///
+/// # Arguments
+///
+/// * `path` - Path of the file to create, which must not exist
+/// * `ftype` - Type of the file, one of `NF3CHR`, `NF3BLK`, `NF3SOCK` and `NF3FIFO`
+/// * `specdata` - Major and minor numbers, used for devices only
+/// * `mode` - Permission bits of the new file, subject to the process umask
+///
+/// # Returns
+///
+/// `NFS3ERR_BADTYPE` for other file types, or the NFS error code of the failed
+/// system call
+#[cfg(unix)]
+pub fn mknod(
+    path: &Path,
+    ftype: nfs3::ftype3,
+    specdata: nfs3::specdata3,
+    mode: u32,
+) -> Result<(), nfs3::nfsstat3> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let (kind, rdev) = match ftype {
+        nfs3::ftype3::NF3FIFO => (None, 0),
+        nfs3::ftype3::NF3SOCK => (Some(libc::S_IFSOCK), 0),
+        nfs3::ftype3::NF3CHR => (Some(libc::S_IFCHR), rdev_from_specdata3(specdata)),
+        nfs3::ftype3::NF3BLK => (Some(libc::S_IFBLK), rdev_from_specdata3(specdata)),
+        _ => return Err(nfs3::nfsstat3::NFS3ERR_BADTYPE),
+    };
+    let is_device = matches!(ftype, nfs3::ftype3::NF3CHR | nfs3::ftype3::NF3BLK);
+    // SAFETY: geteuid has no preconditions and cannot fail
+    if is_device && unsafe { libc::geteuid() } != 0 {
+        debug!("mknod {}: devices can only be created by root", redaction::path(path));
+        return Err(nfs3::nfsstat3::NFS3ERR_NOTSUPP);
+    }
+    let c_path =
+        CString::new(path.as_os_str().as_bytes()).map_err(|_| nfs3::nfsstat3::NFS3ERR_INVAL)?;
+    let perm = (mode & 0o7777) as libc::mode_t;
+    // SAFETY: c_path is a valid NUL-terminated string that outlives the call
+    let ret = unsafe {
+        match kind {
+            None => libc::mkfifo(c_path.as_ptr(), perm),
+            Some(kind) => libc::mknod(c_path.as_ptr(), kind | perm, rdev as libc::dev_t),
+        }
+    };
+    if ret == 0 {
+        return Ok(());
+    }
+    let err = io::Error::last_os_error();
+    debug!("mknod {} failed: {}", redaction::path(path), err);
+    match err.raw_os_error() {
+        Some(libc::EPERM) if is_device => Err(nfs3::nfsstat3::NFS3ERR_NOTSUPP),
+        _ => Err(nfsstat_from_io_error(&err)),
+    }
+}
+
+/// Converts filesystem metadata to NFS file attributes without losing information
+///
+/// Unlike [`metadata_to_fattr3`], the mode, link count, file type, device
+/// numbers and allocated size are taken from the metadata as they are.
+/// `fsid` is left at 0, as clients treat a change of `fsid` as crossing into
+/// another file system.
+///
+/// # Arguments
+///
+/// * `fid` - NFS file ID to use for the file
+/// * `meta` - Filesystem metadata to convert, usually from `symlink_metadata`
+///
+/// # Returns
+///
+/// NFS file attributes structure
+pub fn fattr3_from_metadata(fid: nfs3::fileid3, meta: &Metadata) -> nfs3::fattr3 {
+    let ftype = ftype3_from_file_type(meta.file_type());
+    let rdev = match ftype {
+        nfs3::ftype3::NF3BLK | nfs3::ftype3::NF3CHR => specdata3_from_rdev(meta.rdev()),
+        _ => nfs3::specdata3::default(),
+    };
+    nfs3::fattr3 {
+        ftype,
+        mode: meta.mode() & 0o7777,
+        nlink: u32::try_from(meta.nlink()).unwrap_or(u32::MAX),
+        uid: meta.uid(),
+        gid: meta.gid(),
+        size: meta.size(),
+        // st_blocks counts 512-byte units on every supported platform
+        used: meta.blocks().saturating_mul(512),
+        rdev,
+        fsid: 0,
+        fileid: fid,
+        atime: nfs3::nfstime3::from_unix(meta.atime(), meta.atime_nsec()),
+        mtime: nfs3::nfstime3::from_unix(meta.mtime(), meta.mtime_nsec()),
+        ctime: nfs3::nfstime3::from_unix(meta.ctime(), meta.ctime_nsec()),
+    }
+}
+
 /// Unmasks file mode bits to ensure writability
 ///
 /// This function ensures that files can be written to by setting the write bit,
@@ -125,18 +322,9 @@ pub fn metadata_to_fattr3(fid: nfs3::fileid3, meta: &Metadata) -> nfs3::fattr3 {
             rdev: nfs3::specdata3::default(),
             fsid: 0,
             fileid: fid,
-            atime: nfs3::nfstime3 {
-                seconds: meta.atime() as u32,
-                nseconds: meta.atime_nsec() as u32,
-            },
-            mtime: nfs3::nfstime3 {
-                seconds: meta.mtime() as u32,
-                nseconds: meta.mtime_nsec() as u32,
-            },
-            ctime: nfs3::nfstime3 {
-                seconds: meta.ctime() as u32,
-                nseconds: meta.ctime_nsec() as u32,
-            },
+            atime: nfs3::nfstime3::from_unix(meta.atime(), meta.atime_nsec()),
+            mtime: nfs3::nfstime3::from_unix(meta.mtime(), meta.mtime_nsec()),
+            ctime: nfs3::nfstime3::from_unix(meta.ctime(), meta.ctime_nsec()),
         }
     } else if meta.is_symlink() {
         nfs3::fattr3 {
@@ -150,18 +338,9 @@ pub fn metadata_to_fattr3(fid: nfs3::fileid3, meta: &Metadata) -> nfs3::fattr3 {
             rdev: nfs3::specdata3::default(),
             fsid: 0,
             fileid: fid,
-            atime: nfs3::nfstime3 {
-                seconds: meta.atime() as u32,
-                nseconds: meta.atime_nsec() as u32,
-            },
-            mtime: nfs3::nfstime3 {
-                seconds: meta.mtime() as u32,
-                nseconds: meta.mtime_nsec() as u32,
-            },
-            ctime: nfs3::nfstime3 {
-                seconds: meta.ctime() as u32,
-                nseconds: meta.ctime_nsec() as u32,
-            },
+            atime: nfs3::nfstime3::from_unix(meta.atime(), meta.atime_nsec()),
+            mtime: nfs3::nfstime3::from_unix(meta.mtime(), meta.mtime_nsec()),
+            ctime: nfs3::nfstime3::from_unix(meta.ctime(), meta.ctime_nsec()),
         }
     } else {
         nfs3::fattr3 {
@@ -175,26 +354,35 @@ pub fn metadata_to_fattr3(fid: nfs3::fileid3, meta: &Metadata) -> nfs3::fattr3 {
             rdev: nfs3::specdata3::default(),
             fsid: 0,
             fileid: fid,
-            atime: nfs3::nfstime3 {
-                seconds: meta.atime() as u32,
-                nseconds: meta.atime_nsec() as u32,
-            },
-            mtime: nfs3::nfstime3 {
-                seconds: meta.mtime() as u32,
-                nseconds: meta.mtime_nsec() as u32,
-            },
-            ctime: nfs3::nfstime3 {
-                seconds: meta.ctime() as u32,
-                nseconds: meta.ctime_nsec() as u32,
-            },
+            atime: nfs3::nfstime3::from_unix(meta.atime(), meta.atime_nsec()),
+            mtime: nfs3::nfstime3::from_unix(meta.mtime(), meta.mtime_nsec()),
+            ctime: nfs3::nfstime3::from_unix(meta.ctime(), meta.ctime_nsec()),
         }
     }
 }
 
+/// Resolves the access and modification times requested by `setattr`
+///
+/// `SET_TO_SERVER_TIME` is taken from the system clock; the server resolves it
+/// with the clock of the context before the backend is called.
+///
+/// # Returns
+///
+/// The new access and modification times, `None` for times left unchanged
+fn requested_times(setattr: &nfs3::sattr3) -> (Option<SystemTime>, Option<SystemTime>) {
+    let times = RequestedTimes::resolve(setattr, &SystemClock);
+    (times.atime.map(SystemTime::from), times.mtime.map(SystemTime::from))
+}
+
 /// Sets attributes of a file path based on NFS `SETATTR` operation
 ///
-/// This function applies the attributes specified in an NFS `SETATTR` request
-/// to a file or directory specified by path.
+/// Regular files and directories are opened once and updated through the file
+/// descriptor as [`file_setattr`] does, so the attributes are applied to a
+/// single file even if the path is replaced concurrently; directories are only
+/// opened for reading. Other files are never opened, as opening a FIFO blocks
+/// and opening a device node reaches the device: their attributes are applied by
+/// path, and a size cannot be set. Symbolic links are not followed: ownership
+/// and times are applied to the link itself and its mode is ignored.
 ///
 /// # Arguments
 ///
@@ -203,84 +391,271 @@ pub fn metadata_to_fattr3(fid: nfs3::fileid3, meta: &Metadata) -> nfs3::fattr3 {
 ///
 /// # Returns
 ///
-/// Result indicating success or NFS error code
-pub async fn path_setattr(path: &Path, setattr: &nfs3::sattr3) -> Result<(), nfs3::nfsstat3> {
-    match setattr.atime {
-        nfs3::set_atime::SET_TO_SERVER_TIME => {
-            let _ = filetime::set_file_atime(path, filetime::FileTime::now());
-        }
-        nfs3::set_atime::SET_TO_CLIENT_TIME(time) => {
-            let _ = filetime::set_file_atime(path, time.into());
-        }
-        _ => {}
+/// The attributes after the update, with the inode number as file ID, or the
+/// NFS error code of the first operation that failed
+pub async fn path_setattr(
+    path: &Path,
+    setattr: &nfs3::sattr3,
+) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+    let meta = path.symlink_metadata().map_err(|e| nfsstat_from_io_error(&e))?;
+    if !meta.is_file() && !meta.is_dir() {
+        return special_setattr(path, &meta, setattr);
     }
-
-    match setattr.mtime {
-        nfs3::set_mtime::SET_TO_SERVER_TIME => {
-            let _ = filetime::set_file_mtime(path, filetime::FileTime::now());
-        }
-        nfs3::set_mtime::SET_TO_CLIENT_TIME(time) => {
-            let _ = filetime::set_file_mtime(path, time.into());
+    // O_NONBLOCK keeps a FIFO that replaced the path meanwhile from blocking the open
+    let mut options = OpenOptions::new();
+    if meta.is_file() && setattr.size.is_some() {
+        options.write(true).truncate(false);
+    } else {
+        options.read(true);
+    }
+    let file = options.custom_flags(libc::O_NONBLOCK).open(path).await;
+    match file {
+        Ok(file) => file_setattr(&file.into_std().await, setattr).await,
+        // a file without read permission can still be chmod-ed by its owner
+        Err(err) if err.kind() == io::ErrorKind::PermissionDenied && setattr.size.is_none() => {
+            debug!(" -- setattr {} by path: {:?}", redaction::path(path), err);
+            if setattr.uid.is_some() || setattr.gid.is_some() {
+                std::os::unix::fs::chown(path, setattr.uid, setattr.gid)
+                    .map_err(|e| nfsstat_from_io_error(&e))?;
+            }
+            if let nfs3::set_mode3::Some(mode) = setattr.mode {
+                std::fs::set_permissions(path, Permissions::from_mode(mode_unmask(mode)))
+                    .map_err(|e| nfsstat_from_io_error(&e))?;
+            }
+            set_path_times(path, &meta, setattr, false)?;
+            let meta = path.symlink_metadata().map_err(|e| nfsstat_from_io_error(&e))?;
+            Ok(fattr3_from_metadata(meta.ino(), &meta))
         }
-        _ => {}
+        Err(err) => Err(nfsstat_from_io_error(&err)),
     }
+}
 
-    if let nfs3::set_mode3::Some(mode) = setattr.mode {
-        debug!(" -- set permissions {:?} {:?}", path, mode);
-        let mode = mode_unmask(mode);
-        let _ = std::fs::set_permissions(path, Permissions::from_mode(mode));
+/// Applies `setattr` by path to a file that is neither regular nor a directory
+///
+/// Symbolic links are not followed, and their mode is left unchanged.
+fn special_setattr(
+    path: &Path,
+    meta: &Metadata,
+    setattr: &nfs3::sattr3,
+) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+    if setattr.size.is_some() {
+        return Err(nfs3::nfsstat3::NFS3ERR_INVAL);
     }
-
-    if setattr.uid.is_some() {
-        debug!("Set uid not implemented");
+    if setattr.uid.is_some() || setattr.gid.is_some() {
+        std::os::unix::fs::lchown(path, setattr.uid, setattr.gid)
+            .map_err(|e| nfsstat_from_io_error(&e))?;
     }
-
-    if setattr.gid.is_some() {
-        debug!("Set gid not implemented");
+    if let nfs3::set_mode3::Some(mode) = setattr.mode {
+        if meta.is_symlink() {
+            debug!(" -- ignoring mode of symlink {}", redaction::path(path));
+        } else {
+            std::fs::set_permissions(path, Permissions::from_mode(mode_unmask(mode)))
+                .map_err(|e| nfsstat_from_io_error(&e))?;
+        }
     }
+    set_path_times(path, meta, setattr, true)?;
+    let meta = path.symlink_metadata().map_err(|e| nfsstat_from_io_error(&e))?;
+    Ok(fattr3_from_metadata(meta.ino(), &meta))
+}
 
-    if let nfs3::set_size3::Some(size3) = setattr.size {
-        let file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .truncate(false)
-            .open(path)
-            .await
-            .or(Err(nfs3::nfsstat3::NFS3ERR_IO))?;
-        debug!(" -- set size {:?} {:?}", path, size3);
-        file.set_len(size3).await.or(Err(nfs3::nfsstat3::NFS3ERR_IO))?;
+/// Sets the times requested by `setattr` by path, keeping unchanged ones from `meta`
+///
+/// With `no_follow` a symbolic link at `path` is updated itself.
+fn set_path_times(
+    path: &Path,
+    meta: &Metadata,
+    setattr: &nfs3::sattr3,
+    no_follow: bool,
+) -> Result<(), nfs3::nfsstat3> {
+    let (atime, mtime) = requested_times(setattr);
+    if atime.is_none() && mtime.is_none() {
+        return Ok(());
     }
-
-    Ok(())
+    let atime = atime.map_or_else(
+        || filetime::FileTime::from_last_access_time(meta),
+        filetime::FileTime::from_system_time,
+    );
+    let mtime = mtime.map_or_else(
+        || filetime::FileTime::from_last_modification_time(meta),
+        filetime::FileTime::from_system_time,
+    );
+    let res = if no_follow {
+        filetime::set_symlink_file_times(path, atime, mtime)
+    } else {
+        filetime::set_file_times(path, atime, mtime)
+    };
+    res.map_err(|e| nfsstat_from_io_error(&e))
 }
 
 /// Sets attributes of an open file based on NFS `SETATTR` operation
 ///
-/// This function applies the attributes specified in an NFS `SETATTR` request
-/// to an already open file handle.
+/// All attributes are applied through the file descriptor (`fchown`,
+/// `fchmod`, `ftruncate` and `futimens`), in an order that keeps explicitly
+/// requested times: ownership first, as changing it may clear set-id bits, then
+/// mode, size and finally times. Unset `uid` or `gid` leave the respective owner
+/// unchanged. Failures are reported instead of ignored; attributes applied
+/// before a failure stay applied.
 ///
 /// # Arguments
 ///
-/// * `file` - Open file handle
+/// * `file` - Open file handle, writable if the size is set
 /// * `setattr` - NFS attributes to set
 ///
 /// # Returns
 ///
-/// Result indicating success or NFS error code
+/// The attributes after the update, with the inode number as file ID, or the
+/// NFS error code of the first operation that failed
 pub async fn file_setattr(
     file: &std::fs::File,
     setattr: &nfs3::sattr3,
-) -> Result<(), nfs3::nfsstat3> {
+) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+    if setattr.uid.is_some() || setattr.gid.is_some() {
+        debug!(
+            " -- set owner {:?} {:?}",
+            setattr.uid.map(redaction::id),
+            setattr.gid.map(redaction::id)
+        );
+        std::os::unix::fs::fchown(file, setattr.uid, setattr.gid)
+            .map_err(|e| nfsstat_from_io_error(&e))?;
+    }
+
     if let nfs3::set_mode3::Some(mode) = setattr.mode {
         debug!(" -- set permissions {:?}", mode);
         let mode = mode_unmask(mode);
-        let _ = file.set_permissions(Permissions::from_mode(mode));
+        file.set_permissions(Permissions::from_mode(mode))
+            .map_err(|e| nfsstat_from_io_error(&e))?;
     }
 
     if let nfs3::set_size3::Some(size3) = setattr.size {
         debug!(" -- set size {:?}", size3);
-        file.set_len(size3).or(Err(nfs3::nfsstat3::NFS3ERR_IO))?;
+        file.set_len(size3).map_err(|e| nfsstat_from_io_error(&e))?;
+    }
+
+    let (atime, mtime) = requested_times(setattr);
+    if atime.is_some() || mtime.is_some() {
+        let mut times = FileTimes::new();
+        if let Some(atime) = atime {
+            times = times.set_accessed(atime);
+        }
+        if let Some(mtime) = mtime {
+            times = times.set_modified(mtime);
+        }
+        file.set_times(times).map_err(|e| nfsstat_from_io_error(&e))?;
+    }
+
+    let meta = file.metadata().map_err(|e| nfsstat_from_io_error(&e))?;
+    Ok(fattr3_from_metadata(meta.ino(), &meta))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_metadata_faithfully() {
+        let dir = std::env::temp_dir().join(format!("nfs-mamont-fs-util-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("file");
+        std::fs::write(&file, b"data").unwrap();
+        std::fs::set_permissions(&file, Permissions::from_mode(0o640)).unwrap();
+        std::fs::hard_link(&file, dir.join("link")).unwrap();
+
+        let attr = fattr3_from_metadata(7, &file.symlink_metadata().unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(attr.ftype, nfs3::ftype3::NF3REG));
+        assert_eq!((attr.fileid, attr.mode, attr.nlink, attr.size), (7, 0o640, 2, 4));
+    }
+
+    #[test]
+    fn rdev_round_trips() {
+        for specdata in [(8, 1), (259, 65536), (4095, 255)] {
+            let specdata = nfs3::specdata3 { specdata1: specdata.0, specdata2: specdata.1 };
+            let back = specdata3_from_rdev(rdev_from_specdata3(specdata));
+            assert_eq!((back.specdata1, back.specdata2), (specdata.specdata1, specdata.specdata2));
+        }
+    }
+
+    #[test]
+    fn mknod_creates_fifos() {
+        let dir = std::env::temp_dir().join(format!("nfs-mamont-mknod-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let fifo = dir.join("fifo");
+        let none = nfs3::specdata3::default();
+        let created = mknod(&fifo, nfs3::ftype3::NF3FIFO, none, 0o640);
+        let meta = fifo.symlink_metadata();
+        let again = mknod(&fifo, nfs3::ftype3::NF3FIFO, none, 0o640);
+        let regular = mknod(&dir.join("file"), nfs3::ftype3::NF3REG, none, 0o640);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(created.is_ok());
+        assert!(meta.unwrap().file_type().is_fifo());
+        assert!(matches!(again, Err(nfs3::nfsstat3::NFS3ERR_EXIST)));
+        assert!(matches!(regular, Err(nfs3::nfsstat3::NFS3ERR_BADTYPE)));
     }
 
-    Ok(())
+    #[tokio::test]
+    async fn setattr_truncates_and_keeps_requested_times() {
+        let dir = std::env::temp_dir().join(format!("nfs-mamont-setattr-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("file");
+        std::fs::write(&file, b"some data").unwrap();
+        let link = dir.join("link");
+        std::os::unix::fs::symlink(&file, &link).unwrap();
+
+        let mtime = nfs3::nfstime3 { seconds: 1_000_000, nseconds: 5 };
+        let setattr = nfs3::sattr3 {
+            mode: Some(0o600),
+            size: Some(4),
+            mtime: nfs3::set_mtime::SET_TO_CLIENT_TIME(mtime),
+            ..Default::default()
+        };
+        let attr = path_setattr(&file, &setattr).await.unwrap();
+        let on_link = path_setattr(&link, &setattr).await.map(|_| ()).unwrap_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!((attr.size, attr.mode), (4, 0o600));
+        assert_eq!((attr.mtime.seconds, attr.mtime.nseconds), (1_000_000, 5));
+        assert_eq!(on_link as u32, nfs3::nfsstat3::NFS3ERR_INVAL as u32);
+    }
+
+    #[tokio::test]
+    async fn setattr_on_fifo_does_not_open_it() {
+        let dir =
+            std::env::temp_dir().join(format!("nfs-mamont-setattr-fifo-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let fifo = dir.join("fifo");
+        mknod(&fifo, nfs3::ftype3::NF3FIFO, nfs3::specdata3::default(), 0o644).unwrap();
+
+        let mtime = nfs3::nfstime3 { seconds: 1_000_000, nseconds: 0 };
+        let setattr = nfs3::sattr3 {
+            mode: Some(0o600),
+            mtime: nfs3::set_mtime::SET_TO_CLIENT_TIME(mtime),
+            ..Default::default()
+        };
+        let resize = nfs3::sattr3 { size: Some(0), ..Default::default() };
+        let timeout = std::time::Duration::from_secs(5);
+        let attr = tokio::time::timeout(timeout, path_setattr(&fifo, &setattr)).await;
+        let resized = tokio::time::timeout(timeout, path_setattr(&fifo, &resize)).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let attr = attr.expect("SETATTR on a FIFO blocked").unwrap();
+        assert!(matches!(attr.ftype, nfs3::ftype3::NF3FIFO));
+        assert_eq!(attr.mode, 0o600);
+        assert_eq!(attr.mtime.seconds, 1_000_000);
+        let resized = resized.expect("SETATTR on a FIFO blocked").map(|_| ()).unwrap_err();
+        assert_eq!(resized as u32, nfs3::nfsstat3::NFS3ERR_INVAL as u32);
+    }
+
+    #[test]
+    fn converts_io_errors_and_device_numbers() {
+        let err = io::Error::from(io::ErrorKind::NotFound);
+        assert_eq!(nfsstat_from_io_error(&err) as u32, nfs3::nfsstat3::NFS3ERR_NOENT as u32);
+        #[cfg(target_os = "linux")]
+        {
+            let err = io::Error::from_raw_os_error(28);
+            assert_eq!(nfsstat_from_io_error(&err) as u32, nfs3::nfsstat3::NFS3ERR_NOSPC as u32);
+            let rdev = specdata3_from_rdev(0x0801);
+            assert_eq!((rdev.specdata1, rdev.specdata2), (8, 1));
+        }
+    }
 }
@@ -26,6 +26,28 @@
 //!
 //! - `fs_util`: Utility functions for working with file systems.
 //!
+//! - `client`: NFSv3 client issuing `MOUNT` and `NFS` calls to remote servers over TCP.
+//!
+//! - `clock`: Source of the current time used by the handlers, replaceable in tests.
+//!
+//! - `entropy`: Source of the random values handed out by the server, replaceable in tests.
+//!
+//! - `config`: Export configurations loaded from TOML files (`config` feature, on by default).
+//!
+//! - `memory_budget`: A limit on the memory held by all server caches together.
+//!
+//! - `mount_helper`: Mounting a running server with the system's NFS client, for end-to-end tests
+//!   (Linux and macOS only).
+//!
+//! - `privileges`: Switching to an unprivileged user after binding privileged ports (Unix only).
+//!
+//! - `redaction`: Leaving file names and user identities out of logs.
+//!
+//! - `sandbox`: Confining the file access of the server to its exports with Landlock (Linux only).
+//!
+//! - `write_counter`: Counting of written bytes, and accounting of the space used through the
+//!   server for `FSSTAT`.
+//!
 //! ## Standards Compliance
 //!
 //! This implementation follows these RFCs:
@@ -39,8 +61,21 @@
 //! To create an NFS server, implement the `NFSFileSystem` trait and use the `NFSTcpListener`
 //! to expose it over the network.
 
+pub mod client;
+pub mod clock;
+#[cfg(feature = "config")]
+pub mod config;
+pub mod entropy;
+pub mod memory_budget;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub mod mount_helper;
+#[cfg(unix)]
+pub mod privileges;
 pub mod protocol;
-mod write_counter;
+pub mod redaction;
+#[cfg(target_os = "linux")]
+pub mod sandbox;
+pub mod write_counter;
 
 #[cfg(not(target_os = "windows"))]
 pub mod fs_util;
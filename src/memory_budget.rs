@@ -0,0 +1,291 @@
+//! Central accounting of the memory held by the server caches.
+//!
+//! Every cache of the server is bounded on its own, by a number of entries or
+//! bytes, but the sum of all bounds is far above what most hosts want a file
+//! server to take, and tuning every cache separately to stay below a total is
+//! guesswork. A [`MemoryBudget`] caps the sum instead.
+//!
+//! Caches taking part implement [`MemoryConsumer`] and are registered with the
+//! budget. They charge the approximate size of every entry they keep to their
+//! [`MemoryCharge`], which adds it to the budget. Once the total exceeds the
+//! limit, the budget asks the consumers holding the most memory to
+//! [`MemoryConsumer::shrink`] until it is below the limit again. This happens
+//! on the call that exceeded the limit, so usage is capped without a
+//! background task. Caches keep their own limits; the budget only evicts
+//! earlier.
+//!
+//! Sizes are estimates of the heap memory of entries, including their keys,
+//! not exact allocator figures, so the limit should leave room for the memory
+//! of the server itself and of in-flight calls.
+
+use std::cmp::Reverse;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+
+/// A cache whose memory is accounted for by a [`MemoryBudget`]
+pub trait MemoryConsumer: Send + Sync {
+    /// Returns the account of the memory the consumer holds
+    fn memory(&self) -> &MemoryCharge;
+
+    /// Evicts entries to free about `bytes` of memory
+    ///
+    /// Called when the budget is exceeded, never while the consumer itself is
+    /// charging. Consumers that cannot free memory synchronously return 0 and
+    /// check [`MemoryCharge::is_exceeded`] when they grow instead.
+    ///
+    /// # Returns
+    ///
+    /// * `usize` - The number of bytes freed
+    fn shrink(&self, bytes: usize) -> usize;
+}
+
+/// Memory held by one [`MemoryConsumer`]
+///
+/// Without a budget, the charge only counts. Memory still charged when the
+/// charge is dropped is returned to the budget.
+#[derive(Debug, Default)]
+pub struct MemoryCharge {
+    budget: OnceLock<Arc<MemoryBudget>>,
+    used: AtomicUsize,
+}
+
+impl MemoryCharge {
+    /// Returns the bytes held by the consumer
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Adds bytes the consumer started to hold
+    ///
+    /// Does not evict anything: the consumer calls [`MemoryCharge::reclaim`]
+    /// once it no longer holds its own locks.
+    pub fn charge(&self, bytes: usize) {
+        self.used.fetch_add(bytes, Ordering::Relaxed);
+        if let Some(budget) = self.budget.get() {
+            budget.used.fetch_add(bytes, Ordering::Relaxed);
+        }
+    }
+
+    /// Removes bytes the consumer no longer holds
+    pub fn release(&self, bytes: usize) {
+        self.used.fetch_sub(bytes, Ordering::Relaxed);
+        if let Some(budget) = self.budget.get() {
+            budget.used.fetch_sub(bytes, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns whether the budget of the consumer is exceeded
+    pub fn is_exceeded(&self) -> bool {
+        self.budget.get().is_some_and(|budget| budget.is_exceeded())
+    }
+
+    /// Lets the budget evict entries of all consumers if it is exceeded
+    ///
+    /// Must not be called while holding a lock [`MemoryConsumer::shrink`]
+    /// takes.
+    pub fn reclaim(&self) {
+        if let Some(budget) = self.budget.get() {
+            budget.reclaim();
+        }
+    }
+}
+
+impl Drop for MemoryCharge {
+    fn drop(&mut self) {
+        if let Some(budget) = self.budget.get() {
+            budget.used.fetch_sub(self.used(), Ordering::Relaxed);
+        }
+    }
+}
+
+/// Memory a registered consumer holds
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConsumerUsage {
+    /// Name the consumer was registered under
+    pub name: &'static str,
+    /// Bytes held
+    pub bytes: usize,
+}
+
+/// Limit on the memory of a set of caches
+///
+/// Shared by all caches it limits, which may belong to several listeners.
+#[derive(Debug)]
+pub struct MemoryBudget {
+    /// Bytes the consumers may hold together
+    limit: usize,
+    /// Bytes the consumers hold
+    used: AtomicUsize,
+    /// Registered consumers with their names
+    consumers: Mutex<Vec<(&'static str, Weak<dyn MemoryConsumer>)>>,
+    /// Held while evicting, so concurrent calls do not evict twice
+    reclaiming: Mutex<()>,
+    /// Bytes freed by evictions so far
+    freed: AtomicU64,
+}
+
+impl MemoryBudget {
+    /// Creates a budget of `limit` bytes without consumers
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            used: AtomicUsize::new(0),
+            consumers: Mutex::default(),
+            reclaiming: Mutex::default(),
+            freed: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the bytes the consumers may hold together
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Returns the bytes the consumers hold
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether the consumers hold more than the limit
+    pub fn is_exceeded(&self) -> bool {
+        self.used() > self.limit
+    }
+
+    /// Returns the bytes freed by evictions so far
+    pub fn freed(&self) -> u64 {
+        self.freed.load(Ordering::Relaxed)
+    }
+
+    /// Adds a consumer, charging the memory it already holds
+    ///
+    /// A consumer is limited by the first budget it is registered with;
+    /// registering it with another one does nothing.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name the consumer is reported under, e.g. `read_cache`
+    /// * `consumer` - The consumer, which the budget does not keep alive
+    pub fn register<C: MemoryConsumer + 'static>(
+        self: &Arc<Self>,
+        name: &'static str,
+        consumer: &Arc<C>,
+    ) {
+        let memory = consumer.memory();
+        if memory.budget.set(self.clone()).is_err() {
+            return;
+        }
+        self.used.fetch_add(memory.used(), Ordering::Relaxed);
+        let consumer: Weak<dyn MemoryConsumer> = Arc::downgrade(consumer) as _;
+        let mut consumers = self.consumers.lock().unwrap();
+        consumers.retain(|(_, consumer)| consumer.strong_count() > 0);
+        consumers.push((name, consumer));
+    }
+
+    /// Returns the memory every live consumer holds
+    pub fn usage(&self) -> Vec<ConsumerUsage> {
+        self.consumers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(name, consumer)| {
+                let bytes = consumer.upgrade()?.memory().used();
+                Some(ConsumerUsage { name, bytes })
+            })
+            .collect()
+    }
+
+    /// Evicts entries until the consumers hold no more than the limit
+    ///
+    /// Consumers holding the most memory are asked first. Returns at once if
+    /// another call is already evicting.
+    ///
+    /// # Returns
+    ///
+    /// * `usize` - The number of bytes freed
+    pub fn reclaim(&self) -> usize {
+        if !self.is_exceeded() {
+            return 0;
+        }
+        let Ok(_reclaiming) = self.reclaiming.try_lock() else {
+            return 0;
+        };
+        let mut consumers: Vec<_> = self
+            .consumers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(_, consumer)| consumer.upgrade())
+            .collect();
+        consumers.sort_by_key(|consumer| Reverse(consumer.memory().used()));
+        let mut freed = 0;
+        for consumer in consumers {
+            let excess = self.used().saturating_sub(self.limit);
+            if excess == 0 {
+                break;
+            }
+            freed += consumer.shrink(excess);
+        }
+        self.freed.fetch_add(freed as u64, Ordering::Relaxed);
+        freed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Consumer holding entries of 10 bytes
+    #[derive(Default)]
+    struct Entries {
+        memory: MemoryCharge,
+        count: Mutex<usize>,
+    }
+
+    impl Entries {
+        fn add(&self, count: usize) {
+            *self.count.lock().unwrap() += count;
+            self.memory.charge(count * 10);
+            self.memory.reclaim();
+        }
+    }
+
+    impl MemoryConsumer for Entries {
+        fn memory(&self) -> &MemoryCharge {
+            &self.memory
+        }
+
+        fn shrink(&self, bytes: usize) -> usize {
+            let mut count = self.count.lock().unwrap();
+            let evicted = bytes.div_ceil(10).min(*count);
+            *count -= evicted;
+            self.memory.release(evicted * 10);
+            evicted * 10
+        }
+    }
+
+    #[test]
+    fn evicts_from_largest_consumers_beyond_limit() {
+        let budget = Arc::new(MemoryBudget::new(100));
+        let (small, large) = (Arc::new(Entries::default()), Arc::new(Entries::default()));
+        large.add(5);
+        budget.register("small", &small);
+        budget.register("large", &large);
+        assert_eq!(budget.used(), 50);
+
+        small.add(3);
+        large.add(2);
+        assert_eq!(budget.used(), 100);
+        assert_eq!(budget.freed(), 0);
+        small.add(1);
+        assert_eq!(budget.used(), 100);
+        assert_eq!((*small.count.lock().unwrap(), *large.count.lock().unwrap()), (4, 6));
+        let usage = budget.usage();
+        assert_eq!(usage[0], ConsumerUsage { name: "small", bytes: 40 });
+        assert_eq!(budget.freed(), 10);
+
+        // memory of dropped consumers is returned
+        drop(large);
+        assert_eq!(budget.used(), 40);
+        assert_eq!(budget.usage().len(), 1);
+    }
+}
@@ -0,0 +1,200 @@
+//! Mounting a running server through the operating system's NFS client.
+//!
+//! End-to-end tests and tools need to mount the server with the right options:
+//! the server does not register with the system portmapper, so both the `NFS`
+//! and the `MOUNT` port have to be given, and locking has to be disabled as the
+//! `NLM` protocol is not served. [`MountOptions::mount_command`] builds the
+//! invocation of `mount -t nfs` on Linux and `mount_nfs` on macOS:
+//!
+//! ```no_run
+//! # async fn run() -> std::io::Result<()> {
+//! use nfs_mamont::mount_helper::MountOptions;
+//! use nfs_mamont::tcp::{NFSTcp, NFSTcpListener};
+//! use nfs_mamont::vfs::testing::MockFs;
+//!
+//! let listener = NFSTcpListener::bind("127.0.0.1:0", MockFs::new()).await?;
+//! let options = MountOptions::new(("127.0.0.1", listener.get_listen_port()), "/");
+//! tokio::spawn(async move { listener.handle_forever().await });
+//! let mount = options.mount("/mnt/test")?;
+//! // ... use the file system under /mnt/test ...
+//! mount.unmount()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Mounting needs root, or the `CAP_SYS_ADMIN` capability on Linux. The
+//! commands block, so they must not run on a thread the server depends on.
+
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use tracing::warn;
+
+/// How to mount an export of a running server
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MountOptions {
+    /// Address the server listens on; also used for `MOUNT`
+    pub server: SocketAddr,
+    /// Name of the export, e.g. `/`
+    pub export: String,
+    /// Whether to mount read-only
+    pub read_only: bool,
+    /// Further options passed to the client as they are, e.g. `soft`
+    pub extra_options: Vec<String>,
+}
+
+impl MountOptions {
+    /// Creates options for mounting `export` from the server at `server`
+    ///
+    /// # Panics
+    ///
+    /// If `server` does not resolve to an address
+    pub fn new(server: impl ToSocketAddrs, export: impl Into<String>) -> Self {
+        let server = server
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .expect("server address does not resolve");
+        Self { server, export: export.into(), read_only: false, extra_options: Vec::new() }
+    }
+
+    /// Returns the `host:/export` source of the mount
+    fn source(&self) -> String {
+        match self.server.ip() {
+            IpAddr::V4(ip) => format!("{ip}:{}", self.export),
+            IpAddr::V6(ip) => format!("[{ip}]:{}", self.export),
+        }
+    }
+
+    /// Returns the comma-separated option list of the mount
+    fn option_list(&self) -> String {
+        let port = self.server.port();
+        let mut options = if cfg!(target_os = "macos") {
+            vec![format!("vers=3,tcp,port={port},mountport={port},nolocks")]
+        } else {
+            vec![format!("vers=3,proto=tcp,port={port},mountport={port},mountproto=tcp,nolock")]
+        };
+        if self.read_only {
+            options.push(if cfg!(target_os = "macos") { "rdonly" } else { "ro" }.to_string());
+        }
+        options.extend(self.extra_options.iter().cloned());
+        options.join(",")
+    }
+
+    /// Builds the command mounting the export on `mount_point`
+    pub fn mount_command(&self, mount_point: impl AsRef<Path>) -> Command {
+        let mut command = if cfg!(target_os = "macos") {
+            Command::new("mount_nfs")
+        } else {
+            let mut command = Command::new("mount");
+            command.args(["-t", "nfs"]);
+            command
+        };
+        command.arg("-o").arg(self.option_list()).arg(self.source()).arg(mount_point.as_ref());
+        command
+    }
+
+    /// Mounts the export on `mount_point`, which must exist
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<Mount>` - The mount, unmounted when dropped, or an error
+    ///   carrying the output of the failed command
+    pub fn mount(&self, mount_point: impl AsRef<Path>) -> io::Result<Mount> {
+        run(self.mount_command(&mount_point))?;
+        Ok(Mount { mount_point: mount_point.as_ref().to_path_buf(), mounted: true })
+    }
+}
+
+/// Builds the command unmounting `mount_point`
+///
+/// # Arguments
+///
+/// * `mount_point` - Where the export is mounted
+/// * `force` - Whether to unmount even if the server does not respond or the
+///   file system is busy
+pub fn unmount_command(mount_point: impl AsRef<Path>, force: bool) -> Command {
+    let mut command = Command::new("umount");
+    if force {
+        command.arg("-f");
+        if cfg!(target_os = "linux") {
+            // detach even when busy
+            command.arg("-l");
+        }
+    }
+    command.arg(mount_point.as_ref());
+    command
+}
+
+/// Runs a command, turning a failure into an error with its output
+fn run(mut command: Command) -> io::Result<()> {
+    let output = command.output()?;
+    if output.status.success() {
+        return Ok(());
+    }
+    Err(io::Error::other(format!(
+        "{:?} failed with {}: {}",
+        command,
+        output.status,
+        String::from_utf8_lossy(&output.stderr).trim()
+    )))
+}
+
+/// Export mounted with [`MountOptions::mount`]
+///
+/// Dropping it unmounts the export by force, so a failing test does not leave
+/// a mount of a server that is gone behind.
+#[derive(Debug)]
+pub struct Mount {
+    mount_point: PathBuf,
+    /// Whether the export is still mounted
+    mounted: bool,
+}
+
+impl Mount {
+    /// Returns where the export is mounted
+    pub fn mount_point(&self) -> &Path {
+        &self.mount_point
+    }
+
+    /// Unmounts the export, failing if it is busy
+    pub fn unmount(mut self) -> io::Result<()> {
+        run(unmount_command(&self.mount_point, false))?;
+        self.mounted = false;
+        Ok(())
+    }
+}
+
+impl Drop for Mount {
+    fn drop(&mut self) {
+        if self.mounted {
+            if let Err(e) = run(unmount_command(&self.mount_point, true)) {
+                warn!("Cannot unmount {}: {}", self.mount_point.display(), e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_mount_commands() {
+        let mut options = MountOptions::new("[::1]:11111", "/data");
+        options.read_only = true;
+        options.extra_options.push("soft".to_string());
+        let command = options.mount_command("/mnt");
+        let args: Vec<_> = command.get_args().map(|arg| arg.to_str().unwrap()).collect();
+        let list = args[args.len() - 3];
+        assert!(list.contains("port=11111,mountport=11111"));
+        assert!(list.ends_with(",soft"));
+        assert_eq!(args[args.len() - 2..], ["[::1]:/data", "/mnt"]);
+
+        let command = unmount_command("/mnt", true);
+        assert_eq!(command.get_program(), "umount");
+        assert_eq!(command.get_args().next().unwrap(), "-f");
+    }
+}
@@ -0,0 +1,226 @@
+//! Dropping root privileges once the listening sockets are bound.
+//!
+//! The portmapper has to listen on port 111 and NFS conventionally listens on
+//! port 2049, both of which only privileged processes can bind. Serving every
+//! request as root makes any bug in a backend a root compromise, so a server
+//! should bind its ports first and then switch to an unprivileged user:
+//!
+//! ```no_run
+//! # async fn run(fs: impl nfs_mamont::vfs::NFSFileSystem + Send + Sync + 'static)
+//! #     -> std::io::Result<()> {
+//! use nfs_mamont::privileges::{self, Identity};
+//! use nfs_mamont::tcp::{NFSTcp, NFSTcpListener};
+//!
+//! let nobody: Identity = "nobody".parse()?;
+//! let listener = NFSTcpListener::bind("0.0.0.0:2049", fs).await?;
+//! privileges::drop_privileges(nobody)?;
+//! listener.handle_forever().await
+//! # }
+//! ```
+//!
+//! The C library applies the change to every thread of the process, so it is
+//! safe to drop privileges from within a running Tokio runtime.
+
+use std::ffi::CString;
+use std::io;
+use std::str::FromStr;
+
+/// Linux capability allowing to bind ports below 1024
+#[cfg(target_os = "linux")]
+const CAP_NET_BIND_SERVICE: u32 = 10;
+
+/// User and group a server switches to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Identity {
+    /// User ID
+    pub uid: u32,
+    /// Primary and only group ID
+    pub gid: u32,
+}
+
+impl FromStr for Identity {
+    type Err = io::Error;
+
+    /// Parses `user[:group]`, where both parts are names or numeric IDs
+    ///
+    /// Without a group, the primary group of the user is used, which needs the
+    /// user to exist in the user database.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (user, group) = match text.split_once(':') {
+            Some((user, group)) => (user, Some(group)),
+            None => (text, None),
+        };
+        let (uid, primary_gid) = match user.parse::<u32>() {
+            Ok(uid) if group.is_some() => (uid, None),
+            Ok(uid) => (uid, Some(lookup_uid(uid)?)),
+            Err(_) => {
+                let (uid, gid) = lookup_user(user)?;
+                (uid, Some(gid))
+            }
+        };
+        let gid = match group {
+            Some(group) => match group.parse::<u32>() {
+                Ok(gid) => gid,
+                Err(_) => lookup_group(group)?,
+            },
+            None => primary_gid.expect("primary group is looked up without a group"),
+        };
+        Ok(Identity { uid, gid })
+    }
+}
+
+/// Size of the buffer for the strings of user and group database entries
+const ENTRY_BUFFER_SIZE: usize = 16 * 1024;
+
+/// Returns an error for a missing user or group
+fn not_found(what: &str, name: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("no such {what}: {name}"))
+}
+
+/// Converts a name to a C string
+fn c_name(name: &str) -> io::Result<CString> {
+    CString::new(name).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "name contains NUL"))
+}
+
+/// Looks up the user ID and primary group ID of a user name
+fn lookup_user(name: &str) -> io::Result<(u32, u32)> {
+    let c_name = c_name(name)?;
+    let mut buf = vec![0 as libc::c_char; ENTRY_BUFFER_SIZE];
+    // SAFETY: passwd is plain old data, all zeroes is a valid value
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    // SAFETY: all pointers are valid for the duration of the call and buf.len()
+    // is the size of buf
+    let rc = unsafe {
+        libc::getpwnam_r(c_name.as_ptr(), &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result)
+    };
+    if rc != 0 {
+        return Err(io::Error::from_raw_os_error(rc));
+    }
+    if result.is_null() {
+        return Err(not_found("user", name));
+    }
+    Ok((pwd.pw_uid, pwd.pw_gid))
+}
+
+/// Looks up the primary group ID of a user ID
+fn lookup_uid(uid: u32) -> io::Result<u32> {
+    let mut buf = vec![0 as libc::c_char; ENTRY_BUFFER_SIZE];
+    // SAFETY: passwd is plain old data, all zeroes is a valid value
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    // SAFETY: all pointers are valid for the duration of the call and buf.len()
+    // is the size of buf
+    let rc = unsafe { libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if rc != 0 {
+        return Err(io::Error::from_raw_os_error(rc));
+    }
+    if result.is_null() {
+        return Err(not_found("user ID", uid));
+    }
+    Ok(pwd.pw_gid)
+}
+
+/// Looks up the group ID of a group name
+fn lookup_group(name: &str) -> io::Result<u32> {
+    let c_name = c_name(name)?;
+    let mut buf = vec![0 as libc::c_char; ENTRY_BUFFER_SIZE];
+    // SAFETY: group is plain old data, all zeroes is a valid value
+    let mut grp: libc::group = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    // SAFETY: all pointers are valid for the duration of the call and buf.len()
+    // is the size of buf
+    let rc = unsafe {
+        libc::getgrnam_r(c_name.as_ptr(), &mut grp, buf.as_mut_ptr(), buf.len(), &mut result)
+    };
+    if rc != 0 {
+        return Err(io::Error::from_raw_os_error(rc));
+    }
+    if result.is_null() {
+        return Err(not_found("group", name));
+    }
+    Ok(grp.gr_gid)
+}
+
+/// Returns whether the process may bind ports below 1024
+///
+/// That is the case when it runs as root or, on Linux, holds the
+/// `CAP_NET_BIND_SERVICE` capability.
+pub fn can_bind_privileged_ports() -> bool {
+    // SAFETY: geteuid has no preconditions and cannot fail
+    if unsafe { libc::geteuid() } == 0 {
+        return true;
+    }
+    #[cfg(target_os = "linux")]
+    if let Ok(status) = std::fs::read_to_string("/proc/self/status") {
+        let effective = status
+            .lines()
+            .find_map(|line| line.strip_prefix("CapEff:"))
+            .and_then(|caps| u64::from_str_radix(caps.trim(), 16).ok());
+        if let Some(caps) = effective {
+            return caps & (1 << CAP_NET_BIND_SERVICE) != 0;
+        }
+    }
+    false
+}
+
+/// Switches the whole process to `identity` for good
+///
+/// Supplementary groups are cleared and the real, effective and saved IDs are
+/// all set, so the process cannot regain root. Fails with
+/// `PermissionDenied` if the process is not root, and with an error as well if
+/// root can still be regained afterwards.
+///
+/// Call it after every privileged port is bound and every file only root may
+/// open is opened.
+pub fn drop_privileges(identity: Identity) -> io::Result<()> {
+    // SAFETY: geteuid has no preconditions and cannot fail
+    if unsafe { libc::geteuid() } != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "dropping privileges needs to run as root",
+        ));
+    }
+    let gid = identity.gid as libc::gid_t;
+    // SAFETY: the group list is a single valid gid_t
+    if unsafe { libc::setgroups(1, &gid) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // the group has to change first, an unprivileged user may not change it
+    // SAFETY: setgid and setuid have no memory safety preconditions
+    if unsafe { libc::setgid(gid) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: as above
+    if unsafe { libc::setuid(identity.uid as libc::uid_t) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: getuid, geteuid, getgid and getegid cannot fail
+    let (uid, euid, gid, egid) =
+        unsafe { (libc::getuid(), libc::geteuid(), libc::getgid(), libc::getegid()) };
+    let switched = uid == identity.uid && euid == identity.uid;
+    // SAFETY: setuid has no memory safety preconditions
+    let regained = identity.uid != 0 && unsafe { libc::setuid(0) } == 0;
+    if !switched || gid != identity.gid || egid != identity.gid || regained {
+        return Err(io::Error::other("privileges were not dropped completely"));
+    }
+    tracing::info!("dropped privileges to uid {} gid {}", identity.uid, identity.gid);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_identities() {
+        let parse = |text: &str| text.parse::<Identity>().unwrap();
+        assert_eq!(parse("1000:100"), Identity { uid: 1000, gid: 100 });
+        assert_eq!(parse("root"), Identity { uid: 0, gid: 0 });
+        assert_eq!(parse("0"), Identity { uid: 0, gid: 0 });
+        assert_eq!(parse("root:1"), Identity { uid: 0, gid: 1 });
+        let err = "no-such-user-here".parse::<Identity>().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,123 @@
+//! Stream of the activity clients cause on the file system.
+//!
+//! Host applications often need to learn about changes made over NFS: to index
+//! new files, invalidate caches or update a user interface. [`Events`]
+//! broadcasts an [`Event`] for every successful change and mount to all
+//! subscribers, so they need not poll the file system or parse logs.
+//!
+//! Events are published once the file system reported success, in the order
+//! the calls of a connection completed; the calls of different connections may
+//! interleave. Subscribers lagging behind by more than the capacity of the
+//! channel miss the oldest events and are told so by
+//! [`broadcast::error::RecvError::Lagged`]. Without subscribers events are
+//! dropped.
+//!
+//! The `Display` form of an event is a line of an access log, e.g.
+//! `10.0.0.7:812 created "notes.txt" in 1 as 42`, with names redacted by the
+//! installed [`crate::redaction::RedactionPolicy`].
+
+use std::fmt;
+use std::net::SocketAddr;
+
+use tokio::sync::broadcast;
+
+use crate::protocol::xdr::nfs3;
+
+/// Number of events buffered per subscriber by default
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+/// Activity of a client, see [`EventKind`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Event {
+    /// Address of the client that caused it
+    pub client: SocketAddr,
+    /// What happened
+    pub kind: EventKind,
+}
+
+/// Kinds of activity published
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    /// A file, directory, symbolic link, special file or hard link named
+    /// `name` was created in directory `dirid`
+    Created { dirid: nfs3::fileid3, name: Vec<u8>, id: nfs3::fileid3 },
+    /// `count` bytes were written at `offset` of a file
+    Written { id: nfs3::fileid3, offset: u64, count: u32 },
+    /// The entry `name` was removed from directory `dirid`
+    Removed { dirid: nfs3::fileid3, name: Vec<u8> },
+    /// An entry was renamed, possibly into another directory
+    Renamed {
+        from_dirid: nfs3::fileid3,
+        from_name: Vec<u8>,
+        to_dirid: nfs3::fileid3,
+        to_name: Vec<u8>,
+    },
+    /// The client mounted `path` of the export
+    Mounted { path: Vec<u8> },
+    /// The client unmounted `path`, or all paths if `None`
+    Unmounted { path: Option<Vec<u8>> },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use crate::redaction::name;
+
+        let client = self.client;
+        match &self.kind {
+            EventKind::Created { dirid, name: entry, id } => {
+                write!(f, "{client} created {} in {dirid} as {id}", name(entry))
+            }
+            EventKind::Written { id, offset, count } => {
+                write!(f, "{client} wrote {count} bytes at {offset} of {id}")
+            }
+            EventKind::Removed { dirid, name: entry } => {
+                write!(f, "{client} removed {} from {dirid}", name(entry))
+            }
+            EventKind::Renamed { from_dirid, from_name, to_dirid, to_name } => write!(
+                f,
+                "{client} renamed {} in {from_dirid} to {} in {to_dirid}",
+                name(from_name),
+                name(to_name)
+            ),
+            EventKind::Mounted { path } => write!(f, "{client} mounted {}", name(path)),
+            EventKind::Unmounted { path: Some(path) } => {
+                write!(f, "{client} unmounted {}", name(path))
+            }
+            EventKind::Unmounted { path: None } => write!(f, "{client} unmounted all"),
+        }
+    }
+}
+
+/// Broadcasts events to subscribers
+#[derive(Debug)]
+pub struct Events {
+    sender: broadcast::Sender<Event>,
+}
+
+impl Events {
+    /// Creates a channel buffering up to `capacity` events per subscriber
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is 0.
+    pub fn new(capacity: usize) -> Self {
+        Self { sender: broadcast::channel(capacity).0 }
+    }
+
+    /// Returns a receiver of the events published from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+
+    /// Publishes an event to the current subscribers
+    pub fn publish(&self, event: Event) {
+        // without subscribers the event is dropped
+        let _ = self.sender.send(event);
+    }
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
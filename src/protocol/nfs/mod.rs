@@ -14,11 +14,19 @@
 //!   allows clients to discover which port numbers are assigned to specific RPC programs.
 //!   This is used by clients to locate the NFS and `MOUNT` services.
 //!
+//! - `events`: A broadcast stream of the changes and mounts clients make, for
+//!   host applications to follow the activity on the file system.
+//!
+//! - `mount_table`: The mounts clients hold, listed by `MOUNTPROC3_DUMP` and
+//!   dropped for clients that vanished without unmounting.
+//!
 //! Together, these protocols form a complete NFS version 3 service as defined by
 //! the relevant RFCs. The NFS protocol is designed to be transport-independent,
 //! though in this implementation it is primarily used over TCP.
 
+pub mod events;
 pub mod mount;
+pub mod mount_table;
 pub mod portmap;
 pub mod v3;
 pub mod v4;
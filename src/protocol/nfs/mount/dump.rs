@@ -0,0 +1,43 @@
+//! Implementation of the DUMP procedure (procedure 2) for `MOUNT` version 3 protocol
+//! as defined in RFC 1813 section 5.2.2.
+//! <https://datatracker.ietf.org/doc/html/rfc1813#section-5.2.2>.
+
+use std::io::Write;
+
+use tracing::debug;
+
+use crate::protocol::rpc;
+use crate::protocol::xdr::{self, Serialize};
+
+/// Handles `MOUNTPROC3_DUMP` procedure.
+///
+/// Function returns the list of mounts clients hold, as recorded in the mount
+/// table by calls to MNT. Clients are named by their IP address, since the
+/// server does not resolve host names.
+///
+/// # Arguments
+///
+/// * `xid` - RPC transaction ID
+/// * `output` - Output stream for writing the response
+/// * `context` - Server context containing the mount table
+///
+/// # Returns
+///
+/// * `Result<(), rpc::ServerError>` - Ok(()) on success or an error
+pub fn mountproc3_dump(
+    xid: u32,
+    output: &mut impl Write,
+    context: &rpc::Context,
+) -> Result<(), rpc::ServerError> {
+    debug!("mountproc3_dump({:?}) ", xid);
+    xdr::rpc::make_success_reply(xid).serialize(output)?;
+    for entry in context.mount_table.entries() {
+        true.serialize(output)?;
+        // Host name and directory of one mount
+        entry.client.ip().to_string().serialize(output)?;
+        entry.path.serialize(output)?;
+    }
+    // No next mounts
+    false.serialize(output)?;
+    Ok(())
+}
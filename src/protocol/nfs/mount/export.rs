@@ -24,12 +24,12 @@ use crate::protocol::xdr::{self, Serialize};
 ///
 /// # Returns
 ///
-/// * `Result<(), anyhow::Error>` - Ok(()) on success or an error
+/// * `Result<(), rpc::ServerError>` - Ok(()) on success or an error
 pub fn mountproc3_export(
     xid: u32,
     output: &mut impl Write,
     context: &rpc::Context,
-) -> Result<(), anyhow::Error> {
+) -> Result<(), rpc::ServerError> {
     debug!("mountproc3_export({:?}) ", xid);
     xdr::rpc::make_success_reply(xid).serialize(output)?;
     true.serialize(output)?;
@@ -7,13 +7,17 @@ use std::io::{Read, Write};
 use num_traits::cast::ToPrimitive;
 use tracing::debug;
 
+use crate::protocol::nfs::events::EventKind;
 use crate::protocol::rpc;
+use crate::protocol::xdr::rpc::auth_flavor;
 use crate::protocol::xdr::{self, deserialize, mount, Serialize};
 
 /// Handles `MOUNTPROC3_MNT` procedure.
 ///
 /// Function returns file handle for the requested
-/// mount point and supported authentication flavors.
+/// mount point and the authentication flavors of the auth policy, see
+/// [`rpc::AuthPolicy::mount_flavors`]. Mounts requested with credentials of
+/// another flavor are refused with `MNT3ERR_ACCES`.
 ///
 /// TODO: Currently there is only one mount point, to support
 /// full functionality we need to extend support for multiple mount points.
@@ -21,22 +25,24 @@ use crate::protocol::xdr::{self, deserialize, mount, Serialize};
 /// # Arguments
 ///
 /// * `xid` - RPC transaction ID
+/// * `flavor` - Flavor of the credentials of the call
 /// * `input` - Input stream containing the directory path to mount
 /// * `output` - Output stream for writing the response
 /// * `context` - Server context containing exports and VFS information
 ///
 /// # Returns
 ///
-/// * `Result<(), anyhow::Error>` - Ok(()) on success or an error
+/// * `Result<(), rpc::ServerError>` - Ok(()) on success or an error
 pub async fn mountproc3_mnt(
     xid: u32,
+    flavor: auth_flavor,
     input: &mut impl Read,
     output: &mut impl Write,
     context: &rpc::Context,
-) -> Result<(), anyhow::Error> {
+) -> Result<(), rpc::ServerError> {
     let path = deserialize::<Vec<u8>>(input)?;
     let utf8path = std::str::from_utf8(&path).unwrap_or_default();
-    debug!("mountproc3_mnt({:?},{:?}) ", xid, utf8path);
+    debug!("mountproc3_mnt({:?},{}) ", xid, crate::redaction::name(&path));
     let path = if let Some(path) = utf8path.strip_prefix(context.export_name.as_str()) {
         let path = path.trim_start_matches('/').trim_end_matches('/').trim().as_bytes();
         let mut new_path = Vec::with_capacity(path.len() + 1);
@@ -50,18 +56,41 @@ pub async fn mountproc3_mnt(
         mount::mountstat3::MNT3ERR_NOENT.serialize(output)?;
         return Ok(());
     };
+    // the credentials of an AUTH_SHORT handle are AUTH_UNIX ones
+    let flavor = match flavor {
+        auth_flavor::AUTH_SHORT => auth_flavor::AUTH_UNIX,
+        flavor => flavor,
+    };
+    if !context.auth_policy.allows_mount(flavor) {
+        debug!("{:?} --> MNT3ERR_ACCES for {:?} credentials", xid, flavor);
+        xdr::rpc::make_success_reply(xid).serialize(output)?;
+        mount::mountstat3::MNT3ERR_ACCES.serialize(output)?;
+        return Ok(());
+    }
     if let Ok(fileid) = context.vfs.path_to_id(&path).await {
+        let Ok(fh) = context.id_to_fh(fileid) else {
+            debug!("{:?} --> MNT3ERR_SERVERFAULT", xid);
+            xdr::rpc::make_success_reply(xid).serialize(output)?;
+            mount::mountstat3::MNT3ERR_SERVERFAULT.serialize(output)?;
+            return Ok(());
+        };
         let response = mount::mountres3_ok {
-            fhandle: context.vfs.id_to_fh(fileid).data,
-            auth_flavors: vec![
-                xdr::rpc::auth_flavor::AUTH_NULL.to_u32().unwrap(),
-                xdr::rpc::auth_flavor::AUTH_UNIX.to_u32().unwrap(),
-            ],
+            fhandle: fh.data,
+            auth_flavors: context
+                .auth_policy
+                .mount_flavors()
+                .into_iter()
+                .map(|flavor| flavor.to_u32().unwrap())
+                .collect(),
         };
         debug!("{:?} --> {:?}", xid, response);
-        if let Some(ref chan) = context.mount_signal {
-            let _ = chan.send(true).await;
+        let mounted = context.clock.now();
+        if context.mount_table.mounted(context.client_addr, utf8path.as_bytes(), mounted) {
+            if let Some(ref chan) = context.mount_signal {
+                let _ = chan.send(true).await;
+            }
         }
+        context.publish(EventKind::Mounted { path });
         xdr::rpc::make_success_reply(xid).serialize(output)?;
         mount::mountstat3::MNT3_OK.serialize(output)?;
         response.serialize(output)?;
@@ -8,12 +8,14 @@ use num_traits::cast::FromPrimitive;
 use crate::protocol::rpc;
 use crate::protocol::xdr::{self, mount, Serialize};
 
+mod dump;
 mod export;
 mod mnt;
 mod null;
 mod umnt;
 mod umnt_all;
 
+use dump::mountproc3_dump;
 use export::mountproc3_export;
 use mnt::mountproc3_mnt;
 use null::mountproc3_null;
@@ -22,8 +24,6 @@ use umnt_all::mountproc3_umnt_all;
 
 /// Main handler for `MOUNT` procedures of version 3 protocol.
 ///
-/// TODO: `MOUNTPROC3_DUMP` function is not implemented.
-///
 /// # Arguments
 ///
 /// * `xid` - RPC transaction ID from the client
@@ -34,19 +34,22 @@ use umnt_all::mountproc3_umnt_all;
 ///
 /// # Returns
 ///
-/// * `Result<(), anyhow::Error>` - Ok(()) on success or an error
+/// * `Result<(), rpc::ServerError>` - Ok(()) on success or an error
 pub async fn handle_mount(
     xid: u32,
     call: xdr::rpc::call_body,
     input: &mut impl Read,
     output: &mut impl Write,
     context: &rpc::Context,
-) -> Result<(), anyhow::Error> {
+) -> Result<(), rpc::ServerError> {
     let prog = mount::MountProgram::from_u32(call.proc).unwrap_or(mount::MountProgram::INVALID);
 
     match prog {
         mount::MountProgram::MOUNTPROC3_NULL => mountproc3_null(xid, output)?,
-        mount::MountProgram::MOUNTPROC3_MNT => mountproc3_mnt(xid, input, output, context).await?,
+        mount::MountProgram::MOUNTPROC3_MNT => {
+            mountproc3_mnt(xid, call.cred.flavor, input, output, context).await?;
+        }
+        mount::MountProgram::MOUNTPROC3_DUMP => mountproc3_dump(xid, output, context)?,
         mount::MountProgram::MOUNTPROC3_UMNT => {
             mountproc3_umnt(xid, input, output, context).await?;
         }
@@ -6,39 +6,39 @@ use std::io::{Read, Write};
 
 use tracing::debug;
 
+use crate::protocol::nfs::events::EventKind;
 use crate::protocol::rpc;
 use crate::protocol::xdr::{self, deserialize, mount, Serialize};
 
 /// Handles `MOUNTPROC3_UMNT` procedure.
 ///
-/// Function removes the mount entry from the mount list for
-/// the requested diretory.
-///
-/// TODO: Currently directory path is not used and only single
-/// mount point is removed. Need to extend functionality.
+/// Function removes the mount entry of the client from the mount list for
+/// the requested directory, and notifies the mount listener if there was one.
 ///
 /// # Arguments
 ///
 /// * `xid` - RPC transaction ID
 /// * `input` - Input stream containing the directory path to unmount
 /// * `output` - Output stream for writing the response
-/// * `context` - Server context containing mount signal information
+/// * `context` - Server context containing the mount table and mount signal
 ///
 /// # Returns
 ///
-/// * `Result<(), anyhow::Error>` - Ok(()) on success or an error
+/// * `Result<(), rpc::ServerError>` - Ok(()) on success or an error
 pub async fn mountproc3_umnt(
     xid: u32,
     input: &mut impl Read,
     output: &mut impl Write,
     context: &rpc::Context,
-) -> Result<(), anyhow::Error> {
+) -> Result<(), rpc::ServerError> {
     let path = deserialize::<Vec<_>>(input)?;
-    let utf8path = std::str::from_utf8(&path).unwrap_or_default();
-    debug!("mountproc3_umnt({:?},{:?}) ", xid, utf8path);
-    if let Some(ref chan) = context.mount_signal {
-        let _ = chan.send(false).await;
+    debug!("mountproc3_umnt({:?},{}) ", xid, crate::redaction::name(&path));
+    if context.mount_table.unmounted(context.client_addr.ip(), &path) {
+        if let Some(ref chan) = context.mount_signal {
+            let _ = chan.send(false).await;
+        }
     }
+    context.publish(EventKind::Unmounted { path: Some(path) });
     xdr::rpc::make_success_reply(xid).serialize(output)?;
     mount::mountstat3::MNT3_OK.serialize(output)?;
     Ok(())
@@ -6,35 +6,37 @@ use std::io::Write;
 
 use tracing::debug;
 
+use crate::protocol::nfs::events::EventKind;
 use crate::protocol::rpc;
 use crate::protocol::xdr::{self, mount, Serialize};
 
 /// Handles `MOUNTPROC3_UMNTALL` procedure.
 ///
 /// Function removes all of the mount entries for
-/// this client previously recorded by calls to MNT.
-///
-/// TODO: Currently we have only one mount point,
-/// if there will be more, we need to extend functionality.
+/// this client previously recorded by calls to MNT, and notifies the mount
+/// listener once for every entry removed.
 ///
 /// # Arguments
 ///
 /// * `xid` - RPC transaction ID
 /// * `output` - Output stream for writing the response
-/// * `context` - Server context containing mount signal information
+/// * `context` - Server context containing the mount table and mount signal
 ///
 /// # Returns
 ///
-/// * `Result<(), anyhow::Error>` - Ok(()) on success or an error
+/// * `Result<(), rpc::ServerError>` - Ok(()) on success or an error
 pub async fn mountproc3_umnt_all(
     xid: u32,
     output: &mut impl Write,
     context: &rpc::Context,
-) -> Result<(), anyhow::Error> {
+) -> Result<(), rpc::ServerError> {
     debug!("mountproc3_umnt_all({:?}) ", xid);
-    if let Some(ref chan) = context.mount_signal {
-        let _ = chan.send(false).await;
+    for _ in context.mount_table.unmounted_all(context.client_addr.ip()) {
+        if let Some(ref chan) = context.mount_signal {
+            let _ = chan.send(false).await;
+        }
     }
+    context.publish(EventKind::Unmounted { path: None });
     xdr::rpc::make_success_reply(xid).serialize(output)?;
     mount::mountstat3::MNT3_OK.serialize(output)?;
     Ok(())
@@ -0,0 +1,227 @@
+//! Table of the mounts clients hold, as listed by `MOUNTPROC3_DUMP`.
+//!
+//! `MNT` adds an entry for the client and directory, `UMNT` and `UMNTALL`
+//! remove them. Clients that crash, are switched off or lose their network
+//! never unmount, and their entries would stay forever: `showmount -a` keeps
+//! listing them, and a host waiting for the last unmount before shutting down
+//! never sees it.
+//!
+//! With [`MountLivenessConfig`], a client counts as gone once it made no call
+//! for the idle timeout, on any connection or program. The listener checks the
+//! table periodically, drops the mounts of such clients and sends the same
+//! notifications as for `UMNT`. Clients are told apart by IP address, since
+//! they usually call `MOUNT` and NFS from different ports. NFSv3 keeps no
+//! state per mount, so a client dropped while merely idle keeps working; it
+//! is only missing from the table until it mounts again. The idle timeout
+//! should therefore be long, well above the intervals at which clients with
+//! open files revalidate them.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// When clients count as gone and how often the table is checked
+#[derive(Clone, Copy, Debug)]
+pub struct MountLivenessConfig {
+    /// Time without calls after which the mounts of a client are dropped
+    pub idle_timeout: Duration,
+    /// Interval of the checks for idle clients
+    pub check_interval: Duration,
+}
+
+impl Default for MountLivenessConfig {
+    fn default() -> Self {
+        Self { idle_timeout: Duration::from_secs(3600), check_interval: Duration::from_secs(60) }
+    }
+}
+
+/// A mount held by a client
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MountEntry {
+    /// Address the client mounted from
+    pub client: SocketAddr,
+    /// Directory as requested by the client
+    pub path: Vec<u8>,
+}
+
+/// Mounts of one client
+#[derive(Debug)]
+struct ClientMounts {
+    /// Address of the latest `MNT` call
+    addr: SocketAddr,
+    /// Mounted directories, in the order they were mounted
+    paths: Vec<Vec<u8>>,
+    /// Time of the latest call, in milliseconds since the Unix epoch
+    last_seen: AtomicU64,
+}
+
+/// Mounts of all clients of a listener
+#[derive(Debug, Default)]
+pub struct MountTable {
+    liveness: Option<MountLivenessConfig>,
+    clients: RwLock<HashMap<IpAddr, ClientMounts>>,
+}
+
+/// Returns `time` in milliseconds since the Unix epoch
+fn millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_millis() as u64)
+}
+
+impl MountTable {
+    /// Creates an empty table, dropping idle clients if `liveness` is set
+    pub fn new(liveness: Option<MountLivenessConfig>) -> Self {
+        Self { liveness, clients: RwLock::default() }
+    }
+
+    /// Returns when clients count as gone, `None` if mounts are only removed
+    /// by unmounting
+    pub fn liveness(&self) -> Option<MountLivenessConfig> {
+        self.liveness
+    }
+
+    /// Records that `client` mounted `path`
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Whether the mount is new, rather than a repeated `MNT`
+    pub fn mounted(&self, client: SocketAddr, path: &[u8], now: SystemTime) -> bool {
+        let mut clients = self.clients.write().unwrap();
+        let mounts = clients.entry(client.ip()).or_insert_with(|| ClientMounts {
+            addr: client,
+            paths: Vec::new(),
+            last_seen: AtomicU64::new(0),
+        });
+        mounts.addr = client;
+        mounts.last_seen.store(millis(now), Ordering::Relaxed);
+        if mounts.paths.iter().any(|mounted| mounted == path) {
+            return false;
+        }
+        mounts.paths.push(path.to_vec());
+        true
+    }
+
+    /// Records that `client` unmounted `path`
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Whether the client had mounted `path`
+    pub fn unmounted(&self, client: IpAddr, path: &[u8]) -> bool {
+        let mut clients = self.clients.write().unwrap();
+        let Some(mounts) = clients.get_mut(&client) else {
+            return false;
+        };
+        let before = mounts.paths.len();
+        mounts.paths.retain(|mounted| mounted != path);
+        let removed = mounts.paths.len() != before;
+        if mounts.paths.is_empty() {
+            clients.remove(&client);
+        }
+        removed
+    }
+
+    /// Records that `client` unmounted everything
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<Vec<u8>>` - The directories the client had mounted
+    pub fn unmounted_all(&self, client: IpAddr) -> Vec<Vec<u8>> {
+        self.clients.write().unwrap().remove(&client).map(|mounts| mounts.paths).unwrap_or_default()
+    }
+
+    /// Records a call of `client`, which shows it is still alive
+    ///
+    /// Takes the lock of the table for reading only.
+    pub fn touch(&self, client: IpAddr, now: SystemTime) {
+        if let Some(mounts) = self.clients.read().unwrap().get(&client) {
+            mounts.last_seen.fetch_max(millis(now), Ordering::Relaxed);
+        }
+    }
+
+    /// Returns all mounts, grouped by client
+    pub fn entries(&self) -> Vec<MountEntry> {
+        let clients = self.clients.read().unwrap();
+        let mut entries: Vec<_> = clients
+            .values()
+            .flat_map(|mounts| {
+                mounts
+                    .paths
+                    .iter()
+                    .map(|path| MountEntry { client: mounts.addr, path: path.clone() })
+            })
+            .collect();
+        entries.sort_by(|a, b| a.client.ip().cmp(&b.client.ip()));
+        entries
+    }
+
+    /// Drops the mounts of clients idle for longer than the idle timeout
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<MountEntry>` - The mounts dropped, empty if the table has no
+    ///   liveness configuration
+    pub fn expire(&self, now: SystemTime) -> Vec<MountEntry> {
+        let Some(liveness) = self.liveness else {
+            return Vec::new();
+        };
+        let deadline = millis(now).saturating_sub(liveness.idle_timeout.as_millis() as u64);
+        let mut expired = Vec::new();
+        self.clients.write().unwrap().retain(|_, mounts| {
+            if mounts.last_seen.load(Ordering::Relaxed) >= deadline {
+                return true;
+            }
+            let addr = mounts.addr;
+            let paths = std::mem::take(&mut mounts.paths);
+            expired.extend(paths.into_iter().map(|path| MountEntry { client: addr, path }));
+            false
+        });
+        expired
+    }
+
+    /// Returns the number of mounts
+    pub fn len(&self) -> usize {
+        self.clients.read().unwrap().values().map(|mounts| mounts.paths.len()).sum()
+    }
+
+    /// Returns whether no client holds a mount
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_mounts_of_idle_clients() {
+        let liveness =
+            MountLivenessConfig { idle_timeout: Duration::from_secs(60), ..Default::default() };
+        let table = MountTable::new(Some(liveness));
+        let (a, b): (SocketAddr, SocketAddr) =
+            (([10, 0, 0, 1], 700).into(), ([10, 0, 0, 2], 800).into());
+        let start = UNIX_EPOCH + Duration::from_secs(1_000);
+        assert!(table.mounted(a, b"/export", start));
+        assert!(!table.mounted(a, b"/export", start));
+        table.mounted(a, b"/export/sub", start);
+        table.mounted(b, b"/export", start);
+        assert_eq!(table.len(), 3);
+
+        // calls from another port of the same host keep the client alive
+        table.touch(IpAddr::from([10, 0, 0, 1]), start + Duration::from_secs(50));
+        assert!(table.expire(start + Duration::from_secs(59)).is_empty());
+        let expired = table.expire(start + Duration::from_secs(61));
+        assert_eq!(expired, [MountEntry { client: b, path: b"/export".to_vec() }]);
+
+        assert!(table.unmounted(a.ip(), b"/export"));
+        assert!(!table.unmounted(a.ip(), b"/export"));
+        assert_eq!(table.unmounted_all(a.ip()), [b"/export/sub".to_vec()]);
+        assert!(table.is_empty());
+
+        // without liveness mounts stay until unmounted
+        let table = MountTable::default();
+        table.mounted(a, b"/export", start);
+        assert!(table.expire(start + Duration::from_secs(86_400)).is_empty());
+    }
+}
@@ -1,6 +1,6 @@
 use std::io::Write;
 
-use crate::protocol::rpc::Context;
+use crate::protocol::rpc::{Context, ServerError};
 use crate::xdr;
 use crate::xdr::portmap::{mapping, pmaplist};
 use crate::xdr::Serialize;
@@ -25,19 +25,8 @@ pub fn pmapproc_dump(
     xid: u32,
     output: &mut impl Write,
     context: &Context,
-) -> Result<(), anyhow::Error> {
-    let binding = context.portmap_table.read().unwrap();
-    let entries: Vec<mapping> = binding
-        .table
-        .iter()
-        .map(|(entry, port)| mapping {
-            prog: entry.prog,
-            vers: entry.vers,
-            prot: entry.prot,
-            port: *port as u32,
-        })
-        .collect();
-    drop(binding);
+) -> Result<(), ServerError> {
+    let entries: Vec<mapping> = context.portmap_table.read().unwrap().mappings();
     let result = {
         let mut list_head = None;
         for map in entries.iter().rev() {
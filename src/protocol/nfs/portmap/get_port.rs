@@ -5,7 +5,7 @@
 use std::io::{Read, Write};
 
 use crate::protocol::nfs::portmap::{get_port, PortmapKey};
-use crate::protocol::rpc::Context;
+use crate::protocol::rpc::{Context, ServerError};
 use crate::protocol::xdr::{self, deserialize, portmap::mapping, Serialize};
 
 /// Handles `PMAPPROC_GETPORT` procedure.
@@ -25,7 +25,7 @@ use crate::protocol::xdr::{self, deserialize, portmap::mapping, Serialize};
 /// * `context` - Shared RPC context containing the portmap table (read-only)
 ///
 /// # Returns
-/// `Result<(), anyhow::Error>` indicating:
+/// `Result<(), ServerError>` indicating:
 /// - `Ok(())` on successful operation
 /// - `Err` if deserialization or serialization fails
 ///
@@ -41,7 +41,7 @@ pub fn pmapproc_getport(
     read: &mut impl Read,
     output: &mut impl Write,
     context: &Context,
-) -> Result<(), anyhow::Error> {
+) -> Result<(), ServerError> {
     let mapping = deserialize::<mapping>(read)?;
     let entry = PortmapKey { prog: mapping.prog, vers: mapping.vers, prot: mapping.prot };
     let port = get_port(context, &entry);
@@ -3,9 +3,10 @@
 
 use std::collections::HashMap;
 use std::io::{Read, Write};
+use std::net::SocketAddr;
 
 use num_traits::cast::FromPrimitive;
-use tracing::error;
+use tracing::{error, warn};
 
 use crate::protocol::xdr::{self, portmap, Serialize};
 
@@ -18,7 +19,7 @@ mod unset_port;
 use crate::protocol::nfs::portmap::dump::pmapproc_dump;
 use crate::protocol::nfs::portmap::set_port::pmapproc_setport;
 use crate::protocol::nfs::portmap::unset_port::pmapproc_unsetport;
-use crate::protocol::rpc::Context;
+use crate::protocol::rpc::{Context, ServerError};
 use get_port::pmapproc_getport;
 use null::pmapproc_null;
 
@@ -26,6 +27,92 @@ use null::pmapproc_null;
 #[derive(Default)]
 pub struct PortmapTable {
     table: HashMap<PortmapKey, u16>,
+    policy: RegistrationPolicy,
+}
+
+impl PortmapTable {
+    /// Creates an empty table that accepts `SET` and `UNSET` calls according to `policy`
+    pub fn with_policy(policy: RegistrationPolicy) -> Self {
+        Self { table: HashMap::new(), policy }
+    }
+
+    /// Changes which clients may change mappings, keeping the mappings
+    pub fn set_policy(&mut self, policy: RegistrationPolicy) {
+        self.policy = policy;
+    }
+
+    /// Maps a program version and transport protocol to a port, replacing any
+    /// previous mapping
+    ///
+    /// Used by the server to announce its own programs; unlike `PMAPPROC_SET`
+    /// it is not subject to the registration policy.
+    pub fn register(&mut self, prog: u32, vers: u32, prot: u32, port: u16) {
+        self.table.insert(PortmapKey { prog, vers, prot }, port);
+    }
+
+    /// Removes the mapping of a program version and transport protocol,
+    /// returning its port
+    ///
+    /// Lets the server drop mappings of services known to be gone, which
+    /// registered clients otherwise leave behind; like
+    /// [`PortmapTable::register`], it is not subject to the registration policy.
+    pub fn unregister(&mut self, prog: u32, vers: u32, prot: u32) -> Option<u16> {
+        self.table.remove(&PortmapKey { prog, vers, prot })
+    }
+
+    /// Returns all mappings, in no particular order
+    pub fn mappings(&self) -> Vec<portmap::mapping> {
+        self.table
+            .iter()
+            .map(|(key, port)| portmap::mapping {
+                prog: key.prog,
+                vers: key.vers,
+                prot: key.prot,
+                port: u32::from(*port),
+            })
+            .collect()
+    }
+
+    /// Returns the number of mappings
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    /// Returns whether there are no mappings
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+}
+
+/// Restricts which clients may change the mappings of a [`PortmapTable`]
+///
+/// `PMAPPROC_SET` and `PMAPPROC_UNSET` from clients that do not satisfy the
+/// policy are answered with `false` and leave the table unchanged, like rpcbind
+/// does for non-local callers. The default accepts every client.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RegistrationPolicy {
+    /// Only accept calls from loopback addresses
+    pub loopback_only: bool,
+    /// Only accept calls from privileged source ports (below 1024), which only
+    /// privileged processes can bind on most systems
+    pub privileged_port_only: bool,
+}
+
+impl RegistrationPolicy {
+    /// Accepts only local callers bound to a privileged port
+    pub const STRICT: Self = Self { loopback_only: true, privileged_port_only: true };
+
+    /// Returns whether a client at `client_addr` may change mappings
+    pub fn allows(&self, client_addr: SocketAddr) -> bool {
+        let loopback = match client_addr {
+            SocketAddr::V4(v4) => v4.ip().is_loopback(),
+            SocketAddr::V6(v6) => {
+                v6.ip().is_loopback() || v6.ip().to_ipv4_mapped().is_some_and(|v4| v4.is_loopback())
+            }
+        };
+        (!self.loopback_only || loopback)
+            && (!self.privileged_port_only || client_addr.port() < 1024)
+    }
 }
 ///Represents entry of PortmapTable
 #[derive(Debug, Hash, Eq, PartialEq)]
@@ -55,14 +142,14 @@ pub struct PortmapKey {
 ///
 /// # Returns
 ///
-/// * `Result<(), anyhow::Error>` - Ok(()) on success or an error
+/// * `Result<(), ServerError>` - Ok(()) on success or an error
 pub fn handle_portmap(
     xid: u32,
     call: &xdr::rpc::call_body,
     input: &mut impl Read,
     output: &mut impl Write,
     context: &mut Context,
-) -> Result<(), anyhow::Error> {
+) -> Result<(), ServerError> {
     if call.vers != portmap::VERSION {
         error!("Invalid Portmap Version number {} != {}", call.vers, portmap::VERSION);
         xdr::rpc::prog_mismatch_reply_message(xid, portmap::VERSION).serialize(output)?;
@@ -84,6 +171,15 @@ pub fn handle_portmap(
     Ok(())
 }
 
+/// Returns whether the caller may change mappings, logging rejected calls
+fn registration_allowed(context: &Context, procedure: &str) -> bool {
+    let allowed = context.portmap_table.read().unwrap().policy.allows(context.client_addr);
+    if !allowed {
+        warn!("rejecting {} from {}", procedure, context.client_addr);
+    }
+    allowed
+}
+
 /// Looks up a port in the Portmap table using the specified entry
 fn get_port(context: &Context, entry: &PortmapKey) -> Option<u16> {
     let binding = context.portmap_table.read().unwrap();
@@ -6,6 +6,7 @@ use std::io::Write;
 
 use tracing::debug;
 
+use crate::protocol::rpc;
 use crate::protocol::xdr::{self, Serialize};
 
 /// Handles `PMAPPROC_NULL` procedure.
@@ -20,8 +21,8 @@ use crate::protocol::xdr::{self, Serialize};
 ///
 /// # Returns
 ///
-/// * `Result<(), anyhow::Error>` - Ok(()) on success or an error
-pub fn pmapproc_null(xid: u32, output: &mut impl Write) -> Result<(), anyhow::Error> {
+/// * `Result<(), rpc::ServerError>` - Ok(()) on success or an error
+pub fn pmapproc_null(xid: u32, output: &mut impl Write) -> Result<(), rpc::ServerError> {
     debug!("pmapproc_null({:?}) ", xid);
     // build an RPC reply
     let msg = xdr::rpc::make_success_reply(xid);
@@ -1,7 +1,7 @@
 use std::io::{Read, Write};
 
-use crate::protocol::nfs::portmap::PortmapKey;
-use crate::protocol::rpc::Context;
+use crate::protocol::nfs::portmap::{registration_allowed, PortmapKey};
+use crate::protocol::rpc::{Context, ServerError};
 use crate::xdr;
 use crate::xdr::portmap::mapping;
 use crate::xdr::{deserialize, Serialize};
@@ -15,20 +15,27 @@ use crate::xdr::{deserialize, Serialize};
 /// * `context` - Shared RPC context containing the portmap table
 ///
 /// # Returns
-/// `Result<(), anyhow::Error>` indicating success or failure
+/// `Result<(), ServerError>` indicating success or failure
 ///
 /// # Behavior
 /// 1. Deserializes the mapping request
-/// 2. Checks if the mapping already exists
-/// 3. If not exists, adds the new mapping
-/// 4. Sends success response with boolean result (true = added, false = existed)
+/// 2. Checks the caller against the table's registration policy
+/// 3. Checks if the mapping already exists
+/// 4. If not exists, adds the new mapping
+/// 5. Sends success response with boolean result (true = added, false = existed
+///    or rejected)
 pub fn pmapproc_setport(
     xid: u32,
     read: &mut impl Read,
     output: &mut impl Write,
     context: &mut Context,
-) -> Result<(), anyhow::Error> {
+) -> Result<(), ServerError> {
     let mapping = deserialize::<mapping>(read)?;
+    if !registration_allowed(context, "PMAPPROC_SET") {
+        xdr::rpc::make_success_reply(xid).serialize(output)?;
+        false.serialize(output)?;
+        return Ok(());
+    }
     let entry = PortmapKey { prog: mapping.prog, vers: mapping.vers, prot: mapping.prot };
     let mut binding = context.portmap_table.write().unwrap();
     let port = binding.table.get(&entry).copied();
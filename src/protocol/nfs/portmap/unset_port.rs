@@ -1,7 +1,7 @@
 use std::io::{Read, Write};
 
-use crate::protocol::nfs::portmap::PortmapKey;
-use crate::protocol::rpc::Context;
+use crate::protocol::nfs::portmap::{registration_allowed, PortmapKey};
+use crate::protocol::rpc::{Context, ServerError};
 use crate::xdr;
 use crate::xdr::portmap::{mapping, IPPROTO_TCP, IPPROTO_UDP};
 use crate::xdr::{deserialize, Serialize};
@@ -11,8 +11,9 @@ use crate::xdr::{deserialize, Serialize};
 /// This RPC procedure (`PMAPPROC_UNSET`) handles requests to unregister a program's ports
 /// for both TCP and UDP protocols. It performs the following steps:
 /// 1. Deserializes the input `mapping` (containing `prog`, `vers`, etc.).
-/// 2. Attempts to remove entries for both TCP (`IPPROTO_TCP`) and UDP (`IPPROTO_UDP`).
-/// 3. Returns an RPC success reply with a boolean indicating if any deletion occurred.
+/// 2. Replies `false` if the caller is rejected by the table's registration policy.
+/// 3. Attempts to remove entries for both TCP (`IPPROTO_TCP`) and UDP (`IPPROTO_UDP`).
+/// 4. Returns an RPC success reply with a boolean indicating if any deletion occurred.
 ///
 /// # Parameters
 /// - `xid`: Transaction ID for RPC reply correlation.
@@ -24,14 +25,19 @@ use crate::xdr::{deserialize, Serialize};
 /// - `Ok(())` on success, serializing:
 ///   - RPC success header (via `make_success_reply`).
 ///   - Boolean `true` if at least one port was removed, `false` otherwise.
-/// - `Err(anyhow::Error)` on deserialization or serialization failures.
+/// - `Err(ServerError)` on deserialization or serialization failures.
 pub fn pmapproc_unsetport(
     xid: u32,
     read: &mut impl Read,
     output: &mut impl Write,
     context: &Context,
-) -> Result<(), anyhow::Error> {
+) -> Result<(), ServerError> {
     let mapping = deserialize::<mapping>(read)?;
+    if !registration_allowed(context, "PMAPPROC_UNSET") {
+        xdr::rpc::make_success_reply(xid).serialize(output)?;
+        false.serialize(output)?;
+        return Ok(());
+    }
     let mut binding = context.portmap_table.write().unwrap();
     let tcp_removed = binding
         .table
@@ -44,18 +44,18 @@ use crate::vfs;
 ///
 /// # Returns
 ///
-/// * `Result<(), anyhow::Error>` - Ok(()) on success or an error
+/// * `Result<(), rpc::ServerError>` - Ok(()) on success or an error
 pub async fn nfsproc3_access(
     xid: u32,
     input: &mut impl Read,
     output: &mut impl Write,
     context: &rpc::Context,
-) -> Result<(), anyhow::Error> {
+) -> Result<(), rpc::ServerError> {
     let handle = deserialize::<nfs3::nfs_fh3>(input)?;
     let access = deserialize::<u32>(input)?;
     debug!("nfsproc3_access({:?},{:?},{:?})", xid, handle, access);
 
-    let id = context.vfs.fh_to_id(&handle);
+    let id = context.fh_to_id(&handle);
     // Fail if unable to convert file handle
     if let Err(stat) = id {
         xdr::rpc::make_success_reply(xid).serialize(output)?;
@@ -66,6 +66,7 @@ pub async fn nfsproc3_access(
     let id = id.unwrap();
 
     // Get object attributes
+    super::flush_coalesced(context, id).await;
     let obj_attr = match context.vfs.getattr(id).await {
         Ok(v) => nfs3::post_op_attr::Some(v),
         Err(stat) => {
@@ -97,67 +98,11 @@ pub async fn nfsproc3_access(
         }
     };
 
-    // Check access permissions based on file type and attributes
-    let mut granted_access = 0;
-
-    // Always allow LOOKUP for existing objects
-    granted_access |= nfs3::ACCESS3_LOOKUP;
-
-    // Check permissions based on file type
-    match attr.ftype {
-        nfs3::ftype3::NF3REG => {
-            // For regular files
-            if !matches!(context.vfs.capabilities(), vfs::Capabilities::ReadWrite) {
-                // If the file system is read-only, allow only reading
-                if access & (nfs3::ACCESS3_READ | nfs3::ACCESS3_EXECUTE) != 0 {
-                    granted_access |= access & (nfs3::ACCESS3_READ | nfs3::ACCESS3_EXECUTE);
-                }
-            } else {
-                // If the file system supports read and write, check access permissions
-                // Here you can add a check for real access permissions based on file attributes
-                // For example, check owner, group, and access permissions
-
-                // For simplicity, allow all requested permissions
-                granted_access |= access;
-            }
-        }
-        nfs3::ftype3::NF3DIR => {
-            // For directories
-            if !matches!(context.vfs.capabilities(), vfs::Capabilities::ReadWrite) {
-                // If the file system is read-only, allow only reading
-                if access & nfs3::ACCESS3_READ != 0 {
-                    granted_access |= nfs3::ACCESS3_READ;
-                }
-            } else {
-                // If the file system supports read and write, check access permissions
-                // For directories, allow reading and execution
-                if access & (nfs3::ACCESS3_READ | nfs3::ACCESS3_EXECUTE) != 0 {
-                    granted_access |= access & (nfs3::ACCESS3_READ | nfs3::ACCESS3_EXECUTE);
-                }
-
-                // For operations that modify the directory, check write permissions
-                if access & (nfs3::ACCESS3_MODIFY | nfs3::ACCESS3_EXTEND | nfs3::ACCESS3_DELETE)
-                    != 0
-                {
-                    // Here you can add a check for real access permissions
-                    granted_access |= access
-                        & (nfs3::ACCESS3_MODIFY | nfs3::ACCESS3_EXTEND | nfs3::ACCESS3_DELETE);
-                }
-            }
-        }
-        nfs3::ftype3::NF3LNK => {
-            // For symbolic links, allow only reading
-            if access & nfs3::ACCESS3_READ != 0 {
-                granted_access |= nfs3::ACCESS3_READ;
-            }
-        }
-        _ => {
-            // For other file types (devices, sockets, etc.)
-            // Allow only reading and execution
-            if access & (nfs3::ACCESS3_READ | nfs3::ACCESS3_EXECUTE) != 0 {
-                granted_access |= access & (nfs3::ACCESS3_READ | nfs3::ACCESS3_EXECUTE);
-            }
-        }
+    // Check access permissions based on mode, owner and group of the object
+    let mut granted_access = vfs::permissions::access(&attr, &context.auth, access);
+    if !context.vfs.capabilities().contains(vfs::Capabilities::WRITE) {
+        // A read-only file system allows no changes whatever the mode says
+        granted_access &= !(nfs3::ACCESS3_MODIFY | nfs3::ACCESS3_EXTEND | nfs3::ACCESS3_DELETE);
     }
 
     debug!(" {:?} ---> {:?}", xid, granted_access);
@@ -0,0 +1,241 @@
+//! End-to-end checksums of the data written and read.
+//!
+//! Backends that transform data on its way to storage, such as encrypting,
+//! compressing or object store backends, can corrupt it in ways the file
+//! system never notices. [`Checksums`] computes a CRC-32C of the data of every
+//! `WRITE` as received from the client, and of every `READ` as sent back.
+//! Whenever a read covers a range written earlier, the checksum of that part of
+//! the read data is compared with the one recorded at write time, and a
+//! mismatch is logged as an error and counted.
+//!
+//! Checksums are logged at debug level and can be queried with
+//! [`Checksums::written`], so operators can compare them with checksums taken
+//! elsewhere. Only the most recent writes are remembered, see
+//! [`ChecksumConfig::max_tracked`]. Reads streamed after the reply (see
+//! [`crate::protocol::rpc::ReplyStream`]) are not checked, as their data never
+//! passes the handler.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use tracing::{debug, error};
+
+use crate::protocol::xdr::nfs3;
+
+/// Settings of [`Checksums`]
+#[derive(Clone, Copy, Debug)]
+pub struct ChecksumConfig {
+    /// Number of written ranges whose checksums are remembered; the oldest are
+    /// forgotten first beyond it
+    pub max_tracked: usize,
+}
+
+impl Default for ChecksumConfig {
+    fn default() -> Self {
+        Self { max_tracked: 65536 }
+    }
+}
+
+/// Length and checksum of a written range
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Written {
+    len: u32,
+    crc: u32,
+}
+
+/// Checksums of written ranges
+#[derive(Debug, Default)]
+struct Ranges {
+    /// Ranges by start offset per file, disjoint
+    files: HashMap<nfs3::fileid3, BTreeMap<u64, Written>>,
+    /// File and start offset of the ranges in the order written
+    order: VecDeque<(nfs3::fileid3, u64, Written)>,
+}
+
+impl Ranges {
+    /// Forgets the ranges of a file overlapping `start..end`
+    fn forget(&mut self, id: nfs3::fileid3, start: u64, end: u64) {
+        let Some(file) = self.files.get_mut(&id) else {
+            return;
+        };
+        let overlapping: Vec<u64> = file
+            .range(..end)
+            .rev()
+            .take_while(|(&offset, written)| offset + u64::from(written.len) > start)
+            .map(|(&offset, _)| offset)
+            .collect();
+        for offset in overlapping {
+            file.remove(&offset);
+        }
+        if file.is_empty() {
+            self.files.remove(&id);
+        }
+    }
+}
+
+/// Records checksums of written data and verifies the data read against them
+#[derive(Debug, Default)]
+pub struct Checksums {
+    config: ChecksumConfig,
+    ranges: Mutex<Ranges>,
+    /// Number of reads whose data differed from the data written
+    mismatches: AtomicU64,
+}
+
+impl Checksums {
+    /// Creates a tracker with the given settings
+    pub fn new(config: ChecksumConfig) -> Self {
+        Self { config, ..Self::default() }
+    }
+
+    /// Records the data written at `offset` of a file
+    ///
+    /// Checksums of earlier writes overlapping the range are forgotten.
+    pub fn record_write(&self, id: nfs3::fileid3, offset: u64, data: &[u8]) {
+        let written = Written { len: data.len() as u32, crc: crc32c(data) };
+        debug!("checksum of {} bytes at {} of {}: {:08x}", written.len, offset, id, written.crc);
+        let mut ranges = self.ranges.lock().unwrap();
+        ranges.forget(id, offset, offset.saturating_add(u64::from(written.len)));
+        if written.len == 0 || self.config.max_tracked == 0 {
+            return;
+        }
+        while ranges.order.len() >= self.config.max_tracked {
+            let Some((old_id, old_offset, old)) = ranges.order.pop_front() else {
+                break;
+            };
+            // the range may have been overwritten since
+            if let Some(file) = ranges.files.get_mut(&old_id) {
+                if file.get(&old_offset) == Some(&old) {
+                    file.remove(&old_offset);
+                }
+                if file.is_empty() {
+                    ranges.files.remove(&old_id);
+                }
+            }
+        }
+        ranges.files.entry(id).or_default().insert(offset, written);
+        ranges.order.push_back((id, offset, written));
+    }
+
+    /// Checks the data read at `offset` of a file against the checksums of the
+    /// ranges written earlier within it
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Whether all those ranges matched
+    pub fn check_read(&self, id: nfs3::fileid3, offset: u64, data: &[u8]) -> bool {
+        debug!(
+            "checksum of {} bytes read at {} of {}: {:08x}",
+            data.len(),
+            offset,
+            id,
+            crc32c(data)
+        );
+        let end = offset.saturating_add(data.len() as u64);
+        let covered: Vec<(u64, Written)> = {
+            let ranges = self.ranges.lock().unwrap();
+            let Some(file) = ranges.files.get(&id) else {
+                return true;
+            };
+            file.range(offset..end)
+                .filter(|(&start, written)| start + u64::from(written.len) <= end)
+                .map(|(&start, written)| (start, *written))
+                .collect()
+        };
+        let mut matched = true;
+        for (start, written) in covered {
+            let from = (start - offset) as usize;
+            let crc = crc32c(&data[from..from + written.len as usize]);
+            if crc != written.crc {
+                error!(
+                    "data of {} bytes at {} of {} changed since written: checksum {:08x}, \
+                     expected {:08x}",
+                    written.len, start, id, crc, written.crc
+                );
+                self.mismatches.fetch_add(1, Ordering::Relaxed);
+                matched = false;
+            }
+        }
+        matched
+    }
+
+    /// Forgets the checksums of a file beyond `size`, after it was truncated
+    pub fn truncate(&self, id: nfs3::fileid3, size: u64) {
+        self.ranges.lock().unwrap().forget(id, size, u64::MAX);
+    }
+
+    /// Forgets the checksums of a file, after it was removed
+    pub fn forget(&self, id: nfs3::fileid3) {
+        self.ranges.lock().unwrap().files.remove(&id);
+    }
+
+    /// Returns the checksum of the data last written at `offset` of a file, if
+    /// `len` bytes were written there at once and are still remembered
+    pub fn written(&self, id: nfs3::fileid3, offset: u64, len: u32) -> Option<u32> {
+        let ranges = self.ranges.lock().unwrap();
+        let written = ranges.files.get(&id)?.get(&offset)?;
+        (written.len == len).then_some(written.crc)
+    }
+
+    /// Returns the number of reads whose data differed from the data written
+    pub fn mismatches(&self) -> u64 {
+        self.mismatches.load(Ordering::Relaxed)
+    }
+}
+
+/// Lookup table of the CRC-32C polynomial, in reflected form
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0x82f6_3b78 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Computes the CRC-32C (Castagnoli) checksum of `data`, as used by iSCSI and
+/// most storage systems
+pub fn crc32c(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        CRC32C_TABLE[((crc ^ u32::from(byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_changed_data() {
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+
+        let checksums = Checksums::new(ChecksumConfig { max_tracked: 2 });
+        checksums.record_write(1, 0, b"hello");
+        checksums.record_write(1, 5, b"world");
+        assert_eq!(checksums.written(1, 0, 5), Some(crc32c(b"hello")));
+        assert!(checksums.check_read(1, 0, b"helloworld"));
+        assert!(!checksums.check_read(1, 0, b"hellow0rld"));
+        // ranges only partly read are not checked
+        assert!(checksums.check_read(1, 2, b"lloworld"));
+        assert_eq!(checksums.mismatches(), 1);
+
+        // overwrites replace the checksums, the oldest are forgotten first
+        checksums.record_write(1, 3, b"p!");
+        assert_eq!(checksums.written(1, 0, 5), None);
+        assert!(checksums.check_read(1, 0, b"help!world"));
+        checksums.record_write(2, 0, b"x");
+        assert_eq!(checksums.written(1, 5, 5), None);
+        assert_eq!(checksums.written(1, 3, 2), Some(crc32c(b"p!")));
+
+        checksums.truncate(1, 4);
+        assert_eq!(checksums.written(1, 3, 2), None);
+    }
+}
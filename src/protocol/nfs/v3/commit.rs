@@ -17,6 +17,10 @@
 //! - The file attributes before and after the operation
 //! - A write verifier that the client can compare with the one from previous WRITEs
 //!   to detect server reboots that might have lost uncommitted data
+//!
+//! The range counts as committed once the VFS commit succeeded. A failed commit
+//! may have lost the uncommitted data of the file, so it changes the verifier,
+//! see [`super::UnstableWrites`].
 
 use std::io::{Read, Write};
 
@@ -40,17 +44,17 @@ use crate::protocol::xdr::{self, deserialize, nfs3, Serialize};
 ///
 /// # Returns
 ///
-/// * `Result<(), anyhow::Error>` - Ok(()) on success or an error
+/// * `Result<(), rpc::ServerError>` - Ok(()) on success or an error
 pub async fn nfsproc3_commit(
     xid: u32,
     input: &mut impl Read,
     output: &mut impl Write,
     context: &rpc::Context,
-) -> Result<(), anyhow::Error> {
+) -> Result<(), rpc::ServerError> {
     let args = deserialize::<nfs3::file::COMMIT3args>(input)?;
     debug!("nfsproc3_commit({:?}, {:?}) ", xid, args);
 
-    let id = context.vfs.fh_to_id(&args.file);
+    let id = context.fh_to_id(&args.file);
     // fail if unable to convert file handle
     if let Err(stat) = id {
         xdr::rpc::make_success_reply(xid).serialize(output)?;
@@ -59,6 +63,7 @@ pub async fn nfsproc3_commit(
         return Ok(());
     }
     let id = id.unwrap();
+    super::record_io(context, id);
 
     // get the object attributes before the commit
     let pre_obj_attr = context
@@ -68,14 +73,43 @@ pub async fn nfsproc3_commit(
         .map(|v| nfs3::wcc_attr { size: v.size, mtime: v.mtime, ctime: v.ctime })
         .ok();
 
+    if let Some(Err(stat)) = context.content_inspection.as_ref().map(|c| c.check_access(id)) {
+        xdr::rpc::make_success_reply(xid).serialize(output)?;
+        stat.serialize(output)?;
+        nfs3::wcc_data { before: pre_obj_attr, after: None }.serialize(output)?;
+        return Ok(());
+    }
+
+    // Write out coalesced data first, failures of deferred writes fail the commit
+    let coalesced = match &context.write_coalescer {
+        Some(coalescer) => coalescer.commit(context.vfs.as_ref(), id).await,
+        None => Ok(()),
+    };
+
     // Call VFS commit method
-    match context.vfs.commit(id, args.offset, args.count).await {
+    let result = match coalesced {
+        Ok(()) => context.vfs.commit(id, args.offset, args.count).await,
+        Err(stat) => Err(stat),
+    };
+    match result {
         Ok(fattr) => {
+            context.unstable_writes.commit(id, args.offset, args.count);
             let post_obj_attr = nfs3::post_op_attr::Some(fattr);
 
+            // the data is stable, refusing it must not make clients send it again
+            if let Some(inspection) = &context.content_inspection {
+                if let Err(stat) = inspection.check_commit(context.vfs.as_ref(), id).await {
+                    xdr::rpc::make_success_reply(xid).serialize(output)?;
+                    stat.serialize(output)?;
+                    nfs3::wcc_data { before: pre_obj_attr, after: post_obj_attr }
+                        .serialize(output)?;
+                    return Ok(());
+                }
+            }
+
             let res = nfs3::file::COMMIT3resok {
                 file_wcc: nfs3::wcc_data { before: pre_obj_attr, after: post_obj_attr },
-                verf: context.vfs.server_id(),
+                verf: context.unstable_writes.verifier(context.vfs.server_id()),
             };
 
             debug!("nfsproc3_commit success");
@@ -84,6 +118,7 @@ pub async fn nfsproc3_commit(
             res.serialize(output)?;
         }
         Err(stat) => {
+            context.unstable_writes.lose(id);
             let post_obj_attr = context.vfs.getattr(id).await.ok();
 
             let wcc_data = nfs3::wcc_data { before: pre_obj_attr, after: post_obj_attr };
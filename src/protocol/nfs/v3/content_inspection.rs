@@ -0,0 +1,187 @@
+//! Inspection of the content clients write, e.g. by virus scanners.
+//!
+//! Shares clients upload to often must not store, or hand out, content a
+//! scanner objects to. A [`ContentInspector`] is asked about:
+//!
+//! - the data of every `WRITE`, before it reaches the file system, and
+//! - every file whose data a `COMMIT` made stable, after the commit.
+//!
+//! `NFSv3` has no `CLOSE`, so a `COMMIT` is the closest the protocol comes to a
+//! client being done with a file. Clients writing with `FILE_SYNC` may never
+//! commit, so their files are only inspected write by write.
+//!
+//! A [`Verdict::Deny`] fails the call with `NFS3ERR_ACCES`. A
+//! [`Verdict::Quarantine`] fails it the same way and refuses every later
+//! `READ`, `WRITE` and `COMMIT` of the file, until [`ContentInspection::release`]
+//! is called, e.g. after an operator had a look. The inspector may also move or
+//! remove the file itself.
+
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use tracing::warn;
+
+use crate::protocol::xdr::nfs3;
+use crate::vfs::NFSFileSystem;
+
+/// Decision of a [`ContentInspector`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// Let the call proceed
+    Allow,
+    /// Fail the call with `NFS3ERR_ACCES`
+    Deny,
+    /// Fail the call and refuse further I/O on the file
+    Quarantine,
+}
+
+/// Inspects the content written to files
+///
+/// Both methods allow everything by default, so an inspector implements only
+/// what it needs. They run on the connection's task, so slow scanners should
+/// be queried with a timeout.
+#[async_trait]
+pub trait ContentInspector: Send + Sync {
+    /// Inspects data before it is written at `offset` of a file
+    async fn inspect_write(&self, _id: nfs3::fileid3, _offset: u64, _data: &[u8]) -> Verdict {
+        Verdict::Allow
+    }
+
+    /// Inspects a file after its data was committed
+    ///
+    /// # Arguments
+    ///
+    /// * `vfs` - File system to read the file from
+    /// * `id` - The committed file
+    async fn inspect_file(
+        &self,
+        _vfs: &(dyn NFSFileSystem + Send + Sync),
+        _id: nfs3::fileid3,
+    ) -> Verdict {
+        Verdict::Allow
+    }
+}
+
+/// Applies the verdicts of an inspector and tracks quarantined files
+pub struct ContentInspection {
+    inspector: Box<dyn ContentInspector>,
+    quarantined: Mutex<HashSet<nfs3::fileid3>>,
+}
+
+impl ContentInspection {
+    /// Creates an inspection asking `inspector`
+    pub fn new(inspector: impl ContentInspector + 'static) -> Self {
+        Self { inspector: Box::new(inspector), quarantined: Mutex::default() }
+    }
+
+    /// Checks that a file is not quarantined
+    pub fn check_access(&self, id: nfs3::fileid3) -> Result<(), nfs3::nfsstat3> {
+        if self.is_quarantined(id) {
+            return Err(nfs3::nfsstat3::NFS3ERR_ACCES);
+        }
+        Ok(())
+    }
+
+    /// Checks data about to be written at `offset` of a file
+    pub async fn check_write(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(), nfs3::nfsstat3> {
+        self.check_access(id)?;
+        let verdict = self.inspector.inspect_write(id, offset, data).await;
+        self.apply(id, verdict)
+    }
+
+    /// Checks a file whose data was committed
+    pub async fn check_commit(
+        &self,
+        vfs: &(dyn NFSFileSystem + Send + Sync),
+        id: nfs3::fileid3,
+    ) -> Result<(), nfs3::nfsstat3> {
+        let verdict = self.inspector.inspect_file(vfs, id).await;
+        self.apply(id, verdict)
+    }
+
+    /// Returns whether I/O on a file is refused
+    pub fn is_quarantined(&self, id: nfs3::fileid3) -> bool {
+        self.quarantined.lock().unwrap().contains(&id)
+    }
+
+    /// Lifts the quarantine of a file, returning whether it was quarantined
+    pub fn release(&self, id: nfs3::fileid3) -> bool {
+        self.quarantined.lock().unwrap().remove(&id)
+    }
+
+    /// Turns a verdict on a file into the result of the call
+    fn apply(&self, id: nfs3::fileid3, verdict: Verdict) -> Result<(), nfs3::nfsstat3> {
+        match verdict {
+            Verdict::Allow => return Ok(()),
+            Verdict::Deny => warn!("content of {} denied by the inspector", id),
+            Verdict::Quarantine => {
+                warn!("{} quarantined by the inspector", id);
+                self.quarantined.lock().unwrap().insert(id);
+            }
+        }
+        Err(nfs3::nfsstat3::NFS3ERR_ACCES)
+    }
+}
+
+impl std::fmt::Debug for ContentInspection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ContentInspection").field("quarantined", &self.quarantined).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::testing::MockFs;
+
+    /// Denies data containing "virus", quarantines files on commit
+    struct Scanner;
+
+    #[async_trait]
+    impl ContentInspector for Scanner {
+        async fn inspect_write(&self, _id: nfs3::fileid3, _offset: u64, data: &[u8]) -> Verdict {
+            if data.windows(5).any(|window| window == b"virus") {
+                Verdict::Deny
+            } else {
+                Verdict::Allow
+            }
+        }
+
+        async fn inspect_file(
+            &self,
+            _vfs: &(dyn NFSFileSystem + Send + Sync),
+            id: nfs3::fileid3,
+        ) -> Verdict {
+            if id == 3 {
+                Verdict::Quarantine
+            } else {
+                Verdict::Allow
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn applies_verdicts() {
+        let inspection = ContentInspection::new(Scanner);
+        let fs = MockFs::new();
+        assert!(inspection.check_write(2, 0, b"hello").await.is_ok());
+        assert!(matches!(
+            inspection.check_write(2, 0, b"a virus").await,
+            Err(nfs3::nfsstat3::NFS3ERR_ACCES)
+        ));
+        // denied data leaves the file accessible
+        assert!(inspection.check_access(2).is_ok());
+        assert!(inspection.check_commit(&fs, 2).await.is_ok());
+
+        assert!(inspection.check_commit(&fs, 3).await.is_err());
+        assert!(inspection.check_write(3, 0, b"hello").await.is_err());
+        assert!(inspection.release(3));
+        assert!(inspection.check_access(3).is_ok());
+    }
+}
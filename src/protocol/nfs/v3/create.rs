@@ -23,6 +23,7 @@ use std::io::{Read, Write};
 
 use tracing::{debug, error, warn};
 
+use crate::protocol::nfs::events::EventKind;
 use crate::protocol::rpc;
 use crate::protocol::xdr::{self, deserialize, nfs3, Deserialize, Serialize};
 use crate::vfs;
@@ -42,15 +43,15 @@ use crate::vfs;
 ///
 /// # Returns
 ///
-/// * `Result<(), anyhow::Error>` - Ok(()) on success or an error
+/// * `Result<(), rpc::ServerError>` - Ok(()) on success or an error
 pub async fn nfsproc3_create(
     xid: u32,
     input: &mut impl Read,
     output: &mut impl Write,
     context: &rpc::Context,
-) -> Result<(), anyhow::Error> {
+) -> Result<(), rpc::ServerError> {
     // if we do not have write capabilities
-    if !matches!(context.vfs.capabilities(), vfs::Capabilities::ReadWrite) {
+    if !context.vfs.capabilities().contains(vfs::Capabilities::WRITE) {
         warn!("No write capabilities.");
         xdr::rpc::make_success_reply(xid).serialize(output)?;
         nfs3::nfsstat3::NFS3ERR_ROFS.serialize(output)?;
@@ -62,10 +63,19 @@ pub async fn nfsproc3_create(
     let createhow = deserialize::<nfs3::createmode3>(input)?;
 
     debug!("nfsproc3_create({:?}, {:?}, {:?}) ", xid, dirops, createhow);
+    if matches!(createhow, nfs3::createmode3::EXCLUSIVE)
+        && !context.vfs.capabilities().contains(vfs::Capabilities::EXCLUSIVE_CREATE)
+    {
+        warn!("No exclusive create capability.");
+        xdr::rpc::make_success_reply(xid).serialize(output)?;
+        nfs3::nfsstat3::NFS3ERR_NOTSUPP.serialize(output)?;
+        nfs3::wcc_data::default().serialize(output)?;
+        return Ok(());
+    }
 
     // find the directory we are supposed to create the
     // new file in
-    let dirid = context.vfs.fh_to_id(&dirops.dir);
+    let dirid = context.fh_to_id(&dirops.dir);
     if let Err(stat) = dirid {
         // directory does not exist
         xdr::rpc::make_success_reply(xid).serialize(output)?;
@@ -77,6 +87,9 @@ pub async fn nfsproc3_create(
     // found the directory, get the attributes
     let dirid = dirid.unwrap();
 
+    // changes of the directory run one at a time if configured
+    let _dir_guard = super::lock_dirs(context, dirid, None).await;
+
     // get the object attributes before the write
     let pre_dir_attr = match context.vfs.getattr(dirid).await {
         Ok(v) => {
@@ -97,6 +110,10 @@ pub async fn nfsproc3_create(
         nfs3::createmode3::UNCHECKED => {
             target_attributes.deserialize(input)?;
             debug!("create unchecked {:?}", target_attributes);
+            // an existing file is truncated rather than replaced
+            if target_attributes.size.is_some() {
+                super::flush_coalesced_name(context, dirid, &dirops.name).await;
+            }
         }
         nfs3::createmode3::GUARDED => {
             target_attributes.deserialize(input)?;
@@ -118,17 +135,37 @@ pub async fn nfsproc3_create(
         }
     }
 
+    // refuse files and names beyond the limits of the export
+    let mut limited =
+        target_attributes.size.map_or(Ok(()), |size| context.export_limits.check_size(size));
+    if limited.is_ok() {
+        limited = super::check_new_entry(context, dirid, &dirops.name, true).await;
+    }
+
     let fid: Result<nfs3::fileid3, nfs3::nfsstat3>;
     let postopattr: nfs3::post_op_attr;
     // fill in the fid and post op attr here
-    if matches!(createhow, nfs3::createmode3::EXCLUSIVE) {
+    if let Err(stat) = limited {
+        fid = Err(stat);
+        postopattr = nfs3::post_op_attr::None;
+    } else if matches!(createhow, nfs3::createmode3::EXCLUSIVE) {
         // the API for exclusive is very slightly different
         // We are not returning a post op attribute
         fid = context.vfs.create_exclusive(dirid, &dirops.name).await;
-        postopattr = nfs3::post_op_attr::None;
+        super::name_added(context, dirid, &dirops.name);
+        postopattr = match fid {
+            Ok(fid) => {
+                let attr = nfs3::sattr3::default();
+                super::set_creation_defaults(context, fid, attr, nfs3::ftype3::NF3REG).await
+            }
+            Err(_) => nfs3::post_op_attr::None,
+        };
     } else {
         // create!
+        super::apply_creation_defaults(context, &mut target_attributes, nfs3::ftype3::NF3REG);
+        super::resolve_server_time(context, &mut target_attributes);
         let res = context.vfs.create(dirid, &dirops.name, target_attributes).await;
+        super::name_added(context, dirid, &dirops.name);
         fid = res.map(|x| x.0);
         postopattr = res.map(|(_, fattr)| fattr).ok();
     }
@@ -140,11 +177,15 @@ pub async fn nfsproc3_create(
     match fid {
         Ok(fid) => {
             debug!("create success --> {:?}, {:?}", fid, postopattr);
+            super::account_created(context);
+            let name = dirops.name.0.clone();
+            context.publish(EventKind::Created { dirid, name, id: fid });
             xdr::rpc::make_success_reply(xid).serialize(output)?;
             nfs3::nfsstat3::NFS3_OK.serialize(output)?;
             // serialize CREATE3resok
-            let fh = context.vfs.id_to_fh(fid);
-            nfs3::post_op_fh3::Some(fh).serialize(output)?;
+            // the client looks the name up if the handle cannot be issued
+            let fh: nfs3::post_op_fh3 = context.id_to_fh(fid).ok();
+            fh.serialize(output)?;
             postopattr.serialize(output)?;
             wcc_res.serialize(output)?;
         }
@@ -0,0 +1,126 @@
+//! Server-side defaults for the attributes of new files.
+//!
+//! Clients may create files, directories and links without a mode or owner,
+//! leaving them to the server. Backends fill them in differently: some apply
+//! the umask of the server process, some the attributes of the parent
+//! directory, and some leave the mode at 0, so files come out unreadable
+//! depending on the backend behind an export. [`CreationDefaults`] fills in
+//! what the client left out before the backend is called, the same way for
+//! all backends:
+//!
+//! - the mode of files and special files defaults to
+//!   [`CreationDefaults::file_mode`], that of directories to
+//!   [`CreationDefaults::dir_mode`], both with the bits of
+//!   [`CreationDefaults::umask`] cleared;
+//! - the owner and group default as chosen by [`DefaultOwner`].
+//!
+//! Modes and owners sent by clients are kept as they are, as clients already
+//! apply the umask of their user. Symbolic links get no mode, as it has no
+//! meaning for them. Since the backend calls creating directories and
+//! exclusive files take no attributes, `MKDIR` and `EXCLUSIVE` `CREATE` apply
+//! them with a `SETATTR` right after the file was created.
+
+use crate::protocol::xdr::nfs3;
+use crate::protocol::xdr::rpc::auth_unix;
+
+/// Owner and group given to new files the client sends none for
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum DefaultOwner {
+    /// Left to the backend
+    #[default]
+    Backend,
+    /// The user and group of the `AUTH_UNIX` credentials of the caller
+    Caller,
+    /// A fixed user and group, e.g. the owner of a shared export
+    Fixed {
+        /// User ID
+        uid: u32,
+        /// Group ID
+        gid: u32,
+    },
+}
+
+/// Attributes given to new files where the client sends none
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default, deny_unknown_fields))]
+pub struct CreationDefaults {
+    /// Permission bits cleared from the default modes
+    pub umask: u32,
+    /// Mode of files and special files before the umask is applied
+    pub file_mode: u32,
+    /// Mode of directories before the umask is applied
+    pub dir_mode: u32,
+    /// Owner and group of new files
+    pub owner: DefaultOwner,
+}
+
+impl Default for CreationDefaults {
+    fn default() -> Self {
+        Self { umask: 0o022, file_mode: 0o666, dir_mode: 0o777, owner: DefaultOwner::Backend }
+    }
+}
+
+impl CreationDefaults {
+    /// Returns the mode of a new file of type `ftype` the client sent none for
+    ///
+    /// # Returns
+    ///
+    /// * `Option<u32>` - The mode, `None` for symbolic links
+    pub fn mode(&self, ftype: nfs3::ftype3) -> Option<u32> {
+        let mode = match ftype {
+            nfs3::ftype3::NF3LNK => return None,
+            nfs3::ftype3::NF3DIR => self.dir_mode,
+            _ => self.file_mode,
+        };
+        Some(mode & !self.umask & 0o7777)
+    }
+
+    /// Fills in the mode, owner and group the client left out of the
+    /// attributes of a new file
+    ///
+    /// # Arguments
+    ///
+    /// * `attr` - Attributes sent by the client
+    /// * `ftype` - Type of the file being created
+    /// * `auth` - Credentials of the caller
+    pub fn apply(&self, attr: &mut nfs3::sattr3, ftype: nfs3::ftype3, auth: &auth_unix) {
+        if attr.mode.is_none() {
+            attr.mode = self.mode(ftype);
+        }
+        let (uid, gid) = match self.owner {
+            DefaultOwner::Backend => return,
+            DefaultOwner::Caller => (auth.uid, auth.gid),
+            DefaultOwner::Fixed { uid, gid } => (uid, gid),
+        };
+        attr.uid = attr.uid.or(Some(uid));
+        attr.gid = attr.gid.or(Some(gid));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_in_only_what_the_client_left_out() {
+        let auth = auth_unix { uid: 1000, gid: 100, ..Default::default() };
+        let defaults = CreationDefaults::default();
+        let mut attr = nfs3::sattr3::default();
+        defaults.apply(&mut attr, nfs3::ftype3::NF3REG, &auth);
+        assert_eq!((attr.mode, attr.uid, attr.gid), (Some(0o644), None, None));
+        assert_eq!(defaults.mode(nfs3::ftype3::NF3DIR), Some(0o755));
+        assert_eq!(defaults.mode(nfs3::ftype3::NF3LNK), None);
+
+        let defaults = CreationDefaults { umask: 0o077, owner: DefaultOwner::Caller, ..defaults };
+        let mut attr = nfs3::sattr3 { mode: Some(0o640), gid: Some(5), ..Default::default() };
+        defaults.apply(&mut attr, nfs3::ftype3::NF3REG, &auth);
+        assert_eq!((attr.mode, attr.uid, attr.gid), (Some(0o640), Some(1000), Some(5)));
+
+        let defaults =
+            CreationDefaults { owner: DefaultOwner::Fixed { uid: 7, gid: 8 }, ..defaults };
+        let mut attr = nfs3::sattr3::default();
+        defaults.apply(&mut attr, nfs3::ftype3::NF3DIR, &auth);
+        assert_eq!((attr.mode, attr.uid, attr.gid), (Some(0o700), Some(7), Some(8)));
+    }
+}
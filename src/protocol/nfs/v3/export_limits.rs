@@ -0,0 +1,150 @@
+//! Limits on the data clients can store in an export.
+//!
+//! Exports handed to untrusted clients, such as scratch space of sandboxes,
+//! should not be able to grow without bounds even when the backend could. An
+//! [`ExportLimits`] caps, independently of the backend:
+//!
+//! - the size of files, refusing `WRITE` and `SETATTR` calls beyond it with
+//!   `NFS3ERR_FBIG`;
+//! - the number of entries per directory, refusing calls adding names to a
+//!   full directory with `NFS3ERR_NOSPC`;
+//! - the number of files of the whole export, refusing calls creating files
+//!   beyond it with `NFS3ERR_DQUOT`.
+//!
+//! The number of files is taken from `FSSTAT` of the backend, or from the
+//! [`crate::write_counter::SpaceCounter`] of the listener if the backend does
+//! not implement it. Without either, that limit is not enforced.
+
+use tracing::debug;
+
+use crate::protocol::xdr::nfs3;
+use crate::vfs::{readdir, NFSFileSystem, ReadDirCookie};
+use crate::write_counter::SpaceCounter;
+
+/// Maxima of an export, unlimited by default
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ExportLimits {
+    /// Largest size of a file
+    pub max_file_size: Option<u64>,
+    /// Largest number of entries of a directory
+    pub max_dir_entries: Option<u64>,
+    /// Largest number of files of the export
+    pub max_entries: Option<u64>,
+}
+
+impl ExportLimits {
+    /// Returns whether a call adding a directory entry is checked at all
+    pub fn limits_entries(&self) -> bool {
+        self.max_dir_entries.is_some() || self.max_entries.is_some()
+    }
+
+    /// Checks that a file may grow to `size` bytes
+    pub fn check_size(&self, size: u64) -> Result<(), nfs3::nfsstat3> {
+        match self.max_file_size {
+            Some(max) if size > max => {
+                debug!("file size {} exceeds the limit of {}", size, max);
+                Err(nfs3::nfsstat3::NFS3ERR_FBIG)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that a directory has room for another entry
+    ///
+    /// Lists at most as many entries as the limit allows.
+    pub async fn check_directory(
+        &self,
+        vfs: &(dyn NFSFileSystem + Send + Sync),
+        dirid: nfs3::fileid3,
+    ) -> Result<(), nfs3::nfsstat3> {
+        let Some(max) = self.max_dir_entries else {
+            return Ok(());
+        };
+        let max = usize::try_from(max).unwrap_or(usize::MAX);
+        let (mut cookie, mut listed) = (ReadDirCookie::Start, 0);
+        while listed < max {
+            let result = vfs.readdir_with_cookie(dirid, cookie, max - listed).await?;
+            let Some(last) = result.entries.last() else {
+                return Ok(());
+            };
+            listed += result.entries.len();
+            if result.end {
+                break;
+            }
+            cookie = readdir::resume_after(vfs, dirid, listed, last);
+        }
+        if listed >= max {
+            debug!("directory {} holds {} entries, the limit", dirid, listed);
+            return Err(nfs3::nfsstat3::NFS3ERR_NOSPC);
+        }
+        Ok(())
+    }
+
+    /// Checks that the export has room for another file
+    ///
+    /// # Arguments
+    ///
+    /// * `vfs` - File system of the export
+    /// * `space_counter` - Accounting of the listener, consulted if the file
+    ///   system does not implement `fsstat`
+    pub async fn check_total(
+        &self,
+        vfs: &(dyn NFSFileSystem + Send + Sync),
+        space_counter: Option<&SpaceCounter>,
+    ) -> Result<(), nfs3::nfsstat3> {
+        let Some(max) = self.max_entries else {
+            return Ok(());
+        };
+        let files = match vfs.fsstat(vfs.root_dir()).await {
+            Ok(stat) => stat.tfiles.saturating_sub(stat.ffiles),
+            Err(_) => match space_counter {
+                Some(counter) => counter.files(),
+                None => return Ok(()),
+            },
+        };
+        if files >= max {
+            debug!("export holds {} files, the limit", files);
+            return Err(nfs3::nfsstat3::NFS3ERR_DQUOT);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::testing::{Call, MockFs};
+    use crate::vfs::{DirEntry, ReadDirResult};
+
+    #[tokio::test]
+    async fn refuses_beyond_the_limits() {
+        let limits = ExportLimits {
+            max_file_size: Some(10),
+            max_dir_entries: Some(2),
+            max_entries: Some(5),
+        };
+        assert!(limits.check_size(10).is_ok());
+        assert!(matches!(limits.check_size(11), Err(nfs3::nfsstat3::NFS3ERR_FBIG)));
+
+        let fs = MockFs::new();
+        let entry = |fileid| DirEntry { fileid, ..Default::default() };
+        let listing = |entries, end| Ok::<_, nfs3::nfsstat3>(ReadDirResult { entries, end });
+        let start = Call::ReadDir { dirid: 1, cookie: ReadDirCookie::Start, max_entries: 2 };
+        fs.expect(start.clone(), listing(vec![entry(2)], true));
+        fs.expect(start, listing(vec![entry(2)], false));
+        let rest =
+            Call::ReadDir { dirid: 1, cookie: ReadDirCookie::AfterFileId(2), max_entries: 1 };
+        fs.expect(rest, listing(vec![entry(3)], true));
+        assert!(limits.check_directory(&fs, 1).await.is_ok());
+        assert!(matches!(limits.check_directory(&fs, 1).await, Err(nfs3::nfsstat3::NFS3ERR_NOSPC)));
+
+        // without FSSTAT, the files are counted by the listener if it can
+        assert!(limits.check_total(&fs, None).await.is_ok());
+        let counter = SpaceCounter::new(1 << 20, 100).with_usage(0, 5);
+        assert!(matches!(
+            limits.check_total(&fs, Some(&counter)).await,
+            Err(nfs3::nfsstat3::NFS3ERR_DQUOT)
+        ));
+        fs.assert_done();
+    }
+}
@@ -15,6 +15,8 @@
 //! - Server time precision (`time_delta`)
 //! - The file system properties (whether it supports hard links, symbolic links, etc.)
 //! - The maximum file size supported by the server
+//!
+//! Transfer sizes are capped for clients with [`rpc::ClientQuirks`].
 
 use std::io::{Read, Write};
 
@@ -38,17 +40,17 @@ use crate::protocol::xdr::{self, deserialize, nfs3, Serialize};
 ///
 /// # Returns
 ///
-/// * `Result<(), anyhow::Error>` - Ok(()) on success or an error
+/// * `Result<(), rpc::ServerError>` - Ok(()) on success or an error
 pub async fn nfsproc3_fsinfo(
     xid: u32,
     input: &mut impl Read,
     output: &mut impl Write,
     context: &rpc::Context,
-) -> Result<(), anyhow::Error> {
+) -> Result<(), rpc::ServerError> {
     let handle = deserialize::<nfs3::nfs_fh3>(input)?;
     debug!("nfsproc3_fsinfo({:?},{:?}) ", xid, handle);
 
-    let id = context.vfs.fh_to_id(&handle);
+    let id = context.fh_to_id(&handle);
     // fail if unable to convert file handle
     if let Err(stat) = id {
         xdr::rpc::make_success_reply(xid).serialize(output)?;
@@ -60,8 +62,17 @@ pub async fn nfsproc3_fsinfo(
     let id = id.unwrap();
 
     match context.vfs.fsinfo(id).await {
-        Ok(fsinfo) => {
+        Ok(mut fsinfo) => {
+            let quirks = context.quirks;
+            // transfers must fit into the longest record accepted
+            let max = context.max_transfer_size();
+            fsinfo.rtmax = quirks.transfer_size(fsinfo.rtmax).min(max);
+            fsinfo.rtpref = quirks.transfer_size(fsinfo.rtpref).min(max);
+            fsinfo.wtmax = quirks.transfer_size(fsinfo.wtmax).min(max);
+            fsinfo.wtpref = quirks.transfer_size(fsinfo.wtpref).min(max);
+            fsinfo.dtpref = quirks.dir_transfer_size(fsinfo.dtpref);
             debug!(" {:?} --> {:?}", xid, fsinfo);
+            context.readdirplus_limits.negotiated(fsinfo.dtpref);
             xdr::rpc::make_success_reply(xid).serialize(output)?;
             nfs3::nfsstat3::NFS3_OK.serialize(output)?;
             fsinfo.serialize(output)?;
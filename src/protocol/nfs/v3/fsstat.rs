@@ -18,10 +18,15 @@
 //! - Free file slots in the file system
 //! - Available file slots to the user (accounting for quotas)
 //! - How long this information remains valid (invarsec)
+//!
+//! The values come from [`crate::vfs::NFSFileSystem::fsstat`]. Backends that do
+//! not implement it get the totals of the listener's
+//! [`crate::write_counter::SpaceCounter`] if accounting is enabled, or fixed
+//! large values.
 
 use std::io::{Read, Write};
 
-use tracing::debug;
+use tracing::{debug, error};
 
 use crate::protocol::rpc;
 use crate::protocol::xdr::{self, deserialize, nfs3, Serialize};
@@ -41,16 +46,16 @@ use crate::protocol::xdr::{self, deserialize, nfs3, Serialize};
 ///
 /// # Returns
 ///
-/// * `Result<(), anyhow::Error>` - Ok(()) on success or an error
+/// * `Result<(), rpc::ServerError>` - Ok(()) on success or an error
 pub async fn nfsproc3_fsstat(
     xid: u32,
     input: &mut impl Read,
     output: &mut impl Write,
     context: &rpc::Context,
-) -> Result<(), anyhow::Error> {
+) -> Result<(), rpc::ServerError> {
     let handle = deserialize::<nfs3::nfs_fh3>(input)?;
     debug!("nfsproc3_fsstat({:?},{:?}) ", xid, handle);
-    let id = context.vfs.fh_to_id(&handle);
+    let id = context.fh_to_id(&handle);
     // fail if unable to convert file handle
     if let Err(stat) = id {
         xdr::rpc::make_success_reply(xid).serialize(output)?;
@@ -60,16 +65,32 @@ pub async fn nfsproc3_fsstat(
     }
     let id = id.unwrap();
 
-    let obj_attr = context.vfs.getattr(id).await.ok();
-    let res = nfs3::fs::FSSTAT3resok {
-        obj_attributes: obj_attr,
-        tbytes: 1024 * 1024 * 1024 * 1024,
-        fbytes: 1024 * 1024 * 1024 * 1024,
-        abytes: 1024 * 1024 * 1024 * 1024,
-        tfiles: 1024 * 1024 * 1024,
-        ffiles: 1024 * 1024 * 1024,
-        afiles: 1024 * 1024 * 1024,
-        invarsec: u32::MAX,
+    let res = match context.vfs.fsstat(id).await {
+        Ok(res) => res,
+        Err(nfs3::nfsstat3::NFS3ERR_NOTSUPP) => {
+            let obj_attr = context.vfs.getattr(id).await.ok();
+            match &context.space_counter {
+                Some(counter) => counter.fsstat(obj_attr),
+                None => nfs3::fs::FSSTAT3resok {
+                    obj_attributes: obj_attr,
+                    tbytes: 1024 * 1024 * 1024 * 1024,
+                    fbytes: 1024 * 1024 * 1024 * 1024,
+                    abytes: 1024 * 1024 * 1024 * 1024,
+                    tfiles: 1024 * 1024 * 1024,
+                    ffiles: 1024 * 1024 * 1024,
+                    afiles: 1024 * 1024 * 1024,
+                    invarsec: u32::MAX,
+                },
+            }
+        }
+        Err(stat) => {
+            error!("fsstat error {:?} --> {:?}", xid, stat);
+            let obj_attr = context.vfs.getattr(id).await.ok();
+            xdr::rpc::make_success_reply(xid).serialize(output)?;
+            stat.serialize(output)?;
+            obj_attr.serialize(output)?;
+            return Ok(());
+        }
     };
     xdr::rpc::make_success_reply(xid).serialize(output)?;
     nfs3::nfsstat3::NFS3_OK.serialize(output)?;
@@ -31,17 +31,17 @@ use crate::protocol::xdr::{self, deserialize, nfs3, Serialize};
 ///
 /// # Returns
 ///
-/// * `Result<(), anyhow::Error>` - Ok(()) on success or an error
+/// * `Result<(), rpc::ServerError>` - Ok(()) on success or an error
 pub async fn nfsproc3_getattr(
     xid: u32,
     input: &mut impl Read,
     output: &mut impl Write,
     context: &rpc::Context,
-) -> Result<(), anyhow::Error> {
+) -> Result<(), rpc::ServerError> {
     let handle = deserialize::<nfs3::nfs_fh3>(input)?;
     debug!("nfsproc3_getattr({:?},{:?}) ", xid, handle);
 
-    let id = context.vfs.fh_to_id(&handle);
+    let id = context.fh_to_id(&handle);
     // fail if unable to convert file handle
     if let Err(stat) = id {
         xdr::rpc::make_success_reply(xid).serialize(output)?;
@@ -49,6 +49,7 @@ pub async fn nfsproc3_getattr(
         return Ok(());
     }
     let id = id.unwrap();
+    super::flush_coalesced(context, id).await;
     match context.vfs.getattr(id).await {
         Ok(fh) => {
             debug!(" {:?} --> {:?}", xid, fh);
@@ -64,3 +65,58 @@ pub async fn nfsproc3_getattr(
     }
     Ok(())
 }
+
+/// Size of the largest `GETATTR` reply: accepted RPC reply header, status and `fattr3`
+const GETATTR_REPLY_SIZE: usize = 24 + 4 + 84;
+
+/// Handles `NFSv3` `GETATTR` on the RPC fast path
+///
+/// Behaves like [`nfsproc3_getattr`] but takes the raw file handle bytes that the
+/// caller located in the request and encodes the reply into a stack buffer that is
+/// appended to `output` in one copy, avoiding the generic reader and writer layers.
+///
+/// # Arguments
+///
+/// * `xid` - RPC transaction ID
+/// * `handle` - Raw bytes of the `nfs_fh3` argument
+/// * `output` - Buffer the reply is appended to
+/// * `context` - Server context containing VFS
+///
+/// # Returns
+///
+/// * `Result<(), rpc::ServerError>` - Ok(()) on success or an error
+pub async fn nfsproc3_getattr_fast(
+    xid: u32,
+    handle: &[u8],
+    output: &mut Vec<u8>,
+    context: &rpc::Context,
+) -> Result<(), rpc::ServerError> {
+    let handle = nfs3::nfs_fh3 { data: handle.to_vec() };
+    debug!("nfsproc3_getattr_fast({:?},{:?}) ", xid, handle);
+
+    let result = match context.fh_to_id(&handle) {
+        Ok(id) => {
+            super::flush_coalesced(context, id).await;
+            context.vfs.getattr(id).await
+        }
+        Err(stat) => Err(stat),
+    };
+
+    let mut buf = [0_u8; GETATTR_REPLY_SIZE];
+    let mut dest = &mut buf[..];
+    xdr::rpc::make_success_reply(xid).serialize(&mut dest)?;
+    match result {
+        Ok(attr) => {
+            debug!(" {:?} --> {:?}", xid, attr);
+            nfs3::nfsstat3::NFS3_OK.serialize(&mut dest)?;
+            attr.serialize(&mut dest)?;
+        }
+        Err(stat) => {
+            error!("nfsproc3_getattr error {:?} --> {:?}", xid, stat);
+            stat.serialize(&mut dest)?;
+        }
+    }
+    let len = GETATTR_REPLY_SIZE - dest.len();
+    output.extend_from_slice(&buf[..len]);
+    Ok(())
+}
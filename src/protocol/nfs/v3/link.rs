@@ -20,6 +20,7 @@ use std::io::{Read, Write};
 
 use tracing::{debug, warn};
 
+use crate::protocol::nfs::events::EventKind;
 use crate::protocol::rpc;
 use crate::protocol::xdr::{self, deserialize, nfs3, Serialize};
 use crate::vfs;
@@ -39,15 +40,15 @@ use crate::vfs;
 ///
 /// # Returns
 ///
-/// * `Result<(), anyhow::Error>` - Ok(()) on success or an error
+/// * `Result<(), rpc::ServerError>` - Ok(()) on success or an error
 pub async fn nfsproc3_link(
     xid: u32,
     input: &mut impl Read,
     output: &mut impl Write,
     context: &rpc::Context,
-) -> Result<(), anyhow::Error> {
+) -> Result<(), rpc::ServerError> {
     // if we do not have write capabilities
-    if !matches!(context.vfs.capabilities(), vfs::Capabilities::ReadWrite) {
+    if !context.vfs.capabilities().contains(vfs::Capabilities::WRITE) {
         warn!("No write capabilities.");
         xdr::rpc::make_success_reply(xid).serialize(output)?;
         nfs3::nfsstat3::NFS3ERR_ROFS.serialize(output)?;
@@ -55,12 +56,20 @@ pub async fn nfsproc3_link(
         nfs3::wcc_data::default().serialize(output)?;
         return Ok(());
     }
+    if !context.vfs.capabilities().contains(vfs::Capabilities::HARDLINKS) {
+        warn!("No hard link capability.");
+        xdr::rpc::make_success_reply(xid).serialize(output)?;
+        nfs3::nfsstat3::NFS3ERR_NOTSUPP.serialize(output)?;
+        nfs3::post_op_attr::None.serialize(output)?;
+        nfs3::wcc_data::default().serialize(output)?;
+        return Ok(());
+    }
 
     let args = deserialize::<nfs3::file::LINK3args>(input)?;
     debug!("nfsproc3_link({:?}, {:?}) ", xid, args);
 
     // Get the file id
-    let fileid = context.vfs.fh_to_id(&args.file);
+    let fileid = context.fh_to_id(&args.file);
     if let Err(stat) = fileid {
         xdr::rpc::make_success_reply(xid).serialize(output)?;
         stat.serialize(output)?;
@@ -71,7 +80,7 @@ pub async fn nfsproc3_link(
     let fileid = fileid.unwrap();
 
     // Get the directory id
-    let dirid = context.vfs.fh_to_id(&args.link.dir);
+    let dirid = context.fh_to_id(&args.link.dir);
     if let Err(stat) = dirid {
         xdr::rpc::make_success_reply(xid).serialize(output)?;
         stat.serialize(output)?;
@@ -81,6 +90,9 @@ pub async fn nfsproc3_link(
     }
     let dirid = dirid.unwrap();
 
+    // changes of the directory run one at a time if configured
+    let _dir_guard = super::lock_dirs(context, dirid, None).await;
+
     // Get the directory attributes before the operation
     let pre_dir_attr = context
         .vfs
@@ -89,9 +101,19 @@ pub async fn nfsproc3_link(
         .map(|v| nfs3::wcc_attr { size: v.size, mtime: v.mtime, ctime: v.ctime })
         .ok();
 
-    // Call VFS link method
-    match context.vfs.link(fileid, dirid, &args.link.name).await {
+    // Call VFS link method, within the limits of the export
+    let res = match super::check_new_entry(context, dirid, &args.link.name, false).await {
+        Ok(()) => {
+            super::flush_coalesced(context, fileid).await;
+            context.vfs.link(fileid, dirid, &args.link.name).await
+        }
+        Err(stat) => Err(stat),
+    };
+    super::name_added(context, dirid, &args.link.name);
+    match res {
         Ok(fattr) => {
+            let name = args.link.name.0.clone();
+            context.publish(EventKind::Created { dirid, name, id: fileid });
             // Get file attributes
             let file_attr = nfs3::post_op_attr::Some(fattr);
 
@@ -11,6 +11,10 @@
 //! - The file handle of the requested file
 //! - The attributes of the requested file
 //! - The attributes of the directory (for cache validation)
+//!
+//! When the public file handle of WebNFS (RFC 2054) is enabled and used as the
+//! directory, the name may be a path of several components separated by `/`,
+//! resolved from the root of the export (RFC 2055 section 5).
 
 use std::io::{Read, Write};
 
@@ -34,17 +38,17 @@ use crate::protocol::xdr::{self, deserialize, nfs3, Serialize};
 ///
 /// # Returns
 ///
-/// * `Result<(), anyhow::Error>` - Ok(()) on success or an error
+/// * `Result<(), rpc::ServerError>` - Ok(()) on success or an error
 pub async fn nfsproc3_lookup(
     xid: u32,
     input: &mut impl Read,
     output: &mut impl Write,
     context: &rpc::Context,
-) -> Result<(), anyhow::Error> {
+) -> Result<(), rpc::ServerError> {
     let dirops = deserialize::<nfs3::diropargs3>(input)?;
     debug!("nfsproc3_lookup({:?},{:?}) ", xid, dirops);
 
-    let dirid = context.vfs.fh_to_id(&dirops.dir);
+    let dirid = context.fh_to_id(&dirops.dir);
 
     // fail if unable to convert file handle
     if let Err(stat) = dirid {
@@ -55,22 +59,103 @@ pub async fn nfsproc3_lookup(
     }
 
     let dirid = dirid.unwrap();
+    if !context.is_public_fh(&dirops.dir) {
+        return lookup_in(xid, dirid, Some(&dirops.name), output, context).await;
+    }
+    match walk_public_path(context, dirid, &dirops.name).await {
+        Ok((dirid, name)) => lookup_in(xid, dirid, name.as_ref(), output, context).await,
+        Err((dirid, stat)) => {
+            debug!("nfsproc3_lookup error {:?}({:?}) --> {:?}", xid, dirops.name, stat);
+            xdr::rpc::make_success_reply(xid).serialize(output)?;
+            stat.serialize(output)?;
+            context.vfs.getattr(dirid).await.ok().serialize(output)?;
+            Ok(())
+        }
+    }
+}
 
+/// Resolves all but the last component of a path looked up in the public
+/// directory `dirid`
+///
+/// # Returns
+///
+/// * `Result<(fileid3, Option<filename3>), (fileid3, nfsstat3)>` - The
+///   directory and name to look up, no name for a path naming the public
+///   directory itself, or the directory a component was not found in and the
+///   error
+async fn walk_public_path(
+    context: &rpc::Context,
+    mut dirid: nfs3::fileid3,
+    path: &[u8],
+) -> Result<(nfs3::fileid3, Option<nfs3::filename3>), (nfs3::fileid3, nfs3::nfsstat3)> {
+    let components: Vec<&[u8]> =
+        path.split(|byte| *byte == b'/').filter(|c| !c.is_empty()).collect();
+    let Some((last, parents)) = components.split_last() else {
+        return Ok((dirid, None));
+    };
+    for component in parents {
+        dirid = lookup_component(context, dirid, &(*component).into())
+            .await
+            .map_err(|stat| (dirid, stat))?;
+    }
+    Ok((dirid, Some((*last).into())))
+}
+
+/// Looks up a single name, hiding the entries the server hides and answering
+/// from the cache of missing names if enabled
+async fn lookup_component(
+    context: &rpc::Context,
+    dirid: nfs3::fileid3,
+    name: &nfs3::filename3,
+) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+    if super::is_hidden(context, name) {
+        return Err(nfs3::nfsstat3::NFS3ERR_NOENT);
+    }
+    let Some(cache) = &context.negative_lookups else {
+        return context.vfs.lookup(dirid, name).await;
+    };
+    if cache.is_missing(dirid, name, context.clock.now()) {
+        context.stats.negative_lookups.hit();
+        return Err(nfs3::nfsstat3::NFS3ERR_NOENT);
+    }
+    context.stats.negative_lookups.miss();
+    let epoch = cache.epoch();
+    let res = context.vfs.lookup(dirid, name).await;
+    if matches!(res, Err(nfs3::nfsstat3::NFS3ERR_NOENT)) {
+        cache.record_missing(dirid, name, epoch, context.clock.now());
+    }
+    res
+}
+
+/// Looks up `name` in directory `dirid`, or the directory itself without a
+/// name, and writes the reply
+async fn lookup_in(
+    xid: u32,
+    dirid: nfs3::fileid3,
+    name: Option<&nfs3::filename3>,
+    output: &mut impl Write,
+    context: &rpc::Context,
+) -> Result<(), rpc::ServerError> {
     let dir_attr = context.vfs.getattr(dirid).await.ok();
 
-    match context.vfs.lookup(dirid, &dirops.name).await {
-        Ok(fid) => {
+    let found = match name {
+        Some(name) => lookup_component(context, dirid, name).await,
+        None => Ok(dirid),
+    };
+    match found.and_then(|fid| Ok((fid, context.id_to_fh(fid)?))) {
+        Ok((fid, fh)) => {
+            super::flush_coalesced(context, fid).await;
             let obj_attr = context.vfs.getattr(fid).await.ok();
 
             debug!("nfsproc3_lookup success {:?} --> {:?}", xid, obj_attr);
             xdr::rpc::make_success_reply(xid).serialize(output)?;
             nfs3::nfsstat3::NFS3_OK.serialize(output)?;
-            context.vfs.id_to_fh(fid).serialize(output)?;
+            fh.serialize(output)?;
             obj_attr.serialize(output)?;
             dir_attr.serialize(output)?;
         }
         Err(stat) => {
-            debug!("nfsproc3_lookup error {:?}({:?}) --> {:?}", xid, dirops.name, stat);
+            debug!("nfsproc3_lookup error {:?}({:?}) --> {:?}", xid, name, stat);
             xdr::rpc::make_success_reply(xid).serialize(output)?;
             stat.serialize(output)?;
             dir_attr.serialize(output)?;
@@ -19,6 +19,7 @@ use std::io::{Read, Write};
 
 use tracing::{debug, error, warn};
 
+use crate::protocol::nfs::events::EventKind;
 use crate::protocol::rpc;
 use crate::protocol::xdr::{self, deserialize, nfs3, Serialize};
 use crate::vfs;
@@ -38,15 +39,15 @@ use crate::vfs;
 ///
 /// # Returns
 ///
-/// * `Result<(), anyhow::Error>` - Ok(()) on success or an error
+/// * `Result<(), rpc::ServerError>` - Ok(()) on success or an error
 pub async fn nfsproc3_mkdir(
     xid: u32,
     input: &mut impl Read,
     output: &mut impl Write,
     context: &rpc::Context,
-) -> Result<(), anyhow::Error> {
+) -> Result<(), rpc::ServerError> {
     // if we do not have write capabilities
-    if !matches!(context.vfs.capabilities(), vfs::Capabilities::ReadWrite) {
+    if !context.vfs.capabilities().contains(vfs::Capabilities::WRITE) {
         warn!("No write capabilities.");
         xdr::rpc::make_success_reply(xid).serialize(output)?;
         nfs3::nfsstat3::NFS3ERR_ROFS.serialize(output)?;
@@ -59,7 +60,7 @@ pub async fn nfsproc3_mkdir(
 
     // find the directory we are supposed to create the
     // new file in
-    let dirid = context.vfs.fh_to_id(&args.dirops.dir);
+    let dirid = context.fh_to_id(&args.dirops.dir);
     if let Err(stat) = dirid {
         // directory does not exist
         xdr::rpc::make_success_reply(xid).serialize(output)?;
@@ -71,6 +72,9 @@ pub async fn nfsproc3_mkdir(
     // found the directory, get the attributes
     let dirid = dirid.unwrap();
 
+    // changes of the directory run one at a time if configured
+    let _dir_guard = super::lock_dirs(context, dirid, None).await;
+
     // get the object attributes before the write
     let pre_dir_attr = match context.vfs.getattr(dirid).await {
         Ok(v) => {
@@ -86,7 +90,20 @@ pub async fn nfsproc3_mkdir(
         }
     };
 
-    let res = context.vfs.mkdir(dirid, &args.dirops.name).await;
+    let res = match super::check_new_entry(context, dirid, &args.dirops.name, true).await {
+        Ok(()) => context.vfs.mkdir(dirid, &args.dirops.name).await,
+        Err(stat) => Err(stat),
+    };
+    // the VFS takes no attributes, so the defaults are applied afterwards
+    let res = match res {
+        Ok((fid, fattr)) => {
+            let attr = nfs3::sattr3 { size: None, ..args.attributes };
+            let defaults = super::set_creation_defaults(context, fid, attr, nfs3::ftype3::NF3DIR);
+            Ok((fid, defaults.await.unwrap_or(fattr)))
+        }
+        Err(stat) => Err(stat),
+    };
+    super::name_added(context, dirid, &args.dirops.name);
 
     // Re-read dir attributes for post op attr
     let post_dir_attr = context.vfs.getattr(dirid).await.ok();
@@ -95,11 +112,15 @@ pub async fn nfsproc3_mkdir(
     match res {
         Ok((fid, fattr)) => {
             debug!("mkdir success --> {:?}, {:?}", fid, fattr);
+            super::account_created(context);
+            let name = args.dirops.name.0.clone();
+            context.publish(EventKind::Created { dirid, name, id: fid });
             xdr::rpc::make_success_reply(xid).serialize(output)?;
             nfs3::nfsstat3::NFS3_OK.serialize(output)?;
             // serialize CREATE3resok
-            let fh = context.vfs.id_to_fh(fid);
-            nfs3::post_op_fh3::Some(fh).serialize(output)?;
+            // the client looks the name up if the handle cannot be issued
+            let fh: nfs3::post_op_fh3 = context.id_to_fh(fid).ok();
+            fh.serialize(output)?;
             nfs3::post_op_attr::Some(fattr).serialize(output)?;
             wcc_res.serialize(output)?;
         }
@@ -23,6 +23,7 @@ use std::io::{Read, Write};
 
 use tracing::{debug, error, warn};
 
+use crate::protocol::nfs::events::EventKind;
 use crate::protocol::rpc;
 use crate::protocol::xdr::{self, deserialize, nfs3, Serialize};
 use crate::vfs;
@@ -42,27 +43,34 @@ use crate::vfs;
 ///
 /// # Returns
 ///
-/// * `Result<(), anyhow::Error>` - Ok(()) on success or an error
+/// * `Result<(), rpc::ServerError>` - Ok(()) on success or an error
 pub async fn nfsproc3_mknod(
     xid: u32,
     input: &mut impl Read,
     output: &mut impl Write,
     context: &rpc::Context,
-) -> Result<(), anyhow::Error> {
+) -> Result<(), rpc::ServerError> {
     // if we do not have write capabilities
-    if !matches!(context.vfs.capabilities(), vfs::Capabilities::ReadWrite) {
+    if !context.vfs.capabilities().contains(vfs::Capabilities::WRITE) {
         warn!("No write capabilities.");
         xdr::rpc::make_success_reply(xid).serialize(output)?;
         nfs3::nfsstat3::NFS3ERR_ROFS.serialize(output)?;
         nfs3::wcc_data::default().serialize(output)?;
         return Ok(());
     }
+    if !context.vfs.capabilities().contains(vfs::Capabilities::DEVICES) {
+        warn!("No device capability.");
+        xdr::rpc::make_success_reply(xid).serialize(output)?;
+        nfs3::nfsstat3::NFS3ERR_NOTSUPP.serialize(output)?;
+        nfs3::wcc_data::default().serialize(output)?;
+        return Ok(());
+    }
 
     let args = deserialize::<nfs3::dir::MKNOD3args>(input)?;
     debug!("nfsproc3_mknod({:?}, {:?}) ", xid, args);
 
     // find the directory we are supposed to create the special file in
-    let dirid = context.vfs.fh_to_id(&args.where_dir.dir);
+    let dirid = context.fh_to_id(&args.where_dir.dir);
     if let Err(stat) = dirid {
         // directory does not exist
         xdr::rpc::make_success_reply(xid).serialize(output)?;
@@ -74,6 +82,9 @@ pub async fn nfsproc3_mknod(
     // found the directory, get the attributes
     let dirid = dirid.unwrap();
 
+    // changes of the directory run one at a time if configured
+    let _dir_guard = super::lock_dirs(context, dirid, None).await;
+
     // get the object attributes before the operation
     let pre_dir_attr = context
         .vfs
@@ -83,16 +94,24 @@ pub async fn nfsproc3_mknod(
         .ok();
 
     // Create default attributes if necessary
-    let attr = nfs3::sattr3::default();
-
-    // Call VFS mknod method
-    match context
-        .vfs
-        .mknod(dirid, &args.where_dir.name, args.what.mknod_type, args.what.device.device, &attr)
-        .await
-    {
+    let mut attr = nfs3::sattr3::default();
+    super::apply_creation_defaults(context, &mut attr, args.what.mknod_type);
+
+    // Call VFS mknod method, within the limits of the export
+    let res = match super::check_new_entry(context, dirid, &args.where_dir.name, true).await {
+        Ok(()) => {
+            let (name, what) = (&args.where_dir.name, &args.what);
+            context.vfs.mknod(dirid, name, what.mknod_type, what.device.device, &attr).await
+        }
+        Err(stat) => Err(stat),
+    };
+    super::name_added(context, dirid, &args.where_dir.name);
+    match res {
         Ok((fid, fattr)) => {
             debug!("nfsproc3_mknod success --> {:?}, {:?}", fid, fattr);
+            super::account_created(context);
+            let name = args.where_dir.name.0.clone();
+            context.publish(EventKind::Created { dirid, name, id: fid });
 
             // Get the directory attributes after the operation
             let post_dir_attr = context.vfs.getattr(dirid).await.ok();
@@ -102,8 +121,9 @@ pub async fn nfsproc3_mknod(
             xdr::rpc::make_success_reply(xid).serialize(output)?;
             nfs3::nfsstat3::NFS3_OK.serialize(output)?;
             // serialize MKNOD3resok
-            let fh = context.vfs.id_to_fh(fid);
-            nfs3::post_op_fh3::Some(fh).serialize(output)?;
+            // the client looks the name up if the handle cannot be issued
+            let fh: nfs3::post_op_fh3 = context.id_to_fh(fid).ok();
+            fh.serialize(output)?;
             nfs3::post_op_attr::Some(fattr).serialize(output)?;
             wcc_res.serialize(output)?;
         }
@@ -42,12 +42,19 @@ use std::io::{Read, Write};
 use num_traits::cast::FromPrimitive;
 use tracing::warn;
 
+use crate::clock::RequestedTimes;
 use crate::protocol::rpc;
 use crate::protocol::xdr::{self, nfs3, Serialize};
+use crate::vfs;
 
 mod access;
+mod checksums;
 mod commit;
+mod content_inspection;
 mod create;
+mod creation_defaults;
+mod dir_locks;
+mod export_limits;
 mod fsinfo;
 mod fsstat;
 mod getattr;
@@ -55,17 +62,25 @@ mod link;
 mod lookup;
 mod mkdir;
 mod mknod;
+mod name_filter;
+mod negative_cache;
 mod null;
 mod pathconf;
 mod read;
+mod read_ahead;
+mod read_cache;
 mod readdir;
+mod readdir_limits;
 mod readdirplus;
 mod readlink;
 mod remove;
 mod rename;
 mod setattr;
+mod soft_delete;
 mod symlink;
+mod unstable_writes;
 mod write;
+mod write_coalescer;
 
 use access::nfsproc3_access;
 use commit::nfsproc3_commit;
@@ -89,6 +104,248 @@ use setattr::nfsproc3_setattr;
 use symlink::nfsproc3_symlink;
 use write::nfsproc3_write;
 
+pub use checksums::{crc32c, ChecksumConfig, Checksums};
+pub use content_inspection::{ContentInspection, ContentInspector, Verdict};
+pub use creation_defaults::{CreationDefaults, DefaultOwner};
+pub use dir_locks::{DirGuard, DirLocks};
+pub use export_limits::ExportLimits;
+pub(crate) use getattr::nfsproc3_getattr_fast;
+pub use name_filter::NameFilter;
+pub use negative_cache::{NegativeLookupCache, NegativeLookupConfig};
+pub use read_ahead::{ReadAheadConfig, ReadAheadDetector};
+pub use read_cache::{ReadCache, ReadCacheConfig};
+pub use readdir_limits::ReadDirPlusLimits;
+pub use soft_delete::{SoftDelete, SoftDeleteConfig};
+pub use unstable_writes::UnstableWrites;
+pub use write_coalescer::{WriteCoalescer, WriteCoalescerConfig};
+
+/// Locks the directories a namespace change touches if the context serializes
+/// such changes, see [`DirLocks`]
+async fn lock_dirs(
+    context: &rpc::Context,
+    dirid: nfs3::fileid3,
+    other: Option<nfs3::fileid3>,
+) -> Option<DirGuard<'_>> {
+    match &context.dir_locks {
+        Some(locks) => Some(locks.lock(dirid, other).await),
+        None => None,
+    }
+}
+
+/// Replaces `SET_TO_SERVER_TIME` in attributes to set with the time of the
+/// context's clock, so all backends and connections agree on the server time
+fn resolve_server_time(context: &rpc::Context, attr: &mut nfs3::sattr3) {
+    RequestedTimes::resolve_in_place(attr, context.clock.as_ref());
+}
+
+/// Fills in the mode and owner of a new file of type `ftype` the client left
+/// out, if the context has [`CreationDefaults`]
+fn apply_creation_defaults(context: &rpc::Context, attr: &mut nfs3::sattr3, ftype: nfs3::ftype3) {
+    if let Some(defaults) = &context.creation_defaults {
+        defaults.apply(attr, ftype, &context.auth);
+    }
+}
+
+/// Applies the [`CreationDefaults`] of the context with a `SETATTR` to a file
+/// the VFS created without attributes
+///
+/// # Returns
+///
+/// * `Option<nfs3::fattr3>` - The attributes of the file afterwards, `None`
+///   without defaults or if they could not be applied
+async fn set_creation_defaults(
+    context: &rpc::Context,
+    id: nfs3::fileid3,
+    mut attr: nfs3::sattr3,
+    ftype: nfs3::ftype3,
+) -> Option<nfs3::fattr3> {
+    let defaults = context.creation_defaults.as_ref()?;
+    defaults.apply(&mut attr, ftype, &context.auth);
+    resolve_server_time(context, &mut attr);
+    match context.vfs.setattr(id, attr).await {
+        Ok(fattr) => Some(fattr),
+        Err(stat) => {
+            warn!("cannot apply creation defaults to {}: {:?}", id, stat);
+            None
+        }
+    }
+}
+
+/// Returns whether the name filter of the context hides an entry, or the entry
+/// is a file hidden instead of removed
+fn is_hidden(context: &rpc::Context, name: &[u8]) -> bool {
+    context.name_filter.as_ref().is_some_and(|filter| filter.is_hidden(name))
+        || (context.soft_delete.is_some() && SoftDelete::is_hidden_name(name))
+}
+
+/// Returns the cookie sent to clients for an entry of a listing resumed from `cookie`
+fn entry_cookie(
+    context: &rpc::Context,
+    dirid: nfs3::fileid3,
+    cookie: vfs::ReadDirCookie,
+    position: usize,
+    fileid: nfs3::fileid3,
+    name: &nfs3::filename3,
+) -> nfs3::cookie3 {
+    match context.vfs.readdir_cookie_kind() {
+        vfs::ReadDirCookieKind::Opaque => context.vfs.readdir_entry_cookie(dirid, fileid, name),
+        _ => cookie.entry_cookie(position, fileid),
+    }
+}
+
+/// Returns where to continue a listing resumed from `cookie` if its batch of
+/// `entries`, given as file id and name, holds hidden entries only
+///
+/// A reply without visible entries that is not the end of the directory leaves
+/// the client no cookie to resume from, so such a batch is followed by the next
+/// one instead. Returns `None` if an entry is visible or the batch is empty.
+fn resume_after_hidden<'a>(
+    context: &rpc::Context,
+    dirid: nfs3::fileid3,
+    cookie: vfs::ReadDirCookie,
+    entries: impl Iterator<Item = (nfs3::fileid3, &'a nfs3::filename3)>,
+) -> Option<vfs::ReadDirCookie> {
+    let mut last = None;
+    for (position, (fileid, name)) in entries.enumerate() {
+        if !is_hidden(context, name) {
+            return None;
+        }
+        last = Some((position, fileid, name));
+    }
+    let (position, fileid, name) = last?;
+    let next = entry_cookie(context, dirid, cookie, position, fileid, name);
+    Some(vfs::ReadDirCookie::from_cookie3(next, context.vfs.readdir_cookie_kind()))
+}
+
+/// Records a change of a file's size in the space accounting of the context,
+/// if it has one and both sizes are known
+fn account_resize(context: &rpc::Context, before: &nfs3::pre_op_attr, after: &nfs3::post_op_attr) {
+    if let (Some(counter), Some(before), Some(after)) = (&context.space_counter, before, after) {
+        counter.resize(before.size, after.size);
+    }
+}
+
+/// Records a new file in the space accounting of the context, if it has one
+fn account_created(context: &rpc::Context) {
+    if let Some(counter) = &context.space_counter {
+        counter.add_file();
+    }
+}
+
+/// Records I/O on a file for the deferred removal of the context, if enabled
+fn record_io(context: &rpc::Context, id: nfs3::fileid3) {
+    if let Some(soft_delete) = &context.soft_delete {
+        soft_delete.record_io(id, context.clock.now());
+    }
+}
+
+/// Drops the cached miss of a name just added to a directory, if the context
+/// caches misses
+fn name_added(context: &rpc::Context, dirid: nfs3::fileid3, name: &[u8]) {
+    if let Some(cache) = &context.negative_lookups {
+        cache.invalidate(dirid, name);
+    }
+}
+
+/// Drops the cached reads of a file being modified, if the context caches
+/// reads
+fn data_modified(context: &rpc::Context, id: nfs3::fileid3) {
+    if let Some(cache) = &context.read_cache {
+        cache.invalidate(id);
+    }
+}
+
+/// Checks the limits of the export before `name` is added to a directory
+///
+/// Replacing an existing name adds no entry and is not limited. `counts_total`
+/// tells whether the call creates a file rather than another name of one.
+async fn check_new_entry(
+    context: &rpc::Context,
+    dirid: nfs3::fileid3,
+    name: &nfs3::filename3,
+    counts_total: bool,
+) -> Result<(), nfs3::nfsstat3> {
+    let limits = &context.export_limits;
+    if !limits.limits_entries() || context.vfs.lookup(dirid, name).await.is_ok() {
+        return Ok(());
+    }
+    limits.check_directory(context.vfs.as_ref(), dirid).await?;
+    if counts_total {
+        limits.check_total(context.vfs.as_ref(), context.space_counter.as_deref()).await?;
+    }
+    Ok(())
+}
+
+/// Writes out coalesced `UNSTABLE` data of `id` before its data or attributes
+/// are read from the VFS
+///
+/// Failures are left for the next `COMMIT` of the file to report.
+async fn flush_coalesced(context: &rpc::Context, id: nfs3::fileid3) {
+    if let Some(coalescer) = &context.write_coalescer {
+        let _ = coalescer.flush(context.vfs.as_ref(), id).await;
+    }
+}
+
+/// Writes out coalesced `UNSTABLE` data of the file named `name` in `dirid`
+/// before the name is removed or replaced
+///
+/// Once the name is gone the backend may free the file ID, or hand it to
+/// another file, so the data could not be written out later.
+async fn flush_coalesced_name(
+    context: &rpc::Context,
+    dirid: nfs3::fileid3,
+    name: &nfs3::filename3,
+) {
+    if context.write_coalescer.is_some() {
+        if let Ok(id) = context.vfs.lookup(dirid, name).await {
+            flush_coalesced(context, id).await;
+        }
+    }
+}
+
+/// Writes a failed reply to an `NFSv3` call without executing it
+///
+/// The failure body of every procedure carries optional attributes only, which
+/// are all left out.
+///
+/// # Arguments
+///
+/// * `xid` - Transaction ID from the RPC call
+/// * `proc` - Procedure number of the call
+/// * `stat` - Error status to reply with
+/// * `output` - Output stream for writing the reply
+pub(crate) fn serialize_failure(
+    xid: u32,
+    proc: u32,
+    stat: nfs3::nfsstat3,
+    output: &mut impl Write,
+) -> Result<(), rpc::ServerError> {
+    use nfs3::NFSProgram::*;
+
+    let prog = nfs3::NFSProgram::from_u32(proc).unwrap_or(nfs3::NFSProgram::INVALID);
+    // number of absent post_op_attr and pre_op_attr values in the failure body
+    let absent = match prog {
+        NFSPROC3_NULL | INVALID => {
+            xdr::rpc::proc_unavail_reply_message(xid).serialize(output)?;
+            return Ok(());
+        }
+        NFSPROC3_GETATTR => 0,
+        NFSPROC3_LOOKUP | NFSPROC3_ACCESS | NFSPROC3_READLINK | NFSPROC3_READ
+        | NFSPROC3_READDIR | NFSPROC3_READDIRPLUS | NFSPROC3_FSSTAT | NFSPROC3_FSINFO
+        | NFSPROC3_PATHCONF => 1,
+        NFSPROC3_SETATTR | NFSPROC3_WRITE | NFSPROC3_CREATE | NFSPROC3_MKDIR | NFSPROC3_SYMLINK
+        | NFSPROC3_MKNOD | NFSPROC3_REMOVE | NFSPROC3_RMDIR | NFSPROC3_COMMIT => 2,
+        NFSPROC3_LINK => 3,
+        NFSPROC3_RENAME => 4,
+    };
+    xdr::rpc::make_success_reply(xid).serialize(output)?;
+    stat.serialize(output)?;
+    for _ in 0..absent {
+        false.serialize(output)?;
+    }
+    Ok(())
+}
+
 /// Main handler for `NFSv3` protocol
 ///
 /// Dispatches `NFSv3` RPC calls to appropriate procedure handlers based on procedure number.
@@ -105,14 +362,14 @@ use write::nfsproc3_write;
 ///
 /// # Returns
 ///
-/// * `Result<(), anyhow::Error>` - Ok(()) on success or an error
+/// * `Result<(), rpc::ServerError>` - Ok(()) on success or an error
 pub async fn handle_nfs(
     xid: u32,
     call: xdr::rpc::call_body,
     input: &mut impl Read,
     output: &mut impl Write,
     context: &rpc::Context,
-) -> Result<(), anyhow::Error> {
+) -> Result<(), rpc::ServerError> {
     if call.vers != nfs3::VERSION {
         warn!("Invalid NFS Version number {} != {}", call.vers, nfs3::VERSION);
         xdr::rpc::prog_mismatch_reply_message(xid, nfs3::VERSION).serialize(output)?;
@@ -0,0 +1,118 @@
+//! Hiding of directory entries by name.
+//!
+//! A [`NameFilter`] holds glob patterns of names clients must not see, such as
+//! dotfiles, `lost+found` or editor temp files. Hidden entries are left out of
+//! `READDIR` and `READDIRPLUS` replies and `LOOKUP` reports them as missing, no
+//! matter what the backend returns. Handles already held by a client stay valid,
+//! and the backend still sees the real names for all other procedures.
+//!
+//! Patterns match whole names byte by byte. `*` matches any run of bytes, `?`
+//! any single byte, and `\` makes the next byte literal. `.` and `..` are never
+//! hidden.
+
+/// Glob patterns of hidden entry names
+#[derive(Clone, Debug, Default)]
+pub struct NameFilter {
+    /// Patterns a name is hidden by
+    patterns: Vec<Vec<u8>>,
+}
+
+impl NameFilter {
+    /// Creates a filter that hides nothing
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hides names matching a glob pattern
+    pub fn hide(&mut self, pattern: &str) -> &mut Self {
+        self.patterns.push(pattern.as_bytes().to_vec());
+        self
+    }
+
+    /// Hides names starting with a dot
+    pub fn hide_dotfiles(&mut self) -> &mut Self {
+        self.hide(".*")
+    }
+
+    /// Returns whether an entry is hidden from clients
+    pub fn is_hidden(&self, name: &[u8]) -> bool {
+        if name == b"." || name == b".." {
+            return false;
+        }
+        self.patterns.iter().any(|pattern| glob_match(pattern, name))
+    }
+}
+
+/// Matches `name` against a glob pattern
+fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // position after the last `*` and the name position it currently covers up to
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                p += 1;
+                backtrack = Some((p, n));
+                continue;
+            }
+            Some(b'?') => {
+                p += 1;
+                n += 1;
+                continue;
+            }
+            Some(b'\\') if pattern.get(p + 1) == Some(&name[n]) => {
+                p += 2;
+                n += 1;
+                continue;
+            }
+            Some(&c) if c != b'\\' && c == name[n] => {
+                p += 1;
+                n += 1;
+                continue;
+            }
+            _ => {}
+        }
+        // let the last `*` cover one more byte
+        match backtrack {
+            Some((star_p, star_n)) => {
+                backtrack = Some((star_p, star_n + 1));
+                p = star_p;
+                n = star_n + 1;
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_globs() {
+        assert!(glob_match(b"*", b"anything"));
+        assert!(glob_match(b".*", b".snapshot"));
+        assert!(!glob_match(b".*", b"file"));
+        assert!(glob_match(b"*.tmp", b"a.b.tmp"));
+        assert!(!glob_match(b"*.tmp", b"a.tmp.bak"));
+        assert!(glob_match(b"~$*", b"~$report.docx"));
+        assert!(glob_match(b"file?.txt", b"file1.txt"));
+        assert!(!glob_match(b"file?.txt", b"file.txt"));
+        assert!(glob_match(b"lost+found", b"lost+found"));
+        assert!(glob_match(b"\\*", b"*"));
+        assert!(!glob_match(b"\\*", b"x"));
+        assert!(glob_match(b"a*b*c", b"aXbYbZc"));
+    }
+
+    #[test]
+    fn never_hides_dot_entries() {
+        let mut filter = NameFilter::new();
+        filter.hide_dotfiles().hide("lost+found");
+        assert!(filter.is_hidden(b".git"));
+        assert!(filter.is_hidden(b"lost+found"));
+        assert!(!filter.is_hidden(b"."));
+        assert!(!filter.is_hidden(b".."));
+        assert!(!filter.is_hidden(b"README"));
+    }
+}
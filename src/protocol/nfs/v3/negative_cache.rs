@@ -0,0 +1,193 @@
+//! Caching of names `LOOKUP` found missing.
+//!
+//! Compilers searching include paths, shells searching `PATH` and dynamic
+//! loaders searching library directories look up many names that do not
+//! exist, often the same ones over and over. Without a cache every such miss
+//! reaches the backend. The [`NegativeLookupCache`] remembers `NFS3ERR_NOENT`
+//! results per directory and name for a short time and answers repeated
+//! lookups itself.
+//!
+//! Procedures adding a name (`CREATE`, `MKDIR`, `SYMLINK`, `MKNOD`, `LINK` and
+//! `RENAME`) drop the cached miss of the name once the backend is done. A
+//! lookup that was already underway when a name was added does not cache its
+//! miss, so a lookup racing a create cannot hide the new file. Names added to
+//! the backend by other means, e.g. by another server sharing the storage,
+//! stay hidden for up to the time to live.
+//!
+//! The cache is a [`MemoryConsumer`]: under pressure of a
+//! [`crate::memory_budget::MemoryBudget`], the oldest misses are dropped first.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use crate::memory_budget::{MemoryCharge, MemoryConsumer};
+use crate::protocol::xdr::nfs3::fileid3;
+
+/// Returns the approximate memory of a cached miss of a name
+fn entry_size(name: &[u8]) -> usize {
+    // the key is stored in the map and in the order of insertion
+    2 * (std::mem::size_of::<((fileid3, Vec<u8>), SystemTime)>() + name.len())
+}
+
+/// Limits of the [`NegativeLookupCache`]
+#[derive(Clone, Copy, Debug)]
+pub struct NegativeLookupConfig {
+    /// Time a miss is remembered
+    pub ttl: Duration,
+    /// Number of misses remembered; the oldest ones are dropped beyond it
+    pub capacity: usize,
+}
+
+impl Default for NegativeLookupConfig {
+    fn default() -> Self {
+        Self { ttl: Duration::from_secs(2), capacity: 16384 }
+    }
+}
+
+/// Cached misses
+#[derive(Debug, Default)]
+struct State {
+    /// Time of every cached miss by directory and name
+    misses: HashMap<(fileid3, Vec<u8>), SystemTime>,
+    /// Cached misses in the order they were recorded, including ones dropped
+    /// from `misses` since
+    order: VecDeque<((fileid3, Vec<u8>), SystemTime)>,
+}
+
+/// Names recently found missing, by directory
+#[derive(Debug, Default)]
+pub struct NegativeLookupCache {
+    config: NegativeLookupConfig,
+    /// Number of invalidations so far
+    epoch: AtomicU64,
+    state: Mutex<State>,
+    memory: MemoryCharge,
+}
+
+impl NegativeLookupCache {
+    /// Creates an empty cache with the given limits
+    pub fn new(config: NegativeLookupConfig) -> Self {
+        Self {
+            config,
+            epoch: AtomicU64::new(0),
+            state: Mutex::default(),
+            memory: MemoryCharge::default(),
+        }
+    }
+
+    /// Returns whether `name` was found missing in `dirid` within the time to
+    /// live
+    pub fn is_missing(&self, dirid: fileid3, name: &[u8], now: SystemTime) -> bool {
+        let state = self.state.lock().unwrap();
+        state.misses.get(&(dirid, name.to_vec())).is_some_and(|recorded| {
+            now.duration_since(*recorded).unwrap_or_default() < self.config.ttl
+        })
+    }
+
+    /// Returns the current epoch, to be passed to
+    /// [`NegativeLookupCache::record_missing`] for a lookup started now
+    pub fn epoch(&self) -> u64 {
+        self.epoch.load(Ordering::Acquire)
+    }
+
+    /// Remembers that `name` is missing in `dirid`
+    ///
+    /// Nothing is remembered if a name was added anywhere since `epoch` was
+    /// taken, as the lookup may have missed it.
+    pub fn record_missing(&self, dirid: fileid3, name: &[u8], epoch: u64, now: SystemTime) {
+        {
+            let mut state = self.state.lock().unwrap();
+            if self.epoch() != epoch {
+                return;
+            }
+            while state.order.len() >= self.config.capacity.max(1) {
+                self.evict_oldest(&mut state);
+            }
+            let key = (dirid, name.to_vec());
+            state.misses.insert(key.clone(), now);
+            state.order.push_back((key, now));
+            self.memory.charge(entry_size(name));
+        }
+        self.memory.reclaim();
+    }
+
+    /// Drops the oldest recorded miss
+    ///
+    /// # Returns
+    ///
+    /// * `usize` - The memory freed, 0 if nothing was recorded
+    fn evict_oldest(&self, state: &mut State) -> usize {
+        let Some((key, recorded)) = state.order.pop_front() else {
+            return 0;
+        };
+        if state.misses.get(&key) == Some(&recorded) {
+            state.misses.remove(&key);
+        }
+        let size = entry_size(&key.1);
+        self.memory.release(size);
+        size
+    }
+
+    /// Forgets that `name` is missing in `dirid`, as it is being added
+    pub fn invalidate(&self, dirid: fileid3, name: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        self.epoch.fetch_add(1, Ordering::AcqRel);
+        state.misses.remove(&(dirid, name.to_vec()));
+    }
+
+    /// Returns the number of cached misses, including expired ones
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().misses.len()
+    }
+
+    /// Returns whether no misses are cached
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl MemoryConsumer for NegativeLookupCache {
+    fn memory(&self) -> &MemoryCharge {
+        &self.memory
+    }
+
+    fn shrink(&self, bytes: usize) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let mut freed = 0;
+        while freed < bytes && !state.order.is_empty() {
+            freed += self.evict_oldest(&mut state);
+        }
+        freed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remembers_misses_until_invalidated_or_expired() {
+        let config = NegativeLookupConfig { ttl: Duration::from_secs(2), capacity: 2 };
+        let cache = NegativeLookupCache::new(config);
+        let now = SystemTime::UNIX_EPOCH;
+        cache.record_missing(1, b"a", cache.epoch(), now);
+        assert!(cache.is_missing(1, b"a", now + Duration::from_secs(1)));
+        assert!(!cache.is_missing(1, b"a", now + Duration::from_secs(2)));
+        assert!(!cache.is_missing(2, b"a", now));
+
+        // a lookup started before an invalidation is not cached
+        let epoch = cache.epoch();
+        cache.invalidate(1, b"a");
+        assert!(!cache.is_missing(1, b"a", now));
+        cache.record_missing(1, b"b", epoch, now);
+        assert!(!cache.is_missing(1, b"b", now));
+
+        for name in [b"c", b"d", b"e"] {
+            cache.record_missing(1, name, cache.epoch(), now);
+        }
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.is_missing(1, b"c", now) && cache.is_missing(1, b"e", now));
+    }
+}
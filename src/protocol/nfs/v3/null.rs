@@ -12,13 +12,14 @@ use std::io::Write;
 
 use tracing::debug;
 
+use crate::protocol::rpc;
 use crate::protocol::xdr::{self, Serialize};
 
 /// Handles `NFSv3` `NULL` procedure
 ///
 /// `NULL` is a no-operation RPC call used to check if the server is responding.
 /// Takes no arguments and returns nothing but an RPC success.
-pub fn nfsproc3_null(xid: u32, output: &mut impl Write) -> Result<(), anyhow::Error> {
+pub fn nfsproc3_null(xid: u32, output: &mut impl Write) -> Result<(), rpc::ServerError> {
     debug!("nfsproc3_null({:?}) ", xid);
     let msg = xdr::rpc::make_success_reply(xid);
     debug!("\t{:?} --> {:?}", xid, msg);
@@ -12,10 +12,13 @@
 //! - The actual number of bytes read
 //! - An EOF flag indicating whether the read reached the end of file
 //! - The data read from the file
+//!
+//! Large reads may be streamed to the client chunk by chunk, see
+//! [`rpc::ReplyStream`].
 
 use std::io::{Read, Write};
 
-use tracing::{debug, error};
+use tracing::{debug, error, trace};
 
 use crate::protocol::rpc;
 use crate::protocol::xdr::{self, deserialize, nfs3, Serialize};
@@ -35,17 +38,18 @@ use crate::protocol::xdr::{self, deserialize, nfs3, Serialize};
 ///
 /// # Returns
 ///
-/// * `Result<(), anyhow::Error>` - Ok(()) on success or an error
+/// * `Result<(), rpc::ServerError>` - Ok(()) on success or an error
 pub async fn nfsproc3_read(
     xid: u32,
     input: &mut impl Read,
     output: &mut impl Write,
     context: &rpc::Context,
-) -> Result<(), anyhow::Error> {
-    let args = deserialize::<nfs3::file::READ3args>(input)?;
+) -> Result<(), rpc::ServerError> {
+    let mut args = deserialize::<nfs3::file::READ3args>(input)?;
     debug!("nfsproc3_read({:?},{:?}) ", xid, args);
+    args.count = context.quirks.transfer_size(args.count);
 
-    let id = context.vfs.fh_to_id(&args.file);
+    let id = context.fh_to_id(&args.file);
     if let Err(stat) = id {
         xdr::rpc::make_success_reply(xid).serialize(output)?;
         stat.serialize(output)?;
@@ -53,16 +57,69 @@ pub async fn nfsproc3_read(
         return Ok(());
     }
     let id = id.unwrap();
+    super::record_io(context, id);
+    if let Some(Err(stat)) = context.content_inspection.as_ref().map(|c| c.check_access(id)) {
+        xdr::rpc::make_success_reply(xid).serialize(output)?;
+        stat.serialize(output)?;
+        nfs3::post_op_attr::None.serialize(output)?;
+        return Ok(());
+    }
+    super::flush_coalesced(context, id).await;
 
     let obj_attr = context.vfs.getattr(id).await.ok();
+    let cache =
+        context.read_cache.as_ref().filter(|cache| cache.caches(context.vfs.is_immutable(id)));
+    if let Some(cache) = cache {
+        if let Some((data, eof)) = cache.get(id, args.offset, args.count) {
+            debug!("nfsproc3_read answering {} bytes of {} from the cache", data.len(), id);
+            context.stats.read_cache.hit();
+            context.stats.export(&context.export_name).record_read(data.len() as u64);
+            let count = data.len() as u32;
+            let res = nfs3::file::READ3resok { file_attributes: obj_attr, count, eof, data };
+            xdr::rpc::make_success_reply(xid).serialize(output)?;
+            nfs3::nfsstat3::NFS3_OK.serialize(output)?;
+            res.serialize(output)?;
+            return Ok(());
+        }
+        context.stats.read_cache.miss();
+    }
+    // cached files are read into memory, so that the data can be kept
+    let streamed = match cache {
+        Some(_) => None,
+        None => streamed_count(context, &args, obj_attr.as_ref()),
+    };
+    if let Some(count) = streamed {
+        let size = obj_attr.as_ref().map_or(0, |attr| attr.size);
+        let eof = args.offset + u64::from(count) >= size;
+        debug!("nfsproc3_read streaming {} bytes of {}", count, id);
+        hint_read_ahead(context, id, args.offset, count, eof, obj_attr.as_ref()).await;
+        xdr::rpc::make_success_reply(xid).serialize(output)?;
+        nfs3::nfsstat3::NFS3_OK.serialize(output)?;
+        obj_attr.serialize(output)?;
+        count.serialize(output)?;
+        eof.serialize(output)?;
+        // length of the opaque data, which the connection streams after the reply
+        count.serialize(output)?;
+        if let Some(stream) = &context.reply_stream {
+            stream.set(context.vfs.clone(), id, args.offset, count);
+        }
+        context.stats.export(&context.export_name).record_read(u64::from(count));
+        return Ok(());
+    }
+    let epoch = cache.map(|cache| cache.epoch());
     match context.vfs.read(id, args.offset, args.count).await {
         Ok((bytes, eof)) => {
-            let res = nfs3::file::READ3resok {
-                file_attributes: obj_attr,
-                count: bytes.len() as u32,
-                eof,
-                data: bytes,
-            };
+            let len = bytes.len() as u32;
+            if let Some((cache, epoch)) = cache.zip(epoch) {
+                cache.insert(id, args.offset, &bytes, eof, epoch);
+            }
+            if let Some(checksums) = &context.checksums {
+                checksums.check_read(id, args.offset, &bytes);
+            }
+            hint_read_ahead(context, id, args.offset, len, eof, obj_attr.as_ref()).await;
+            context.stats.export(&context.export_name).record_read(u64::from(len));
+            let res =
+                nfs3::file::READ3resok { file_attributes: obj_attr, count: len, eof, data: bytes };
             xdr::rpc::make_success_reply(xid).serialize(output)?;
             nfs3::nfsstat3::NFS3_OK.serialize(output)?;
             res.serialize(output)?;
@@ -76,3 +133,43 @@ pub async fn nfsproc3_read(
     }
     Ok(())
 }
+
+/// Returns the length of the data if the reply is streamed
+///
+/// Only reads of regular files whose data exceeds the connection's chunk size
+/// are streamed. The length is derived from the file size, as the reply carries
+/// it before the data.
+fn streamed_count(
+    context: &rpc::Context,
+    args: &nfs3::file::READ3args,
+    attr: Option<&nfs3::fattr3>,
+) -> Option<u32> {
+    let stream = context.reply_stream.as_ref()?;
+    let attr = attr.filter(|attr| matches!(attr.ftype, nfs3::ftype3::NF3REG))?;
+    let available = attr.size.saturating_sub(args.offset);
+    let count = u64::from(args.count).min(available) as u32;
+    stream.streams(count).then_some(count)
+}
+
+/// Passes a read to the read-ahead detector and forwards its hint to the VFS
+async fn hint_read_ahead(
+    context: &rpc::Context,
+    id: nfs3::fileid3,
+    offset: u64,
+    len: u32,
+    eof: bool,
+    attr: Option<&nfs3::fattr3>,
+) {
+    let (Some(detector), false) = (&context.read_ahead, eof) else {
+        return;
+    };
+    let size = attr.map_or(u64::MAX, |attr| attr.size);
+    let hint = detector.observe(context.client_addr, id, offset, u64::from(len), size);
+    if let Some((offset, length)) = hint {
+        trace!("nfsproc3_read readahead {} {}+{}", id, offset, length);
+        context.stats.read_ahead.hit();
+        context.vfs.readahead(id, offset, length).await;
+    } else {
+        context.stats.read_ahead.miss();
+    }
+}
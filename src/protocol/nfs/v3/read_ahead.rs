@@ -0,0 +1,174 @@
+//! Detection of sequential `READ` streams for read-ahead hints.
+//!
+//! A client streaming a file sends `READ` calls whose offsets continue where
+//! the previous call ended. The [`ReadAheadDetector`] tracks the next expected
+//! offset per (client, file) pair. Once a stream is seen reading sequentially it
+//! yields a read-ahead window past the data just read, doubling the window on
+//! every further sequential read up to a maximum, similar to the Linux page
+//! cache. Any non-sequential read resets the stream.
+//!
+//! The window is passed to [`crate::vfs::NFSFileSystem::readahead`], so
+//! backends can prefetch the data before the client asks for it.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::protocol::xdr::nfs3::fileid3;
+
+/// Limits applied by the [`ReadAheadDetector`]
+#[derive(Clone, Copy, Debug)]
+pub struct ReadAheadConfig {
+    /// Window hinted after the first sequential read
+    pub min_window: u64,
+    /// Largest window hinted for a single stream
+    pub max_window: u64,
+    /// Number of streams tracked; the least recently used ones are dropped beyond it
+    pub max_streams: usize,
+}
+
+impl Default for ReadAheadConfig {
+    fn default() -> Self {
+        Self { min_window: 128 << 10, max_window: 4 << 20, max_streams: 1024 }
+    }
+}
+
+/// State of a single (client, file) stream
+#[derive(Debug)]
+struct Stream {
+    /// Offset the next read starts at if the stream stays sequential
+    next_offset: u64,
+    /// Current read-ahead window, zero until the stream is sequential
+    window: u64,
+    /// End of the range already hinted, so ranges are not hinted twice
+    hinted_to: u64,
+    /// Time of the last read, for eviction
+    last_used: Instant,
+}
+
+/// Tracks `READ` offsets per client and file and derives read-ahead ranges
+#[derive(Debug, Default)]
+pub struct ReadAheadDetector {
+    /// Window limits
+    config: ReadAheadConfig,
+    /// Streams keyed by client address and file
+    streams: Mutex<HashMap<(SocketAddr, fileid3), Stream>>,
+}
+
+impl ReadAheadDetector {
+    /// Creates a detector with the given limits
+    pub fn new(config: ReadAheadConfig) -> Self {
+        Self { config, streams: Mutex::default() }
+    }
+
+    /// Records a completed read and returns the range to prefetch, if any
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - Address of the client that issued the read
+    /// * `id` - File that was read
+    /// * `offset` - Offset of the read
+    /// * `count` - Number of bytes returned
+    /// * `size` - Size of the file, hints are not extended past it
+    ///
+    /// # Returns
+    ///
+    /// * `Option<(u64, u64)>` - Offset and length of the range to hint, or `None`
+    ///   if the stream is not sequential or the window is already hinted
+    pub fn observe(
+        &self,
+        client: SocketAddr,
+        id: fileid3,
+        offset: u64,
+        count: u64,
+        size: u64,
+    ) -> Option<(u64, u64)> {
+        let end = offset.saturating_add(count);
+        let mut streams = self.streams.lock().unwrap();
+        if streams.len() >= self.config.max_streams && !streams.contains_key(&(client, id)) {
+            Self::evict(&mut streams, self.config.max_streams / 2);
+        }
+        let stream = streams.entry((client, id)).or_insert(Stream {
+            next_offset: u64::MAX,
+            window: 0,
+            hinted_to: 0,
+            last_used: Instant::now(),
+        });
+        stream.last_used = Instant::now();
+
+        if offset != stream.next_offset {
+            stream.next_offset = end;
+            stream.window = 0;
+            stream.hinted_to = end;
+            return None;
+        }
+        stream.next_offset = end;
+        stream.window = if stream.window == 0 {
+            self.config.min_window
+        } else {
+            stream.window.saturating_mul(2).min(self.config.max_window)
+        };
+
+        let start = stream.hinted_to.max(end);
+        let hint_end = end.saturating_add(stream.window).min(size);
+        if hint_end <= start {
+            return None;
+        }
+        stream.hinted_to = hint_end;
+        Some((start, hint_end - start))
+    }
+
+    /// Drops the least recently used streams until `keep` are left
+    fn evict(streams: &mut HashMap<(SocketAddr, fileid3), Stream>, keep: usize) {
+        let mut ages: Vec<Instant> = streams.values().map(|s| s.last_used).collect();
+        ages.sort_unstable_by(|a, b| b.cmp(a));
+        if let Some(&cutoff) = ages.get(keep) {
+            streams.retain(|_, s| s.last_used > cutoff);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    const SIZE: u64 = 1 << 30;
+    const C: SocketAddr = SocketAddr::new(LOCALHOST, 700);
+    const OTHER: SocketAddr = SocketAddr::new(LOCALHOST, 701);
+
+    fn detector() -> ReadAheadDetector {
+        ReadAheadDetector::new(ReadAheadConfig { min_window: 100, max_window: 400, max_streams: 4 })
+    }
+
+    #[test]
+    fn hints_growing_windows_for_sequential_reads() {
+        let d = detector();
+        assert_eq!(d.observe(C, 1, 0, 50, SIZE), None);
+        assert_eq!(d.observe(C, 1, 50, 50, SIZE), Some((100, 100)));
+        assert_eq!(d.observe(C, 1, 100, 50, SIZE), Some((200, 150)));
+        assert_eq!(d.observe(C, 1, 150, 50, SIZE), Some((350, 250)));
+        assert_eq!(d.observe(C, 1, 200, 50, SIZE), Some((600, 50)));
+    }
+
+    #[test]
+    fn random_reads_reset_the_stream() {
+        let d = detector();
+        d.observe(C, 1, 0, 50, SIZE);
+        assert!(d.observe(C, 1, 50, 50, SIZE).is_some());
+        assert_eq!(d.observe(C, 1, 1000, 50, SIZE), None);
+        assert_eq!(d.observe(C, 2, 50, 50, SIZE), None);
+        assert_eq!(d.observe(OTHER, 1, 1050, 50, SIZE), None);
+        assert_eq!(d.observe(C, 1, 1050, 50, SIZE), Some((1100, 100)));
+    }
+
+    #[test]
+    fn hints_stop_at_end_of_file() {
+        let d = detector();
+        d.observe(C, 1, 0, 50, 120);
+        assert_eq!(d.observe(C, 1, 50, 50, 120), Some((100, 20)));
+        assert_eq!(d.observe(C, 1, 100, 20, 120), None);
+    }
+}
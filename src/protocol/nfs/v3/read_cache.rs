@@ -0,0 +1,284 @@
+//! Caching of `READ` results in memory.
+//!
+//! Content-addressed stores, archives and build caches serve files that never
+//! change once written, and some of them, e.g. toolchains or container layers,
+//! are read by many clients at once. The [`ReadCache`] keeps the data of
+//! recent reads by file and offset, so repeated reads of hot files are
+//! answered without reaching the backend.
+//!
+//! Only files the backend reports as immutable through
+//! [`crate::vfs::NFSFileSystem::is_immutable`] are cached by default. With
+//! [`ReadCacheConfig::all_files`], other files are cached too; `WRITE` and
+//! `SETATTR` drop the cached data of a file, so this is only correct if
+//! nothing but this server modifies the backend. A read that was already
+//! underway when a file was modified does not cache its data.
+//!
+//! The cache is a [`MemoryConsumer`]: under pressure of a
+//! [`crate::memory_budget::MemoryBudget`], the oldest reads are dropped first.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::memory_budget::{MemoryCharge, MemoryConsumer};
+use crate::protocol::xdr::nfs3::fileid3;
+
+/// Limits of the [`ReadCache`]
+#[derive(Clone, Copy, Debug)]
+pub struct ReadCacheConfig {
+    /// Bytes of data cached; the oldest reads are dropped beyond it
+    pub capacity: usize,
+    /// Reads longer than this are not cached, so a single large file cannot
+    /// push out all others
+    pub max_read: u32,
+    /// Whether files the backend does not report as immutable are cached too
+    pub all_files: bool,
+}
+
+impl Default for ReadCacheConfig {
+    fn default() -> Self {
+        Self { capacity: 64 * 1024 * 1024, max_read: 1024 * 1024, all_files: false }
+    }
+}
+
+/// Data read at an offset
+#[derive(Debug)]
+struct Page {
+    /// Sequence number of the insertion, to tell replaced pages apart
+    seq: u64,
+    data: Vec<u8>,
+    /// Whether the read reached the end of the file
+    eof: bool,
+}
+
+/// Cached reads
+#[derive(Debug, Default)]
+struct State {
+    /// Pages by file and offset
+    files: HashMap<fileid3, BTreeMap<u64, Page>>,
+    /// Pages in the order they were inserted, including ones dropped from
+    /// `files` since
+    order: VecDeque<(fileid3, u64, u64)>,
+    /// Bytes of data in `files`
+    bytes: usize,
+    /// Sequence number of the next insertion
+    next_seq: u64,
+}
+
+impl Page {
+    /// Returns the approximate memory of the page, including its keys
+    fn size(&self) -> usize {
+        self.data.len()
+            + std::mem::size_of::<(fileid3, u64, Page)>()
+            + std::mem::size_of::<(fileid3, u64, u64)>()
+    }
+}
+
+impl State {
+    /// Removes a page, if it is still the one inserted as `seq`
+    ///
+    /// # Returns
+    ///
+    /// * `usize` - The memory freed, 0 if the page was replaced or removed
+    fn remove(&mut self, id: fileid3, offset: u64, seq: u64) -> usize {
+        let Some(pages) = self.files.get_mut(&id) else {
+            return 0;
+        };
+        if pages.get(&offset).is_none_or(|page| page.seq != seq) {
+            return 0;
+        }
+        let page = pages.remove(&offset).unwrap();
+        self.bytes -= page.data.len();
+        if pages.is_empty() {
+            self.files.remove(&id);
+        }
+        page.size()
+    }
+}
+
+/// Data of recent reads, by file and offset
+#[derive(Debug, Default)]
+pub struct ReadCache {
+    config: ReadCacheConfig,
+    /// Number of invalidations so far
+    epoch: AtomicU64,
+    state: Mutex<State>,
+    memory: MemoryCharge,
+}
+
+impl ReadCache {
+    /// Creates an empty cache with the given limits
+    pub fn new(config: ReadCacheConfig) -> Self {
+        Self {
+            config,
+            epoch: AtomicU64::new(0),
+            state: Mutex::default(),
+            memory: MemoryCharge::default(),
+        }
+    }
+
+    /// Returns whether reads of a file are cached
+    ///
+    /// # Arguments
+    ///
+    /// * `immutable` - Whether the backend reports the file as immutable
+    pub fn caches(&self, immutable: bool) -> bool {
+        immutable || self.config.all_files
+    }
+
+    /// Returns up to `count` bytes at `offset` of a file and whether they
+    /// reach the end of the file, `None` if no earlier read covers them
+    pub fn get(&self, id: fileid3, offset: u64, count: u32) -> Option<(Vec<u8>, bool)> {
+        let state = self.state.lock().unwrap();
+        let page = state.files.get(&id)?.get(&offset)?;
+        let count = count as usize;
+        if count <= page.data.len() {
+            Some((page.data[..count].to_vec(), page.eof && count == page.data.len()))
+        } else if page.eof {
+            Some((page.data.clone(), true))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the current epoch, to be passed to [`ReadCache::insert`] for a
+    /// read started now
+    pub fn epoch(&self) -> u64 {
+        self.epoch.load(Ordering::Acquire)
+    }
+
+    /// Remembers the result of a read at `offset` of a file
+    ///
+    /// Nothing is remembered if any file was modified since `epoch` was taken,
+    /// as the data may be stale, or if the read is longer than the limit.
+    pub fn insert(&self, id: fileid3, offset: u64, data: &[u8], eof: bool, epoch: u64) {
+        if data.len() > self.config.max_read as usize || data.len() > self.config.capacity {
+            return;
+        }
+        {
+            let mut state = self.state.lock().unwrap();
+            if self.epoch() != epoch {
+                return;
+            }
+            while state.bytes + data.len() > self.config.capacity {
+                if self.evict_oldest(&mut state).is_none() {
+                    break;
+                }
+            }
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            let page = Page { seq, data: data.to_vec(), eof };
+            state.bytes += data.len();
+            self.memory.charge(page.size());
+            if let Some(old) = state.files.entry(id).or_default().insert(offset, page) {
+                state.bytes -= old.data.len();
+                self.memory.release(old.size());
+            }
+            state.order.push_back((id, offset, seq));
+        }
+        self.memory.reclaim();
+    }
+
+    /// Drops the oldest inserted page, if it is still cached
+    ///
+    /// # Returns
+    ///
+    /// * `Option<usize>` - The memory freed, `None` if nothing was inserted
+    fn evict_oldest(&self, state: &mut State) -> Option<usize> {
+        let (id, offset, seq) = state.order.pop_front()?;
+        let freed = state.remove(id, offset, seq);
+        self.memory.release(freed);
+        Some(freed)
+    }
+
+    /// Drops the cached data of a file, as it is being modified
+    pub fn invalidate(&self, id: fileid3) {
+        let mut state = self.state.lock().unwrap();
+        self.epoch.fetch_add(1, Ordering::AcqRel);
+        if let Some(pages) = state.files.remove(&id) {
+            state.bytes -= pages.values().map(|page| page.data.len()).sum::<usize>();
+            self.memory.release(pages.values().map(Page::size).sum());
+        }
+    }
+
+    /// Returns the number of bytes cached
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().bytes
+    }
+
+    /// Returns whether nothing is cached
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl MemoryConsumer for ReadCache {
+    fn memory(&self) -> &MemoryCharge {
+        &self.memory
+    }
+
+    fn shrink(&self, bytes: usize) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let mut freed = 0;
+        while freed < bytes {
+            match self.evict_oldest(&mut state) {
+                Some(size) => freed += size,
+                None => break,
+            }
+        }
+        freed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::memory_budget::MemoryBudget;
+
+    #[test]
+    fn serves_cached_reads_until_invalidated_or_evicted() {
+        let config = ReadCacheConfig { capacity: 8, max_read: 6, all_files: false };
+        let cache = ReadCache::new(config);
+        assert!(cache.caches(true) && !cache.caches(false));
+        cache.insert(1, 0, b"abcd", false, cache.epoch());
+        assert_eq!(cache.get(1, 0, 2), Some((b"ab".to_vec(), false)));
+        assert_eq!(cache.get(1, 0, 8), None);
+        assert_eq!(cache.get(1, 4, 4), None);
+        cache.insert(1, 4, b"ef", true, cache.epoch());
+        assert_eq!(cache.get(1, 4, 8), Some((b"ef".to_vec(), true)));
+        assert_eq!(cache.get(1, 4, 2), Some((b"ef".to_vec(), true)));
+
+        // a read started before an invalidation is not cached
+        let epoch = cache.epoch();
+        cache.invalidate(1);
+        assert_eq!(cache.get(1, 0, 2), None);
+        cache.insert(1, 0, b"abcd", false, epoch);
+        assert!(cache.is_empty());
+
+        cache.insert(2, 0, b"1234567", false, cache.epoch());
+        assert_eq!(cache.len(), 0, "reads above the limit are not cached");
+        for offset in [0, 4, 8] {
+            cache.insert(3, offset, b"wxyz", false, cache.epoch());
+        }
+        assert_eq!(cache.len(), 8);
+        assert_eq!(cache.get(3, 0, 4), None);
+        assert!(cache.get(3, 8, 4).is_some());
+    }
+
+    #[test]
+    fn drops_oldest_reads_beyond_memory_budget() {
+        let cache = Arc::new(ReadCache::new(ReadCacheConfig::default()));
+        let page = Page { seq: 0, data: vec![0; 100], eof: false }.size();
+        let budget = Arc::new(MemoryBudget::new(2 * page));
+        budget.register("read_cache", &cache);
+        for offset in [0, 100, 200] {
+            cache.insert(1, offset, &[0; 100], false, cache.epoch());
+        }
+        assert_eq!(budget.used(), 2 * page);
+        assert!(cache.get(1, 0, 100).is_none() && cache.get(1, 200, 100).is_some());
+        cache.invalidate(1);
+        assert_eq!(budget.used(), 0);
+    }
+}
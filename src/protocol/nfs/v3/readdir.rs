@@ -25,6 +25,7 @@ use tracing::{debug, error, trace};
 
 use crate::protocol::rpc;
 use crate::protocol::xdr::{self, deserialize, nfs3, Serialize};
+use crate::vfs;
 
 /// Handles `NFSv3` ``READDIR`` procedure (procedure 16)
 ///
@@ -41,17 +42,17 @@ use crate::protocol::xdr::{self, deserialize, nfs3, Serialize};
 ///
 /// # Returns
 ///
-/// * `Result<(), anyhow::Error>` - Ok(()) on success or an error
+/// * `Result<(), rpc::ServerError>` - Ok(()) on success or an error
 pub async fn nfsproc3_readdir(
     xid: u32,
     input: &mut impl Read,
     output: &mut impl Write,
     context: &rpc::Context,
-) -> Result<(), anyhow::Error> {
-    let args = deserialize::<nfs3::dir::READDIR3args>(input)?;
+) -> Result<(), rpc::ServerError> {
+    let mut args = deserialize::<nfs3::dir::READDIR3args>(input)?;
     debug!("nfsproc3_readdirplus({:?},{:?}) ", xid, args);
 
-    let dirid = context.vfs.fh_to_id(&args.dir);
+    let dirid = context.fh_to_id(&args.dir);
     // fail if unable to convert file handle
     if let Err(stat) = dirid {
         xdr::rpc::make_success_reply(xid).serialize(output)?;
@@ -73,6 +74,7 @@ pub async fn nfsproc3_readdir(
     };
     debug!(" -- Dir attr {:?}", dir_attr);
     debug!(" -- Dir version {:?}", dirversion);
+    args.dircount = context.quirks.dir_transfer_size(args.dircount);
     let has_version = args.cookieverf != nfs3::cookieverf3::default();
     // subtract off the final entryplus* field (which must be false) and the eof
     let max_bytes_allowed = args.dircount as usize - 128;
@@ -81,7 +83,35 @@ pub async fn nfsproc3_readdir(
     let estimated_max_results = args.dircount / 16;
     let mut ctr = 0;
 
-    match context.vfs.readdir_simple(dirid, args.cookie, estimated_max_results as usize).await {
+    let mut cookie =
+        vfs::ReadDirCookie::from_cookie3(args.cookie, context.vfs.readdir_cookie_kind());
+    let result = loop {
+        let result = match cookie {
+            vfs::ReadDirCookie::Index(_) | vfs::ReadDirCookie::Opaque(_) => context
+                .vfs
+                .readdir_with_cookie(dirid, cookie, estimated_max_results as usize)
+                .await
+                .map(|result| vfs::ReadDirSimpleResult::from_readdir_result(&result)),
+            vfs::ReadDirCookie::AfterFileId(start_after) => {
+                context.vfs.readdir_simple(dirid, start_after, estimated_max_results as usize).await
+            }
+            vfs::ReadDirCookie::Start => {
+                context.vfs.readdir_simple(dirid, 0, estimated_max_results as usize).await
+            }
+        };
+        let next = match &result {
+            Ok(batch) if !batch.end => {
+                let entries = batch.entries.iter().map(|entry| (entry.fileid, &entry.name));
+                super::resume_after_hidden(context, dirid, cookie, entries)
+            }
+            _ => None,
+        };
+        match next {
+            Some(next) => cookie = next,
+            None => break result,
+        }
+    };
+    match result {
         Ok(result) => {
             // we count dir_count seperately as it is just a subset of fields
             let mut accumulated_dircount: usize = 0;
@@ -95,11 +125,21 @@ pub async fn nfsproc3_readdir(
             nfs3::nfsstat3::NFS3_OK.serialize(&mut counting_output)?;
             dir_attr.serialize(&mut counting_output)?;
             dirversion.serialize(&mut counting_output)?;
-            for entry in result.entries {
+            for (position, entry) in result.entries.into_iter().enumerate() {
+                if super::is_hidden(context, &entry.name) {
+                    continue;
+                }
                 let entry = nfs3::dir::entry3 {
                     fileid: entry.fileid,
+                    cookie: super::entry_cookie(
+                        context,
+                        dirid,
+                        cookie,
+                        position,
+                        entry.fileid,
+                        &entry.name,
+                    ),
                     name: entry.name,
-                    cookie: entry.fileid,
                 };
                 // write the entry into a buffer first
                 let mut write_buf: Vec<u8> = Vec::new();
@@ -0,0 +1,75 @@
+//! Limits on the size of `READDIRPLUS` requests.
+//!
+//! A `READDIRPLUS` call carries two sizes chosen by the client: `dircount`, the
+//! bytes of names and cookies it wants, and `maxcount`, the size of the whole
+//! reply including attributes and handles. The server sizes the listing it asks
+//! the file system for from `dircount`, so a client sending huge values makes
+//! the server fetch attributes of far more entries than a reply can carry.
+//!
+//! [`ReadDirPlusLimits`] clamps both values to configured maxima. `dircount` is
+//! additionally clamped to the `dtpref` the server last announced in `FSINFO`,
+//! which is the listing size clients were told to use.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Maxima applied to `READDIRPLUS` requests
+#[derive(Debug)]
+pub struct ReadDirPlusLimits {
+    /// Largest `dircount` honored
+    max_dircount: u32,
+    /// Largest `maxcount` honored
+    max_maxcount: u32,
+    /// `dtpref` of the last `FSINFO` reply, 0 before the first one
+    dtpref: AtomicU32,
+}
+
+impl Default for ReadDirPlusLimits {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_MAX_DIRCOUNT, Self::DEFAULT_MAX_MAXCOUNT)
+    }
+}
+
+impl ReadDirPlusLimits {
+    /// Largest `dircount` honored by default
+    pub const DEFAULT_MAX_DIRCOUNT: u32 = 64 << 10;
+    /// Largest `maxcount` honored by default
+    pub const DEFAULT_MAX_MAXCOUNT: u32 = 1 << 20;
+
+    /// Creates limits with the given maxima
+    ///
+    /// # Arguments
+    ///
+    /// * `max_dircount` - Largest `dircount` honored
+    /// * `max_maxcount` - Largest `maxcount` honored
+    pub fn new(max_dircount: u32, max_maxcount: u32) -> Self {
+        Self { max_dircount, max_maxcount, dtpref: AtomicU32::new(0) }
+    }
+
+    /// Records the `dtpref` announced to clients in an `FSINFO` reply
+    pub fn negotiated(&self, dtpref: u32) {
+        self.dtpref.store(dtpref, Ordering::Relaxed);
+    }
+
+    /// Returns `dircount` and `maxcount` of a request clamped to the limits
+    pub fn clamp(&self, dircount: u32, maxcount: u32) -> (u32, u32) {
+        let dircount = match self.dtpref.load(Ordering::Relaxed) {
+            0 => dircount.min(self.max_dircount),
+            dtpref => dircount.min(self.max_dircount).min(dtpref),
+        };
+        (dircount, maxcount.min(self.max_maxcount))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clamps_to_maxima_and_dtpref() {
+        let limits = ReadDirPlusLimits::new(8192, 65536);
+        assert_eq!(limits.clamp(4096, 16384), (4096, 16384));
+        assert_eq!(limits.clamp(u32::MAX, u32::MAX), (8192, 65536));
+        limits.negotiated(1024);
+        assert_eq!(limits.clamp(4096, 16384), (1024, 16384));
+    }
+}
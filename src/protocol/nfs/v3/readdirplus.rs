@@ -22,6 +22,17 @@
 //!   * The file attributes
 //!   * The file handle
 //! - A flag indicating whether the end of the directory was reached
+//!
+//! The sizes requested by the client are clamped to the
+//! [`super::ReadDirPlusLimits`] of the context. If not even one entry fits in
+//! the clamped sizes, the call fails with `NFS3ERR_TOOSMALL`.
+//!
+//! Entry attributes come from the listing unless the file system reports
+//! otherwise with [`crate::vfs::NFSFileSystem::readdir_has_attributes`], in
+//! which case they are fetched in one batch with
+//! [`crate::vfs::NFSFileSystem::getattr_bulk`]. Backends without a batch
+//! lookup of their own get concurrent `GETATTR`s of the entries, bounded by
+//! [`crate::vfs::NFSFileSystem::getattr_concurrency`].
 
 use std::io::{Read, Write};
 
@@ -29,6 +40,7 @@ use tracing::{debug, error, trace};
 
 use crate::protocol::rpc;
 use crate::protocol::xdr::{self, deserialize, nfs3, Serialize};
+use crate::vfs;
 
 /// Handles `NFSv3` `READDIRPLUS` procedure (procedure 17)
 ///
@@ -45,17 +57,25 @@ use crate::protocol::xdr::{self, deserialize, nfs3, Serialize};
 ///
 /// # Returns
 ///
-/// * `Result<(), anyhow::Error>` - Ok(()) on success or an error
+/// * `Result<(), rpc::ServerError>` - Ok(()) on success or an error
 pub async fn nfsproc3_readdirplus(
     xid: u32,
     input: &mut impl Read,
     output: &mut impl Write,
     context: &rpc::Context,
-) -> Result<(), anyhow::Error> {
+) -> Result<(), rpc::ServerError> {
     let args = deserialize::<nfs3::dir::READDIRPLUS3args>(input)?;
     debug!("nfsproc3_readdirplus({:?},{:?}) ", xid, args);
 
-    let dirid = context.vfs.fh_to_id(&args.dir);
+    if !context.readdirplus {
+        debug!(" -- READDIRPLUS disabled");
+        xdr::rpc::make_success_reply(xid).serialize(output)?;
+        nfs3::nfsstat3::NFS3ERR_NOTSUPP.serialize(output)?;
+        nfs3::post_op_attr::None.serialize(output)?;
+        return Ok(());
+    }
+
+    let dirid = context.fh_to_id(&args.dir);
     // fail if unable to convert file handle
     if let Err(stat) = dirid {
         xdr::rpc::make_success_reply(xid).serialize(output)?;
@@ -141,63 +161,112 @@ pub async fn nfsproc3_readdirplus(
         dir_attr.serialize(output)?;
         return Ok(());
     }*/
+    let (dircount, maxcount) = context.readdirplus_limits.clamp(args.dircount, args.maxcount);
+    let maxcount = context.quirks.dir_transfer_size(maxcount);
+    if (dircount, maxcount) != (args.dircount, args.maxcount) {
+        debug!(" -- clamped dircount {} maxcount {}", dircount, maxcount);
+    }
     // subtract off the final entryplus* field (which must be false) and the eof
-    let max_bytes_allowed = args.maxcount as usize - 128;
-    // args.dircount is bytes of just fileid, name, cookie.
+    let max_bytes_allowed = (maxcount as usize).saturating_sub(128);
+    // dircount is bytes of just fileid, name, cookie.
     // This is hard to ballpark, so we just divide it by 16
-    let estimated_max_results = args.dircount / 16;
-    let max_dircount_bytes = args.dircount as usize;
+    let estimated_max_results = (dircount / 16).max(1);
+    let max_dircount_bytes = dircount as usize;
     let mut ctr = 0;
-    match context.vfs.readdir(dirid, args.cookie, estimated_max_results as usize).await {
+    let mut cookie =
+        vfs::ReadDirCookie::from_cookie3(args.cookie, context.vfs.readdir_cookie_kind());
+    let result = loop {
+        let result =
+            context.vfs.readdir_with_cookie(dirid, cookie, estimated_max_results as usize).await;
+        let next = match &result {
+            Ok(batch) if !batch.end => {
+                let entries = batch.entries.iter().map(|entry| (entry.fileid, &entry.name));
+                super::resume_after_hidden(context, dirid, cookie, entries)
+            }
+            _ => None,
+        };
+        match next {
+            Some(next) => cookie = next,
+            None => break result,
+        }
+    };
+    match result {
         Ok(result) => {
             // we count dir_count seperately as it is just a subset of fields
             let mut accumulated_dircount: usize = 0;
             let mut all_entries_written = true;
 
-            // this is a wrapper around a writer that also just counts the number of bytes
-            // written
-            let mut counting_output = crate::write_counter::WriteCounter::new(output);
-
-            xdr::rpc::make_success_reply(xid).serialize(&mut counting_output)?;
-            nfs3::nfsstat3::NFS3_OK.serialize(&mut counting_output)?;
-            dir_attr.serialize(&mut counting_output)?;
-            dirversion.serialize(&mut counting_output)?;
-            for entry in result.entries {
-                let obj_attr = entry.attr;
-                let handle = nfs3::post_op_fh3::Some(context.vfs.id_to_fh(entry.fileid));
+            // the reply is built in memory, as it is replaced by NFS3ERR_TOOSMALL
+            // if not even one entry fits
+            let mut reply: Vec<u8> = Vec::new();
+            xdr::rpc::make_success_reply(xid).serialize(&mut reply)?;
+            nfs3::nfsstat3::NFS3_OK.serialize(&mut reply)?;
+            dir_attr.serialize(&mut reply)?;
+            dirversion.serialize(&mut reply)?;
+            let entries: Vec<_> = result
+                .entries
+                .into_iter()
+                .enumerate()
+                .filter(|(_, entry)| !super::is_hidden(context, &entry.name))
+                .collect();
+            // attributes listed by the VFS miss the data still being coalesced
+            let mut flushed = false;
+            if let Some(coalescer) = &context.write_coalescer {
+                for (_, entry) in &entries {
+                    if coalescer.is_pending(entry.fileid) {
+                        super::flush_coalesced(context, entry.fileid).await;
+                        flushed = true;
+                    }
+                }
+            }
+            let attrs: Vec<nfs3::post_op_attr> = if context.vfs.readdir_has_attributes() && !flushed
+            {
+                entries.iter().map(|(_, entry)| Some(entry.attr)).collect()
+            } else {
+                let ids: Vec<_> = entries.iter().map(|(_, entry)| entry.fileid).collect();
+                context.vfs.getattr_bulk(&ids).await.into_iter().map(Result::ok).collect()
+            };
+            for ((position, entry), obj_attr) in entries.into_iter().zip(attrs) {
+                let handle: nfs3::post_op_fh3 = context.id_to_fh(entry.fileid).ok();
 
+                let entry_cookie = super::entry_cookie(
+                    context,
+                    dirid,
+                    cookie,
+                    position,
+                    entry.fileid,
+                    &entry.name,
+                );
                 let entry = nfs3::dir::entryplus3 {
                     fileid: entry.fileid,
                     name: entry.name,
-                    cookie: entry.fileid,
-                    name_attributes: nfs3::post_op_attr::Some(obj_attr),
+                    cookie: entry_cookie,
+                    name_attributes: obj_attr,
                     name_handle: handle,
                 };
                 // write the entry into a buffer first
                 let mut write_buf: Vec<u8> = Vec::new();
-                let mut write_cursor = std::io::Cursor::new(&mut write_buf);
                 // true flag for the entryplus3* to mark that this contains an entry
-                true.serialize(&mut write_cursor)?;
-                entry.serialize(&mut write_cursor)?;
-                write_cursor.flush()?;
+                true.serialize(&mut write_buf)?;
+                entry.serialize(&mut write_buf)?;
                 let added_dircount = std::mem::size_of::<nfs3::fileid3>()                   // fileid
                                     + std::mem::size_of::<u32>() + entry.name.len()  // name
                                     + std::mem::size_of::<nfs3::cookie3>(); // cookie
                 let added_output_bytes = write_buf.len();
                 // check if we can write without hitting the limits
-                if added_output_bytes + counting_output.bytes_written() < max_bytes_allowed
+                if added_output_bytes + reply.len() < max_bytes_allowed
                     && added_dircount + accumulated_dircount < max_dircount_bytes
                 {
                     trace!("  -- dirent {:?}", entry);
                     // commit the entry
                     ctr += 1;
-                    counting_output.write_all(&write_buf)?;
+                    reply.extend_from_slice(&write_buf);
                     accumulated_dircount += added_dircount;
                     trace!(
                         "  -- lengths: {:?} / {:?} {:?} / {:?}",
                         accumulated_dircount,
                         max_dircount_bytes,
-                        counting_output.bytes_written(),
+                        reply.len(),
                         max_bytes_allowed
                     );
                 } else {
@@ -206,16 +275,24 @@ pub async fn nfsproc3_readdirplus(
                     break;
                 }
             }
+            if ctr == 0 && !all_entries_written {
+                debug!(" -- no entry fits in dircount {} maxcount {}", dircount, maxcount);
+                xdr::rpc::make_success_reply(xid).serialize(output)?;
+                nfs3::nfsstat3::NFS3ERR_TOOSMALL.serialize(output)?;
+                dir_attr.serialize(output)?;
+                return Ok(());
+            }
             // false flag for the final entryplus* linked list
-            false.serialize(&mut counting_output)?;
+            false.serialize(&mut reply)?;
             // eof flag is only valid here if we wrote everything
             if all_entries_written {
                 debug!("  -- readdir eof {:?}", result.end);
-                result.end.serialize(&mut counting_output)?;
+                result.end.serialize(&mut reply)?;
             } else {
                 debug!("  -- readdir eof {:?}", false);
-                false.serialize(&mut counting_output)?;
+                false.serialize(&mut reply)?;
             }
+            output.write_all(&reply)?;
             debug!(
                 "readir {}, has_version {},  start at {}, flushing {} entries, complete {}",
                 dirid, has_version, args.cookie, ctr, all_entries_written
@@ -17,6 +17,9 @@
 //! use the RMDIR procedure instead. In this implementation, the `REMOVE` procedure performs
 //! additional checks before removing directory entries to ensure file system consistency.
 //!
+//! With [`super::SoftDelete`] enabled, files that saw I/O recently are renamed
+//! to a hidden name instead of being removed, and removed once idle.
+//!
 //! Common errors include:
 //! - `NFS3ERR_ROFS` - If the file system is read-only
 //! - `NFS3ERR_NOENT` - If the target file doesn't exist
@@ -27,6 +30,7 @@ use std::io::{Read, Write};
 
 use tracing::{debug, error, warn};
 
+use crate::protocol::nfs::events::EventKind;
 use crate::protocol::rpc;
 use crate::protocol::xdr::{self, deserialize, nfs3, Serialize};
 use crate::vfs;
@@ -46,7 +50,7 @@ use crate::vfs;
 ///
 /// # Returns
 ///
-/// * `Result<(), anyhow::Error>` - Ok(()) on success or an error
+/// * `Result<(), rpc::ServerError>` - Ok(()) on success or an error
 ///
 /// # Errors
 ///
@@ -62,9 +66,9 @@ pub async fn nfsproc3_remove(
     input: &mut impl Read,
     output: &mut impl Write,
     context: &rpc::Context,
-) -> Result<(), anyhow::Error> {
+) -> Result<(), rpc::ServerError> {
     // if we do not have write capabilities
-    if !matches!(context.vfs.capabilities(), vfs::Capabilities::ReadWrite) {
+    if !context.vfs.capabilities().contains(vfs::Capabilities::WRITE) {
         warn!("No write capabilities.");
         xdr::rpc::make_success_reply(xid).serialize(output)?;
         nfs3::nfsstat3::NFS3ERR_ROFS.serialize(output)?;
@@ -77,7 +81,7 @@ pub async fn nfsproc3_remove(
     debug!("nfsproc3_remove({:?}, {:?}) ", xid, dirops);
 
     // find the directory with the file
-    let dirid = context.vfs.fh_to_id(&dirops.dir);
+    let dirid = context.fh_to_id(&dirops.dir);
     if let Err(stat) = dirid {
         // directory does not exist
         xdr::rpc::make_success_reply(xid).serialize(output)?;
@@ -88,6 +92,16 @@ pub async fn nfsproc3_remove(
     }
     let dirid = dirid.unwrap();
 
+    // files hidden earlier are removed once idle, before the directory lock is
+    // taken as they may be in this directory
+    if let Some(soft_delete) = &context.soft_delete {
+        let counter = context.space_counter.as_deref();
+        soft_delete.purge(context.vfs.as_ref(), counter, context.clock.now()).await;
+    }
+
+    // changes of the directory run one at a time if configured
+    let _dir_guard = super::lock_dirs(context, dirid, None).await;
+
     // get the object attributes before the write
     let pre_dir_attr = match context.vfs.getattr(dirid).await {
         Ok(v) => {
@@ -103,8 +117,51 @@ pub async fn nfsproc3_remove(
         }
     };
 
-    // delete!
-    let res = context.vfs.remove(dirid, &dirops.name).await;
+    // coalesced data must reach the file while it still exists
+    super::flush_coalesced_name(context, dirid, &dirops.name).await;
+
+    // the size of the object is only known before it is gone
+    let removed_attr = match (
+        &context.space_counter,
+        &context.soft_delete,
+        &context.checksums,
+        context.unstable_writes.is_empty(),
+    ) {
+        (None, None, None, true) => None,
+        _ => match context.vfs.lookup(dirid, &dirops.name).await {
+            Ok(id) => context.vfs.getattr(id).await.ok(),
+            Err(_) => None,
+        },
+    };
+
+    // files in use are hidden rather than deleted, and keep their space until
+    // they are purged
+    let hide = match (&context.soft_delete, &removed_attr) {
+        (Some(soft_delete), Some(attr)) => {
+            !matches!(attr.ftype, nfs3::ftype3::NF3DIR)
+                && soft_delete.in_use(attr.fileid, context.clock.now())
+        }
+        _ => false,
+    };
+    let res = match (&context.soft_delete, &removed_attr) {
+        (Some(soft_delete), Some(attr)) if hide => {
+            soft_delete.hide(context.vfs.as_ref(), dirid, &dirops.name, attr.fileid).await
+        }
+        _ => context.vfs.remove(dirid, &dirops.name).await,
+    };
+    let res = match (res, &context.soft_delete, &removed_attr) {
+        // a directory holding only hidden files looks empty to the client
+        (Err(nfs3::nfsstat3::NFS3ERR_NOTEMPTY), Some(soft_delete), Some(attr))
+            if matches!(attr.ftype, nfs3::ftype3::NF3DIR) =>
+        {
+            let counter = context.space_counter.as_deref();
+            match soft_delete.purge_dir(context.vfs.as_ref(), counter, attr.fileid).await {
+                Ok(true) => context.vfs.remove(dirid, &dirops.name).await,
+                _ => Err(nfs3::nfsstat3::NFS3ERR_NOTEMPTY),
+            }
+        }
+        (res, _, _) => res,
+    };
 
     // Re-read dir attributes for post op attr
     let post_dir_attr = context.vfs.getattr(dirid).await.ok();
@@ -113,6 +170,27 @@ pub async fn nfsproc3_remove(
     match res {
         Ok(()) => {
             debug!("remove success");
+            context.publish(EventKind::Removed { dirid, name: dirops.name.0.clone() });
+            if let (Some(checksums), Some(attr)) = (&context.checksums, &removed_attr) {
+                if attr.nlink <= 1 {
+                    checksums.forget(attr.fileid);
+                }
+            }
+            if let (Some(attr), false) = (&removed_attr, hide) {
+                if attr.nlink <= 1 {
+                    context.unstable_writes.forget(attr.fileid);
+                }
+            }
+            if let (Some(counter), Some(attr), false) = (&context.space_counter, removed_attr, hide)
+            {
+                // the data stays while other hard links remain
+                let freed = match attr.ftype {
+                    nfs3::ftype3::NF3DIR => attr.size,
+                    _ if attr.nlink <= 1 => attr.size,
+                    _ => 0,
+                };
+                counter.remove_file(freed);
+            }
             xdr::rpc::make_success_reply(xid).serialize(output)?;
             nfs3::nfsstat3::NFS3_OK.serialize(output)?;
             wcc_res.serialize(output)?;
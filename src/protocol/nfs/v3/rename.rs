@@ -28,6 +28,7 @@ use std::io::{Read, Write};
 
 use tracing::{debug, error, warn};
 
+use crate::protocol::nfs::events::EventKind;
 use crate::protocol::rpc;
 use crate::protocol::xdr::{self, deserialize, nfs3, Serialize};
 use crate::vfs;
@@ -51,15 +52,15 @@ use crate::vfs;
 ///
 /// # Returns
 ///
-/// * `Result<(), anyhow::Error>` - Ok(()) on success or an error
+/// * `Result<(), rpc::ServerError>` - Ok(()) on success or an error
 pub async fn nfsproc3_rename(
     xid: u32,
     input: &mut impl Read,
     output: &mut impl Write,
     context: &rpc::Context,
-) -> Result<(), anyhow::Error> {
+) -> Result<(), rpc::ServerError> {
     // if we do not have write capabilities
-    if !matches!(context.vfs.capabilities(), vfs::Capabilities::ReadWrite) {
+    if !context.vfs.capabilities().contains(vfs::Capabilities::WRITE) {
         warn!("No write capabilities.");
         xdr::rpc::make_success_reply(xid).serialize(output)?;
         nfs3::nfsstat3::NFS3ERR_ROFS.serialize(output)?;
@@ -73,7 +74,7 @@ pub async fn nfsproc3_rename(
     debug!("nfsproc3_rename({:?}, {:?}, {:?}) ", xid, fromdirops, todirops);
 
     // find the from directory
-    let from_dirid = context.vfs.fh_to_id(&fromdirops.dir);
+    let from_dirid = context.fh_to_id(&fromdirops.dir);
     if let Err(stat) = from_dirid {
         // directory does not exist
         xdr::rpc::make_success_reply(xid).serialize(output)?;
@@ -84,7 +85,7 @@ pub async fn nfsproc3_rename(
     }
 
     // find the to directory
-    let to_dirid = context.vfs.fh_to_id(&todirops.dir);
+    let to_dirid = context.fh_to_id(&todirops.dir);
     if let Err(stat) = to_dirid {
         // directory does not exist
         xdr::rpc::make_success_reply(xid).serialize(output)?;
@@ -98,6 +99,9 @@ pub async fn nfsproc3_rename(
     let from_dirid = from_dirid.unwrap();
     let to_dirid = to_dirid.unwrap();
 
+    // changes of the directories run one at a time if configured
+    let _dir_guard = super::lock_dirs(context, from_dirid, Some(to_dirid)).await;
+
     // get the object attributes before the write
     let pre_from_dir_attr = match context.vfs.getattr(from_dirid).await {
         Ok(v) => {
@@ -129,7 +133,21 @@ pub async fn nfsproc3_rename(
     };
 
     // rename!
-    let res = context.vfs.rename(from_dirid, &fromdirops.name, to_dirid, &todirops.name).await;
+    // moving within a directory adds no entry to it
+    let limited = if from_dirid == to_dirid {
+        Ok(())
+    } else {
+        super::check_new_entry(context, to_dirid, &todirops.name, false).await
+    };
+    let res = match limited {
+        Ok(()) => {
+            // a file renamed over loses its name, and maybe its file ID
+            super::flush_coalesced_name(context, to_dirid, &todirops.name).await;
+            context.vfs.rename(from_dirid, &fromdirops.name, to_dirid, &todirops.name).await
+        }
+        Err(stat) => Err(stat),
+    };
+    super::name_added(context, to_dirid, &todirops.name);
 
     // Re-read dir attributes for post op attr
     let post_from_dir_attr = context.vfs.getattr(from_dirid).await.ok();
@@ -141,6 +159,12 @@ pub async fn nfsproc3_rename(
     match res {
         Ok(()) => {
             debug!("rename success");
+            context.publish(EventKind::Renamed {
+                from_dirid,
+                from_name: fromdirops.name.0.clone(),
+                to_dirid,
+                to_name: todirops.name.0.clone(),
+            });
             xdr::rpc::make_success_reply(xid).serialize(output)?;
             nfs3::nfsstat3::NFS3_OK.serialize(output)?;
             from_wcc_res.serialize(output)?;
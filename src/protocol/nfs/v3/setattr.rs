@@ -43,24 +43,33 @@ use crate::vfs;
 ///
 /// # Returns
 ///
-/// * `Result<(), anyhow::Error>` - Ok(()) on success or an error
+/// * `Result<(), rpc::ServerError>` - Ok(()) on success or an error
 pub async fn nfsproc3_setattr(
     xid: u32,
     input: &mut impl Read,
     output: &mut impl Write,
     context: &rpc::Context,
-) -> Result<(), anyhow::Error> {
-    if !matches!(context.vfs.capabilities(), vfs::Capabilities::ReadWrite) {
+) -> Result<(), rpc::ServerError> {
+    if !context.vfs.capabilities().contains(vfs::Capabilities::WRITE) {
         warn!("No write capabilities.");
         xdr::rpc::make_success_reply(xid).serialize(output)?;
         nfs3::nfsstat3::NFS3ERR_ROFS.serialize(output)?;
         nfs3::wcc_data::default().serialize(output)?;
         return Ok(());
     }
-    let args = deserialize::<nfs3::SETATTR3args>(input)?;
+    let mut args = deserialize::<nfs3::SETATTR3args>(input)?;
     debug!("nfsproc3_setattr({:?},{:?}) ", xid, args);
+    let sets_time = !matches!(args.new_attribute.atime, nfs3::set_atime::DONT_CHANGE)
+        || !matches!(args.new_attribute.mtime, nfs3::set_mtime::DONT_CHANGE);
+    if sets_time && !context.vfs.capabilities().contains(vfs::Capabilities::SET_TIME) {
+        warn!("No time setting capability.");
+        xdr::rpc::make_success_reply(xid).serialize(output)?;
+        nfs3::nfsstat3::NFS3ERR_NOTSUPP.serialize(output)?;
+        nfs3::wcc_data::default().serialize(output)?;
+        return Ok(());
+    }
 
-    let id = context.vfs.fh_to_id(&args.object);
+    let id = context.fh_to_id(&args.object);
     // fail if unable to convert file handle
     if let Err(stat) = id {
         xdr::rpc::make_success_reply(xid).serialize(output)?;
@@ -68,38 +77,57 @@ pub async fn nfsproc3_setattr(
         return Ok(());
     }
     let id = id.unwrap();
+    super::flush_coalesced(context, id).await;
 
-    let ctime;
-
-    let pre_op_attr = match context.vfs.getattr(id).await {
-        Ok(v) => {
-            let wccattr = nfs3::wcc_attr { size: v.size, mtime: v.mtime, ctime: v.ctime };
-            ctime = v.ctime;
-            nfs3::pre_op_attr::Some(wccattr)
+    // backends reporting the attributes before the change spare the lookup,
+    // unless the guard needs the current ctime
+    let mut pre_op_attr = nfs3::pre_op_attr::None;
+    if args.guard.is_some() || !context.vfs.reports_pre_op_attrs() {
+        match context.vfs.getattr(id).await {
+            Ok(v) => {
+                let wccattr = nfs3::wcc_attr { size: v.size, mtime: v.mtime, ctime: v.ctime };
+                pre_op_attr = nfs3::pre_op_attr::Some(wccattr);
+            }
+            Err(stat) => {
+                xdr::rpc::make_success_reply(xid).serialize(output)?;
+                stat.serialize(output)?;
+                nfs3::wcc_data::default().serialize(output)?;
+                return Ok(());
+            }
         }
-        Err(stat) => {
-            xdr::rpc::make_success_reply(xid).serialize(output)?;
-            stat.serialize(output)?;
-            nfs3::wcc_data::default().serialize(output)?;
-            return Ok(());
-        }
-    };
+    }
     // handle the guard
-    if let nfs3::sattrguard3::Some(c) = args.guard {
-        if c.seconds != ctime.seconds || c.nseconds != ctime.nseconds {
+    if let (nfs3::sattrguard3::Some(c), Some(pre)) = (args.guard, &pre_op_attr) {
+        if c.seconds != pre.ctime.seconds || c.nseconds != pre.ctime.nseconds {
             xdr::rpc::make_success_reply(xid).serialize(output)?;
             nfs3::nfsstat3::NFS3ERR_NOT_SYNC.serialize(output)?;
-            nfs3::wcc_data::default().serialize(output)?;
+            nfs3::wcc_data { before: pre_op_attr, after: None }.serialize(output)?;
+            return Ok(());
         }
     }
 
-    match context.vfs.setattr(id, args.new_attribute).await {
-        Ok(post_op_attr) => {
+    if let Some(Err(stat)) =
+        args.new_attribute.size.map(|size| context.export_limits.check_size(size))
+    {
+        xdr::rpc::make_success_reply(xid).serialize(output)?;
+        stat.serialize(output)?;
+        nfs3::wcc_data { before: pre_op_attr, after: None }.serialize(output)?;
+        return Ok(());
+    }
+
+    super::resolve_server_time(context, &mut args.new_attribute);
+    let res = context.vfs.setattr_wcc(id, args.new_attribute).await;
+    super::data_modified(context, id);
+    match res {
+        Ok((reported, post_op_attr)) => {
+            let pre_op_attr = reported.or(pre_op_attr);
             debug!(" setattr success {:?} --> {:?}", xid, post_op_attr);
-            let wcc_res = nfs3::wcc_data {
-                before: pre_op_attr,
-                after: nfs3::post_op_attr::Some(post_op_attr),
-            };
+            if let Some(checksums) = &context.checksums {
+                checksums.truncate(id, post_op_attr.size);
+            }
+            let after = nfs3::post_op_attr::Some(post_op_attr);
+            super::account_resize(context, &pre_op_attr, &after);
+            let wcc_res = nfs3::wcc_data { before: pre_op_attr, after };
             xdr::rpc::make_success_reply(xid).serialize(output)?;
             nfs3::nfsstat3::NFS3_OK.serialize(output)?;
             wcc_res.serialize(output)?;
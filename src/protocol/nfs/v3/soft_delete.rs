@@ -0,0 +1,251 @@
+//! Deferred removal of files that are still in use.
+//!
+//! Local file systems keep the data of a removed file until its last open
+//! descriptor is closed, and applications rely on it: they create a temporary
+//! file, remove it right away and keep using it. NFSv3 has no notion of open
+//! files. Clients emulate the behavior by renaming files they hold open to
+//! `.nfsXXXX` names instead of removing them ("silly rename"), but only for
+//! files opened on the same client, and not at all after a client restart.
+//!
+//! [`SoftDelete`] adds the server side of it. A `REMOVE` of a file that saw
+//! `READ`, `WRITE` or `COMMIT` calls within the configured idle time renames
+//! the file to a hidden name in the same directory instead, so handles held by
+//! any client keep working. Hidden files are left out of listings and lookups,
+//! and are removed for good once they have been idle for that long, checked
+//! whenever a `REMOVE` is served or [`SoftDelete::purge`] is called. A
+//! directory holding nothing but hidden files looks empty to clients, so
+//! `RMDIR` removes them right away, see [`SoftDelete::purge_dir`]. The space of
+//! a hidden file is accounted as freed only once it is removed for good.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use tracing::{debug, warn};
+
+use crate::protocol::xdr::nfs3;
+use crate::vfs::{NFSFileSystem, ReadDirCookie};
+use crate::write_counter::SpaceCounter;
+
+/// Prefix of the names files are hidden under
+const HIDDEN_PREFIX: &[u8] = b".nfs-mamont-deleted.";
+
+/// Most entries [`SoftDelete::purge_dir`] lists in a directory
+const MAX_PURGED_ENTRIES: usize = 1024;
+
+/// Settings of [`SoftDelete`]
+#[derive(Clone, Copy, Debug)]
+pub struct SoftDeleteConfig {
+    /// Time since the last I/O during which a file counts as in use
+    pub idle: Duration,
+    /// Number of files whose last I/O is tracked; files idle for longer than
+    /// `idle` are forgotten first beyond it
+    pub max_tracked: usize,
+}
+
+impl Default for SoftDeleteConfig {
+    fn default() -> Self {
+        Self { idle: Duration::from_secs(60), max_tracked: 4096 }
+    }
+}
+
+/// File hidden instead of removed
+#[derive(Clone, Debug)]
+struct Hidden {
+    /// Directory containing the file
+    dirid: nfs3::fileid3,
+    /// Name the file is hidden under
+    name: nfs3::filename3,
+    /// The file
+    fileid: nfs3::fileid3,
+}
+
+/// Tracks I/O per file and defers the removal of files in use
+#[derive(Debug, Default)]
+pub struct SoftDelete {
+    config: SoftDeleteConfig,
+    /// Time of the last I/O per file
+    last_io: Mutex<HashMap<nfs3::fileid3, SystemTime>>,
+    /// Files hidden and not purged yet
+    hidden: Mutex<Vec<Hidden>>,
+    /// Counter making hidden names unique
+    sequence: AtomicU64,
+}
+
+impl SoftDelete {
+    /// Creates a tracker with the given settings
+    pub fn new(config: SoftDeleteConfig) -> Self {
+        Self { config, ..Self::default() }
+    }
+
+    /// Returns whether a name is one files are hidden under
+    pub fn is_hidden_name(name: &[u8]) -> bool {
+        name.starts_with(HIDDEN_PREFIX)
+    }
+
+    /// Records I/O on a file at `now`
+    pub fn record_io(&self, fileid: nfs3::fileid3, now: SystemTime) {
+        let mut last_io = self.last_io.lock().unwrap();
+        if last_io.len() >= self.config.max_tracked && !last_io.contains_key(&fileid) {
+            let idle = self.config.idle;
+            last_io.retain(|_, at| now.duration_since(*at).is_ok_and(|elapsed| elapsed < idle));
+        }
+        if last_io.len() < self.config.max_tracked {
+            last_io.insert(fileid, now);
+        }
+    }
+
+    /// Returns whether a file saw I/O within the idle time before `now`
+    pub fn in_use(&self, fileid: nfs3::fileid3, now: SystemTime) -> bool {
+        self.last_io.lock().unwrap().get(&fileid).is_some_and(|at| {
+            !now.duration_since(*at).is_ok_and(|elapsed| elapsed >= self.config.idle)
+        })
+    }
+
+    /// Returns the number of files hidden and not purged yet
+    pub fn hidden(&self) -> usize {
+        self.hidden.lock().unwrap().len()
+    }
+
+    /// Hides the entry `name` of a directory instead of removing it
+    ///
+    /// # Arguments
+    ///
+    /// * `vfs` - File system containing the entry
+    /// * `dirid` - Directory containing the entry
+    /// * `name` - Name of the entry
+    /// * `fileid` - File the entry refers to
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), nfsstat3>` - The result of renaming the entry
+    pub async fn hide(
+        &self,
+        vfs: &(dyn NFSFileSystem + Send + Sync),
+        dirid: nfs3::fileid3,
+        name: &nfs3::filename3,
+        fileid: nfs3::fileid3,
+    ) -> Result<(), nfs3::nfsstat3> {
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        let mut hidden_name = HIDDEN_PREFIX.to_vec();
+        hidden_name.extend_from_slice(format!("{fileid:x}.{sequence}").as_bytes());
+        let hidden_name = nfs3::filename3::from(hidden_name);
+        vfs.rename(dirid, name, dirid, &hidden_name).await?;
+        debug!("hid {:?} of {} as {:?}", name, dirid, hidden_name);
+        self.hidden.lock().unwrap().push(Hidden { dirid, name: hidden_name, fileid });
+        Ok(())
+    }
+
+    /// Removes the hidden files that have been idle for the idle time
+    ///
+    /// Files that cannot be removed are dropped from the list with a warning,
+    /// so they are not retried forever. The space of removed files is recorded
+    /// in `counter`, if given.
+    pub async fn purge(
+        &self,
+        vfs: &(dyn NFSFileSystem + Send + Sync),
+        counter: Option<&SpaceCounter>,
+        now: SystemTime,
+    ) {
+        let expired: Vec<Hidden> = {
+            let mut hidden = self.hidden.lock().unwrap();
+            let (expired, kept) =
+                hidden.drain(..).partition(|hidden| !self.in_use(hidden.fileid, now));
+            *hidden = kept;
+            expired
+        };
+        for hidden in expired {
+            self.last_io.lock().unwrap().remove(&hidden.fileid);
+            match remove_hidden(vfs, counter, hidden.dirid, &hidden.name, hidden.fileid).await {
+                Ok(()) => debug!("purged hidden {:?} of {}", hidden.name, hidden.dirid),
+                Err(stat) => warn!("cannot purge hidden {:?}: {:?}", hidden.name, stat),
+            }
+        }
+    }
+
+    /// Removes the hidden files of directory `dirid` if it holds nothing else
+    ///
+    /// Called when a directory is not removed as it is not empty. Its hidden
+    /// files are removed even if they are in use, as clients are removing the
+    /// directory they are in. Files hidden before the server restarted are
+    /// found too. The space of removed files is recorded in `counter`, if given.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, nfsstat3>` - Whether the directory held only hidden
+    ///   files, which are now removed; `false` if it holds others or too many
+    ///   to list at once
+    pub async fn purge_dir(
+        &self,
+        vfs: &(dyn NFSFileSystem + Send + Sync),
+        counter: Option<&SpaceCounter>,
+        dirid: nfs3::fileid3,
+    ) -> Result<bool, nfs3::nfsstat3> {
+        let listed =
+            vfs.readdir_with_cookie(dirid, ReadDirCookie::Start, MAX_PURGED_ENTRIES).await?;
+        let entries: Vec<_> = listed
+            .entries
+            .into_iter()
+            .filter(|entry| !matches!(&entry.name[..], b"." | b".."))
+            .collect();
+        if !listed.end || entries.iter().any(|entry| !Self::is_hidden_name(&entry.name)) {
+            return Ok(false);
+        }
+        for entry in &entries {
+            remove_hidden(vfs, counter, dirid, &entry.name, entry.fileid).await?;
+            debug!("purged hidden {:?} of removed directory {}", entry.name, dirid);
+        }
+        self.hidden.lock().unwrap().retain(|hidden| hidden.dirid != dirid);
+        let mut last_io = self.last_io.lock().unwrap();
+        for entry in &entries {
+            last_io.remove(&entry.fileid);
+        }
+        Ok(true)
+    }
+}
+
+/// Removes a hidden file, recording the space it frees in `counter`, if given
+async fn remove_hidden(
+    vfs: &(dyn NFSFileSystem + Send + Sync),
+    counter: Option<&SpaceCounter>,
+    dirid: nfs3::fileid3,
+    name: &nfs3::filename3,
+    fileid: nfs3::fileid3,
+) -> Result<(), nfs3::nfsstat3> {
+    // the size is only known before the file is gone
+    let attr = match counter {
+        Some(_) => vfs.getattr(fileid).await.ok(),
+        None => None,
+    };
+    vfs.remove(dirid, name).await?;
+    if let (Some(counter), Some(attr)) = (counter, attr) {
+        // the data stays while other hard links remain
+        counter.remove_file(if attr.nlink <= 1 { attr.size } else { 0 });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use super::*;
+
+    #[test]
+    fn files_with_recent_io_are_in_use() {
+        let config = SoftDeleteConfig { idle: Duration::from_secs(10), max_tracked: 2 };
+        let soft_delete = SoftDelete::new(config);
+        let start = UNIX_EPOCH + Duration::from_secs(1000);
+        soft_delete.record_io(1, start);
+        assert!(soft_delete.in_use(1, start + Duration::from_secs(9)));
+        assert!(!soft_delete.in_use(1, start + Duration::from_secs(10)));
+        assert!(!soft_delete.in_use(2, start));
+
+        // idle files make room for new ones
+        soft_delete.record_io(2, start);
+        soft_delete.record_io(3, start + Duration::from_secs(20));
+        assert!(soft_delete.in_use(3, start + Duration::from_secs(20)));
+        assert!(SoftDelete::is_hidden_name(b".nfs-mamont-deleted.3.0"));
+    }
+}
@@ -30,6 +30,7 @@ use std::io::{Read, Write};
 
 use tracing::{debug, error, warn};
 
+use crate::protocol::nfs::events::EventKind;
 use crate::protocol::rpc;
 use crate::protocol::xdr::{self, deserialize, nfs3, Serialize};
 use crate::vfs;
@@ -49,7 +50,7 @@ use crate::vfs;
 ///
 /// # Returns
 ///
-/// * `Result<(), anyhow::Error>` - Ok(()) on success or an error
+/// * `Result<(), rpc::ServerError>` - Ok(()) on success or an error
 ///
 /// # Errors
 ///
@@ -65,22 +66,29 @@ pub async fn nfsproc3_symlink(
     input: &mut impl Read,
     output: &mut impl Write,
     context: &rpc::Context,
-) -> Result<(), anyhow::Error> {
+) -> Result<(), rpc::ServerError> {
     // if we do not have write capabilities
-    if !matches!(context.vfs.capabilities(), vfs::Capabilities::ReadWrite) {
+    if !context.vfs.capabilities().contains(vfs::Capabilities::WRITE) {
         warn!("No write capabilities.");
         xdr::rpc::make_success_reply(xid).serialize(output)?;
         nfs3::nfsstat3::NFS3ERR_ROFS.serialize(output)?;
         nfs3::wcc_data::default().serialize(output)?;
         return Ok(());
     }
+    if !context.vfs.capabilities().contains(vfs::Capabilities::SYMLINKS) {
+        warn!("No symbolic link capability.");
+        xdr::rpc::make_success_reply(xid).serialize(output)?;
+        nfs3::nfsstat3::NFS3ERR_NOTSUPP.serialize(output)?;
+        nfs3::wcc_data::default().serialize(output)?;
+        return Ok(());
+    }
     let args = deserialize::<nfs3::dir::SYMLINK3args>(input)?;
 
     debug!("nfsproc3_symlink({:?}, {:?}) ", xid, args);
 
     // find the directory we are supposed to create the
     // new file in
-    let dirid = context.vfs.fh_to_id(&args.dirops.dir);
+    let dirid = context.fh_to_id(&args.dirops.dir);
     if let Err(stat) = dirid {
         // directory does not exist
         xdr::rpc::make_success_reply(xid).serialize(output)?;
@@ -92,6 +100,9 @@ pub async fn nfsproc3_symlink(
     // found the directory, get the attributes
     let dirid = dirid.unwrap();
 
+    // changes of the directory run one at a time if configured
+    let _dir_guard = super::lock_dirs(context, dirid, None).await;
+
     // get the object attributes before the write
     let pre_dir_attr = match context.vfs.getattr(dirid).await {
         Ok(v) => {
@@ -107,15 +118,15 @@ pub async fn nfsproc3_symlink(
         }
     };
 
-    let res = context
-        .vfs
-        .symlink(
-            dirid,
-            &args.dirops.name,
-            &args.symlink.symlink_data,
-            &args.symlink.symlink_attributes,
-        )
-        .await;
+    let mut attr = args.symlink.symlink_attributes;
+    super::apply_creation_defaults(context, &mut attr, nfs3::ftype3::NF3LNK);
+    let res = match super::check_new_entry(context, dirid, &args.dirops.name, true).await {
+        Ok(()) => {
+            context.vfs.symlink(dirid, &args.dirops.name, &args.symlink.symlink_data, &attr).await
+        }
+        Err(stat) => Err(stat),
+    };
+    super::name_added(context, dirid, &args.dirops.name);
 
     // Re-read dir attributes for post op attr
     let post_dir_attr = context.vfs.getattr(dirid).await.ok();
@@ -124,11 +135,15 @@ pub async fn nfsproc3_symlink(
     match res {
         Ok((fid, fattr)) => {
             debug!("symlink success --> {:?}, {:?}", fid, fattr);
+            super::account_created(context);
+            let name = args.dirops.name.0.clone();
+            context.publish(EventKind::Created { dirid, name, id: fid });
             xdr::rpc::make_success_reply(xid).serialize(output)?;
             nfs3::nfsstat3::NFS3_OK.serialize(output)?;
             // serialize CREATE3resok
-            let fh = context.vfs.id_to_fh(fid);
-            nfs3::post_op_fh3::Some(fh).serialize(output)?;
+            // the client looks the name up if the handle cannot be issued
+            let fh: nfs3::post_op_fh3 = context.id_to_fh(fid).ok();
+            fh.serialize(output)?;
             nfs3::post_op_attr::Some(fattr).serialize(output)?;
             wcc_res.serialize(output)?;
         }
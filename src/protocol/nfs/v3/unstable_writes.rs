@@ -0,0 +1,315 @@
+//! Tracking of `UNSTABLE` writes until they are committed.
+//!
+//! A reply to an `UNSTABLE` `WRITE` only promises that the data reached the
+//! server. The client keeps a copy until a `COMMIT` of the range succeeds with
+//! the same write verifier as the writes; if the verifier differs, it assumes
+//! the server lost the data and writes it again (RFC 1813 section 3.3.21).
+//!
+//! [`UnstableWrites`] records the ranges written `UNSTABLE` per file and
+//! forgets them once [`crate::vfs::NFSFileSystem::commit`] succeeded for them.
+//! When uncommitted data is lost, e.g. because a deferred write of the
+//! [`super::WriteCoalescer`] or the commit itself failed, it moves to a new
+//! verifier, so clients resend everything they have not seen committed.
+//!
+//! Ranges of removed files are forgotten. The number of tracked ranges is
+//! capped, and the tracker is a [`MemoryConsumer`]: files evicted beyond the
+//! cap or under pressure of a [`crate::memory_budget::MemoryBudget`] are
+//! treated as lost, which costs clients a resend but never loses data.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use tracing::warn;
+
+use crate::memory_budget::{MemoryCharge, MemoryConsumer};
+use crate::protocol::xdr::nfs3::{fileid3, writeverf3};
+
+/// Default number of uncommitted ranges tracked over all files
+const DEFAULT_CAPACITY: usize = 1 << 16;
+
+/// Returns the approximate memory of the tracked ranges of a file
+fn file_size(ranges: usize) -> usize {
+    match ranges {
+        0 => 0,
+        n => {
+            std::mem::size_of::<(fileid3, Vec<(u64, u64)>)>()
+                + n * std::mem::size_of::<(u64, u64)>()
+        }
+    }
+}
+
+/// Tracked ranges
+#[derive(Debug, Default)]
+struct State {
+    /// Sorted, disjoint `(start, end)` ranges per file
+    files: HashMap<fileid3, Vec<(u64, u64)>>,
+    /// Number of ranges over all files
+    len: usize,
+}
+
+/// Uncommitted ranges of every file, and the write verifier they were written
+/// under
+#[derive(Debug)]
+pub struct UnstableWrites {
+    /// Number of losses of uncommitted data, mixed into the verifier
+    epoch: AtomicU64,
+    /// Number of ranges tracked before files are evicted
+    capacity: usize,
+    state: Mutex<State>,
+    memory: MemoryCharge,
+}
+
+impl Default for UnstableWrites {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+}
+
+impl UnstableWrites {
+    /// Creates a tracker without uncommitted data
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a tracker that keeps at most `capacity` ranges over all files
+    ///
+    /// Beyond it, the uncommitted data of other files is treated as lost.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            epoch: AtomicU64::new(0),
+            capacity: capacity.max(1),
+            state: Mutex::default(),
+            memory: MemoryCharge::default(),
+        }
+    }
+
+    /// Returns the write verifier to send to clients
+    ///
+    /// # Arguments
+    ///
+    /// * `server_id` - Verifier of the file system, see
+    ///   [`crate::vfs::NFSFileSystem::server_id`]
+    pub fn verifier(&self, server_id: writeverf3) -> writeverf3 {
+        let epoch = self.epoch.load(Ordering::Acquire);
+        u64::from_le_bytes(server_id).wrapping_add(epoch).to_le_bytes()
+    }
+
+    /// Records `len` bytes at `offset` of a file as written `UNSTABLE`
+    pub fn record(&self, id: fileid3, offset: u64, len: u32) {
+        if len == 0 {
+            return;
+        }
+        let (start, end) = (offset, offset.saturating_add(u64::from(len)));
+        {
+            let mut state = self.state.lock().unwrap();
+            let file = state.files.entry(id).or_default();
+            let before = file.len();
+            // merge with every range that overlaps or touches the new one
+            let first = file.partition_point(|&(_, e)| e < start);
+            let last = file.partition_point(|&(s, _)| s <= end);
+            let merged = file[first..last]
+                .iter()
+                .fold((start, end), |(s, e), &(rs, re)| (s.min(rs), e.max(re)));
+            file.splice(first..last, [merged]);
+            let after = file.len();
+            self.resize(&mut state, before, after);
+            while state.len > self.capacity {
+                if self.evict(&mut state, Some(id)) == 0 {
+                    // a file alone beyond the capacity keeps one range
+                    // spanning all of its own, which over-reports but never
+                    // misses uncommitted data
+                    let file = state.files.get_mut(&id).unwrap();
+                    let (before, span) = (file.len(), (file[0].0, file[file.len() - 1].1));
+                    *file = vec![span];
+                    self.resize(&mut state, before, 1);
+                    break;
+                }
+            }
+        }
+        self.memory.reclaim();
+    }
+
+    /// Marks `count` bytes at `offset` of a file committed, as after a
+    /// successful [`crate::vfs::NFSFileSystem::commit`]
+    ///
+    /// A `count` of 0 commits everything from `offset` to the end of the file.
+    pub fn commit(&self, id: fileid3, offset: u64, count: u32) {
+        let end = match count {
+            0 => u64::MAX,
+            count => offset.saturating_add(u64::from(count)),
+        };
+        let mut state = self.state.lock().unwrap();
+        let Some(file) = state.files.get_mut(&id) else {
+            return;
+        };
+        let before = file.len();
+        let mut kept = Vec::with_capacity(file.len() + 1);
+        for &(s, e) in file.iter() {
+            if e <= offset || s >= end {
+                kept.push((s, e));
+                continue;
+            }
+            if s < offset {
+                kept.push((s, offset));
+            }
+            if e > end {
+                kept.push((end, e));
+            }
+        }
+        let after = kept.len();
+        if kept.is_empty() {
+            state.files.remove(&id);
+        } else {
+            *file = kept;
+        }
+        self.resize(&mut state, before, after);
+    }
+
+    /// Returns the uncommitted `(start, end)` ranges of a file, in order
+    pub fn uncommitted(&self, id: fileid3) -> Vec<(u64, u64)> {
+        self.state.lock().unwrap().files.get(&id).cloned().unwrap_or_default()
+    }
+
+    /// Returns whether no file has uncommitted data
+    pub fn is_empty(&self) -> bool {
+        self.state.lock().unwrap().files.is_empty()
+    }
+
+    /// Forgets the uncommitted data of a removed file
+    ///
+    /// The verifier stays the same: the data is gone with the file, so there
+    /// is nothing for clients to write again.
+    pub fn forget(&self, id: fileid3) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(file) = state.files.remove(&id) {
+            self.resize(&mut state, file.len(), 0);
+        }
+    }
+
+    /// Records that the uncommitted data of a file may be lost and, if it had
+    /// any, changes the verifier
+    ///
+    /// Clients then write every uncommitted range again, of any file, as the
+    /// verifier is shared by the whole server.
+    pub fn lose(&self, id: fileid3) {
+        let mut state = self.state.lock().unwrap();
+        let Some(lost) = state.files.remove(&id) else {
+            return;
+        };
+        self.resize(&mut state, lost.len(), 0);
+        warn!("uncommitted data of {} lost: {:?}, changing the write verifier", id, lost);
+        self.epoch.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Accounts for a file going from `before` to `after` ranges
+    fn resize(&self, state: &mut State, before: usize, after: usize) {
+        state.len = state.len + after - before;
+        let (old, new) = (file_size(before), file_size(after));
+        if new > old {
+            self.memory.charge(new - old);
+        } else {
+            self.memory.release(old - new);
+        }
+    }
+
+    /// Drops the ranges of one file other than `keep` and changes the
+    /// verifier, as if its data was lost
+    ///
+    /// # Returns
+    ///
+    /// * `usize` - The memory freed, 0 if no other file had ranges
+    fn evict(&self, state: &mut State, keep: Option<fileid3>) -> usize {
+        let Some(&id) = state.files.keys().find(|&&id| Some(id) != keep) else {
+            return 0;
+        };
+        let evicted = state.files.remove(&id).unwrap_or_default();
+        self.resize(state, evicted.len(), 0);
+        warn!("evicting uncommitted ranges of {}, changing the write verifier", id);
+        self.epoch.fetch_add(1, Ordering::AcqRel);
+        file_size(evicted.len())
+    }
+}
+
+impl MemoryConsumer for UnstableWrites {
+    fn memory(&self) -> &MemoryCharge {
+        &self.memory
+    }
+
+    fn shrink(&self, bytes: usize) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let mut freed = 0;
+        while freed < bytes {
+            match self.evict(&mut state, None) {
+                0 => break,
+                n => freed += n,
+            }
+        }
+        freed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_ranges_until_committed() {
+        let writes = UnstableWrites::new();
+        writes.record(1, 0, 10);
+        writes.record(1, 20, 10);
+        writes.record(1, 10, 5);
+        assert_eq!(writes.uncommitted(1), [(0, 15), (20, 30)]);
+
+        writes.commit(1, 5, 20);
+        assert_eq!(writes.uncommitted(1), [(0, 5), (25, 30)]);
+        writes.commit(1, 0, 0);
+        assert!(writes.uncommitted(1).is_empty());
+
+        let verf = writes.verifier([1; 8]);
+        writes.lose(2);
+        assert_eq!(writes.verifier([1; 8]), verf);
+        writes.record(2, 0, 1);
+        writes.lose(2);
+        assert_ne!(writes.verifier([1; 8]), verf);
+        assert!(writes.uncommitted(2).is_empty());
+    }
+
+    #[test]
+    fn forgets_removed_files_without_changing_the_verifier() {
+        let writes = UnstableWrites::new();
+        writes.record(1, 0, 10);
+        writes.record(1, 20, 10);
+        let verf = writes.verifier([1; 8]);
+        writes.forget(1);
+        assert!(writes.is_empty());
+        assert_eq!(writes.verifier([1; 8]), verf);
+        assert_eq!(writes.memory().used(), 0);
+    }
+
+    #[test]
+    fn evicts_other_files_beyond_the_capacity() {
+        let writes = UnstableWrites::with_capacity(2);
+        writes.record(1, 0, 10);
+        writes.record(2, 0, 10);
+        let verf = writes.verifier([1; 8]);
+        writes.record(3, 0, 10);
+        assert_ne!(writes.verifier([1; 8]), verf);
+        assert_eq!(writes.uncommitted(3), [(0, 10)]);
+        assert_eq!(writes.state.lock().unwrap().len, 2);
+
+        // shrinking drops files and changes the verifier as well
+        let verf = writes.verifier([1; 8]);
+        assert!(writes.shrink(1) > 0);
+        assert_ne!(writes.verifier([1; 8]), verf);
+        writes.shrink(usize::MAX);
+        assert!(writes.is_empty());
+        assert_eq!(writes.memory().used(), 0);
+
+        // a file alone beyond the capacity is tracked as one range
+        writes.record(3, 0, 10);
+        writes.record(3, 20, 10);
+        writes.record(3, 40, 10);
+        assert_eq!(writes.uncommitted(3), [(0, 50)]);
+    }
+}
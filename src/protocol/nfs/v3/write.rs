@@ -20,6 +20,7 @@ use std::io::{Read, Write};
 
 use tracing::{debug, error, warn};
 
+use crate::protocol::nfs::events::EventKind;
 use crate::protocol::rpc;
 use crate::protocol::xdr::{self, deserialize, nfs3, Serialize};
 use crate::vfs;
@@ -39,15 +40,15 @@ use crate::vfs;
 ///
 /// # Returns
 ///
-/// * `Result<(), anyhow::Error>` - Ok(()) on success or an error
+/// * `Result<(), rpc::ServerError>` - Ok(()) on success or an error
 pub async fn nfsproc3_write(
     xid: u32,
     input: &mut impl Read,
     output: &mut impl Write,
     context: &rpc::Context,
-) -> Result<(), anyhow::Error> {
+) -> Result<(), rpc::ServerError> {
     // if we do not have write capabilities
-    if !matches!(context.vfs.capabilities(), vfs::Capabilities::ReadWrite) {
+    if !context.vfs.capabilities().contains(vfs::Capabilities::WRITE) {
         warn!("No write capabilities.");
         xdr::rpc::make_success_reply(xid).serialize(output)?;
         nfs3::nfsstat3::NFS3ERR_ROFS.serialize(output)?;
@@ -63,7 +64,7 @@ pub async fn nfsproc3_write(
         return Ok(());
     }
 
-    let id = context.vfs.fh_to_id(&args.file);
+    let id = context.fh_to_id(&args.file);
     if let Err(stat) = id {
         xdr::rpc::make_success_reply(xid).serialize(output)?;
         stat.serialize(output)?;
@@ -71,26 +72,152 @@ pub async fn nfsproc3_write(
         return Ok(());
     }
     let id = id.unwrap();
+    super::record_io(context, id);
 
-    // get the object attributes before the write
-    let pre_obj_attr = context
-        .vfs
-        .getattr(id)
-        .await
-        .map(|v| nfs3::wcc_attr { size: v.size, mtime: v.mtime, ctime: v.ctime })
-        .ok();
+    // get the object attributes before the write, unless the backend reports them
+    let pre_obj_attr = if context.vfs.reports_pre_op_attrs() {
+        None
+    } else {
+        context
+            .vfs
+            .getattr(id)
+            .await
+            .map(|v| nfs3::wcc_attr { size: v.size, mtime: v.mtime, ctime: v.ctime })
+            .ok()
+    };
 
-    match context.vfs.write(id, args.offset, &args.data).await {
-        Ok(fattr) => {
+    let end = args.offset.saturating_add(u64::from(args.count));
+    let mut checked = context.export_limits.check_size(end);
+    if let (Ok(()), Some(inspection)) = (&checked, &context.content_inspection) {
+        checked = inspection.check_write(id, args.offset, &args.data).await;
+    }
+    if let Err(stat) = checked {
+        xdr::rpc::make_success_reply(xid).serialize(output)?;
+        stat.serialize(output)?;
+        nfs3::wcc_data { before: pre_obj_attr, after: None }.serialize(output)?;
+        return Ok(());
+    }
+
+    if let Some(coalescer) = &context.write_coalescer {
+        if args.stable == nfs3::file::stable_how::UNSTABLE as u32 {
+            return write_coalesced(xid, id, args, pre_obj_attr, coalescer, output, context).await;
+        }
+        // stable writes must land after the data buffered before them
+        if let Err(stat) = coalescer.flush(context.vfs.as_ref(), id).await {
+            error!("write error {:?} --> {:?}", xid, stat);
+            xdr::rpc::make_success_reply(xid).serialize(output)?;
+            stat.serialize(output)?;
+            nfs3::wcc_data { before: pre_obj_attr, after: None }.serialize(output)?;
+            return Ok(());
+        }
+    }
+
+    let res = context.vfs.write_wcc(id, args.offset, &args.data).await;
+    super::data_modified(context, id);
+    match res {
+        Ok((reported, fattr, count)) => {
+            let pre_obj_attr = reported.or(pre_obj_attr);
             debug!("write success {:?} --> {:?}", xid, fattr);
+            let count = count.min(args.count);
+            if count < args.count {
+                debug!("short write {:?}: {} of {} bytes", xid, count, args.count);
+            }
+            if let Some(checksums) = &context.checksums {
+                checksums.record_write(id, args.offset, &args.data[..count as usize]);
+            }
+            context.publish(EventKind::Written { id, offset: args.offset, count });
+            context.stats.export(&context.export_name).record_write(u64::from(count));
+            // unstable data is only promised to be stable after a COMMIT
+            let committed = if args.stable == nfs3::file::stable_how::UNSTABLE as u32 {
+                context.unstable_writes.record(id, args.offset, count);
+                nfs3::file::stable_how::UNSTABLE
+            } else {
+                nfs3::file::stable_how::FILE_SYNC
+            };
+            let after = nfs3::post_op_attr::Some(fattr);
+            super::account_resize(context, &pre_obj_attr, &after);
+            let res = nfs3::file::WRITE3resok {
+                file_wcc: nfs3::wcc_data { before: pre_obj_attr, after },
+                count,
+                committed,
+                verf: context.unstable_writes.verifier(context.vfs.server_id()),
+            };
+            xdr::rpc::make_success_reply(xid).serialize(output)?;
+            nfs3::nfsstat3::NFS3_OK.serialize(output)?;
+            res.serialize(output)?;
+        }
+        Err(stat) => {
+            error!("write error {:?} --> {:?}", xid, stat);
+            // a failed write may still have changed the file
+            if let Some(checksums) = &context.checksums {
+                checksums.forget(id);
+            }
+            let after = context.vfs.getattr(id).await.ok();
+            super::account_resize(context, &pre_obj_attr, &after);
+            xdr::rpc::make_success_reply(xid).serialize(output)?;
+            stat.serialize(output)?;
+            nfs3::wcc_data { before: pre_obj_attr, after }.serialize(output)?;
+        }
+    }
+    Ok(())
+}
+
+/// Buffers an `UNSTABLE` write in the write coalescer
+///
+/// The reply promises only `UNSTABLE` stability. As the VFS has not seen the
+/// data yet, the post-operation attributes are the pre-operation ones with the
+/// size extended to cover the buffered data.
+///
+/// # Arguments
+///
+/// * `xid` - RPC transaction ID
+/// * `id` - File being written
+/// * `args` - Decoded `WRITE` arguments
+/// * `pre_obj_attr` - Attributes of the file before the write
+/// * `coalescer` - Coalescer buffering the data
+/// * `output` - Output stream for writing the response
+/// * `context` - Server context containing VFS
+///
+/// # Returns
+///
+/// * `Result<(), rpc::ServerError>` - Ok(()) on success or an error
+async fn write_coalesced(
+    xid: u32,
+    id: nfs3::fileid3,
+    args: nfs3::file::WRITE3args,
+    pre_obj_attr: nfs3::pre_op_attr,
+    coalescer: &super::WriteCoalescer,
+    output: &mut impl Write,
+    context: &rpc::Context,
+) -> Result<(), rpc::ServerError> {
+    let res = coalescer.write(context.vfs.as_ref(), id, args.offset, &args.data).await;
+    super::data_modified(context, id);
+    match res {
+        Ok((end, merged)) => {
+            if merged {
+                context.stats.write_coalescing.hit();
+            } else {
+                context.stats.write_coalescing.miss();
+            }
+            let after = match context.vfs.getattr(id).await {
+                Ok(mut attr) => {
+                    attr.size = attr.size.max(end);
+                    nfs3::post_op_attr::Some(attr)
+                }
+                Err(_) => nfs3::post_op_attr::None,
+            };
+            debug!("write buffered {:?} --> {:?}", xid, after);
+            if let Some(checksums) = &context.checksums {
+                checksums.record_write(id, args.offset, &args.data);
+            }
+            context.publish(EventKind::Written { id, offset: args.offset, count: args.count });
+            context.stats.export(&context.export_name).record_write(u64::from(args.count));
+            context.unstable_writes.record(id, args.offset, args.count);
             let res = nfs3::file::WRITE3resok {
-                file_wcc: nfs3::wcc_data {
-                    before: pre_obj_attr,
-                    after: nfs3::post_op_attr::Some(fattr),
-                },
+                file_wcc: nfs3::wcc_data { before: pre_obj_attr, after },
                 count: args.count,
-                committed: nfs3::file::stable_how::FILE_SYNC,
-                verf: context.vfs.server_id(),
+                committed: nfs3::file::stable_how::UNSTABLE,
+                verf: context.unstable_writes.verifier(context.vfs.server_id()),
             };
             xdr::rpc::make_success_reply(xid).serialize(output)?;
             nfs3::nfsstat3::NFS3_OK.serialize(output)?;
@@ -100,7 +227,7 @@ pub async fn nfsproc3_write(
             error!("write error {:?} --> {:?}", xid, stat);
             xdr::rpc::make_success_reply(xid).serialize(output)?;
             stat.serialize(output)?;
-            nfs3::wcc_data::default().serialize(output)?;
+            nfs3::wcc_data { before: pre_obj_attr, after: None }.serialize(output)?;
         }
     }
     Ok(())
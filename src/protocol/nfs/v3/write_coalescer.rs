@@ -0,0 +1,316 @@
+//! Coalescing of sequential `UNSTABLE` writes.
+//!
+//! Clients mounted with a small `wsize` split streaming writes into many
+//! `UNSTABLE` `WRITE` calls of e.g. 32KB each. Forwarding every one of them to
+//! the VFS multiplies backend round trips, which hurts backends where each write
+//! is expensive (object stores, network file systems, copy-on-write files).
+//!
+//! `UNSTABLE` data only has to reach stable storage by the time the client sends
+//! `COMMIT` (RFC 1813 section 3.3.7), so the server may hold it back. The
+//! [`WriteCoalescer`] keeps one contiguous pending range per file and merges
+//! adjacent or overlapping writes into it. The range is written to the VFS when:
+//!
+//! - a write arrives that does not touch the pending range,
+//! - the range grows beyond [`WriteCoalescerConfig::max_pending_bytes`],
+//! - the range is older than [`WriteCoalescerConfig::max_delay`] when another
+//!   write arrives,
+//! - the client commits the file,
+//! - a procedure reads the file's data or attributes: `READ`, `GETATTR`,
+//!   `SETATTR`, `ACCESS`, `LOOKUP`, `LINK` and `READDIRPLUS`,
+//! - a procedure removes or replaces a name of the file: `REMOVE`, `RENAME`
+//!   onto it, and `UNCHECKED` `CREATE` setting its size, so no data is written
+//!   to a file ID the backend has freed, or
+//! - the [`crate::memory_budget::MemoryBudget`] the coalescer is registered
+//!   with is exceeded.
+//!
+//! Errors of deferred writes are reported by the next `COMMIT` of the file, so
+//! the client retransmits the data as RFC 1813 requires.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::{debug, error};
+
+use crate::memory_budget::{MemoryCharge, MemoryConsumer};
+use crate::protocol::xdr::nfs3::{fileid3, nfsstat3};
+use crate::vfs::NFSFileSystem;
+
+/// Limits applied by the [`WriteCoalescer`]
+#[derive(Clone, Copy, Debug)]
+pub struct WriteCoalescerConfig {
+    /// Size at which a pending range is written out
+    pub max_pending_bytes: usize,
+    /// Age after which a pending range is written out
+    pub max_delay: Duration,
+}
+
+impl Default for WriteCoalescerConfig {
+    fn default() -> Self {
+        Self { max_pending_bytes: 1 << 20, max_delay: Duration::from_secs(1) }
+    }
+}
+
+/// Contiguous range of buffered data
+#[derive(Debug)]
+struct Pending {
+    /// File offset of the first buffered byte
+    offset: u64,
+    /// Buffered data
+    data: Vec<u8>,
+    /// When the range was started
+    since: Instant,
+}
+
+impl Pending {
+    /// Offset just past the last buffered byte
+    fn end(&self) -> u64 {
+        self.offset + self.data.len() as u64
+    }
+
+    /// Merges a write into the range if it overlaps or adjoins it
+    ///
+    /// Data of the new write takes precedence over buffered data.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - `false` if the write is disjoint and was not merged
+    fn merge(&mut self, offset: u64, data: &[u8]) -> bool {
+        let end = self.end();
+        let new_end = offset + data.len() as u64;
+        if offset > end || new_end < self.offset {
+            return false;
+        }
+        if offset <= self.offset {
+            let mut merged = data.to_vec();
+            if end > new_end {
+                merged.extend_from_slice(&self.data[(new_end - self.offset) as usize..]);
+            }
+            self.offset = offset;
+            self.data = merged;
+        } else {
+            let start = (offset - self.offset) as usize;
+            if new_end >= end {
+                self.data.truncate(start);
+                self.data.extend_from_slice(data);
+            } else {
+                self.data[start..start + data.len()].copy_from_slice(data);
+            }
+        }
+        true
+    }
+}
+
+/// Pending range of a single file, locked across the VFS write that flushes it
+type FileSlot = Arc<tokio::sync::Mutex<Option<Pending>>>;
+
+/// Merges `UNSTABLE` writes per file before handing them to the VFS
+///
+/// Writes to the same file are merged whichever connection they arrive on.
+#[derive(Debug, Default)]
+pub struct WriteCoalescer {
+    /// Limits for pending ranges
+    config: WriteCoalescerConfig,
+    /// Pending range of every file with buffered data
+    files: Mutex<HashMap<fileid3, FileSlot>>,
+    /// Failures of deferred writes, reported by the next `COMMIT`
+    errors: Mutex<HashMap<fileid3, nfsstat3>>,
+    /// Buffered data
+    memory: MemoryCharge,
+}
+
+impl WriteCoalescer {
+    /// Creates a coalescer with the given limits
+    pub fn new(config: WriteCoalescerConfig) -> Self {
+        Self { config, ..Default::default() }
+    }
+
+    /// Returns the slot of `id`, creating it if needed
+    fn slot(&self, id: fileid3) -> FileSlot {
+        self.files.lock().unwrap().entry(id).or_default().clone()
+    }
+
+    /// Writes out `pending` and records a failure for the next `COMMIT`
+    async fn write_out(
+        &self,
+        vfs: &(dyn NFSFileSystem + Send + Sync),
+        id: fileid3,
+        pending: Pending,
+    ) -> Result<(), nfsstat3> {
+        debug!("flushing {} coalesced bytes at {} of {}", pending.data.len(), pending.offset, id);
+        self.memory.release(pending.data.len());
+        if let Err(stat) = vfs.write(id, pending.offset, &pending.data).await {
+            error!("coalesced write of {} failed: {:?}", id, stat);
+            self.errors.lock().unwrap().insert(id, stat);
+            return Err(stat);
+        }
+        Ok(())
+    }
+
+    /// Buffers an `UNSTABLE` write
+    ///
+    /// # Arguments
+    ///
+    /// * `vfs` - File system the data is eventually written to
+    /// * `id` - File being written
+    /// * `offset` - Offset of the write
+    /// * `data` - Data of the write
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(u64, bool), nfsstat3>` - Offset just past the end of the buffered
+    ///   range, which the file size is at least, and whether the write was merged
+    ///   into an existing range; or the error of a range that had to be written out
+    pub async fn write(
+        &self,
+        vfs: &(dyn NFSFileSystem + Send + Sync),
+        id: fileid3,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(u64, bool), nfsstat3> {
+        self.flush_expired(vfs, id).await;
+
+        let slot = self.slot(id);
+        let mut pending = slot.lock().await;
+        let before = pending.as_ref().map_or(0, |current| current.data.len());
+        let merged = pending.as_mut().is_some_and(|current| current.merge(offset, data));
+        if merged {
+            // merged ranges only grow
+            self.memory.charge(pending.as_ref().unwrap().data.len() - before);
+        } else {
+            if let Some(previous) = pending.take() {
+                self.write_out(vfs, id, previous).await?;
+            }
+            *pending = Some(Pending { offset, data: data.to_vec(), since: Instant::now() });
+            self.memory.charge(data.len());
+        }
+        // caches make room first; buffered data cannot be dropped, so it is
+        // written out if that is not enough
+        self.memory.reclaim();
+        let current = pending.as_ref().unwrap();
+        let end = current.end();
+        if current.data.len() >= self.config.max_pending_bytes || self.memory.is_exceeded() {
+            let full = pending.take().unwrap();
+            self.write_out(vfs, id, full).await?;
+        }
+        Ok((end, merged))
+    }
+
+    /// Writes out buffered data of `id`, if any
+    ///
+    /// Must be called before the VFS is asked for the data or attributes of
+    /// the file, or before a stable write to it.
+    pub async fn flush(
+        &self,
+        vfs: &(dyn NFSFileSystem + Send + Sync),
+        id: fileid3,
+    ) -> Result<(), nfsstat3> {
+        let Some(slot) = self.files.lock().unwrap().get(&id).cloned() else {
+            return Ok(());
+        };
+        // keep the slot locked until the data reached the VFS
+        let mut pending = slot.lock().await;
+        match pending.take() {
+            Some(taken) => self.write_out(vfs, id, taken).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Returns whether data of `id` is buffered
+    ///
+    /// A range being written out at the moment counts as buffered.
+    pub fn is_pending(&self, id: fileid3) -> bool {
+        self.files
+            .lock()
+            .unwrap()
+            .get(&id)
+            .is_some_and(|slot| slot.try_lock().map_or(true, |pending| pending.is_some()))
+    }
+
+    /// Writes out buffered data of `id` and collects deferred write failures
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), nfsstat3>` - The first failure since the last commit, if any
+    pub async fn commit(
+        &self,
+        vfs: &(dyn NFSFileSystem + Send + Sync),
+        id: fileid3,
+    ) -> Result<(), nfsstat3> {
+        let flushed = self.flush(vfs, id).await;
+        match self.errors.lock().unwrap().remove(&id) {
+            Some(stat) => Err(stat),
+            None => flushed,
+        }
+    }
+
+    /// Writes out ranges of files other than `current` that exceeded the
+    /// maximum delay and forgets idle files
+    async fn flush_expired(&self, vfs: &(dyn NFSFileSystem + Send + Sync), current: fileid3) {
+        let candidates: Vec<(fileid3, FileSlot)> = {
+            let mut files = self.files.lock().unwrap();
+            // nobody else holds an idle slot, and nobody can clone it without the map lock
+            files.retain(|_, slot| {
+                Arc::strong_count(slot) > 1 || slot.try_lock().map_or(true, |p| p.is_some())
+            });
+            files
+                .iter()
+                .filter(|(id, _)| **id != current)
+                .map(|(id, slot)| (*id, slot.clone()))
+                .collect()
+        };
+        for (id, slot) in candidates {
+            let Ok(mut pending) = slot.try_lock() else {
+                continue;
+            };
+            if pending.as_ref().is_some_and(|p| p.since.elapsed() >= self.config.max_delay) {
+                let expired = pending.take().unwrap();
+                let _ = self.write_out(vfs, id, expired).await;
+            }
+        }
+    }
+}
+
+impl MemoryConsumer for WriteCoalescer {
+    fn memory(&self) -> &MemoryCharge {
+        &self.memory
+    }
+
+    /// Frees nothing, as writing out buffered data needs the VFS; writes are
+    /// passed through instead while the budget is exceeded
+    fn shrink(&self, _bytes: usize) -> usize {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending(offset: u64, data: &[u8]) -> Pending {
+        Pending { offset, data: data.to_vec(), since: Instant::now() }
+    }
+
+    #[test]
+    fn merges_adjacent_and_overlapping_writes() {
+        let mut p = pending(10, b"abcd");
+        assert!(p.merge(14, b"ef"));
+        assert_eq!((p.offset, &p.data[..]), (10, &b"abcdef"[..]));
+        assert!(p.merge(12, b"XY"));
+        assert_eq!((p.offset, &p.data[..]), (10, &b"abXYef"[..]));
+        assert!(p.merge(8, b"01a"));
+        assert_eq!((p.offset, &p.data[..]), (8, &b"01abXYef"[..]));
+        assert!(p.merge(6, b"------------"));
+        assert_eq!((p.offset, &p.data[..]), (6, &b"------------"[..]));
+        assert!(p.merge(16, b"zz"));
+        assert_eq!((p.offset, &p.data[..]), (6, &b"----------zz"[..]));
+    }
+
+    #[test]
+    fn rejects_disjoint_writes() {
+        let mut p = pending(10, b"abcd");
+        assert!(!p.merge(15, b"x"));
+        assert!(!p.merge(0, b"123456789"));
+        assert_eq!((p.offset, &p.data[..]), (10, &b"abcd"[..]));
+    }
+}
@@ -1,9 +1,27 @@
 //! Module for NFSv4 protocol implementation.
 //! Provides functionality for working with NFS version 4 protocol context and operations.
+//!
+//! - `session`: NFSv4.1 sessions, slot tables and the per-slot reply cache that
+//!   provides exactly-once semantics for compounds started with `SEQUENCE`. It
+//!   is a building block: no compound dispatcher serves it to clients yet.
+//! - `pnfs`: The files layout type of pNFS and the `LayoutProvider` trait used when
+//!   the server acts as a metadata server. No layout is served to clients yet.
+//!
+//! The NFSv4.2 sparse file operations `ALLOCATE`, `DEALLOCATE` and `SEEK` map onto
+//! the optional `NFSFileSystem::allocate`, `NFSFileSystem::deallocate` and
+//! `NFSFileSystem::seek` methods; `vfs::SeekContent` uses the `data_content4` values.
+
+pub mod pnfs;
+pub mod session;
+
+use std::sync::Arc;
 
 #[derive(Default)]
 /// Represents the context for NFSv4 operations.
 /// Contains necessary state and configuration for NFSv4 protocol handling.
 pub struct NFSv4Context {
-    // TODO: find out what should be here
+    /// Active NFSv4.1 sessions shared by all connections
+    pub sessions: session::SessionTable,
+    /// Layout provider used to answer pNFS requests, if the server is a metadata server
+    pub layout_provider: Option<Arc<dyn pnfs::LayoutProvider>>,
 }
@@ -0,0 +1,407 @@
+//! pNFS files layout support as described in RFC 8881 chapters 12 and 13.
+//!
+//! With pNFS the server acts as a metadata server (MDS): clients still open files
+//! and manage attributes through it, but ask for layouts (`LAYOUTGET`) that tell
+//! them which data servers (DS) hold the file contents and read and write those
+//! servers directly. For the files layout type a data server is a plain NFSv4.1
+//! server, so the data servers may be other instances of this crate exporting the
+//! same backend.
+//!
+//! Layout decisions are delegated to a [`LayoutProvider`]. [`StripedLayoutProvider`]
+//! is a ready-made provider that stripes every file round-robin across a fixed set
+//! of data servers which share the metadata server's file handles.
+//!
+//! No layout is served yet: the server has no NFSv4 compound dispatcher, so
+//! `LAYOUTGET` and `GETDEVICEINFO` never reach a provider. The types encode to
+//! and decode from their XDR forms, `layout4` with a files layout body and
+//! `nfsv4_1_file_layout_ds_addr4`, for code embedding them.
+
+use std::io::{Cursor, Read, Write};
+use std::net::SocketAddr;
+
+use async_trait::async_trait;
+use num_derive::{FromPrimitive, ToPrimitive};
+
+use crate::xdr::{
+    deserialize, nfs3, Deserialize, DeserializeEnum, DeserializeStruct, Serialize, SerializeEnum,
+    SerializeStruct,
+};
+
+/// Opaque device identifier (`deviceid4`)
+pub type DeviceId = [u8; 16];
+
+/// `LAYOUT4_NFSV4_1_FILES` layout type
+pub const LAYOUT4_NFSV4_1_FILES: u32 = 1;
+
+/// `nfl_util` flag: data servers use dense packing of stripes
+pub const NFL4_UFLG_DENSE: u32 = 0x0000_0001;
+/// `nfl_util` flag: `COMMIT` must be sent to the metadata server
+pub const NFL4_UFLG_COMMIT_THRU_MDS: u32 = 0x0000_0002;
+/// `nfl_util` mask selecting the stripe unit size
+pub const NFL4_UFLG_STRIPE_UNIT_SIZE_MASK: u32 = 0xFFFF_FFC0;
+
+/// Layout length meaning "until the end of the file" (`NFS4_UINT64_MAX`)
+pub const LAYOUT_TO_EOF: u64 = u64::MAX;
+
+/// I/O mode a layout is requested for (`layoutiomode4`)
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+#[repr(u32)]
+pub enum LayoutIoMode {
+    /// The layout may only be used for reading
+    #[default]
+    Read = 1,
+    /// The layout may be used for reading and writing
+    ReadWrite = 2,
+    /// Any layout; only valid in `LAYOUTRETURN`
+    Any = 3,
+}
+impl SerializeEnum for LayoutIoMode {}
+impl DeserializeEnum for LayoutIoMode {}
+
+/// Network address of a data server (`netaddr4`)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NetAddr {
+    /// Network id, e.g. `"tcp"` or `"tcp6"`
+    pub netid: String,
+    /// Universal address as defined in RFC 5665
+    pub uaddr: String,
+}
+DeserializeStruct!(NetAddr, netid, uaddr);
+SerializeStruct!(NetAddr, netid, uaddr);
+
+impl NetAddr {
+    /// Builds a TCP universal address from a socket address
+    ///
+    /// The port is appended as two decimal octets, e.g. `127.0.0.1:2049`
+    /// becomes `127.0.0.1.8.1`.
+    pub fn tcp(addr: SocketAddr) -> Self {
+        let port = addr.port();
+        let (netid, ip) = match addr {
+            SocketAddr::V4(v4) => ("tcp", v4.ip().to_string()),
+            SocketAddr::V6(v6) => ("tcp6", v6.ip().to_string()),
+        };
+        Self { netid: netid.to_string(), uaddr: format!("{ip}.{}.{}", port >> 8, port & 0xff) }
+    }
+}
+
+/// Addresses of the data servers behind a device (`nfsv4_1_file_layout_ds_addr4`)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FileLayoutDevice {
+    /// Maps a stripe index to an index in `multipath_ds_list`
+    pub stripe_indices: Vec<u32>,
+    /// For every data server, the list of equivalent addresses it can be reached at
+    pub multipath_ds_list: Vec<Vec<NetAddr>>,
+}
+DeserializeStruct!(FileLayoutDevice, stripe_indices, multipath_ds_list);
+
+impl Serialize for FileLayoutDevice {
+    fn serialize<W: Write>(&self, dest: &mut W) -> std::io::Result<()> {
+        self.stripe_indices.serialize(dest)?;
+        (self.multipath_ds_list.len() as u32).serialize(dest)?;
+        for addrs in &self.multipath_ds_list {
+            addrs.serialize(dest)?;
+        }
+        Ok(())
+    }
+}
+
+/// A files layout segment handed to a client (`nfsv4_1_file_layout4` plus its range)
+///
+/// Encoded as a `layout4` whose `loc_body` holds the `nfsv4_1_file_layout4`.
+#[derive(Clone, Debug, Default)]
+pub struct FileLayout {
+    /// First byte covered by the layout
+    pub offset: u64,
+    /// Number of bytes covered, or [`LAYOUT_TO_EOF`]
+    pub length: u64,
+    /// I/O mode the layout is valid for
+    pub iomode: LayoutIoMode,
+    /// Device describing the data servers
+    pub device_id: DeviceId,
+    /// Stripe unit size in bytes; must be a multiple of 64
+    pub stripe_unit: u32,
+    /// Whether data servers use dense stripe packing
+    pub dense: bool,
+    /// Whether the client must send `COMMIT` to the metadata server
+    pub commit_through_mds: bool,
+    /// Stripe index of the first stripe unit of the file
+    pub first_stripe_index: u32,
+    /// Logical offset of the start of the striping pattern
+    pub pattern_offset: u64,
+    /// File handles to use on the data servers, one per server or a single shared one
+    pub fh_list: Vec<nfs3::nfs_fh3>,
+}
+
+impl FileLayout {
+    /// Encodes the stripe unit and flags as the wire `nfl_util` value
+    pub fn nfl_util(&self) -> u32 {
+        let mut util = self.stripe_unit & NFL4_UFLG_STRIPE_UNIT_SIZE_MASK;
+        if self.dense {
+            util |= NFL4_UFLG_DENSE;
+        }
+        if self.commit_through_mds {
+            util |= NFL4_UFLG_COMMIT_THRU_MDS;
+        }
+        util
+    }
+}
+
+impl Serialize for FileLayout {
+    fn serialize<W: Write>(&self, dest: &mut W) -> std::io::Result<()> {
+        self.offset.serialize(dest)?;
+        self.length.serialize(dest)?;
+        self.iomode.serialize(dest)?;
+        LAYOUT4_NFSV4_1_FILES.serialize(dest)?;
+        let mut body = Vec::new();
+        self.device_id.serialize(&mut body)?;
+        self.nfl_util().serialize(&mut body)?;
+        self.first_stripe_index.serialize(&mut body)?;
+        self.pattern_offset.serialize(&mut body)?;
+        self.fh_list.serialize(&mut body)?;
+        body.serialize(dest)
+    }
+}
+
+impl Deserialize for FileLayout {
+    fn deserialize<R: Read>(&mut self, src: &mut R) -> std::io::Result<()> {
+        self.offset.deserialize(src)?;
+        self.length.deserialize(src)?;
+        self.iomode.deserialize(src)?;
+        if deserialize::<u32>(src)? != LAYOUT4_NFSV4_1_FILES {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "not a files layout"));
+        }
+        let body = deserialize::<Vec<u8>>(src)?;
+        let mut body = Cursor::new(body);
+        self.device_id.deserialize(&mut body)?;
+        let util = deserialize::<u32>(&mut body)?;
+        self.stripe_unit = util & NFL4_UFLG_STRIPE_UNIT_SIZE_MASK;
+        self.dense = util & NFL4_UFLG_DENSE != 0;
+        self.commit_through_mds = util & NFL4_UFLG_COMMIT_THRU_MDS != 0;
+        self.first_stripe_index.deserialize(&mut body)?;
+        self.pattern_offset.deserialize(&mut body)?;
+        self.fh_list.deserialize(&mut body)?;
+        Ok(())
+    }
+}
+
+/// Errors a layout provider can report
+///
+/// Each variant corresponds to the `nfsstat4` code named in its documentation,
+/// available through [`LayoutError::nfsstat4`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LayoutError {
+    /// No layout is available for the file; the client uses the MDS (`NFS4ERR_LAYOUTUNAVAILABLE`)
+    Unavailable,
+    /// A layout may become available later (`NFS4ERR_LAYOUTTRYLATER`)
+    TryLater,
+    /// The device id is unknown (`NFS4ERR_NOENT`)
+    NoDevice,
+    /// The layout range or device is inconsistent (`NFS4ERR_BADLAYOUT`)
+    BadLayout,
+}
+
+impl LayoutError {
+    /// Returns the `nfsstat4` value to report to the client
+    pub fn nfsstat4(self) -> u32 {
+        match self {
+            LayoutError::Unavailable => 10059,
+            LayoutError::TryLater => 10058,
+            LayoutError::NoDevice => 2,
+            LayoutError::BadLayout => 10050,
+        }
+    }
+}
+
+/// Decides which data servers serve which part of a file
+///
+/// Installed on the metadata server. All methods receive the backend file id and
+/// the metadata server's file handle of the file the operation applies to.
+#[async_trait]
+pub trait LayoutProvider: Sync + Send {
+    /// Handles `LAYOUTGET`
+    ///
+    /// # Arguments
+    ///
+    /// * `fileid` - File the layout is requested for
+    /// * `fh` - Metadata server file handle of the file
+    /// * `iomode` - Requested I/O mode
+    /// * `offset` - First byte the client needs
+    /// * `length` - Number of bytes the client needs
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<FileLayout>, LayoutError>` - Layout segments covering at least
+    ///   `offset` or an error
+    async fn layout_get(
+        &self,
+        fileid: nfs3::fileid3,
+        fh: &nfs3::nfs_fh3,
+        iomode: LayoutIoMode,
+        offset: u64,
+        length: u64,
+    ) -> Result<Vec<FileLayout>, LayoutError>;
+
+    /// Handles `GETDEVICEINFO`
+    ///
+    /// # Arguments
+    ///
+    /// * `device_id` - Device named in a previously returned layout
+    ///
+    /// # Returns
+    ///
+    /// * `Result<FileLayoutDevice, LayoutError>` - Data server addresses of the device
+    async fn device_info(&self, device_id: &DeviceId) -> Result<FileLayoutDevice, LayoutError>;
+
+    /// Handles `LAYOUTRETURN`; the default implementation keeps no per-layout state
+    async fn layout_return(
+        &self,
+        _fileid: nfs3::fileid3,
+        _offset: u64,
+        _length: u64,
+    ) -> Result<(), LayoutError> {
+        Ok(())
+    }
+
+    /// Handles `LAYOUTCOMMIT`
+    ///
+    /// # Arguments
+    ///
+    /// * `fileid` - File written through the data servers
+    /// * `last_write_offset` - Highest byte offset written by the client, if known
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<u64>, LayoutError>` - New file size if the metadata server
+    ///   must update it, `None` if the size is already correct
+    async fn layout_commit(
+        &self,
+        _fileid: nfs3::fileid3,
+        _last_write_offset: Option<u64>,
+    ) -> Result<Option<u64>, LayoutError> {
+        Ok(None)
+    }
+}
+
+/// Stripes every file round-robin across a fixed set of data servers
+///
+/// The data servers must export the same backend as the metadata server, so the
+/// metadata server file handle is valid on every data server. Layouts are sparse
+/// and committed through the data servers.
+#[derive(Clone, Debug)]
+pub struct StripedLayoutProvider {
+    device_id: DeviceId,
+    data_servers: Vec<NetAddr>,
+    stripe_unit: u32,
+}
+
+impl StripedLayoutProvider {
+    /// Creates a provider striping across `data_servers`
+    ///
+    /// # Arguments
+    ///
+    /// * `data_servers` - Addresses of the data servers, in stripe order
+    /// * `stripe_unit` - Stripe unit size in bytes, rounded down to a multiple of 64
+    ///   and to at least 64
+    pub fn new(data_servers: Vec<NetAddr>, stripe_unit: u32) -> Self {
+        let stripe_unit = (stripe_unit & NFL4_UFLG_STRIPE_UNIT_SIZE_MASK).max(64);
+        Self { device_id: [0; 16], data_servers, stripe_unit }
+    }
+}
+
+#[async_trait]
+impl LayoutProvider for StripedLayoutProvider {
+    async fn layout_get(
+        &self,
+        _fileid: nfs3::fileid3,
+        fh: &nfs3::nfs_fh3,
+        iomode: LayoutIoMode,
+        _offset: u64,
+        _length: u64,
+    ) -> Result<Vec<FileLayout>, LayoutError> {
+        if self.data_servers.is_empty() {
+            return Err(LayoutError::Unavailable);
+        }
+        if iomode == LayoutIoMode::Any {
+            return Err(LayoutError::BadLayout);
+        }
+        Ok(vec![FileLayout {
+            offset: 0,
+            length: LAYOUT_TO_EOF,
+            iomode,
+            device_id: self.device_id,
+            stripe_unit: self.stripe_unit,
+            dense: false,
+            commit_through_mds: false,
+            first_stripe_index: 0,
+            pattern_offset: 0,
+            fh_list: vec![fh.clone()],
+        }])
+    }
+
+    async fn device_info(&self, device_id: &DeviceId) -> Result<FileLayoutDevice, LayoutError> {
+        if *device_id != self.device_id {
+            return Err(LayoutError::NoDevice);
+        }
+        Ok(FileLayoutDevice {
+            stripe_indices: (0..self.data_servers.len() as u32).collect(),
+            multipath_ds_list: self.data_servers.iter().map(|ds| vec![ds.clone()]).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_layout_round_trips() {
+        let layout = FileLayout {
+            offset: 4096,
+            length: LAYOUT_TO_EOF,
+            iomode: LayoutIoMode::ReadWrite,
+            device_id: [7; 16],
+            stripe_unit: 65536,
+            dense: true,
+            commit_through_mds: false,
+            first_stripe_index: 2,
+            pattern_offset: 0,
+            fh_list: vec![nfs3::nfs_fh3 { data: vec![1, 2, 3] }],
+        };
+        let mut encoded = Vec::new();
+        layout.serialize(&mut encoded).unwrap();
+        // offset, length, iomode and layout type precede the opaque body
+        assert_eq!(&encoded[16..24], &[0, 0, 0, 2, 0, 0, 0, 1]);
+
+        let decoded = deserialize::<FileLayout>(&mut Cursor::new(&encoded)).unwrap();
+        assert_eq!((decoded.offset, decoded.length), (4096, LAYOUT_TO_EOF));
+        assert_eq!(decoded.iomode, LayoutIoMode::ReadWrite);
+        assert_eq!(decoded.device_id, [7; 16]);
+        assert_eq!(decoded.nfl_util(), layout.nfl_util());
+        assert_eq!((decoded.stripe_unit, decoded.dense), (65536, true));
+        assert_eq!(decoded.first_stripe_index, 2);
+        assert_eq!(decoded.fh_list[0].data, [1, 2, 3]);
+        let mut reencoded = Vec::new();
+        decoded.serialize(&mut reencoded).unwrap();
+        assert_eq!(reencoded, encoded);
+
+        // other layout types are refused
+        encoded[23] = 2;
+        assert!(deserialize::<FileLayout>(&mut Cursor::new(&encoded)).is_err());
+    }
+
+    #[test]
+    fn device_round_trips() {
+        let device = FileLayoutDevice {
+            stripe_indices: vec![0, 1],
+            multipath_ds_list: vec![
+                vec![NetAddr::tcp("127.0.0.1:2049".parse().unwrap())],
+                vec![NetAddr::tcp("[::1]:2049".parse().unwrap())],
+            ],
+        };
+        let mut encoded = Vec::new();
+        device.serialize(&mut encoded).unwrap();
+        let decoded = deserialize::<FileLayoutDevice>(&mut Cursor::new(&encoded)).unwrap();
+        assert_eq!(decoded, device);
+        assert_eq!(decoded.multipath_ds_list[0][0].uaddr, "127.0.0.1.8.1");
+    }
+}
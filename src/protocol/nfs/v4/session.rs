@@ -0,0 +1,318 @@
+//! NFSv4.1 sessions and exactly-once semantics as described in RFC 8881 section 2.10.
+//!
+//! A session owns a fixed-size slot table negotiated at `CREATE_SESSION` time.
+//! Every compound starts with a `SEQUENCE` operation naming a slot and a sequence
+//! id; the server processes at most one request per slot at a time and keeps the
+//! reply of the last request in each slot. This gives:
+//!
+//! - Exactly-once semantics: a retransmission of the last request on a slot is
+//!   answered from the slot's reply cache instead of being executed again
+//! - Bounded state: the reply cache holds at most one reply per slot, unlike the
+//!   time-based v3 `TransactionTracker`
+//! - Trunking: the cache is keyed by session rather than by client address, so a
+//!   client may retry a request over a different connection
+//!
+//! The table is transport independent. A compound processor calls
+//! [`SessionTable::sequence`] for the `SEQUENCE` operation, executes the rest of
+//! the compound when told to, and stores the encoded reply with
+//! [`SessionTable::complete`].
+//!
+//! This is a building block: the server has no NFSv4 compound dispatcher yet,
+//! so nothing serves `CREATE_SESSION` or `SEQUENCE` to clients and the table
+//! is only used by code embedding it.
+//!
+//! Session ids are drawn from an [`Entropy`], so a client cannot guess the
+//! session of another client and use its slots.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::entropy::Entropy;
+
+/// Opaque session identifier (`sessionid4`)
+pub type SessionId = [u8; 16];
+
+/// Upper bound on the number of slots a client can negotiate for one session
+pub const MAX_SLOTS: u32 = 1024;
+
+/// Errors returned by `SEQUENCE` processing
+///
+/// Each variant corresponds to the `nfsstat4` code named in its documentation,
+/// available through [`SequenceError::nfsstat4`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SequenceError {
+    /// The session does not exist (`NFS4ERR_BADSESSION`)
+    BadSession,
+    /// The slot id is outside the session's slot table (`NFS4ERR_BADSLOT`)
+    BadSlot,
+    /// The sequence id is neither a retry nor the next request (`NFS4ERR_SEQ_MISORDERED`)
+    Misordered,
+    /// The request is a retry but its reply was not cached (`NFS4ERR_RETRY_UNCACHED_REP`)
+    RetryUncachedReply,
+    /// The original request is still being executed (`NFS4ERR_DELAY`)
+    Delay,
+}
+
+impl SequenceError {
+    /// Returns the `nfsstat4` value to report to the client
+    pub fn nfsstat4(self) -> u32 {
+        match self {
+            SequenceError::BadSession => 10052,
+            SequenceError::BadSlot => 10053,
+            SequenceError::Misordered => 10063,
+            SequenceError::RetryUncachedReply => 10068,
+            SequenceError::Delay => 10008,
+        }
+    }
+}
+
+/// Result of a successful `SEQUENCE` operation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SequenceOutcome {
+    /// The request is new and the remaining operations must be executed.
+    /// The caller must finish with [`SessionTable::complete`].
+    Execute {
+        /// Highest slot id the server currently allows (`sr_highest_slotid`)
+        highest_slotid: u32,
+    },
+    /// The request is a retry; the cached reply must be sent instead
+    Replay(Arc<Vec<u8>>),
+}
+
+/// State of a single slot in a session's slot table
+#[derive(Debug)]
+enum SlotState {
+    /// The slot has never been used or its last reply was not cached
+    Idle,
+    /// A request is executing on the slot
+    InProgress { cache_this: bool },
+    /// The last request completed and its reply is kept for retries
+    Cached(Arc<Vec<u8>>),
+}
+
+/// One entry of the slot table
+#[derive(Debug)]
+struct Slot {
+    seqid: u32,
+    state: SlotState,
+}
+
+/// A single NFSv4.1 session
+#[derive(Debug)]
+pub struct Session {
+    client_id: u64,
+    slots: Mutex<Vec<Slot>>,
+}
+
+impl Session {
+    /// Returns the client id that created this session
+    pub fn client_id(&self) -> u64 {
+        self.client_id
+    }
+
+    /// Returns the number of slots in the fore channel
+    pub fn slot_count(&self) -> u32 {
+        self.slots.lock().expect("unable to unlock slots mutex").len() as u32
+    }
+}
+
+/// Table of active sessions shared by all connections of a server
+#[derive(Debug, Default)]
+pub struct SessionTable {
+    sessions: Mutex<HashMap<SessionId, Arc<Session>>>,
+}
+
+impl SessionTable {
+    /// Creates an empty session table
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a session for a confirmed client
+    ///
+    /// # Arguments
+    ///
+    /// * `client_id` - Client id returned by `EXCHANGE_ID`
+    /// * `requested_slots` - `ca_maxrequests` of the fore channel attributes
+    /// * `entropy` - Source of the session id
+    ///
+    /// # Returns
+    ///
+    /// * `(SessionId, u32)` - The new session id and the number of slots granted,
+    ///   which is clamped to `1..=MAX_SLOTS`
+    pub fn create_session(
+        &self,
+        client_id: u64,
+        requested_slots: u32,
+        entropy: &dyn Entropy,
+    ) -> (SessionId, u32) {
+        let slot_count = requested_slots.clamp(1, MAX_SLOTS);
+        let slots =
+            (0..slot_count).map(|_| Slot { seqid: 0, state: SlotState::Idle }).collect::<Vec<_>>();
+        let session = Arc::new(Session { client_id, slots: Mutex::new(slots) });
+
+        let mut sessions = self.sessions.lock().expect("unable to unlock sessions mutex");
+        let id = loop {
+            let mut id = [0u8; 16];
+            id[..8].copy_from_slice(&entropy.next_u64().to_le_bytes());
+            id[8..].copy_from_slice(&entropy.next_u64().to_le_bytes());
+            if !sessions.contains_key(&id) {
+                break id;
+            }
+        };
+        sessions.insert(id, session);
+        (id, slot_count)
+    }
+
+    /// Destroys a session, dropping its reply cache
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - `true` if the session existed
+    pub fn destroy_session(&self, id: &SessionId) -> bool {
+        self.sessions.lock().expect("unable to unlock sessions mutex").remove(id).is_some()
+    }
+
+    /// Destroys every session owned by a client, e.g. on lease expiry
+    pub fn destroy_client(&self, client_id: u64) {
+        self.sessions
+            .lock()
+            .expect("unable to unlock sessions mutex")
+            .retain(|_, session| session.client_id != client_id);
+    }
+
+    /// Looks up a session by id
+    pub fn get(&self, id: &SessionId) -> Option<Arc<Session>> {
+        self.sessions.lock().expect("unable to unlock sessions mutex").get(id).cloned()
+    }
+
+    /// Processes the `SEQUENCE` operation that starts every v4.1 compound
+    ///
+    /// Implements the slot sequencing rules of RFC 8881 section 2.10.6.1:
+    /// a sequence id one greater than the slot's last one starts a new request,
+    /// an equal sequence id is a retry and anything else is misordered.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Session named by the request
+    /// * `slotid` - Slot the request is sent on
+    /// * `seqid` - Sequence id of the request
+    /// * `cache_this` - Whether the client asked for the reply to be cached
+    ///
+    /// # Returns
+    ///
+    /// * `Result<SequenceOutcome, SequenceError>` - Whether to execute the compound
+    ///   or replay a cached reply, or the error to return from `SEQUENCE`
+    pub fn sequence(
+        &self,
+        id: &SessionId,
+        slotid: u32,
+        seqid: u32,
+        cache_this: bool,
+    ) -> Result<SequenceOutcome, SequenceError> {
+        let session = self.get(id).ok_or(SequenceError::BadSession)?;
+        let mut slots = session.slots.lock().expect("unable to unlock slots mutex");
+        let highest_slotid = slots.len() as u32 - 1;
+        let slot = slots.get_mut(slotid as usize).ok_or(SequenceError::BadSlot)?;
+
+        if seqid == slot.seqid.wrapping_add(1) {
+            if matches!(slot.state, SlotState::InProgress { .. }) {
+                // the client may not reuse a slot before it received a reply
+                return Err(SequenceError::Misordered);
+            }
+            slot.seqid = seqid;
+            slot.state = SlotState::InProgress { cache_this };
+            return Ok(SequenceOutcome::Execute { highest_slotid });
+        }
+
+        if seqid == slot.seqid {
+            return match &slot.state {
+                SlotState::InProgress { .. } => Err(SequenceError::Delay),
+                SlotState::Cached(reply) => Ok(SequenceOutcome::Replay(reply.clone())),
+                SlotState::Idle => Err(SequenceError::RetryUncachedReply),
+            };
+        }
+
+        Err(SequenceError::Misordered)
+    }
+
+    /// Records the reply of a request started by [`SessionTable::sequence`]
+    ///
+    /// The reply is kept only if the client set `sa_cachethis`; otherwise a later
+    /// retry of the same request is answered with `NFS4ERR_RETRY_UNCACHED_REP`.
+    /// Completing an unknown session or slot is ignored, as the session may have
+    /// been destroyed while the request was executing.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Session the request was sent on
+    /// * `slotid` - Slot the request was sent on
+    /// * `reply` - Encoded compound reply
+    pub fn complete(&self, id: &SessionId, slotid: u32, reply: Vec<u8>) {
+        let Some(session) = self.get(id) else {
+            return;
+        };
+        let mut slots = session.slots.lock().expect("unable to unlock slots mutex");
+        if let Some(slot) = slots.get_mut(slotid as usize) {
+            if let SlotState::InProgress { cache_this } = slot.state {
+                slot.state =
+                    if cache_this { SlotState::Cached(Arc::new(reply)) } else { SlotState::Idle };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entropy::SeededEntropy;
+
+    #[test]
+    fn retry_is_served_from_slot_cache() {
+        let table = SessionTable::new();
+        let (id, slots) = table.create_session(7, 4, &SeededEntropy::new(0));
+        assert_eq!(slots, 4);
+
+        assert_eq!(
+            table.sequence(&id, 1, 1, true),
+            Ok(SequenceOutcome::Execute { highest_slotid: 3 })
+        );
+        assert_eq!(table.sequence(&id, 1, 1, true), Err(SequenceError::Delay));
+        table.complete(&id, 1, vec![1, 2, 3]);
+        assert_eq!(
+            table.sequence(&id, 1, 1, true),
+            Ok(SequenceOutcome::Replay(Arc::new(vec![1, 2, 3])))
+        );
+        assert_eq!(table.sequence(&id, 1, 3, true), Err(SequenceError::Misordered));
+        assert_eq!(table.sequence(&id, 4, 1, true), Err(SequenceError::BadSlot));
+    }
+
+    #[test]
+    fn uncached_retry_and_destroyed_session() {
+        let table = SessionTable::new();
+        let (id, _) = table.create_session(1, 0, &SeededEntropy::new(0));
+
+        assert!(matches!(table.sequence(&id, 0, 1, false), Ok(SequenceOutcome::Execute { .. })));
+        table.complete(&id, 0, vec![0]);
+        assert_eq!(table.sequence(&id, 0, 1, false), Err(SequenceError::RetryUncachedReply));
+
+        table.destroy_client(1);
+        assert_eq!(table.sequence(&id, 0, 2, false), Err(SequenceError::BadSession));
+    }
+
+    #[test]
+    fn session_ids_are_drawn_from_entropy() {
+        let table = SessionTable::new();
+        let entropy = SeededEntropy::new(3);
+        let (first, _) = table.create_session(1, 1, &entropy);
+        let (second, _) = table.create_session(1, 1, &entropy);
+        assert_ne!(first, second);
+        assert!(!first.starts_with(&1u64.to_le_bytes()));
+
+        // an id already in use is never handed out again
+        let (third, _) = table.create_session(2, 1, &SeededEntropy::new(3));
+        assert!(third != first && third != second);
+        assert_eq!(table.get(&first).unwrap().client_id(), 1);
+        assert_eq!(table.get(&third).unwrap().client_id(), 2);
+    }
+}
@@ -0,0 +1,131 @@
+//! Per-program authentication requirements.
+//!
+//! An [`AuthPolicy`] lists the credential flavors each RPC program accepts.
+//! Calls with any other flavor are denied with `AUTH_TOOWEAK` before they reach
+//! the program, so the MOUNT and PORTMAP programs can be held to the same
+//! requirement as NFS itself: otherwise a peer that may not access files could
+//! still enumerate exports or rewrite port mappings.
+//!
+//! `NULL` procedures are always accepted, as clients use them to probe the
+//! server and to negotiate security contexts before they hold credentials.
+//!
+//! The flavors required for the NFS program are advertised to clients in the
+//! reply to `MOUNTPROC3_MNT`, see [`AuthPolicy::mount_flavors`]. They may
+//! include the `RPCSEC_GSS` pseudo-flavors of RFC 2623, which stand for calls
+//! with `RPCSEC_GSS` credentials.
+//!
+//! The server currently decodes `AUTH_NULL` and `AUTH_UNIX` credentials only,
+//! and refuses calls with `RPCSEC_GSS` credentials.
+
+use std::collections::HashMap;
+
+use crate::protocol::xdr::rpc::auth_flavor;
+use crate::protocol::xdr::{mount, nfs3, portmap};
+
+/// Flavors advertised in `MOUNT` replies while the NFS program has no
+/// requirements, the ones the server decodes
+pub const DEFAULT_MOUNT_FLAVORS: [auth_flavor; 2] =
+    [auth_flavor::AUTH_NULL, auth_flavor::AUTH_UNIX];
+
+/// Credential flavors accepted per RPC program
+///
+/// Programs without an entry accept every flavor, which is the default for all
+/// programs.
+#[derive(Clone, Debug, Default)]
+pub struct AuthPolicy {
+    /// Accepted flavors keyed by program number
+    programs: HashMap<u32, Vec<auth_flavor>>,
+}
+
+impl AuthPolicy {
+    /// Creates a policy that accepts every flavor for every program
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Restricts calls of `program` to the given flavors
+    ///
+    /// # Arguments
+    ///
+    /// * `program` - RPC program number
+    /// * `flavors` - Credential flavors accepted for the program
+    pub fn require(&mut self, program: u32, flavors: &[auth_flavor]) -> &mut Self {
+        self.programs.insert(program, flavors.to_vec());
+        self
+    }
+
+    /// Restricts the NFS, MOUNT and PORTMAP programs to the given flavors
+    pub fn require_for_all(&mut self, flavors: &[auth_flavor]) -> &mut Self {
+        for program in [nfs3::PROGRAM, mount::PROGRAM, portmap::PROGRAM] {
+            self.require(program, flavors);
+        }
+        self
+    }
+
+    /// Returns whether a call is accepted
+    ///
+    /// # Arguments
+    ///
+    /// * `program` - RPC program number of the call
+    /// * `procedure` - Procedure number of the call
+    /// * `flavor` - Flavor of the call's credentials
+    pub fn allows(&self, program: u32, procedure: u32, flavor: auth_flavor) -> bool {
+        if procedure == 0 {
+            return true;
+        }
+        self.programs.get(&program).is_none_or(|accepted| lists(accepted, flavor))
+    }
+
+    /// Returns the flavors advertised in `MOUNTPROC3_MNT` replies, in order of
+    /// preference
+    ///
+    /// These are the flavors required for the NFS program, or
+    /// [`DEFAULT_MOUNT_FLAVORS`] without requirements.
+    pub fn mount_flavors(&self) -> Vec<auth_flavor> {
+        self.programs.get(&nfs3::PROGRAM).cloned().unwrap_or_else(|| DEFAULT_MOUNT_FLAVORS.to_vec())
+    }
+
+    /// Returns whether a mount negotiated with credentials of `flavor` is
+    /// accepted, i.e. whether the flavor is among [`AuthPolicy::mount_flavors`]
+    pub fn allows_mount(&self, flavor: auth_flavor) -> bool {
+        lists(&self.mount_flavors(), flavor)
+    }
+}
+
+/// Returns whether calls with credentials of `flavor` match one of `flavors`
+fn lists(flavors: &[auth_flavor], flavor: auth_flavor) -> bool {
+    flavors.iter().any(|listed| listed.credential_flavor() as u32 == flavor as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gates_programs_except_null() {
+        let mut policy = AuthPolicy::new();
+        assert!(policy.allows(mount::PROGRAM, 1, auth_flavor::AUTH_NULL));
+
+        policy.require_for_all(&[auth_flavor::AUTH_UNIX]);
+        assert!(!policy.allows(mount::PROGRAM, 1, auth_flavor::AUTH_NULL));
+        assert!(!policy.allows(portmap::PROGRAM, 1, auth_flavor::AUTH_NULL));
+        assert!(policy.allows(portmap::PROGRAM, 1, auth_flavor::AUTH_UNIX));
+        assert!(policy.allows(mount::PROGRAM, 0, auth_flavor::AUTH_NULL));
+        assert!(policy.allows(12345, 1, auth_flavor::AUTH_NULL));
+    }
+
+    #[test]
+    fn advertises_nfs_flavors_for_mounts() {
+        let mut policy = AuthPolicy::new();
+        assert_eq!(policy.mount_flavors().len(), DEFAULT_MOUNT_FLAVORS.len());
+        assert!(policy.allows_mount(auth_flavor::AUTH_NULL));
+
+        policy.require(nfs3::PROGRAM, &[auth_flavor::RPCSEC_GSS_KRB5P, auth_flavor::AUTH_UNIX]);
+        let advertised: Vec<u32> = policy.mount_flavors().iter().map(|f| *f as u32).collect();
+        assert_eq!(advertised, [390005, 1]);
+        assert!(policy.allows_mount(auth_flavor::RPCSEC_GSS));
+        assert!(policy.allows_mount(auth_flavor::AUTH_UNIX));
+        assert!(!policy.allows_mount(auth_flavor::AUTH_NULL));
+        assert!(policy.allows(nfs3::PROGRAM, 1, auth_flavor::RPCSEC_GSS));
+    }
+}
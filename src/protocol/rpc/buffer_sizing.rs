@@ -0,0 +1,108 @@
+//! Sizing of response buffers from the replies seen so far.
+//!
+//! Replies differ in size by orders of magnitude between procedures: a
+//! `GETATTR` reply takes about a hundred bytes, while `READ` and `READDIR`
+//! replies take as much as the client asked for, commonly 64 KiB to 1 MiB.
+//! Starting every response buffer at the same capacity makes the large ones
+//! grow, and reallocate, several times per call.
+//!
+//! [`ResponseSizer`] remembers the size of recent replies per program, version
+//! and procedure of a connection, and gives the capacity to reserve before
+//! encoding the next reply of the same procedure.
+
+use std::collections::HashMap;
+
+/// Largest capacity reserved ahead of a reply
+const MAX_ESTIMATE: usize = 4 * 1024 * 1024;
+
+/// Share of the estimate dropped per smaller reply, as a power of two; the
+/// estimate falls to half after about six replies of the smaller size
+const DECAY_SHIFT: u32 = 3;
+
+/// Program, version and procedure of a call
+type ProcedureKey = (u32, u32, u32);
+
+/// Estimates of the reply size per procedure
+#[derive(Debug)]
+pub(crate) struct ResponseSizer {
+    /// Capacity for calls that cannot be decoded, and the least reserved
+    default_capacity: usize,
+    /// Decaying maximum of the reply sizes per procedure
+    estimates: HashMap<ProcedureKey, usize>,
+}
+
+impl ResponseSizer {
+    /// Creates a sizer reserving `default_capacity` until replies were seen
+    pub(crate) fn new(default_capacity: usize) -> Self {
+        Self { default_capacity, estimates: HashMap::new() }
+    }
+
+    /// Returns the procedure of an encoded call, if it is one
+    ///
+    /// The program, version and procedure follow the transaction id, message
+    /// type and RPC version at fixed offsets of every call.
+    pub(crate) fn procedure(call: &[u8]) -> Option<ProcedureKey> {
+        let word = |index: usize| {
+            call.get(index * 4..index * 4 + 4).map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+        };
+        // message type 0 is a call
+        if word(1)? != 0 {
+            return None;
+        }
+        Some((word(3)?, word(4)?, word(5)?))
+    }
+
+    /// Returns the capacity to reserve for the reply to a call
+    pub(crate) fn capacity(&self, procedure: Option<ProcedureKey>) -> usize {
+        procedure
+            .and_then(|key| self.estimates.get(&key))
+            .map_or(self.default_capacity, |&estimate| estimate.max(self.default_capacity))
+    }
+
+    /// Records the size of the reply to a call
+    ///
+    /// Larger replies raise the estimate at once, so the next one of the same
+    /// size fits, while smaller ones lower it gradually, so an occasional small
+    /// `READ` at the end of a file does not shrink the buffers of the next.
+    pub(crate) fn record(&mut self, procedure: Option<ProcedureKey>, size: usize) {
+        let Some(key) = procedure else {
+            return;
+        };
+        let size = size.min(MAX_ESTIMATE);
+        let estimate = self.estimates.entry(key).or_insert(size);
+        *estimate = size.max(*estimate - (*estimate >> DECAY_SHIFT));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(program: u32, version: u32, procedure: u32) -> Vec<u8> {
+        [7, 0, 2, program, version, procedure].iter().flat_map(|w: &u32| w.to_be_bytes()).collect()
+    }
+
+    #[test]
+    fn sizes_buffers_per_procedure() {
+        let mut sizer = ResponseSizer::new(8192);
+        let read = ResponseSizer::procedure(&call(100003, 3, 6));
+        let getattr = ResponseSizer::procedure(&call(100003, 3, 1));
+        assert_eq!(read, Some((100003, 3, 6)));
+        assert_eq!(ResponseSizer::procedure(&[0; 8]), None);
+        assert_eq!(sizer.capacity(read), 8192);
+
+        sizer.record(read, 1024 * 1024);
+        sizer.record(getattr, 120);
+        assert_eq!(sizer.capacity(read), 1024 * 1024);
+        assert_eq!(sizer.capacity(getattr), 8192);
+        assert_eq!(sizer.capacity(None), 8192);
+
+        // a single small reply lowers the estimate a little only
+        sizer.record(read, 100);
+        assert_eq!(sizer.capacity(read), 1024 * 1024 - 128 * 1024);
+        for _ in 0..64 {
+            sizer.record(read, 100);
+        }
+        assert_eq!(sizer.capacity(read), 8192);
+    }
+}
@@ -0,0 +1,539 @@
+//! Recording of the traffic of TCP connections for offline debugging.
+//!
+//! Interoperability problems with a particular client are easiest to analyze
+//! from the exact bytes exchanged. A [`Capture`] installed on a listener with
+//! [`crate::tcp::NFSTcpListener::with_capture`] records them in one of two
+//! forms:
+//!
+//! - [`CaptureFormat::Pcapng`]: the byte streams wrapped in synthesized IP and
+//!   TCP headers, which Wireshark and tcpdump dissect as ONC RPC and NFS
+//! - [`CaptureFormat::Json`]: one JSON object per line for every RPC record,
+//!   with the decoded call or reply header and the record as hex
+//!
+//! Capturing can be switched on and off at runtime, for all connections or for
+//! the connections of a single client:
+//!
+//! ```no_run
+//! # fn run() -> std::io::Result<()> {
+//! use std::sync::Arc;
+//!
+//! use nfs_mamont::protocol::rpc::{Capture, CaptureFormat};
+//!
+//! let capture = Arc::new(Capture::create("nfs.pcapng", CaptureFormat::Pcapng)?);
+//! capture.set_enabled(false);
+//! capture.set_client_enabled("192.0.2.7:812".parse().unwrap(), Some(true));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Only the traffic of connections is recorded, no SYN or FIN segments, and a
+//! connection captured from its middle may start within a record.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Cursor, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::io::AsyncWrite;
+use tracing::warn;
+
+use crate::protocol::xdr::{self, deserialize};
+
+/// Last-fragment flag of a record marking header
+const LAST_FRAGMENT: u32 = 1 << 31;
+/// Largest TCP payload of a synthesized segment
+const MAX_SEGMENT: usize = 32 * 1024;
+/// `LINKTYPE_RAW`: packets start with an IPv4 or IPv6 header
+const LINKTYPE_RAW: u16 = 101;
+
+/// Form of the recorded traffic
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaptureFormat {
+    /// pcapng file of synthesized TCP/IP packets
+    Pcapng,
+    /// JSON lines, one per RPC record
+    Json,
+}
+
+/// Direction of recorded traffic
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Direction {
+    /// From the client to the server
+    Call = 0,
+    /// From the server to the client
+    Reply = 1,
+}
+
+/// Sink for the traffic of the connections of a listener
+pub struct Capture {
+    format: CaptureFormat,
+    out: Mutex<Box<dyn Write + Send>>,
+    /// Whether connections without a setting of their client are captured
+    enabled: AtomicBool,
+    /// Settings of single clients
+    clients: Mutex<HashMap<SocketAddr, bool>>,
+    /// Number of the next connection
+    next_connection: AtomicU64,
+    /// Whether a write error was logged already
+    failed: AtomicBool,
+}
+
+impl std::fmt::Debug for Capture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Capture")
+            .field("format", &self.format)
+            .field("enabled", &self.enabled)
+            .field("clients", &self.clients)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Capture {
+    /// Creates a capture writing to `out`, enabled for every connection
+    ///
+    /// A pcapng capture writes the file header right away.
+    pub fn new(out: impl Write + Send + 'static, format: CaptureFormat) -> io::Result<Self> {
+        let mut out: Box<dyn Write + Send> = Box::new(out);
+        if format == CaptureFormat::Pcapng {
+            write_pcapng_header(&mut out)?;
+            out.flush()?;
+        }
+        Ok(Self {
+            format,
+            out: Mutex::new(out),
+            enabled: AtomicBool::new(true),
+            clients: Mutex::new(HashMap::new()),
+            next_connection: AtomicU64::new(1),
+            failed: AtomicBool::new(false),
+        })
+    }
+
+    /// Creates a capture writing to a new file at `path`
+    pub fn create(path: impl AsRef<Path>, format: CaptureFormat) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), format)
+    }
+
+    /// Returns the form of the recorded traffic
+    pub fn format(&self) -> CaptureFormat {
+        self.format
+    }
+
+    /// Switches capturing of connections without a client setting on or off
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Release);
+    }
+
+    /// Switches capturing of the connection from `client` on or off, or back
+    /// to the setting of all connections for `None`
+    pub fn set_client_enabled(&self, client: SocketAddr, enabled: Option<bool>) {
+        let mut clients = self.clients.lock().unwrap();
+        match enabled {
+            Some(enabled) => clients.insert(client, enabled),
+            None => clients.remove(&client),
+        };
+    }
+
+    /// Returns whether the connection from `client` is captured
+    pub fn is_enabled(&self, client: SocketAddr) -> bool {
+        match self.clients.lock().unwrap().get(&client) {
+            Some(&enabled) => enabled,
+            None => self.enabled.load(Ordering::Acquire),
+        }
+    }
+
+    /// Writes out buffered traffic
+    pub fn flush(&self) -> io::Result<()> {
+        self.out.lock().unwrap().flush()
+    }
+
+    /// Creates the tap of a new connection between `client` and `server`
+    pub(crate) fn tap(self: &Arc<Self>, client: SocketAddr, server: SocketAddr) -> Tap {
+        Tap {
+            capture: self.clone(),
+            connection: self.next_connection.fetch_add(1, Ordering::Relaxed),
+            client,
+            server,
+            seq: [1, 1],
+            framers: Default::default(),
+        }
+    }
+
+    /// Writes `data`, logging the first failure only
+    fn write(&self, data: &[u8]) {
+        let mut out = self.out.lock().unwrap();
+        if let Err(e) = out.write_all(data).and_then(|()| out.flush()) {
+            if !self.failed.swap(true, Ordering::Relaxed) {
+                warn!("Cannot write capture: {}", e);
+            }
+        }
+    }
+}
+
+/// Recorder of the traffic of one connection
+#[derive(Debug)]
+pub(crate) struct Tap {
+    capture: Arc<Capture>,
+    /// Number of the connection within the capture
+    connection: u64,
+    client: SocketAddr,
+    server: SocketAddr,
+    /// Next TCP sequence number per direction
+    seq: [u32; 2],
+    /// Record reassembly per direction, for JSON
+    framers: [Framer; 2],
+}
+
+impl Tap {
+    /// Records bytes of the stream in `direction`
+    pub(crate) fn record(&mut self, direction: Direction, data: &[u8]) {
+        let enabled = self.capture.is_enabled(self.client);
+        match self.capture.format {
+            CaptureFormat::Pcapng if enabled => self.write_segments(direction, data),
+            CaptureFormat::Pcapng => {
+                // skipped bytes show up as a gap in the sequence numbers
+                let seq = &mut self.seq[direction as usize];
+                *seq = seq.wrapping_add(data.len() as u32);
+            }
+            CaptureFormat::Json => {
+                // boundaries are tracked while disabled to find the next record
+                for record in self.framers[direction as usize].push(data, enabled) {
+                    self.capture.write(self.json_line(direction, &record).as_bytes());
+                }
+            }
+        }
+    }
+
+    /// Writes `data` as TCP segments in `direction`
+    fn write_segments(&mut self, direction: Direction, data: &[u8]) {
+        let (src, dst) = match direction {
+            Direction::Call => (self.client, self.server),
+            Direction::Reply => (self.server, self.client),
+        };
+        let mut blocks = Vec::new();
+        for segment in data.chunks(MAX_SEGMENT) {
+            let seq = self.seq[direction as usize];
+            let ack = self.seq[1 - direction as usize];
+            let packet = tcp_packet(src, dst, seq, ack, segment);
+            write_packet_block(&mut blocks, &packet);
+            self.seq[direction as usize] = seq.wrapping_add(segment.len() as u32);
+        }
+        self.capture.write(&blocks);
+    }
+
+    /// Formats a record as a JSON line
+    fn json_line(&self, direction: Direction, record: &[u8]) -> String {
+        let mut line = format!(
+            "{{\"time\":{:.6},\"connection\":{},\"client\":\"{}\",\"direction\":\"{}\",\"length\":{}",
+            now().as_secs_f64(),
+            self.connection,
+            self.client,
+            match direction {
+                Direction::Call => "call",
+                Direction::Reply => "reply",
+            },
+            record.len()
+        );
+        if let Ok(msg) = deserialize::<xdr::rpc::rpc_msg>(&mut Cursor::new(record)) {
+            let _ = write!(line, ",\"xid\":{}", msg.xid);
+            match msg.body {
+                xdr::rpc::rpc_body::CALL(call) => {
+                    let _ = write!(
+                        line,
+                        ",\"program\":{},\"version\":{},\"procedure\":{}",
+                        call.prog, call.vers, call.proc
+                    );
+                }
+                xdr::rpc::rpc_body::REPLY(reply) => {
+                    let status = match reply {
+                        xdr::rpc::reply_body::MSG_ACCEPTED(accepted) => match accepted.reply_data {
+                            xdr::rpc::accept_body::SUCCESS => "SUCCESS",
+                            xdr::rpc::accept_body::PROG_UNAVAIL => "PROG_UNAVAIL",
+                            xdr::rpc::accept_body::PROG_MISMATCH(_) => "PROG_MISMATCH",
+                            xdr::rpc::accept_body::PROC_UNAVAIL => "PROC_UNAVAIL",
+                            xdr::rpc::accept_body::GARBAGE_ARGS => "GARBAGE_ARGS",
+                        },
+                        xdr::rpc::reply_body::MSG_DENIED(denied) => match denied {
+                            xdr::rpc::rejected_reply::RPC_MISMATCH(_) => "RPC_MISMATCH",
+                            xdr::rpc::rejected_reply::AUTH_ERROR(_) => "AUTH_ERROR",
+                        },
+                    };
+                    let _ = write!(line, ",\"status\":\"{status}\"");
+                }
+            }
+        }
+        line.push_str(",\"data\":\"");
+        for byte in record {
+            let _ = write!(line, "{byte:02x}");
+        }
+        line.push_str("\"}\n");
+        line
+    }
+}
+
+/// Reassembly of records from a record-marked stream
+#[derive(Debug)]
+struct Framer {
+    /// Bytes of the current fragment header
+    header: Vec<u8>,
+    /// Bytes of the current fragment still to come
+    left: usize,
+    /// Whether the current fragment is the last of its record
+    last: bool,
+    /// Whether the next fragment starts a record
+    at_record_start: bool,
+    /// Data of the current record, if it is kept
+    record: Option<Vec<u8>>,
+}
+
+impl Default for Framer {
+    fn default() -> Self {
+        Self { header: Vec::new(), left: 0, last: false, at_record_start: true, record: None }
+    }
+}
+
+impl Framer {
+    /// Consumes stream bytes, returning the records completed by them
+    ///
+    /// Records starting while `keep` is false are skipped.
+    fn push(&mut self, mut data: &[u8], keep: bool) -> Vec<Vec<u8>> {
+        let mut records = Vec::new();
+        while !data.is_empty() {
+            if self.header.len() < 4 {
+                let len = (4 - self.header.len()).min(data.len());
+                self.header.extend_from_slice(&data[..len]);
+                data = &data[len..];
+                if self.header.len() < 4 {
+                    break;
+                }
+                let header = u32::from_be_bytes(self.header[..].try_into().unwrap());
+                self.left = (header & !LAST_FRAGMENT) as usize;
+                self.last = header & LAST_FRAGMENT != 0;
+                if self.at_record_start && keep {
+                    self.record = Some(Vec::new());
+                }
+            }
+            let len = self.left.min(data.len());
+            if let Some(record) = &mut self.record {
+                record.extend_from_slice(&data[..len]);
+            }
+            self.left -= len;
+            data = &data[len..];
+            if self.left == 0 {
+                self.header.clear();
+                self.at_record_start = self.last;
+                if self.last {
+                    records.extend(self.record.take());
+                }
+            }
+        }
+        records
+    }
+}
+
+/// Returns the time since the epoch
+fn now() -> std::time::Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+/// Appends a pcapng block of `block_type` with `body` to `out`
+fn write_block(out: &mut Vec<u8>, block_type: u32, body: &[u8]) {
+    let padded = body.len().next_multiple_of(4);
+    let total = (12 + padded) as u32;
+    out.extend_from_slice(&block_type.to_le_bytes());
+    out.extend_from_slice(&total.to_le_bytes());
+    out.extend_from_slice(body);
+    out.resize(out.len() + padded - body.len(), 0);
+    out.extend_from_slice(&total.to_le_bytes());
+}
+
+/// Writes the section header and the interface description of a pcapng file
+fn write_pcapng_header(out: &mut impl Write) -> io::Result<()> {
+    let mut blocks = Vec::new();
+    let mut section = Vec::new();
+    section.extend_from_slice(&0x1A2B_3C4D_u32.to_le_bytes());
+    section.extend_from_slice(&1_u16.to_le_bytes());
+    section.extend_from_slice(&0_u16.to_le_bytes());
+    // section length unknown
+    section.extend_from_slice(&(-1_i64).to_le_bytes());
+    write_block(&mut blocks, 0x0A0D_0D0A, &section);
+    let mut interface = Vec::new();
+    interface.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+    interface.extend_from_slice(&0_u16.to_le_bytes());
+    // no snapshot length limit
+    interface.extend_from_slice(&0_u32.to_le_bytes());
+    write_block(&mut blocks, 1, &interface);
+    out.write_all(&blocks)
+}
+
+/// Appends an enhanced packet block with `packet`, stamped with the current time
+fn write_packet_block(out: &mut Vec<u8>, packet: &[u8]) {
+    let micros = now().as_micros() as u64;
+    let mut body = Vec::with_capacity(20 + packet.len());
+    // interface 0
+    body.extend_from_slice(&0_u32.to_le_bytes());
+    body.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+    body.extend_from_slice(&(micros as u32).to_le_bytes());
+    body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+    body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+    body.extend_from_slice(packet);
+    write_block(out, 6, &body);
+}
+
+/// Returns the one's complement sum of 16-bit words, as used by IP checksums
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|word| u32::from(u16::from_be_bytes([word[0], *word.get(1).unwrap_or(&0)])))
+        .sum();
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Builds an IP packet carrying a TCP segment with `payload`
+///
+/// The TCP checksum is left 0, which dissectors do not verify by default.
+fn tcp_packet(src: SocketAddr, dst: SocketAddr, seq: u32, ack: u32, payload: &[u8]) -> Vec<u8> {
+    let mut tcp = Vec::with_capacity(20 + payload.len());
+    tcp.extend_from_slice(&src.port().to_be_bytes());
+    tcp.extend_from_slice(&dst.port().to_be_bytes());
+    tcp.extend_from_slice(&seq.to_be_bytes());
+    tcp.extend_from_slice(&ack.to_be_bytes());
+    // 20 byte header, PSH and ACK, window, checksum, urgent pointer
+    tcp.extend_from_slice(&[0x50, 0x18, 0xFF, 0xFF, 0, 0, 0, 0]);
+    tcp.extend_from_slice(payload);
+
+    let (src_ip, dst_ip) = match (src.ip(), dst.ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            let mut packet = Vec::with_capacity(20 + tcp.len());
+            packet.extend_from_slice(&[0x45, 0]);
+            packet.extend_from_slice(&((20 + tcp.len()) as u16).to_be_bytes());
+            // identification, don't fragment, TTL 64, TCP, checksum
+            packet.extend_from_slice(&[0, 0, 0x40, 0, 64, 6, 0, 0]);
+            packet.extend_from_slice(&src.octets());
+            packet.extend_from_slice(&dst.octets());
+            let sum = checksum(&packet);
+            packet[10..12].copy_from_slice(&sum.to_be_bytes());
+            packet.extend_from_slice(&tcp);
+            return packet;
+        }
+        (src, dst) => (to_v6(src), to_v6(dst)),
+    };
+    let mut packet = Vec::with_capacity(40 + tcp.len());
+    packet.extend_from_slice(&[0x60, 0, 0, 0]);
+    packet.extend_from_slice(&(tcp.len() as u16).to_be_bytes());
+    // TCP, hop limit 64
+    packet.extend_from_slice(&[6, 64]);
+    packet.extend_from_slice(&src_ip.octets());
+    packet.extend_from_slice(&dst_ip.octets());
+    packet.extend_from_slice(&tcp);
+    packet
+}
+
+/// Converts an address to IPv6, mapping IPv4 addresses
+fn to_v6(ip: IpAddr) -> std::net::Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+/// Writer recording everything written through it as replies of a connection
+pub(crate) struct TapWriter<'a, W> {
+    pub(crate) inner: &'a mut W,
+    pub(crate) tap: &'a mut Tap,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for TapWriter<'_, W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let poll = Pin::new(&mut *this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            this.tap.record(Direction::Reply, &buf[..written]);
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Buffer shared with a capture
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn json_lines_follow_records_and_toggling() {
+        let out = Shared::default();
+        let capture = Arc::new(Capture::new(out.clone(), CaptureFormat::Json).unwrap());
+        let client = "127.0.0.1:900".parse().unwrap();
+        let mut tap = capture.tap(client, "127.0.0.1:2049".parse().unwrap());
+
+        // two fragments, delivered in pieces
+        let stream = [&[0, 0, 0, 2, 0xAA, 0xBB][..], &[0x80, 0, 0, 1, 0xCC]].concat();
+        tap.record(Direction::Call, &stream[..3]);
+        tap.record(Direction::Call, &stream[3..]);
+        capture.set_client_enabled(client, Some(false));
+        tap.record(Direction::Call, &stream[..4]);
+        capture.set_client_enabled(client, None);
+        // the rest of the skipped record is not reported
+        tap.record(Direction::Call, &stream[4..]);
+        tap.record(Direction::Reply, &[0x80, 0, 0, 1, 0xDD]);
+
+        let text = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("\"direction\":\"call\",\"length\":3"));
+        assert!(lines[0].ends_with("\"data\":\"aabbcc\"}"));
+        assert!(lines[1].contains("\"direction\":\"reply\""));
+    }
+
+    #[test]
+    fn pcapng_packets_carry_the_stream() {
+        let out = Shared::default();
+        let capture = Arc::new(Capture::new(out.clone(), CaptureFormat::Pcapng).unwrap());
+        let mut tap = capture.tap("[::1]:900".parse().unwrap(), "[::1]:2049".parse().unwrap());
+        tap.record(Direction::Call, b"abc");
+
+        let data = out.0.lock().unwrap().clone();
+        // section header and interface description blocks
+        assert_eq!(data[..4], 0x0A0D_0D0A_u32.to_le_bytes());
+        let packet_block = &data[28 + 20..];
+        assert_eq!(packet_block[..4], 6_u32.to_le_bytes());
+        // IPv6 and TCP headers, then the payload
+        assert_eq!(packet_block[28], 0x60);
+        assert_eq!(&packet_block[28 + 60..28 + 63], b"abc");
+        assert_eq!(packet_block.len(), 32 + (60 + 3_usize).next_multiple_of(4));
+    }
+}
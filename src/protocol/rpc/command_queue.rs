@@ -4,11 +4,11 @@
 //! are processed in the exact order they were received, preserving FIFO semantics
 //! necessary for proper NFS protocol operation.
 
-use anyhow::anyhow;
 use tokio::sync::mpsc;
 use tracing::{debug, error, trace};
 
 use crate::protocol::rpc;
+use crate::protocol::rpc::buffer_sizing::ResponseSizer;
 
 /// Represents a response buffer that minimizes data copying
 pub struct ResponseBuffer {
@@ -16,12 +16,14 @@ pub struct ResponseBuffer {
     buffer: Vec<u8>,
     /// Indicates that the buffer contains data to send
     has_content: bool,
+    /// Data streamed after the buffered reply
+    body: Option<rpc::StreamedBody>,
 }
 
 impl ResponseBuffer {
     /// Creates a new response buffer with pre-allocated capacity
     pub fn with_capacity(capacity: usize) -> Self {
-        Self { buffer: Vec::with_capacity(capacity), has_content: false }
+        Self { buffer: Vec::with_capacity(capacity), has_content: false, body: None }
     }
 
     /// Gets the internal buffer for writing
@@ -39,15 +41,16 @@ impl ResponseBuffer {
         self.has_content
     }
 
-    /// Takes the internal buffer, consuming the structure
-    pub fn into_inner(self) -> Vec<u8> {
-        self.buffer
+    /// Takes the buffered reply and its streamed body, consuming the structure
+    pub fn into_reply(self) -> rpc::Reply {
+        rpc::Reply { data: self.buffer, body: self.body }
     }
 
     /// Clears the buffer for reuse
     pub fn clear(&mut self) {
         self.buffer.clear();
         self.has_content = false;
+        self.body = None;
     }
 }
 
@@ -61,15 +64,15 @@ pub struct RpcCommand {
 }
 
 /// Command processing result
-pub type CommandResult = Result<Option<ResponseBuffer>, anyhow::Error>;
+pub type CommandResult = Result<Option<ResponseBuffer>, rpc::ServerError>;
 
 /// Type for asynchronous RPC command processor
-pub type AsyncCommandProcessor = for<'a> fn(
-    data: &[u8],
-    output: &'a mut ResponseBuffer,
-    context: rpc::Context,
-)
-    -> futures::future::BoxFuture<'a, anyhow::Result<bool>>;
+pub type AsyncCommandProcessor =
+    for<'a> fn(
+        data: Vec<u8>,
+        output: &'a mut ResponseBuffer,
+        context: rpc::Context,
+    ) -> futures::future::BoxFuture<'a, Result<bool, rpc::ServerError>>;
 
 /// Queue for sequential processing of RPC commands
 ///
@@ -97,7 +100,8 @@ impl CommandQueue {
     ///
     /// * `processor` - Asynchronous function for processing RPC commands
     /// * `result_sender` - Channel for sending processing results
-    /// * `buffer_capacity` - Initial capacity for response buffers
+    /// * `buffer_capacity` - Least capacity reserved for a response, and the
+    ///   capacity for procedures without replies seen yet
     pub fn new(
         processor: AsyncCommandProcessor,
         result_sender: mpsc::UnboundedSender<CommandResult>,
@@ -107,33 +111,38 @@ impl CommandQueue {
 
         // Start worker task that processes commands in order
         tokio::spawn(async move {
-            // Create reusable buffer for responses
-            let mut output_buffer = ResponseBuffer::with_capacity(buffer_capacity);
+            // Create reusable buffer for responses, sized per call
+            let mut output_buffer = ResponseBuffer::with_capacity(0);
+            let mut sizer = ResponseSizer::new(buffer_capacity);
 
             while let Some(command) = command_receiver.recv().await {
                 trace!("Processing command from queue");
 
-                // Clear buffer for reuse
+                // Clear buffer for reuse and make room for the expected reply
                 output_buffer.clear();
+                let procedure = ResponseSizer::procedure(&command.data);
+                output_buffer.buffer.reserve(sizer.capacity(procedure));
 
                 // Call async processor
-                let result =
-                    match processor(&command.data, &mut output_buffer, command.context).await {
-                        Ok(true) => {
-                            // Processor indicated response needs to be sent
-                            output_buffer.mark_has_content();
-                            let buffer_to_send = std::mem::replace(
-                                &mut output_buffer,
-                                ResponseBuffer::with_capacity(buffer_capacity),
-                            );
-                            Ok(Some(buffer_to_send))
-                        }
-                        Ok(false) => {
-                            // No response needed (e.g. retransmission)
-                            Ok(None)
-                        }
-                        Err(e) => Err(e),
-                    };
+                let reply_stream = command.context.reply_stream.clone();
+                let processed = processor(command.data, &mut output_buffer, command.context).await;
+                // take the body even without a reply, so it does not leak into the next one
+                output_buffer.body = reply_stream.and_then(|stream| stream.take());
+                sizer.record(procedure, output_buffer.buffer.len());
+                let result = match processed {
+                    Ok(true) => {
+                        // Processor indicated response needs to be sent
+                        output_buffer.mark_has_content();
+                        let buffer_to_send =
+                            std::mem::replace(&mut output_buffer, ResponseBuffer::with_capacity(0));
+                        Ok(Some(buffer_to_send))
+                    }
+                    Ok(false) => {
+                        // No response needed (e.g. retransmission)
+                        Ok(None)
+                    }
+                    Err(e) => Err(e),
+                };
 
                 // Send result
                 if let Err(e) = result_sender.send(result) {
@@ -165,9 +174,9 @@ impl CommandQueue {
         &self,
         data: Vec<u8>,
         context: rpc::Context,
-    ) -> Result<(), anyhow::Error> {
+    ) -> Result<(), rpc::ServerError> {
         self.command_sender
             .send(RpcCommand { data, context })
-            .map_err(|e| anyhow!("Failed to send command: {}", e))
+            .map_err(|e| rpc::ServerError::Internal(format!("Failed to send command: {e}")))
     }
 }
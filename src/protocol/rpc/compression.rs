@@ -0,0 +1,288 @@
+//! Compression of replies between nfs-mamont endpoints, a private extension.
+//!
+//! Replies to `READ` and `READDIRPLUS` make up most of the traffic of a
+//! connection, and across a WAN, e.g. when a proxy built on
+//! [`crate::client::Client`] re-exports a remote server, the link rather than
+//! the backend limits throughput. Standard NFS clients know nothing of
+//! compression, so it is only used when the client asks for it.
+//!
+//! A client that supports the extension calls procedure `NEGOTIATE` of the
+//! private program [`COMPRESSION_PROGRAM`] with the [`Algorithm`]s it can decompress, in
+//! order of preference. A server with compression enabled answers with the
+//! first one it supports, or 0 for none; other servers answer
+//! `PROG_UNAVAIL`. From then on, the server may send any reply of the
+//! connection as a compressed record:
+//!
+//! | bytes  | content                                        |
+//! |--------|------------------------------------------------|
+//! | 0..4   | transaction ID of the reply                    |
+//! | 4..8   | [`COMPRESSED_MSG_TYPE`] plus the algorithm ID  |
+//! | 8..12  | length of the uncompressed record              |
+//! | 12..   | the whole reply record, compressed             |
+//!
+//! The message type is neither `CALL` nor `REPLY`, so compressed records
+//! cannot be mistaken for plain ones and need no further framing: the client
+//! passes every record it receives through [`decompress_record`]. Small
+//! replies, replies that do not shrink, and replies streamed with
+//! [`super::ReplyStream`] are sent as is. Calls are never compressed.
+//!
+//! LZ4 needs the `compression` feature, which is enabled by default. Without
+//! it, servers and clients negotiate no algorithm.
+
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use tracing::debug;
+
+use crate::protocol::rpc;
+use crate::protocol::xdr::{self, deserialize, Serialize};
+
+/// Program number of the extension, from the range for private use
+pub const COMPRESSION_PROGRAM: u32 = 0x2000_4d4d;
+/// Version of the extension
+pub const COMPRESSION_VERSION: u32 = 1;
+/// Procedure that does nothing
+const PROC_NULL: u32 = 0;
+/// Procedure choosing the algorithm of the replies of the connection
+pub const COMPRESSION_PROC_NEGOTIATE: u32 = 1;
+
+/// Message type of compressed records, with the algorithm ID in the low byte
+pub const COMPRESSED_MSG_TYPE: u32 = 0x4d4d_5a00;
+/// Length of the header of compressed records
+const HEADER_LEN: usize = 12;
+
+/// Compression algorithms of the extension
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Algorithm {
+    /// LZ4 block format, fast enough to keep up with fast links
+    Lz4 = 1,
+}
+
+impl Algorithm {
+    /// Returns the algorithm with the given ID
+    pub fn from_id(id: u32) -> Option<Self> {
+        Self::supported().iter().copied().find(|algorithm| *algorithm as u32 == id)
+    }
+
+    /// Returns the algorithms this build can compress and decompress
+    pub fn supported() -> &'static [Self] {
+        if cfg!(feature = "compression") {
+            &[Self::Lz4]
+        } else {
+            &[]
+        }
+    }
+
+    /// Compresses `data`, `None` if this build lacks the algorithm
+    fn compress(self, data: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            #[cfg(feature = "compression")]
+            Self::Lz4 => Some(lz4_flex::block::compress(data)),
+            #[cfg(not(feature = "compression"))]
+            Self::Lz4 => {
+                let _ = data;
+                None
+            }
+        }
+    }
+
+    /// Decompresses `data` into `len` bytes
+    fn decompress(self, data: &[u8], len: usize) -> io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "compression")]
+            Self::Lz4 => lz4_flex::block::decompress(data, len)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+            #[cfg(not(feature = "compression"))]
+            Self::Lz4 => {
+                let _ = (data, len);
+                Err(io::Error::new(io::ErrorKind::Unsupported, "LZ4 support is not built in"))
+            }
+        }
+    }
+}
+
+/// Limits of reply compression
+#[derive(Clone, Copy, Debug)]
+pub struct CompressionConfig {
+    /// Replies shorter than this are sent as is, as compressing them saves
+    /// less than it costs
+    pub min_size: usize,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self { min_size: 1024 }
+    }
+}
+
+/// Compression of the replies of one connection
+///
+/// Every connection has its own instance, as the algorithm is negotiated per
+/// connection.
+#[derive(Debug)]
+pub struct ReplyCompression {
+    config: CompressionConfig,
+    /// ID of the negotiated algorithm, 0 before negotiation
+    algorithm: AtomicU32,
+}
+
+impl ReplyCompression {
+    /// Creates the compression of a new connection, which compresses nothing
+    /// until the client negotiates an algorithm
+    pub fn new(config: CompressionConfig) -> Self {
+        Self { config, algorithm: AtomicU32::new(0) }
+    }
+
+    /// Returns the negotiated algorithm
+    pub fn algorithm(&self) -> Option<Algorithm> {
+        Algorithm::from_id(self.algorithm.load(Ordering::Relaxed))
+    }
+
+    /// Chooses the first of the algorithms offered by the client that this
+    /// build supports, and uses it for the replies from now on
+    pub fn negotiate(&self, offered: &[u32]) -> Option<Algorithm> {
+        let chosen = offered.iter().find_map(|id| Algorithm::from_id(*id));
+        self.algorithm.store(chosen.map_or(0, |algorithm| algorithm as u32), Ordering::Relaxed);
+        chosen
+    }
+
+    /// Returns a reply record as it is sent: compressed if an algorithm was
+    /// negotiated and compression shrinks it, as is otherwise
+    pub fn compress(&self, record: Vec<u8>) -> Vec<u8> {
+        let Some(algorithm) = self.algorithm() else {
+            return record;
+        };
+        if record.len() < self.config.min_size.max(HEADER_LEN) {
+            return record;
+        }
+        let Some(compressed) = algorithm.compress(&record) else {
+            return record;
+        };
+        if compressed.len() + HEADER_LEN >= record.len() {
+            return record;
+        }
+        let mut out = Vec::with_capacity(HEADER_LEN + compressed.len());
+        out.extend_from_slice(&record[..4]);
+        out.extend_from_slice(&(COMPRESSED_MSG_TYPE | algorithm as u32).to_be_bytes());
+        out.extend_from_slice(&(record.len() as u32).to_be_bytes());
+        out.extend_from_slice(&compressed);
+        out
+    }
+}
+
+/// Returns a received record uncompressed
+///
+/// Records that are not compressed are returned as they are. The length a
+/// record claims to have uncompressed is checked against `max_len` before any
+/// memory is allocated for it, as it comes from the peer.
+///
+/// # Arguments
+///
+/// * `record` - The received record
+/// * `max_len` - Longest record accepted, uncompressed
+///
+/// # Returns
+///
+/// * `io::Result<Vec<u8>>` - The plain record, or an error of kind
+///   `InvalidData` if it cannot be decompressed or is longer than `max_len`
+pub fn decompress_record(record: Vec<u8>, max_len: usize) -> io::Result<Vec<u8>> {
+    if record.len() < HEADER_LEN {
+        return Ok(record);
+    }
+    let word = |at: usize| u32::from_be_bytes(record[at..at + 4].try_into().unwrap());
+    let msg_type = word(4);
+    if msg_type & !0xff != COMPRESSED_MSG_TYPE {
+        return Ok(record);
+    }
+    let Some(algorithm) = Algorithm::from_id(msg_type & 0xff) else {
+        let message = format!("record compressed with unknown algorithm {}", msg_type & 0xff);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+    };
+    let len = word(8) as usize;
+    if len > max_len {
+        let message = format!("compressed record of {len} bytes exceeds {max_len} bytes");
+        return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+    }
+    let plain = algorithm.decompress(&record[HEADER_LEN..], len)?;
+    if plain.get(..4) != Some(&record[..4]) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "compressed record is corrupt"));
+    }
+    Ok(plain)
+}
+
+/// Handles calls of the compression extension
+///
+/// Connections without compression answer `PROG_UNAVAIL`, like servers that
+/// do not know the extension.
+///
+/// # Arguments
+///
+/// * `xid` - RPC transaction ID
+/// * `call` - The RPC call body
+/// * `input` - Input stream containing the offered algorithm IDs
+/// * `output` - Output stream for writing the response
+/// * `context` - Server context holding the compression of the connection
+pub fn handle_compression(
+    xid: u32,
+    call: &xdr::rpc::call_body,
+    input: &mut impl Read,
+    output: &mut impl Write,
+    context: &rpc::Context,
+) -> Result<(), rpc::ServerError> {
+    let Some(compression) = &context.compression else {
+        xdr::rpc::prog_unavail_reply_message(xid).serialize(output)?;
+        return Ok(());
+    };
+    if call.vers != COMPRESSION_VERSION {
+        xdr::rpc::prog_mismatch_reply_message(xid, COMPRESSION_VERSION).serialize(output)?;
+        return Ok(());
+    }
+    match call.proc {
+        PROC_NULL => xdr::rpc::make_success_reply(xid).serialize(output)?,
+        COMPRESSION_PROC_NEGOTIATE => {
+            let offered = deserialize::<Vec<u32>>(input)?;
+            let chosen = compression.negotiate(&offered);
+            debug!("negotiated reply compression {:?} from {:?}", chosen, offered);
+            xdr::rpc::make_success_reply(xid).serialize(output)?;
+            chosen.map_or(0, |algorithm| algorithm as u32).serialize(output)?;
+        }
+        _ => xdr::rpc::proc_unavail_reply_message(xid).serialize(output)?,
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "compression"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compresses_negotiated_replies() {
+        let compression = ReplyCompression::new(CompressionConfig { min_size: 64 });
+        let record: Vec<u8> = [7, 0, 0, 1].into_iter().chain([0; 4096]).collect();
+        assert_eq!(compression.compress(record.clone()), record);
+
+        assert_eq!(compression.negotiate(&[99, Algorithm::Lz4 as u32]), Some(Algorithm::Lz4));
+        let sent = compression.compress(record.clone());
+        assert!(sent.len() < 100, "{} bytes sent", sent.len());
+        assert_eq!(decompress_record(sent.clone(), 4100).unwrap(), record);
+        // short records are left alone
+        assert_eq!(compression.compress(record[..32].to_vec()), &record[..32]);
+        assert_eq!(decompress_record(record[..32].to_vec(), 4100).unwrap(), &record[..32]);
+
+        // the claimed length is checked before decompressing
+        let err = decompress_record(sent, 4099).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let bomb: Vec<u8> = [7, 0, 0, 1]
+            .into_iter()
+            .chain((COMPRESSED_MSG_TYPE | Algorithm::Lz4 as u32).to_be_bytes())
+            .chain(u32::MAX.to_be_bytes())
+            .chain([0; 8])
+            .collect();
+        let err = decompress_record(bomb, rpc::DEFAULT_MAX_RECORD_LENGTH).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        assert_eq!(compression.negotiate(&[99]), None);
+        assert_eq!(compression.compress(record.clone()), record);
+    }
+}
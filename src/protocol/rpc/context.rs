@@ -14,12 +14,17 @@
 //! server configuration.
 
 use std::fmt;
+use std::net::SocketAddr;
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
 use tokio::sync::mpsc;
+use tracing::warn;
 
-use crate::protocol::nfs::portmap::PortmapTable;
-use crate::protocol::xdr;
+use crate::clock::{Clock, SystemClock};
+use crate::entropy::{Entropy, SystemEntropy};
+use crate::protocol::nfs::{self, portmap::PortmapTable};
+use crate::protocol::xdr::{self, nfs3};
 use crate::vfs;
 
 /// Represents the execution context for RPC operations
@@ -35,13 +40,19 @@ use crate::vfs;
 ///
 /// Each RPC connection maintains its own Context instance, ensuring proper isolation
 /// between different client sessions and enabling accurate tracking of client state.
+/// The tables, caches and counters it refers to through an `Arc` are shared by
+/// all connections of a listener, unless their documentation says otherwise.
 #[derive(Clone)]
 pub struct Context {
     /// Port number on which the server is listening
     pub local_port: u16,
 
-    /// Client's network address (IP:port) used for logging and request tracking
-    pub client_addr: String,
+    /// Client's network address used for logging and request tracking
+    ///
+    /// Link-local IPv6 addresses keep the scope ID of the interface the client
+    /// was reached on, so equal addresses on different links are different
+    /// clients.
+    pub client_addr: SocketAddr,
 
     /// UNIX-style authentication credentials from the client
     /// Contains user ID, group IDs, and other identity information
@@ -55,6 +66,9 @@ pub struct Context {
     /// Used to track file system mount status changes
     pub mount_signal: Option<mpsc::Sender<bool>>,
 
+    /// Mounts held by clients, listed by `MOUNTPROC3_DUMP`
+    pub mount_table: Arc<nfs::mount_table::MountTable>,
+
     /// Name of the exported file system available to clients
     pub export_name: Arc<String>,
 
@@ -65,6 +79,340 @@ pub struct Context {
     /// Portmap table storing port-to-program mappings
     /// (like a portmap service)
     pub portmap_table: Arc<RwLock<PortmapTable>>,
+
+    /// Whether only portmapper calls are answered, as on the port of the
+    /// built-in portmapper; calls to other programs get `PROG_UNAVAIL`
+    pub portmap_only: bool,
+
+    /// Coalescer for `UNSTABLE` writes
+    /// Writes go straight to the VFS when not set
+    pub write_coalescer: Option<Arc<nfs::v3::WriteCoalescer>>,
+
+    /// Ranges written `UNSTABLE` and not committed yet, and the write verifier
+    pub unstable_writes: Arc<nfs::v3::UnstableWrites>,
+
+    /// Accounting of the bytes and files used, reported by `FSSTAT`
+    /// Only used when the VFS does not implement `fsstat`
+    pub space_counter: Option<Arc<crate::write_counter::SpaceCounter>>,
+
+    /// Deferred removal of files in use, if enabled
+    pub soft_delete: Option<Arc<nfs::v3::SoftDelete>>,
+
+    /// Checksums of the data written, verified on reads, if enabled
+    pub checksums: Option<Arc<nfs::v3::Checksums>>,
+
+    /// Inspection of the content written, if enabled
+    pub content_inspection: Option<Arc<nfs::v3::ContentInspection>>,
+
+    /// Channel publishing the activity of clients, if enabled
+    pub events: Option<Arc<nfs::events::Events>>,
+
+    /// Detector of sequential reads that triggers VFS read-ahead hints
+    /// No hints are issued when not set
+    pub read_ahead: Option<Arc<nfs::v3::ReadAheadDetector>>,
+
+    /// Cache of names `LOOKUP` recently found missing, if enabled
+    pub negative_lookups: Option<Arc<nfs::v3::NegativeLookupCache>>,
+
+    /// Cache of `READ` results, if enabled
+    pub read_cache: Option<Arc<nfs::v3::ReadCache>>,
+
+    /// Maxima applied to the sizes requested by `READDIRPLUS` calls
+    pub readdirplus_limits: Arc<nfs::v3::ReadDirPlusLimits>,
+
+    /// Whether `READDIRPLUS` is served
+    /// Refused with `NFS3ERR_NOTSUPP` otherwise, so clients use `READDIR`
+    pub readdirplus: bool,
+
+    /// Maxima of file sizes and directory entries clients can create
+    /// Unlimited by default
+    pub export_limits: nfs::v3::ExportLimits,
+
+    /// Mode and owner given to new files the client sends none for
+    /// Left to the VFS when not set
+    pub creation_defaults: Option<nfs::v3::CreationDefaults>,
+
+    /// Locks serializing changes of the same directory
+    /// Namespace changes run concurrently when not set
+    pub dir_locks: Option<Arc<nfs::v3::DirLocks>>,
+
+    /// Whether file handles are bound to the export they were issued under
+    /// See [`Context::id_to_fh`] and [`Context::fh_to_id`]
+    pub subtree_check: bool,
+
+    /// Secret key of the export tags of file handles under subtree checking
+    /// See [`Context::export_tag`]
+    pub handle_key: [u64; 2],
+
+    /// Whether the zero-length public file handle of WebNFS stands for the
+    /// root of the export
+    /// See [`Context::is_public_fh`]
+    pub public_fh: bool,
+
+    /// End of the grace period after a restart during which stale handles are
+    /// passed to [`vfs::NFSFileSystem::rehydrate_handle`]
+    /// Stale handles are rejected right away when not set
+    pub handle_grace_until: Option<Instant>,
+
+    /// How long clients wait for a reply
+    /// Calls get a deadline this far after their arrival when set, see
+    /// [`vfs::request::RequestContext`]
+    pub request_timeout: Option<Duration>,
+
+    /// Longest record accepted from clients
+    /// Longer records are answered with `GARBAGE_ARGS`, and transfer sizes
+    /// announced by `FSINFO` are capped to fit, see [`Context::max_transfer_size`]
+    pub max_record_length: usize,
+
+    /// Counters of handled calls and cache hit rates
+    pub stats: Arc<super::ServerStats>,
+
+    /// Share of the connection in the calls processed at once, if the
+    /// listener schedules calls
+    /// Calls are processed as soon as they arrive when not set
+    pub lane: Option<Arc<super::Lane>>,
+
+    /// Concurrency limits per class of procedures, if any
+    pub priorities: Option<Arc<super::PriorityLimits>>,
+
+    /// Credential flavors accepted per program
+    /// Calls with other flavors are denied before dispatch
+    pub auth_policy: Arc<super::AuthPolicy>,
+
+    /// Handles issued for `AUTH_UNIX` credentials, if enabled
+    pub short_auth: Option<Arc<super::ShortAuth>>,
+
+    /// Mapping of client identities to the anonymous user
+    /// Applied to the credentials of every call before dispatch
+    pub squash: super::Squash,
+
+    /// Interoperability workarounds for the client of the connection
+    pub quirks: super::ClientQuirks,
+
+    /// Slot for `READ` data streamed after the reply, see [`super::ReplyStream`]
+    /// Only set for connections whose writer sends the streamed body
+    pub reply_stream: Option<super::ReplyStream>,
+
+    /// Compression of the replies of this connection, if offered to clients
+    /// Not shared between connections, see [`super::ReplyCompression`]
+    pub compression: Option<Arc<super::ReplyCompression>>,
+
+    /// Hooks run around the dispatch of every call
+    pub hooks: Arc<super::DispatchHooks>,
+
+    /// Names hidden from directory listings and lookups
+    /// All names are visible when not set
+    pub name_filter: Option<Arc<nfs::v3::NameFilter>>,
+
+    /// Mapping of backend errors to status codes, passed to the VFS with every
+    /// call; only the built-in conversions apply when not set
+    pub error_mapper: Option<vfs::ErrorMapper>,
+
+    /// Source of the current time, e.g. for `SET_TO_SERVER_TIME`
+    pub clock: Arc<dyn Clock>,
+
+    /// Source of the random values handed out, e.g. `AUTH_SHORT` handles
+    pub entropy: Arc<dyn Entropy>,
+}
+
+/// Length of the export tag appended to file handles under subtree checking
+const EXPORT_TAG_SIZE: usize = 8;
+
+impl Context {
+    /// Creates a context serving `vfs` with default settings
+    ///
+    /// The context has no optional feature enabled, serves `READDIRPLUS`,
+    /// names its export `/` and has a transaction tracker, portmap table and
+    /// mount table of its own. Callers set the fields they need with struct
+    /// update syntax, e.g. `Context { client_addr, ..Context::new(vfs) }`, so
+    /// that options added later do not break them.
+    pub fn new(vfs: Arc<dyn vfs::NFSFileSystem + Send + Sync>) -> Self {
+        let entropy = Arc::new(SystemEntropy::default());
+        Self {
+            local_port: 0,
+            client_addr: ([0, 0, 0, 0], 0).into(),
+            auth: xdr::rpc::auth_unix::default(),
+            vfs,
+            mount_signal: None,
+            mount_table: Arc::default(),
+            export_name: Arc::new("/".to_string()),
+            transaction_tracker: Arc::new(super::TransactionTracker::new(Duration::from_secs(60))),
+            portmap_table: Arc::default(),
+            portmap_only: false,
+            write_coalescer: None,
+            unstable_writes: Arc::default(),
+            space_counter: None,
+            soft_delete: None,
+            checksums: None,
+            content_inspection: None,
+            events: None,
+            read_ahead: None,
+            negative_lookups: None,
+            read_cache: None,
+            readdirplus_limits: Arc::default(),
+            readdirplus: true,
+            export_limits: nfs::v3::ExportLimits::default(),
+            creation_defaults: None,
+            dir_locks: None,
+            subtree_check: false,
+            handle_key: [entropy.next_u64(), entropy.next_u64()],
+            public_fh: false,
+            handle_grace_until: None,
+            request_timeout: None,
+            max_record_length: super::DEFAULT_MAX_RECORD_LENGTH,
+            stats: Arc::default(),
+            lane: None,
+            priorities: None,
+            auth_policy: Arc::default(),
+            short_auth: None,
+            squash: super::Squash::default(),
+            quirks: super::ClientQuirks::default(),
+            reply_stream: None,
+            compression: None,
+            hooks: Arc::default(),
+            name_filter: None,
+            error_mapper: None,
+            clock: Arc::new(SystemClock),
+            entropy,
+        }
+    }
+
+    /// Returns the tag binding a file handle of the VFS to the export of this
+    /// context
+    ///
+    /// The tag is a SipHash-2-4 of the export name and the handle under
+    /// [`Context::handle_key`]. Clients know both, but without the key they
+    /// can neither derive the tag of a handle they were not given nor reuse a
+    /// tag of another export.
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - The handle produced by the VFS
+    pub fn export_tag(&self, inner: &[u8]) -> u64 {
+        // export names are UTF-8, so the separator cannot occur in them
+        let mut data = Vec::with_capacity(self.export_name.len() + 1 + inner.len());
+        data.extend_from_slice(self.export_name.as_bytes());
+        data.push(0xff);
+        data.extend_from_slice(inner);
+        super::siphash::siphash24(self.handle_key, &data)
+    }
+
+    /// Converts a file ID to the file handle given to clients of this export
+    ///
+    /// With subtree checking enabled the export tag of the handle produced by
+    /// the VFS is appended to it.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The file ID to convert
+    ///
+    /// # Returns
+    ///
+    /// * `Result<nfs_fh3, nfsstat3>` - The file handle, or NFS3ERR_SERVERFAULT if
+    ///   it would be longer than `NFS3_FHSIZE`, e.g. because the handle of the VFS
+    ///   leaves no room for the export tag
+    pub fn id_to_fh(&self, id: nfs3::fileid3) -> Result<nfs3::nfs_fh3, nfs3::nfsstat3> {
+        let mut fh = self.vfs.id_to_fh(id);
+        if self.subtree_check {
+            let tag = self.export_tag(&fh.data);
+            fh.data.extend_from_slice(&tag.to_be_bytes());
+        }
+        if fh.data.len() > nfs3::NFS3_FHSIZE as usize {
+            warn!("handle of {} is {} bytes, longer than NFS3_FHSIZE", id, fh.data.len());
+            return Err(nfs3::nfsstat3::NFS3ERR_SERVERFAULT);
+        }
+        Ok(fh)
+    }
+
+    /// Converts a file handle received from a client of this export to a file ID
+    ///
+    /// With subtree checking enabled, handles that were not issued under this
+    /// export are rejected, so a client cannot reach files of another export that
+    /// shares the backend by presenting a handle obtained there, nor forge a
+    /// handle of a file it was never given.
+    ///
+    /// # Arguments
+    ///
+    /// * `fh` - The file handle from the request
+    ///
+    /// # Returns
+    ///
+    /// * `Result<fileid3, nfsstat3>` - The file ID, NFS3ERR_STALE for a handle of
+    ///   another export or with a forged tag, or the error of [`vfs::NFSFileSystem::fh_to_id`]
+    ///
+    /// During the handle grace period, handles the VFS reports as stale are
+    /// given to [`vfs::NFSFileSystem::rehydrate_handle`] before failing. The
+    /// public file handle maps to the root of the export if enabled.
+    pub fn fh_to_id(&self, fh: &nfs3::nfs_fh3) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        if self.is_public_fh(fh) {
+            return Ok(self.vfs.root_dir());
+        }
+        if !self.subtree_check {
+            return self.vfs_fh_to_id(fh);
+        }
+        let Some(split) = fh.data.len().checked_sub(EXPORT_TAG_SIZE) else {
+            return Err(nfs3::nfsstat3::NFS3ERR_BADHANDLE);
+        };
+        let (inner, tag) = fh.data.split_at(split);
+        let expected = self.export_tag(inner).to_be_bytes();
+        // compared in constant time, so the tag cannot be guessed byte by byte
+        if tag.iter().zip(expected).fold(0, |diff, (a, b)| diff | (a ^ b)) != 0 {
+            return Err(nfs3::nfsstat3::NFS3ERR_STALE);
+        }
+        self.vfs_fh_to_id(&nfs3::nfs_fh3 { data: inner.to_vec() })
+    }
+
+    /// Returns whether `fh` is the public file handle of WebNFS (RFC 2054)
+    ///
+    /// The public file handle has zero length and is only recognized when
+    /// [`Context::public_fh`] is enabled; clients use it in place of a handle
+    /// obtained through the `MOUNT` protocol.
+    pub fn is_public_fh(&self, fh: &nfs3::nfs_fh3) -> bool {
+        self.public_fh && fh.data.is_empty()
+    }
+
+    /// Publishes activity of the client to the subscribers of events, if enabled
+    pub fn publish(&self, kind: nfs::events::EventKind) {
+        if let Some(events) = &self.events {
+            events.publish(nfs::events::Event { client: self.client_addr, kind });
+        }
+    }
+
+    /// Returns the largest `READ` or `WRITE` transfer size a record of
+    /// [`Context::max_record_length`] holds
+    pub fn max_transfer_size(&self) -> u32 {
+        let size = self.max_record_length.saturating_sub(super::RECORD_OVERHEAD);
+        u32::try_from(size).unwrap_or(u32::MAX)
+    }
+
+    /// Returns the information about a call with transaction ID `xid` passed to
+    /// the VFS, see [`vfs::request::RequestContext`]
+    pub fn request_context(&self, xid: u32) -> vfs::request::RequestContext {
+        let deadline = self.request_timeout.map(|timeout| Instant::now() + timeout);
+        let request = vfs::request::RequestContext::with_trace_id(
+            xid,
+            self.entropy.next_u64(),
+            self.client_addr,
+            self.auth.clone(),
+            deadline,
+        );
+        vfs::request::RequestContext { error_mapper: self.error_mapper.clone(), ..request }
+    }
+
+    /// Converts a handle with the VFS, rehydrating stale ones during the grace period
+    fn vfs_fh_to_id(&self, fh: &nfs3::nfs_fh3) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        match self.vfs.fh_to_id(fh) {
+            Err(nfs3::nfsstat3::NFS3ERR_STALE) if self.in_handle_grace() => {
+                self.vfs.rehydrate_handle(fh)
+            }
+            result => result,
+        }
+    }
+
+    /// Returns true while stale handles may still be rehydrated
+    fn in_handle_grace(&self) -> bool {
+        self.handle_grace_until.is_some_and(|until| Instant::now() < until)
+    }
 }
 
 impl fmt::Debug for Context {
@@ -0,0 +1,77 @@
+//! Errors of RPC processing returned to embedders.
+//!
+//! Handlers report the outcome of a call to the client in the reply, so an
+//! error leaving [`super::handle_rpc`] and the functions built on it means
+//! that no reply could be produced at all. [`ServerError`] tells apart why, so
+//! an embedder driving connections itself can decide what to do with them.
+
+use std::io;
+
+use thiserror::Error;
+
+/// Failure to process an RPC message
+#[derive(Debug, Error)]
+pub enum ServerError {
+    /// Reading from or writing to the transport failed
+    #[error("I/O error: {0}")]
+    Io(#[source] io::Error),
+    /// The peer sent a message that cannot be decoded or is not supported
+    #[error("protocol violation: {0}")]
+    Protocol(String),
+    /// The server reached a state it should never be in
+    #[error("internal error: {0}")]
+    Internal(String),
+}
+
+impl ServerError {
+    /// Returns whether the connection the error occurred on is unusable
+    ///
+    /// An I/O failure may have lost or cut short data of the stream, and an
+    /// internal error leaves the state of the connection unknown. A protocol
+    /// violation spoils only the message it occurred in.
+    pub fn is_fatal(&self) -> bool {
+        !matches!(self, Self::Protocol(_))
+    }
+}
+
+/// Decoding errors are protocol violations, as the message comes from the peer
+///
+/// Failures of the transport are wrapped in [`ServerError::Io`] explicitly, as
+/// a stream ending early also reports `UnexpectedEof`.
+impl From<io::Error> for ServerError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => {
+                Self::Protocol(err.to_string())
+            }
+            _ => Self::Io(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::rpc::read_fragment;
+    use crate::protocol::xdr::{deserialize, rpc};
+
+    #[test]
+    fn classifies_errors() {
+        let truncated = deserialize::<rpc::rpc_msg>(&mut io::Cursor::new([0u8; 3])).unwrap_err();
+        let err = ServerError::from(truncated);
+        assert!(matches!(err, ServerError::Protocol(_)));
+        assert!(!err.is_fatal());
+
+        let err = ServerError::from(io::Error::from(io::ErrorKind::BrokenPipe));
+        assert!(matches!(err, ServerError::Io(_)));
+        assert!(err.is_fatal());
+        assert!(ServerError::Internal("queue closed".into()).is_fatal());
+    }
+
+    #[tokio::test]
+    async fn transport_errors_are_fatal() {
+        let mut stream = &[0x80, 0, 0, 8, 1, 2][..];
+        let err = read_fragment(&mut stream, &mut Vec::new()).await.unwrap_err();
+        assert!(matches!(err, ServerError::Io(_)));
+    }
+}
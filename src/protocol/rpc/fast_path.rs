@@ -0,0 +1,148 @@
+//! Fast paths for hot procedures that bypass the generic RPC dispatch.
+//!
+//! Small-op workloads are dominated by `GETATTR`. For such calls the generic path
+//! deserializes the whole call header into owned structures, copies the record,
+//! and encodes the reply through layered writers. The fast path instead inspects
+//! the raw record in place and hands the file handle bytes straight to the
+//! procedure, falling back to the generic path for anything it does not recognize.
+
+use std::io::Cursor;
+use std::time::Instant;
+
+use tracing::debug;
+
+use crate::protocol::xdr::{deserialize, nfs3, rpc::auth_flavor};
+use crate::protocol::{nfs, rpc};
+
+/// `msg_type` value of an RPC call
+const CALL: u32 = 0;
+/// Only supported RPC protocol version
+const RPC_VERSION: u32 = 2;
+
+/// Reads a big-endian `u32` at `offset`
+pub(super) fn be_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+/// Skips an `opaque_auth` at `offset` and returns its flavor and the offset after it
+pub(super) fn skip_auth(data: &[u8], offset: usize) -> Option<(u32, usize)> {
+    let flavor = be_u32(data, offset)?;
+    let len = be_u32(data, offset + 4)? as usize;
+    let end = offset + 8 + len.next_multiple_of(4);
+    (end <= data.len()).then_some((flavor, end))
+}
+
+/// Parts of an `NFSv3` `GETATTR` call
+struct GetAttr<'a> {
+    xid: u32,
+    flavor: auth_flavor,
+    /// Encoded credentials
+    cred: &'a [u8],
+    /// Bytes of the file handle
+    handle: &'a [u8],
+}
+
+/// Locates the credentials and file handle of an `NFSv3` `GETATTR` call
+///
+/// Only calls with `AUTH_NULL` or `AUTH_UNIX` credentials are recognized, as
+/// those are the flavors the generic path accepts without further checks.
+///
+/// Returns `None` if the record is not a well-formed `GETATTR` call.
+fn parse_getattr(data: &[u8]) -> Option<GetAttr<'_>> {
+    let xid = be_u32(data, 0)?;
+    if be_u32(data, 4)? != CALL
+        || be_u32(data, 8)? != RPC_VERSION
+        || be_u32(data, 12)? != nfs3::PROGRAM
+        || be_u32(data, 16)? != nfs3::VERSION
+        || be_u32(data, 20)? != nfs3::NFSProgram::NFSPROC3_GETATTR as u32
+    {
+        return None;
+    }
+    let (cred_flavor, cred_end) = skip_auth(data, 24)?;
+    let flavor = match cred_flavor {
+        f if f == auth_flavor::AUTH_NULL as u32 => auth_flavor::AUTH_NULL,
+        f if f == auth_flavor::AUTH_UNIX as u32 => auth_flavor::AUTH_UNIX,
+        _ => return None,
+    };
+    let cred = &data[32..cred_end];
+    let (_, offset) = skip_auth(data, cred_end)?;
+    let len = be_u32(data, offset)? as usize;
+    if len > nfs3::NFS3_FHSIZE as usize {
+        return None;
+    }
+    let handle = data.get(offset + 4..offset + 4 + len)?;
+    Some(GetAttr { xid, flavor, cred, handle })
+}
+
+/// Handles the record on a fast path if one applies
+///
+/// Performs the same retransmission tracking as [`super::wire::handle_rpc`].
+/// Credentials are only decoded for the
+/// [`crate::vfs::request::RequestContext`] of the call, as `GETATTR` does not
+/// depend on them; calls whose flavor the auth policy rejects, and all calls
+/// while dispatch hooks are installed, are left to the generic path.
+///
+/// # Arguments
+///
+/// * `data` - Complete RPC record
+/// * `output` - Buffer the reply is appended to
+/// * `context` - RPC processing context
+///
+/// # Returns
+///
+/// * `Option<Result<bool, rpc::ServerError>>` - `None` if the generic path must handle
+///   the record, otherwise the same result `handle_rpc` would return
+pub async fn try_fast_path(
+    data: &[u8],
+    output: &mut Vec<u8>,
+    context: &rpc::Context,
+) -> Option<Result<bool, rpc::ServerError>> {
+    if !context.hooks.is_empty() {
+        return None;
+    }
+    let GetAttr { xid, flavor, cred, handle } = parse_getattr(data)?;
+    let getattr = nfs3::NFSProgram::NFSPROC3_GETATTR as u32;
+    if !context.auth_policy.allows(nfs3::PROGRAM, getattr, flavor) {
+        return None;
+    }
+    let mut request = context.request_context(xid);
+    if let auth_flavor::AUTH_UNIX = flavor {
+        request.auth = deserialize(&mut Cursor::new(cred)).ok()?;
+    }
+    context.squash.apply(&mut request.auth);
+    let tracked = context.transaction_tracker.tracks(nfs3::PROGRAM, nfs3::VERSION, getattr);
+    if tracked {
+        if context.transaction_tracker.is_retransmission(xid, context.client_addr) {
+            debug!(
+                "Retransmission detected, xid: {}, client_addr: {}, GETATTR",
+                xid, context.client_addr
+            );
+            context.stats.duplicate_cache.hit();
+            return Some(Ok(false));
+        }
+        context.stats.duplicate_cache.miss();
+    }
+    let started = Instant::now();
+    let res = request
+        .scope(nfs::v3::nfsproc3_getattr_fast(xid, handle, output, context))
+        .await
+        .map(|_| true);
+    let elapsed = started.elapsed();
+    context.stats.export(&context.export_name).record_call(nfs3::PROGRAM, nfs3::VERSION, getattr);
+    if context.stats.record_call(nfs3::PROGRAM, nfs3::VERSION, getattr, elapsed, res.is_ok()) {
+        let fileid = context.fh_to_id(&nfs3::nfs_fh3 { data: handle.to_vec() }).ok();
+        super::stats::warn_slow_call(
+            nfs3::PROGRAM,
+            nfs3::VERSION,
+            getattr,
+            fileid,
+            elapsed,
+            context.client_addr,
+        );
+    }
+    if tracked {
+        context.transaction_tracker.mark_processed(xid, context.client_addr);
+    }
+    Some(res)
+}
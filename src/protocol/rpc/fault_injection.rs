@@ -0,0 +1,290 @@
+//! Network fault simulation for tests of the record-marking layer.
+//!
+//! Loopback connections deliver every record in one piece, right away and in
+//! order, so tests over them never exercise the paths that reassemble records
+//! from partial reads or cope with connections lost halfway through a record.
+//! [`relay_with_faults`] forwards a byte stream while injecting such
+//! conditions, and [`FaultProxy`] puts it between clients and a listener:
+//!
+//! ```no_run
+//! # async fn run() -> std::io::Result<()> {
+//! use nfs_mamont::protocol::rpc::{FaultConfig, FaultProxy};
+//!
+//! let config = FaultConfig { max_chunk: 3, reorder: true, ..Default::default() };
+//! let proxy = FaultProxy::bind("127.0.0.1:11111".parse().unwrap(), config).await?;
+//! let addr = proxy.local_addr()?;
+//! tokio::spawn(proxy.run());
+//! // connect clients to `addr` instead of the listener
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Faults are only injected into the calls flowing to the server; replies
+//! are passed through unchanged. The random choices are seeded, so a failing
+//! test can be reproduced.
+
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tracing::debug;
+
+/// How long a record held back for reordering waits for a successor
+const REORDER_WINDOW: Duration = Duration::from_millis(50);
+/// Last-fragment flag of a record marking header
+const LAST_FRAGMENT: u32 = 1 << 31;
+
+/// Faults injected by [`relay_with_faults`]
+#[derive(Clone, Copy, Debug)]
+pub struct FaultConfig {
+    /// Seed of the random chunk sizes and delays
+    pub seed: u64,
+    /// Delay before every chunk is forwarded
+    pub latency: Duration,
+    /// Upper bound of a random delay added to `latency`
+    pub jitter: Duration,
+    /// Largest chunk forwarded at once. Chunks have random sizes up to it, so
+    /// records and their headers are split at odd boundaries; 0 forwards data
+    /// as it arrives.
+    pub max_chunk: usize,
+    /// Whether consecutive records are swapped in pairs. A record without a
+    /// successor is forwarded after a short while, so clients waiting for the
+    /// reply do not stall.
+    pub reorder: bool,
+    /// Number of bytes after which the connection is cut, usually in the
+    /// middle of a record
+    pub disconnect_after: Option<u64>,
+}
+
+impl Default for FaultConfig {
+    /// Forwards everything unchanged
+    fn default() -> Self {
+        Self {
+            seed: 1,
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            max_chunk: 0,
+            reorder: false,
+            disconnect_after: None,
+        }
+    }
+}
+
+/// Xorshift generator, good enough for picking chunk sizes
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // xorshift never leaves zero
+        Self(seed.max(1))
+    }
+
+    /// Returns a number in `0..bound`, or 0 for a bound of 0
+    fn below(&mut self, bound: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        if bound == 0 {
+            0
+        } else {
+            self.0 % bound
+        }
+    }
+}
+
+/// Writing side of a relay
+struct Faults<W> {
+    config: FaultConfig,
+    rng: Rng,
+    writer: W,
+    /// Bytes forwarded so far
+    written: u64,
+}
+
+impl<W: AsyncWrite + Unpin> Faults<W> {
+    /// Forwards `data` in chunks, returning false once the connection is cut
+    async fn forward(&mut self, mut data: &[u8]) -> io::Result<bool> {
+        while !data.is_empty() {
+            let mut len = match self.config.max_chunk {
+                0 => data.len(),
+                max => 1 + self.rng.below(max as u64) as usize,
+            }
+            .min(data.len());
+            if let Some(limit) = self.config.disconnect_after {
+                let left = limit.saturating_sub(self.written);
+                if left == 0 {
+                    debug!("Cutting connection after {} bytes", self.written);
+                    self.writer.shutdown().await?;
+                    return Ok(false);
+                }
+                len = len.min(left as usize);
+            }
+            let jitter = self.rng.below(self.config.jitter.as_micros() as u64);
+            let delay = self.config.latency + Duration::from_micros(jitter);
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+            self.writer.write_all(&data[..len]).await?;
+            self.writer.flush().await?;
+            self.written += len as u64;
+            data = &data[len..];
+        }
+        if self.config.disconnect_after == Some(self.written) {
+            debug!("Cutting connection after {} bytes", self.written);
+            self.writer.shutdown().await?;
+            return Ok(false);
+        }
+        Ok(true)
+    }
+}
+
+/// Removes the first complete record, with its record marking, from `buf`
+fn take_record(buf: &mut Vec<u8>) -> Option<Vec<u8>> {
+    let mut offset = 0;
+    loop {
+        let header = u32::from_be_bytes(buf.get(offset..offset + 4)?.try_into().unwrap());
+        offset += 4 + (header & !LAST_FRAGMENT) as usize;
+        if offset > buf.len() {
+            return None;
+        }
+        if header & LAST_FRAGMENT != 0 {
+            return Some(buf.drain(..offset).collect());
+        }
+    }
+}
+
+/// Copies a record-marked stream from `reader` to `writer`, injecting faults
+///
+/// Returns when `reader` ends, after shutting `writer` down, or when the
+/// connection is cut as configured.
+pub async fn relay_with_faults(
+    mut reader: impl AsyncRead + Unpin,
+    writer: impl AsyncWrite + Unpin,
+    config: FaultConfig,
+) -> io::Result<()> {
+    let mut faults = Faults { config, rng: Rng::new(config.seed), writer, written: 0 };
+    let mut buf = vec![0; 64 * 1024];
+    // received bytes not forming a complete record yet, when reordering
+    let mut pending = Vec::new();
+    // complete record waiting to be swapped with its successor
+    let mut held: Option<Vec<u8>> = None;
+    loop {
+        let read = match held {
+            Some(_) => tokio::time::timeout(REORDER_WINDOW, reader.read(&mut buf)).await.ok(),
+            None => Some(reader.read(&mut buf).await),
+        };
+        let len = match read {
+            // nothing followed the held record in time
+            None => {
+                let record = held.take().unwrap();
+                if !faults.forward(&record).await? {
+                    return Ok(());
+                }
+                continue;
+            }
+            Some(read) => read?,
+        };
+        if len == 0 {
+            let rest = held.take().unwrap_or_default();
+            if faults.forward(&rest).await? && faults.forward(&pending).await? {
+                faults.writer.shutdown().await?;
+            }
+            return Ok(());
+        }
+        if !config.reorder {
+            if !faults.forward(&buf[..len]).await? {
+                return Ok(());
+            }
+            continue;
+        }
+        pending.extend_from_slice(&buf[..len]);
+        while let Some(record) = take_record(&mut pending) {
+            let Some(first) = held.take() else {
+                held = Some(record);
+                continue;
+            };
+            if !faults.forward(&record).await? || !faults.forward(&first).await? {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// TCP proxy injecting faults into the calls of every connection
+#[derive(Debug)]
+pub struct FaultProxy {
+    listener: TcpListener,
+    /// Address of the server
+    target: SocketAddr,
+    config: FaultConfig,
+}
+
+impl FaultProxy {
+    /// Binds a loopback port forwarding to the server at `target`
+    pub async fn bind(target: SocketAddr, config: FaultConfig) -> io::Result<Self> {
+        let local = if target.is_ipv4() { "127.0.0.1:0" } else { "[::1]:0" };
+        Ok(Self { listener: TcpListener::bind(local).await?, target, config })
+    }
+
+    /// Returns the address clients connect to
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts connections until accepting fails
+    ///
+    /// Every connection gets a seed of its own, derived from the configured
+    /// one, and is closed in both directions once either direction ends.
+    pub async fn run(self) -> io::Result<()> {
+        for connection in 0_u64.. {
+            let (client, _) = self.listener.accept().await?;
+            let server = tokio::net::TcpStream::connect(self.target).await?;
+            let config =
+                FaultConfig { seed: self.config.seed.wrapping_add(connection), ..self.config };
+            tokio::spawn(async move {
+                let (client_read, mut client_write) = client.into_split();
+                let (mut server_read, server_write) = server.into_split();
+                tokio::select! {
+                    res = relay_with_faults(client_read, server_write, config) => {
+                        debug!("Fault proxy relay ended: {:?}", res);
+                    }
+                    res = tokio::io::copy(&mut server_read, &mut client_write) => {
+                        debug!("Fault proxy reply stream ended: {:?}", res);
+                    }
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Frames `body` as a single-fragment record
+    fn record(body: &[u8]) -> Vec<u8> {
+        let mut out = (body.len() as u32 | LAST_FRAGMENT).to_be_bytes().to_vec();
+        out.extend_from_slice(body);
+        out
+    }
+
+    #[tokio::test]
+    async fn swaps_records_and_splits_chunks() {
+        let mut input = record(b"first");
+        input.extend(record(b"second"));
+        let config = FaultConfig { max_chunk: 3, reorder: true, ..Default::default() };
+        let mut output = Vec::new();
+        relay_with_faults(&input[..], &mut output, config).await.unwrap();
+        assert_eq!(take_record(&mut output).unwrap(), record(b"second"));
+        assert_eq!(take_record(&mut output).unwrap(), record(b"first"));
+        assert!(output.is_empty());
+
+        let config = FaultConfig { disconnect_after: Some(7), ..Default::default() };
+        let mut output = Vec::new();
+        relay_with_faults(&input[..], &mut output, config).await.unwrap();
+        assert_eq!(output, input[..7]);
+    }
+}
@@ -0,0 +1,130 @@
+//! Hooks around the dispatch of RPC calls.
+//!
+//! A [`DispatchHook`] sees every call after authentication and retransmission
+//! checks, just before it reaches the program handler, and its reply just after.
+//! Applications use hooks for rules the server does not know about, such as
+//! refusing `MKNOD` on an export, for rewriting arguments, or for shadowing
+//! traffic to another server, without forking the dispatch code.
+//!
+//! Hooks run in the order they were added. The first hook that does not return
+//! [`HookDecision::Continue`] decides the call; `after` hooks run for every
+//! call that was dispatched.
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::protocol::xdr::nfs3;
+
+/// Identification of a call passed to hooks
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CallInfo {
+    /// Transaction ID
+    pub xid: u32,
+    /// RPC program number
+    pub program: u32,
+    /// Program version
+    pub version: u32,
+    /// Procedure number
+    pub procedure: u32,
+}
+
+/// Outcome of [`DispatchHook::before`]
+#[derive(Debug)]
+pub enum HookDecision {
+    /// Dispatch the call
+    Continue,
+    /// Reply with an error status without dispatching the call. `NFSv3` calls get
+    /// the status with empty attributes, calls of other programs are rejected
+    /// with `AUTH_TOOWEAK`.
+    Fail(nfs3::nfsstat3),
+    /// Send the given reply message without dispatching the call
+    Reply(Vec<u8>),
+    /// Send no reply at all
+    Drop,
+}
+
+/// Callbacks run around the dispatch of every call
+///
+/// Both methods have no effect by default, so a hook implements only what it
+/// needs. Hooks run on the connection's task and should not block.
+pub trait DispatchHook: Send + Sync {
+    /// Called before a call is dispatched
+    ///
+    /// # Arguments
+    ///
+    /// * `call` - The call being dispatched
+    /// * `context` - Context of the call, with the caller's credentials
+    /// * `args` - Encoded procedure arguments, which may be rewritten
+    fn before(
+        &self,
+        _call: &CallInfo,
+        _context: &super::Context,
+        _args: &mut Vec<u8>,
+    ) -> HookDecision {
+        HookDecision::Continue
+    }
+
+    /// Called after a call was dispatched
+    ///
+    /// # Arguments
+    ///
+    /// * `call` - The call that was dispatched
+    /// * `context` - Context of the call
+    /// * `reply` - Complete reply message, starting with the transaction ID
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Vec<u8>>` - A reply message sent instead of `reply`, if any
+    fn after(&self, _call: &CallInfo, _context: &super::Context, _reply: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// Hooks installed on a listener, in the order they run
+#[derive(Clone, Default)]
+pub struct DispatchHooks {
+    hooks: Vec<Arc<dyn DispatchHook>>,
+}
+
+impl fmt::Debug for DispatchHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DispatchHooks").field("len", &self.hooks.len()).finish()
+    }
+}
+
+impl DispatchHooks {
+    /// Appends a hook that runs after the ones added before
+    pub fn push(&mut self, hook: Arc<dyn DispatchHook>) {
+        self.hooks.push(hook);
+    }
+
+    /// Returns whether no hook is installed
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    /// Runs the `before` hooks until one decides the call
+    pub fn before(
+        &self,
+        call: &CallInfo,
+        context: &super::Context,
+        args: &mut Vec<u8>,
+    ) -> HookDecision {
+        for hook in &self.hooks {
+            match hook.before(call, context, args) {
+                HookDecision::Continue => {}
+                decision => return decision,
+            }
+        }
+        HookDecision::Continue
+    }
+
+    /// Runs the `after` hooks, each seeing the reply left by the previous ones
+    pub fn after(&self, call: &CallInfo, context: &super::Context, reply: &mut Vec<u8>) {
+        for hook in &self.hooks {
+            if let Some(replacement) = hook.after(call, context, reply) {
+                *reply = replacement;
+            }
+        }
+    }
+}
@@ -7,12 +7,24 @@
 //! This module implements RPC version 2 with the following features:
 //!
 //! 1. Message framing for TCP using the Record Marking Standard
-//! 2. Transaction tracking for detecting and handling retransmissions
-//! 3. Authentication (`AUTH_UNIX`)
+//! 2. Transaction tracking for detecting and handling retransmissions, and a
+//!    reply cache answering retransmitted datagrams
+//! 3. Authentication (`AUTH_UNIX`, with `AUTH_SHORT` handles) and per-program
+//!    flavor requirements
 //! 4. Program/procedure number dispatching
-//! 5. Error handling and reporting
+//! 5. Error handling and reporting, with a [`ServerError`] telling apart I/O
+//!    failures, protocol violations and internal errors
 //! 6. Asynchronous message processing
-//! 7. Ordered command processing with FIFO guarantees
+//! 7. Ordered command processing with FIFO guarantees, with response buffers
+//!    sized from the replies seen per procedure
+//! 8. Fault injection into the transport for tests
+//! 9. Capture of the traffic of connections in pcapng or JSON form
+//! 10. Interoperability workarounds for specific clients
+//! 11. Deterministic replay of captured traffic against a fresh backend
+//! 12. Weighted fair scheduling of calls across connections, and concurrency
+//!     limits per class of procedures
+//! 13. Compression of replies negotiated by clients of this crate, a private
+//!     extension
 //!
 //! RPC provides important benefits for distributed systems:
 //! - Location transparency (clients don't need to know server locations)
@@ -24,11 +36,60 @@
 //! the NFS, MOUNT, and PORTMAP protocols, handling all aspects of message
 //! encoding, transmission, and routing.
 
+mod auth_policy;
+mod buffer_sizing;
+mod capture;
 mod command_queue;
+mod compression;
 mod context;
+mod error;
+mod fast_path;
+mod fault_injection;
+mod hooks;
+mod priority;
+mod quirks;
+mod replay;
+mod reply_cache;
+mod reply_stream;
+mod scheduler;
+mod short_auth;
+mod siphash;
+mod squash;
+mod stats;
 mod transaction_tracker;
 mod wire;
 
+pub use auth_policy::{AuthPolicy, DEFAULT_MOUNT_FLAVORS};
+pub use capture::{Capture, CaptureFormat};
+pub(crate) use capture::{Direction, Tap, TapWriter};
+pub(crate) use compression::handle_compression;
+pub use compression::{
+    decompress_record, Algorithm, CompressionConfig, ReplyCompression, COMPRESSED_MSG_TYPE,
+    COMPRESSION_PROC_NEGOTIATE, COMPRESSION_PROGRAM, COMPRESSION_VERSION,
+};
 pub use context::Context;
-pub use transaction_tracker::TransactionTracker;
-pub use wire::{write_fragment, SocketMessageHandler};
+pub use error::ServerError;
+pub use fault_injection::{relay_with_faults, FaultConfig, FaultProxy};
+pub use hooks::{CallInfo, DispatchHook, DispatchHooks, HookDecision};
+pub use priority::{PriorityClass, PriorityLimits};
+#[cfg(feature = "config")]
+pub(crate) use quirks::in_network;
+pub use quirks::{ClientQuirks, QuirkPolicy};
+pub use replay::{read_capture, CapturedRecord, Replay, ReplayedCall};
+pub use reply_cache::{CachedReply, ReplyCache, ReplyCacheConfig};
+pub use reply_stream::{write_reply, Reply, ReplyStream, StreamedBody};
+pub use scheduler::{Lane, Permit, Scheduler};
+pub use short_auth::ShortAuth;
+pub use squash::{Squash, SquashMode};
+pub use stats::{
+    CacheCounters, CacheStats, ExportCounters, ExportStats, ProcedureStats, ServerStats,
+    StatsSnapshot,
+};
+pub use transaction_tracker::{
+    is_idempotent, RetransmissionPolicy, TrackerLockStats, TrackerStats, TransactionTracker,
+    TransactionTrackerConfig,
+};
+pub use wire::{
+    handle_rpc, process_datagram, process_message, read_fragment, read_fragment_capped,
+    write_fragment, SocketMessageHandler, DEFAULT_MAX_RECORD_LENGTH, RECORD_OVERHEAD,
+};
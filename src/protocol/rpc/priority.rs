@@ -0,0 +1,140 @@
+//! Separate concurrency limits for classes of procedures.
+//!
+//! Clients streaming large writes can occupy the backend with `WRITE` and
+//! `COMMIT` calls until interactive clients wait seconds for a `LOOKUP`.
+//! [`PriorityLimits`] sorts procedures into [`PriorityClass`]es and bounds the
+//! number of calls of each class processed at once, so that metadata calls
+//! always find a free slot while data transfers queue behind their own limit.
+//!
+//! Calls of one TCP connection are processed in order, so a metadata call sent
+//! after a waiting write on the same connection still waits for it; the limits
+//! keep connections from holding up each other.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::protocol::xdr::nfs3::{self, NFSProgram};
+
+/// Class of a procedure, from the most to the least interactive
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PriorityClass {
+    /// Lookups, attributes and namespace changes, and all calls of programs
+    /// other than NFS
+    Metadata,
+    /// `READ`, `READLINK`, `READDIR` and `READDIRPLUS`
+    Read,
+    /// `WRITE` and `COMMIT`
+    Write,
+}
+
+impl PriorityClass {
+    /// Returns the default class of a procedure
+    pub fn of(program: u32, procedure: u32) -> Self {
+        if program != nfs3::PROGRAM {
+            return Self::Metadata;
+        }
+        match procedure {
+            p if p == NFSProgram::NFSPROC3_READ as u32
+                || p == NFSProgram::NFSPROC3_READLINK as u32
+                || p == NFSProgram::NFSPROC3_READDIR as u32
+                || p == NFSProgram::NFSPROC3_READDIRPLUS as u32 =>
+            {
+                Self::Read
+            }
+            p if p == NFSProgram::NFSPROC3_WRITE as u32
+                || p == NFSProgram::NFSPROC3_COMMIT as u32 =>
+            {
+                Self::Write
+            }
+            _ => Self::Metadata,
+        }
+    }
+}
+
+/// Concurrency limits per class of procedures
+///
+/// Classes are unlimited by default.
+#[derive(Clone, Debug, Default)]
+pub struct PriorityLimits {
+    /// Slots of the limited classes
+    limits: HashMap<PriorityClass, Arc<Semaphore>>,
+    /// Classes of procedures that differ from their default, keyed by program
+    /// and procedure number
+    classes: HashMap<(u32, u32), PriorityClass>,
+}
+
+impl PriorityLimits {
+    /// Creates limits letting every call through
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits the calls of a class processed at once
+    ///
+    /// # Arguments
+    ///
+    /// * `class` - Class of procedures
+    /// * `limit` - Number of calls of the class processed at once, at least 1
+    pub fn limit(&mut self, class: PriorityClass, limit: usize) -> &mut Self {
+        self.limits.insert(class, Arc::new(Semaphore::new(limit.max(1))));
+        self
+    }
+
+    /// Puts a procedure into another class than its default
+    pub fn assign(&mut self, program: u32, procedure: u32, class: PriorityClass) -> &mut Self {
+        self.classes.insert((program, procedure), class);
+        self
+    }
+
+    /// Returns the class of a procedure
+    pub fn class_of(&self, program: u32, procedure: u32) -> PriorityClass {
+        self.classes
+            .get(&(program, procedure))
+            .copied()
+            .unwrap_or_else(|| PriorityClass::of(program, procedure))
+    }
+
+    /// Returns the number of free slots of a class, `None` if it is unlimited
+    pub fn available(&self, class: PriorityClass) -> Option<usize> {
+        self.limits.get(&class).map(|slots| slots.available_permits())
+    }
+
+    /// Waits for a slot of the class of a procedure, held until the permit is
+    /// dropped
+    ///
+    /// Returns `None` right away for unlimited classes.
+    pub async fn acquire(&self, program: u32, procedure: u32) -> Option<OwnedSemaphorePermit> {
+        let slots = self.limits.get(&self.class_of(program, procedure))?;
+        slots.clone().acquire_owned().await.ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::xdr::mount;
+
+    #[tokio::test]
+    async fn limits_classes_separately() {
+        let read = NFSProgram::NFSPROC3_READ as u32;
+        let write = NFSProgram::NFSPROC3_WRITE as u32;
+        let lookup = NFSProgram::NFSPROC3_LOOKUP as u32;
+        assert_eq!(PriorityClass::of(nfs3::PROGRAM, read), PriorityClass::Read);
+        assert_eq!(PriorityClass::of(mount::PROGRAM, read), PriorityClass::Metadata);
+
+        let mut limits = PriorityLimits::new();
+        limits.limit(PriorityClass::Write, 1).assign(nfs3::PROGRAM, read, PriorityClass::Write);
+        assert_eq!(limits.class_of(nfs3::PROGRAM, read), PriorityClass::Write);
+
+        let writing = limits.acquire(nfs3::PROGRAM, write).await.unwrap();
+        assert_eq!(limits.available(PriorityClass::Write), Some(0));
+        // metadata calls are not held up by the writes
+        assert!(limits.acquire(nfs3::PROGRAM, lookup).await.is_none());
+        let mut waiting = Box::pin(limits.acquire(nfs3::PROGRAM, read));
+        assert!(futures::poll!(waiting.as_mut()).is_pending());
+        drop(writing);
+        assert!(waiting.await.is_some());
+    }
+}
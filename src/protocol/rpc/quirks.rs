@@ -0,0 +1,126 @@
+//! Interoperability workarounds for specific clients.
+//!
+//! Some clients misbehave when the server announces what RFC 1813 allows: old
+//! ESXi releases and some embedded BSD clients fail on transfer sizes above
+//! 64 KiB, or on directory replies larger than they asked for in the past. A
+//! [`QuirkPolicy`] assigns [`ClientQuirks`] to networks of client addresses, so
+//! such clients can be served within their limits without lowering them for
+//! everybody else.
+//!
+//! The quirks are resolved once per connection from the peer address. File
+//! handles are always included in `READDIRPLUS` entries and in the replies of
+//! the procedures creating files, so no quirk is needed for clients relying on
+//! them.
+
+use std::net::IpAddr;
+
+/// Workarounds applied to the calls of a client
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ClientQuirks {
+    /// Largest read and write size announced by `FSINFO`, also the largest
+    /// `READ` count honored
+    pub max_transfer_size: Option<u32>,
+    /// Largest preferred directory read size announced by `FSINFO`, also the
+    /// largest `READDIR` and `READDIRPLUS` `maxcount` honored
+    pub max_dir_transfer_size: Option<u32>,
+}
+
+impl ClientQuirks {
+    /// Quirks of clients failing on transfers above 64 KiB
+    pub const SMALL_TRANSFERS: Self =
+        Self { max_transfer_size: Some(64 * 1024), max_dir_transfer_size: Some(64 * 1024) };
+
+    /// Caps a read or write size
+    pub fn transfer_size(&self, size: u32) -> u32 {
+        self.max_transfer_size.map_or(size, |max| size.min(max))
+    }
+
+    /// Caps a directory read size
+    pub fn dir_transfer_size(&self, size: u32) -> u32 {
+        self.max_dir_transfer_size.map_or(size, |max| size.min(max))
+    }
+}
+
+/// Quirks assigned to networks of client addresses
+///
+/// Clients matching no rule get no quirks.
+#[derive(Clone, Debug, Default)]
+pub struct QuirkPolicy {
+    /// Network address, prefix length and quirks, in the order added
+    rules: Vec<(IpAddr, u8, ClientQuirks)>,
+}
+
+impl QuirkPolicy {
+    /// Creates a policy without quirks for any client
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Assigns quirks to the clients of a network
+    ///
+    /// The first rule matching a client applies.
+    ///
+    /// # Arguments
+    ///
+    /// * `network` - Network address
+    /// * `prefix_len` - Number of leading bits of `network` a client address
+    ///   must share, the full length of the address for a single client
+    /// * `quirks` - Workarounds applied to the clients
+    pub fn add(&mut self, network: IpAddr, prefix_len: u8, quirks: ClientQuirks) -> &mut Self {
+        self.rules.push((network, prefix_len, quirks));
+        self
+    }
+
+    /// Returns the quirks of the client at `client`
+    pub fn for_client(&self, client: IpAddr) -> ClientQuirks {
+        self.rules
+            .iter()
+            .find(|(network, prefix_len, _)| in_network(client, *network, *prefix_len))
+            .map(|(_, _, quirks)| *quirks)
+            .unwrap_or_default()
+    }
+}
+
+/// Returns whether `client` shares the first `prefix_len` bits of `network`
+///
+/// IPv4 clients of a dual-stack listener show up as mapped IPv6 addresses and
+/// match IPv4 networks.
+pub(crate) fn in_network(client: IpAddr, network: IpAddr, prefix_len: u8) -> bool {
+    let client = match (network, client) {
+        (IpAddr::V4(_), IpAddr::V6(v6)) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => return false,
+        },
+        _ => client,
+    };
+    match (network, client) {
+        (IpAddr::V4(net), IpAddr::V4(client)) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix_len)).unwrap_or(0);
+            u32::from(net) & mask == u32::from(client) & mask
+        }
+        (IpAddr::V6(net), IpAddr::V6(client)) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix_len)).unwrap_or(0);
+            u128::from(net) & mask == u128::from(client) & mask
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assigns_quirks_by_network() {
+        let mut policy = QuirkPolicy::new();
+        policy.add("10.1.0.0".parse().unwrap(), 16, ClientQuirks::SMALL_TRANSFERS);
+        let quirks = policy.for_client("::ffff:10.1.2.3".parse().unwrap());
+        assert_eq!(quirks, ClientQuirks::SMALL_TRANSFERS);
+        assert_eq!(quirks.transfer_size(1024 * 1024), 64 * 1024);
+        assert_eq!(quirks.transfer_size(4096), 4096);
+
+        let quirks = policy.for_client("10.2.0.1".parse().unwrap());
+        assert_eq!(quirks, ClientQuirks::default());
+        assert_eq!(quirks.dir_transfer_size(1024 * 1024), 1024 * 1024);
+    }
+}
@@ -0,0 +1,313 @@
+//! Replay of captured traffic against a server context, for reproducing bugs.
+//!
+//! Users reporting a bug can record the traffic leading to it with a
+//! [`super::Capture`] in [`super::CaptureFormat::Json`] form. [`Replay`] feeds
+//! the recorded calls, in order, to a fresh backend and compares the status of
+//! every reply with the recorded one, pointing at the first call that behaved
+//! differently.
+//!
+//! Runs are deterministic:
+//!
+//! - calls get transaction IDs counting up from a fixed start instead of the
+//!   recorded ones, so the transaction tracker sees the same IDs every run;
+//! - the clock of the context is a [`ManualClock`] set to the recorded time of
+//!   every call before it is processed;
+//! - calls are processed one at a time, as the client of the recording
+//!   connection sent them.
+//!
+//! The fresh backend issues its own file handles. Handles returned by `MNT`,
+//! `LOOKUP`, `CREATE`, `MKDIR`, `SYMLINK` and `MKNOD` in the recording are
+//! therefore replaced by the ones the replay returned in later calls.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, Cursor};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::clock::ManualClock;
+use crate::protocol::xdr::{self, deserialize, mount, nfs3};
+
+/// Transaction ID of the first replayed call by default
+pub const DEFAULT_FIRST_XID: u32 = 1;
+
+/// An RPC record read from a JSON capture
+#[derive(Clone, Debug, PartialEq)]
+pub struct CapturedRecord {
+    /// Seconds since the Unix epoch when it was recorded
+    pub time: f64,
+    /// Number of the connection within the capture
+    pub connection: u64,
+    /// Address of the client
+    pub client: SocketAddr,
+    /// Whether the client sent it
+    pub is_call: bool,
+    /// The record, starting with the transaction ID
+    pub data: Vec<u8>,
+}
+
+/// Reads the records of a JSON capture
+///
+/// Lines that are not records, e.g. a trailing partial line, are skipped.
+pub fn read_capture(input: impl BufRead) -> io::Result<Vec<CapturedRecord>> {
+    let mut records = Vec::new();
+    for line in input.lines() {
+        if let Some(record) = parse_line(&line?) {
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+/// Parses one line of a JSON capture
+fn parse_line(line: &str) -> Option<CapturedRecord> {
+    // the lines are written by the capture, so fields never contain quotes
+    let field = |name: &str| {
+        let start = line.find(&format!("\"{name}\":"))? + name.len() + 3;
+        let rest = &line[start..];
+        let end = rest.find([',', '}'])?;
+        Some(rest[..end].trim_matches('"'))
+    };
+    let hex = field("data")?;
+    let data = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    Some(CapturedRecord {
+        time: field("time")?.parse().ok()?,
+        connection: field("connection")?.parse().ok()?,
+        client: field("client")?.parse().ok()?,
+        is_call: field("direction")? == "call",
+        data,
+    })
+}
+
+/// Outcome of a replayed call
+#[derive(Clone, Debug)]
+pub struct ReplayedCall {
+    /// Transaction ID of the call in the recording
+    pub recorded_xid: u32,
+    /// Transaction ID the call was replayed with
+    pub xid: u32,
+    /// Program called
+    pub program: u32,
+    /// Procedure number within the program
+    pub procedure: u32,
+    /// Status of the recorded reply, if it was recorded
+    pub recorded_status: Option<u32>,
+    /// Status of the replayed reply, if one was sent
+    pub status: Option<u32>,
+    /// The replayed reply
+    pub reply: Vec<u8>,
+}
+
+impl ReplayedCall {
+    /// Returns whether the reply differed in status from the recorded one
+    ///
+    /// Calls whose reply was not recorded never diverge.
+    pub fn diverged(&self) -> bool {
+        self.recorded_status.is_some() && self.recorded_status != self.status
+    }
+}
+
+/// Replays captured calls in order
+pub struct Replay {
+    clock: Arc<ManualClock>,
+    next_xid: u32,
+    /// Recorded handles, as encoded opaque data, and their replayed counterparts
+    handles: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl Default for Replay {
+    fn default() -> Self {
+        Self::new(DEFAULT_FIRST_XID)
+    }
+}
+
+impl Replay {
+    /// Creates a replay numbering calls from `first_xid`
+    pub fn new(first_xid: u32) -> Self {
+        Self {
+            clock: Arc::new(ManualClock::new(UNIX_EPOCH)),
+            next_xid: first_xid,
+            handles: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the clock the replayed calls see
+    pub fn clock(&self) -> Arc<ManualClock> {
+        self.clock.clone()
+    }
+
+    /// Replays the calls of a capture, paired with their recorded replies
+    ///
+    /// # Arguments
+    ///
+    /// * `records` - Records of a capture, see [`read_capture`]
+    /// * `context` - Context of a fresh backend; its clock and client address
+    ///   are replaced per call
+    pub async fn run(
+        &mut self,
+        records: &[CapturedRecord],
+        context: &super::Context,
+    ) -> io::Result<Vec<ReplayedCall>> {
+        // recorded replies by connection and transaction ID
+        let replies: HashMap<(u64, u32), &[u8]> = records
+            .iter()
+            .filter(|record| !record.is_call && record.data.len() >= 4)
+            .map(|record| ((record.connection, xid_of(&record.data)), &record.data[..]))
+            .collect();
+        let mut replayed = Vec::new();
+        for record in records.iter().filter(|record| record.is_call) {
+            let Some(call) = self.replay_call(record, &replies, context).await? else {
+                continue;
+            };
+            replayed.push(call);
+        }
+        Ok(replayed)
+    }
+
+    /// Replays a single call
+    async fn replay_call(
+        &mut self,
+        record: &CapturedRecord,
+        replies: &HashMap<(u64, u32), &[u8]>,
+        context: &super::Context,
+    ) -> io::Result<Option<ReplayedCall>> {
+        let mut cursor = Cursor::new(&record.data[..]);
+        let Ok(msg) = deserialize::<xdr::rpc::rpc_msg>(&mut cursor) else {
+            return Ok(None);
+        };
+        let xdr::rpc::rpc_body::CALL(header) = msg.body else {
+            return Ok(None);
+        };
+        let header_len = cursor.position() as usize;
+
+        let xid = self.next_xid;
+        self.next_xid = self.next_xid.wrapping_add(1);
+        let mut call = record.data.clone();
+        call[..4].copy_from_slice(&xid.to_be_bytes());
+        let args = call.split_off(header_len);
+        call.extend(replace_handles(&args, &self.handles));
+
+        self.clock.set(UNIX_EPOCH + Duration::from_secs_f64(record.time.max(0.0)));
+        let mut call_context = context.clone();
+        call_context.clock = self.clock.clone();
+        call_context.client_addr = record.client;
+        call_context.reply_stream = None;
+        let mut reply = Vec::new();
+        super::process_message(&call, &mut reply, call_context).await.map_err(io::Error::other)?;
+
+        let recorded = replies.get(&(record.connection, msg.xid)).copied();
+        if let Some(recorded) = recorded {
+            let procedure = (header.prog, header.proc);
+            if let (Some(old), Some(new)) =
+                (returned_handle(procedure, recorded), returned_handle(procedure, &reply))
+            {
+                if old != new {
+                    self.handles.insert(old, new);
+                }
+            }
+        }
+        Ok(Some(ReplayedCall {
+            recorded_xid: msg.xid,
+            xid,
+            program: header.prog,
+            procedure: header.proc,
+            recorded_status: recorded.and_then(status_of),
+            status: status_of(&reply),
+            reply,
+        }))
+    }
+}
+
+/// Returns the transaction ID of a record
+fn xid_of(record: &[u8]) -> u32 {
+    u32::from_be_bytes(record[..4].try_into().unwrap())
+}
+
+/// Returns the status of an accepted reply: the first word of its body, or 0
+/// for procedures without a body such as `NULL`
+fn status_of(reply: &[u8]) -> Option<u32> {
+    let mut cursor = Cursor::new(reply);
+    let msg = deserialize::<xdr::rpc::rpc_msg>(&mut cursor).ok()?;
+    let xdr::rpc::rpc_body::REPLY(xdr::rpc::reply_body::MSG_ACCEPTED(accepted)) = msg.body else {
+        return None;
+    };
+    if !matches!(accepted.reply_data, xdr::rpc::accept_body::SUCCESS) {
+        return None;
+    }
+    Some(deserialize::<u32>(&mut cursor).unwrap_or(0))
+}
+
+/// Returns the handle a successful reply returns, as encoded opaque data
+fn returned_handle((program, procedure): (u32, u32), reply: &[u8]) -> Option<Vec<u8>> {
+    let mut cursor = Cursor::new(reply);
+    let msg = deserialize::<xdr::rpc::rpc_msg>(&mut cursor).ok()?;
+    let xdr::rpc::rpc_body::REPLY(xdr::rpc::reply_body::MSG_ACCEPTED(_)) = msg.body else {
+        return None;
+    };
+    if deserialize::<u32>(&mut cursor).ok()? != 0 {
+        return None;
+    }
+    let optional = match (program, procedure) {
+        (mount::PROGRAM, 1) | (nfs3::PROGRAM, 3) => false,
+        (nfs3::PROGRAM, 8..=11) => true,
+        _ => return None,
+    };
+    if optional && deserialize::<u32>(&mut cursor).ok()? == 0 {
+        return None;
+    }
+    let start = cursor.position() as usize;
+    let len = deserialize::<u32>(&mut cursor).ok()? as usize;
+    reply.get(start..start + 4 + len.next_multiple_of(4)).map(<[u8]>::to_vec)
+}
+
+/// Replaces the recorded handles in encoded arguments with the replayed ones
+///
+/// The arguments are scanned once, so a replayed handle equal to another
+/// recorded one is not replaced again.
+fn replace_handles(data: &[u8], handles: &BTreeMap<Vec<u8>, Vec<u8>>) -> Vec<u8> {
+    let mut replaced = Vec::with_capacity(data.len());
+    let mut rest = data;
+    while !rest.is_empty() {
+        match handles.iter().find(|(recorded, _)| rest.starts_with(recorded)) {
+            Some((recorded, replayed)) => {
+                replaced.extend_from_slice(replayed);
+                rest = &rest[recorded.len()..];
+            }
+            None => {
+                replaced.push(rest[0]);
+                rest = &rest[1..];
+            }
+        }
+    }
+    replaced
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_capture_lines() {
+        let line = "{\"time\":12.5,\"connection\":3,\"client\":\"127.0.0.1:900\",\
+                    \"direction\":\"reply\",\"length\":2,\"xid\":7,\"status\":\"SUCCESS\",\
+                    \"data\":\"00ff\"}";
+        let record = parse_line(line).unwrap();
+        assert_eq!(record.time, 12.5);
+        assert_eq!((record.connection, record.client), (3, "127.0.0.1:900".parse().unwrap()));
+        assert!(!record.is_call);
+        assert_eq!(record.data, [0, 0xff]);
+        assert!(parse_line("{\"time\":1").is_none());
+    }
+
+    #[test]
+    fn replaces_handles() {
+        let old = [0, 0, 0, 2, 1, 2, 0, 0];
+        let new = [0, 0, 0, 2, 3, 4, 0, 0];
+        let handles = BTreeMap::from([(old.to_vec(), new.to_vec()), (new.to_vec(), old.to_vec())]);
+        let args = [&old[..], &[9, 9], &new].concat();
+        assert_eq!(replace_handles(&args, &handles), [&new[..], &[9, 9], &old].concat());
+    }
+}
@@ -0,0 +1,273 @@
+//! Duplicate request cache for datagram transports.
+//!
+//! Over TCP a retransmitted call is only sent after the connection was reset, and
+//! the [`super::TransactionTracker`] drops it so a non-idempotent procedure is not
+//! executed twice. A UDP client instead retransmits whenever a reply is late or
+//! lost, and waits for a reply to every transmission: dropping the duplicate
+//! leaves it retrying until it gives up. The [`ReplyCache`] therefore keeps the
+//! encoded replies of completed calls and sends them again for retransmissions.
+//!
+//! Every UDP socket owns its cache. Entries are keyed by transaction ID and client
+//! address like the tracker, and additionally by a checksum of the call, so a
+//! client reusing an XID for a different call after a reboot is not answered
+//! with a stale reply.
+//!
+//! The cache is a [`MemoryConsumer`]: under pressure of a
+//! [`crate::memory_budget::MemoryBudget`], the oldest replies are dropped
+//! first. Calls still in progress are kept.
+
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+use std::hash::BuildHasher;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::transaction_tracker::RetransmissionPolicy;
+use crate::memory_budget::{MemoryCharge, MemoryConsumer};
+
+/// Limits of a [`ReplyCache`]
+#[derive(Clone, Copy, Debug)]
+pub struct ReplyCacheConfig {
+    /// How long replies are kept for retransmissions
+    pub retention_period: Duration,
+    /// Maximum number of cached calls; the oldest ones are forgotten first
+    pub capacity: usize,
+    /// Which calls are cached. Replies of other calls are computed again for
+    /// retransmissions.
+    pub policy: RetransmissionPolicy,
+}
+
+impl Default for ReplyCacheConfig {
+    fn default() -> Self {
+        Self {
+            retention_period: Duration::from_secs(120),
+            capacity: 4096,
+            policy: RetransmissionPolicy::default(),
+        }
+    }
+}
+
+/// Outcome of [`ReplyCache::begin`]
+#[derive(Debug, PartialEq, Eq)]
+pub enum CachedReply {
+    /// The call was not seen before and has to be executed
+    New,
+    /// The call is still being executed; the retransmission is dropped
+    InProgress,
+    /// The call was executed; the reply has to be sent again
+    Replay(Vec<u8>),
+}
+
+/// State of a cached call
+#[derive(Debug)]
+enum Slot {
+    /// Executing, no reply yet
+    InProgress,
+    /// Executed with the given reply at the given time
+    Done(Vec<u8>, Instant),
+}
+
+/// Cached call
+#[derive(Debug)]
+struct Entry {
+    /// Checksum of the call record
+    checksum: u64,
+    /// Execution state
+    slot: Slot,
+}
+
+impl Entry {
+    /// Returns the approximate memory of the entry, including its keys
+    fn size(&self) -> usize {
+        let reply = match &self.slot {
+            Slot::InProgress => 0,
+            Slot::Done(reply, _) => reply.len(),
+        };
+        reply
+            + std::mem::size_of::<((u32, SocketAddr), Entry)>()
+            + std::mem::size_of::<(u32, SocketAddr)>()
+    }
+}
+
+/// Cache contents, with the keys in insertion order for eviction
+#[derive(Debug, Default)]
+struct Entries {
+    calls: HashMap<(u32, SocketAddr), Entry>,
+    order: VecDeque<(u32, SocketAddr)>,
+}
+
+/// Per-socket cache of replies to non-idempotent calls
+#[derive(Debug)]
+pub struct ReplyCache {
+    config: ReplyCacheConfig,
+    /// Computes call checksums
+    hasher: RandomState,
+    entries: Mutex<Entries>,
+    memory: MemoryCharge,
+}
+
+impl Default for ReplyCache {
+    fn default() -> Self {
+        Self::new(ReplyCacheConfig::default())
+    }
+}
+
+impl ReplyCache {
+    /// Creates a cache with the given limits
+    pub fn new(config: ReplyCacheConfig) -> Self {
+        Self {
+            config,
+            hasher: RandomState::new(),
+            entries: Mutex::default(),
+            memory: MemoryCharge::default(),
+        }
+    }
+
+    /// Returns whether replies of a procedure are cached
+    pub fn caches(&self, program: u32, version: u32, procedure: u32) -> bool {
+        self.config.policy.covers(program, version, procedure)
+    }
+
+    /// Looks up a call and marks it in progress if it is new
+    ///
+    /// # Arguments
+    ///
+    /// * `xid` - Transaction ID of the call
+    /// * `client_addr` - Address the datagram was received from
+    /// * `call` - Complete call record
+    pub fn begin(&self, xid: u32, client_addr: SocketAddr, call: &[u8]) -> CachedReply {
+        let checksum = self.hasher.hash_one(call);
+        let key = (xid, client_addr);
+        {
+            let mut entries = self.entries.lock().unwrap();
+            self.expire(&mut entries);
+            match entries.calls.get(&key) {
+                Some(entry) if entry.checksum == checksum => match &entry.slot {
+                    Slot::InProgress => return CachedReply::InProgress,
+                    Slot::Done(reply, _) => return CachedReply::Replay(reply.clone()),
+                },
+                // same XID for a different call, the old entry is outdated
+                Some(_) => {}
+                None => entries.order.push_back(key),
+            }
+            let entry = Entry { checksum, slot: Slot::InProgress };
+            self.memory.charge(entry.size());
+            if let Some(outdated) = entries.calls.insert(key, entry) {
+                self.memory.release(outdated.size());
+            }
+        }
+        self.memory.reclaim();
+        CachedReply::New
+    }
+
+    /// Stores the reply of a call started with [`ReplyCache::begin`]
+    pub fn complete(&self, xid: u32, client_addr: SocketAddr, reply: &[u8]) {
+        let key = (xid, client_addr);
+        if let Some(entry) = self.entries.lock().unwrap().calls.get_mut(&key) {
+            self.memory.release(entry.size());
+            entry.slot = Slot::Done(reply.to_vec(), Instant::now());
+            self.memory.charge(entry.size());
+        }
+        self.memory.reclaim();
+    }
+
+    /// Forgets a call that failed without a reply, so a retransmission is
+    /// executed again
+    pub fn abandon(&self, xid: u32, client_addr: SocketAddr) {
+        let key = (xid, client_addr);
+        if let Some(entry) = self.entries.lock().unwrap().calls.remove(&key) {
+            self.memory.release(entry.size());
+        }
+    }
+
+    /// Returns the number of cached calls
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().calls.len()
+    }
+
+    /// Returns whether no call is cached
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes replies older than the retention period and the oldest calls
+    /// until there is room for another one
+    fn expire(&self, entries: &mut Entries) {
+        while let Some(key) = entries.order.front() {
+            let evict = match entries.calls.get(key) {
+                None => true,
+                Some(_) if entries.calls.len() >= self.config.capacity => true,
+                Some(Entry { slot: Slot::Done(_, at), .. }) => {
+                    at.elapsed() >= self.config.retention_period
+                }
+                Some(Entry { slot: Slot::InProgress, .. }) => false,
+            };
+            if !evict {
+                break;
+            }
+            let key = entries.order.pop_front().unwrap();
+            if let Some(entry) = entries.calls.remove(&key) {
+                self.memory.release(entry.size());
+            }
+        }
+    }
+}
+
+impl MemoryConsumer for ReplyCache {
+    fn memory(&self) -> &MemoryCharge {
+        &self.memory
+    }
+
+    fn shrink(&self, bytes: usize) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let mut freed = 0;
+        while freed < bytes {
+            let Some(key) = entries.order.front().copied() else {
+                break;
+            };
+            if matches!(entries.calls.get(&key), Some(Entry { slot: Slot::InProgress, .. })) {
+                break;
+            }
+            entries.order.pop_front();
+            if let Some(entry) = entries.calls.remove(&key) {
+                freed += entry.size();
+            }
+        }
+        self.memory.release(freed);
+        freed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replays_completed_calls() {
+        let cache = ReplyCache::default();
+        let (client, other) = ("10.0.0.1:700".parse().unwrap(), "10.0.0.2:700".parse().unwrap());
+        assert_eq!(cache.begin(1, client, b"call"), CachedReply::New);
+        assert_eq!(cache.begin(1, client, b"call"), CachedReply::InProgress);
+        cache.complete(1, client, b"reply");
+        assert_eq!(cache.begin(1, client, b"call"), CachedReply::Replay(b"reply".to_vec()));
+        assert_eq!(cache.begin(1, other, b"call"), CachedReply::New);
+        assert_eq!(cache.begin(1, client, b"different call"), CachedReply::New);
+
+        cache.abandon(1, other);
+        assert_eq!(cache.begin(1, other, b"call"), CachedReply::New);
+    }
+
+    #[test]
+    fn evicts_oldest_calls() {
+        let cache = ReplyCache::new(ReplyCacheConfig { capacity: 2, ..Default::default() });
+        let client = "10.0.0.1:700".parse().unwrap();
+        for xid in 0..3 {
+            assert_eq!(cache.begin(xid, client, b"call"), CachedReply::New);
+            cache.complete(xid, client, b"reply");
+        }
+        assert_eq!(cache.begin(3, client, b"call"), CachedReply::New);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.begin(0, client, b"call"), CachedReply::New);
+    }
+}
@@ -0,0 +1,187 @@
+//! Streaming of large reply bodies over record-marked connections.
+//!
+//! A `READ` reply is normally encoded into one buffer, so a client with an
+//! `rtmax` of 1MB makes the server hold the data twice for every call: once as
+//! returned by the VFS and once encoded in the reply. With streaming enabled, the
+//! `READ` handler only encodes the reply up to the data length and leaves a
+//! [`StreamedBody`] in the connection's [`ReplyStream`]. The connection then
+//! sends the buffered part as a first record fragment and reads the data
+//! chunk by chunk from the VFS, sending every chunk as a fragment of its own, so
+//! at most one chunk is held in memory.
+//!
+//! The length of the data is part of the reply before the data, so it is
+//! derived from the file size. If the VFS returns fewer bytes than promised, the
+//! file shrank during the call and the connection is closed; the client then
+//! retransmits the call on a new connection.
+
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tracing::trace;
+
+use crate::protocol::rpc::ServerError;
+use crate::protocol::xdr::nfs3;
+use crate::vfs::NFSFileSystem;
+
+/// Highest bit of a record marking header, set on the last fragment
+const LAST_FRAGMENT: u32 = 1 << 31;
+
+/// File data appended to a reply after its buffered part
+pub struct StreamedBody {
+    /// File system the data is read from
+    vfs: Arc<dyn NFSFileSystem + Send + Sync>,
+    /// File being read
+    id: nfs3::fileid3,
+    /// Offset of the next chunk
+    offset: u64,
+    /// Bytes still to send, excluding padding
+    remaining: u32,
+    /// Maximum size of a chunk
+    chunk_size: u32,
+    /// Bytes of XDR padding after the data
+    padding: usize,
+}
+
+impl fmt::Debug for StreamedBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamedBody")
+            .field("id", &self.id)
+            .field("offset", &self.offset)
+            .field("remaining", &self.remaining)
+            .finish()
+    }
+}
+
+impl StreamedBody {
+    /// Reads the next chunk of data, with the XDR padding after the last one
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<Vec<u8>>, nfsstat3>` - The chunk, `None` when all data was
+    ///   returned, or the error of the VFS. Short reads are continued until the
+    ///   chunk is complete; reaching the end of the file first is an `NFS3ERR_IO`
+    ///   error, as the reply already promised the length.
+    pub async fn next_chunk(&mut self) -> Result<Option<Vec<u8>>, nfs3::nfsstat3> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        let count = self.remaining.min(self.chunk_size);
+        let (mut data, _) = self.vfs.read(self.id, self.offset, count).await?;
+        while data.len() < count as usize {
+            let offset = self.offset + data.len() as u64;
+            let (more, _) = self.vfs.read(self.id, offset, count - data.len() as u32).await?;
+            if more.is_empty() {
+                return Err(nfs3::nfsstat3::NFS3ERR_IO);
+            }
+            data.extend_from_slice(&more);
+        }
+        data.truncate(count as usize);
+        self.offset += u64::from(count);
+        self.remaining -= count;
+        if self.remaining == 0 {
+            data.resize(data.len() + self.padding, 0);
+        }
+        Ok(Some(data))
+    }
+}
+
+/// Per-connection slot for a reply body that is streamed after the buffered reply
+///
+/// Cloned into the context of every call of a connection. Calls of a connection
+/// are processed one at a time, and the connection takes the body out of the
+/// slot before it processes the next call.
+#[derive(Clone, Debug)]
+pub struct ReplyStream {
+    /// Size of the chunks data is read and sent in; smaller reads are not streamed
+    chunk_size: u32,
+    /// Body of the call being processed
+    body: Arc<Mutex<Option<StreamedBody>>>,
+}
+
+impl ReplyStream {
+    /// Creates an empty slot streaming data in chunks of `chunk_size` bytes
+    pub fn new(chunk_size: u32) -> Self {
+        Self { chunk_size: chunk_size.max(1), body: Arc::default() }
+    }
+
+    /// Returns whether a read of `count` bytes should be streamed
+    pub fn streams(&self, count: u32) -> bool {
+        count > self.chunk_size
+    }
+
+    /// Stores the body of the current reply
+    ///
+    /// # Arguments
+    ///
+    /// * `vfs` - File system the data is read from
+    /// * `id` - File being read
+    /// * `offset` - Offset of the data
+    /// * `count` - Length of the data, as announced in the reply
+    pub fn set(
+        &self,
+        vfs: Arc<dyn NFSFileSystem + Send + Sync>,
+        id: nfs3::fileid3,
+        offset: u64,
+        count: u32,
+    ) {
+        let padding = (4 - count as usize % 4) % 4;
+        let body = StreamedBody {
+            vfs,
+            id,
+            offset,
+            remaining: count,
+            chunk_size: self.chunk_size,
+            padding,
+        };
+        *self.body.lock().unwrap() = Some(body);
+    }
+
+    /// Takes the body of the current reply, if any
+    pub fn take(&self) -> Option<StreamedBody> {
+        self.body.lock().unwrap().take()
+    }
+}
+
+/// Encoded reply of a call, ready to be sent
+#[derive(Debug)]
+pub struct Reply {
+    /// Buffered part of the reply
+    pub data: Vec<u8>,
+    /// Data following the buffered part, read while sending
+    pub body: Option<StreamedBody>,
+}
+
+/// Writes a reply as record-marked fragments
+///
+/// The buffered part becomes the first fragment and every chunk of the streamed
+/// body another one. A reply without a body is written as by
+/// [`super::write_fragment`].
+///
+/// # Returns
+///
+/// * `Result<(), ServerError>` - An error if writing failed or the body could not be
+///   read completely, in which case the record is incomplete and the connection
+///   must be closed
+pub async fn write_reply(
+    socket: &mut (impl AsyncWrite + Unpin),
+    reply: Reply,
+) -> Result<(), ServerError> {
+    let Some(mut body) = reply.body else {
+        return super::write_fragment(socket, &reply.data).await;
+    };
+    socket.write_all(&(reply.data.len() as u32).to_be_bytes()).await.map_err(ServerError::Io)?;
+    socket.write_all(&reply.data).await.map_err(ServerError::Io)?;
+    while let Some(chunk) = body.next_chunk().await.map_err(|stat| {
+        let message = format!("streamed read of {} at {} failed: {:?}", body.id, body.offset, stat);
+        ServerError::Io(io::Error::other(message))
+    })? {
+        let last = body.remaining == 0;
+        let header = chunk.len() as u32 | if last { LAST_FRAGMENT } else { 0 };
+        trace!("Writing streamed fragment length:{}, last:{}", chunk.len(), last);
+        socket.write_all(&header.to_be_bytes()).await.map_err(ServerError::Io)?;
+        socket.write_all(&chunk).await.map_err(ServerError::Io)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,213 @@
+//! Fair sharing of call processing between connections.
+//!
+//! Every connection processes its calls in a task of its own. When the backend
+//! is the bottleneck, e.g. a database with few connections, a client issuing
+//! calls back to back gets the backend as often as the runtime happens to poll
+//! its task, and can starve clients issuing calls now and then. A [`Scheduler`]
+//! bounds the number of calls processed at once across all connections, and
+//! hands out free slots to the waiting connections in weighted round-robin
+//! order.
+//!
+//! Every connection is a [`Lane`] with a weight. When slots are contended, a
+//! lane of weight 2 is served twice as often as a lane of weight 1. A lane
+//! that was idle does not save up turns: it joins the round where the others
+//! currently are.
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::oneshot;
+
+use super::quirks::in_network;
+
+/// Distance between the turns of a lane of weight 1
+const STRIDE: u64 = 1 << 20;
+
+/// Bounds and orders the calls processed at once
+#[derive(Debug)]
+pub struct Scheduler {
+    /// Number of calls processed at once at most
+    limit: usize,
+    /// Network address, prefix length and weight, in the order added
+    weights: Vec<(IpAddr, u8, u32)>,
+    state: Mutex<State>,
+}
+
+/// Slots and waiting calls
+#[derive(Debug, Default)]
+struct State {
+    /// Number of calls processing
+    running: usize,
+    /// Turn of the call granted last
+    now: u64,
+    /// Waiting calls by turn, and by arrival among equal turns
+    waiting: BTreeMap<(u64, u64), oneshot::Sender<Permit>>,
+    /// Next turn of each lane
+    turns: HashMap<u64, u64>,
+    /// Number of lanes and calls seen, for unique keys
+    lanes: u64,
+    arrivals: u64,
+}
+
+impl Scheduler {
+    /// Creates a scheduler processing up to `limit` calls at once
+    pub fn new(limit: usize) -> Self {
+        Self { limit: limit.max(1), weights: Vec::new(), state: Mutex::default() }
+    }
+
+    /// Assigns a weight to the clients of a network
+    ///
+    /// The first rule matching a client applies, clients matching no rule get
+    /// weight 1.
+    ///
+    /// # Arguments
+    ///
+    /// * `network` - Network address
+    /// * `prefix_len` - Number of leading bits of `network` a client address
+    ///   must share, the full length of the address for a single client
+    /// * `weight` - Share of the client relative to others, at least 1
+    pub fn add_weight(&mut self, network: IpAddr, prefix_len: u8, weight: u32) -> &mut Self {
+        self.weights.push((network, prefix_len, weight.max(1)));
+        self
+    }
+
+    /// Returns the weight of the client at `client`
+    pub fn weight_for(&self, client: IpAddr) -> u32 {
+        self.weights
+            .iter()
+            .find(|(network, prefix_len, _)| in_network(client, *network, *prefix_len))
+            .map_or(1, |(_, _, weight)| *weight)
+    }
+
+    /// Creates the lane of a connection
+    pub fn lane(self: &Arc<Self>, weight: u32) -> Lane {
+        let mut state = self.state.lock().unwrap();
+        let id = state.lanes;
+        state.lanes += 1;
+        Lane { id, step: STRIDE / u64::from(weight.max(1)), scheduler: self.clone() }
+    }
+
+    /// Returns the number of calls processing
+    pub fn running(&self) -> usize {
+        self.state.lock().unwrap().running
+    }
+
+    /// Returns the number of calls waiting for a slot
+    pub fn waiting(&self) -> usize {
+        self.state.lock().unwrap().waiting.len()
+    }
+
+    /// Frees a slot and grants it to the next waiting call
+    fn release(self: &Arc<Self>) {
+        let mut state = self.state.lock().unwrap();
+        state.running -= 1;
+        while state.running < self.limit {
+            let Some(((turn, _), waiter)) = state.waiting.pop_first() else {
+                break;
+            };
+            state.now = state.now.max(turn);
+            state.running += 1;
+            if let Err(permit) = waiter.send(Permit { scheduler: self.clone() }) {
+                // the call was given up while waiting
+                std::mem::forget(permit);
+                state.running -= 1;
+            }
+        }
+    }
+}
+
+/// Calls of a connection, see [`Scheduler::lane`]
+#[derive(Debug)]
+pub struct Lane {
+    id: u64,
+    /// Distance between the turns of the lane
+    step: u64,
+    scheduler: Arc<Scheduler>,
+}
+
+impl Lane {
+    /// Waits for a slot to process a call in, held until the permit is dropped
+    pub async fn acquire(&self) -> Permit {
+        let receiver = {
+            let mut state = self.scheduler.state.lock().unwrap();
+            let now = state.now;
+            let turn = state.turns.get(&self.id).map_or(now, |turn| (*turn).max(now));
+            state.turns.insert(self.id, turn + self.step);
+            if state.running < self.scheduler.limit && state.waiting.is_empty() {
+                state.now = turn;
+                state.running += 1;
+                return Permit { scheduler: self.scheduler.clone() };
+            }
+            let (sender, receiver) = oneshot::channel();
+            let arrival = state.arrivals;
+            state.arrivals += 1;
+            state.waiting.insert((turn, arrival), sender);
+            receiver
+        };
+        receiver.await.expect("waiting calls are granted or dropped with the scheduler")
+    }
+}
+
+impl Drop for Lane {
+    fn drop(&mut self) {
+        self.scheduler.state.lock().unwrap().turns.remove(&self.id);
+    }
+}
+
+/// Slot of a call processing, freed when dropped
+#[derive(Debug)]
+pub struct Permit {
+    scheduler: Arc<Scheduler>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.scheduler.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn serves_lanes_by_weight() {
+        let scheduler = Arc::new(Scheduler::new(1));
+        let heavy = Arc::new(scheduler.lane(2));
+        let light = Arc::new(scheduler.lane(1));
+        let blocker = heavy.acquire().await;
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut tasks = Vec::new();
+        for (name, lane) in [("heavy", &heavy), ("light", &light)] {
+            for _ in 0..4 {
+                let (lane, order) = (lane.clone(), order.clone());
+                tasks.push(tokio::spawn(async move {
+                    let _permit = lane.acquire().await;
+                    order.lock().unwrap().push(name);
+                }));
+                tokio::task::yield_now().await;
+            }
+        }
+        assert_eq!((scheduler.running(), scheduler.waiting()), (1, 8));
+        drop(blocker);
+        for task in tasks {
+            task.await.unwrap();
+        }
+        let order = order.lock().unwrap();
+        // the heavy lane used its first turn for the blocker, then gets two
+        // turns per turn of the light one while both wait
+        let expected = ["light", "heavy", "heavy", "light", "heavy", "heavy", "light", "light"];
+        assert_eq!(*order, expected);
+        assert_eq!(scheduler.running(), 0);
+    }
+
+    #[test]
+    fn weights_by_network() {
+        let mut scheduler = Scheduler::new(4);
+        scheduler.add_weight("10.0.0.0".parse().unwrap(), 8, 3);
+        assert_eq!(scheduler.weight_for("10.1.2.3".parse().unwrap()), 3);
+        assert_eq!(scheduler.weight_for("192.168.0.1".parse().unwrap()), 1);
+    }
+}
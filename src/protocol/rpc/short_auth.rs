@@ -0,0 +1,187 @@
+//! Short-hand credentials (`AUTH_SHORT`) as described in RFC 5531 section 9.1.
+//!
+//! `AUTH_UNIX` credentials carry the machine name, user, group and up to 16
+//! supplementary groups in every call, and the server decodes them every time.
+//! With [`ShortAuth`] enabled, the reply to a call with `AUTH_UNIX` credentials
+//! carries an `AUTH_SHORT` verifier holding a short handle for them. Clients
+//! supporting it send the handle instead of the full credentials from then on.
+//!
+//! A handle the server does not know, e.g. one evicted from the table or issued
+//! before a restart, is refused with `AUTH_REJECTEDCRED`, upon which the client
+//! falls back to its `AUTH_UNIX` credentials and receives a new handle. Clients
+//! ignoring the verifier keep sending `AUTH_UNIX` credentials.
+//!
+//! Handles are drawn from the [`crate::entropy::Entropy`] of the listener, so
+//! those of an earlier run of the server do not map to other credentials by
+//! chance.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::Cursor;
+use std::sync::Mutex;
+
+use super::fast_path;
+use crate::entropy::Entropy;
+use crate::protocol::xdr::deserialize;
+use crate::protocol::xdr::rpc::{auth_flavor, auth_unix};
+
+/// Number of credentials remembered by default
+pub const DEFAULT_CAPACITY: usize = 4096;
+
+/// Length of an encoded `AUTH_SHORT` verifier: flavor, length and handle
+const VERIFIER_LEN: usize = 16;
+
+/// Table of short handles issued for `AUTH_UNIX` credentials
+#[derive(Debug)]
+pub struct ShortAuth {
+    capacity: usize,
+    state: Mutex<State>,
+}
+
+/// Issued handles
+#[derive(Debug, Default)]
+struct State {
+    /// Credentials by handle
+    credentials: HashMap<u64, auth_unix>,
+    /// Handles by encoded credentials
+    handles: HashMap<Vec<u8>, u64>,
+    /// Encoded credentials in the order they were issued a handle
+    issued: VecDeque<Vec<u8>>,
+}
+
+impl ShortAuth {
+    /// Creates a table remembering up to `capacity` credentials
+    ///
+    /// The handles of the oldest credentials are forgotten first.
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity: capacity.max(1), state: Mutex::default() }
+    }
+
+    /// Returns the handle for encoded `AUTH_UNIX` credentials, issuing one if
+    /// needed
+    ///
+    /// New handles are drawn from `entropy`. Returns `None` if the credentials
+    /// cannot be decoded.
+    pub fn issue(&self, credentials: &[u8], entropy: &dyn Entropy) -> Option<u64> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(handle) = state.handles.get(credentials) {
+            return Some(*handle);
+        }
+        let auth = deserialize::<auth_unix>(&mut Cursor::new(credentials)).ok()?;
+        let mut handle = entropy.next_u64();
+        while state.credentials.contains_key(&handle) {
+            handle = entropy.next_u64();
+        }
+        if state.issued.len() >= self.capacity {
+            if let Some(oldest) = state.issued.pop_front() {
+                if let Some(evicted) = state.handles.remove(&oldest) {
+                    state.credentials.remove(&evicted);
+                }
+            }
+        }
+        state.credentials.insert(handle, auth);
+        state.handles.insert(credentials.to_vec(), handle);
+        state.issued.push_back(credentials.to_vec());
+        Some(handle)
+    }
+
+    /// Returns the credentials of the handle in the body of an `AUTH_SHORT`
+    /// credential, if it was issued and not forgotten since
+    pub fn resolve(&self, body: &[u8]) -> Option<auth_unix> {
+        let handle = u64::from_be_bytes(body.try_into().ok()?);
+        self.state.lock().unwrap().credentials.get(&handle).cloned()
+    }
+
+    /// Returns the number of credentials remembered
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().credentials.len()
+    }
+
+    /// Returns whether no credentials are remembered
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for ShortAuth {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+/// Returns the encoded `AUTH_UNIX` credentials of a call record, if it has any
+pub(super) fn unix_credentials(record: &[u8]) -> Option<&[u8]> {
+    let (flavor, end) = fast_path::skip_auth(record, 24)?;
+    (flavor == auth_flavor::AUTH_UNIX as u32).then(|| &record[32..end])
+}
+
+/// Replaces the empty `AUTH_NULL` verifier of an accepted reply starting at
+/// `start` of `output` with an `AUTH_SHORT` verifier holding `handle`
+///
+/// Replies with another verifier, and denied replies, are left alone.
+pub(super) fn attach_verifier(output: &mut Vec<u8>, start: usize, handle: u64) {
+    // xid, REPLY, MSG_ACCEPTED, then the verifier
+    const ACCEPTED: [u8; 8] = [0, 0, 0, 1, 0, 0, 0, 0];
+    let Some(header) = output.get(start + 4..start + 20) else {
+        return;
+    };
+    if header[..8] != ACCEPTED || header[8..] != [0; 8] {
+        return;
+    }
+    let mut verifier = [0; VERIFIER_LEN];
+    verifier[..4].copy_from_slice(&(auth_flavor::AUTH_SHORT as u32).to_be_bytes());
+    verifier[4..8].copy_from_slice(&8_u32.to_be_bytes());
+    verifier[8..].copy_from_slice(&handle.to_be_bytes());
+    output.splice(start + 12..start + 20, verifier);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entropy::SeededEntropy;
+    use crate::protocol::xdr::Serialize;
+
+    fn encoded(uid: u32) -> Vec<u8> {
+        let mut out = Vec::new();
+        auth_unix { uid, ..Default::default() }.serialize(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn issues_and_evicts_handles() {
+        let short_auth = ShortAuth::new(2);
+        let entropy = SeededEntropy::new(1);
+        let first = short_auth.issue(&encoded(1), &entropy).unwrap();
+        assert_eq!(short_auth.issue(&encoded(1), &entropy), Some(first));
+        // the same seed hands out the same handles
+        assert_eq!(ShortAuth::new(2).issue(&encoded(5), &SeededEntropy::new(1)), Some(first));
+        assert_eq!(short_auth.resolve(&first.to_be_bytes()).unwrap().uid, 1);
+        assert!(short_auth.resolve(&[1, 2]).is_none());
+        assert!(short_auth.issue(&[0, 0], &entropy).is_none());
+
+        let second = short_auth.issue(&encoded(2), &entropy).unwrap();
+        assert_ne!(first, second);
+        short_auth.issue(&encoded(3), &entropy).unwrap();
+        assert_eq!(short_auth.len(), 2);
+        assert!(short_auth.resolve(&first.to_be_bytes()).is_none());
+        assert_eq!(short_auth.resolve(&second.to_be_bytes()).unwrap().uid, 2);
+    }
+
+    #[test]
+    fn attaches_verifier_to_accepted_replies() {
+        let mut output = vec![9];
+        crate::protocol::xdr::rpc::make_success_reply(7).serialize(&mut output).unwrap();
+        output.extend_from_slice(&[0, 0, 0, 0]);
+        attach_verifier(&mut output, 1, 0x0102);
+        assert_eq!(&output[13..21], &[0, 0, 0, 2, 0, 0, 0, 8]);
+        assert_eq!(&output[21..29], &0x0102_u64.to_be_bytes());
+        assert_eq!(output.len(), 1 + 24 + 4 + 8);
+
+        let mut denied = Vec::new();
+        crate::protocol::xdr::rpc::auth_error_reply_message(7, Default::default())
+            .serialize(&mut denied)
+            .unwrap();
+        let before = denied.clone();
+        attach_verifier(&mut denied, 0, 1);
+        assert_eq!(denied, before);
+    }
+}
@@ -0,0 +1,88 @@
+//! Mapping of client identities to an anonymous user.
+//!
+//! `AUTH_UNIX` credentials are asserted by the client, so a server that trusts
+//! them lets anybody with root on a client act as root on the export. Like the
+//! `root_squash` and `all_squash` options of `/etc/exports`, a [`Squash`]
+//! replaces such identities with an anonymous user and group before a call is
+//! dispatched. Calls with `AUTH_NULL` credentials carry the identity of root,
+//! so they are squashed along with root.
+
+use crate::protocol::xdr::rpc::auth_unix;
+
+/// Identities replaced by the anonymous user
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SquashMode {
+    /// Every identity is used as sent
+    #[default]
+    None,
+    /// Root is replaced
+    Root,
+    /// Every identity is replaced
+    All,
+}
+
+/// Identity mapping applied to the credentials of every call
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Squash {
+    /// Identities replaced
+    pub mode: SquashMode,
+    /// User ID of the anonymous user
+    pub anon_uid: u32,
+    /// Group ID of the anonymous user
+    pub anon_gid: u32,
+}
+
+impl Default for Squash {
+    /// No squashing, with `nobody` and `nogroup` as the anonymous identity
+    fn default() -> Self {
+        Self { mode: SquashMode::None, anon_uid: 65534, anon_gid: 65534 }
+    }
+}
+
+impl Squash {
+    /// Creates a mapping of the given identities to the default anonymous user
+    pub fn new(mode: SquashMode) -> Self {
+        Self { mode, ..Self::default() }
+    }
+
+    /// Replaces the identity in `auth` if the mode asks for it
+    pub fn apply(&self, auth: &mut auth_unix) {
+        let squash = match self.mode {
+            SquashMode::None => false,
+            SquashMode::Root => auth.uid == 0,
+            SquashMode::All => true,
+        };
+        if squash {
+            auth.uid = self.anon_uid;
+            auth.gid = self.anon_gid;
+            auth.gids.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn squashes_selected_identities() {
+        let user = auth_unix { uid: 1000, gid: 100, gids: vec![10], ..Default::default() };
+        let root = auth_unix { gids: vec![0], ..Default::default() };
+
+        let mut auth = root.clone();
+        Squash::default().apply(&mut auth);
+        assert_eq!(auth.uid, 0);
+
+        let squash = Squash { anon_uid: 99, ..Squash::new(SquashMode::Root) };
+        let mut auth = root.clone();
+        squash.apply(&mut auth);
+        assert_eq!((auth.uid, auth.gid, auth.gids.len()), (99, 65534, 0));
+        let mut auth = user.clone();
+        squash.apply(&mut auth);
+        assert_eq!(auth.uid, 1000);
+
+        let mut auth = user;
+        Squash::new(SquashMode::All).apply(&mut auth);
+        assert_eq!((auth.uid, auth.gid), (65534, 65534));
+    }
+}
@@ -0,0 +1,561 @@
+//! Server statistics for introspection without external metrics infrastructure.
+//!
+//! [`ServerStats`] keeps lock-free counters that the RPC layer updates for every
+//! call: per-procedure call counts, failures and service times, and hit rates
+//! of the server side caches (duplicate request detection, read-ahead, write
+//! coalescing, missing names and read results). Operators take a
+//! [`StatsSnapshot`] at any time, either to inspect the fields programmatically
+//! or to print it in a `/proc`-style text form through its `Display`
+//! implementation.
+//!
+//! With a slow call threshold set, calls taking longer are also counted per
+//! procedure and reported as warnings, which makes a backend that hangs
+//! intermittently visible before clients complain.
+//!
+//! Calls and the bytes read and written are also counted per export, for
+//! chargeback and observability of exports serving different tenants. Every
+//! listener serves one export; listeners sharing one instance through
+//! `NFSTcpListener::with_stats` report all their exports in one snapshot.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use num_traits::FromPrimitive;
+use tracing::warn;
+
+use crate::protocol::xdr::{mount, nfs3, portmap};
+
+/// Identifies a procedure by RPC program, version and procedure number
+type ProcedureKey = (u32, u32, u32);
+
+/// Counters of a single procedure
+#[derive(Debug, Default)]
+struct ProcedureCounters {
+    /// Number of calls handled
+    calls: AtomicU64,
+    /// Number of calls whose handler failed
+    errors: AtomicU64,
+    /// Sum of service times in nanoseconds
+    total_nanos: AtomicU64,
+    /// Longest service time in nanoseconds
+    max_nanos: AtomicU64,
+    /// Number of calls slower than the slow call threshold
+    slow: AtomicU64,
+}
+
+/// Hit and miss counters of a cache
+#[derive(Debug, Default)]
+pub struct CacheCounters {
+    /// Lookups answered by the cache
+    hits: AtomicU64,
+    /// Lookups the cache could not answer
+    misses: AtomicU64,
+}
+
+impl CacheCounters {
+    /// Records a lookup answered by the cache
+    pub fn hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a lookup the cache could not answer
+    pub fn miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the current counter values
+    fn snapshot(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Counters of a single export
+#[derive(Debug, Default)]
+pub struct ExportCounters {
+    /// Calls handled per procedure
+    calls: RwLock<BTreeMap<ProcedureKey, AtomicU64>>,
+    /// Bytes returned by `READ`
+    bytes_read: AtomicU64,
+    /// Bytes accepted by `WRITE`
+    bytes_written: AtomicU64,
+}
+
+impl ExportCounters {
+    /// Records a handled call
+    pub fn record_call(&self, program: u32, version: u32, procedure: u32) {
+        let key = (program, version, procedure);
+        if let Some(calls) = self.calls.read().unwrap().get(&key) {
+            calls.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.calls.write().unwrap().entry(key).or_default().fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records data returned to a client
+    pub fn record_read(&self, bytes: u64) {
+        self.bytes_read.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Records data written by a client
+    pub fn record_write(&self, bytes: u64) {
+        self.bytes_written.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Returns the current counter values
+    fn snapshot(&self, name: &str) -> ExportStats {
+        let calls = self
+            .calls
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(&(program, version, procedure), calls)| {
+                (procedure_name(program, version, procedure), calls.load(Ordering::Relaxed))
+            })
+            .collect();
+        ExportStats {
+            name: name.to_string(),
+            calls,
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Statistics collected by a server
+#[derive(Debug)]
+pub struct ServerStats {
+    /// Time the statistics were created
+    started: Instant,
+    /// Per-procedure counters, created on the first call of a procedure
+    procedures: RwLock<BTreeMap<ProcedureKey, ProcedureCounters>>,
+    /// Service time in nanoseconds above which a call is slow, 0 if disabled
+    slow_threshold_nanos: AtomicU64,
+    /// Per-export counters, created on the first call to an export
+    exports: RwLock<BTreeMap<String, Arc<ExportCounters>>>,
+    /// Retransmitted calls detected by the transaction tracker (hits) versus
+    /// new calls (misses); calls the tracker does not track are not counted
+    pub duplicate_cache: CacheCounters,
+    /// Sequential reads that produced a read-ahead hint (hits) versus other
+    /// reads (misses)
+    pub read_ahead: CacheCounters,
+    /// `UNSTABLE` writes merged into a pending range (hits) versus writes that
+    /// started a new range (misses)
+    pub write_coalescing: CacheCounters,
+    /// `LOOKUP` misses answered from the cache of missing names (hits) versus
+    /// lookups passed to the VFS (misses)
+    pub negative_lookups: CacheCounters,
+    /// `READ` calls answered from the read cache (hits) versus reads of cached
+    /// files passed to the VFS (misses)
+    pub read_cache: CacheCounters,
+}
+
+impl Default for ServerStats {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            procedures: RwLock::default(),
+            slow_threshold_nanos: AtomicU64::new(0),
+            exports: RwLock::default(),
+            duplicate_cache: CacheCounters::default(),
+            read_ahead: CacheCounters::default(),
+            write_coalescing: CacheCounters::default(),
+            negative_lookups: CacheCounters::default(),
+            read_cache: CacheCounters::default(),
+        }
+    }
+}
+
+impl ServerStats {
+    /// Creates empty statistics
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the service time above which calls are counted as slow
+    ///
+    /// Can be changed while the server runs; `None` disables slow call
+    /// detection.
+    pub fn set_slow_threshold(&self, threshold: Option<Duration>) {
+        let nanos = threshold.map_or(0, |t| u64::try_from(t.as_nanos()).unwrap_or(u64::MAX).max(1));
+        self.slow_threshold_nanos.store(nanos, Ordering::Relaxed);
+    }
+
+    /// Returns the service time above which calls are counted as slow
+    pub fn slow_threshold(&self) -> Option<Duration> {
+        match self.slow_threshold_nanos.load(Ordering::Relaxed) {
+            0 => None,
+            nanos => Some(Duration::from_nanos(nanos)),
+        }
+    }
+
+    /// Records a handled call
+    ///
+    /// # Arguments
+    ///
+    /// * `program` - RPC program number
+    /// * `version` - Program version
+    /// * `procedure` - Procedure number
+    /// * `elapsed` - Time spent handling the call
+    /// * `ok` - Whether the handler completed without error
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Whether the call took longer than the slow call threshold
+    pub fn record_call(
+        &self,
+        program: u32,
+        version: u32,
+        procedure: u32,
+        elapsed: Duration,
+        ok: bool,
+    ) -> bool {
+        let key = (program, version, procedure);
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        let threshold = self.slow_threshold_nanos.load(Ordering::Relaxed);
+        let slow = threshold != 0 && nanos > threshold;
+        let update = |counters: &ProcedureCounters| {
+            counters.calls.fetch_add(1, Ordering::Relaxed);
+            if !ok {
+                counters.errors.fetch_add(1, Ordering::Relaxed);
+            }
+            if slow {
+                counters.slow.fetch_add(1, Ordering::Relaxed);
+            }
+            counters.total_nanos.fetch_add(nanos, Ordering::Relaxed);
+            counters.max_nanos.fetch_max(nanos, Ordering::Relaxed);
+        };
+        if let Some(counters) = self.procedures.read().unwrap().get(&key) {
+            update(counters);
+            return slow;
+        }
+        update(self.procedures.write().unwrap().entry(key).or_default());
+        slow
+    }
+
+    /// Returns the counters of an export, creating them on first use
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the export, e.g. `/data`
+    pub fn export(&self, name: &str) -> Arc<ExportCounters> {
+        if let Some(counters) = self.exports.read().unwrap().get(name) {
+            return counters.clone();
+        }
+        self.exports.write().unwrap().entry(name.to_string()).or_default().clone()
+    }
+
+    /// Takes a consistent-enough copy of all counters
+    ///
+    /// Counters are read individually without stopping the server, so values
+    /// of different counters may be a few calls apart.
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let procedures = self
+            .procedures
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(&(program, version, procedure), counters)| ProcedureStats {
+                program,
+                version,
+                procedure,
+                name: procedure_name(program, version, procedure),
+                calls: counters.calls.load(Ordering::Relaxed),
+                errors: counters.errors.load(Ordering::Relaxed),
+                slow: counters.slow.load(Ordering::Relaxed),
+                total_time: Duration::from_nanos(counters.total_nanos.load(Ordering::Relaxed)),
+                max_time: Duration::from_nanos(counters.max_nanos.load(Ordering::Relaxed)),
+            })
+            .collect();
+        let exports = self
+            .exports
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, counters)| counters.snapshot(name))
+            .collect();
+        StatsSnapshot {
+            uptime: self.started.elapsed(),
+            procedures,
+            exports,
+            duplicate_cache: self.duplicate_cache.snapshot(),
+            read_ahead: self.read_ahead.snapshot(),
+            write_coalescing: self.write_coalescing.snapshot(),
+            negative_lookups: self.negative_lookups.snapshot(),
+            read_cache: self.read_cache.snapshot(),
+        }
+    }
+}
+
+/// Emits the warning for a call slower than the slow call threshold
+///
+/// # Arguments
+///
+/// * `program` - RPC program number
+/// * `version` - Program version
+/// * `procedure` - Procedure number
+/// * `fileid` - File the call operated on, if known
+/// * `elapsed` - Time spent handling the call
+/// * `client` - Address of the calling client
+pub(super) fn warn_slow_call(
+    program: u32,
+    version: u32,
+    procedure: u32,
+    fileid: Option<nfs3::fileid3>,
+    elapsed: Duration,
+    client: SocketAddr,
+) {
+    warn!(
+        procedure = %procedure_name(program, version, procedure),
+        fileid,
+        duration_ms = u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX),
+        %client,
+        "slow call"
+    );
+}
+
+/// Returns a readable name for a procedure, e.g. `NFSPROC3_GETATTR`
+fn procedure_name(program: u32, version: u32, procedure: u32) -> String {
+    let name = match (program, version) {
+        (nfs3::PROGRAM, nfs3::VERSION) => {
+            nfs3::NFSProgram::from_u32(procedure).map(|p| format!("{p:?}"))
+        }
+        (mount::PROGRAM, mount::VERSION) => {
+            mount::MountProgram::from_u32(procedure).map(|p| format!("{p:?}"))
+        }
+        (portmap::PROGRAM, portmap::VERSION) => {
+            portmap::PortmapProgram::from_u32(procedure).map(|p| format!("{p:?}"))
+        }
+        _ => None,
+    };
+    name.unwrap_or_else(|| format!("{program}.{version}.{procedure}"))
+}
+
+/// Counters of a single procedure at the time of a snapshot
+#[derive(Clone, Debug)]
+pub struct ProcedureStats {
+    /// RPC program number
+    pub program: u32,
+    /// Program version
+    pub version: u32,
+    /// Procedure number
+    pub procedure: u32,
+    /// Readable procedure name, or `program.version.procedure` if unknown
+    pub name: String,
+    /// Number of calls handled
+    pub calls: u64,
+    /// Number of calls whose handler failed
+    pub errors: u64,
+    /// Number of calls slower than the slow call threshold
+    pub slow: u64,
+    /// Sum of service times
+    pub total_time: Duration,
+    /// Longest service time
+    pub max_time: Duration,
+}
+
+impl ProcedureStats {
+    /// Returns the mean service time, zero if there were no calls
+    pub fn mean_time(&self) -> Duration {
+        let nanos = self.total_time.as_nanos() / u128::from(self.calls.max(1));
+        Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX))
+    }
+}
+
+/// Cache counters at the time of a snapshot
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Lookups answered by the cache
+    pub hits: u64,
+    /// Lookups the cache could not answer
+    pub misses: u64,
+}
+
+impl CacheStats {
+    /// Returns the fraction of lookups answered by the cache, zero without lookups
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+/// Counters of a single export at the time of a snapshot
+#[derive(Clone, Debug, Default)]
+pub struct ExportStats {
+    /// Name of the export
+    pub name: String,
+    /// Number of calls handled by readable procedure name
+    pub calls: BTreeMap<String, u64>,
+    /// Bytes returned by `READ`
+    pub bytes_read: u64,
+    /// Bytes accepted by `WRITE`
+    pub bytes_written: u64,
+}
+
+impl ExportStats {
+    /// Returns the number of calls of all procedures
+    pub fn total_calls(&self) -> u64 {
+        self.calls.values().sum()
+    }
+}
+
+/// Copy of the server statistics at one point in time
+#[derive(Clone, Debug)]
+pub struct StatsSnapshot {
+    /// Time since the statistics were created
+    pub uptime: Duration,
+    /// Counters of every procedure that was called, ordered by program,
+    /// version and procedure number
+    pub procedures: Vec<ProcedureStats>,
+    /// Counters of every export that was called, ordered by name
+    pub exports: Vec<ExportStats>,
+    /// Duplicate request detection
+    pub duplicate_cache: CacheStats,
+    /// Read-ahead hints
+    pub read_ahead: CacheStats,
+    /// `UNSTABLE` write coalescing
+    pub write_coalescing: CacheStats,
+    /// Cached `LOOKUP` misses
+    pub negative_lookups: CacheStats,
+    /// Cached `READ` results
+    pub read_cache: CacheStats,
+}
+
+impl fmt::Display for StatsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "uptime {:.3}s", self.uptime.as_secs_f64())?;
+        writeln!(
+            f,
+            "{:<24} {:>12} {:>8} {:>8} {:>12} {:>12}",
+            "procedure", "calls", "errors", "slow", "mean_us", "max_us"
+        )?;
+        for proc in &self.procedures {
+            writeln!(
+                f,
+                "{:<24} {:>12} {:>8} {:>8} {:>12} {:>12}",
+                proc.name,
+                proc.calls,
+                proc.errors,
+                proc.slow,
+                proc.mean_time().as_micros(),
+                proc.max_time.as_micros()
+            )?;
+        }
+        writeln!(
+            f,
+            "{:<24} {:>12} {:>16} {:>16}",
+            "export", "calls", "read_bytes", "written_bytes"
+        )?;
+        for export in &self.exports {
+            writeln!(
+                f,
+                "{:<24} {:>12} {:>16} {:>16}",
+                export.name,
+                export.total_calls(),
+                export.bytes_read,
+                export.bytes_written
+            )?;
+        }
+        writeln!(f, "{:<24} {:>12} {:>12} {:>8}", "cache", "hits", "misses", "rate")?;
+        for (name, cache) in [
+            ("duplicate_cache", self.duplicate_cache),
+            ("read_ahead", self.read_ahead),
+            ("write_coalescing", self.write_coalescing),
+            ("negative_lookups", self.negative_lookups),
+            ("read_cache", self.read_cache),
+        ] {
+            writeln!(
+                f,
+                "{:<24} {:>12} {:>12} {:>7.1}%",
+                name,
+                cache.hits,
+                cache.misses,
+                cache.hit_rate() * 100.0
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_calls_per_procedure() {
+        let stats = ServerStats::new();
+        stats.record_call(nfs3::PROGRAM, nfs3::VERSION, 1, Duration::from_micros(10), true);
+        stats.record_call(nfs3::PROGRAM, nfs3::VERSION, 1, Duration::from_micros(30), false);
+        stats.record_call(mount::PROGRAM, mount::VERSION, 1, Duration::from_micros(5), true);
+        stats.duplicate_cache.hit();
+        stats.duplicate_cache.miss();
+        stats.duplicate_cache.miss();
+        stats.duplicate_cache.miss();
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.procedures.len(), 2);
+        let getattr = &snapshot.procedures[0];
+        assert_eq!(getattr.name, "NFSPROC3_GETATTR");
+        assert_eq!((getattr.calls, getattr.errors), (2, 1));
+        assert_eq!(getattr.mean_time(), Duration::from_micros(20));
+        assert_eq!(getattr.max_time, Duration::from_micros(30));
+        assert_eq!(snapshot.procedures[1].name, "MOUNTPROC3_MNT");
+        assert_eq!(snapshot.duplicate_cache.hit_rate(), 0.25);
+        assert!(snapshot.to_string().contains("NFSPROC3_GETATTR"));
+    }
+
+    #[test]
+    fn counts_calls_and_bytes_per_export() {
+        let stats = ServerStats::new();
+        let data = stats.export("/data");
+        data.record_call(nfs3::PROGRAM, nfs3::VERSION, 6);
+        data.record_call(nfs3::PROGRAM, nfs3::VERSION, 6);
+        data.record_read(4096);
+        stats.export("/data").record_write(100);
+        let home = stats.export("/home");
+        home.record_call(nfs3::PROGRAM, nfs3::VERSION, 7);
+        home.record_write(512);
+
+        let snapshot = stats.snapshot();
+        let names: Vec<_> = snapshot.exports.iter().map(|export| export.name.as_str()).collect();
+        assert_eq!(names, ["/data", "/home"]);
+        let data = &snapshot.exports[0];
+        assert_eq!(data.calls["NFSPROC3_READ"], 2);
+        assert_eq!((data.bytes_read, data.bytes_written), (4096, 100));
+        let home = &snapshot.exports[1];
+        assert_eq!((home.total_calls(), home.bytes_read, home.bytes_written), (1, 0, 512));
+        assert!(snapshot.to_string().contains("/home"));
+    }
+
+    #[test]
+    fn counts_slow_calls() {
+        let stats = ServerStats::new();
+        assert!(!stats.record_call(nfs3::PROGRAM, nfs3::VERSION, 6, Duration::from_secs(9), true));
+
+        stats.set_slow_threshold(Some(Duration::from_millis(100)));
+        assert_eq!(stats.slow_threshold(), Some(Duration::from_millis(100)));
+        assert!(!stats.record_call(
+            nfs3::PROGRAM,
+            nfs3::VERSION,
+            6,
+            Duration::from_millis(5),
+            true
+        ));
+        assert!(stats.record_call(nfs3::PROGRAM, nfs3::VERSION, 6, Duration::from_secs(1), false));
+
+        let read = &stats.snapshot().procedures[0];
+        assert_eq!((read.calls, read.slow), (3, 1));
+        stats.set_slow_threshold(None);
+        assert_eq!(stats.slow_threshold(), None);
+    }
+}
@@ -12,10 +12,166 @@
 //! The transaction tracking system is essential for maintaining the at-most-once
 //! semantics required by NFS and other RPC-based protocols, where duplicate
 //! operations (like file writes) could cause data corruption.
+//!
+//! Transactions are split into shards by a hash of the client address, each
+//! behind its own lock, so concurrent calls of different clients rarely wait
+//! for each other. [`TransactionTracker::lock_stats`] reports how often a lock
+//! was found held by another call.
+//!
+//! Completed transactions are forgotten once they are older than the retention
+//! period, but only when another call of a client in the same shard comes in.
+//! [`TransactionTracker::stats`] reports how much is tracked, and
+//! [`TransactionTracker::purge_expired`] and
+//! [`TransactionTracker::forget_client`] let a long-running server drop stale
+//! state without waiting for traffic.
+
+use std::collections::hash_map::{Entry, RandomState};
+use std::collections::{HashMap, HashSet};
+use std::hash::BuildHasher;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard, TryLockError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use num_traits::FromPrimitive;
+
+use crate::protocol::xdr::{deserialize, mount, nfs3, portmap, Serialize};
+
+/// Magic number at the start of a dump, "NMTT"
+const DUMP_MAGIC: u32 = 0x4e4d_5454;
+/// Version of the dump format
+const DUMP_VERSION: u32 = 1;
+
+/// Selects the calls a [`TransactionTracker`] tracks
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RetransmissionPolicy {
+    /// Track every call
+    All,
+    /// Track only calls of non-idempotent procedures, see [`is_idempotent`].
+    /// Executing an idempotent procedure twice yields the same result, so
+    /// tracking it only costs memory and lock contention.
+    #[default]
+    NonIdempotent,
+}
+
+impl RetransmissionPolicy {
+    /// Returns whether calls of a procedure are covered by the policy
+    pub fn covers(self, program: u32, version: u32, procedure: u32) -> bool {
+        match self {
+            RetransmissionPolicy::All => true,
+            RetransmissionPolicy::NonIdempotent => !is_idempotent(program, version, procedure),
+        }
+    }
+}
+
+/// Returns whether executing a procedure twice has the same effect as once
+///
+/// Follows the classification of RFC 1813 section 4.5 and common duplicate
+/// request cache implementations. Procedures of unknown programs are treated as
+/// non-idempotent.
+///
+/// # Arguments
+///
+/// * `program` - RPC program number
+/// * `version` - Program version
+/// * `procedure` - Procedure number
+pub fn is_idempotent(program: u32, version: u32, procedure: u32) -> bool {
+    use mount::MountProgram::*;
+    use nfs3::NFSProgram::*;
+    use portmap::PortmapProgram::*;
+
+    match (program, version) {
+        (nfs3::PROGRAM, nfs3::VERSION) => matches!(
+            nfs3::NFSProgram::from_u32(procedure),
+            Some(
+                NFSPROC3_NULL
+                    | NFSPROC3_GETATTR
+                    | NFSPROC3_LOOKUP
+                    | NFSPROC3_ACCESS
+                    | NFSPROC3_READLINK
+                    | NFSPROC3_READ
+                    | NFSPROC3_READDIR
+                    | NFSPROC3_READDIRPLUS
+                    | NFSPROC3_FSSTAT
+                    | NFSPROC3_FSINFO
+                    | NFSPROC3_PATHCONF
+                    | NFSPROC3_COMMIT
+            )
+        ),
+        (mount::PROGRAM, mount::VERSION) => matches!(
+            mount::MountProgram::from_u32(procedure),
+            Some(MOUNTPROC3_NULL | MOUNTPROC3_DUMP | MOUNTPROC3_EXPORT)
+        ),
+        (portmap::PROGRAM, portmap::VERSION) => matches!(
+            portmap::PortmapProgram::from_u32(procedure),
+            Some(PMAPPROC_NULL | PMAPPROC_GETPORT | PMAPPROC_DUMP)
+        ),
+        _ => false,
+    }
+}
 
-use std::collections::HashMap;
-use std::sync::Mutex;
-use std::time::{Duration, SystemTime};
+/// Limits of a [`TransactionTracker`]
+#[derive(Clone, Copy, Debug)]
+pub struct TransactionTrackerConfig {
+    /// How long completed transactions are remembered
+    pub retention_period: Duration,
+    /// Maximum number of completed transactions remembered; the oldest ones are
+    /// forgotten first when it is exceeded. The limit is divided evenly among
+    /// the shards.
+    pub capacity: usize,
+    /// Which calls are tracked
+    pub policy: RetransmissionPolicy,
+    /// Number of independently locked shards
+    pub shards: usize,
+}
+
+impl Default for TransactionTrackerConfig {
+    fn default() -> Self {
+        Self {
+            retention_period: Duration::from_secs(60),
+            capacity: 65_536,
+            policy: RetransmissionPolicy::default(),
+            shards: 16,
+        }
+    }
+}
+
+/// Lock statistics of a [`TransactionTracker`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TrackerLockStats {
+    /// Number of shards
+    pub shards: usize,
+    /// Number of shard lock acquisitions
+    pub acquisitions: u64,
+    /// Number of acquisitions that had to wait for another holder
+    pub contended: u64,
+}
+
+/// Occupancy of a [`TransactionTracker`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TrackerStats {
+    /// Number of transactions tracked
+    pub transactions: usize,
+    /// Number of tracked transactions still being processed
+    pub in_progress: usize,
+    /// Number of distinct client addresses with tracked transactions
+    pub clients: usize,
+}
+
+impl TrackerLockStats {
+    /// Returns the fraction of acquisitions that had to wait, zero without any
+    pub fn contention_rate(&self) -> f64 {
+        if self.acquisitions == 0 {
+            0.0
+        } else {
+            self.contended as f64 / self.acquisitions as f64
+        }
+    }
+}
+
+/// Transactions of the clients whose address hashes to one shard
+type Shard = HashMap<(u32, SocketAddr), TransactionState>;
 
 /// Tracks RPC transactions to detect and handle retransmissions
 ///
@@ -25,7 +181,14 @@ use std::time::{Duration, SystemTime};
 /// and maintains transaction state for a configurable retention period.
 pub struct TransactionTracker {
     retention_period: Duration,
-    transactions: Mutex<HashMap<(u32, String), TransactionState>>,
+    /// Capacity of every shard
+    shard_capacity: usize,
+    policy: RetransmissionPolicy,
+    /// Selects the shard of a client address
+    hasher: RandomState,
+    shards: Box<[Mutex<Shard>]>,
+    acquisitions: AtomicU64,
+    contended: AtomicU64,
 }
 
 impl TransactionTracker {
@@ -35,7 +198,125 @@ impl TransactionTracker {
     /// for the given duration. This helps balance memory usage with the ability
     /// to detect retransmissions over time.
     pub fn new(retention_period: Duration) -> Self {
-        Self { retention_period, transactions: Mutex::new(HashMap::new()) }
+        Self::with_config(TransactionTrackerConfig { retention_period, ..Default::default() })
+    }
+
+    /// Creates a new transaction tracker with the given retention period and capacity
+    pub fn with_config(config: TransactionTrackerConfig) -> Self {
+        let shards = config.shards.max(1);
+        Self {
+            retention_period: config.retention_period,
+            shard_capacity: config.capacity.div_ceil(shards),
+            policy: config.policy,
+            hasher: RandomState::new(),
+            shards: (0..shards).map(|_| Mutex::default()).collect(),
+            acquisitions: AtomicU64::new(0),
+            contended: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns how often the shard locks were acquired and had to wait
+    pub fn lock_stats(&self) -> TrackerLockStats {
+        TrackerLockStats {
+            shards: self.shards.len(),
+            acquisitions: self.acquisitions.load(Ordering::Relaxed),
+            contended: self.contended.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns how many transactions and clients are tracked
+    ///
+    /// Expired transactions not purged yet are counted as well.
+    pub fn stats(&self) -> TrackerStats {
+        let mut stats = TrackerStats::default();
+        for index in 0..self.shards.len() {
+            let transactions = self.lock_index(index);
+            stats.transactions += transactions.len();
+            stats.in_progress += transactions
+                .values()
+                .filter(|state| matches!(state, TransactionState::InProgress))
+                .count();
+            // clients never span shards, so counting per shard is exact
+            stats.clients +=
+                transactions.keys().map(|(_, client)| client).collect::<HashSet<_>>().len();
+        }
+        stats
+    }
+
+    /// Returns the addresses of the clients with tracked transactions
+    pub fn clients(&self) -> Vec<SocketAddr> {
+        let mut clients = HashSet::new();
+        for index in 0..self.shards.len() {
+            let transactions = self.lock_index(index);
+            clients.extend(transactions.keys().map(|(_, client)| *client));
+        }
+        clients.into_iter().collect()
+    }
+
+    /// Forgets completed transactions older than the retention period in all
+    /// shards
+    ///
+    /// Shards are otherwise only cleaned up when a call arrives for them, so
+    /// transactions of clients that went away stay until then.
+    ///
+    /// # Returns
+    ///
+    /// * `usize` - The number of transactions forgotten
+    pub fn purge_expired(&self) -> usize {
+        let mut purged = 0;
+        for index in 0..self.shards.len() {
+            let mut transactions = self.lock_index(index);
+            let before = transactions.len();
+            housekeeping(&mut transactions, self.retention_period, self.shard_capacity);
+            purged += before - transactions.len();
+        }
+        purged
+    }
+
+    /// Forgets all transactions of a client, e.g. one known to have unmounted
+    ///
+    /// Transactions still in progress are forgotten as well, so a retransmission
+    /// of one of them arriving afterwards is processed again.
+    ///
+    /// # Returns
+    ///
+    /// * `usize` - The number of transactions forgotten
+    pub fn forget_client(&self, client_addr: SocketAddr) -> usize {
+        let mut transactions = self.lock_shard(client_addr);
+        let before = transactions.len();
+        transactions.retain(|(_, client), _| *client != client_addr);
+        before - transactions.len()
+    }
+
+    /// Locks the shard at `index`, counting the acquisition
+    fn lock_index(&self, index: usize) -> MutexGuard<'_, Shard> {
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+        match self.shards[index].try_lock() {
+            Ok(shard) => shard,
+            Err(TryLockError::WouldBlock) => {
+                self.contended.fetch_add(1, Ordering::Relaxed);
+                self.shards[index].lock().expect("unable to unlock transactions mutex")
+            }
+            Err(TryLockError::Poisoned(_)) => panic!("unable to unlock transactions mutex"),
+        }
+    }
+
+    /// Returns the index of the shard holding transactions of `client_addr`
+    fn shard_index(&self, client_addr: SocketAddr) -> usize {
+        (self.hasher.hash_one(client_addr) % self.shards.len() as u64) as usize
+    }
+
+    /// Locks the shard holding transactions of `client_addr`
+    fn lock_shard(&self, client_addr: SocketAddr) -> MutexGuard<'_, Shard> {
+        self.lock_index(self.shard_index(client_addr))
+    }
+
+    /// Returns whether calls of a procedure go through retransmission detection
+    ///
+    /// Callers skip [`TransactionTracker::is_retransmission`] and
+    /// [`TransactionTracker::mark_processed`] for procedures that are not tracked.
+    pub fn tracks(&self, program: u32, version: u32, procedure: u32) -> bool {
+        self.policy.covers(program, version, procedure)
     }
 
     /// Checks if a transaction is a retransmission
@@ -43,12 +324,11 @@ impl TransactionTracker {
     /// Identifies whether the transaction with given XID and client address
     /// has been seen before. If it's a new transaction, marks it as in-progress.
     /// Returns true for retransmissions, false for new transactions.
-    pub fn is_retransmission(&self, xid: u32, client_addr: &str) -> bool {
-        let key = (xid, client_addr.to_string());
-        let mut transactions =
-            self.transactions.lock().expect("unable to unlock transactions mutex");
-        housekeeping(&mut transactions, self.retention_period);
-        if let std::collections::hash_map::Entry::Vacant(e) = transactions.entry(key) {
+    pub fn is_retransmission(&self, xid: u32, client_addr: SocketAddr) -> bool {
+        let key = (xid, client_addr);
+        let mut transactions = self.lock_shard(client_addr);
+        housekeeping(&mut transactions, self.retention_period, self.shard_capacity);
+        if let Entry::Vacant(e) = transactions.entry(key) {
             e.insert(TransactionState::InProgress);
             false
         } else {
@@ -61,28 +341,151 @@ impl TransactionTracker {
     /// Updates the state of a transaction from in-progress to completed,
     /// recording the completion time for retention period calculations.
     /// Called after a transaction has been fully processed and responded to.
-    pub fn mark_processed(&self, xid: u32, client_addr: &str) {
-        let key = (xid, client_addr.to_string());
+    pub fn mark_processed(&self, xid: u32, client_addr: SocketAddr) {
+        let key = (xid, client_addr);
         let completion_time = SystemTime::now();
-        let mut transactions =
-            self.transactions.lock().expect("unable to unlock transactions mutex");
+        let mut transactions = self.lock_shard(client_addr);
         if let Some(tx) = transactions.get_mut(&key) {
             *tx = TransactionState::Completed(completion_time);
         }
     }
+
+    /// Writes the tracked transactions to `dest`
+    ///
+    /// Lets a supervising process persist the duplicate detection state across a
+    /// fast restart, so retransmissions of calls handled by the old process are
+    /// not executed again by the new one. Transactions still in progress are
+    /// written as completed now: their effects may already be visible, and
+    /// executing them twice is worse than dropping a retransmission.
+    ///
+    /// The format is XDR: a magic number, a version, the entry count, and for each
+    /// entry the XID, client address as text, including the scope ID of IPv6
+    /// addresses, and completion time since the Unix epoch.
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<usize>` - The number of transactions written
+    pub fn dump(&self, dest: &mut impl Write) -> io::Result<usize> {
+        let now = SystemTime::now();
+        let mut entries = Vec::new();
+        for index in 0..self.shards.len() {
+            let transactions = self.lock_index(index);
+            entries.extend(transactions.iter().map(|((xid, client_addr), state)| {
+                let completed = match state {
+                    TransactionState::InProgress => now,
+                    TransactionState::Completed(time) => *time,
+                };
+                (*xid, *client_addr, completed)
+            }));
+        }
+        DUMP_MAGIC.serialize(dest)?;
+        DUMP_VERSION.serialize(dest)?;
+        (entries.len() as u32).serialize(dest)?;
+        for (xid, client_addr, completed) in &entries {
+            let since_epoch = completed.duration_since(UNIX_EPOCH).unwrap_or_default();
+            xid.serialize(dest)?;
+            client_addr.to_string().as_str().serialize(dest)?;
+            since_epoch.as_secs().serialize(dest)?;
+            since_epoch.subsec_nanos().serialize(dest)?;
+        }
+        Ok(entries.len())
+    }
+
+    /// Adds transactions written by [`TransactionTracker::dump`]
+    ///
+    /// Entries older than the retention period are skipped, and entries already
+    /// tracked are kept as they are.
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<usize>` - The number of transactions added, or an
+    ///   `InvalidData` error for input that is not a dump of a supported version
+    pub fn load(&self, src: &mut impl Read) -> io::Result<usize> {
+        if deserialize::<u32>(src)? != DUMP_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a transaction tracker dump",
+            ));
+        }
+        if deserialize::<u32>(src)? != DUMP_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unsupported transaction tracker dump version",
+            ));
+        }
+        let count = deserialize::<u32>(src)?;
+        let cutoff = SystemTime::now().checked_sub(self.retention_period).unwrap_or(UNIX_EPOCH);
+        let mut loaded = vec![Vec::new(); self.shards.len()];
+        for _ in 0..count {
+            let xid = deserialize::<u32>(src)?;
+            let client_addr = deserialize::<String>(src)?.parse::<SocketAddr>().map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidData, "invalid client address in dump")
+            })?;
+            let secs = deserialize::<u64>(src)?;
+            let nanos = deserialize::<u32>(src)?;
+            let completed = Duration::from_secs(secs)
+                .checked_add(Duration::from_nanos(nanos.into()))
+                .and_then(|since_epoch| UNIX_EPOCH.checked_add(since_epoch))
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "invalid completion time in dump")
+                })?;
+            if completed >= cutoff {
+                let index = self.shard_index(client_addr);
+                loaded[index].push(((xid, client_addr), completed));
+            }
+        }
+
+        let mut added = 0;
+        for (index, entries) in loaded.into_iter().enumerate() {
+            let mut transactions = self.lock_index(index);
+            for (key, completed) in entries {
+                if let Entry::Vacant(e) = transactions.entry(key) {
+                    e.insert(TransactionState::Completed(completed));
+                    added += 1;
+                }
+            }
+            housekeeping(&mut transactions, self.retention_period, self.shard_capacity);
+        }
+        Ok(added)
+    }
 }
 
 /// Removes expired transactions from the tracking map
 ///
-/// Cleans up completed transactions that have exceeded the maximum retention age.
+/// Cleans up completed transactions that have exceeded the maximum retention age,
+/// and the oldest completed ones beyond `capacity`.
 /// Keeps in-progress transactions regardless of age to prevent processing duplicates.
 /// Called during transaction checks to maintain memory efficiency.
-fn housekeeping(transactions: &mut HashMap<(u32, String), TransactionState>, max_age: Duration) {
+fn housekeeping(transactions: &mut Shard, max_age: Duration, capacity: usize) {
     let mut cutoff = SystemTime::now() - max_age;
     transactions.retain(|_, v| match v {
         TransactionState::InProgress => true,
         TransactionState::Completed(completion_time) => completion_time >= &mut cutoff,
     });
+    if transactions.len() <= capacity {
+        return;
+    }
+    let mut completed: Vec<SystemTime> = transactions
+        .values()
+        .filter_map(|v| match v {
+            TransactionState::InProgress => None,
+            TransactionState::Completed(time) => Some(*time),
+        })
+        .collect();
+    let excess = (transactions.len() - capacity).min(completed.len());
+    if excess == 0 {
+        return;
+    }
+    let (_, newest_evicted, _) = completed.select_nth_unstable(excess - 1);
+    let newest_evicted = *newest_evicted;
+    let mut remaining = excess;
+    transactions.retain(|_, v| match v {
+        TransactionState::Completed(time) if remaining > 0 && *time <= newest_evicted => {
+            remaining -= 1;
+            false
+        }
+        _ => true,
+    });
 }
 
 /// Represents the current state of an RPC transaction
@@ -94,3 +497,135 @@ enum TransactionState {
     InProgress,
     Completed(SystemTime),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(text: &str) -> SocketAddr {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn dump_and_load_round_trip() {
+        let tracker = TransactionTracker::new(Duration::from_secs(60));
+        assert!(!tracker.is_retransmission(1, addr("10.0.0.1:700")));
+        tracker.mark_processed(1, addr("10.0.0.1:700"));
+        assert!(!tracker.is_retransmission(2, addr("10.0.0.2:701")));
+
+        let mut dump = Vec::new();
+        assert_eq!(tracker.dump(&mut dump).unwrap(), 2);
+
+        let restarted = TransactionTracker::new(Duration::from_secs(60));
+        assert_eq!(restarted.load(&mut dump.as_slice()).unwrap(), 2);
+        assert!(restarted.is_retransmission(1, addr("10.0.0.1:700")));
+        assert!(restarted.is_retransmission(2, addr("10.0.0.2:701")));
+        assert!(!restarted.is_retransmission(1, addr("10.0.0.2:701")));
+
+        assert!(restarted.load(&mut &b"garbage!"[..]).is_err());
+
+        let mut overflowing = Vec::new();
+        for value in [DUMP_MAGIC, DUMP_VERSION, 1, 3] {
+            value.serialize(&mut overflowing).unwrap();
+        }
+        "10.0.0.3:702".serialize(&mut overflowing).unwrap();
+        u64::MAX.serialize(&mut overflowing).unwrap();
+        u32::MAX.serialize(&mut overflowing).unwrap();
+        let err = restarted.load(&mut overflowing.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn link_local_clients_differ_by_scope() {
+        let tracker = TransactionTracker::new(Duration::from_secs(60));
+        let (eth0, eth1) = (addr("[fe80::1%2]:700"), addr("[fe80::1%3]:700"));
+        assert!(!tracker.is_retransmission(1, eth0));
+        tracker.mark_processed(1, eth0);
+        assert!(!tracker.is_retransmission(1, eth1));
+        assert_eq!(tracker.stats().clients, 2);
+
+        let mut dump = Vec::new();
+        tracker.dump(&mut dump).unwrap();
+        let restarted = TransactionTracker::new(Duration::from_secs(60));
+        assert_eq!(restarted.load(&mut dump.as_slice()).unwrap(), 2);
+        assert_eq!(restarted.forget_client(eth1), 1);
+        assert!(restarted.is_retransmission(1, eth0));
+        assert!(!restarted.is_retransmission(1, eth1));
+    }
+
+    #[test]
+    fn tracks_only_non_idempotent_procedures() {
+        let tracker = TransactionTracker::new(Duration::from_secs(60));
+        let getattr = nfs3::NFSProgram::NFSPROC3_GETATTR as u32;
+        let write = nfs3::NFSProgram::NFSPROC3_WRITE as u32;
+        assert!(!tracker.tracks(nfs3::PROGRAM, nfs3::VERSION, getattr));
+        assert!(tracker.tracks(nfs3::PROGRAM, nfs3::VERSION, write));
+        assert!(tracker.tracks(mount::PROGRAM, mount::VERSION, 1));
+        assert!(tracker.tracks(12345, 1, 0));
+    }
+
+    #[test]
+    fn counts_lock_acquisitions_across_shards() {
+        let tracker = TransactionTracker::new(Duration::from_secs(60));
+        std::thread::scope(|scope| {
+            for client in 0..8 {
+                let tracker = &tracker;
+                scope.spawn(move || {
+                    let addr = addr(&format!("10.0.0.{client}:700"));
+                    for xid in 0..100 {
+                        assert!(!tracker.is_retransmission(xid, addr));
+                        tracker.mark_processed(xid, addr);
+                        assert!(tracker.is_retransmission(xid, addr));
+                    }
+                });
+            }
+        });
+        let stats = tracker.lock_stats();
+        assert_eq!(stats.shards, 16);
+        assert_eq!(stats.acquisitions, 8 * 100 * 3);
+        assert!(stats.contended <= stats.acquisitions);
+
+        let mut dump = Vec::new();
+        assert_eq!(tracker.dump(&mut dump).unwrap(), 800);
+    }
+
+    #[test]
+    fn capacity_evicts_oldest_completed() {
+        let tracker = TransactionTracker::with_config(TransactionTrackerConfig {
+            retention_period: Duration::from_secs(60),
+            capacity: 2,
+            policy: RetransmissionPolicy::All,
+            shards: 1,
+        });
+        for xid in 0..3 {
+            assert!(!tracker.is_retransmission(xid, addr("10.0.0.9:700")));
+            tracker.mark_processed(xid, addr("10.0.0.9:700"));
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        // the new transaction pushes the map over capacity, evicting xid 0
+        assert!(!tracker.is_retransmission(3, addr("10.0.0.9:700")));
+        assert!(!tracker.is_retransmission(0, addr("10.0.0.9:700")));
+    }
+
+    #[test]
+    fn purges_stale_state_on_demand() {
+        let tracker = TransactionTracker::new(Duration::from_millis(20));
+        for client in [addr("10.0.0.1:700"), addr("10.0.0.2:700")] {
+            assert!(!tracker.is_retransmission(1, client));
+            tracker.mark_processed(1, client);
+        }
+        assert!(!tracker.is_retransmission(2, addr("10.0.0.1:700")));
+        let stats = tracker.stats();
+        assert_eq!(stats, TrackerStats { transactions: 3, in_progress: 1, clients: 2 });
+        let mut clients = tracker.clients();
+        clients.sort();
+        assert_eq!(clients, [addr("10.0.0.1:700"), addr("10.0.0.2:700")]);
+
+        assert_eq!(tracker.forget_client(addr("10.0.0.2:700")), 1);
+        assert_eq!(tracker.forget_client(addr("10.0.0.2:700")), 0);
+        std::thread::sleep(Duration::from_millis(30));
+        // only the completed transaction expires
+        assert_eq!(tracker.purge_expired(), 1);
+        assert_eq!(tracker.stats(), TrackerStats { transactions: 1, in_progress: 1, clients: 1 });
+    }
+}
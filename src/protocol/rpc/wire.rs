@@ -19,15 +19,15 @@
 
 use std::io::Cursor;
 use std::io::{Read, Write};
+use std::time::Instant;
 
-use anyhow::anyhow;
-use tokio::io::AsyncReadExt;
-use tokio::io::AsyncWriteExt;
 use tokio::io::DuplexStream;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::sync::mpsc;
 use tracing::{debug, error, trace, warn};
 
 use crate::protocol::rpc::command_queue::{CommandQueue, CommandResult, ResponseBuffer};
+use crate::protocol::rpc::{fast_path, short_auth};
 use crate::protocol::xdr::{self, deserialize, mount, nfs3, portmap, Serialize};
 use crate::protocol::{nfs, rpc};
 
@@ -44,18 +44,56 @@ const NFS_ID_MAP_PROGRAM: u32 = 100270;
 const NFS_LOCALIO_PROGRAM: u32 = 400122;
 /// RPC program number for NFS Metadata
 const NFS_METADATA_PROGRAM: u32 = 200024;
-/// Initial size of RPC response buffer
+/// Least size of RPC response buffers, used until replies of a procedure were
+/// seen
 const DEFAULT_RESPONSE_BUFFER_CAPACITY: usize = 8192;
 
+/// Room the headers around the data of `READ` and `WRITE` take in a record at
+/// most: an RPC header with the largest `AUTH_UNIX` credentials, a file handle
+/// and the attributes of the file
+pub const RECORD_OVERHEAD: usize = 1024;
+/// Longest record accepted by default, holding transfers of 1 MiB
+pub const DEFAULT_MAX_RECORD_LENGTH: usize = 1024 * 1024 + RECORD_OVERHEAD;
+
+/// Length of an encoded file handle of the largest size
+const HANDLE_HEAD_LEN: usize = 4 + nfs3::NFS3_FHSIZE as usize;
+
+/// Reader that keeps a copy of the first bytes read through it
+struct ArgsHead<'a, R> {
+    /// Reader of the call arguments
+    inner: &'a mut R,
+    /// Bytes read so far, up to `limit`
+    head: Vec<u8>,
+    /// Number of bytes to keep
+    limit: usize,
+}
+
+impl<'a, R: Read> ArgsHead<'a, R> {
+    fn new(inner: &'a mut R, limit: usize) -> Self {
+        Self { inner, head: Vec::with_capacity(limit), limit }
+    }
+}
+
+impl<R: Read> Read for ArgsHead<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        let keep = n.min(self.limit - self.head.len());
+        self.head.extend_from_slice(&buf[..keep]);
+        Ok(n)
+    }
+}
+
 /// Processes a single RPC message
 ///
 /// This function forms the core of the RPC message dispatcher. It:
 /// 1. Deserializes the incoming RPC message using XDR format
 /// 2. Validates the RPC version number (must be version 2)
-/// 3. Extracts authentication information if provided
+/// 3. Extracts authentication information if provided and applies the auth policy
 /// 4. Checks for retransmissions to ensure idempotent operation
-/// 5. Routes the call to the appropriate protocol handler (NFS, MOUNT, PORTMAP)
-/// 6. Tracks transaction completion state
+/// 5. Routes the call to the appropriate protocol handler (NFS, MOUNT, PORTMAP),
+///    through the dispatch hooks of the context if any are installed
+/// 6. Tracks transaction completion state and reports calls slower than the
+///    threshold of [`rpc::ServerStats::set_slow_threshold`]
 ///
 /// This implementation follows RFC 5531 (previously RFC 1057) section on Authentication and
 /// Record Marking Standard for proper RPC message handling.
@@ -65,68 +103,187 @@ pub async fn handle_rpc(
     input: &mut impl Read,
     output: &mut impl Write,
     mut context: rpc::Context,
-) -> Result<bool, anyhow::Error> {
+) -> Result<bool, rpc::ServerError> {
     let recv = deserialize::<xdr::rpc::rpc_msg>(input)?;
     let xid = recv.xid;
     if let xdr::rpc::rpc_body::CALL(call) = recv.body {
-        if let xdr::rpc::auth_flavor::AUTH_UNIX = call.cred.flavor {
-            context.auth = deserialize(&mut Cursor::new(&call.cred.body))?;
+        let mut flavor = call.cred.flavor;
+        match (flavor, &context.short_auth) {
+            (xdr::rpc::auth_flavor::AUTH_UNIX, _) => {
+                context.auth = deserialize(&mut Cursor::new(&call.cred.body))?;
+            }
+            (xdr::rpc::auth_flavor::AUTH_SHORT, Some(short_auth)) => {
+                let Some(auth) = short_auth.resolve(&call.cred.body) else {
+                    debug!("Unknown AUTH_SHORT handle, xid: {}", xid);
+                    xdr::rpc::auth_error_reply_message(xid, xdr::rpc::auth_stat::AUTH_REJECTEDCRED)
+                        .serialize(output)?;
+                    return Ok(true);
+                };
+                context.auth = auth;
+                // the handle stands for the credentials it was issued for
+                flavor = xdr::rpc::auth_flavor::AUTH_UNIX;
+            }
+            (xdr::rpc::auth_flavor::RPCSEC_GSS, _) => {
+                // no security contexts are established, so none can be valid
+                debug!("Refusing RPCSEC_GSS credentials, xid: {}", xid);
+                xdr::rpc::auth_error_reply_message(xid, xdr::rpc::auth_stat::AUTH_BADCRED)
+                    .serialize(output)?;
+                return Ok(true);
+            }
+            _ => {}
         }
+        context.squash.apply(&mut context.auth);
         if call.rpcvers != 2 {
             warn!("Invalid RPC version {} != 2", call.rpcvers);
             xdr::rpc::rpc_vers_mismatch(xid).serialize(output)?;
             return Ok(true);
         }
 
-        if context.transaction_tracker.is_retransmission(xid, &context.client_addr) {
+        if !context.auth_policy.allows(call.prog, call.proc, flavor) {
+            warn!(
+                "Denying call {}.{} with {:?} credentials from {}",
+                call.prog, call.proc, flavor, context.client_addr
+            );
+            xdr::rpc::auth_error_reply_message(xid, xdr::rpc::auth_stat::AUTH_TOOWEAK)
+                .serialize(output)?;
+            return Ok(true);
+        }
+
+        let tracked = context.transaction_tracker.tracks(call.prog, call.vers, call.proc);
+        if tracked && context.transaction_tracker.is_retransmission(xid, context.client_addr) {
             // This is a retransmission
             // Drop the message and return
             debug!(
                 "Retransmission detected, xid: {}, client_addr: {}, call: {:?}",
                 xid, context.client_addr, call
             );
+            context.stats.duplicate_cache.hit();
             return Ok(false);
         }
+        if tracked {
+            context.stats.duplicate_cache.miss();
+        }
 
-        let res = {
-            match call.prog {
-                nfs3::PROGRAM => match call.vers {
-                    nfs3::VERSION => nfs::v3::handle_nfs(xid, call, input, output, &context).await,
-                    _ => {
-                        error!("NFSv4 not implemented");
-                        Err(anyhow!("NFSv4 protocol error"))
-                    }
-                },
-                portmap::PROGRAM => {
-                    nfs::portmap::handle_portmap(xid, &call, input, output, &mut context)
-                }
-                mount::PROGRAM => {
-                    nfs::mount::handle_mount(xid, call, input, output, &context).await
-                }
-                NFS_ACL_PROGRAM | NFS_ID_MAP_PROGRAM | NFS_METADATA_PROGRAM => {
-                    trace!("ignoring NFS_ACL packet");
-                    xdr::rpc::prog_unavail_reply_message(xid).serialize(output)?;
-                    Ok(())
+        let (prog, vers, proc) = (call.prog, call.vers, call.proc);
+        // keep the start of NFS arguments, which holds the file handle, for
+        // reporting slow calls
+        let watched = prog == nfs3::PROGRAM && context.stats.slow_threshold().is_some();
+        let input = &mut ArgsHead::new(input, if watched { HANDLE_HEAD_LEN } else { 0 });
+        let started = Instant::now();
+        let request = context.request_context(xid);
+        let res = request
+            .scope(async {
+                if context.hooks.is_empty() {
+                    dispatch(xid, call, input, output, &mut context).await.map(|_| true)
+                } else {
+                    dispatch_hooked(xid, call, input, output, &mut context).await
                 }
-                NFS_LOCALIO_PROGRAM => {
-                    trace!("Ignoring NFS_LOCALIO packet");
-                    xdr::rpc::prog_unavail_reply_message(xid).serialize(output)?;
-                    Ok(())
-                }
-                unknown_number => {
-                    warn!("Unknown RPC Program number {} != {}", unknown_number, nfs3::PROGRAM);
-                    xdr::rpc::prog_unavail_reply_message(xid).serialize(output)?;
-                    Ok(())
-                }
-            }
+            })
+            .await;
+        let elapsed = started.elapsed();
+        context.stats.export(&context.export_name).record_call(prog, vers, proc);
+        if context.stats.record_call(prog, vers, proc, elapsed, res.is_ok()) {
+            let fileid = deserialize::<nfs3::nfs_fh3>(&mut Cursor::new(&input.head))
+                .ok()
+                .and_then(|fh| context.fh_to_id(&fh).ok());
+            rpc::stats::warn_slow_call(prog, vers, proc, fileid, elapsed, context.client_addr);
+        }
+        if tracked {
+            context.transaction_tracker.mark_processed(xid, context.client_addr);
         }
-        .map(|_| true);
-        context.transaction_tracker.mark_processed(xid, &context.client_addr);
         res
     } else {
         error!("Unexpectedly received a Reply instead of a Call");
-        Err(anyhow!("Bad RPC Call format"))
+        Err(rpc::ServerError::Protocol("Bad RPC Call format".into()))
+    }
+}
+
+/// Routes a call to the handler of its program
+async fn dispatch(
+    xid: u32,
+    call: xdr::rpc::call_body,
+    input: &mut impl Read,
+    output: &mut impl Write,
+    context: &mut rpc::Context,
+) -> Result<(), rpc::ServerError> {
+    if context.portmap_only && call.prog != portmap::PROGRAM {
+        debug!("Refusing program {} on the portmapper port, xid: {}", call.prog, xid);
+        xdr::rpc::prog_unavail_reply_message(xid).serialize(output)?;
+        return Ok(());
+    }
+    match call.prog {
+        nfs3::PROGRAM => match call.vers {
+            nfs3::VERSION => nfs::v3::handle_nfs(xid, call, input, output, context).await,
+            _ => {
+                error!("NFSv4 not implemented");
+                Err(rpc::ServerError::Protocol("NFSv4 protocol error".into()))
+            }
+        },
+        portmap::PROGRAM => nfs::portmap::handle_portmap(xid, &call, input, output, context),
+        mount::PROGRAM => nfs::mount::handle_mount(xid, call, input, output, context).await,
+        rpc::COMPRESSION_PROGRAM => rpc::handle_compression(xid, &call, input, output, context),
+        NFS_ACL_PROGRAM | NFS_ID_MAP_PROGRAM | NFS_METADATA_PROGRAM => {
+            trace!("ignoring NFS_ACL packet");
+            xdr::rpc::prog_unavail_reply_message(xid).serialize(output)?;
+            Ok(())
+        }
+        NFS_LOCALIO_PROGRAM => {
+            trace!("Ignoring NFS_LOCALIO packet");
+            xdr::rpc::prog_unavail_reply_message(xid).serialize(output)?;
+            Ok(())
+        }
+        unknown_number => {
+            warn!("Unknown RPC Program number {} != {}", unknown_number, nfs3::PROGRAM);
+            xdr::rpc::prog_unavail_reply_message(xid).serialize(output)?;
+            Ok(())
+        }
+    }
+}
+
+/// Routes a call through the dispatch hooks of the context
+///
+/// # Returns
+///
+/// * `Result<bool, rpc::ServerError>` - Whether a reply was written
+async fn dispatch_hooked(
+    xid: u32,
+    call: xdr::rpc::call_body,
+    input: &mut impl Read,
+    output: &mut impl Write,
+    context: &mut rpc::Context,
+) -> Result<bool, rpc::ServerError> {
+    let info = rpc::CallInfo { xid, program: call.prog, version: call.vers, procedure: call.proc };
+    let mut args = Vec::new();
+    input.read_to_end(&mut args)?;
+    let hooks = context.hooks.clone();
+    match hooks.before(&info, context, &mut args) {
+        rpc::HookDecision::Continue => {}
+        rpc::HookDecision::Fail(stat) => {
+            debug!("Hook failed call {:?} with {:?}", info, stat);
+            if (info.program, info.version) == (nfs3::PROGRAM, nfs3::VERSION) {
+                nfs::v3::serialize_failure(xid, info.procedure, stat, output)?;
+            } else {
+                xdr::rpc::auth_error_reply_message(xid, xdr::rpc::auth_stat::AUTH_TOOWEAK)
+                    .serialize(output)?;
+            }
+            return Ok(true);
+        }
+        rpc::HookDecision::Reply(reply) => {
+            output.write_all(&reply)?;
+            return Ok(true);
+        }
+        rpc::HookDecision::Drop => {
+            debug!("Hook dropped call {:?}", info);
+            return Ok(false);
+        }
     }
+    // after hooks see the complete reply
+    context.reply_stream = None;
+    let mut reply = Vec::new();
+    dispatch(xid, call, &mut Cursor::new(args), &mut reply, context).await?;
+    hooks.after(&info, context, &mut reply);
+    output.write_all(&reply)?;
+    Ok(true)
 }
 
 /// Reads a single record-marked fragment from a stream
@@ -143,24 +300,48 @@ pub async fn handle_rpc(
 ///
 /// Returns true if this was the last fragment in the RPC record, false otherwise.
 /// This allows for reassembly of multi-fragment RPC messages.
-async fn read_fragment(
-    socket: &mut DuplexStream,
+pub async fn read_fragment(
+    socket: &mut (impl AsyncRead + Unpin),
+    append_to: &mut Vec<u8>,
+) -> Result<bool, rpc::ServerError> {
+    read_fragment_capped(socket, append_to, usize::MAX).await
+}
+
+/// Reads a single record-marked fragment like [`read_fragment`], growing
+/// `append_to` to at most `max_len` bytes
+///
+/// The data of the fragment beyond `max_len` is read from the stream and
+/// discarded, so the next fragment can still be read.
+pub async fn read_fragment_capped(
+    socket: &mut (impl AsyncRead + Unpin),
     append_to: &mut Vec<u8>,
-) -> Result<bool, anyhow::Error> {
+    max_len: usize,
+) -> Result<bool, rpc::ServerError> {
     let mut header_buf = [0_u8; 4];
-    socket.read_exact(&mut header_buf).await?;
+    socket.read_exact(&mut header_buf).await.map_err(rpc::ServerError::Io)?;
     let fragment_header = u32::from_be_bytes(header_buf);
     let is_last = (fragment_header & (1 << 31)) > 0;
     let length = (fragment_header & ((1 << 31) - 1)) as usize;
     trace!("Reading fragment length:{}, last:{}", length, is_last);
     let start_offset = append_to.len();
-    append_to.resize(append_to.len() + length, 0);
-    socket.read_exact(&mut append_to[start_offset..]).await?;
+    let kept = length.min(max_len.saturating_sub(start_offset));
+    append_to.resize(start_offset + kept, 0);
+    socket.read_exact(&mut append_to[start_offset..]).await.map_err(rpc::ServerError::Io)?;
+    if kept < length {
+        let excess = (length - kept) as u64;
+        let skipped = tokio::io::copy(&mut socket.take(excess), &mut tokio::io::sink())
+            .await
+            .map_err(rpc::ServerError::Io)?;
+        if skipped < excess {
+            let eof = std::io::Error::from(std::io::ErrorKind::UnexpectedEof);
+            return Err(rpc::ServerError::Io(eof));
+        }
+    }
     trace!("Finishing Reading fragment length:{}, last:{}", length, is_last);
     Ok(is_last)
 }
 
-/// Writes data as record-marked fragments to a TCP stream
+/// Writes data as record-marked fragments to a stream
 ///
 /// Implements the RFC 5531 (previously RFC 1057 section 10) Record Marking Standard for TCP transport.
 /// This standard enables RPC to utilize TCP as a transport while maintaining proper
@@ -176,9 +357,9 @@ async fn read_fragment(
 /// This ensures reliable transmission of RPC messages over TCP with proper
 /// message framing and enables receivers to allocate appropriate buffer space.
 pub async fn write_fragment(
-    socket: &mut tokio::net::TcpStream,
+    socket: &mut (impl AsyncWrite + Unpin),
     buf: &[u8],
-) -> Result<(), anyhow::Error> {
+) -> Result<(), rpc::ServerError> {
     // Maximum fragment size is 2^31 - 1 bytes
     const MAX_FRAGMENT_SIZE: usize = (1 << 31) - 1;
 
@@ -197,10 +378,13 @@ pub async fn write_fragment(
             if is_last { fragment_size as u32 + (1 << 31) } else { fragment_size as u32 };
 
         let header_buf = u32::to_be_bytes(fragment_header);
-        socket.write_all(&header_buf).await?;
+        socket.write_all(&header_buf).await.map_err(rpc::ServerError::Io)?;
 
         trace!("Writing fragment length:{}, last:{}", fragment_size, is_last);
-        socket.write_all(&buf[offset..offset + fragment_size]).await?;
+        socket
+            .write_all(&buf[offset..offset + fragment_size])
+            .await
+            .map_err(rpc::ServerError::Io)?;
 
         offset += fragment_size;
     }
@@ -208,7 +392,7 @@ pub async fn write_fragment(
     Ok(())
 }
 
-pub type SocketMessageType = Result<Vec<u8>, anyhow::Error>;
+pub type SocketMessageType = Result<rpc::Reply, rpc::ServerError>;
 
 /// Handles RPC message processing over a TCP connection
 ///
@@ -255,7 +439,7 @@ impl SocketMessageHandler {
             while let Some(result) = result_receiver.recv().await {
                 match result {
                     Ok(Some(response_buffer)) if response_buffer.has_content() => {
-                        let _ = msgsend.send(Ok(response_buffer.into_inner()));
+                        let _ = msgsend.send(Ok(response_buffer.into_reply()));
                     }
                     Ok(None) => {
                         // No response needed, so nothing to send
@@ -290,9 +474,13 @@ impl SocketMessageHandler {
     /// the current message buffer. If the fragment is the last one in the record,
     /// submits a command to the queue for processing in order.
     /// Should be called in a loop to continuously process incoming messages.
-    pub async fn read(&mut self) -> Result<(), anyhow::Error> {
+    pub async fn read(&mut self) -> Result<(), rpc::ServerError> {
+        // keep a byte beyond the limit, so process_message recognizes
+        // oversized records
+        let max_len = self.context.max_record_length.saturating_add(1);
         let is_last =
-            read_fragment(&mut self.socket_receive_channel, &mut self.cur_fragment).await?;
+            read_fragment_capped(&mut self.socket_receive_channel, &mut self.cur_fragment, max_len)
+                .await?;
         if is_last {
             // Take buffer and create new one for next fragment
             let fragment_data = std::mem::take(&mut self.cur_fragment);
@@ -301,13 +489,141 @@ impl SocketMessageHandler {
             // Submit command to queue for ordered processing
             if let Err(e) = self.command_queue.submit_command(fragment_data, context) {
                 error!("Failed to submit command to queue: {:?}", e);
-                return Err(anyhow::anyhow!("Command queue error: {}", e));
+                return Err(e);
             }
         }
         Ok(())
     }
 }
 
+/// Processes a complete RPC record and writes the reply
+///
+/// Tries the fast paths for hot procedures first and falls back to the
+/// generic [`handle_rpc`] dispatcher. Replies to calls with `AUTH_UNIX`
+/// credentials carry an `AUTH_SHORT` verifier if the context has a
+/// [`rpc::ShortAuth`]. The call first waits for a slot of its class if the
+/// context has [`rpc::PriorityLimits`], then for its turn if it has a
+/// [`rpc::Lane`]. Records longer than
+/// [`rpc::Context::max_record_length`] are answered with `GARBAGE_ARGS`
+/// without being decoded. Every record, including ones answered on a fast
+/// path, keeps the mounts of the client alive in
+/// [`rpc::Context::mount_table`].
+///
+/// # Arguments
+///
+/// * `data` - Complete RPC record, without record marking
+/// * `output` - Buffer the reply is appended to
+/// * `context` - RPC processing context
+///
+/// # Returns
+///
+/// `Ok(true)` if a reply was written,
+/// `Ok(false)` if no reply is needed (e.g. retransmission)
+/// `Err` if a processing error occurred
+pub async fn process_message(
+    data: &[u8],
+    output: &mut Vec<u8>,
+    context: rpc::Context,
+) -> Result<bool, rpc::ServerError> {
+    // before the fast path, so clients only revalidating attributes stay alive
+    context.mount_table.touch(context.client_addr.ip(), context.clock.now());
+    if data.len() > context.max_record_length {
+        let Some(xid) = fast_path::be_u32(data, 0) else {
+            return Err(rpc::ServerError::Protocol("record without a header".into()));
+        };
+        warn!(
+            "Refusing record of more than {} bytes, xid: {}, client_addr: {}",
+            context.max_record_length, xid, context.client_addr
+        );
+        xdr::rpc::garbage_args_reply_message(xid).serialize(output)?;
+        return Ok(true);
+    }
+    let handle = context
+        .short_auth
+        .as_ref()
+        .and_then(|table| table.issue(short_auth::unix_credentials(data)?, &*context.entropy));
+    let _class_permit = match (&context.priorities, fast_path::be_u32(data, 12)) {
+        (Some(priorities), Some(program)) => {
+            let procedure = fast_path::be_u32(data, 20).unwrap_or(0);
+            priorities.acquire(program, procedure).await
+        }
+        _ => None,
+    };
+    let _permit = match &context.lane {
+        Some(lane) => Some(lane.acquire().await),
+        None => None,
+    };
+    let start = output.len();
+    let fast = if context.portmap_only {
+        None
+    } else {
+        fast_path::try_fast_path(data, output, &context).await
+    };
+    let res = match fast {
+        Some(result) => result,
+        None => handle_rpc(&mut Cursor::new(data), output, context).await,
+    };
+    if let (Ok(true), Some(handle)) = (&res, handle) {
+        short_auth::attach_verifier(output, start, handle);
+    }
+    res
+}
+
+/// Processes an RPC call received as a datagram and writes the reply
+///
+/// Retransmissions of calls the socket's `cache` covers are answered with the
+/// cached reply instead of being dropped, as datagram clients expect a reply to
+/// every transmission. Other calls are handed to [`process_message`].
+///
+/// # Arguments
+///
+/// * `data` - Datagram payload, a complete RPC record
+/// * `output` - Buffer the reply is appended to
+/// * `context` - RPC processing context, with the sender as client address
+/// * `cache` - Reply cache of the receiving socket
+///
+/// # Returns
+///
+/// The same result as [`process_message`]
+pub async fn process_datagram(
+    data: &[u8],
+    output: &mut Vec<u8>,
+    mut context: rpc::Context,
+    cache: &rpc::ReplyCache,
+) -> Result<bool, rpc::ServerError> {
+    // a datagram carries the whole reply
+    context.reply_stream = None;
+    let header = (0..6).map(|i| fast_path::be_u32(data, i * 4)).collect::<Option<Vec<u32>>>();
+    let Some(&[xid, 0, _, prog, vers, proc]) = header.as_deref() else {
+        return process_message(data, output, context).await;
+    };
+    if !cache.caches(prog, vers, proc) {
+        return process_message(data, output, context).await;
+    }
+    match cache.begin(xid, context.client_addr, data) {
+        rpc::CachedReply::Replay(reply) => {
+            debug!("Replaying cached reply, xid: {}, client_addr: {}", xid, context.client_addr);
+            context.stats.duplicate_cache.hit();
+            output.extend_from_slice(&reply);
+            return Ok(true);
+        }
+        rpc::CachedReply::InProgress => {
+            debug!("Call in progress, xid: {}, client_addr: {}", xid, context.client_addr);
+            context.stats.duplicate_cache.hit();
+            return Ok(false);
+        }
+        rpc::CachedReply::New => {}
+    }
+    let client_addr = context.client_addr;
+    let start = output.len();
+    let res = process_message(data, output, context).await;
+    match res {
+        Ok(true) => cache.complete(xid, client_addr, &output[start..]),
+        _ => cache.abandon(xid, client_addr),
+    }
+    res
+}
+
 /// Standard async RPC processing function that can be used with `CommandQueue`
 ///
 /// Processes an RPC command by:
@@ -326,26 +642,30 @@ impl SocketMessageHandler {
 /// `Ok(true)` if response needs to be sent
 /// `Ok(false)` if no response needed (e.g. retransmission)
 /// `Err` if processing error occurred
-pub fn process_rpc_command<'a>(
-    data: &[u8],
-    output: &'a mut ResponseBuffer,
+pub fn process_rpc_command(
+    data: Vec<u8>,
+    output: &mut ResponseBuffer,
     context: rpc::Context,
-) -> futures::future::BoxFuture<'a, anyhow::Result<bool>> {
-    // Clone data to own it in closure
-    let data_clone = data.to_vec();
-
-    Box::pin(async move {
-        // Create cursor for reading data
-        let mut input_cursor = Cursor::new(data_clone);
+) -> futures::future::BoxFuture<'_, Result<bool, rpc::ServerError>> {
+    Box::pin(async move { process_message(&data, output.get_mut_buffer(), context).await })
+}
 
-        // Get internal buffer for writing
-        let output_buffer = output.get_mut_buffer();
-        let mut output_cursor = Cursor::new(output_buffer);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        // Call RPC handler
-        let result = handle_rpc(&mut input_cursor, &mut output_cursor, context).await?;
+    #[tokio::test]
+    async fn capped_fragments_skip_excess_data() {
+        let stream = [&[0, 0, 0, 4][..], &[1, 2, 3, 4], &[0x80, 0, 0, 2], &[5, 6]].concat();
+        let mut stream = &stream[..];
+        let mut record = Vec::new();
+        assert!(!read_fragment_capped(&mut stream, &mut record, 3).await.unwrap());
+        assert!(read_fragment_capped(&mut stream, &mut record, 3).await.unwrap());
+        assert_eq!(record, [1, 2, 3]);
+        assert!(stream.is_empty());
 
-        // If response was generated, return true
-        Ok(result)
-    })
+        let mut truncated = &[0x80, 0, 0, 8, 1, 2][..];
+        let err = read_fragment_capped(&mut truncated, &mut Vec::new(), 1).await.unwrap_err();
+        assert!(err.is_fatal());
+    }
 }
@@ -141,6 +141,22 @@ impl Deserialize for bool {
     }
 }
 
+/// XDR `void` type serialization implementation, which writes nothing.
+///
+/// Used for the empty arms of unions, e.g. the failure of `GETATTR`.
+impl Serialize for () {
+    fn serialize<R: Write>(&self, _dest: &mut R) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// XDR `void` type deserialization implementation, which reads nothing.
+impl Deserialize for () {
+    fn deserialize<R: Read>(&mut self, _src: &mut R) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
 /// XDR `int` type serialization implementation.
 impl Serialize for i32 {
     fn serialize<R: Write>(&self, dest: &mut R) -> std::io::Result<()> {
@@ -2,11 +2,16 @@
 //! as defined in RFC 1813.
 //!
 //! This module includes data structures for the following directory operations:
+//! - LOOKUP: Look up a file name (procedure 3)
+//! - CREATE: Create a file (procedure 8)
 //! - MKDIR: Create a directory (procedure 9)
 //! - SYMLINK: Create a symbolic link (procedure 10)
+//! - MKNOD: Create a special device (procedure 11)
+//! - REMOVE: Remove a file (procedure 12)
+//! - RMDIR: Remove a directory (procedure 13)
+//! - RENAME: Rename a file or directory (procedure 14)
 //! - READDIR: Read from a directory (procedure 16)
 //! - READDIRPLUS: Extended read from a directory (procedure 17)
-//! - MKNOD: Create a special device (procedure 11)
 //!
 //! These structures implement the XDR serialization/deserialization interfaces for
 //! the request arguments and response data of directory-related operations.
@@ -23,9 +28,9 @@ use std::io::{Read, Write};
 use num_derive::{FromPrimitive, ToPrimitive};
 
 use super::{
-    cookie3, cookieverf3, count3, diropargs3, fileid3, filename3, ftype3, nfs_fh3, post_op_attr,
-    post_op_fh3, sattr3, specdata3, symlinkdata3, Deserialize, DeserializeEnum, DeserializeStruct,
-    Serialize, SerializeEnum, SerializeStruct,
+    cookie3, cookieverf3, count3, createhow3, deserialize, diropargs3, fileid3, filename3, ftype3,
+    nfs_fh3, post_op_attr, post_op_fh3, res3, sattr3, specdata3, symlinkdata3, wcc_data,
+    Deserialize, DeserializeEnum, DeserializeStruct, Serialize, SerializeEnum, SerializeStruct,
 };
 
 /// Enumeration of device types for special files in NFS version 3
@@ -48,6 +53,175 @@ pub enum devicetype3 {
 impl SerializeEnum for devicetype3 {}
 impl DeserializeEnum for devicetype3 {}
 
+/// Arguments for the LOOKUP procedure (procedure 3)
+/// as defined in RFC 1813 section 3.3.3
+#[allow(non_camel_case_types)]
+#[derive(Clone, Debug, Default)]
+pub struct LOOKUP3args {
+    /// Directory to search and the name to look up
+    pub what: diropargs3,
+}
+DeserializeStruct!(LOOKUP3args, what);
+SerializeStruct!(LOOKUP3args, what);
+
+/// Successful response for the LOOKUP procedure
+/// as defined in RFC 1813 section 3.3.3
+#[allow(non_camel_case_types)]
+#[derive(Debug, Default)]
+pub struct LOOKUP3resok {
+    /// File handle of the object found
+    pub object: nfs_fh3,
+    /// Attributes of the object found
+    pub obj_attributes: post_op_attr,
+    /// Attributes of the directory searched
+    pub dir_attributes: post_op_attr,
+}
+DeserializeStruct!(LOOKUP3resok, object, obj_attributes, dir_attributes);
+SerializeStruct!(LOOKUP3resok, object, obj_attributes, dir_attributes);
+
+/// Failed response for the LOOKUP procedure
+/// as defined in RFC 1813 section 3.3.3
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct LOOKUP3resfail {
+    /// Attributes of the directory searched
+    pub dir_attributes: post_op_attr,
+}
+DeserializeStruct!(LOOKUP3resfail, dir_attributes);
+SerializeStruct!(LOOKUP3resfail, dir_attributes);
+
+/// Result of the LOOKUP procedure
+pub type LOOKUP3res = res3<LOOKUP3resok, LOOKUP3resfail>;
+
+/// Arguments for the CREATE procedure (procedure 8)
+/// as defined in RFC 1813 section 3.3.8
+#[allow(non_camel_case_types)]
+#[derive(Debug, Default)]
+pub struct CREATE3args {
+    /// Directory where the file should be created and its name
+    pub dirops: diropargs3,
+    /// How to create the file
+    pub how: createhow3,
+}
+DeserializeStruct!(CREATE3args, dirops, how);
+SerializeStruct!(CREATE3args, dirops, how);
+
+/// Successful response for the CREATE procedure
+/// as defined in RFC 1813 section 3.3.8
+/// MKDIR, SYMLINK and MKNOD respond with the same data.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Default)]
+pub struct CREATE3resok {
+    /// File handle of the new object
+    pub obj: post_op_fh3,
+    /// Attributes of the new object
+    pub obj_attributes: post_op_attr,
+    /// Attributes of the directory before and after the operation
+    pub dir_wcc: wcc_data,
+}
+DeserializeStruct!(CREATE3resok, obj, obj_attributes, dir_wcc);
+SerializeStruct!(CREATE3resok, obj, obj_attributes, dir_wcc);
+
+/// Failed response for the CREATE procedure
+/// as defined in RFC 1813 section 3.3.8
+/// MKDIR, SYMLINK, MKNOD, REMOVE and RMDIR fail with the same data.
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CREATE3resfail {
+    /// Attributes of the directory before and after the operation
+    pub dir_wcc: wcc_data,
+}
+DeserializeStruct!(CREATE3resfail, dir_wcc);
+SerializeStruct!(CREATE3resfail, dir_wcc);
+
+/// Result of the CREATE procedure
+pub type CREATE3res = res3<CREATE3resok, CREATE3resfail>;
+
+/// Successful response for the MKDIR procedure (RFC 1813 section 3.3.9)
+pub type MKDIR3resok = CREATE3resok;
+/// Failed response for the MKDIR procedure (RFC 1813 section 3.3.9)
+pub type MKDIR3resfail = CREATE3resfail;
+/// Result of the MKDIR procedure
+pub type MKDIR3res = res3<MKDIR3resok, MKDIR3resfail>;
+
+/// Successful response for the SYMLINK procedure (RFC 1813 section 3.3.10)
+pub type SYMLINK3resok = CREATE3resok;
+/// Failed response for the SYMLINK procedure (RFC 1813 section 3.3.10)
+pub type SYMLINK3resfail = CREATE3resfail;
+/// Result of the SYMLINK procedure
+pub type SYMLINK3res = res3<SYMLINK3resok, SYMLINK3resfail>;
+
+/// Successful response for the MKNOD procedure (RFC 1813 section 3.3.11)
+pub type MKNOD3resok = CREATE3resok;
+/// Failed response for the MKNOD procedure (RFC 1813 section 3.3.11)
+pub type MKNOD3resfail = CREATE3resfail;
+/// Result of the MKNOD procedure
+pub type MKNOD3res = res3<MKNOD3resok, MKNOD3resfail>;
+
+/// Arguments for the REMOVE procedure (procedure 12)
+/// as defined in RFC 1813 section 3.3.12
+#[allow(non_camel_case_types)]
+#[derive(Clone, Debug, Default)]
+pub struct REMOVE3args {
+    /// Directory of the entry to remove and its name
+    pub object: diropargs3,
+}
+DeserializeStruct!(REMOVE3args, object);
+SerializeStruct!(REMOVE3args, object);
+
+/// Successful response for the REMOVE procedure
+/// as defined in RFC 1813 section 3.3.12
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct REMOVE3resok {
+    /// Attributes of the directory before and after the operation
+    pub dir_wcc: wcc_data,
+}
+DeserializeStruct!(REMOVE3resok, dir_wcc);
+SerializeStruct!(REMOVE3resok, dir_wcc);
+
+/// Result of the REMOVE procedure
+pub type REMOVE3res = res3<REMOVE3resok, CREATE3resfail>;
+
+/// Arguments for the RMDIR procedure (RFC 1813 section 3.3.13), the same as for REMOVE
+pub type RMDIR3args = REMOVE3args;
+/// Successful response for the RMDIR procedure (RFC 1813 section 3.3.13)
+pub type RMDIR3resok = REMOVE3resok;
+/// Result of the RMDIR procedure
+pub type RMDIR3res = res3<RMDIR3resok, CREATE3resfail>;
+
+/// Arguments for the RENAME procedure (procedure 14)
+/// as defined in RFC 1813 section 3.3.14
+#[allow(non_camel_case_types)]
+#[derive(Clone, Debug, Default)]
+pub struct RENAME3args {
+    /// Directory and name of the entry to rename
+    pub from: diropargs3,
+    /// Directory and name the entry gets
+    pub to: diropargs3,
+}
+DeserializeStruct!(RENAME3args, from, to);
+SerializeStruct!(RENAME3args, from, to);
+
+/// Response for the RENAME procedure
+/// as defined in RFC 1813 section 3.3.14
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RENAME3resok {
+    /// Attributes of the source directory before and after the operation
+    pub fromdir_wcc: wcc_data,
+    /// Attributes of the target directory before and after the operation
+    pub todir_wcc: wcc_data,
+}
+DeserializeStruct!(RENAME3resok, fromdir_wcc, todir_wcc);
+SerializeStruct!(RENAME3resok, fromdir_wcc, todir_wcc);
+
+/// Failed response for the RENAME procedure, with the same data as [`RENAME3resok`]
+pub type RENAME3resfail = RENAME3resok;
+
+/// Result of the RENAME procedure
+pub type RENAME3res = res3<RENAME3resok, RENAME3resfail>;
+
 /// Arguments for the MKDIR procedure (procedure 9)
 /// as defined in RFC 1813 section 3.3.9
 /// Used to create a new directory
@@ -111,6 +285,77 @@ pub struct READDIR3args {
 DeserializeStruct!(READDIR3args, dir, cookie, cookieverf, dircount);
 SerializeStruct!(READDIR3args, dir, cookie, cookieverf, dircount);
 
+/// Serializes entries as the linked list of XDR optional data used by directory listings
+fn serialize_entries<T: Serialize, W: Write>(entries: &[T], dest: &mut W) -> std::io::Result<()> {
+    for entry in entries {
+        true.serialize(dest)?;
+        entry.serialize(dest)?;
+    }
+    false.serialize(dest)
+}
+
+/// Deserializes the linked list of entries of a directory listing
+fn deserialize_entries<T: Deserialize + Default, R: Read>(src: &mut R) -> std::io::Result<Vec<T>> {
+    let mut entries = Vec::new();
+    while deserialize::<bool>(src)? {
+        entries.push(deserialize(src)?);
+    }
+    Ok(entries)
+}
+
+/// Entries of a READDIR reply as defined in RFC 1813 section 3.3.16
+/// On the wire, the entries form a linked list.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Default)]
+pub struct dirlist3 {
+    /// Entries in the order of their cookies
+    pub entries: Vec<entry3>,
+    /// True if the last entry of the directory is included
+    pub eof: bool,
+}
+
+impl Serialize for dirlist3 {
+    fn serialize<W: Write>(&self, dest: &mut W) -> std::io::Result<()> {
+        serialize_entries(&self.entries, dest)?;
+        self.eof.serialize(dest)
+    }
+}
+impl Deserialize for dirlist3 {
+    fn deserialize<R: Read>(&mut self, src: &mut R) -> std::io::Result<()> {
+        self.entries = deserialize_entries(src)?;
+        self.eof.deserialize(src)
+    }
+}
+
+/// Successful response for the READDIR procedure
+/// as defined in RFC 1813 section 3.3.16
+#[allow(non_camel_case_types)]
+#[derive(Debug, Default)]
+pub struct READDIR3resok {
+    /// Attributes of the directory
+    pub dir_attributes: post_op_attr,
+    /// Cookie verifier to send with the next call
+    pub cookieverf: cookieverf3,
+    /// Entries read
+    pub reply: dirlist3,
+}
+DeserializeStruct!(READDIR3resok, dir_attributes, cookieverf, reply);
+SerializeStruct!(READDIR3resok, dir_attributes, cookieverf, reply);
+
+/// Failed response for the READDIR and READDIRPLUS procedures
+/// as defined in RFC 1813 sections 3.3.16 and 3.3.17
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct READDIR3resfail {
+    /// Attributes of the directory
+    pub dir_attributes: post_op_attr,
+}
+DeserializeStruct!(READDIR3resfail, dir_attributes);
+SerializeStruct!(READDIR3resfail, dir_attributes);
+
+/// Result of the READDIR procedure
+pub type READDIR3res = res3<READDIR3resok, READDIR3resfail>;
+
 /// Directory entry with additional attributes for READDIRPLUS operation
 /// as defined in RFC 1813 section 3.3.17
 /// This structure represents a single directory entry with extended information
@@ -151,6 +396,48 @@ pub struct READDIRPLUS3args {
 DeserializeStruct!(READDIRPLUS3args, dir, cookie, cookieverf, dircount, maxcount);
 SerializeStruct!(READDIRPLUS3args, dir, cookie, cookieverf, dircount, maxcount);
 
+/// Entries of a READDIRPLUS reply as defined in RFC 1813 section 3.3.17
+/// On the wire, the entries form a linked list.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Default)]
+pub struct dirlistplus3 {
+    /// Entries in the order of their cookies
+    pub entries: Vec<entryplus3>,
+    /// True if the last entry of the directory is included
+    pub eof: bool,
+}
+
+impl Serialize for dirlistplus3 {
+    fn serialize<W: Write>(&self, dest: &mut W) -> std::io::Result<()> {
+        serialize_entries(&self.entries, dest)?;
+        self.eof.serialize(dest)
+    }
+}
+impl Deserialize for dirlistplus3 {
+    fn deserialize<R: Read>(&mut self, src: &mut R) -> std::io::Result<()> {
+        self.entries = deserialize_entries(src)?;
+        self.eof.deserialize(src)
+    }
+}
+
+/// Successful response for the READDIRPLUS procedure
+/// as defined in RFC 1813 section 3.3.17
+#[allow(non_camel_case_types)]
+#[derive(Debug, Default)]
+pub struct READDIRPLUS3resok {
+    /// Attributes of the directory
+    pub dir_attributes: post_op_attr,
+    /// Cookie verifier to send with the next call
+    pub cookieverf: cookieverf3,
+    /// Entries read
+    pub reply: dirlistplus3,
+}
+DeserializeStruct!(READDIRPLUS3resok, dir_attributes, cookieverf, reply);
+SerializeStruct!(READDIRPLUS3resok, dir_attributes, cookieverf, reply);
+
+/// Result of the READDIRPLUS procedure
+pub type READDIRPLUS3res = res3<READDIRPLUS3resok, READDIR3resfail>;
+
 /// Arguments for the MKNOD procedure (procedure 11)
 /// as defined in RFC 1813 section 3.3.11
 /// Used to create a special device file, FIFO, or socket
@@ -2,13 +2,17 @@
 //! as defined in RFC 1813.
 //!
 //! This module includes data structures for the following operations:
+//! - GETATTR: Get file attributes (procedure 1)
+//! - ACCESS: Check access permission (procedure 4)
+//! - READLINK: Read from a symbolic link (procedure 5)
 //! - READ: Read data from a file (procedure 6)
 //! - WRITE: Write data to a file (procedure 7)
 //! - COMMIT: Commit asynchronously written data to stable storage (procedure 21)
 //! - LINK: Create a hard link (procedure 15)
 //!
 //! The structures implement the XDR serialization/deserialization interfaces for
-//! the request arguments and response data of these operations.
+//! the request arguments and response data of these operations. Each `*3res` type
+//! is the complete result following the RPC reply header, see [`super::res3`].
 
 // Allow unused code warnings since we implement the complete RFC 1813 specification,
 // including procedures that may not be used by all clients
@@ -22,10 +26,106 @@ use std::io::{Read, Write};
 use num_derive::{FromPrimitive, ToPrimitive};
 
 use super::{
-    count3, diropargs3, nfs_fh3, offset3, post_op_attr, wcc_data, writeverf3, Deserialize,
-    DeserializeEnum, DeserializeStruct, Serialize, SerializeEnum, SerializeStruct,
+    count3, diropargs3, fattr3, nfs_fh3, nfspath3, offset3, post_op_attr, res3, wcc_data,
+    writeverf3, Deserialize, DeserializeEnum, DeserializeStruct, Serialize, SerializeEnum,
+    SerializeStruct,
 };
 
+/// Arguments for the GETATTR procedure (procedure 1) as defined in RFC 1813 section 3.3.1
+#[allow(non_camel_case_types)]
+#[derive(Clone, Debug, Default)]
+pub struct GETATTR3args {
+    /// File handle of the object to get the attributes of
+    pub object: nfs_fh3,
+}
+DeserializeStruct!(GETATTR3args, object);
+SerializeStruct!(GETATTR3args, object);
+
+/// Successful response for the GETATTR procedure as defined in RFC 1813 section 3.3.1
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct GETATTR3resok {
+    /// Attributes of the object
+    pub obj_attributes: fattr3,
+}
+DeserializeStruct!(GETATTR3resok, obj_attributes);
+SerializeStruct!(GETATTR3resok, obj_attributes);
+
+/// Result of the GETATTR procedure; a failure carries no data
+pub type GETATTR3res = res3<GETATTR3resok, ()>;
+
+/// Arguments for the ACCESS procedure (procedure 4) as defined in RFC 1813 section 3.3.4
+#[allow(non_camel_case_types)]
+#[derive(Clone, Debug, Default)]
+pub struct ACCESS3args {
+    /// File handle of the object to check
+    pub object: nfs_fh3,
+    /// Bit mask of `ACCESS3_*` permissions to check
+    pub access: u32,
+}
+DeserializeStruct!(ACCESS3args, object, access);
+SerializeStruct!(ACCESS3args, object, access);
+
+/// Successful response for the ACCESS procedure as defined in RFC 1813 section 3.3.4
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ACCESS3resok {
+    /// Attributes of the object
+    pub obj_attributes: post_op_attr,
+    /// Bit mask of the checked permissions that are granted
+    pub access: u32,
+}
+DeserializeStruct!(ACCESS3resok, obj_attributes, access);
+SerializeStruct!(ACCESS3resok, obj_attributes, access);
+
+/// Failed response for the ACCESS procedure as defined in RFC 1813 section 3.3.4
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ACCESS3resfail {
+    /// Attributes of the object
+    pub obj_attributes: post_op_attr,
+}
+DeserializeStruct!(ACCESS3resfail, obj_attributes);
+SerializeStruct!(ACCESS3resfail, obj_attributes);
+
+/// Result of the ACCESS procedure
+pub type ACCESS3res = res3<ACCESS3resok, ACCESS3resfail>;
+
+/// Arguments for the READLINK procedure (procedure 5) as defined in RFC 1813 section 3.3.5
+#[allow(non_camel_case_types)]
+#[derive(Clone, Debug, Default)]
+pub struct READLINK3args {
+    /// File handle of the symbolic link
+    pub symlink: nfs_fh3,
+}
+DeserializeStruct!(READLINK3args, symlink);
+SerializeStruct!(READLINK3args, symlink);
+
+/// Successful response for the READLINK procedure as defined in RFC 1813 section 3.3.5
+#[allow(non_camel_case_types)]
+#[derive(Debug, Default)]
+pub struct READLINK3resok {
+    /// Attributes of the symbolic link
+    pub symlink_attributes: post_op_attr,
+    /// Target of the symbolic link
+    pub data: nfspath3,
+}
+DeserializeStruct!(READLINK3resok, symlink_attributes, data);
+SerializeStruct!(READLINK3resok, symlink_attributes, data);
+
+/// Failed response for the READLINK procedure as defined in RFC 1813 section 3.3.5
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct READLINK3resfail {
+    /// Attributes of the symbolic link
+    pub symlink_attributes: post_op_attr,
+}
+DeserializeStruct!(READLINK3resfail, symlink_attributes);
+SerializeStruct!(READLINK3resfail, symlink_attributes);
+
+/// Result of the READLINK procedure
+pub type READLINK3res = res3<READLINK3resok, READLINK3resfail>;
+
 /// Arguments for the READ procedure (procedure 6) as defined in RFC 1813 section 3.3.6
 /// Used to read data from a regular file
 #[allow(non_camel_case_types)]
@@ -57,6 +157,19 @@ pub struct READ3resok {
 DeserializeStruct!(READ3resok, file_attributes, count, eof, data);
 SerializeStruct!(READ3resok, file_attributes, count, eof, data);
 
+/// Failed response for the READ procedure as defined in RFC 1813 section 3.3.6
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct READ3resfail {
+    /// File attributes after the operation
+    pub file_attributes: post_op_attr,
+}
+DeserializeStruct!(READ3resfail, file_attributes);
+SerializeStruct!(READ3resfail, file_attributes);
+
+/// Result of the READ procedure
+pub type READ3res = res3<READ3resok, READ3resfail>;
+
 /// Arguments for the COMMIT procedure (procedure 21) as defined in RFC 1813 section 3.3.21
 /// Used to commit pending writes to stable storage
 #[allow(non_camel_case_types)]
@@ -84,6 +197,19 @@ pub struct COMMIT3resok {
 DeserializeStruct!(COMMIT3resok, file_wcc, verf);
 SerializeStruct!(COMMIT3resok, file_wcc, verf);
 
+/// Failed response for the COMMIT procedure as defined in RFC 1813 section 3.3.21
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct COMMIT3resfail {
+    /// File attributes before and after the operation
+    pub file_wcc: wcc_data,
+}
+DeserializeStruct!(COMMIT3resfail, file_wcc);
+SerializeStruct!(COMMIT3resfail, file_wcc);
+
+/// Result of the COMMIT procedure
+pub type COMMIT3res = res3<COMMIT3resok, COMMIT3resfail>;
+
 /// Arguments for the LINK procedure (procedure 15) as defined in RFC 1813 section 3.3.15
 /// Used to create a hard link to a file
 #[allow(non_camel_case_types)]
@@ -97,6 +223,24 @@ pub struct LINK3args {
 DeserializeStruct!(LINK3args, file, link);
 SerializeStruct!(LINK3args, file, link);
 
+/// Response for the LINK procedure as defined in RFC 1813 section 3.3.15
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct LINK3resok {
+    /// Attributes of the linked file
+    pub file_attributes: post_op_attr,
+    /// Attributes of the directory of the new link before and after the operation
+    pub linkdir_wcc: wcc_data,
+}
+DeserializeStruct!(LINK3resok, file_attributes, linkdir_wcc);
+SerializeStruct!(LINK3resok, file_attributes, linkdir_wcc);
+
+/// Failed response for the LINK procedure, with the same data as [`LINK3resok`]
+pub type LINK3resfail = LINK3resok;
+
+/// Result of the LINK procedure
+pub type LINK3res = res3<LINK3resok, LINK3resfail>;
+
 /// Enumeration specifying how data should be written to storage
 /// as defined in RFC 1813 section 3.3.7
 #[allow(non_camel_case_types)]
@@ -151,3 +295,16 @@ pub struct WRITE3resok {
 }
 DeserializeStruct!(WRITE3resok, file_wcc, count, committed, verf);
 SerializeStruct!(WRITE3resok, file_wcc, count, committed, verf);
+
+/// Failed response for the WRITE procedure as defined in RFC 1813 section 3.3.7
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct WRITE3resfail {
+    /// File attributes before and after the operation
+    pub file_wcc: wcc_data,
+}
+DeserializeStruct!(WRITE3resfail, file_wcc);
+SerializeStruct!(WRITE3resfail, file_wcc);
+
+/// Result of the WRITE procedure
+pub type WRITE3res = res3<WRITE3resok, WRITE3resfail>;
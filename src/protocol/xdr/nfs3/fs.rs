@@ -17,9 +17,55 @@
 use std::io::{Read, Write};
 
 use super::{
-    nfstime3, post_op_attr, size3, Deserialize, DeserializeStruct, Serialize, SerializeStruct,
+    nfs_fh3, nfstime3, post_op_attr, res3, size3, Deserialize, DeserializeStruct, Serialize,
+    SerializeStruct,
 };
 
+/// Arguments for the FSSTAT procedure (procedure 18) as defined in RFC 1813 section 3.3.18
+#[allow(non_camel_case_types)]
+#[derive(Clone, Debug, Default)]
+pub struct FSSTAT3args {
+    /// File handle of an object in the file system, usually its root
+    pub fsroot: nfs_fh3,
+}
+DeserializeStruct!(FSSTAT3args, fsroot);
+SerializeStruct!(FSSTAT3args, fsroot);
+
+/// Arguments for the FSINFO procedure (procedure 19), the same as for FSSTAT
+pub type FSINFO3args = FSSTAT3args;
+
+/// Arguments for the PATHCONF procedure (procedure 20) as defined in RFC 1813 section 3.3.20
+#[allow(non_camel_case_types)]
+#[derive(Clone, Debug, Default)]
+pub struct PATHCONF3args {
+    /// File handle of the object to get the information for
+    pub object: nfs_fh3,
+}
+DeserializeStruct!(PATHCONF3args, object);
+SerializeStruct!(PATHCONF3args, object);
+
+/// Failed response for the FSSTAT, FSINFO and PATHCONF procedures
+/// as defined in RFC 1813 sections 3.3.18 to 3.3.20
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FSSTAT3resfail {
+    /// Attributes of the object
+    pub obj_attributes: post_op_attr,
+}
+DeserializeStruct!(FSSTAT3resfail, obj_attributes);
+SerializeStruct!(FSSTAT3resfail, obj_attributes);
+
+/// Result of the FSSTAT procedure
+pub type FSSTAT3res = res3<FSSTAT3resok, FSSTAT3resfail>;
+
+/// Successful response for the FSINFO procedure, see [`fsinfo3`]
+pub type FSINFO3resok = fsinfo3;
+/// Result of the FSINFO procedure
+pub type FSINFO3res = res3<FSINFO3resok, FSSTAT3resfail>;
+
+/// Result of the PATHCONF procedure
+pub type PATHCONF3res = res3<PATHCONF3resok, FSSTAT3resfail>;
+
 // Section 3.3.19. Procedure 19: FSINFO - Get static file system Information
 // The following constants are used in fsinfo to construct the bitmask 'properties',
 // which represents the file system properties.
@@ -30,6 +30,11 @@ pub mod dir;
 pub mod file;
 pub mod fs;
 
+// The arguments and results of every procedure are available from this module
+pub use dir::*;
+pub use file::*;
+pub use fs::*;
+
 // Section 2.2 Constants
 /// The RPC program number for NFS version 3 service.
 pub const PROGRAM: u32 = 100_003;
@@ -100,15 +105,17 @@ impl std::ops::Deref for nfsstring {
     }
 }
 
+/// Formatted as a quoted string, or redacted by the installed
+/// [`crate::redaction::RedactionPolicy`]
 impl fmt::Debug for nfsstring {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", String::from_utf8_lossy(&self.0))
+        write!(f, "{}", crate::redaction::name(&self.0))
     }
 }
 
 impl fmt::Display for nfsstring {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", String::from_utf8_lossy(&self.0))
+        write!(f, "{}", crate::redaction::name(&self.0))
     }
 }
 
@@ -384,9 +391,63 @@ pub struct nfstime3 {
 DeserializeStruct!(nfstime3, seconds, nseconds);
 SerializeStruct!(nfstime3, seconds, nseconds);
 
+impl nfstime3 {
+    /// Latest representable time, early in 2106
+    pub const MAX: nfstime3 = nfstime3 { seconds: u32::MAX, nseconds: 999_999_999 };
+
+    /// Converts seconds and nanoseconds since the epoch to an NFS timestamp
+    ///
+    /// `nfstime3` counts unsigned 32-bit seconds from 1970, so times before the
+    /// epoch are clamped to the epoch and times after early 2106 to [`Self::MAX`]
+    /// instead of wrapping around. Nanoseconds are clamped to below one second.
+    pub fn from_unix(seconds: i64, nseconds: i64) -> Self {
+        if seconds < 0 {
+            return nfstime3::default();
+        }
+        match u32::try_from(seconds) {
+            Ok(seconds) => nfstime3 { seconds, nseconds: nseconds.clamp(0, 999_999_999) as u32 },
+            Err(_) => nfstime3::MAX,
+        }
+    }
+
+    /// Converts a duration, such as the `time_delta` of `FSINFO`, saturating at
+    /// [`Self::MAX`]
+    pub fn from_duration(duration: std::time::Duration) -> Self {
+        match u32::try_from(duration.as_secs()) {
+            Ok(seconds) => nfstime3 { seconds, nseconds: duration.subsec_nanos() },
+            Err(_) => nfstime3::MAX,
+        }
+    }
+}
+
+impl From<std::time::SystemTime> for nfstime3 {
+    /// Converts a local time, clamped to the range of `nfstime3`
+    fn from(time: std::time::SystemTime) -> Self {
+        match time.duration_since(std::time::UNIX_EPOCH) {
+            Ok(since_epoch) => nfstime3::from_duration(since_epoch),
+            Err(_) => nfstime3::default(),
+        }
+    }
+}
+
+impl From<nfstime3> for std::time::SystemTime {
+    fn from(time: nfstime3) -> Self {
+        let since_epoch =
+            std::time::Duration::new(u64::from(time.seconds), time.nseconds.min(999_999_999));
+        std::time::UNIX_EPOCH + since_epoch
+    }
+}
+
+impl From<filetime::FileTime> for nfstime3 {
+    /// Converts a file time, clamped to the range of `nfstime3`
+    fn from(time: filetime::FileTime) -> Self {
+        nfstime3::from_unix(time.unix_seconds(), i64::from(time.nanoseconds()))
+    }
+}
+
 impl From<nfstime3> for filetime::FileTime {
     fn from(time: nfstime3) -> Self {
-        Self::from_unix_time(time.seconds as i64, time.nseconds)
+        Self::from_unix_time(time.seconds as i64, time.nseconds.min(999_999_999))
     }
 }
 
@@ -394,7 +455,7 @@ impl From<nfstime3> for filetime::FileTime {
 /// Contains all the standard attributes associated with a file or directory
 /// in the NFS version 3 protocol
 #[allow(non_camel_case_types)]
-#[derive(Copy, Clone, Debug, Default)]
+#[derive(Copy, Clone, Default)]
 pub struct fattr3 {
     /// Type of file (regular, directory, symbolic link, etc.)
     pub ftype: ftype3,
@@ -437,6 +498,28 @@ SerializeStruct!(
     fattr3, ftype, mode, nlink, uid, gid, size, used, rdev, fsid, fileid, atime, mtime, ctime
 );
 
+/// Owner and group are redacted if the installed
+/// [`crate::redaction::RedactionPolicy`] hides IDs
+impl fmt::Debug for fattr3 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("fattr3")
+            .field("ftype", &self.ftype)
+            .field("mode", &self.mode)
+            .field("nlink", &self.nlink)
+            .field("uid", &crate::redaction::id(self.uid))
+            .field("gid", &crate::redaction::id(self.gid))
+            .field("size", &self.size)
+            .field("used", &self.used)
+            .field("rdev", &self.rdev)
+            .field("fsid", &self.fsid)
+            .field("fileid", &self.fileid)
+            .field("atime", &self.atime)
+            .field("mtime", &self.mtime)
+            .field("ctime", &self.ctime)
+            .finish()
+    }
+}
+
 /// Attributes used in weak cache consistency checking as defined in RFC 1813 section 2.3.8
 /// These attributes are used to detect changes to a file by comparing
 /// values before and after operations
@@ -453,6 +536,12 @@ pub struct wcc_attr {
 DeserializeStruct!(wcc_attr, size, mtime, ctime);
 SerializeStruct!(wcc_attr, size, mtime, ctime);
 
+impl From<fattr3> for wcc_attr {
+    fn from(attr: fattr3) -> Self {
+        wcc_attr { size: attr.size, mtime: attr.mtime, ctime: attr.ctime }
+    }
+}
+
 /// Pre-operation attributes for weak cache consistency as defined in RFC 1813 section 2.3.8
 /// These attributes represent the file state before an operation was performed
 /// Used together with post-operation attributes to determine if file state changed
@@ -607,7 +696,7 @@ impl Deserialize for set_mtime {
 
 /// Set of file attributes to change in `SETATTR` operations
 #[allow(non_camel_case_types)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone)]
 pub struct sattr3 {
     /// File mode (permissions)
     pub mode: set_mode3,
@@ -625,6 +714,21 @@ pub struct sattr3 {
 DeserializeStruct!(sattr3, mode, uid, gid, size, atime, mtime);
 SerializeStruct!(sattr3, mode, uid, gid, size, atime, mtime);
 
+/// Owner and group are redacted if the installed
+/// [`crate::redaction::RedactionPolicy`] hides IDs
+impl fmt::Debug for sattr3 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("sattr3")
+            .field("mode", &self.mode)
+            .field("uid", &self.uid.map(crate::redaction::id))
+            .field("gid", &self.gid.map(crate::redaction::id))
+            .field("size", &self.size)
+            .field("atime", &self.atime)
+            .field("mtime", &self.mtime)
+            .finish()
+    }
+}
+
 impl Default for sattr3 {
     fn default() -> sattr3 {
         sattr3 {
@@ -697,6 +801,54 @@ pub enum createmode3 {
 impl SerializeEnum for createmode3 {}
 impl DeserializeEnum for createmode3 {}
 
+/// How a file is created by `CREATE`, the union selected by [`createmode3`]
+/// as defined in RFC 1813 section 3.3.8
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone, Debug)]
+pub enum createhow3 {
+    /// Create the file or truncate an existing one, setting the attributes
+    UNCHECKED(sattr3),
+    /// Create the file with the attributes, failing if it exists
+    GUARDED(sattr3),
+    /// Create the file unless it exists with a different verifier
+    EXCLUSIVE(createverf3),
+}
+
+impl Default for createhow3 {
+    fn default() -> createhow3 {
+        createhow3::UNCHECKED(sattr3::default())
+    }
+}
+
+impl Serialize for createhow3 {
+    fn serialize<R: Write>(&self, dest: &mut R) -> std::io::Result<()> {
+        match self {
+            createhow3::UNCHECKED(attr) => {
+                createmode3::UNCHECKED.serialize(dest)?;
+                attr.serialize(dest)
+            }
+            createhow3::GUARDED(attr) => {
+                createmode3::GUARDED.serialize(dest)?;
+                attr.serialize(dest)
+            }
+            createhow3::EXCLUSIVE(verf) => {
+                createmode3::EXCLUSIVE.serialize(dest)?;
+                verf.serialize(dest)
+            }
+        }
+    }
+}
+impl Deserialize for createhow3 {
+    fn deserialize<R: Read>(&mut self, src: &mut R) -> std::io::Result<()> {
+        *self = match deserialize::<createmode3>(src)? {
+            createmode3::UNCHECKED => createhow3::UNCHECKED(deserialize(src)?),
+            createmode3::GUARDED => createhow3::GUARDED(deserialize(src)?),
+            createmode3::EXCLUSIVE => createhow3::EXCLUSIVE(deserialize(src)?),
+        };
+        Ok(())
+    }
+}
+
 pub type sattrguard3 = Option<nfstime3>;
 
 /// Arguments for `SETATTR` operations
@@ -712,3 +864,236 @@ pub struct SETATTR3args {
 }
 DeserializeStruct!(SETATTR3args, object, new_attribute, guard);
 SerializeStruct!(SETATTR3args, object, new_attribute, guard);
+
+/// Successful response for `SETATTR` operations as defined in RFC 1813 section 3.3.2
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SETATTR3resok {
+    /// Attributes of the object before and after the change
+    pub obj_wcc: wcc_data,
+}
+DeserializeStruct!(SETATTR3resok, obj_wcc);
+SerializeStruct!(SETATTR3resok, obj_wcc);
+
+/// Failed response for `SETATTR` operations, with the same data as
+/// [`SETATTR3resok`]
+pub type SETATTR3resfail = SETATTR3resok;
+
+/// Result of a procedure as defined in RFC 1813: a union switched on the
+/// status, holding the `resok` data for `NFS3_OK` and the `resfail` data
+/// for every error
+///
+/// Every procedure has an alias, e.g. [`file::READ3res`] is
+/// `res3<READ3resok, READ3resfail>`.
+#[allow(non_camel_case_types)]
+#[derive(Clone, Debug)]
+pub enum res3<T, F> {
+    /// The procedure succeeded
+    Ok(T),
+    /// The procedure failed with a status other than `NFS3_OK`
+    Fail(nfsstat3, F),
+}
+
+impl<T, F: Default> Default for res3<T, F> {
+    fn default() -> Self {
+        res3::Fail(nfsstat3::NFS3ERR_SERVERFAULT, F::default())
+    }
+}
+
+impl<T: Serialize, F: Serialize> Serialize for res3<T, F> {
+    fn serialize<R: Write>(&self, dest: &mut R) -> std::io::Result<()> {
+        match self {
+            res3::Ok(resok) => {
+                nfsstat3::NFS3_OK.serialize(dest)?;
+                resok.serialize(dest)
+            }
+            res3::Fail(stat, resfail) => {
+                stat.serialize(dest)?;
+                resfail.serialize(dest)
+            }
+        }
+    }
+}
+impl<T: Deserialize + Default, F: Deserialize + Default> Deserialize for res3<T, F> {
+    fn deserialize<R: Read>(&mut self, src: &mut R) -> std::io::Result<()> {
+        let stat = deserialize::<u32>(src)?;
+        *self = match num_traits::FromPrimitive::from_u32(stat) {
+            Some(nfsstat3::NFS3_OK) => res3::Ok(deserialize(src)?),
+            Some(stat) => res3::Fail(stat, deserialize(src)?),
+            None => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Invalid nfsstat3 value: {stat}"),
+                ));
+            }
+        };
+        Ok(())
+    }
+}
+
+/// Result of `SETATTR` as defined in RFC 1813 section 3.3.2
+pub type SETATTR3res = res3<SETATTR3resok, SETATTR3resfail>;
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use super::*;
+
+    fn parts(time: nfstime3) -> (u32, u32) {
+        (time.seconds, time.nseconds)
+    }
+
+    #[test]
+    fn timestamps_clamp_to_the_representable_range() {
+        assert_eq!(parts(nfstime3::from_unix(-1, 500)), (0, 0));
+        assert_eq!(parts(nfstime3::from_unix(1 << 32, 0)), (u32::MAX, 999_999_999));
+        assert_eq!(parts(nfstime3::from_unix(5, 2_000_000_000)), (5, 999_999_999));
+
+        let before_epoch = UNIX_EPOCH - Duration::from_secs(10);
+        assert_eq!(parts(nfstime3::from(before_epoch)), (0, 0));
+        let after_2106 = UNIX_EPOCH + Duration::from_secs(1 << 33);
+        assert_eq!(parts(nfstime3::from(after_2106)), (u32::MAX, 999_999_999));
+        let time = UNIX_EPOCH + Duration::new(1_700_000_000, 42);
+        assert_eq!(SystemTime::from(nfstime3::from(time)), time);
+
+        let file_time = filetime::FileTime::from_unix_time(-86_400, 0);
+        assert_eq!(parts(nfstime3::from(file_time)), (0, 0));
+        let file_time = filetime::FileTime::from(nfstime3 { seconds: 7, nseconds: 8 });
+        assert_eq!((file_time.unix_seconds(), file_time.nanoseconds()), (7, 8));
+    }
+
+    /// Serializes `value`, deserializes it, and checks that it encodes the same
+    /// way again and that nothing was left over
+    fn round_trip<T: Serialize + Deserialize + Default>(value: T) {
+        let mut encoded = Vec::new();
+        value.serialize(&mut encoded).unwrap();
+        let mut src = std::io::Cursor::new(&encoded);
+        let decoded = deserialize::<T>(&mut src).unwrap();
+        assert_eq!(src.position() as usize, encoded.len());
+        let mut again = Vec::new();
+        decoded.serialize(&mut again).unwrap();
+        assert_eq!(again, encoded);
+    }
+
+    #[test]
+    fn procedure_arguments_and_results_round_trip() {
+        let fh = || nfs_fh3 { data: vec![1, 2, 3] };
+        let dirops = || diropargs3 { dir: fh(), name: b"name".as_slice().into() };
+        let attr = || Some(fattr3 { fileid: 7, size: 9, ..Default::default() });
+        let wcc =
+            || wcc_data { before: Some(wcc_attr { size: 1, ..Default::default() }), after: attr() };
+        let sattr = sattr3 {
+            mode: Some(0o644),
+            mtime: set_mtime::SET_TO_CLIENT_TIME(nfstime3 { seconds: 1, nseconds: 2 }),
+            ..Default::default()
+        };
+
+        round_trip(GETATTR3args { object: fh() });
+        round_trip(GETATTR3res::Ok(GETATTR3resok { obj_attributes: attr().unwrap() }));
+        round_trip(GETATTR3res::Fail(nfsstat3::NFS3ERR_STALE, ()));
+        round_trip(SETATTR3args { object: fh(), new_attribute: sattr, guard: None });
+        round_trip(SETATTR3res::Ok(SETATTR3resok { obj_wcc: wcc() }));
+        round_trip(LOOKUP3args { what: dirops() });
+        round_trip(LOOKUP3res::Ok(LOOKUP3resok {
+            object: fh(),
+            obj_attributes: attr(),
+            dir_attributes: None,
+        }));
+        round_trip(LOOKUP3res::Fail(nfsstat3::NFS3ERR_NOENT, LOOKUP3resfail::default()));
+        round_trip(ACCESS3args { object: fh(), access: ACCESS3_READ | ACCESS3_LOOKUP });
+        round_trip(ACCESS3res::Ok(ACCESS3resok { obj_attributes: attr(), access: ACCESS3_READ }));
+        round_trip(READLINK3args { symlink: fh() });
+        round_trip(READLINK3res::Ok(READLINK3resok {
+            symlink_attributes: attr(),
+            data: b"target".as_slice().into(),
+        }));
+        round_trip(READ3args { file: fh(), offset: 4, count: 5 });
+        round_trip(READ3res::Ok(READ3resok {
+            file_attributes: attr(),
+            count: 3,
+            eof: true,
+            data: b"abc".to_vec(),
+        }));
+        round_trip(READ3res::Fail(nfsstat3::NFS3ERR_IO, READ3resfail { file_attributes: attr() }));
+        round_trip(WRITE3args {
+            file: fh(),
+            offset: 0,
+            count: 2,
+            stable: stable_how::FILE_SYNC as u32,
+            data: b"ab".to_vec(),
+        });
+        round_trip(WRITE3res::Ok(WRITE3resok {
+            file_wcc: wcc(),
+            count: 2,
+            committed: stable_how::FILE_SYNC,
+            verf: [1; 8],
+        }));
+        round_trip(CREATE3args { dirops: dirops(), how: createhow3::GUARDED(sattr) });
+        round_trip(CREATE3args { dirops: dirops(), how: createhow3::EXCLUSIVE([2; 8]) });
+        round_trip(CREATE3res::Ok(CREATE3resok {
+            obj: Some(fh()),
+            obj_attributes: attr(),
+            dir_wcc: wcc(),
+        }));
+        round_trip(CREATE3res::Fail(nfsstat3::NFS3ERR_EXIST, CREATE3resfail { dir_wcc: wcc() }));
+        round_trip(MKDIR3args { dirops: dirops(), attributes: sattr });
+        round_trip(SYMLINK3args {
+            dirops: dirops(),
+            symlink: symlinkdata3 {
+                symlink_attributes: sattr,
+                symlink_data: b"target".as_slice().into(),
+            },
+        });
+        round_trip(MKNOD3args::default());
+        round_trip(MKNOD3res::Fail(nfsstat3::NFS3ERR_NOTSUPP, MKNOD3resfail::default()));
+        round_trip(REMOVE3args { object: dirops() });
+        round_trip(RMDIR3res::Ok(RMDIR3resok { dir_wcc: wcc() }));
+        round_trip(RENAME3args { from: dirops(), to: dirops() });
+        round_trip(RENAME3res::Ok(RENAME3resok { fromdir_wcc: wcc(), todir_wcc: wcc() }));
+        round_trip(LINK3args { file: fh(), link: dirops() });
+        round_trip(LINK3res::Ok(LINK3resok { file_attributes: attr(), linkdir_wcc: wcc() }));
+        round_trip(READDIR3args { dir: fh(), cookie: 1, cookieverf: [3; 8], dircount: 100 });
+        round_trip(READDIR3res::Ok(READDIR3resok {
+            dir_attributes: attr(),
+            cookieverf: [3; 8],
+            reply: dirlist3 {
+                entries: vec![
+                    entry3 { fileid: 1, name: b"a".as_slice().into(), cookie: 1 },
+                    entry3 { fileid: 2, name: b"b".as_slice().into(), cookie: 2 },
+                ],
+                eof: true,
+            },
+        }));
+        round_trip(READDIRPLUS3args {
+            dir: fh(),
+            cookie: 0,
+            cookieverf: [0; 8],
+            dircount: 100,
+            maxcount: 1000,
+        });
+        round_trip(READDIRPLUS3res::Ok(READDIRPLUS3resok {
+            dir_attributes: attr(),
+            cookieverf: [3; 8],
+            reply: dirlistplus3 {
+                entries: vec![entryplus3 {
+                    fileid: 1,
+                    name: b"a".as_slice().into(),
+                    cookie: 1,
+                    name_attributes: attr(),
+                    name_handle: Some(fh()),
+                }],
+                eof: false,
+            },
+        }));
+        round_trip(FSSTAT3args { fsroot: fh() });
+        round_trip(FSSTAT3res::Ok(FSSTAT3resok { tbytes: 10, fbytes: 5, ..Default::default() }));
+        round_trip(FSINFO3args { fsroot: fh() });
+        round_trip(FSINFO3res::Ok(fsinfo3 { rtmax: 1024, ..Default::default() }));
+        round_trip(PATHCONF3args { object: fh() });
+        round_trip(PATHCONF3res::Ok(PATHCONF3resok { name_max: 255, ..Default::default() }));
+        round_trip(COMMIT3args { file: fh(), offset: 0, count: 0 });
+        round_trip(COMMIT3res::Ok(COMMIT3resok { file_wcc: wcc(), verf: [4; 8] }));
+        round_trip(COMMIT3res::Fail(nfsstat3::NFS3ERR_IO, COMMIT3resfail { file_wcc: wcc() }));
+    }
+}
@@ -1,4 +1,5 @@
-//! This module implements the Portmap protocol (RFC 5531, previously RFC 1057 Appendix A) data structures
+// Generated by `cargo xtask xdrgen` from `xdr/portmap.x`. Do not edit.
+//! This module implements the Portmap protocol (RFC 1833, previously RFC 1057 Appendix A) data structures
 //! for XDR serialization and deserialization.
 //!
 //! Portmap (also known as Portmapper) is a service that maps RPC program numbers
@@ -12,16 +13,16 @@
 
 use std::io::{Read, Write};
 
-use super::{
-    Deserialize, DeserializeEnum, DeserializeStruct, Serialize, SerializeEnum, SerializeStruct,
-};
-use crate::xdr::deserialize;
 use num_derive::{FromPrimitive, ToPrimitive};
 
+use crate::xdr::{Deserialize, DeserializeEnum, Serialize, SerializeEnum};
+use crate::{DeserializeStruct, SerializeStruct};
+
+/// Portmapper port number
+pub const PMAP_PORT: u32 = 111;
+
 /// Represents a mapping between an RPC program and a network port.
-#[allow(non_camel_case_types)]
 #[derive(Copy, Clone, Debug, Default)]
-#[repr(C)]
 pub struct mapping {
     /// The RPC program number
     pub prog: u32,
@@ -35,52 +36,57 @@ pub struct mapping {
 DeserializeStruct!(mapping, prog, vers, prot, port);
 SerializeStruct!(mapping, prog, vers, prot, port);
 
-/// A linked list node for port mapper entries following XDR representation
-/// (RFC 1057 for Port Mapper, RFC 4506 for XDR encoding).
-/// where `next` is implicitly optional in XDR (maybe null).
-#[derive(Default, Debug)]
+/// Protocol number for TCP/IP
+pub const IPPROTO_TCP: u32 = 6;
+
+/// Protocol number for UDP/IP
+pub const IPPROTO_UDP: u32 = 17;
+
+/// A linked list node for port mapper entries.
+/// `next` is an XDR optional: `None` terminates the list.
+#[derive(Clone, Debug, Default)]
 pub struct pmaplist {
     /// Current program mapping
     pub map: mapping,
-    /// Next element in the linked list:
-    /// - `None` marks valid list termination (XDR null pointer)
-    /// - `Some(...)` continues the list
+    /// Next element in the linked list
     pub next: Box<Option<pmaplist>>,
 }
-impl Serialize for pmaplist {
-    fn serialize<W: Write>(&self, dest: &mut W) -> std::io::Result<()> {
-        self.map.serialize(dest)?;
-        self.next.serialize(dest)
-    }
+DeserializeStruct!(pmaplist, map, next);
+SerializeStruct!(pmaplist, map, next);
+
+/// Arguments to callit
+#[derive(Clone, Debug, Default)]
+pub struct call_args {
+    /// The RPC program number
+    pub prog: u32,
+    /// The RPC program version number
+    pub vers: u32,
+    /// The procedure number to call
+    pub proc: u32,
+    /// The encoded procedure arguments
+    pub args: Vec<u8>,
 }
+DeserializeStruct!(call_args, prog, vers, proc, args);
+SerializeStruct!(call_args, prog, vers, proc, args);
 
-impl Deserialize for pmaplist {
-    fn deserialize<R: Read>(&mut self, src: &mut R) -> std::io::Result<()> {
-        let mapping = deserialize::<mapping>(src)?;
-        self.map = mapping;
-        let has_next = deserialize::<bool>(src)?;
-        match has_next {
-            true => {
-                let pmaplist = deserialize::<pmaplist>(src)?;
-                self.next = Box::from(Some(pmaplist))
-            }
-            false => self.next = Box::from(None),
-        }
-        Ok(())
-    }
+/// Results of callit
+#[derive(Clone, Debug, Default)]
+pub struct call_result {
+    /// The port of the called program
+    pub port: u32,
+    /// The encoded procedure results
+    pub res: Vec<u8>,
 }
+DeserializeStruct!(call_result, port, res);
+SerializeStruct!(call_result, port, res);
 
-/// Protocol number for TCP/IP
-pub const IPPROTO_TCP: u32 = 6;
-/// Protocol number for UDP/IP
-pub const IPPROTO_UDP: u32 = 17;
 /// Portmap RPC program number
 pub const PROGRAM: u32 = 100000;
+
 /// Portmap RPC version number
 pub const VERSION: u32 = 2;
 
-/// Procedure numbers for the Portmap RPC service.
-#[allow(non_camel_case_types)]
+/// Procedure numbers of `PMAP_PROG` version 2
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Copy, Clone, Debug, FromPrimitive, ToPrimitive)]
 pub enum PortmapProgram {
@@ -50,13 +50,35 @@ pub enum auth_flavor {
     AUTH_SHORT = 2,
     /// DES authentication
     AUTH_DES = 3,
+    /// GSS-API security contexts (RFC 2203)
+    RPCSEC_GSS = 6,
+    /// Pseudo-flavor for `RPCSEC_GSS` with Kerberos V5 authentication, only
+    /// advertised in `MOUNT` replies (RFC 2623 section 2.2)
+    RPCSEC_GSS_KRB5 = 390003,
+    /// Pseudo-flavor for `RPCSEC_GSS` with Kerberos V5 integrity protection
+    RPCSEC_GSS_KRB5I = 390004,
+    /// Pseudo-flavor for `RPCSEC_GSS` with Kerberos V5 privacy protection
+    RPCSEC_GSS_KRB5P = 390005,
     /* and more to be defined */
 }
+
+impl auth_flavor {
+    /// Returns the flavor of the credentials calls of this flavor carry,
+    /// `RPCSEC_GSS` for its pseudo-flavors
+    pub fn credential_flavor(self) -> auth_flavor {
+        match self {
+            Self::RPCSEC_GSS_KRB5 | Self::RPCSEC_GSS_KRB5I | Self::RPCSEC_GSS_KRB5P => {
+                Self::RPCSEC_GSS
+            }
+            flavor => flavor,
+        }
+    }
+}
 impl SerializeEnum for auth_flavor {}
 impl DeserializeEnum for auth_flavor {}
 
 #[allow(non_camel_case_types)]
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Default)]
 /// UNIX-style credentials used for authentication
 pub struct auth_unix {
     /// Timestamp to prevent replay attacks
@@ -73,6 +95,24 @@ pub struct auth_unix {
 DeserializeStruct!(auth_unix, stamp, machinename, uid, gid, gids);
 SerializeStruct!(auth_unix, stamp, machinename, uid, gid, gids);
 
+/// The machine name, user and group IDs are redacted if the installed
+/// [`crate::redaction::RedactionPolicy`] hides IDs
+impl std::fmt::Debug for auth_unix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut debug = f.debug_struct("auth_unix");
+        debug.field("stamp", &self.stamp);
+        if crate::redaction::hides_ids() {
+            return debug.finish_non_exhaustive();
+        }
+        debug
+            .field("machinename", &String::from_utf8_lossy(&self.machinename))
+            .field("uid", &self.uid)
+            .field("gid", &self.gid)
+            .field("gids", &self.gids)
+            .finish()
+    }
+}
+
 /// Authentication data structure used in RPC protocol for both client and server authentication.
 ///
 /// The RPC protocol provides bidirectional authentication between caller and service:
@@ -461,6 +501,12 @@ pub fn rpc_vers_mismatch(xid: u32) -> rpc_msg {
     rpc_msg { xid, body: rpc_body::REPLY(reply) }
 }
 
+/// Creates a reply message denying the call because of its credentials
+pub fn auth_error_reply_message(xid: u32, stat: auth_stat) -> rpc_msg {
+    let reply = reply_body::MSG_DENIED(rejected_reply::AUTH_ERROR(stat));
+    rpc_msg { xid, body: rpc_body::REPLY(reply) }
+}
+
 /// Creates a successful reply message with no additional data
 pub fn make_success_reply(xid: u32) -> rpc_msg {
     let reply = reply_body::MSG_ACCEPTED(accepted_reply {
@@ -0,0 +1,177 @@
+//! Redaction of file names and user identities in logs.
+//!
+//! Logs of a server sharing exports between tenants tell every reader which
+//! files others keep and who works on them. Operators bound by privacy rules
+//! must not keep such logs, yet still need logs to find problems. A
+//! [`RedactionPolicy`] keeps the logs useful while leaving names and
+//! identities out:
+//!
+//! - file names, paths and symbolic link targets are shown, replaced by a
+//!   salted hash, or hidden, see [`NameRedaction`];
+//! - user and group IDs and machine names of callers are shown or hidden.
+//!
+//! The policy is process-wide, like the log subscriber, and installed with
+//! [`set_redaction_policy`], e.g. from the `redaction` section of a
+//! configuration file. It applies to the `Debug` and `Display` output of
+//! names ([`crate::xdr::nfs3::nfsstring`]), credentials
+//! ([`crate::xdr::rpc::auth_unix`]) and attributes, to the span of every call
+//! (see [`crate::vfs::request::RequestContext`]), and to the access log form
+//! of [`crate::protocol::nfs::events::Event`]. Hashes of the same name are
+//! equal, so the activity on one file can still be followed through the logs.
+//! Names are only redacted in what is logged; clients, backends and event
+//! subscribers see them unchanged.
+
+use std::fmt;
+use std::path::Path;
+use std::sync::RwLock;
+
+/// Placeholder for hidden values
+const HIDDEN: &str = "<redacted>";
+
+/// How names appear in logs
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum NameRedaction {
+    /// As they are
+    #[default]
+    Show,
+    /// As a salted hash, e.g. `#3a5f0c2e9d41b876`
+    Hash,
+    /// As `<redacted>`
+    Hide,
+}
+
+/// What is left out of logs
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default, deny_unknown_fields))]
+pub struct RedactionPolicy {
+    /// File names, paths and symbolic link targets
+    pub names: NameRedaction,
+    /// Whether user and group IDs and machine names are hidden
+    pub hide_ids: bool,
+    /// Secret mixed into the hashes of names, so that they cannot be compared
+    /// with hashes of guessed names
+    pub salt: String,
+}
+
+/// Policy installed with [`set_redaction_policy`]
+static POLICY: RwLock<Option<RedactionPolicy>> = RwLock::new(None);
+
+/// Installs the process-wide redaction policy
+///
+/// Applies to everything logged from now on. A later call replaces the
+/// policy. Without a policy nothing is redacted.
+pub fn set_redaction_policy(policy: RedactionPolicy) {
+    *POLICY.write().unwrap() = Some(policy);
+}
+
+/// Returns the installed redaction policy
+pub fn redaction_policy() -> RedactionPolicy {
+    POLICY.read().unwrap().clone().unwrap_or_default()
+}
+
+/// Returns a file name or path as it may be logged
+pub fn name(name: &[u8]) -> RedactedName<'_> {
+    RedactedName(name)
+}
+
+/// Returns a path of the host as it may be logged
+pub fn path(path: &Path) -> RedactedName<'_> {
+    RedactedName(path.as_os_str().as_encoded_bytes())
+}
+
+/// Returns a user or group ID as it may be logged
+pub fn id(id: u32) -> RedactedId {
+    RedactedId(id)
+}
+
+/// Returns whether user and group IDs are hidden
+pub fn hides_ids() -> bool {
+    POLICY.read().unwrap().as_ref().is_some_and(|policy| policy.hide_ids)
+}
+
+/// Name formatted according to the installed policy
+///
+/// Shown names are quoted, like the `Debug` output of strings.
+#[derive(Clone, Copy)]
+pub struct RedactedName<'a>(&'a [u8]);
+
+impl fmt::Display for RedactedName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let policy = POLICY.read().unwrap();
+        let Some(policy) = policy.as_ref() else {
+            return write!(f, "{:?}", String::from_utf8_lossy(self.0));
+        };
+        match policy.names {
+            NameRedaction::Show => write!(f, "{:?}", String::from_utf8_lossy(self.0)),
+            NameRedaction::Hash => write!(f, "#{:016x}", hash(policy.salt.as_bytes(), self.0)),
+            NameRedaction::Hide => f.write_str(HIDDEN),
+        }
+    }
+}
+
+impl fmt::Debug for RedactedName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// User or group ID formatted according to the installed policy
+#[derive(Clone, Copy)]
+pub struct RedactedId(u32);
+
+impl fmt::Display for RedactedId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if hides_ids() {
+            f.write_str(HIDDEN)
+        } else {
+            write!(f, "{}", self.0)
+        }
+    }
+}
+
+impl fmt::Debug for RedactedId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Returns the 64-bit FNV-1a hash of `name` salted with `salt`
+fn hash(salt: &[u8], name: &[u8]) -> u64 {
+    // the separator keeps salt "a" with name "bc" apart from salt "ab" with name "c"
+    let bytes = salt.iter().chain(&[0xff]).chain(name);
+    bytes.fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_names_and_ids_as_configured() {
+        assert_eq!(name(b"notes.txt").to_string(), "\"notes.txt\"");
+        assert_eq!(id(1000).to_string(), "1000");
+
+        let policy = RedactionPolicy {
+            names: NameRedaction::Hash,
+            hide_ids: true,
+            salt: "secret".to_string(),
+        };
+        set_redaction_policy(policy.clone());
+        let hashed = name(b"notes.txt").to_string();
+        assert!(hashed.starts_with('#') && hashed.len() == 17, "{hashed}");
+        assert_eq!(name(b"notes.txt").to_string(), hashed);
+        assert_ne!(name(b"notes.md").to_string(), hashed);
+        assert_eq!(format!("{:?}", id(1000)), HIDDEN);
+
+        set_redaction_policy(RedactionPolicy { salt: "other".to_string(), ..policy.clone() });
+        assert_ne!(name(b"notes.txt").to_string(), hashed);
+        set_redaction_policy(RedactionPolicy { names: NameRedaction::Hide, ..policy });
+        assert_eq!(path(Path::new("/srv/notes.txt")).to_string(), HIDDEN);
+
+        set_redaction_policy(RedactionPolicy::default());
+        assert_eq!(redaction_policy(), RedactionPolicy::default());
+    }
+}
@@ -8,34 +8,76 @@
 //!
 //! The implementation supports configurable export paths and notification
 //! on mount/unmount operations.
+//!
+//! A listener serves its file system as a trait object, so the backend can be
+//! chosen at runtime with [`NFSTcpListener::bind_dyn`] instead of fixing its
+//! type at compile time.
 
+use std::marker::PhantomData;
 use std::net::SocketAddr;
 use std::sync::{Arc, RwLock};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{io, net::IpAddr};
 
-use anyhow;
 use async_trait::async_trait;
+use futures::future::BoxFuture;
 use tokio::io::AsyncWriteExt;
-use tokio::net::TcpListener;
+use tokio::net::{TcpListener, UdpSocket};
 use tokio::sync::mpsc;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
+
+use crate::clock::{Clock, SystemClock};
+use crate::entropy::{Entropy, SystemEntropy};
+use crate::memory_budget::{MemoryBudget, MemoryConsumer};
+use crate::protocol::nfs::portmap::{PortmapTable, RegistrationPolicy};
+use crate::protocol::{nfs, rpc, xdr};
+use crate::vfs::{self, NFSFileSystem};
+use crate::write_counter::SpaceCounter;
+
+/// File system served by a listener, with its type erased
+pub type DynNFSFileSystem = dyn NFSFileSystem + Send + Sync;
+
+/// Places the tasks serving TCP connections, see
+/// [`NFSTcpListener::with_spawner`]
+///
+/// Implemented for closures taking the same arguments as [`Self::spawn`].
+pub trait ConnectionSpawner: Send + Sync {
+    /// Runs `task` to completion, e.g. on a runtime whose worker threads are
+    /// pinned to a set of cores
+    ///
+    /// # Arguments
+    ///
+    /// * `peer` - Address of the client the connection comes from
+    /// * `task` - Serves the connection until it is closed
+    fn spawn(&self, peer: SocketAddr, task: BoxFuture<'static, ()>);
+}
 
-use crate::protocol::nfs::portmap::PortmapTable;
-use crate::protocol::{rpc, xdr};
-use crate::vfs::NFSFileSystem;
+impl<F: Fn(SocketAddr, BoxFuture<'static, ()>) + Send + Sync> ConnectionSpawner for F {
+    fn spawn(&self, peer: SocketAddr, task: BoxFuture<'static, ()>) {
+        self(peer, task)
+    }
+}
 
 /// NFS TCP Connection Handler that listens for incoming NFS client connections
 /// and processes RPC messages over TCP transport.
-pub struct NFSTcpListener<T: NFSFileSystem + Send + Sync + 'static> {
+///
+/// `T` only records the type the listener was bound with; the file system is
+/// always held as a [`DynNFSFileSystem`], so the connection handling code is
+/// shared by all backends. Listeners created with [`NFSTcpListener::bind_dyn`]
+/// have the default type `NFSTcpListener<DynNFSFileSystem>`.
+pub struct NFSTcpListener<T: NFSFileSystem + Send + Sync + ?Sized + 'static = DynNFSFileSystem> {
     /// TCP Listener for accepting incoming connections
     listener: TcpListener,
     /// Port on which the server is listening
     port: u16,
     /// Arc reference to the NFS file system implementation
-    arcfs: Arc<T>,
+    arcfs: Arc<DynNFSFileSystem>,
+    /// Type the listener was bound with
+    backend: PhantomData<fn() -> Arc<T>>,
     /// Optional channel for sending mount/unmount notifications
     mount_signal: Option<mpsc::Sender<bool>>,
+    /// Mounts held by clients
+    mount_table: Arc<nfs::mount_table::MountTable>,
     /// Name of the exported file system path
     export_name: Arc<String>,
     /// Tracker for RPC transactions to handle retransmissions
@@ -43,8 +85,93 @@ pub struct NFSTcpListener<T: NFSFileSystem + Send + Sync + 'static> {
     /// Portmap table storing port-to-program mappings
     /// (like a portmap service)
     portmap_table: Arc<RwLock<PortmapTable>>,
+    /// Coalescer for `UNSTABLE` writes, if enabled
+    write_coalescer: Option<Arc<nfs::v3::WriteCoalescer>>,
+    /// Limit on the memory of the caches, if set
+    memory_budget: Option<Arc<MemoryBudget>>,
+    /// Ranges written `UNSTABLE` and not committed yet
+    unstable_writes: Arc<nfs::v3::UnstableWrites>,
+    space_counter: Option<Arc<SpaceCounter>>,
+    /// Deferred removal of files in use, if enabled
+    soft_delete: Option<Arc<nfs::v3::SoftDelete>>,
+    /// Checksums of the data written, if enabled
+    checksums: Option<Arc<nfs::v3::Checksums>>,
+    /// Inspection of the content written, if enabled
+    content_inspection: Option<Arc<nfs::v3::ContentInspection>>,
+    /// Channel publishing the activity of clients, if enabled
+    events: Option<Arc<nfs::events::Events>>,
+    /// Recorder of the traffic of connections, if enabled
+    capture: Option<Arc<rpc::Capture>>,
+    /// Places connection tasks, on the current runtime if unset
+    spawner: Option<Arc<dyn ConnectionSpawner>>,
+    /// Detector of sequential reads for read-ahead hints, if enabled
+    read_ahead: Option<Arc<nfs::v3::ReadAheadDetector>>,
+    /// Cache of names found missing by `LOOKUP`, if enabled
+    negative_lookups: Option<Arc<nfs::v3::NegativeLookupCache>>,
+    /// Cache of `READ` results, if enabled
+    read_cache: Option<Arc<nfs::v3::ReadCache>>,
+    readdirplus_limits: Arc<nfs::v3::ReadDirPlusLimits>,
+    /// Whether `READDIRPLUS` is served
+    readdirplus: bool,
+    /// Maxima of file sizes and directory entries
+    export_limits: nfs::v3::ExportLimits,
+    creation_defaults: Option<nfs::v3::CreationDefaults>,
+    dir_locks: Option<Arc<nfs::v3::DirLocks>>,
+    /// Whether file handles are bound to the export
+    subtree_check: bool,
+    /// Secret key of the export tags of file handles
+    handle_key: [u64; 2],
+    /// Whether the public file handle of WebNFS is recognized
+    public_fh: bool,
+    /// End of the grace period for stale handles, if enabled
+    handle_grace_until: Option<Instant>,
+    /// How long clients wait for a reply, passed to backends as a deadline
+    request_timeout: Option<Duration>,
+    /// Longest record accepted from clients
+    max_record_length: usize,
+    /// Statistics shared by all connections
+    stats: Arc<rpc::ServerStats>,
+    /// Scheduler of the calls of all connections, if enabled
+    scheduler: Option<Arc<rpc::Scheduler>>,
+    /// Concurrency limits per class of procedures, if any
+    priorities: Option<Arc<rpc::PriorityLimits>>,
+    /// Credential flavors accepted per RPC program
+    auth_policy: Arc<rpc::AuthPolicy>,
+    /// Short-hand credentials handed out to clients, if enabled
+    short_auth: Option<Arc<rpc::ShortAuth>>,
+    /// Mapping of client identities to the anonymous user
+    squash: rpc::Squash,
+    /// Interoperability workarounds per client network
+    quirk_policy: Arc<rpc::QuirkPolicy>,
+    /// Chunk size of streamed `READ` replies, if enabled
+    read_stream_chunk: Option<u32>,
+    /// Limits of reply compression, if offered to clients
+    compression: Option<rpc::CompressionConfig>,
+    /// Hooks run around the dispatch of every call
+    hooks: Arc<rpc::DispatchHooks>,
+    /// Names hidden from clients, if any
+    name_filter: Option<Arc<nfs::v3::NameFilter>>,
+    /// Mapping of backend errors to status codes, if any
+    error_mapper: Option<vfs::ErrorMapper>,
+    /// Source of the current time
+    clock: Arc<dyn Clock>,
+    /// Source of the random values handed out
+    entropy: Arc<dyn Entropy>,
+    /// Sockets of the built-in portmapper, if bound
+    portmapper: Option<Portmapper>,
 }
 
+/// Sockets of the portmapper a listener serves besides its own port
+struct Portmapper {
+    /// Port both sockets are bound to
+    port: u16,
+    tcp: TcpListener,
+    udp: UdpSocket,
+}
+
+/// Largest datagram the portmapper accepts
+const MAX_DATAGRAM_SIZE: usize = 65536;
+
 /// Generates a local loopback IP address from a 16-bit host number
 /// Used for creating multiple local test addresses in the 127.88.x.y range
 pub fn generate_host_ip(hostnum: u16) -> String {
@@ -63,10 +190,12 @@ pub fn generate_host_ip(hostnum: u16) -> String {
 ///
 /// * `socket` - The established TCP connection to the client
 /// * `context` - RPC context containing server state and client information
+/// * `tap` - Recorder of the traffic, if the listener captures it
 async fn process_socket(
     mut socket: tokio::net::TcpStream,
     context: rpc::Context,
-) -> Result<(), anyhow::Error> {
+    mut tap: Option<rpc::Tap>,
+) -> Result<(), rpc::ServerError> {
     let (mut message_handler, mut socksend, mut msgrecvchan) =
         rpc::SocketMessageHandler::new(&context);
     let _ = socket.set_nodelay(true);
@@ -89,6 +218,9 @@ async fn process_socket(
                         return Ok(());
                     }
                     Ok(n) => {
+                        if let Some(tap) = &mut tap {
+                            tap.record(rpc::Direction::Call, &buf[..n]);
+                        }
                         let _ = socksend.write_all(&buf[..n]).await;
                     }
                     Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
@@ -96,7 +228,7 @@ async fn process_socket(
                     }
                     Err(e) => {
                         debug!("Message handling closed : {:?}", e);
-                        return Err(e.into());
+                        return Err(rpc::ServerError::Io(e));
                     }
                 }
 
@@ -107,13 +239,29 @@ async fn process_socket(
                         debug!("Message handling closed : {:?}", e);
                         return Err(e);
                     }
-                    Some(Ok(msg)) => {
-                        if let Err(e) = rpc::write_fragment(&mut socket, &msg).await {
+                    Some(Ok(mut reply)) => {
+                        let streamed = reply.body.is_some();
+                        if let Some(compression) = context.compression.as_ref().filter(|_| !streamed) {
+                            reply.data = compression.compress(std::mem::take(&mut reply.data));
+                        }
+                        let written = match &mut tap {
+                            Some(tap) => {
+                                let mut writer = rpc::TapWriter { inner: &mut socket, tap };
+                                rpc::write_reply(&mut writer, reply).await
+                            }
+                            None => rpc::write_reply(&mut socket, reply).await,
+                        };
+                        if let Err(e) = written {
                             error!("Write error {:?}", e);
+                            if streamed {
+                                // the record was cut short, the stream cannot be resynchronized
+                                return Err(e);
+                            }
                         }
                     }
                     None => {
-                        return Err(anyhow::anyhow!("Unexpected socket context termination"));
+                        let message = "Unexpected socket context termination".to_string();
+                        return Err(rpc::ServerError::Internal(message));
                     }
                 }
             }
@@ -179,13 +327,40 @@ impl<T: NFSFileSystem + Send + Sync + 'static> NFSTcpListener<T> {
     ///
     /// A Result containing either the new [`NFSTcpListener`] or an IO error
     pub async fn bind(ipstr: &str, fs: T) -> io::Result<NFSTcpListener<T>> {
+        NFSTcpListener::bind_arc(ipstr, Arc::new(fs)).await
+    }
+}
+
+impl NFSTcpListener {
+    /// Creates a new NFS TCP listener for a file system chosen at runtime
+    ///
+    /// Works like [`NFSTcpListener::bind`], but takes the file system as a trait
+    /// object, e.g. one picked from a configuration file, or shared with other
+    /// listeners.
+    ///
+    /// # Arguments
+    ///
+    /// * `ipstr` - IP address and port in the format "IP:PORT" (e.g. "127.0.0.1:2049")
+    ///   Special value "auto:PORT" attempts to find an available local address
+    /// * `fs` - The file system that will handle NFS operations
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either the new [`NFSTcpListener`] or an IO error
+    pub async fn bind_dyn(ipstr: &str, fs: Arc<DynNFSFileSystem>) -> io::Result<NFSTcpListener> {
+        NFSTcpListener::bind_arc(ipstr, fs).await
+    }
+}
+
+impl<T: NFSFileSystem + Send + Sync + ?Sized + 'static> NFSTcpListener<T> {
+    /// Binds to `ipstr`, trying local addresses for "auto"
+    async fn bind_arc(ipstr: &str, arcfs: Arc<DynNFSFileSystem>) -> io::Result<NFSTcpListener<T>> {
         let (ip, port) = ipstr.split_once(':').ok_or_else(|| {
             io::Error::new(io::ErrorKind::AddrNotAvailable, "IP Address must be of form ip:port")
         })?;
         let port = port.parse::<u16>().map_err(|_| {
             io::Error::new(io::ErrorKind::AddrNotAvailable, "Port not in range 0..=65535")
         })?;
-        let arcfs: Arc<T> = Arc::new(fs);
 
         if ip != "auto" {
             return NFSTcpListener::bind_internal(ip, port, arcfs).await;
@@ -211,7 +386,11 @@ impl<T: NFSFileSystem + Send + Sync + 'static> NFSTcpListener<T> {
     /// * `ip` - IP address to bind to
     /// * `port` - Port number to bind to
     /// * `arcfs` - Arc reference to the NFS file system implementation
-    async fn bind_internal(ip: &str, port: u16, arcfs: Arc<T>) -> io::Result<NFSTcpListener<T>> {
+    async fn bind_internal(
+        ip: &str,
+        port: u16,
+        arcfs: Arc<DynNFSFileSystem>,
+    ) -> io::Result<NFSTcpListener<T>> {
         let ipstr = format!("{ip}:{port}");
         let listener = TcpListener::bind(&ipstr).await?;
         info!("Listening on {:?}", &ipstr);
@@ -220,14 +399,56 @@ impl<T: NFSFileSystem + Send + Sync + 'static> NFSTcpListener<T> {
             SocketAddr::V4(s) => s.port(),
             SocketAddr::V6(s) => s.port(),
         };
+        let entropy: Arc<dyn Entropy> = Arc::new(SystemEntropy::default());
         Ok(NFSTcpListener {
             listener,
             port,
             arcfs,
+            backend: PhantomData,
             mount_signal: None,
+            mount_table: Arc::default(),
             export_name: Arc::from("/".to_string()),
             transaction_tracker: Arc::new(rpc::TransactionTracker::new(Duration::from_secs(60))),
             portmap_table: Arc::from(RwLock::from(PortmapTable::default())),
+            write_coalescer: None,
+            memory_budget: None,
+            unstable_writes: Arc::default(),
+            space_counter: None,
+            soft_delete: None,
+            checksums: None,
+            content_inspection: None,
+            events: None,
+            capture: None,
+            spawner: None,
+            read_ahead: None,
+            negative_lookups: None,
+            read_cache: None,
+            readdirplus_limits: Arc::default(),
+            readdirplus: true,
+            export_limits: nfs::v3::ExportLimits::default(),
+            creation_defaults: None,
+            dir_locks: None,
+            subtree_check: false,
+            handle_key: [entropy.next_u64(), entropy.next_u64()],
+            public_fh: false,
+            handle_grace_until: None,
+            request_timeout: None,
+            max_record_length: rpc::DEFAULT_MAX_RECORD_LENGTH,
+            stats: Arc::default(),
+            scheduler: None,
+            priorities: None,
+            auth_policy: Arc::default(),
+            short_auth: None,
+            squash: rpc::Squash::default(),
+            quirk_policy: Arc::default(),
+            read_stream_chunk: None,
+            compression: None,
+            hooks: Arc::default(),
+            name_filter: None,
+            error_mapper: None,
+            clock: Arc::new(SystemClock),
+            entropy,
+            portmapper: None,
         })
     }
 
@@ -246,10 +467,878 @@ impl<T: NFSFileSystem + Send + Sync + 'static> NFSTcpListener<T> {
             export_name.as_ref().trim_end_matches('/').trim_start_matches('/')
         ));
     }
+
+    /// Enables coalescing of sequential `UNSTABLE` writes.
+    ///
+    /// Adjacent and overlapping `UNSTABLE` writes to the same file are merged
+    /// before they reach the file system, which reduces backend write
+    /// amplification for clients that send many small writes. Merged data is
+    /// written out no later than the client's `COMMIT` of the file.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: Limits on how much data is held back and for how long.
+    pub fn with_write_coalescing(&mut self, config: nfs::v3::WriteCoalescerConfig) {
+        let coalescer = Arc::new(nfs::v3::WriteCoalescer::new(config));
+        self.register_memory("write_coalescer", &coalescer);
+        self.write_coalescer = Some(coalescer);
+    }
+
+    /// Limits the memory all caches of this listener hold together.
+    ///
+    /// The negative lookup cache, the read cache, the write coalescer, the
+    /// ranges of uncommitted writes and the duplicate request caches of UDP
+    /// sockets are charged to `budget`, and evict entries or write out
+    /// buffered data once it is exceeded. The budget can be shared with other
+    /// listeners and with caches of the backend, e.g. an
+    /// [`crate::vfs::attr_cache::AttrCache`] registered with
+    /// [`MemoryBudget::register`]. Caches are only limited by their own
+    /// capacities by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `budget`: Limit shared by the caches.
+    pub fn with_memory_budget(&mut self, budget: Arc<MemoryBudget>) {
+        self.memory_budget = Some(budget);
+        if let Some(cache) = self.negative_lookups.clone() {
+            self.register_memory("negative_lookups", &cache);
+        }
+        if let Some(cache) = self.read_cache.clone() {
+            self.register_memory("read_cache", &cache);
+        }
+        if let Some(coalescer) = self.write_coalescer.clone() {
+            self.register_memory("write_coalescer", &coalescer);
+        }
+        self.register_memory("unstable_writes", &self.unstable_writes);
+    }
+
+    /// Returns the limit on the memory of the caches, if set.
+    pub fn memory_budget(&self) -> Option<Arc<MemoryBudget>> {
+        self.memory_budget.clone()
+    }
+
+    /// Charges the memory of a cache to the budget, if one is set
+    fn register_memory<C: MemoryConsumer + 'static>(&self, name: &'static str, cache: &Arc<C>) {
+        if let Some(budget) = &self.memory_budget {
+            budget.register(name, cache);
+        }
+    }
+
+    /// Enables accounting of the space used through the server.
+    ///
+    /// For file systems whose [`NFSFileSystem::fsstat`] is not implemented,
+    /// `FSSTAT` then reports totals derived from the bytes and files written
+    /// and freed by clients instead of fixed values.
+    ///
+    /// # Arguments
+    ///
+    /// * `counter`: Capacity and starting usage, see [`SpaceCounter`].
+    pub fn with_space_accounting(&mut self, counter: SpaceCounter) {
+        self.space_counter = Some(Arc::new(counter));
+    }
+
+    /// Defers the removal of files in use.
+    ///
+    /// A `REMOVE` of a file that saw I/O recently renames it to a hidden name
+    /// instead, so clients still holding it open can keep using it, as they
+    /// could on a local file system. Hidden files are removed once idle.
+    /// Disabled by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: When a file counts as in use, or `None` to remove files
+    ///   right away.
+    pub fn with_soft_delete(&mut self, config: Option<nfs::v3::SoftDeleteConfig>) {
+        self.soft_delete = config.map(|config| Arc::new(nfs::v3::SoftDelete::new(config)));
+    }
+
+    /// Checksums the data written and read, to detect corruption by the backend.
+    ///
+    /// The CRC-32C of the data of every `WRITE` is logged and remembered, and
+    /// `READ` replies covering a remembered range are checked against it, with
+    /// mismatches logged as errors. Disabled by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: How many written ranges to remember, or `None` to disable
+    ///   checksumming.
+    pub fn with_checksums(&mut self, config: Option<nfs::v3::ChecksumConfig>) {
+        self.checksums = config.map(|config| Arc::new(nfs::v3::Checksums::new(config)));
+    }
+
+    /// Returns the checksums of the data written, if enabled with
+    /// [`Self::with_checksums`]
+    pub fn checksums(&self) -> Option<Arc<nfs::v3::Checksums>> {
+        self.checksums.clone()
+    }
+
+    /// Has the content clients write inspected, e.g. by a virus scanner.
+    ///
+    /// The inspector sees the data of every `WRITE` before the file system
+    /// does, and every file after a `COMMIT`. Calls it objects to fail with
+    /// `NFS3ERR_ACCES`, see [`nfs::v3::ContentInspection`].
+    ///
+    /// # Arguments
+    ///
+    /// * `inspector`: Decides which content is accepted.
+    pub fn with_content_inspector(&mut self, inspector: impl nfs::v3::ContentInspector + 'static) {
+        self.content_inspection = Some(Arc::new(nfs::v3::ContentInspection::new(inspector)));
+    }
+
+    /// Returns the inspection of the content written, if enabled with
+    /// [`Self::with_content_inspector`], e.g. to release quarantined files
+    pub fn content_inspection(&self) -> Option<Arc<nfs::v3::ContentInspection>> {
+        self.content_inspection.clone()
+    }
+
+    /// Shares the processing of calls fairly between connections.
+    ///
+    /// At most as many calls as the scheduler allows are processed at once;
+    /// further calls wait and are let through in weighted round-robin order
+    /// of their connections, see [`rpc::Scheduler`]. Calls are processed as
+    /// soon as they arrive by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `scheduler`: Limit on concurrent calls and weights of clients.
+    pub fn with_scheduler(&mut self, scheduler: rpc::Scheduler) {
+        self.scheduler = Some(Arc::new(scheduler));
+    }
+
+    /// Limits the calls processed at once per class of procedures.
+    ///
+    /// Bounding the data transfer classes keeps streams of writes from
+    /// starving interactive metadata calls, see [`rpc::PriorityLimits`].
+    ///
+    /// # Arguments
+    ///
+    /// * `limits`: Limits per class and the classes of procedures.
+    pub fn with_priority_limits(&mut self, limits: rpc::PriorityLimits) {
+        self.priorities = Some(Arc::new(limits));
+    }
+
+    /// Hands out `AUTH_SHORT` handles for `AUTH_UNIX` credentials.
+    ///
+    /// Clients supporting them send the short handle instead of their full
+    /// credentials on later calls, see [`rpc::ShortAuth`].
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity`: Number of credentials remembered, or `None` to hand out
+    ///   no handles.
+    pub fn with_short_auth(&mut self, capacity: Option<usize>) {
+        self.short_auth = capacity.map(|capacity| Arc::new(rpc::ShortAuth::new(capacity)));
+    }
+
+    /// Publishes the changes and mounts of clients to subscribers.
+    ///
+    /// Subscribe with [`Self::events`], see [`nfs::events`] for the events
+    /// published.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity`: Number of events buffered per subscriber, or `None` to
+    ///   publish none.
+    pub fn with_events(&mut self, capacity: Option<usize>) {
+        self.events = capacity.map(|capacity| Arc::new(nfs::events::Events::new(capacity)));
+    }
+
+    /// Returns the channel publishing the activity of clients, if enabled with
+    /// [`Self::with_events`]
+    pub fn events(&self) -> Option<Arc<nfs::events::Events>> {
+        self.events.clone()
+    }
+
+    /// Drops the mounts of clients that vanished without unmounting.
+    ///
+    /// Mounts of clients that made no call for the idle timeout are removed
+    /// from the list `MOUNTPROC3_DUMP` returns, and reported to the mount
+    /// listener and subscribers of [`Self::events`] as if unmounted. Mounts
+    /// are only removed by unmounting by default, see [`nfs::mount_table`].
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: Idle timeout and check interval, or `None` to keep mounts
+    ///   until unmounted.
+    pub fn with_mount_liveness(&mut self, config: Option<nfs::mount_table::MountLivenessConfig>) {
+        self.mount_table = Arc::new(nfs::mount_table::MountTable::new(config));
+    }
+
+    /// Returns the mounts clients hold
+    pub fn mount_table(&self) -> Arc<nfs::mount_table::MountTable> {
+        self.mount_table.clone()
+    }
+
+    /// Records the traffic of TCP connections for debugging.
+    ///
+    /// Keep a clone of `capture` to switch recording on and off while the
+    /// server runs, see [`rpc::Capture::set_client_enabled`].
+    ///
+    /// # Arguments
+    ///
+    /// * `capture`: Where and in which form the traffic is recorded.
+    pub fn with_capture(&mut self, capture: Arc<rpc::Capture>) {
+        self.capture = Some(capture);
+    }
+
+    /// Hands the tasks serving connections to `spawner`.
+    ///
+    /// High-throughput deployments can run every connection on a runtime, or
+    /// a worker thread, pinned to the cores close to its network queue or
+    /// memory, so the buffers of its `READ` and `WRITE` calls stay in local
+    /// caches. The socket is moved to the runtime the task is polled on, and
+    /// the further tasks of the connection are spawned there too. Connections
+    /// are served on the runtime accepting them by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `spawner`: Runs the task of every accepted connection.
+    pub fn with_spawner(&mut self, spawner: impl ConnectionSpawner + 'static) {
+        self.spawner = Some(Arc::new(spawner));
+    }
+
+    /// Configures read-ahead hints.
+    ///
+    /// When a client reads a file sequentially, the server calls
+    /// [`NFSFileSystem::readahead`] with the range it expects to be read next.
+    /// Hints are disabled by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: Window limits, or `None` to disable the hints.
+    pub fn with_read_ahead(&mut self, config: Option<nfs::v3::ReadAheadConfig>) {
+        self.read_ahead = config.map(|config| Arc::new(nfs::v3::ReadAheadDetector::new(config)));
+    }
+
+    /// Caches names `LOOKUP` finds missing.
+    ///
+    /// Repeated lookups of a missing name, as made by compilers searching
+    /// include paths, are answered without calling [`NFSFileSystem::lookup`]
+    /// for the time to live. Names added through this listener are visible at
+    /// once; names added to the backend by other means may stay hidden for
+    /// up to the time to live. The cache is disabled by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: Time to live and capacity, or `None` to disable the cache.
+    pub fn with_negative_lookup_cache(&mut self, config: Option<nfs::v3::NegativeLookupConfig>) {
+        self.negative_lookups =
+            config.map(|config| Arc::new(nfs::v3::NegativeLookupCache::new(config)));
+        if let Some(cache) = self.negative_lookups.clone() {
+            self.register_memory("negative_lookups", &cache);
+        }
+    }
+
+    /// Caches `READ` results in memory.
+    ///
+    /// Reads of files [`NFSFileSystem::is_immutable`] reports as immutable are
+    /// answered from memory once read, without calling
+    /// [`NFSFileSystem::read`]. With [`nfs::v3::ReadCacheConfig::all_files`],
+    /// other files are cached until written or their attributes are set
+    /// through this listener, which is only correct if nothing else modifies
+    /// the backend. The cache is disabled by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: Capacity and the files cached, or `None` to disable the cache.
+    pub fn with_read_cache(&mut self, config: Option<nfs::v3::ReadCacheConfig>) {
+        self.read_cache = config.map(|config| Arc::new(nfs::v3::ReadCache::new(config)));
+        if let Some(cache) = self.read_cache.clone() {
+            self.register_memory("read_cache", &cache);
+        }
+    }
+
+    /// Limits the sizes `READDIRPLUS` calls may request.
+    ///
+    /// `dircount` and `maxcount` of every call are clamped to these maxima,
+    /// and `dircount` also to the `dtpref` announced in `FSINFO`, so a client
+    /// cannot make the server fetch attributes of more entries than a reply
+    /// can carry. Defaults to 64KB and 1MB.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_dircount`: Largest `dircount` honored.
+    /// * `max_maxcount`: Largest `maxcount` honored.
+    pub fn with_readdirplus_limits(&mut self, max_dircount: u32, max_maxcount: u32) {
+        self.readdirplus_limits =
+            Arc::new(nfs::v3::ReadDirPlusLimits::new(max_dircount, max_maxcount));
+    }
+
+    /// Enables or disables `READDIRPLUS`.
+    ///
+    /// When disabled, `READDIRPLUS` calls are refused with `NFS3ERR_NOTSUPP`
+    /// and clients list directories with `READDIR` followed by a `GETATTR` or
+    /// `LOOKUP` per entry they need. That is more calls, but backends with
+    /// expensive attribute lookups are only asked for the attributes clients
+    /// actually use. Enabled by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled`: Whether `READDIRPLUS` is served.
+    pub fn with_readdirplus(&mut self, enabled: bool) {
+        self.readdirplus = enabled;
+    }
+
+    /// Limits the size of files and the number of directory entries clients
+    /// can create.
+    ///
+    /// Calls growing a file beyond the maximum size fail with `NFS3ERR_FBIG`,
+    /// calls adding a name to a full directory with `NFS3ERR_NOSPC`, and calls
+    /// creating a file in a full export with `NFS3ERR_DQUOT`, before the file
+    /// system is asked. Unlimited by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `limits`: Maxima of the export, see [`nfs::v3::ExportLimits`].
+    pub fn with_export_limits(&mut self, limits: nfs::v3::ExportLimits) {
+        self.export_limits = limits;
+    }
+
+    /// Fills in the mode and owner of new files the client sends none for.
+    ///
+    /// `CREATE`, `MKDIR`, `SYMLINK` and `MKNOD` calls get the default mode
+    /// and owner before the file system is asked, so new files look the same
+    /// whatever the file system would have chosen. Left to the file system by
+    /// default.
+    ///
+    /// # Arguments
+    ///
+    /// * `defaults`: Modes, umask and owner, see [`nfs::v3::CreationDefaults`],
+    ///   or `None` to leave them to the file system.
+    pub fn with_creation_defaults(&mut self, defaults: Option<nfs::v3::CreationDefaults>) {
+        self.creation_defaults = defaults;
+    }
+
+    /// Serializes changes of the same directory.
+    ///
+    /// `CREATE`, `MKDIR`, `SYMLINK`, `MKNOD`, `LINK`, `REMOVE`, `RMDIR` and
+    /// `RENAME` calls changing a directory wait for other changes of it to
+    /// complete, so file systems without internal locking can keep their
+    /// namespace consistent under concurrent clients. Disabled by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled`: Whether changes of the same directory are serialized.
+    pub fn with_directory_locks(&mut self, enabled: bool) {
+        self.dir_locks = enabled.then(|| Arc::new(nfs::v3::DirLocks::default()));
+    }
+
+    /// Configures streaming of large `READ` replies.
+    ///
+    /// Reads larger than `chunk_size` are sent as a series of record fragments,
+    /// each read from the file system just before it is written to the socket,
+    /// so a client's `rtmax` can be raised to 1MB and beyond without buffering
+    /// whole replies. Streaming is disabled by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `chunk_size`: Bytes read and sent at a time, or `None` to disable streaming.
+    pub fn with_read_streaming(&mut self, chunk_size: Option<u32>) {
+        self.read_stream_chunk = chunk_size;
+    }
+
+    /// Offers compression of replies to clients of this crate.
+    ///
+    /// Clients that negotiate it, like [`crate::client::Client`] after
+    /// [`crate::client::Client::negotiate_compression`], receive large
+    /// replies compressed. Other clients are not affected. Streamed `READ`
+    /// replies are never compressed. Compression is not offered by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: Limits of compression, or `None` to offer none.
+    pub fn with_reply_compression(&mut self, config: Option<rpc::CompressionConfig>) {
+        self.compression = config;
+    }
+
+    /// Hides entries from clients by name.
+    ///
+    /// Entries matching the filter are left out of directory listings and
+    /// cannot be looked up, whatever the file system returns, so e.g.
+    /// `.snapshot` or `lost+found` can be kept from clients of this export.
+    ///
+    /// # Arguments
+    ///
+    /// * `filter`: Names to hide, or `None` to show all entries.
+    pub fn with_name_filter(&mut self, filter: Option<nfs::v3::NameFilter>) {
+        self.name_filter = filter.map(Arc::new);
+    }
+
+    /// Maps errors of the backend to the status codes reported to clients.
+    ///
+    /// [`vfs::NfsError::from_error`] and [`crate::fs_util::nfsstat_from_io_error`]
+    /// call `mapper` with errors raised while serving calls of this listener,
+    /// before the built-in conversions, so applications can give their own
+    /// error types precise codes or log the original error. Returning `None`
+    /// falls back to the built-in conversions, which also apply to work done
+    /// outside of calls, such as flushing coalesced writes in the background.
+    ///
+    /// # Arguments
+    ///
+    /// * `mapper`: Returns the status code for an error, or `None` to defer.
+    pub fn with_error_mapper(
+        &mut self,
+        mapper: impl Fn(&(dyn std::error::Error + 'static)) -> Option<xdr::nfs3::nfsstat3>
+            + Send
+            + Sync
+            + 'static,
+    ) {
+        self.error_mapper = Some(vfs::ErrorMapper::new(mapper));
+    }
+
+    /// Sets the source of the current time used by the handlers.
+    ///
+    /// The system clock is used by default. A [`crate::clock::ManualClock`]
+    /// makes the times set by `SET_TO_SERVER_TIME` predictable in tests.
+    ///
+    /// # Arguments
+    ///
+    /// * `clock`: Clock shared by all connections.
+    pub fn with_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Sets the source of the random values handed out by the handlers.
+    ///
+    /// `AUTH_SHORT` handles and the trace IDs of calls are drawn from it. They
+    /// differ between runs by default. A [`crate::entropy::SeededEntropy`]
+    /// makes them, and so the replies, the same on every run.
+    ///
+    /// # Arguments
+    ///
+    /// * `entropy`: Entropy shared by all connections.
+    pub fn with_entropy(&mut self, entropy: Arc<dyn Entropy>) {
+        self.entropy = entropy;
+    }
+
+    /// Enables `subtree_check`-style validation of file handles.
+    ///
+    /// Handles then carry a tag of the export they were issued under, a keyed
+    /// hash of the handle and export name, see [`rpc::Context::export_tag`].
+    /// Handles issued by a listener with a different export name or key, and
+    /// handles whose tag was not computed with the key, are rejected with
+    /// NFS3ERR_STALE. This keeps clients of one export from reaching files of
+    /// another when several listeners share a backend, even clients that
+    /// craft handles. Handles issued before the option was changed become
+    /// invalid.
+    ///
+    /// The key is drawn from the entropy of the listener when it is bound, so
+    /// handles become stale when the server restarts unless a key is kept
+    /// with [`NFSTcpListener::with_handle_key`].
+    ///
+    /// The tag takes 8 bytes of the 64 a handle may have, so the backend's
+    /// handles must not exceed 56 bytes; files with longer handles are
+    /// answered with NFS3ERR_SERVERFAULT instead of an oversized handle.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled`: Whether to bind handles to the export.
+    pub fn with_subtree_check(&mut self, enabled: bool) {
+        self.subtree_check = enabled;
+    }
+
+    /// Sets the secret key of the export tags of file handles.
+    ///
+    /// A key kept secret and stable across restarts, e.g. read from a file
+    /// only the server can read, keeps the handles of clients valid when the
+    /// server restarts with subtree checking enabled. Listeners sharing a key
+    /// still reject each other's handles if their export names differ.
+    ///
+    /// # Arguments
+    ///
+    /// * `key`: Secret key of 128 bits.
+    pub fn with_handle_key(&mut self, key: [u64; 2]) {
+        self.handle_key = key;
+    }
+
+    /// Lets WebNFS clients browse the export without the `MOUNT` protocol.
+    ///
+    /// The zero-length public file handle of RFC 2054 then stands for the root
+    /// of the export, and `LOOKUP` in it accepts paths of several components
+    /// separated by `/`, so a client can reach any file with a single call.
+    /// Access restrictions of the export apply as they do to mounted clients.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled`: Whether to recognize the public file handle.
+    pub fn with_public_filehandle(&mut self, enabled: bool) {
+        self.public_fh = enabled;
+    }
+
+    /// Enables a grace period for handles issued before a restart.
+    ///
+    /// For `period` from now, handles the file system rejects as stale, such
+    /// as those of an older generation, are passed to
+    /// [`NFSFileSystem::rehydrate_handle`] instead of failing with
+    /// NFS3ERR_STALE right away. Backends whose files survive a restart can
+    /// map them back to file IDs and keep mounted clients working.
+    ///
+    /// # Arguments
+    ///
+    /// * `period`: How long stale handles are rehydrated, counted from this call.
+    pub fn with_handle_grace(&mut self, period: Duration) {
+        self.handle_grace_until = Some(Instant::now() + period);
+    }
+
+    /// Gives every call a deadline `timeout` after its arrival.
+    ///
+    /// Backends read the deadline from the
+    /// [`crate::vfs::request::RequestContext`] of the call, to stop waiting on
+    /// other services once the client would no longer use the reply. Set it to
+    /// the timeout of the clients, e.g. `timeo` of Linux mounts. Disabled by
+    /// default.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout`: How long clients wait for a reply, or `None` for no deadline.
+    pub fn with_request_timeout(&mut self, timeout: Option<Duration>) {
+        self.request_timeout = timeout;
+    }
+
+    /// Sets the longest record accepted from clients.
+    ///
+    /// Longer calls are answered with `GARBAGE_ARGS`, their excess data is
+    /// discarded as it arrives, so a client cannot make the server buffer
+    /// arbitrarily large records. `FSINFO` caps the announced transfer sizes to
+    /// fit into such a record together with its headers. Defaults to
+    /// [`rpc::DEFAULT_MAX_RECORD_LENGTH`].
+    ///
+    /// # Arguments
+    ///
+    /// * `length`: Longest record in bytes, including the RPC header.
+    pub fn with_max_record_length(&mut self, length: usize) {
+        self.max_record_length = length;
+    }
+
+    /// Reports calls that take longer than `threshold`.
+    ///
+    /// Each slow call is logged as a warning with the procedure, the file it
+    /// operated on, its duration and the client, and is counted in the
+    /// `slow` column of [`rpc::ServerStats`]. Disabled by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `threshold`: Service time above which a call is slow, `None` to disable.
+    pub fn with_slow_call_threshold(&mut self, threshold: Option<Duration>) {
+        self.stats.set_slow_threshold(threshold);
+    }
+
+    /// Returns the statistics of this listener.
+    ///
+    /// The returned handle stays live: call [`rpc::ServerStats::snapshot`] on it
+    /// at any time to read per-procedure counters and cache hit rates.
+    pub fn stats(&self) -> Arc<rpc::ServerStats> {
+        self.stats.clone()
+    }
+
+    /// Replaces the statistics of this listener.
+    ///
+    /// Listeners of different exports sharing one instance report all their
+    /// exports in one snapshot, with bytes and calls counted per export in
+    /// [`rpc::StatsSnapshot::exports`]. Call before
+    /// [`NFSTcpListener::with_slow_call_threshold`], which configures the
+    /// instance in use.
+    ///
+    /// # Arguments
+    ///
+    /// * `stats`: Statistics to update.
+    pub fn with_stats(&mut self, stats: Arc<rpc::ServerStats>) {
+        self.stats = stats;
+    }
+
+    /// Replaces the transaction tracker used for retransmission detection.
+    ///
+    /// Use it to configure the retention period and capacity, or to hand in a
+    /// tracker restored with [`rpc::TransactionTracker::load`] after a restart.
+    ///
+    /// # Arguments
+    ///
+    /// * `tracker`: The tracker shared by all connections.
+    pub fn with_transaction_tracker(&mut self, tracker: Arc<rpc::TransactionTracker>) {
+        self.transaction_tracker = tracker;
+    }
+
+    /// Sets the credential flavors accepted per RPC program.
+    ///
+    /// Use [`rpc::AuthPolicy::require_for_all`] to hold the MOUNT and PORTMAP
+    /// programs to the same requirement as NFS, so peers without acceptable
+    /// credentials can neither enumerate exports nor change port mappings.
+    /// The flavors required for NFS are advertised in `MOUNT` replies, and
+    /// mounts requested with other flavors are refused.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy`: The policy applied to every call.
+    pub fn with_auth_policy(&mut self, policy: rpc::AuthPolicy) {
+        self.auth_policy = Arc::new(policy);
+    }
+
+    /// Maps client identities to an anonymous user.
+    ///
+    /// Like `root_squash` and `all_squash` in `/etc/exports`, the credentials
+    /// of calls by root, or by anybody, are replaced with the anonymous user
+    /// and group before permissions are evaluated. Identities are used as sent
+    /// by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `squash`: The mapping applied to every call.
+    pub fn with_squash(&mut self, squash: rpc::Squash) {
+        self.squash = squash;
+    }
+
+    /// Applies interoperability workarounds to the clients of some networks.
+    ///
+    /// The quirks of a client are chosen by its address when it connects, so
+    /// known buggy clients can be served within their limits while others
+    /// get the regular behavior.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy`: The quirks per client network.
+    pub fn with_client_quirks(&mut self, policy: rpc::QuirkPolicy) {
+        self.quirk_policy = Arc::new(policy);
+    }
+
+    /// Restricts which clients may register and unregister port mappings.
+    ///
+    /// By default any client can overwrite mappings with `PMAPPROC_SET` and
+    /// `PMAPPROC_UNSET`. Use [`RegistrationPolicy::STRICT`] to accept them only
+    /// from local, privileged callers as rpcbind does.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy`: Which callers may change mappings.
+    pub fn with_portmap_registration_policy(&mut self, policy: RegistrationPolicy) {
+        self.portmap_table.write().unwrap().set_policy(policy);
+    }
+
+    /// Serves the portmapper on `port` of TCP and UDP as well.
+    ///
+    /// By default the `PORTMAP` program is only reachable on the port of the
+    /// listener, which stock `mount` commands do not know about. Bound to the
+    /// standard port 111, the portmapper answers `PMAPPROC_GETPORT` for the
+    /// `NFS` and `MOUNT` programs of this listener, so clients find them
+    /// without `port=` and `mountport=` options. Only the `PORTMAP` program is
+    /// served there; calls to other programs get `PROG_UNAVAIL`. The sockets are bound to the
+    /// address of the listener; a `port` of 0 picks a free port, the same one
+    /// for both protocols. Port 111 can only be bound by privileged processes,
+    /// see [`crate::privileges`]; a failure leaves the listener unchanged, so
+    /// callers can carry on without the portmapper.
+    ///
+    /// # Arguments
+    ///
+    /// * `port`: Port of the portmapper, normally [`xdr::portmap::PMAP_PORT`].
+    pub async fn bind_portmapper(&mut self, port: u16) -> io::Result<()> {
+        let ip = self.listener.local_addr()?.ip();
+        let tcp = TcpListener::bind((ip, port)).await?;
+        let port = tcp.local_addr()?.port();
+        let udp = UdpSocket::bind((ip, port)).await?;
+        info!("Portmapper listening on {}", tcp.local_addr()?);
+
+        let mut table = self.portmap_table.write().unwrap();
+        for prot in [xdr::portmap::IPPROTO_TCP, xdr::portmap::IPPROTO_UDP] {
+            table.register(xdr::portmap::PROGRAM, xdr::portmap::VERSION, prot, port);
+        }
+        let tcp_port = self.port;
+        table.register(xdr::nfs3::PROGRAM, xdr::nfs3::VERSION, xdr::portmap::IPPROTO_TCP, tcp_port);
+        table.register(
+            xdr::mount::PROGRAM,
+            xdr::mount::VERSION,
+            xdr::portmap::IPPROTO_TCP,
+            tcp_port,
+        );
+        drop(table);
+        self.portmapper = Some(Portmapper { port, tcp, udp });
+        Ok(())
+    }
+
+    /// Returns the port of the built-in portmapper, if bound with
+    /// [`NFSTcpListener::bind_portmapper`].
+    pub fn portmapper_port(&self) -> Option<u16> {
+        self.portmapper.as_ref().map(|portmapper| portmapper.port)
+    }
+
+    /// Adds a hook run around the dispatch of every call.
+    ///
+    /// Hooks can refuse calls, rewrite their arguments or replace their replies,
+    /// see [`rpc::DispatchHook`]. They run in the order they were added. While
+    /// hooks are installed, `GETATTR` calls skip the fast path and `READ`
+    /// replies are not streamed, as hooks see every complete call and reply.
+    ///
+    /// # Arguments
+    ///
+    /// * `hook`: The hook to add after the ones added before.
+    pub fn with_dispatch_hook(&mut self, hook: impl rpc::DispatchHook + 'static) {
+        Arc::make_mut(&mut self.hooks).push(Arc::new(hook));
+    }
+
+    /// Returns the transaction tracker of this listener.
+    ///
+    /// A supervising process can [`rpc::TransactionTracker::dump`] it before
+    /// shutting the server down, and monitor it with
+    /// [`rpc::TransactionTracker::stats`] or trim it with
+    /// [`rpc::TransactionTracker::purge_expired`] while it runs.
+    pub fn transaction_tracker(&self) -> Arc<rpc::TransactionTracker> {
+        self.transaction_tracker.clone()
+    }
+
+    /// Returns the port mappings of this listener.
+    ///
+    /// Mappings registered by clients with `PMAPPROC_SET` stay until they are
+    /// unset; inspect them with [`PortmapTable::mappings`] and drop those of
+    /// services that went away with [`PortmapTable::unregister`].
+    pub fn portmap_table(&self) -> Arc<RwLock<PortmapTable>> {
+        self.portmap_table.clone()
+    }
+
+    /// Creates the RPC context for calls from `client_addr` received on
+    /// `local_port`
+    fn context(&self, local_port: u16, client_addr: SocketAddr) -> rpc::Context {
+        let quirks = self.quirk_policy.for_client(client_addr.ip());
+        let lane = self.scheduler.as_ref().map(|scheduler| {
+            let weight = scheduler.weight_for(client_addr.ip());
+            Arc::new(scheduler.lane(weight))
+        });
+        rpc::Context {
+            local_port,
+            client_addr,
+            auth: xdr::rpc::auth_unix::default(),
+            vfs: self.arcfs.clone(),
+            mount_signal: self.mount_signal.clone(),
+            mount_table: self.mount_table.clone(),
+            export_name: self.export_name.clone(),
+            transaction_tracker: self.transaction_tracker.clone(),
+            portmap_table: self.portmap_table.clone(),
+            portmap_only: self
+                .portmapper
+                .as_ref()
+                .is_some_and(|portmapper| portmapper.port == local_port),
+            write_coalescer: self.write_coalescer.clone(),
+            unstable_writes: self.unstable_writes.clone(),
+            space_counter: self.space_counter.clone(),
+            soft_delete: self.soft_delete.clone(),
+            checksums: self.checksums.clone(),
+            content_inspection: self.content_inspection.clone(),
+            events: self.events.clone(),
+            read_ahead: self.read_ahead.clone(),
+            negative_lookups: self.negative_lookups.clone(),
+            read_cache: self.read_cache.clone(),
+            readdirplus_limits: self.readdirplus_limits.clone(),
+            readdirplus: self.readdirplus,
+            export_limits: self.export_limits,
+            creation_defaults: self.creation_defaults,
+            dir_locks: self.dir_locks.clone(),
+            subtree_check: self.subtree_check,
+            handle_key: self.handle_key,
+            public_fh: self.public_fh,
+            handle_grace_until: self.handle_grace_until,
+            request_timeout: self.request_timeout,
+            max_record_length: self.max_record_length,
+            stats: self.stats.clone(),
+            lane,
+            priorities: self.priorities.clone(),
+            auth_policy: self.auth_policy.clone(),
+            short_auth: self.short_auth.clone(),
+            squash: self.squash,
+            quirks,
+            reply_stream: self.read_stream_chunk.map(rpc::ReplyStream::new),
+            compression: self
+                .compression
+                .map(|config| Arc::new(rpc::ReplyCompression::new(config))),
+            hooks: self.hooks.clone(),
+            name_filter: self.name_filter.clone(),
+            error_mapper: self.error_mapper.clone(),
+            clock: self.clock.clone(),
+            entropy: self.entropy.clone(),
+        }
+    }
+
+    /// Accepts connections on `listener` and serves each in a task of its own
+    async fn accept_connections(&self, listener: &TcpListener, local_port: u16) -> io::Result<()> {
+        loop {
+            let (socket, peer) = listener.accept().await?;
+            let context = self.context(local_port, peer);
+            info!("Accepting connection from {}", context.client_addr);
+            debug!("Accepting socket {:?} {:?}", socket, context);
+            let tap = match &self.capture {
+                Some(capture) => Some(capture.tap(peer, socket.local_addr()?)),
+                None => None,
+            };
+            let Some(spawner) = &self.spawner else {
+                tokio::spawn(async move {
+                    let _ = process_socket(socket, context, tap).await;
+                });
+                continue;
+            };
+            // registered again with the reactor of the runtime the task runs on
+            let socket = socket.into_std()?;
+            spawner.spawn(
+                peer,
+                Box::pin(async move {
+                    match tokio::net::TcpStream::from_std(socket) {
+                        Ok(socket) => {
+                            let _ = process_socket(socket, context, tap).await;
+                        }
+                        Err(e) => warn!("Cannot serve connection from {}: {:?}", peer, e),
+                    }
+                }),
+            );
+        }
+    }
+
+    /// Periodically drops the mounts of idle clients, notifying as for `UMNT`
+    ///
+    /// Never returns; waits forever if the mount table drops no clients.
+    async fn expire_mounts(&self) -> io::Result<()> {
+        let Some(liveness) = self.mount_table.liveness() else {
+            return std::future::pending().await;
+        };
+        let mut interval =
+            tokio::time::interval(liveness.check_interval.max(Duration::from_millis(1)));
+        loop {
+            interval.tick().await;
+            for entry in self.mount_table.expire(self.clock.now()) {
+                info!(
+                    "Dropping mount of {} by idle client {}",
+                    crate::redaction::name(&entry.path),
+                    entry.client
+                );
+                if let Some(chan) = &self.mount_signal {
+                    let _ = chan.send(false).await;
+                }
+                if let Some(events) = &self.events {
+                    let kind = nfs::events::EventKind::Unmounted { path: Some(entry.path) };
+                    events.publish(nfs::events::Event { client: entry.client, kind });
+                }
+            }
+        }
+    }
+
+    /// Answers the calls arriving as datagrams on `socket` one at a time
+    async fn serve_datagrams(&self, socket: &UdpSocket, local_port: u16) -> io::Result<()> {
+        let cache = Arc::new(rpc::ReplyCache::default());
+        self.register_memory("reply_cache", &cache);
+        let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+        loop {
+            let (len, peer) = socket.recv_from(&mut buf).await?;
+            let context = self.context(local_port, peer);
+            let mut reply = Vec::new();
+            match rpc::process_datagram(&buf[..len], &mut reply, context, &cache).await {
+                Ok(true) => {
+                    if let Err(e) = socket.send_to(&reply, peer).await {
+                        warn!("Cannot send reply to {}: {:?}", peer, e);
+                    }
+                }
+                Ok(false) => {}
+                Err(e) => debug!("Dropping datagram from {}: {:?}", peer, e),
+            }
+        }
+    }
 }
 
 #[async_trait]
-impl<T: NFSFileSystem + Send + Sync + 'static> NFSTcp for NFSTcpListener<T> {
+impl<T: NFSFileSystem + Send + Sync + ?Sized + 'static> NFSTcp for NFSTcpListener<T> {
     /// Returns the actual port number on which the server is listening
     ///
     /// This is especially useful when binding to port 0, which allows the OS
@@ -288,27 +1377,25 @@ impl<T: NFSFileSystem + Send + Sync + 'static> NFSTcp for NFSTcpListener<T> {
     /// - Creates a new RPC context for each connection
     /// - Spawns an asynchronous task to handle each connection
     /// - Continues accepting connections indefinitely
+    /// - Drops the mounts of idle clients, if enabled with
+    ///   [`NFSTcpListener::with_mount_liveness`]
     ///
     /// This method runs in an infinite loop and only returns if there's an error
     /// with the underlying TCP listener.
     async fn handle_forever(&self) -> io::Result<()> {
-        loop {
-            let (socket, _) = self.listener.accept().await?;
-            let context = rpc::Context {
-                local_port: self.port,
-                client_addr: socket.peer_addr()?.to_string(),
-                auth: xdr::rpc::auth_unix::default(),
-                vfs: self.arcfs.clone(),
-                mount_signal: self.mount_signal.clone(),
-                export_name: self.export_name.clone(),
-                transaction_tracker: self.transaction_tracker.clone(),
-                portmap_table: self.portmap_table.clone(),
-            };
-            info!("Accepting connection from {}", context.client_addr);
-            debug!("Accepting socket {:?} {:?}", socket, context);
-            tokio::spawn(async move {
-                let _ = process_socket(socket, context).await;
-            });
+        let serve = async {
+            match &self.portmapper {
+                Some(portmapper) => tokio::select! {
+                    res = self.accept_connections(&self.listener, self.port) => res,
+                    res = self.accept_connections(&portmapper.tcp, portmapper.port) => res,
+                    res = self.serve_datagrams(&portmapper.udp, portmapper.port) => res,
+                },
+                None => self.accept_connections(&self.listener, self.port).await,
+            }
+        };
+        tokio::select! {
+            res = serve => res,
+            res = self.expire_mounts() => res,
         }
     }
 }
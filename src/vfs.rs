@@ -17,13 +17,52 @@
 //! - Weak cache consistency through file attributes
 //! - Support for both synchronous and asynchronous I/O operations
 //! - File handle management that detects stale handles after server restarts
+//! - [`NfsError`] for turning backend errors into precise `nfsstat3` codes
+//! - [`testing::MockFs`] for exercising procedure handlers without a real backend
+//! - [`snapshot::SnapshotFs`] for exposing snapshots under a `.snapshot` directory
+//! - [`readdir`] helpers for paging listings, including cookies that survive changes
+//! - [`request::RequestContext`] for learning the deadline, trace and caller of a call
+//! - [`journal::JournalFs`] for making changes of naive backends crash consistent
+//! - [`inode_map::InodeMap`] for giving path-based backends stable file ids
+//! - [`attr_cache::AttrCache`] for serving repeated attribute lookups from memory
+//! - [`links::LinkCounts`] for keeping `nlink` right across hard links
+//! - [`locks::FileLockTable`] for advisory byte-range locks shared between backends
+//! - [`ntfs::NtfsPolicy`] for presenting NTFS streams and reparse points to clients
+//! - [`safe_path`] for building paths from client-supplied names without escaping the export
+//! - [`permissions`] for evaluating POSIX mode bits against caller credentials
+//! - [`tar`] for seeding a file system from a tar archive and capturing it as one
 
 use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
 
 use async_trait::async_trait;
+use futures::StreamExt;
+use tracing::debug;
 
 use crate::protocol::xdr::nfs3;
 
+pub mod attr_cache;
+pub mod inode_map;
+pub mod journal;
+pub mod links;
+pub mod locks;
+pub mod ntfs;
+pub mod permissions;
+pub mod readdir;
+pub mod request;
+pub mod safe_path;
+pub mod snapshot;
+pub mod tar;
+pub mod testing;
+
+/// Number of `getattr` calls [`NFSFileSystem::getattr_bulk`] keeps in flight
+/// at once by default
+pub const DEFAULT_GETATTR_CONCURRENCY: usize = 16;
+
 /// Simplified directory entry containing only file ID and name
 ///
 /// Used for simple directory listing operations where full attributes are not needed
@@ -49,7 +88,7 @@ pub struct ReadDirSimpleResult {
 /// Full directory entry containing file ID, name and attributes
 ///
 /// Used for extended directory listing operations like READDIRPLUS
-#[derive(Default, Debug)]
+#[derive(Clone, Default, Debug)]
 pub struct DirEntry {
     /// Unique file identifier within the file system (similar to inode number)
     pub fileid: nfs3::fileid3,
@@ -62,7 +101,7 @@ pub struct DirEntry {
 /// Result returned by readdir operations
 ///
 /// Contains a vector of complete directory entries and an EOF flag
-#[derive(Default, Debug)]
+#[derive(Clone, Default, Debug)]
 pub struct ReadDirResult {
     /// List of directory entries with full information
     pub entries: Vec<DirEntry>,
@@ -75,7 +114,7 @@ impl ReadDirSimpleResult {
     ///
     /// This allows implementations to provide just the full readdir operation,
     /// and the simplified version can be derived automatically.
-    fn from_readdir_result(result: &ReadDirResult) -> ReadDirSimpleResult {
+    pub(crate) fn from_readdir_result(result: &ReadDirResult) -> ReadDirSimpleResult {
         let entries: Vec<DirEntrySimple> = result
             .entries
             .iter()
@@ -85,12 +124,180 @@ impl ReadDirSimpleResult {
     }
 }
 
-/// Defines the access capabilities supported by a file system implementation
-pub enum Capabilities {
-    /// File system supports read operations only
-    ReadOnly,
-    /// File system supports both read and write operations
-    ReadWrite,
+/// Set of optional features supported by a file system implementation.
+///
+/// Procedures needing a feature the file system lacks are refused by the
+/// server before the backend is called: modifications of a file system
+/// without [`Capabilities::WRITE`] fail with `NFS3ERR_ROFS`, and the other
+/// missing features with `NFS3ERR_NOTSUPP`. Flags combine with `|`:
+///
+/// ```
+/// use nfs_mamont::vfs::Capabilities;
+///
+/// let caps = Capabilities::WRITE | Capabilities::SYMLINKS;
+/// assert!(caps.contains(Capabilities::SYMLINKS));
+/// assert!(!caps.contains(Capabilities::HARDLINKS));
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Capabilities(u32);
+
+#[allow(non_upper_case_globals)]
+impl Capabilities {
+    /// Files and directories can be modified
+    pub const WRITE: Capabilities = Capabilities(1 << 0);
+    /// Symbolic links can be created (`SYMLINK`)
+    pub const SYMLINKS: Capabilities = Capabilities(1 << 1);
+    /// Hard links can be created (`LINK`)
+    pub const HARDLINKS: Capabilities = Capabilities(1 << 2);
+    /// Devices, sockets and FIFOs can be created (`MKNOD`)
+    pub const DEVICES: Capabilities = Capabilities(1 << 3);
+    /// Access and modification times can be set (`SETATTR`)
+    pub const SET_TIME: Capabilities = Capabilities(1 << 4);
+    /// Files can be created with `EXCLUSIVE` semantics (`CREATE`)
+    pub const EXCLUSIVE_CREATE: Capabilities = Capabilities(1 << 5);
+
+    /// Read-only file system
+    pub const ReadOnly: Capabilities = Capabilities(0);
+    /// Writable file system supporting every optional feature
+    pub const ReadWrite: Capabilities = Capabilities((1 << 6) - 1);
+
+    /// Returns the set without any feature
+    pub const fn empty() -> Capabilities {
+        Capabilities(0)
+    }
+
+    /// Returns the set of all features
+    pub const fn all() -> Capabilities {
+        Capabilities::ReadWrite
+    }
+
+    /// Returns the raw bits of the set
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Returns true if every feature of `other` is in the set
+    pub const fn contains(self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns the set with the features of `other` added
+    pub const fn with(self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 | other.0)
+    }
+
+    /// Returns the set with the features of `other` removed
+    pub const fn without(self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 & !other.0)
+    }
+}
+
+impl std::ops::BitOr for Capabilities {
+    type Output = Capabilities;
+
+    fn bitor(self, other: Capabilities) -> Capabilities {
+        self.with(other)
+    }
+}
+
+impl std::ops::BitOrAssign for Capabilities {
+    fn bitor_assign(&mut self, other: Capabilities) {
+        *self = self.with(other);
+    }
+}
+
+impl std::ops::Sub for Capabilities {
+    type Output = Capabilities;
+
+    fn sub(self, other: Capabilities) -> Capabilities {
+        self.without(other)
+    }
+}
+
+/// Kind of region searched for by [`NFSFileSystem::seek`] (`data_content4`)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SeekContent {
+    /// Look for the next byte that holds data (`SEEK_DATA`)
+    Data = 0,
+    /// Look for the next byte inside a hole (`SEEK_HOLE`)
+    Hole = 1,
+}
+
+/// Position a directory listing resumes from
+///
+/// NFS clients resume a listing with the opaque cookie of the last entry they
+/// received. The server builds those cookies according to the file system's
+/// [`ReadDirCookieKind`] and decodes them into this type, so a backend never has
+/// to interpret raw cookies itself.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReadDirCookie {
+    /// Start listing from the first entry
+    Start,
+    /// Continue after the entry with this file id. The entry itself is not
+    /// returned again. Used with [`ReadDirCookieKind::FileId`].
+    AfterFileId(nfs3::fileid3),
+    /// Continue from the entry at this zero-based position, i.e. skip this
+    /// many entries. `Index(0)` starts from the first entry. Used with
+    /// [`ReadDirCookieKind::Index`].
+    Index(u64),
+    /// Continue after the entry whose cookie, as returned by
+    /// [`NFSFileSystem::readdir_entry_cookie`], is this value. The entry itself
+    /// need not exist anymore. Used with [`ReadDirCookieKind::Opaque`].
+    Opaque(nfs3::cookie3),
+}
+
+/// How the server builds the cookies of the directory entries a file system returns
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ReadDirCookieKind {
+    /// The cookie of an entry is its file id. Requires file ids to be unique within a
+    /// directory and the listing order to be stable, so the server can find the entry
+    /// again. This matches the historical `start_after` contract.
+    #[default]
+    FileId,
+    /// The cookie of an entry is one past its position in the listing. Suits
+    /// backends that list by offset, such as sorted vectors or paginated object
+    /// store listings, and directories whose entries may share a file id.
+    Index,
+    /// The cookie of an entry is chosen by the file system with
+    /// [`NFSFileSystem::readdir_entry_cookie`], a hash of the name by default,
+    /// and passed through unchanged. Listings stay consistent while entries are
+    /// added or removed between calls, as long as the file system lists entries
+    /// in ascending cookie order, see [`readdir::page_by_cookie`].
+    Opaque,
+}
+
+impl ReadDirCookie {
+    /// Decodes a cookie received from a client
+    ///
+    /// # Arguments
+    /// * `cookie` - Cookie from a `READDIR` or `READDIRPLUS` call, 0 for a new listing
+    /// * `kind` - Cookie kind of the file system
+    pub fn from_cookie3(cookie: nfs3::cookie3, kind: ReadDirCookieKind) -> Self {
+        match kind {
+            ReadDirCookieKind::FileId if cookie == 0 => ReadDirCookie::Start,
+            ReadDirCookieKind::FileId => ReadDirCookie::AfterFileId(cookie),
+            ReadDirCookieKind::Index => ReadDirCookie::Index(cookie),
+            ReadDirCookieKind::Opaque if cookie == 0 => ReadDirCookie::Start,
+            ReadDirCookieKind::Opaque => ReadDirCookie::Opaque(cookie),
+        }
+    }
+
+    /// Builds the cookie for an entry of a listing that resumed from `self`
+    ///
+    /// Not used for [`ReadDirCookieKind::Opaque`] file systems, whose cookies
+    /// come from [`NFSFileSystem::readdir_entry_cookie`].
+    ///
+    /// # Arguments
+    /// * `position` - Position of the entry within the returned entries
+    /// * `fileid` - File id of the entry
+    pub fn entry_cookie(self, position: usize, fileid: nfs3::fileid3) -> nfs3::cookie3 {
+        match self {
+            ReadDirCookie::Start | ReadDirCookie::AfterFileId(_) | ReadDirCookie::Opaque(_) => {
+                fileid
+            }
+            ReadDirCookie::Index(start) => start + position as u64 + 1,
+        }
+    }
 }
 
 /// The basic API to implement to provide an NFS file system
@@ -170,6 +377,40 @@ pub trait NFSFileSystem: Sync {
     /// * `Result<fattr3, nfsstat3>` - The file attributes on success, or an NFS error code
     async fn getattr(&self, id: nfs3::fileid3) -> Result<nfs3::fattr3, nfs3::nfsstat3>;
 
+    /// Returns the attributes of several files or directories at once
+    ///
+    /// Backends storing attributes in a database or an object store can batch
+    /// the lookups into a single query. The server calls it for the entries of
+    /// a `READDIRPLUS` reply when [`NFSFileSystem::readdir_has_attributes`] is
+    /// false. The default implementation calls [`NFSFileSystem::getattr`] for
+    /// up to [`NFSFileSystem::getattr_concurrency`] files at once, see
+    /// [`getattr_concurrently`].
+    ///
+    /// # Arguments
+    /// * `ids` - The file IDs to get attributes for
+    ///
+    /// # Returns
+    /// * `Vec<Result<fattr3, nfsstat3>>` - The attributes or error of every file,
+    ///   in the order of `ids`
+    async fn getattr_bulk(
+        &self,
+        ids: &[nfs3::fileid3],
+    ) -> Vec<Result<nfs3::fattr3, nfs3::nfsstat3>> {
+        getattr_concurrently(self, ids, self.getattr_concurrency()).await
+    }
+
+    /// Returns how many `getattr` calls the default
+    /// [`NFSFileSystem::getattr_bulk`] keeps in flight at once
+    ///
+    /// Overlapping the calls hides the latency of remote backends: listing a
+    /// directory with `ls -l` then takes about one round trip per that many
+    /// entries instead of one per entry. Backends that cannot serve concurrent
+    /// calls, or serve them from memory anyway, can return 1. The default is
+    /// [`DEFAULT_GETATTR_CONCURRENCY`].
+    fn getattr_concurrency(&self) -> usize {
+        DEFAULT_GETATTR_CONCURRENCY
+    }
+
     /// Sets the attributes of a file or directory
     ///
     /// This method allows changing file metadata such as permissions, ownership, and timestamps.
@@ -187,12 +428,52 @@ pub trait NFSFileSystem: Sync {
         setattr: nfs3::sattr3,
     ) -> Result<nfs3::fattr3, nfs3::nfsstat3>;
 
+    /// Returns whether [`NFSFileSystem::setattr_wcc`] and
+    /// [`NFSFileSystem::write_wcc`] report the attributes of the file before
+    /// the change
+    ///
+    /// Clients compare the attributes before and after a change with the ones
+    /// they cached (weak cache consistency). When false, `SETATTR` and `WRITE`
+    /// fetch the attributes before the change with
+    /// [`NFSFileSystem::getattr`], an extra call to the backend on every write.
+    /// Backends that know them anyway, e.g. from metadata they keep, override
+    /// this to return true and report them instead. The default is false.
+    fn reports_pre_op_attrs(&self) -> bool {
+        false
+    }
+
+    /// Sets the attributes of a file or directory, reporting its attributes
+    /// before the change
+    ///
+    /// The `SETATTR` procedure calls this instead of [`NFSFileSystem::setattr`].
+    /// The default reports no attributes before the change and calls
+    /// [`NFSFileSystem::setattr`].
+    ///
+    /// # Arguments
+    /// * `id` - The file ID to set attributes for
+    /// * `setattr` - The attributes to set
+    ///
+    /// # Returns
+    /// * `Result<(Option<wcc_attr>, fattr3), nfsstat3>` - The attributes before the change if
+    ///   known and the updated attributes, or an NFS error code
+    async fn setattr_wcc(
+        &self,
+        id: nfs3::fileid3,
+        setattr: nfs3::sattr3,
+    ) -> Result<(Option<nfs3::wcc_attr>, nfs3::fattr3), nfs3::nfsstat3> {
+        Ok((None, self.setattr(id, setattr).await?))
+    }
+
     /// Reads data from a file
     ///
     /// This method reads a portion of a file's content starting at the specified offset.
     /// If offset+count extends beyond the end of the file, all remaining data should be returned.
     /// The returned boolean indicates whether the read operation reached the end of the file.
     ///
+    /// A read may return fewer than `count` bytes before the end of the file, e.g. when the
+    /// backend reads in blocks. The client then asks for the rest in another call. Such a
+    /// short read must return at least one byte and report no EOF.
+    ///
     /// # Arguments
     /// * `id` - The file ID to read from
     /// * `offset` - Byte offset within the file to start reading
@@ -227,6 +508,58 @@ pub trait NFSFileSystem: Sync {
         data: &[u8],
     ) -> Result<nfs3::fattr3, nfs3::nfsstat3>;
 
+    /// Writes a prefix of `data` to a file
+    ///
+    /// The `WRITE` procedure calls this instead of [`NFSFileSystem::write`]. Backends
+    /// that may store less than asked, e.g. when a device fills up or an upstream server
+    /// accepts a partial write, override it to report how much was written; the client
+    /// sends the rest again. At least one byte must be written unless `data` is empty,
+    /// otherwise an error must be returned. The default writes all of `data` with
+    /// [`NFSFileSystem::write`].
+    ///
+    /// # Arguments
+    /// * `id` - The file ID to write to
+    /// * `offset` - Byte offset within the file to start writing
+    /// * `data` - The data to write
+    ///
+    /// # Returns
+    /// * `Result<(fattr3, u32), nfsstat3>` - The updated file attributes and the number of
+    ///   bytes written from the start of `data`, or an NFS error code
+    async fn write_partial(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(nfs3::fattr3, u32), nfs3::nfsstat3> {
+        let attr = self.write(id, offset, data).await?;
+        Ok((attr, data.len() as u32))
+    }
+
+    /// Writes a prefix of `data` to a file, reporting its attributes before
+    /// the write
+    ///
+    /// The `WRITE` procedure calls this instead of
+    /// [`NFSFileSystem::write_partial`]. The default reports no attributes
+    /// before the write and calls [`NFSFileSystem::write_partial`].
+    ///
+    /// # Arguments
+    /// * `id` - The file ID to write to
+    /// * `offset` - Byte offset within the file to start writing
+    /// * `data` - The data to write
+    ///
+    /// # Returns
+    /// * `Result<(Option<wcc_attr>, fattr3, u32), nfsstat3>` - The attributes before the write
+    ///   if known, the updated attributes and the number of bytes written, or an NFS error code
+    async fn write_wcc(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(Option<nfs3::wcc_attr>, nfs3::fattr3, u32), nfs3::nfsstat3> {
+        let (attr, count) = self.write_partial(id, offset, data).await?;
+        Ok((None, attr, count))
+    }
+
     /// Creates a new file with the specified attributes
     ///
     /// This method creates a new file in the specified directory.
@@ -323,6 +656,10 @@ pub trait NFSFileSystem: Sync {
     /// This method retrieves a list of entries from a directory, starting after a specific entry.
     /// The directory listing should be deterministic and support resuming from any point.
     ///
+    /// This is the file id based form of [`NFSFileSystem::readdir_with_cookie`], which
+    /// the server calls. Implement exactly one of the two: the default implementations
+    /// call each other.
+    ///
     /// # Arguments
     /// * `dirid` - The directory ID to read
     /// * `start_after` - The file ID after which to start listing (0 means start from beginning)
@@ -335,7 +672,83 @@ pub trait NFSFileSystem: Sync {
         dirid: nfs3::fileid3,
         start_after: nfs3::fileid3,
         max_entries: usize,
-    ) -> Result<ReadDirResult, nfs3::nfsstat3>;
+    ) -> Result<ReadDirResult, nfs3::nfsstat3> {
+        let cookie = ReadDirCookie::from_cookie3(start_after, ReadDirCookieKind::FileId);
+        self.readdir_with_cookie(dirid, cookie, max_entries).await
+    }
+
+    /// Returns how the server builds directory entry cookies for this file system
+    ///
+    /// Determines which [`ReadDirCookie`] variants [`NFSFileSystem::readdir_with_cookie`]
+    /// receives. The default is [`ReadDirCookieKind::FileId`].
+    fn readdir_cookie_kind(&self) -> ReadDirCookieKind {
+        ReadDirCookieKind::FileId
+    }
+
+    /// Returns the cookie of a directory entry for [`ReadDirCookieKind::Opaque`]
+    ///
+    /// The cookie is sent to clients as is and handed back in
+    /// [`ReadDirCookie::Opaque`] when they resume after the entry. It must be
+    /// nonzero and unique within the directory, and should not depend on the
+    /// entry's position so that listings survive concurrent changes. The
+    /// default implementation hashes the name with [`readdir::name_cookie`].
+    ///
+    /// # Arguments
+    /// * `dirid` - The directory being listed
+    /// * `fileid` - The file ID of the entry
+    /// * `name` - The name of the entry
+    fn readdir_entry_cookie(
+        &self,
+        _dirid: nfs3::fileid3,
+        _fileid: nfs3::fileid3,
+        name: &nfs3::filename3,
+    ) -> nfs3::cookie3 {
+        readdir::name_cookie(name)
+    }
+
+    /// Returns whether listed directory entries carry their attributes
+    ///
+    /// When false, [`DirEntry::attr`] of the entries returned by
+    /// [`NFSFileSystem::readdir_with_cookie`] is ignored, and `READDIRPLUS`
+    /// fetches the attributes of the entries it replies with through
+    /// [`NFSFileSystem::getattr_bulk`] instead. That suits backends listing
+    /// names from one place and attributes from another. The default is true.
+    fn readdir_has_attributes(&self) -> bool {
+        true
+    }
+
+    /// Reads directory entries starting at a decoded cookie
+    ///
+    /// Receives [`ReadDirCookie::Start`] and [`ReadDirCookie::AfterFileId`] for file
+    /// systems with [`ReadDirCookieKind::FileId`] cookies, only
+    /// [`ReadDirCookie::Index`] for [`ReadDirCookieKind::Index`] ones, and
+    /// [`ReadDirCookie::Start`] and [`ReadDirCookie::Opaque`] for
+    /// [`ReadDirCookieKind::Opaque`] ones. The helpers in [`readdir`] page a
+    /// full listing for either scheme. The default implementation forwards file
+    /// id cookies to [`NFSFileSystem::readdir`] and rejects other cookies with
+    /// NFS3ERR_BAD_COOKIE.
+    ///
+    /// # Arguments
+    /// * `dirid` - The directory ID to read
+    /// * `cookie` - Where to resume the listing
+    /// * `max_entries` - Maximum number of entries to return
+    ///
+    /// # Returns
+    /// * `Result<ReadDirResult, nfsstat3>` - Directory entries and EOF flag on success, or an NFS error code
+    async fn readdir_with_cookie(
+        &self,
+        dirid: nfs3::fileid3,
+        cookie: ReadDirCookie,
+        max_entries: usize,
+    ) -> Result<ReadDirResult, nfs3::nfsstat3> {
+        match cookie {
+            ReadDirCookie::Start => self.readdir(dirid, 0, max_entries).await,
+            ReadDirCookie::AfterFileId(fileid) => self.readdir(dirid, fileid, max_entries).await,
+            ReadDirCookie::Index(_) | ReadDirCookie::Opaque(_) => {
+                Err(nfs3::nfsstat3::NFS3ERR_BAD_COOKIE)
+            }
+        }
+    }
 
     /// Simplified version of readdir that returns only file names and IDs
     ///
@@ -455,6 +868,117 @@ pub trait NFSFileSystem: Sync {
         count: u32,
     ) -> Result<nfs3::fattr3, nfs3::nfsstat3>;
 
+    /// Reserves storage for a byte range of a file (NFSv4.2 `ALLOCATE`)
+    ///
+    /// After a successful call, writes within the range must not fail for lack of space.
+    /// The file size grows if the range extends past the end of the file.
+    /// The default implementation returns NFS3ERR_NOTSUPP.
+    ///
+    /// # Arguments
+    /// * `file_id` - The file ID to allocate storage for
+    /// * `offset` - Starting offset of the range
+    /// * `length` - Number of bytes in the range
+    ///
+    /// # Returns
+    /// * `Result<fattr3, nfsstat3>` - The file attributes after the allocation on success, or an NFS error code
+    async fn allocate(
+        &self,
+        _file_id: nfs3::fileid3,
+        _offset: u64,
+        _length: u64,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_NOTSUPP)
+    }
+
+    /// Releases storage for a byte range of a file (NFSv4.2 `DEALLOCATE`)
+    ///
+    /// The range reads back as zeroes afterwards and may become a hole.
+    /// The file size never changes. The default implementation returns NFS3ERR_NOTSUPP.
+    ///
+    /// # Arguments
+    /// * `file_id` - The file ID to punch a hole into
+    /// * `offset` - Starting offset of the range
+    /// * `length` - Number of bytes in the range
+    ///
+    /// # Returns
+    /// * `Result<fattr3, nfsstat3>` - The file attributes after the deallocation on success, or an NFS error code
+    async fn deallocate(
+        &self,
+        _file_id: nfs3::fileid3,
+        _offset: u64,
+        _length: u64,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_NOTSUPP)
+    }
+
+    /// Finds the next data or hole region of a file (NFSv4.2 `SEEK`)
+    ///
+    /// The default implementation treats every file as fully allocated: data is
+    /// found at `offset` itself and the only hole is the implicit one at the end of
+    /// the file. File systems that track sparse regions should override it.
+    ///
+    /// # Arguments
+    /// * `file_id` - The file ID to search
+    /// * `offset` - Offset to start searching from
+    /// * `what` - Whether to look for data or for a hole
+    ///
+    /// # Returns
+    /// * `Result<(u64, bool), nfsstat3>` - The offset of the region found and an EOF flag,
+    ///   or NFS3ERR_NXIO if `offset` is at or beyond the end of the file
+    async fn seek(
+        &self,
+        file_id: nfs3::fileid3,
+        offset: u64,
+        what: SeekContent,
+    ) -> Result<(u64, bool), nfs3::nfsstat3> {
+        let size = self.getattr(file_id).await?.size;
+        if offset >= size {
+            return Err(nfs3::nfsstat3::NFS3ERR_NXIO);
+        }
+        match what {
+            SeekContent::Data => Ok((offset, false)),
+            SeekContent::Hole => Ok((size, true)),
+        }
+    }
+
+    /// Hints that a range of a file is likely to be read soon
+    ///
+    /// Called when the server detects that a client reads a file sequentially, with
+    /// the range just past the data already requested. Backends with high read latency
+    /// (object stores, spinning disks) can use it to start prefetching. The call is
+    /// awaited on the `READ` path, so implementations should only schedule the
+    /// prefetch and return. The default implementation ignores the hint.
+    ///
+    /// # Arguments
+    /// * `file_id` - The file ID that is being read
+    /// * `offset` - Offset of the range expected to be read next
+    /// * `length` - Length of the range
+    async fn readahead(&self, _file_id: nfs3::fileid3, _offset: u64, _length: u64) {}
+
+    /// Returns whether the contents of a file never change
+    ///
+    /// Content-addressed stores, archives and snapshots know their files are
+    /// immutable. The server caches `READ` results of such files in memory when
+    /// a read cache is enabled, and serves hot files without calling
+    /// [`Self::read`] again. Called on the `READ` path, so implementations
+    /// should answer without I/O. The default reports every file as mutable.
+    ///
+    /// # Arguments
+    /// * `id` - The file ID being read
+    fn is_immutable(&self, _id: nfs3::fileid3) -> bool {
+        false
+    }
+
+    /// Returns the granularity of the timestamps the file system stores
+    ///
+    /// Reported to clients as `time_delta` by the default [`Self::fsinfo`].
+    /// Clients use it to tell whether two timestamps can differ at all, so a
+    /// backend storing whole seconds (or the 2 seconds of FAT) should say so.
+    /// The default is one millisecond.
+    fn time_delta(&self) -> Duration {
+        Duration::from_millis(1)
+    }
+
     /// Retrieves static file system information
     ///
     /// This method provides information about the file system's capabilities and parameters.
@@ -481,7 +1005,7 @@ pub trait NFSFileSystem: Sync {
             wtmult: 1024 * 1024,
             dtpref: 1024 * 1024,
             maxfilesize: 128 * 1024 * 1024 * 1024,
-            time_delta: nfs3::nfstime3 { seconds: 0, nseconds: 1_000_000 },
+            time_delta: nfs3::nfstime3::from_duration(self.time_delta()),
             properties: nfs3::fs::FSF_SYMLINK
                 | nfs3::fs::FSF_HOMOGENEOUS
                 | nfs3::fs::FSF_CANSETTIME,
@@ -489,6 +1013,25 @@ pub trait NFSFileSystem: Sync {
         Ok(res)
     }
 
+    /// Retrieves volatile file system information, such as free space
+    ///
+    /// The default implementation returns `NFS3ERR_NOTSUPP`, for which the server
+    /// reports the totals of its [`crate::write_counter::SpaceCounter`] if one is
+    /// configured, or fixed large values otherwise. Any other error is returned
+    /// to the client.
+    ///
+    /// # Arguments
+    /// * `root_fileid` - The file ID of the object the client asked about
+    ///
+    /// # Returns
+    /// * `Result<FSSTAT3resok, nfsstat3>` - File system statistics on success, or an NFS error code
+    async fn fsstat(
+        &self,
+        _root_fileid: nfs3::fileid3,
+    ) -> Result<nfs3::fs::FSSTAT3resok, nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_NOTSUPP)
+    }
+
     /// Converts a file ID to an opaque NFS file handle
     ///
     /// This method creates an opaque file handle from a file ID by combining
@@ -534,6 +1077,35 @@ pub trait NFSFileSystem: Sync {
         }
     }
 
+    /// Maps a stale file handle from before a restart back to a file ID
+    ///
+    /// Called by listeners with a handle grace period (see
+    /// `NFSTcpListener::with_handle_grace`) for handles that [`Self::fh_to_id`]
+    /// rejected with NFS3ERR_STALE. Backends whose file IDs survive a restart
+    /// can check that the file still exists and return its ID, so clients keep
+    /// using their mounts. The default implementation keeps rejecting them.
+    ///
+    /// With the default handle format the old file ID is stored after the
+    /// 8-byte generation number:
+    ///
+    /// ```ignore
+    /// fn rehydrate_handle(&self, old: &nfs3::nfs_fh3) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+    ///     let id = old.data.get(8..16).ok_or(nfs3::nfsstat3::NFS3ERR_BADHANDLE)?;
+    ///     let id = u64::from_le_bytes(id.try_into().unwrap());
+    ///     if self.exists(id) { Ok(id) } else { Err(nfs3::nfsstat3::NFS3ERR_STALE) }
+    /// }
+    /// ```
+    ///
+    /// # Arguments
+    /// * `old` - The stale file handle sent by the client
+    ///
+    /// # Returns
+    /// * `Result<fileid3, nfsstat3>` - The current file ID of the handle's file,
+    ///   or an NFS error code, usually NFS3ERR_STALE
+    fn rehydrate_handle(&self, _old: &nfs3::nfs_fh3) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        Err(nfs3::nfsstat3::NFS3ERR_STALE)
+    }
+
     /// Converts a path to a file ID by walking the directory structure
     ///
     /// This method translates a full path to a file ID by traversing the directory
@@ -569,3 +1141,310 @@ pub trait NFSFileSystem: Sync {
         self.generation().to_le_bytes()
     }
 }
+
+/// Function mapping an error to a status code, `None` to defer
+type MapFn = dyn Fn(&(dyn Error + 'static)) -> Option<nfs3::nfsstat3> + Send + Sync;
+
+/// Application supplied mapping from backend errors to status codes
+///
+/// Installed per listener with [`crate::tcp::NFSTcpListener::with_error_mapper`]
+/// and consulted by [`NfsError::from_error`] for errors raised while serving a
+/// call of that listener, see [`request::RequestContext::error_mapper`].
+#[derive(Clone)]
+pub struct ErrorMapper(Arc<MapFn>);
+
+impl ErrorMapper {
+    /// Wraps `mapper`, which returns the status code for an error, or `None`
+    /// to defer to the built-in conversions
+    pub fn new(
+        mapper: impl Fn(&(dyn Error + 'static)) -> Option<nfs3::nfsstat3> + Send + Sync + 'static,
+    ) -> Self {
+        Self(Arc::new(mapper))
+    }
+
+    /// Returns the status code the mapping gives `err`, if any
+    pub fn map(&self, err: &(dyn Error + 'static)) -> Option<nfs3::nfsstat3> {
+        (self.0)(err)
+    }
+}
+
+impl fmt::Debug for ErrorMapper {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ErrorMapper")
+    }
+}
+
+/// Returns the attributes of several files, calling `getattr` for up to
+/// `limit` of them at once
+///
+/// The results are in the order of `ids`, whatever order the calls complete
+/// in. A `limit` of 0 is taken as 1.
+pub async fn getattr_concurrently<F: NFSFileSystem + ?Sized>(
+    fs: &F,
+    ids: &[nfs3::fileid3],
+    limit: usize,
+) -> Vec<Result<nfs3::fattr3, nfs3::nfsstat3>> {
+    futures::stream::iter(ids.iter().copied())
+        .map(|id| fs.getattr(id))
+        .buffered(limit.max(1))
+        .collect()
+        .await
+}
+
+/// Error of a file system operation together with the status code reported to the client
+///
+/// Backends convert their errors with `?` or [`NfsError::from_error`] instead of
+/// collapsing every failure to `NFS3ERR_IO`. Converting an `NfsError` into an
+/// `nfsstat3` logs the original error at debug level, and `std::io::Error`
+/// converts into `nfsstat3` directly, so `?` works in [`NFSFileSystem`] methods.
+#[derive(Debug)]
+pub struct NfsError {
+    /// Code reported to the client
+    status: nfs3::nfsstat3,
+    /// Error the code was derived from
+    source: Option<Box<dyn Error + Send + Sync>>,
+}
+
+impl NfsError {
+    /// Creates an error with the given status code and no underlying error
+    pub fn new(status: nfs3::nfsstat3) -> Self {
+        Self { status, source: None }
+    }
+
+    /// Creates an error with an explicit status code for an underlying error
+    pub fn with_source(
+        status: nfs3::nfsstat3,
+        source: impl Into<Box<dyn Error + Send + Sync>>,
+    ) -> Self {
+        Self { status, source: Some(source.into()) }
+    }
+
+    /// Derives the status code of an arbitrary error
+    ///
+    /// Consults the [`ErrorMapper`] of the listener serving the current call
+    /// first, if it has one. Otherwise the
+    /// first `std::io::Error` in the error's source chain is converted by its OS
+    /// error code or kind, and any other error becomes `NFS3ERR_IO`.
+    pub fn from_error(err: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+        let err = err.into();
+        let status = map_error(&*err).unwrap_or(nfs3::nfsstat3::NFS3ERR_IO);
+        Self { status, source: Some(err) }
+    }
+
+    /// Returns the status code reported to the client
+    pub fn status(&self) -> nfs3::nfsstat3 {
+        self.status
+    }
+}
+
+/// Applies the mapper of the current call and the built-in conversions to `err`
+pub(crate) fn map_error(err: &(dyn Error + 'static)) -> Option<nfs3::nfsstat3> {
+    // cloned out of the call, so the mapper runs without borrowing it
+    if let Some(mapper) = request::RequestContext::current_error_mapper() {
+        if let Some(status) = mapper.map(err) {
+            return Some(status);
+        }
+    }
+    let mut current = Some(err);
+    while let Some(err) = current {
+        if let Some(io_err) = err.downcast_ref::<io::Error>() {
+            // errors wrapped with `io::Error::other` carry the interesting cause
+            if let Some(inner) = io_err.get_ref().filter(|_| io_err.raw_os_error().is_none()) {
+                if let Some(status) = map_error(inner) {
+                    return Some(status);
+                }
+            }
+            return Some(io_error_status(io_err));
+        }
+        current = err.source();
+    }
+    None
+}
+
+/// Converts an I/O error by its OS error code, falling back to its kind
+fn io_error_status(err: &io::Error) -> nfs3::nfsstat3 {
+    use nfs3::nfsstat3::*;
+
+    if let Some(status) = err.raw_os_error().and_then(errno::status) {
+        return status;
+    }
+    match err.kind() {
+        io::ErrorKind::NotFound => NFS3ERR_NOENT,
+        io::ErrorKind::PermissionDenied => NFS3ERR_ACCES,
+        io::ErrorKind::AlreadyExists => NFS3ERR_EXIST,
+        io::ErrorKind::InvalidInput => NFS3ERR_INVAL,
+        io::ErrorKind::Unsupported => NFS3ERR_NOTSUPP,
+        io::ErrorKind::NotADirectory => NFS3ERR_NOTDIR,
+        io::ErrorKind::IsADirectory => NFS3ERR_ISDIR,
+        io::ErrorKind::DirectoryNotEmpty => NFS3ERR_NOTEMPTY,
+        io::ErrorKind::ReadOnlyFilesystem => NFS3ERR_ROFS,
+        io::ErrorKind::StorageFull => NFS3ERR_NOSPC,
+        io::ErrorKind::StaleNetworkFileHandle => NFS3ERR_STALE,
+        _ => NFS3ERR_IO,
+    }
+}
+
+/// OS error codes with an `nfsstat3` equivalent
+///
+/// Codes up to `EMLINK` are shared by all Unix systems and equal the NFS codes.
+/// The others differ between systems.
+#[cfg(unix)]
+mod errno {
+    use crate::protocol::xdr::nfs3::nfsstat3::{self, *};
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    const OTHERS: [(i32, nfsstat3); 5] = [
+        (36, NFS3ERR_NAMETOOLONG),
+        (39, NFS3ERR_NOTEMPTY),
+        (95, NFS3ERR_NOTSUPP),
+        (116, NFS3ERR_STALE),
+        (122, NFS3ERR_DQUOT),
+    ];
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    const OTHERS: [(i32, nfsstat3); 5] = [
+        (63, NFS3ERR_NAMETOOLONG),
+        (66, NFS3ERR_NOTEMPTY),
+        (45, NFS3ERR_NOTSUPP),
+        (70, NFS3ERR_STALE),
+        (69, NFS3ERR_DQUOT),
+    ];
+
+    /// Returns the status code of an OS error code, if it has one
+    pub(super) fn status(errno: i32) -> Option<nfsstat3> {
+        Some(match errno {
+            1 => NFS3ERR_PERM,
+            2 => NFS3ERR_NOENT,
+            5 => NFS3ERR_IO,
+            6 => NFS3ERR_NXIO,
+            13 => NFS3ERR_ACCES,
+            17 => NFS3ERR_EXIST,
+            18 => NFS3ERR_XDEV,
+            19 => NFS3ERR_NODEV,
+            20 => NFS3ERR_NOTDIR,
+            21 => NFS3ERR_ISDIR,
+            22 => NFS3ERR_INVAL,
+            27 => NFS3ERR_FBIG,
+            28 => NFS3ERR_NOSPC,
+            30 => NFS3ERR_ROFS,
+            31 => NFS3ERR_MLINK,
+            _ => return OTHERS.iter().find(|(code, _)| *code == errno).map(|(_, status)| *status),
+        })
+    }
+}
+
+/// Without Unix error codes only the error kind is used
+#[cfg(not(unix))]
+mod errno {
+    use crate::protocol::xdr::nfs3::nfsstat3;
+
+    /// Returns the status code of an OS error code, if it has one
+    pub(super) fn status(_errno: i32) -> Option<nfsstat3> {
+        None
+    }
+}
+
+impl fmt::Display for NfsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.source {
+            Some(source) => write!(f, "{:?}: {}", self.status, source),
+            None => write!(f, "{:?}", self.status),
+        }
+    }
+}
+
+impl Error for NfsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_ref().map(|source| &**source as &(dyn Error + 'static))
+    }
+}
+
+impl From<io::Error> for NfsError {
+    fn from(err: io::Error) -> Self {
+        Self::from_error(err)
+    }
+}
+
+impl From<nfs3::nfsstat3> for NfsError {
+    fn from(status: nfs3::nfsstat3) -> Self {
+        Self::new(status)
+    }
+}
+
+impl From<NfsError> for nfs3::nfsstat3 {
+    fn from(err: NfsError) -> Self {
+        if let Some(source) = &err.source {
+            debug!("reporting {:?} for: {}", err.status, source);
+        }
+        err.status
+    }
+}
+
+impl From<io::Error> for nfs3::nfsstat3 {
+    fn from(err: io::Error) -> Self {
+        NfsError::from(err).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(err: io::Error) -> u32 {
+        nfs3::nfsstat3::from(err) as u32
+    }
+
+    #[test]
+    fn converts_io_errors() {
+        use nfs3::nfsstat3::*;
+
+        assert_eq!(status(io::ErrorKind::NotFound.into()), NFS3ERR_NOENT as u32);
+        assert_eq!(status(io::ErrorKind::StorageFull.into()), NFS3ERR_NOSPC as u32);
+        assert_eq!(status(io::Error::other("backend")), NFS3ERR_IO as u32);
+        #[cfg(target_os = "linux")]
+        {
+            assert_eq!(status(io::Error::from_raw_os_error(122)), NFS3ERR_DQUOT as u32);
+            assert_eq!(status(io::Error::from_raw_os_error(36)), NFS3ERR_NAMETOOLONG as u32);
+            assert_eq!(status(io::Error::from_raw_os_error(1)), NFS3ERR_PERM as u32);
+        }
+
+        let wrapped = io::Error::other(io::Error::from(io::ErrorKind::NotFound));
+        assert_eq!(NfsError::from_error(wrapped).status() as u32, NFS3ERR_NOENT as u32);
+        let context = anyhow::Error::from(io::Error::from(io::ErrorKind::AlreadyExists))
+            .context("creating block");
+        assert_eq!(NfsError::from_error(context).status() as u32, NFS3ERR_EXIST as u32);
+    }
+
+    #[derive(Debug)]
+    struct Throttled;
+
+    impl fmt::Display for Throttled {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "backend throttled")
+        }
+    }
+
+    impl Error for Throttled {}
+
+    #[tokio::test]
+    async fn consults_mapper_of_the_call() {
+        use nfs3::nfsstat3::*;
+
+        let mapper = ErrorMapper::new(|err| err.is::<Throttled>().then_some(NFS3ERR_JUKEBOX));
+        let addr = "10.0.0.1:800".parse().unwrap();
+        let request = request::RequestContext {
+            error_mapper: Some(mapper),
+            ..request::RequestContext::new(1, addr, Default::default(), None)
+        };
+        let (mapped, io) = request
+            .scope(async {
+                let mapped = NfsError::from_error(Throttled).status();
+                (mapped, status(io::ErrorKind::NotFound.into()))
+            })
+            .await;
+        assert_eq!(mapped as u32, NFS3ERR_JUKEBOX as u32);
+        assert_eq!(io, NFS3ERR_NOENT as u32);
+        // calls of other listeners, and code outside of calls, are not affected
+        assert_eq!(NfsError::from_error(Throttled).status() as u32, NFS3ERR_IO as u32);
+    }
+}
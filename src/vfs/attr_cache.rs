@@ -0,0 +1,201 @@
+//! Cached attributes for backends whose `getattr` is expensive.
+//!
+//! Clients issue `GETATTR` and `LOOKUP` far more often than they change files,
+//! and backends asking a remote store or walking a slow disk for every one of
+//! them spend most of their time on attributes they returned a moment ago.
+//! [`AttrCache`] keeps recent attributes for a short time.
+//!
+//! The cache is built for many concurrent readers. Entries are split into
+//! shards by file id, each behind its own [`std::sync::RwLock`], so readers
+//! never wait for each other, and a writer only holds up the readers of its
+//! shard. Locks are never held across an `.await`, so the cache can be used
+//! from async code without an async lock. Keeping a whole backend state behind
+//! one `tokio::sync::Mutex` instead serializes every `GETATTR` and `LOOKUP`
+//! of all clients, even when nothing changes.
+//!
+//! The cache is a [`MemoryConsumer`]. Backends register it with the
+//! [`crate::memory_budget::MemoryBudget`] of their listeners to have expired
+//! entries, and then arbitrary ones, dropped under memory pressure.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crate::clock::{Clock, SystemClock};
+use crate::memory_budget::{MemoryCharge, MemoryConsumer};
+use crate::protocol::xdr::nfs3;
+
+/// Number of shards used by default
+pub const DEFAULT_SHARDS: usize = 16;
+
+/// Attributes with the time they were stored
+type Shard = HashMap<nfs3::fileid3, (nfs3::fattr3, SystemTime)>;
+
+/// Approximate memory of an entry
+const ENTRY_SIZE: usize = std::mem::size_of::<(nfs3::fileid3, (nfs3::fattr3, SystemTime))>();
+
+/// Attributes of recently seen files, forgotten after a time to live
+#[derive(Debug)]
+pub struct AttrCache {
+    /// Time an entry is returned after it was stored
+    ttl: Duration,
+    /// Source of store and lookup times
+    clock: Arc<dyn Clock>,
+    shards: Box<[RwLock<Shard>]>,
+    memory: MemoryCharge,
+}
+
+impl AttrCache {
+    /// Creates a cache returning attributes for up to `ttl` after they were
+    /// stored
+    ///
+    /// The time to live should not exceed what clients tolerate as staleness,
+    /// typically their own attribute cache timeout of a few seconds.
+    pub fn new(ttl: Duration) -> Self {
+        Self::with_shards(ttl, DEFAULT_SHARDS, Arc::new(SystemClock))
+    }
+
+    /// Creates a cache with a given number of shards and clock
+    ///
+    /// # Arguments
+    ///
+    /// * `ttl` - Time an entry is returned after it was stored
+    /// * `shards` - Number of independently locked shards, at least 1; more
+    ///   shards let more writers proceed at once
+    /// * `clock` - Source of the current time
+    pub fn with_shards(ttl: Duration, shards: usize, clock: Arc<dyn Clock>) -> Self {
+        let shards = (0..shards.max(1)).map(|_| RwLock::default()).collect();
+        Self { ttl, clock, shards, memory: MemoryCharge::default() }
+    }
+
+    /// Returns the attributes of a file, if stored within the time to live
+    ///
+    /// Takes the lock of the shard for reading only.
+    pub fn get(&self, fileid: nfs3::fileid3) -> Option<nfs3::fattr3> {
+        let shard = self.shard(fileid).read().unwrap();
+        let (attr, stored_at) = shard.get(&fileid)?;
+        let age = self.clock.now().duration_since(*stored_at).unwrap_or_default();
+        (age < self.ttl).then_some(*attr)
+    }
+
+    /// Stores the attributes of a file, as just returned by the backend
+    pub fn insert(&self, attr: nfs3::fattr3) {
+        let now = self.clock.now();
+        let replaced = self.shard(attr.fileid).write().unwrap().insert(attr.fileid, (attr, now));
+        if replaced.is_none() {
+            self.memory.charge(ENTRY_SIZE);
+            self.memory.reclaim();
+        }
+    }
+
+    /// Forgets the attributes of a file, e.g. after changing it
+    pub fn invalidate(&self, fileid: nfs3::fileid3) {
+        if self.shard(fileid).write().unwrap().remove(&fileid).is_some() {
+            self.memory.release(ENTRY_SIZE);
+        }
+    }
+
+    /// Forgets the entries older than the time to live in all shards
+    ///
+    /// Expired entries are not returned but stay stored until then.
+    ///
+    /// # Returns
+    ///
+    /// * `usize` - The number of entries forgotten
+    pub fn purge_expired(&self) -> usize {
+        let now = self.clock.now();
+        let mut purged = 0;
+        for shard in self.shards.iter() {
+            let mut shard = shard.write().unwrap();
+            let before = shard.len();
+            shard.retain(|_, (_, stored_at)| {
+                now.duration_since(*stored_at).unwrap_or_default() < self.ttl
+            });
+            purged += before - shard.len();
+        }
+        self.memory.release(purged * ENTRY_SIZE);
+        purged
+    }
+
+    /// Forgets all entries
+    pub fn clear(&self) {
+        for shard in self.shards.iter() {
+            let mut shard = shard.write().unwrap();
+            self.memory.release(shard.len() * ENTRY_SIZE);
+            shard.clear();
+        }
+    }
+
+    /// Returns the number of stored entries, including expired ones
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.read().unwrap().len()).sum()
+    }
+
+    /// Returns whether no entries are stored
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the shard of a file
+    ///
+    /// File ids are usually handed out in sequence, so consecutive files land
+    /// in different shards.
+    fn shard(&self, fileid: nfs3::fileid3) -> &RwLock<Shard> {
+        &self.shards[(fileid % self.shards.len() as u64) as usize]
+    }
+}
+
+impl MemoryConsumer for AttrCache {
+    fn memory(&self) -> &MemoryCharge {
+        &self.memory
+    }
+
+    fn shrink(&self, bytes: usize) -> usize {
+        let purged = self.purge_expired();
+        let mut evict = bytes.div_ceil(ENTRY_SIZE).saturating_sub(purged);
+        let mut freed = 0;
+        for shard in self.shards.iter() {
+            if evict == 0 {
+                break;
+            }
+            let mut shard = shard.write().unwrap();
+            let victims: Vec<_> = shard.keys().take(evict).copied().collect();
+            for fileid in &victims {
+                shard.remove(fileid);
+            }
+            evict -= victims.len();
+            freed += victims.len() * ENTRY_SIZE;
+        }
+        self.memory.release(freed);
+        purged * ENTRY_SIZE + freed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    #[test]
+    fn returns_attributes_within_ttl() {
+        let clock = Arc::new(ManualClock::new(SystemTime::UNIX_EPOCH));
+        let cache = AttrCache::with_shards(Duration::from_secs(3), 4, clock.clone());
+        for fileid in 1..=8 {
+            cache.insert(nfs3::fattr3 { fileid, size: fileid * 10, ..Default::default() });
+        }
+        assert_eq!(cache.get(5).map(|attr| attr.size), Some(50));
+        assert!(cache.get(9).is_none());
+        cache.invalidate(5);
+        assert!(cache.get(5).is_none());
+
+        clock.advance(Duration::from_secs(2));
+        cache.insert(nfs3::fattr3 { fileid: 1, size: 11, ..Default::default() });
+        clock.advance(Duration::from_secs(1));
+        assert!(cache.get(2).is_none());
+        assert_eq!(cache.get(1).map(|attr| attr.size), Some(11));
+        assert_eq!(cache.len(), 7);
+        assert_eq!(cache.purge_expired(), 6);
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+}
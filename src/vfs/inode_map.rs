@@ -0,0 +1,579 @@
+//! File ids for backends that address files by path.
+//!
+//! NFS identifies files by `fileid`, while directories on disk, object stores
+//! and archives name them by path. Such backends need a stable id for every
+//! path a client has seen, the path behind every id handed out, and both kept
+//! right across `REMOVE` and `RENAME`, including everything below a renamed or
+//! removed directory.
+//!
+//! [`InodeMap`] keeps that mapping. Path components are interned, so the map
+//! stores every distinct name once however many directories contain it. A
+//! renamed file keeps its id, as does everything below a renamed directory.
+//! A path that is removed and created again gets a new id, so stale handles of
+//! the old file do not reach the new one. Ids come from a [`FileIdAllocator`],
+//! which never reuses them by default; maps of long-running servers creating
+//! many files can let it recycle ids once a quarantine window has passed, after
+//! which no client is expected to hold a handle of the old file anymore. The
+//! map also carries the generation the backend returns from
+//! [`super::NFSFileSystem::generation`], which tells handles of an earlier map
+//! apart after the server restarts.
+//!
+//! The map does not touch the backend; callers record what they observe,
+//! typically from `LOOKUP`, `READDIR` and their own changes. It is not
+//! synchronized itself. [`SharedInodeMap`] puts it behind a
+//! [`std::sync::RwLock`]: lookups of ids and paths, by far the most frequent
+//! calls, take the lock for reading and proceed concurrently, while recording
+//! new entries and changes take it for writing. Entries already known are
+//! found under the read lock, so repeated `LOOKUP`s of the same names do not
+//! contend. The lock is never held across an `.await`, unlike a
+//! `tokio::sync::Mutex` around the map, which serializes all lookups.
+
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crate::clock::Clock;
+use crate::protocol::xdr::nfs3;
+
+/// Source of file ids that recycles released ids only after a quarantine
+#[derive(Clone, Debug)]
+pub struct FileIdAllocator {
+    /// Id handed out when no released one can be reused
+    next: nfs3::fileid3,
+    /// Time a released id stays unused, `None` to never reuse ids
+    quarantine: Option<Duration>,
+    /// Released ids with the time of their release, oldest first
+    released: VecDeque<(SystemTime, nfs3::fileid3)>,
+    /// Source of release and reuse times
+    clock: Arc<dyn Clock>,
+}
+
+impl FileIdAllocator {
+    /// Creates an allocator handing out `first` and up, never reusing an id
+    pub fn new(first: nfs3::fileid3) -> Self {
+        Self {
+            next: first,
+            quarantine: None,
+            released: VecDeque::new(),
+            clock: Arc::new(crate::clock::SystemClock),
+        }
+    }
+
+    /// Creates an allocator reusing released ids after `quarantine`
+    ///
+    /// # Arguments
+    ///
+    /// * `first` - Smallest id handed out
+    /// * `quarantine` - Time a released id stays unused; it should exceed the
+    ///   time clients cache handles, e.g. their attribute cache timeout plus
+    ///   the longest time a file is kept open
+    /// * `clock` - Source of the current time
+    pub fn with_quarantine(
+        first: nfs3::fileid3,
+        quarantine: Duration,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        Self { quarantine: Some(quarantine), clock, ..Self::new(first) }
+    }
+
+    /// Returns an id not in use, a released one if its quarantine is over
+    pub fn allocate(&mut self) -> nfs3::fileid3 {
+        if let (Some(quarantine), Some(&(released_at, id))) =
+            (self.quarantine, self.released.front())
+        {
+            let now = self.clock.now();
+            if now.duration_since(released_at).is_ok_and(|elapsed| elapsed >= quarantine) {
+                self.released.pop_front();
+                return id;
+            }
+        }
+        let id = self.next;
+        self.next += 1;
+        id
+    }
+
+    /// Returns an id no longer in use, to be reused after the quarantine
+    pub fn release(&mut self, id: nfs3::fileid3) {
+        if self.quarantine.is_some() {
+            self.released.push_back((self.clock.now(), id));
+        }
+    }
+
+    /// Returns the number of released ids waiting for their quarantine to end
+    pub fn quarantined(&self) -> usize {
+        self.released.len()
+    }
+}
+
+/// Interned path component
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct Symbol(u32);
+
+/// Entry of a file known to the map
+#[derive(Clone, Debug)]
+struct Inode {
+    /// Interned components of the path below the root
+    path: Vec<Symbol>,
+    /// Directory containing the file, the root for the root itself
+    parent: nfs3::fileid3,
+    /// Files known to be in the directory
+    children: BTreeSet<nfs3::fileid3>,
+}
+
+/// Bidirectional mapping between file ids and paths
+#[derive(Clone, Debug)]
+pub struct InodeMap {
+    /// Generation identifying the ids of this map
+    generation: u64,
+    /// Source of the ids of new paths
+    ids: FileIdAllocator,
+    /// Interned names, indexed by symbol
+    names: Vec<Box<[u8]>>,
+    /// Symbol of every interned name
+    symbols: HashMap<Box<[u8]>, Symbol>,
+    /// Entry of every known id
+    inodes: HashMap<nfs3::fileid3, Inode>,
+    /// Id of every known path
+    paths: HashMap<Vec<Symbol>, nfs3::fileid3>,
+}
+
+impl InodeMap {
+    /// File id of the root directory
+    pub const ROOT: nfs3::fileid3 = 1;
+
+    /// Creates a map knowing only the root directory
+    ///
+    /// # Arguments
+    ///
+    /// * `generation` - Generation of the ids, e.g. the server start time in
+    ///   seconds; it should differ between maps a server may be restarted with
+    pub fn new(generation: u64) -> Self {
+        Self::with_allocator(generation, FileIdAllocator::new(Self::ROOT + 1))
+    }
+
+    /// Creates a map knowing only the root directory, taking the ids of new
+    /// paths from `ids`
+    ///
+    /// `ids` must not hand out [`InodeMap::ROOT`] or 0.
+    pub fn with_allocator(generation: u64, ids: FileIdAllocator) -> Self {
+        let root = Inode { path: Vec::new(), parent: Self::ROOT, children: BTreeSet::new() };
+        Self {
+            generation,
+            ids,
+            names: Vec::new(),
+            symbols: HashMap::new(),
+            inodes: HashMap::from([(Self::ROOT, root)]),
+            paths: HashMap::from([(Vec::new(), Self::ROOT)]),
+        }
+    }
+
+    /// Returns the generation of the ids handed out by the map
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Returns the number of known files, including the root
+    pub fn len(&self) -> usize {
+        self.inodes.len()
+    }
+
+    /// Returns whether only the root is known
+    pub fn is_empty(&self) -> bool {
+        self.inodes.len() == 1
+    }
+
+    /// Returns whether an id is known
+    pub fn contains(&self, id: nfs3::fileid3) -> bool {
+        self.inodes.contains_key(&id)
+    }
+
+    /// Returns the id of the entry `name` of a directory, if known
+    pub fn lookup(&self, dirid: nfs3::fileid3, name: &[u8]) -> Option<nfs3::fileid3> {
+        let mut path = self.inodes.get(&dirid)?.path.clone();
+        path.push(*self.symbols.get(name)?);
+        self.paths.get(&path).copied()
+    }
+
+    /// Records the entry `name` of a directory, returning its id
+    ///
+    /// Known entries keep their id, new ones get an id never handed out by the
+    /// map before.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<fileid3, nfsstat3>` - The id of the entry, or NFS3ERR_STALE if
+    ///   the directory is unknown
+    pub fn insert(
+        &mut self,
+        dirid: nfs3::fileid3,
+        name: &[u8],
+    ) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        let mut path = self.inodes.get(&dirid).ok_or(nfs3::nfsstat3::NFS3ERR_STALE)?.path.clone();
+        path.push(self.intern(name));
+        if let Some(id) = self.paths.get(&path) {
+            return Ok(*id);
+        }
+        let id = self.ids.allocate();
+        self.paths.insert(path.clone(), id);
+        self.inodes.insert(id, Inode { path, parent: dirid, children: BTreeSet::new() });
+        self.inodes.get_mut(&dirid).unwrap().children.insert(id);
+        Ok(id)
+    }
+
+    /// Returns the components of the path of a file below the root, or `None`
+    /// if the id is unknown
+    ///
+    /// The root has no components.
+    pub fn components(&self, id: nfs3::fileid3) -> Option<Vec<&[u8]>> {
+        let inode = self.inodes.get(&id)?;
+        Some(inode.path.iter().map(|symbol| &*self.names[symbol.0 as usize]).collect())
+    }
+
+    /// Returns the path of a file relative to the root, or `None` if the id is
+    /// unknown
+    ///
+    /// The root has an empty path, so joining the result to the root of the
+    /// backend gives the full path.
+    #[cfg(unix)]
+    pub fn path(&self, id: nfs3::fileid3) -> Option<std::path::PathBuf> {
+        use std::os::unix::ffi::OsStrExt;
+
+        let components = self.components(id)?;
+        Some(components.into_iter().map(std::ffi::OsStr::from_bytes).collect())
+    }
+
+    /// Returns the name of a file in its directory, empty for the root
+    pub fn name(&self, id: nfs3::fileid3) -> Option<&[u8]> {
+        let inode = self.inodes.get(&id)?;
+        Some(inode.path.last().map_or(&[][..], |symbol| &*self.names[symbol.0 as usize]))
+    }
+
+    /// Returns the directory containing a file, the root for the root itself
+    pub fn parent(&self, id: nfs3::fileid3) -> Option<nfs3::fileid3> {
+        Some(self.inodes.get(&id)?.parent)
+    }
+
+    /// Returns the ids of the known entries of a directory, in id order
+    pub fn children(&self, dirid: nfs3::fileid3) -> Vec<nfs3::fileid3> {
+        self.inodes
+            .get(&dirid)
+            .map(|dir| dir.children.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Forgets a file and, for a directory, everything below it
+    ///
+    /// The ids forgotten are released to the [`FileIdAllocator`]. The root
+    /// cannot be removed.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<fileid3>` - The ids forgotten, empty if the id was unknown
+    pub fn remove(&mut self, id: nfs3::fileid3) -> Vec<nfs3::fileid3> {
+        if id == Self::ROOT || !self.inodes.contains_key(&id) {
+            return Vec::new();
+        }
+        let parent = self.inodes[&id].parent;
+        let removed = self.subtree(id);
+        for removed_id in &removed {
+            if let Some(inode) = self.inodes.remove(removed_id) {
+                self.paths.remove(&inode.path);
+                self.ids.release(*removed_id);
+            }
+        }
+        if let Some(parent) = self.inodes.get_mut(&parent) {
+            parent.children.remove(&id);
+        }
+        removed
+    }
+
+    /// Forgets the entry `name` of a directory, as after `REMOVE` or `RMDIR`
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<fileid3>` - The ids forgotten, empty if the entry was unknown
+    pub fn remove_entry(&mut self, dirid: nfs3::fileid3, name: &[u8]) -> Vec<nfs3::fileid3> {
+        match self.lookup(dirid, name) {
+            Some(id) => self.remove(id),
+            None => Vec::new(),
+        }
+    }
+
+    /// Moves an entry to a new name, as after `RENAME`
+    ///
+    /// The moved file and everything below it keep their ids. An entry that
+    /// existed under the new name is replaced and forgotten.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<fileid3>, nfsstat3>` - The ids forgotten with the
+    ///   replaced entry, NFS3ERR_NOENT if the source is unknown, NFS3ERR_STALE
+    ///   if the target directory is unknown, or NFS3ERR_INVAL if a directory
+    ///   would be moved below itself
+    pub fn rename(
+        &mut self,
+        from_dirid: nfs3::fileid3,
+        from_name: &[u8],
+        to_dirid: nfs3::fileid3,
+        to_name: &[u8],
+    ) -> Result<Vec<nfs3::fileid3>, nfs3::nfsstat3> {
+        let id = self.lookup(from_dirid, from_name).ok_or(nfs3::nfsstat3::NFS3ERR_NOENT)?;
+        if !self.inodes.contains_key(&to_dirid) {
+            return Err(nfs3::nfsstat3::NFS3ERR_STALE);
+        }
+        let moved = self.subtree(id);
+        if moved.contains(&to_dirid) {
+            return Err(nfs3::nfsstat3::NFS3ERR_INVAL);
+        }
+        let replaced = match self.lookup(to_dirid, to_name) {
+            Some(target) if target == id => return Ok(Vec::new()),
+            Some(target) => self.remove(target),
+            None => Vec::new(),
+        };
+
+        let old_len = self.inodes[&id].path.len();
+        let mut new_prefix = self.inodes[&to_dirid].path.clone();
+        new_prefix.push(self.intern(to_name));
+        for moved_id in &moved {
+            let inode = self.inodes.get_mut(moved_id).unwrap();
+            let mut path = new_prefix.clone();
+            path.extend_from_slice(&inode.path[old_len..]);
+            let old_path = std::mem::replace(&mut inode.path, path.clone());
+            self.paths.remove(&old_path);
+            self.paths.insert(path, *moved_id);
+        }
+        self.inodes.get_mut(&from_dirid).unwrap().children.remove(&id);
+        self.inodes.get_mut(&to_dirid).unwrap().children.insert(id);
+        self.inodes.get_mut(&id).unwrap().parent = to_dirid;
+        Ok(replaced)
+    }
+
+    /// Returns the symbol of a name, interning it if needed
+    fn intern(&mut self, name: &[u8]) -> Symbol {
+        if let Some(symbol) = self.symbols.get(name) {
+            return *symbol;
+        }
+        let symbol = Symbol(u32::try_from(self.names.len()).expect("too many distinct names"));
+        self.names.push(name.into());
+        self.symbols.insert(name.into(), symbol);
+        symbol
+    }
+
+    /// Returns a file and everything below it, the file first
+    fn subtree(&self, id: nfs3::fileid3) -> Vec<nfs3::fileid3> {
+        let mut ids = vec![id];
+        let mut next = 0;
+        while let Some(current) = ids.get(next).copied() {
+            if let Some(inode) = self.inodes.get(&current) {
+                ids.extend(inode.children.iter().copied());
+            }
+            next += 1;
+        }
+        ids
+    }
+}
+
+/// [`InodeMap`] shared by concurrent calls
+///
+/// Methods reading the map take its lock for reading, methods changing it for
+/// writing. Results borrowing from the map are returned owned.
+#[derive(Debug)]
+pub struct SharedInodeMap {
+    map: RwLock<InodeMap>,
+}
+
+impl SharedInodeMap {
+    /// Shares a map
+    pub fn new(map: InodeMap) -> Self {
+        Self { map: RwLock::new(map) }
+    }
+
+    /// Returns the generation of the ids handed out by the map
+    pub fn generation(&self) -> u64 {
+        self.read().generation()
+    }
+
+    /// Returns the number of known files, including the root
+    pub fn len(&self) -> usize {
+        self.read().len()
+    }
+
+    /// Returns whether only the root is known
+    pub fn is_empty(&self) -> bool {
+        self.read().is_empty()
+    }
+
+    /// Returns whether an id is known
+    pub fn contains(&self, id: nfs3::fileid3) -> bool {
+        self.read().contains(id)
+    }
+
+    /// Returns the id of the entry `name` of a directory, if known
+    pub fn lookup(&self, dirid: nfs3::fileid3, name: &[u8]) -> Option<nfs3::fileid3> {
+        self.read().lookup(dirid, name)
+    }
+
+    /// Records the entry `name` of a directory, returning its id
+    ///
+    /// Known entries are found under the read lock, only new ones take the
+    /// write lock. See [`InodeMap::insert`].
+    pub fn insert(
+        &self,
+        dirid: nfs3::fileid3,
+        name: &[u8],
+    ) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        if let Some(id) = self.lookup(dirid, name) {
+            return Ok(id);
+        }
+        self.write().insert(dirid, name)
+    }
+
+    /// Returns the components of the path of a file below the root
+    pub fn components(&self, id: nfs3::fileid3) -> Option<Vec<Vec<u8>>> {
+        let map = self.read();
+        Some(map.components(id)?.into_iter().map(<[u8]>::to_vec).collect())
+    }
+
+    /// Returns the path of a file relative to the root
+    #[cfg(unix)]
+    pub fn path(&self, id: nfs3::fileid3) -> Option<std::path::PathBuf> {
+        self.read().path(id)
+    }
+
+    /// Returns the name of a file in its directory, empty for the root
+    pub fn name(&self, id: nfs3::fileid3) -> Option<Vec<u8>> {
+        self.read().name(id).map(<[u8]>::to_vec)
+    }
+
+    /// Returns the directory containing a file, the root for the root itself
+    pub fn parent(&self, id: nfs3::fileid3) -> Option<nfs3::fileid3> {
+        self.read().parent(id)
+    }
+
+    /// Returns the ids of the known entries of a directory, in id order
+    pub fn children(&self, dirid: nfs3::fileid3) -> Vec<nfs3::fileid3> {
+        self.read().children(dirid)
+    }
+
+    /// Forgets a file and everything below it, see [`InodeMap::remove`]
+    pub fn remove(&self, id: nfs3::fileid3) -> Vec<nfs3::fileid3> {
+        self.write().remove(id)
+    }
+
+    /// Forgets the entry `name` of a directory, see [`InodeMap::remove_entry`]
+    pub fn remove_entry(&self, dirid: nfs3::fileid3, name: &[u8]) -> Vec<nfs3::fileid3> {
+        self.write().remove_entry(dirid, name)
+    }
+
+    /// Moves an entry to a new name, see [`InodeMap::rename`]
+    pub fn rename(
+        &self,
+        from_dirid: nfs3::fileid3,
+        from_name: &[u8],
+        to_dirid: nfs3::fileid3,
+        to_name: &[u8],
+    ) -> Result<Vec<nfs3::fileid3>, nfs3::nfsstat3> {
+        self.write().rename(from_dirid, from_name, to_dirid, to_name)
+    }
+
+    /// Runs `f` with the map locked for reading, for several lookups that must
+    /// see the same state
+    pub fn with_read<R>(&self, f: impl FnOnce(&InodeMap) -> R) -> R {
+        f(&self.read())
+    }
+
+    /// Runs `f` with the map locked for writing, for several changes that must
+    /// be applied at once
+    pub fn with_write<R>(&self, f: impl FnOnce(&mut InodeMap) -> R) -> R {
+        f(&mut self.write())
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, InodeMap> {
+        self.map.read().unwrap()
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, InodeMap> {
+        self.map.write().unwrap()
+    }
+}
+
+impl Default for SharedInodeMap {
+    fn default() -> Self {
+        Self::new(InodeMap::new(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_ids_and_paths_across_changes() {
+        let mut map = InodeMap::new(7);
+        let dir = map.insert(InodeMap::ROOT, b"dir").unwrap();
+        let file = map.insert(dir, b"file").unwrap();
+        assert_eq!(map.insert(dir, b"file").ok(), Some(file));
+        assert_eq!(map.lookup(dir, b"file"), Some(file));
+        assert_eq!(map.components(file).unwrap(), [&b"dir"[..], b"file"]);
+        assert_eq!(map.parent(file), Some(dir));
+
+        let other = map.insert(InodeMap::ROOT, b"other").unwrap();
+        assert_eq!(map.rename(InodeMap::ROOT, b"dir", other, b"moved").unwrap(), []);
+        assert_eq!(map.components(file).unwrap(), [&b"other"[..], b"moved", b"file"]);
+        assert_eq!(map.lookup(InodeMap::ROOT, b"dir"), None);
+        assert_eq!(map.children(other), [dir]);
+        let err = map.rename(other, b"moved", file, b"x").unwrap_err();
+        assert!(matches!(err, nfs3::nfsstat3::NFS3ERR_INVAL));
+
+        assert_eq!(map.remove_entry(InodeMap::ROOT, b"other"), [other, dir, file]);
+        assert!(map.is_empty());
+        let again = map.insert(InodeMap::ROOT, b"other").unwrap();
+        assert!(again > file, "ids are not reused");
+        assert_eq!(map.generation(), 7);
+    }
+
+    #[test]
+    fn reuses_ids_after_quarantine() {
+        let clock = Arc::new(crate::clock::ManualClock::new(SystemTime::UNIX_EPOCH));
+        let ids = FileIdAllocator::with_quarantine(2, Duration::from_secs(60), clock.clone());
+        let mut map = InodeMap::with_allocator(1, ids);
+        let file = map.insert(InodeMap::ROOT, b"file").unwrap();
+        map.remove(file);
+        let second = map.insert(InodeMap::ROOT, b"file").unwrap();
+        assert_ne!(second, file, "id reused within the quarantine");
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(map.insert(InodeMap::ROOT, b"third").ok(), Some(file));
+
+        let mut ids = FileIdAllocator::new(2);
+        let id = ids.allocate();
+        ids.release(id);
+        assert_eq!((ids.allocate(), ids.quarantined()), (3, 0));
+    }
+
+    #[test]
+    fn shared_map_serves_concurrent_lookups() {
+        let map = Arc::new(SharedInodeMap::new(InodeMap::new(3)));
+        let dir = map.insert(InodeMap::ROOT, b"dir").unwrap();
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let map = map.clone();
+                std::thread::spawn(move || {
+                    (0..100).map(|i| map.insert(dir, format!("f{i}").as_bytes()).unwrap()).sum()
+                })
+            })
+            .collect();
+        let sums: Vec<nfs3::fileid3> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+        // every thread sees the same id for the same name
+        assert!(sums.windows(2).all(|pair| pair[0] == pair[1]));
+        assert_eq!(map.len(), 102);
+        assert_eq!(
+            map.components(map.lookup(dir, b"f7").unwrap()).unwrap(),
+            [b"dir".to_vec(), b"f7".to_vec()]
+        );
+        assert_eq!(map.rename(InodeMap::ROOT, b"dir", InodeMap::ROOT, b"moved").unwrap(), []);
+        assert_eq!(map.name(dir).unwrap(), b"moved");
+        assert_eq!(map.with_read(|map| map.children(dir).len()), 100);
+        assert_eq!(map.remove(dir).len(), 101);
+        assert!(map.is_empty());
+    }
+}
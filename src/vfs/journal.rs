@@ -0,0 +1,842 @@
+//! Write-ahead journaling of changes for backends without crash consistency.
+//!
+//! Backends that apply a change in several steps, such as a rename copying a
+//! file before deleting the original, or object stores updating data and
+//! metadata separately, leave the file system half changed when the server
+//! crashes in between. [`JournalFs`] wraps such a backend and appends every
+//! change to a journal file, and syncs it, before passing the change on. Once
+//! the backend returns, the record is marked done. When the server starts
+//! again, [`JournalFs::open`] applies the changes that were never marked done
+//! once more, in the order they were journaled, so every change a client may
+//! have seen is complete.
+//!
+//! Replayed changes are expected to find their effect partly or completely in
+//! place, so the errors of creating existing entries and removing missing ones
+//! are ignored during replay. File IDs of the backend must be stable across
+//! restarts, as records refer to files by ID.
+//!
+//! Records are framed by their length and a CRC-32C checksum; a record torn by
+//! a crash ends the journal. The journal is emptied at startup and whenever it
+//! grew beyond [`JournalFs::with_max_size`] while no change is in flight.
+
+use std::io::{self, Cursor};
+use std::path::Path;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+
+use super::{
+    Capabilities, NFSFileSystem, ReadDirCookie, ReadDirCookieKind, ReadDirResult,
+    ReadDirSimpleResult, SeekContent,
+};
+use crate::protocol::nfs::v3::crc32c;
+use crate::protocol::xdr::{deserialize, nfs3, Deserialize, Serialize};
+
+/// Size beyond which the journal is emptied by default
+pub const DEFAULT_MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Bytes framing every record: length and checksum of the payload
+const FRAME_SIZE: usize = 8;
+
+/// A journaled change, or the mark of one being done
+#[derive(Clone, Debug)]
+enum Record {
+    Done,
+    Setattr {
+        id: nfs3::fileid3,
+        attr: nfs3::sattr3,
+    },
+    Write {
+        id: nfs3::fileid3,
+        offset: u64,
+        data: Vec<u8>,
+    },
+    Create {
+        dirid: nfs3::fileid3,
+        name: Vec<u8>,
+        attr: nfs3::sattr3,
+    },
+    CreateExclusive {
+        dirid: nfs3::fileid3,
+        name: Vec<u8>,
+    },
+    Mkdir {
+        dirid: nfs3::fileid3,
+        name: Vec<u8>,
+    },
+    Remove {
+        dirid: nfs3::fileid3,
+        name: Vec<u8>,
+    },
+    Rename {
+        from_dirid: nfs3::fileid3,
+        from_name: Vec<u8>,
+        to_dirid: nfs3::fileid3,
+        to_name: Vec<u8>,
+    },
+    Symlink {
+        dirid: nfs3::fileid3,
+        name: Vec<u8>,
+        target: Vec<u8>,
+        attr: nfs3::sattr3,
+    },
+    Link {
+        id: nfs3::fileid3,
+        dirid: nfs3::fileid3,
+        name: Vec<u8>,
+    },
+    Mknod {
+        dirid: nfs3::fileid3,
+        name: Vec<u8>,
+        ftype: nfs3::ftype3,
+        device: nfs3::specdata3,
+        attr: nfs3::sattr3,
+    },
+    Allocate {
+        id: nfs3::fileid3,
+        offset: u64,
+        len: u64,
+    },
+    Deallocate {
+        id: nfs3::fileid3,
+        offset: u64,
+        len: u64,
+    },
+}
+
+impl Record {
+    /// Encodes the record with its sequence number, framed
+    fn encode(&self, seq: u64) -> io::Result<Vec<u8>> {
+        let mut payload = Vec::new();
+        let out = &mut payload;
+        match self {
+            Record::Done => 0u32.serialize(out)?,
+            Record::Setattr { id, attr } => {
+                1u32.serialize(out)?;
+                id.serialize(out)?;
+                attr.serialize(out)?;
+            }
+            Record::Write { id, offset, data } => {
+                2u32.serialize(out)?;
+                id.serialize(out)?;
+                offset.serialize(out)?;
+                data[..].serialize(out)?;
+            }
+            Record::Create { dirid, name, attr } => {
+                3u32.serialize(out)?;
+                dirid.serialize(out)?;
+                name[..].serialize(out)?;
+                attr.serialize(out)?;
+            }
+            Record::CreateExclusive { dirid, name } => {
+                4u32.serialize(out)?;
+                dirid.serialize(out)?;
+                name[..].serialize(out)?;
+            }
+            Record::Mkdir { dirid, name } => {
+                5u32.serialize(out)?;
+                dirid.serialize(out)?;
+                name[..].serialize(out)?;
+            }
+            Record::Remove { dirid, name } => {
+                6u32.serialize(out)?;
+                dirid.serialize(out)?;
+                name[..].serialize(out)?;
+            }
+            Record::Rename { from_dirid, from_name, to_dirid, to_name } => {
+                7u32.serialize(out)?;
+                from_dirid.serialize(out)?;
+                from_name[..].serialize(out)?;
+                to_dirid.serialize(out)?;
+                to_name[..].serialize(out)?;
+            }
+            Record::Symlink { dirid, name, target, attr } => {
+                8u32.serialize(out)?;
+                dirid.serialize(out)?;
+                name[..].serialize(out)?;
+                target[..].serialize(out)?;
+                attr.serialize(out)?;
+            }
+            Record::Link { id, dirid, name } => {
+                9u32.serialize(out)?;
+                id.serialize(out)?;
+                dirid.serialize(out)?;
+                name[..].serialize(out)?;
+            }
+            Record::Mknod { dirid, name, ftype, device, attr } => {
+                10u32.serialize(out)?;
+                dirid.serialize(out)?;
+                name[..].serialize(out)?;
+                ftype.serialize(out)?;
+                device.serialize(out)?;
+                attr.serialize(out)?;
+            }
+            Record::Allocate { id, offset, len } => {
+                11u32.serialize(out)?;
+                id.serialize(out)?;
+                offset.serialize(out)?;
+                len.serialize(out)?;
+            }
+            Record::Deallocate { id, offset, len } => {
+                12u32.serialize(out)?;
+                id.serialize(out)?;
+                offset.serialize(out)?;
+                len.serialize(out)?;
+            }
+        }
+        seq.serialize(out)?;
+        let mut framed = Vec::with_capacity(FRAME_SIZE + payload.len());
+        framed.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        framed.extend_from_slice(&crc32c(&payload).to_be_bytes());
+        framed.extend_from_slice(&payload);
+        Ok(framed)
+    }
+
+    /// Decodes the payload of a record and its sequence number
+    fn decode(payload: &[u8]) -> io::Result<(Record, u64)> {
+        let input = &mut Cursor::new(payload);
+        let sattr = |input: &mut Cursor<&[u8]>| -> io::Result<nfs3::sattr3> {
+            let mut attr = nfs3::sattr3::default();
+            attr.deserialize(input)?;
+            Ok(attr)
+        };
+        let record = match deserialize::<u32>(input)? {
+            0 => Record::Done,
+            1 => Record::Setattr { id: deserialize(input)?, attr: sattr(input)? },
+            2 => Record::Write {
+                id: deserialize(input)?,
+                offset: deserialize(input)?,
+                data: deserialize(input)?,
+            },
+            3 => Record::Create {
+                dirid: deserialize(input)?,
+                name: deserialize(input)?,
+                attr: sattr(input)?,
+            },
+            4 => Record::CreateExclusive { dirid: deserialize(input)?, name: deserialize(input)? },
+            5 => Record::Mkdir { dirid: deserialize(input)?, name: deserialize(input)? },
+            6 => Record::Remove { dirid: deserialize(input)?, name: deserialize(input)? },
+            7 => Record::Rename {
+                from_dirid: deserialize(input)?,
+                from_name: deserialize(input)?,
+                to_dirid: deserialize(input)?,
+                to_name: deserialize(input)?,
+            },
+            8 => Record::Symlink {
+                dirid: deserialize(input)?,
+                name: deserialize(input)?,
+                target: deserialize(input)?,
+                attr: sattr(input)?,
+            },
+            9 => Record::Link {
+                id: deserialize(input)?,
+                dirid: deserialize(input)?,
+                name: deserialize(input)?,
+            },
+            10 => Record::Mknod {
+                dirid: deserialize(input)?,
+                name: deserialize(input)?,
+                ftype: deserialize(input)?,
+                device: deserialize(input)?,
+                attr: sattr(input)?,
+            },
+            11 => Record::Allocate {
+                id: deserialize(input)?,
+                offset: deserialize(input)?,
+                len: deserialize(input)?,
+            },
+            12 => Record::Deallocate {
+                id: deserialize(input)?,
+                offset: deserialize(input)?,
+                len: deserialize(input)?,
+            },
+            kind => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown journal record {kind}"),
+                ))
+            }
+        };
+        Ok((record, deserialize(input)?))
+    }
+
+    /// Applies a change again during replay
+    async fn replay(self, fs: &(dyn NFSFileSystem + Send + Sync)) -> Result<(), nfs3::nfsstat3> {
+        let name = |name: Vec<u8>| nfs3::filename3::from(name);
+        let result = match self {
+            Record::Done => Ok(()),
+            Record::Setattr { id, attr } => fs.setattr(id, attr).await.map(drop),
+            Record::Write { id, offset, data } => fs.write(id, offset, &data).await.map(drop),
+            Record::Create { dirid, name: n, attr } => {
+                fs.create(dirid, &name(n), attr).await.map(drop)
+            }
+            Record::CreateExclusive { dirid, name: n } => {
+                fs.create_exclusive(dirid, &name(n)).await.map(drop)
+            }
+            Record::Mkdir { dirid, name: n } => fs.mkdir(dirid, &name(n)).await.map(drop),
+            Record::Remove { dirid, name: n } => fs.remove(dirid, &name(n)).await,
+            Record::Rename { from_dirid, from_name, to_dirid, to_name } => {
+                fs.rename(from_dirid, &name(from_name), to_dirid, &name(to_name)).await
+            }
+            Record::Symlink { dirid, name: n, target, attr } => {
+                fs.symlink(dirid, &name(n), &target.into(), &attr).await.map(drop)
+            }
+            Record::Link { id, dirid, name: n } => fs.link(id, dirid, &name(n)).await.map(drop),
+            Record::Mknod { dirid, name: n, ftype, device, attr } => {
+                fs.mknod(dirid, &name(n), ftype, device, &attr).await.map(drop)
+            }
+            Record::Allocate { id, offset, len } => fs.allocate(id, offset, len).await.map(drop),
+            Record::Deallocate { id, offset, len } => {
+                fs.deallocate(id, offset, len).await.map(drop)
+            }
+        };
+        // the change was completely or partly in place already
+        match result {
+            Err(nfs3::nfsstat3::NFS3ERR_EXIST | nfs3::nfsstat3::NFS3ERR_NOENT) => Ok(()),
+            result => result,
+        }
+    }
+}
+
+/// Parses the records of a journal, up to the first torn or corrupt one
+fn parse(journal: &[u8]) -> Vec<(Record, u64)> {
+    let mut records = Vec::new();
+    let mut rest = journal;
+    while rest.len() >= FRAME_SIZE {
+        let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        let crc = u32::from_be_bytes(rest[4..8].try_into().unwrap());
+        let Some(payload) = rest[FRAME_SIZE..].get(..len) else {
+            break;
+        };
+        if crc32c(payload) != crc {
+            break;
+        }
+        match Record::decode(payload) {
+            Ok(record) => records.push(record),
+            Err(err) => {
+                warn!("journal record undecodable: {}", err);
+                break;
+            }
+        }
+        rest = &rest[FRAME_SIZE + len..];
+    }
+    if !rest.is_empty() {
+        warn!("journal ends with {} bytes of a torn record", rest.len());
+    }
+    records
+}
+
+/// Open journal file
+struct Journal {
+    file: File,
+    /// Current size of the file
+    size: u64,
+    /// Sequence number of the next change
+    next_seq: u64,
+    /// Number of changes journaled but not done yet
+    in_flight: usize,
+}
+
+/// File system combinator journaling changes before applying them
+pub struct JournalFs<F> {
+    inner: F,
+    journal: Mutex<Journal>,
+    max_size: u64,
+}
+
+impl<F: NFSFileSystem + Send + Sync> JournalFs<F> {
+    /// Wraps `inner`, journaling to the file at `path`
+    ///
+    /// Changes an earlier instance journaled but never completed are applied to
+    /// `inner` first. Changes failing again are logged and dropped.
+    pub async fn open(inner: F, path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let contents = match tokio::fs::read(path).await {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        let records = parse(&contents);
+        let done: std::collections::HashSet<u64> = records
+            .iter()
+            .filter(|(record, _)| matches!(record, Record::Done))
+            .map(|(_, seq)| *seq)
+            .collect();
+        let mut replayed = 0;
+        for (record, seq) in records {
+            if matches!(record, Record::Done) || done.contains(&seq) {
+                continue;
+            }
+            if let Err(stat) = record.replay(&inner).await {
+                warn!("journaled change {} failed again: {:?}", seq, stat);
+            }
+            replayed += 1;
+        }
+        if replayed > 0 {
+            info!("replayed {} journaled changes from {}", replayed, path.display());
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path).await?;
+        // everything is in place, start over
+        file.set_len(0).await?;
+        file.sync_all().await?;
+        let journal = Journal { file, size: 0, next_seq: 0, in_flight: 0 };
+        Ok(Self { inner, journal: Mutex::new(journal), max_size: DEFAULT_MAX_SIZE })
+    }
+
+    /// Empties the journal once it grew beyond `bytes`, when no change is in
+    /// flight; [`DEFAULT_MAX_SIZE`] by default
+    pub fn with_max_size(mut self, bytes: u64) -> Self {
+        self.max_size = bytes;
+        self
+    }
+
+    /// Returns the wrapped file system
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// Journals a change durably, returning its sequence number
+    async fn begin(&self, record: Record) -> Result<u64, nfs3::nfsstat3> {
+        let mut journal = self.journal.lock().await;
+        let seq = journal.next_seq;
+        let result = async {
+            let framed = record.encode(seq)?;
+            journal.file.write_all(&framed).await?;
+            journal.file.sync_data().await?;
+            io::Result::Ok(framed.len() as u64)
+        }
+        .await;
+        match result {
+            Ok(len) => {
+                journal.size += len;
+                journal.next_seq += 1;
+                journal.in_flight += 1;
+                Ok(seq)
+            }
+            Err(err) => {
+                error!("cannot journal change: {}", err);
+                Err(nfs3::nfsstat3::NFS3ERR_IO)
+            }
+        }
+    }
+
+    /// Marks a change done, whether it succeeded or not
+    ///
+    /// The mark is not synced: a lost mark only replays a change in place.
+    async fn end(&self, seq: u64) {
+        let mut journal = self.journal.lock().await;
+        journal.in_flight -= 1;
+        if journal.in_flight == 0 && journal.size > self.max_size {
+            match journal.file.set_len(0).await {
+                Ok(()) => {
+                    journal.size = 0;
+                    return;
+                }
+                Err(err) => warn!("cannot empty journal: {}", err),
+            }
+        }
+        let written = match Record::Done.encode(seq) {
+            Ok(framed) => {
+                async {
+                    journal.file.write_all(&framed).await?;
+                    // tokio hands writes to a blocking thread, flushing waits for it
+                    journal.file.flush().await?;
+                    io::Result::Ok(framed.len() as u64)
+                }
+                .await
+            }
+            Err(err) => Err(err),
+        };
+        match written {
+            Ok(len) => journal.size += len,
+            Err(err) => warn!("cannot mark journaled change {} done: {}", seq, err),
+        }
+    }
+
+    /// Journals a change, applies it and marks it done
+    async fn journaled<T>(
+        &self,
+        record: Record,
+        apply: impl std::future::Future<Output = Result<T, nfs3::nfsstat3>>,
+    ) -> Result<T, nfs3::nfsstat3> {
+        let seq = self.begin(record).await?;
+        let result = apply.await;
+        self.end(seq).await;
+        result
+    }
+}
+
+#[async_trait]
+impl<F: NFSFileSystem + Send + Sync> NFSFileSystem for JournalFs<F> {
+    fn generation(&self) -> u64 {
+        self.inner.generation()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn root_dir(&self) -> nfs3::fileid3 {
+        self.inner.root_dir()
+    }
+
+    async fn lookup(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        self.inner.lookup(dirid, filename).await
+    }
+
+    async fn getattr(&self, id: nfs3::fileid3) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        self.inner.getattr(id).await
+    }
+
+    async fn getattr_bulk(
+        &self,
+        ids: &[nfs3::fileid3],
+    ) -> Vec<Result<nfs3::fattr3, nfs3::nfsstat3>> {
+        self.inner.getattr_bulk(ids).await
+    }
+
+    async fn setattr(
+        &self,
+        id: nfs3::fileid3,
+        setattr: nfs3::sattr3,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        let record = Record::Setattr { id, attr: setattr };
+        self.journaled(record, self.inner.setattr(id, setattr)).await
+    }
+
+    fn reports_pre_op_attrs(&self) -> bool {
+        self.inner.reports_pre_op_attrs()
+    }
+
+    async fn setattr_wcc(
+        &self,
+        id: nfs3::fileid3,
+        setattr: nfs3::sattr3,
+    ) -> Result<(Option<nfs3::wcc_attr>, nfs3::fattr3), nfs3::nfsstat3> {
+        let record = Record::Setattr { id, attr: setattr };
+        self.journaled(record, self.inner.setattr_wcc(id, setattr)).await
+    }
+
+    async fn read(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        count: u32,
+    ) -> Result<(Vec<u8>, bool), nfs3::nfsstat3> {
+        self.inner.read(id, offset, count).await
+    }
+
+    async fn write(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        let record = Record::Write { id, offset, data: data.to_vec() };
+        self.journaled(record, self.inner.write(id, offset, data)).await
+    }
+
+    async fn write_partial(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(nfs3::fattr3, u32), nfs3::nfsstat3> {
+        // a replay writes all of the data, which a short write also allows
+        let record = Record::Write { id, offset, data: data.to_vec() };
+        self.journaled(record, self.inner.write_partial(id, offset, data)).await
+    }
+
+    async fn write_wcc(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(Option<nfs3::wcc_attr>, nfs3::fattr3, u32), nfs3::nfsstat3> {
+        let record = Record::Write { id, offset, data: data.to_vec() };
+        self.journaled(record, self.inner.write_wcc(id, offset, data)).await
+    }
+
+    async fn create(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        attr: nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        let record = Record::Create { dirid, name: filename.0.clone(), attr };
+        self.journaled(record, self.inner.create(dirid, filename, attr)).await
+    }
+
+    async fn create_exclusive(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        let record = Record::CreateExclusive { dirid, name: filename.0.clone() };
+        self.journaled(record, self.inner.create_exclusive(dirid, filename)).await
+    }
+
+    async fn mkdir(
+        &self,
+        dirid: nfs3::fileid3,
+        dirname: &nfs3::filename3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        let record = Record::Mkdir { dirid, name: dirname.0.clone() };
+        self.journaled(record, self.inner.mkdir(dirid, dirname)).await
+    }
+
+    async fn remove(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> Result<(), nfs3::nfsstat3> {
+        let record = Record::Remove { dirid, name: filename.0.clone() };
+        self.journaled(record, self.inner.remove(dirid, filename)).await
+    }
+
+    async fn rename(
+        &self,
+        from_dirid: nfs3::fileid3,
+        from_filename: &nfs3::filename3,
+        to_dirid: nfs3::fileid3,
+        to_filename: &nfs3::filename3,
+    ) -> Result<(), nfs3::nfsstat3> {
+        let record = Record::Rename {
+            from_dirid,
+            from_name: from_filename.0.clone(),
+            to_dirid,
+            to_name: to_filename.0.clone(),
+        };
+        let apply = self.inner.rename(from_dirid, from_filename, to_dirid, to_filename);
+        self.journaled(record, apply).await
+    }
+
+    async fn readdir(
+        &self,
+        dirid: nfs3::fileid3,
+        start_after: nfs3::fileid3,
+        max_entries: usize,
+    ) -> Result<ReadDirResult, nfs3::nfsstat3> {
+        self.inner.readdir(dirid, start_after, max_entries).await
+    }
+
+    fn readdir_cookie_kind(&self) -> ReadDirCookieKind {
+        self.inner.readdir_cookie_kind()
+    }
+
+    fn readdir_entry_cookie(
+        &self,
+        dirid: nfs3::fileid3,
+        fileid: nfs3::fileid3,
+        name: &nfs3::filename3,
+    ) -> nfs3::cookie3 {
+        self.inner.readdir_entry_cookie(dirid, fileid, name)
+    }
+
+    fn readdir_has_attributes(&self) -> bool {
+        self.inner.readdir_has_attributes()
+    }
+
+    async fn readdir_with_cookie(
+        &self,
+        dirid: nfs3::fileid3,
+        cookie: ReadDirCookie,
+        max_entries: usize,
+    ) -> Result<ReadDirResult, nfs3::nfsstat3> {
+        self.inner.readdir_with_cookie(dirid, cookie, max_entries).await
+    }
+
+    async fn readdir_simple(
+        &self,
+        dirid: nfs3::fileid3,
+        start_after: nfs3::fileid3,
+        count: usize,
+    ) -> Result<ReadDirSimpleResult, nfs3::nfsstat3> {
+        self.inner.readdir_simple(dirid, start_after, count).await
+    }
+
+    async fn symlink(
+        &self,
+        dirid: nfs3::fileid3,
+        linkname: &nfs3::filename3,
+        symlink: &nfs3::nfspath3,
+        attr: &nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        let record = Record::Symlink {
+            dirid,
+            name: linkname.0.clone(),
+            target: symlink.0.clone(),
+            attr: *attr,
+        };
+        self.journaled(record, self.inner.symlink(dirid, linkname, symlink, attr)).await
+    }
+
+    async fn readlink(&self, id: nfs3::fileid3) -> Result<nfs3::nfspath3, nfs3::nfsstat3> {
+        self.inner.readlink(id).await
+    }
+
+    async fn link(
+        &self,
+        file_id: nfs3::fileid3,
+        link_dir_id: nfs3::fileid3,
+        link_name: &nfs3::filename3,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        let record = Record::Link { id: file_id, dirid: link_dir_id, name: link_name.0.clone() };
+        self.journaled(record, self.inner.link(file_id, link_dir_id, link_name)).await
+    }
+
+    async fn mknod(
+        &self,
+        dir_id: nfs3::fileid3,
+        name: &nfs3::filename3,
+        ftype: nfs3::ftype3,
+        specdata: nfs3::specdata3,
+        attrs: &nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        let record = Record::Mknod {
+            dirid: dir_id,
+            name: name.0.clone(),
+            ftype,
+            device: specdata,
+            attr: *attrs,
+        };
+        self.journaled(record, self.inner.mknod(dir_id, name, ftype, specdata, attrs)).await
+    }
+
+    async fn commit(
+        &self,
+        file_id: nfs3::fileid3,
+        offset: u64,
+        count: u32,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        self.inner.commit(file_id, offset, count).await
+    }
+
+    async fn allocate(
+        &self,
+        file_id: nfs3::fileid3,
+        offset: u64,
+        length: u64,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        let record = Record::Allocate { id: file_id, offset, len: length };
+        self.journaled(record, self.inner.allocate(file_id, offset, length)).await
+    }
+
+    async fn deallocate(
+        &self,
+        file_id: nfs3::fileid3,
+        offset: u64,
+        length: u64,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        let record = Record::Deallocate { id: file_id, offset, len: length };
+        self.journaled(record, self.inner.deallocate(file_id, offset, length)).await
+    }
+
+    async fn seek(
+        &self,
+        file_id: nfs3::fileid3,
+        offset: u64,
+        what: SeekContent,
+    ) -> Result<(u64, bool), nfs3::nfsstat3> {
+        self.inner.seek(file_id, offset, what).await
+    }
+
+    async fn readahead(&self, file_id: nfs3::fileid3, offset: u64, length: u64) {
+        self.inner.readahead(file_id, offset, length).await
+    }
+
+    fn time_delta(&self) -> Duration {
+        self.inner.time_delta()
+    }
+
+    async fn fsinfo(
+        &self,
+        root_fileid: nfs3::fileid3,
+    ) -> Result<nfs3::fs::fsinfo3, nfs3::nfsstat3> {
+        self.inner.fsinfo(root_fileid).await
+    }
+
+    async fn fsstat(
+        &self,
+        root_fileid: nfs3::fileid3,
+    ) -> Result<nfs3::fs::FSSTAT3resok, nfs3::nfsstat3> {
+        self.inner.fsstat(root_fileid).await
+    }
+
+    fn id_to_fh(&self, id: nfs3::fileid3) -> nfs3::nfs_fh3 {
+        self.inner.id_to_fh(id)
+    }
+
+    fn fh_to_id(&self, id: &nfs3::nfs_fh3) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        self.inner.fh_to_id(id)
+    }
+
+    fn rehydrate_handle(&self, old: &nfs3::nfs_fh3) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        self.inner.rehydrate_handle(old)
+    }
+
+    async fn path_to_id(&self, path: &[u8]) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        self.inner.path_to_id(path).await
+    }
+
+    fn server_id(&self) -> nfs3::cookieverf3 {
+        self.inner.server_id()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::testing::{Call, MockFs};
+
+    #[tokio::test]
+    async fn replays_changes_not_done() {
+        let path = std::env::temp_dir().join(format!("nfs-mamont-{}.journal", std::process::id()));
+        // a crash after journaling a rename, with a torn record at the end
+        let rename = Record::Rename {
+            from_dirid: 1,
+            from_name: b"a".to_vec(),
+            to_dirid: 1,
+            to_name: b"b".to_vec(),
+        };
+        let write = Record::Write { id: 2, offset: 0, data: b"done".to_vec() };
+        let mut journal = write.encode(0).unwrap();
+        journal.extend(Record::Done.encode(0).unwrap());
+        journal.extend(rename.encode(1).unwrap());
+        journal.extend(&Record::Mkdir { dirid: 1, name: b"c".to_vec() }.encode(2).unwrap()[..9]);
+        std::fs::write(&path, &journal).unwrap();
+
+        let fs = MockFs::new();
+        let renamed = Call::Rename {
+            from_dirid: 1,
+            from_name: b"a".to_vec(),
+            to_dirid: 1,
+            to_name: b"b".to_vec(),
+        };
+        fs.expect(renamed, Ok(()));
+        let journaled = JournalFs::open(fs, &path).await.unwrap();
+        journaled.inner().assert_done();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+
+        // changes are journaled until done, failed ones too
+        let attr = nfs3::fattr3::default();
+        journaled.inner().expect(Call::Write { id: 2, offset: 0, data: b"x".to_vec() }, Ok(attr));
+        journaled.write(2, 0, b"x").await.unwrap();
+        let name = nfs3::filename3::from(b"c".to_vec());
+        assert!(journaled.mkdir(1, &name).await.is_err());
+        let records = parse(&std::fs::read(&path).unwrap());
+        let seqs: Vec<(bool, u64)> =
+            records.iter().map(|(record, seq)| (matches!(record, Record::Done), *seq)).collect();
+        assert_eq!(seqs, [(false, 0), (true, 0), (false, 1), (true, 1)]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
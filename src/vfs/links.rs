@@ -0,0 +1,107 @@
+//! Link counting for file systems with hard links.
+//!
+//! Clients identify a file by its `fileid`, so every name of a hard-linked file
+//! must report the same `fileid` and an `nlink` equal to its number of names.
+//! Tools like `rsync -H` and `cp -l` rely on both to detect links. Directories
+//! cannot be hard linked; their `nlink` is 2 plus the number of subdirectories,
+//! counting the entry in the parent, their own `.` and the `..` of every
+//! subdirectory.
+//!
+//! [`LinkCounts`] keeps the number of names of every non-directory file across
+//! `CREATE`, `LINK`, `REMOVE` and `RENAME`, and tells the file system when the
+//! last name of a file is gone so its contents can be released.
+
+use std::collections::HashMap;
+
+use crate::protocol::xdr::nfs3;
+
+/// Returns the `nlink` of a directory with `subdirectories` child directories
+pub fn dir_nlink(subdirectories: usize) -> u32 {
+    u32::try_from(subdirectories).unwrap_or(u32::MAX).saturating_add(2)
+}
+
+/// Number of names of every file
+///
+/// Files unknown to the table have no names.
+#[derive(Clone, Debug, Default)]
+pub struct LinkCounts {
+    /// Number of directory entries naming each file, never 0
+    counts: HashMap<nfs3::fileid3, u32>,
+}
+
+impl LinkCounts {
+    /// Creates an empty table
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of names of a file
+    pub fn nlink(&self, fileid: nfs3::fileid3) -> u32 {
+        self.counts.get(&fileid).copied().unwrap_or(0)
+    }
+
+    /// Records a new name of a file, as made by `CREATE` or `LINK`
+    ///
+    /// # Returns
+    ///
+    /// * `u32` - The `nlink` of the file after the change
+    pub fn link(&mut self, fileid: nfs3::fileid3) -> u32 {
+        let count = self.counts.entry(fileid).or_insert(0);
+        *count = count.saturating_add(1);
+        *count
+    }
+
+    /// Records a removed name of a file, as by `REMOVE`
+    ///
+    /// # Returns
+    ///
+    /// * `u32` - The `nlink` of the file after the change; at 0 the file has no
+    ///   names left and its contents can be released
+    pub fn unlink(&mut self, fileid: nfs3::fileid3) -> u32 {
+        match self.counts.get_mut(&fileid) {
+            Some(count) if *count > 1 => {
+                *count -= 1;
+                *count
+            }
+            _ => {
+                self.counts.remove(&fileid);
+                0
+            }
+        }
+    }
+
+    /// Records a `RENAME` of `moved` over the existing entry of `replaced`
+    ///
+    /// The moved file keeps its number of names. The replaced file loses one,
+    /// unless it is the moved file itself: renaming a name onto another name of
+    /// the same file does nothing, as in POSIX.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<u32>` - The `nlink` of the replaced file after the change, or
+    ///   `None` if nothing was replaced
+    pub fn rename_over(&mut self, moved: nfs3::fileid3, replaced: nfs3::fileid3) -> Option<u32> {
+        (moved != replaced).then(|| self.unlink(replaced))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_names_across_operations() {
+        let mut links = LinkCounts::new();
+        assert_eq!(links.link(5), 1);
+        assert_eq!(links.link(5), 2);
+        assert_eq!(links.link(6), 1);
+        assert_eq!(links.rename_over(5, 5), None);
+        assert_eq!(links.nlink(5), 2);
+        assert_eq!(links.rename_over(5, 6), Some(0));
+        assert_eq!(links.nlink(6), 0);
+        assert_eq!(links.unlink(5), 1);
+        assert_eq!(links.unlink(5), 0);
+        assert_eq!(links.unlink(5), 0);
+        assert_eq!(dir_nlink(3), 5);
+    }
+}
@@ -0,0 +1,441 @@
+//! Advisory byte-range locks for backends.
+//!
+//! NFSv3 leaves file locking to the separate NLM protocol, but backends that
+//! expose the same files through several paths, or a future NLM service, need
+//! one place that knows which owner holds which byte range. [`FileLockTable`]
+//! is that place: an in-process table of shared and exclusive locks keyed by
+//! file ID and lock owner, following POSIX `fcntl` semantics.
+//!
+//! - Locks of one owner never conflict with each other. A new lock of an owner
+//!   replaces the part of its existing locks it overlaps, which upgrades,
+//!   downgrades or splits them.
+//! - Unlocking a range releases only that range, splitting locks as needed.
+//! - Blocking requests wait in a first-in, first-out queue per file and are
+//!   granted as soon as the conflicting locks are gone.
+//! - A blocking request that would wait, directly or through other waiting
+//!   owners, for a lock held by its own owner fails with
+//!   [`LockError::Deadlock`] instead of hanging both clients.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+use std::sync::Mutex;
+
+use tokio::sync::oneshot;
+
+use crate::protocol::xdr::nfs3::fileid3;
+
+/// Kind of a lock
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockKind {
+    /// Read lock, compatible with other shared locks
+    Shared,
+    /// Write lock, compatible with no other lock
+    Exclusive,
+}
+
+/// Range of bytes of a file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ByteRange {
+    /// First byte of the range
+    pub offset: u64,
+    /// Number of bytes, 0 for everything from `offset` to the end of the file
+    /// however large it grows, as in NLM and `fcntl`
+    pub length: u64,
+}
+
+impl ByteRange {
+    /// Creates a range of `length` bytes from `offset`, 0 meaning to the end
+    pub fn new(offset: u64, length: u64) -> Self {
+        Self { offset, length }
+    }
+
+    /// Returns the range covering the whole file
+    pub fn whole_file() -> Self {
+        Self { offset: 0, length: 0 }
+    }
+
+    /// Returns the first byte past the range
+    fn end(&self) -> u64 {
+        match self.length {
+            0 => u64::MAX,
+            length => self.offset.saturating_add(length),
+        }
+    }
+
+    /// Returns whether two ranges share at least one byte
+    fn overlaps(&self, other: &ByteRange) -> bool {
+        self.offset < other.end() && other.offset < self.end()
+    }
+
+    /// Returns the range from `offset` to `end`
+    fn between(offset: u64, end: u64) -> Self {
+        Self { offset, length: if end == u64::MAX { 0 } else { end - offset } }
+    }
+}
+
+/// A lock held on a file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lock<O> {
+    /// Owner holding the lock
+    pub owner: O,
+    /// Kind of the lock
+    pub kind: LockKind,
+    /// Locked bytes
+    pub range: ByteRange,
+}
+
+impl<O: Eq> Lock<O> {
+    /// Returns whether this lock prevents `owner` from taking a `kind` lock on `range`
+    fn blocks(&self, owner: &O, kind: LockKind, range: &ByteRange) -> bool {
+        self.owner != *owner
+            && (self.kind == LockKind::Exclusive || kind == LockKind::Exclusive)
+            && self.range.overlaps(range)
+    }
+}
+
+/// Reason a lock was not granted
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LockError<O> {
+    /// The range is locked by another owner, one of whose locks is given
+    Conflict(Lock<O>),
+    /// Waiting for the lock would deadlock
+    Deadlock,
+    /// The request was withdrawn by [`FileLockTable::release_owner`] while waiting
+    Withdrawn,
+}
+
+/// A blocking request waiting for its lock
+#[derive(Debug)]
+struct Waiter<O> {
+    /// The requested lock
+    lock: Lock<O>,
+    /// Wakes the request once the lock is granted
+    granted: oneshot::Sender<()>,
+}
+
+/// Locks and waiting requests of a file
+#[derive(Debug)]
+struct FileLocks<O> {
+    /// Locks held, at most one per owner and byte
+    held: Vec<Lock<O>>,
+    /// Blocking requests, in arrival order
+    waiters: VecDeque<Waiter<O>>,
+}
+
+impl<O> Default for FileLocks<O> {
+    fn default() -> Self {
+        Self { held: Vec::new(), waiters: VecDeque::new() }
+    }
+}
+
+impl<O: Clone + Eq> FileLocks<O> {
+    /// Returns a held lock conflicting with a request
+    fn conflict(&self, owner: &O, kind: LockKind, range: &ByteRange) -> Option<&Lock<O>> {
+        self.held.iter().find(|lock| lock.blocks(owner, kind, range))
+    }
+
+    /// Removes `range` from the locks of `owner`, splitting them as needed
+    fn unlock(&mut self, owner: &O, range: &ByteRange) {
+        let mut kept = Vec::with_capacity(self.held.len() + 1);
+        for lock in self.held.drain(..) {
+            if lock.owner != *owner || !lock.range.overlaps(range) {
+                kept.push(lock);
+                continue;
+            }
+            if lock.range.offset < range.offset {
+                let before = ByteRange::between(lock.range.offset, range.offset);
+                kept.push(Lock { range: before, ..lock.clone() });
+            }
+            if range.end() < lock.range.end() {
+                let after = ByteRange::between(range.end(), lock.range.end());
+                kept.push(Lock { range: after, ..lock });
+            }
+        }
+        self.held = kept;
+    }
+
+    /// Records a lock known not to conflict
+    fn grant(&mut self, lock: Lock<O>) {
+        self.unlock(&lock.owner, &lock.range);
+        self.held.push(lock);
+    }
+
+    /// Grants queued requests that no longer conflict, in arrival order
+    fn wake_waiters(&mut self) {
+        let mut index = 0;
+        while index < self.waiters.len() {
+            let waiter = &self.waiters[index];
+            let lock = &waiter.lock;
+            if self.conflict(&lock.owner, lock.kind, &lock.range).is_some() {
+                index += 1;
+                continue;
+            }
+            let waiter = self.waiters.remove(index).unwrap();
+            if waiter.granted.is_closed() {
+                // the request was abandoned while waiting
+                continue;
+            }
+            self.grant(waiter.lock.clone());
+            if waiter.granted.send(()).is_err() {
+                self.unlock(&waiter.lock.owner, &waiter.lock.range);
+            }
+            // a newly granted lock may block requests queued after it, so
+            // start over from the front
+            index = 0;
+        }
+    }
+}
+
+/// Table of advisory byte-range locks, keyed by file ID and owner
+///
+/// The owner type identifies whoever holds a lock, e.g. an NLM client name
+/// and process ID. A single table is meant to be shared by everything that
+/// locks the same files.
+#[derive(Debug)]
+pub struct FileLockTable<O> {
+    /// Locks of every file with locks or waiting requests
+    files: Mutex<HashMap<fileid3, FileLocks<O>>>,
+}
+
+impl<O> Default for FileLockTable<O> {
+    fn default() -> Self {
+        Self { files: Mutex::default() }
+    }
+}
+
+impl<O: Clone + Eq + Hash> FileLockTable<O> {
+    /// Creates an empty table
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a lock that would prevent a request, as for NLM `TEST`
+    ///
+    /// # Arguments
+    ///
+    /// * `fileid` - File to lock
+    /// * `owner` - Owner requesting the lock
+    /// * `kind` - Kind of lock requested
+    /// * `range` - Bytes to lock
+    pub fn test(
+        &self,
+        fileid: fileid3,
+        owner: &O,
+        kind: LockKind,
+        range: ByteRange,
+    ) -> Option<Lock<O>> {
+        let files = self.files.lock().unwrap();
+        files.get(&fileid)?.conflict(owner, kind, &range).cloned()
+    }
+
+    /// Takes a lock if no other owner holds a conflicting one
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), LockError<O>>` - `LockError::Conflict` with a lock in the way
+    pub fn try_lock(
+        &self,
+        fileid: fileid3,
+        owner: O,
+        kind: LockKind,
+        range: ByteRange,
+    ) -> Result<(), LockError<O>> {
+        let mut files = self.files.lock().unwrap();
+        let file = files.entry(fileid).or_default();
+        if let Some(conflict) = file.conflict(&owner, kind, &range) {
+            return Err(LockError::Conflict(conflict.clone()));
+        }
+        file.grant(Lock { owner, kind, range });
+        // a downgrade may unblock waiting requests
+        file.wake_waiters();
+        Ok(())
+    }
+
+    /// Takes a lock, waiting in line until conflicting locks are released
+    ///
+    /// Dropping the returned future before it completes withdraws the request.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), LockError<O>>` - `LockError::Deadlock` if waiting would
+    ///   deadlock with the owners the request waits for, `LockError::Withdrawn`
+    ///   if the owner was released while waiting
+    pub async fn lock(
+        &self,
+        fileid: fileid3,
+        owner: O,
+        kind: LockKind,
+        range: ByteRange,
+    ) -> Result<(), LockError<O>> {
+        let granted = {
+            let mut files = self.files.lock().unwrap();
+            let conflicts = match files.get(&fileid) {
+                Some(file) => file.conflict(&owner, kind, &range).is_some(),
+                None => false,
+            };
+            if !conflicts {
+                let file = files.entry(fileid).or_default();
+                file.grant(Lock { owner, kind, range });
+                file.wake_waiters();
+                return Ok(());
+            }
+            let lock = Lock { owner, kind, range };
+            if Self::would_deadlock(&files, fileid, &lock) {
+                return Err(LockError::Deadlock);
+            }
+            let (granted, wait) = oneshot::channel();
+            files.entry(fileid).or_default().waiters.push_back(Waiter { lock, granted });
+            wait
+        };
+        granted.await.map_err(|_| LockError::Withdrawn)
+    }
+
+    /// Releases `range` from the locks of `owner` on a file
+    pub fn unlock(&self, fileid: fileid3, owner: &O, range: ByteRange) {
+        let mut files = self.files.lock().unwrap();
+        if let Some(file) = files.get_mut(&fileid) {
+            file.unlock(owner, &range);
+            file.wake_waiters();
+            if file.held.is_empty() && file.waiters.is_empty() {
+                files.remove(&fileid);
+            }
+        }
+    }
+
+    /// Releases every lock of an owner and withdraws its waiting requests,
+    /// e.g. when a client reboots
+    pub fn release_owner(&self, owner: &O) {
+        let mut files = self.files.lock().unwrap();
+        for file in files.values_mut() {
+            file.held.retain(|lock| lock.owner != *owner);
+            file.waiters.retain(|waiter| waiter.lock.owner != *owner);
+            file.wake_waiters();
+        }
+        files.retain(|_, file| !file.held.is_empty() || !file.waiters.is_empty());
+    }
+
+    /// Returns the locks held on a file
+    pub fn locks(&self, fileid: fileid3) -> Vec<Lock<O>> {
+        let files = self.files.lock().unwrap();
+        files.get(&fileid).map(|file| file.held.clone()).unwrap_or_default()
+    }
+
+    /// Returns whether queueing `request` on `fileid` closes a cycle of owners
+    /// waiting for each other
+    fn would_deadlock(
+        files: &HashMap<fileid3, FileLocks<O>>,
+        fileid: fileid3,
+        request: &Lock<O>,
+    ) -> bool {
+        // owners each owner waits for, through any of its queued requests
+        let blockers = |file: &FileLocks<O>, lock: &Lock<O>| -> Vec<O> {
+            file.held
+                .iter()
+                .filter(|held| held.blocks(&lock.owner, lock.kind, &lock.range))
+                .map(|held| held.owner.clone())
+                .collect()
+        };
+        let mut waits_for: HashMap<O, Vec<O>> = HashMap::new();
+        for file in files.values() {
+            for waiter in file.waiters.iter().filter(|w| !w.granted.is_closed()) {
+                let owners = blockers(file, &waiter.lock);
+                waits_for.entry(waiter.lock.owner.clone()).or_default().extend(owners);
+            }
+        }
+
+        let mut pending = blockers(&files[&fileid], request);
+        let mut seen = HashSet::new();
+        while let Some(owner) = pending.pop() {
+            if owner == request.owner {
+                return true;
+            }
+            if seen.insert(owner.clone()) {
+                pending.extend(waits_for.get(&owner).into_iter().flatten().cloned());
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn conflicts_between_owners_only() {
+        let table = FileLockTable::new();
+        table.try_lock(1, "a", LockKind::Shared, ByteRange::new(0, 100)).unwrap();
+        table.try_lock(1, "b", LockKind::Shared, ByteRange::new(50, 100)).unwrap();
+        let err = table.try_lock(1, "c", LockKind::Exclusive, ByteRange::new(90, 1)).unwrap_err();
+        assert!(matches!(err, LockError::Conflict(Lock { owner: "a" | "b", .. })));
+        // past the end of every lock
+        table.try_lock(1, "c", LockKind::Exclusive, ByteRange::new(150, 0)).unwrap();
+        // other files are independent
+        table.try_lock(2, "c", LockKind::Exclusive, ByteRange::whole_file()).unwrap();
+        // an owner's own locks never conflict; upgrading replaces the overlap
+        table.try_lock(1, "a", LockKind::Exclusive, ByteRange::new(0, 10)).unwrap();
+        assert!(table.test(1, &"b", LockKind::Shared, ByteRange::new(0, 10)).is_some());
+        assert!(table.test(1, &"b", LockKind::Shared, ByteRange::new(10, 10)).is_none());
+    }
+
+    #[test]
+    fn unlock_splits_ranges() {
+        let table = FileLockTable::new();
+        table.try_lock(1, "a", LockKind::Exclusive, ByteRange::new(0, 0)).unwrap();
+        table.unlock(1, &"a", ByteRange::new(10, 10));
+        let mut ranges: Vec<ByteRange> = table.locks(1).into_iter().map(|l| l.range).collect();
+        ranges.sort_by_key(|range| range.offset);
+        assert_eq!(ranges, [ByteRange::new(0, 10), ByteRange::new(20, 0)]);
+        table.try_lock(1, "b", LockKind::Exclusive, ByteRange::new(12, 5)).unwrap();
+        table.release_owner(&"a");
+        assert_eq!(table.locks(1).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn blocking_locks_wait_in_line() {
+        let table = Arc::new(FileLockTable::new());
+        table.try_lock(1, "a", LockKind::Exclusive, ByteRange::whole_file()).unwrap();
+
+        let waiting = {
+            let table = table.clone();
+            tokio::spawn(async move {
+                table.lock(1, "b", LockKind::Shared, ByteRange::new(0, 10)).await
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+
+        table.unlock(1, &"a", ByteRange::new(0, 5));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished(), "granted while part of the range is locked");
+
+        table.unlock(1, &"a", ByteRange::whole_file());
+        tokio::time::timeout(Duration::from_secs(1), waiting).await.unwrap().unwrap().unwrap();
+        assert_eq!(table.locks(1)[0].owner, "b");
+    }
+
+    #[tokio::test]
+    async fn detects_deadlocks() {
+        let table = Arc::new(FileLockTable::new());
+        table.try_lock(1, "a", LockKind::Exclusive, ByteRange::whole_file()).unwrap();
+        table.try_lock(2, "b", LockKind::Exclusive, ByteRange::whole_file()).unwrap();
+
+        // a waits for b
+        let waiting = {
+            let table = table.clone();
+            tokio::spawn(async move {
+                table.lock(2, "a", LockKind::Exclusive, ByteRange::whole_file()).await
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        // b waiting for a would close the cycle
+        let err = table.lock(1, "b", LockKind::Exclusive, ByteRange::whole_file()).await;
+        assert_eq!(err, Err(LockError::Deadlock));
+
+        table.release_owner(&"b");
+        tokio::time::timeout(Duration::from_secs(1), waiting).await.unwrap().unwrap().unwrap();
+    }
+}
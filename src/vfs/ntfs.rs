@@ -0,0 +1,208 @@
+//! Exposure of NTFS alternate data streams and reparse points.
+//!
+//! Files on NTFS can carry named streams next to their contents, addressed as
+//! `file:stream`, and directories can hold reparse points: symbolic links,
+//! junctions, and placeholders of cloud or deduplicated files. Neither maps
+//! onto NFS, and passing them through as the Windows API reports them breaks
+//! clients. Listing streams as entries named `file:stream` makes tools copy
+//! them as separate files, and `file::$DATA` names the contents of `file`
+//! under a name no access check expects. Following junctions such as
+//! `Application Data`, which points to its own parent, sends recursive
+//! listings into endless loops.
+//!
+//! [`NtfsPolicy`] decides what clients see. By default streams are hidden and
+//! names addressing them are reported missing, symbolic links and junctions
+//! are reported as symbolic links, and other reparse points as the files they
+//! stand for. Backends serving NTFS check client-supplied names with
+//! [`NtfsPolicy::check_name`], list streams with [`NtfsPolicy::stream_entry`],
+//! and report the type of entries with [`NtfsPolicy::entry_type`].
+//!
+//! The policy is plain data so that it can be configured, e.g. in the `ntfs`
+//! section of an export in a configuration file, and tested on any platform.
+
+use crate::protocol::xdr::nfs3;
+
+/// Reparse tag of NTFS symbolic links
+pub const IO_REPARSE_TAG_SYMLINK: u32 = 0xA000_000C;
+/// Reparse tag of junctions and volume mount points
+pub const IO_REPARSE_TAG_MOUNT_POINT: u32 = 0xA000_0003;
+
+/// Stream type suffix of data streams, the only type that can be exposed
+const DATA_STREAM_TYPE: &[u8] = b"$DATA";
+
+/// Whether alternate data streams are visible
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum StreamPolicy {
+    /// Streams are left out of listings and names addressing them are missing
+    #[default]
+    Hide,
+    /// Streams are listed as regular files named `file:stream`
+    Expose,
+}
+
+/// How a reparse point is presented
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum ReparseAction {
+    /// As a symbolic link to its target
+    Symlink,
+    /// As the file or directory it resolves to; junctions pointing to an
+    /// ancestor make listings recursive
+    Follow,
+    /// Left out of listings and reported missing
+    Hide,
+}
+
+/// Presentation of NTFS specifics to clients
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default, deny_unknown_fields))]
+pub struct NtfsPolicy {
+    /// Alternate data streams
+    pub streams: StreamPolicy,
+    /// Reparse points tagged [`IO_REPARSE_TAG_SYMLINK`]
+    pub symlinks: ReparseAction,
+    /// Reparse points tagged [`IO_REPARSE_TAG_MOUNT_POINT`]
+    pub mount_points: ReparseAction,
+    /// All other reparse points, e.g. cloud file placeholders
+    pub other_reparse_points: ReparseAction,
+}
+
+/// Client-supplied name checked against an [`NtfsPolicy`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NtfsName<'a> {
+    /// Name of a file or directory
+    File(&'a [u8]),
+    /// Name of an exposed stream of a file
+    Stream {
+        /// Name of the file
+        file: &'a [u8],
+        /// Name of the stream, without type suffix
+        stream: &'a [u8],
+    },
+}
+
+impl Default for NtfsPolicy {
+    fn default() -> Self {
+        Self {
+            streams: StreamPolicy::Hide,
+            symlinks: ReparseAction::Symlink,
+            mount_points: ReparseAction::Symlink,
+            other_reparse_points: ReparseAction::Follow,
+        }
+    }
+}
+
+impl NtfsPolicy {
+    /// Checks a client-supplied name of a directory entry
+    ///
+    /// Names without `:` name files. Others address streams: `file:stream`
+    /// and `file:stream:$DATA` are accepted if streams are exposed, while the
+    /// unnamed stream, as in `file::$DATA`, and streams of other types are
+    /// always refused.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<NtfsName, nfsstat3>` - What the name addresses, or
+    ///   NFS3ERR_NOENT if it addresses a stream clients must not see
+    pub fn check_name<'a>(&self, name: &'a [u8]) -> Result<NtfsName<'a>, nfs3::nfsstat3> {
+        let Some(colon) = name.iter().position(|byte| *byte == b':') else {
+            return Ok(NtfsName::File(name));
+        };
+        let (file, mut stream) = (&name[..colon], &name[colon + 1..]);
+        if let Some(suffix) = stream.iter().position(|byte| *byte == b':') {
+            if !stream[suffix + 1..].eq_ignore_ascii_case(DATA_STREAM_TYPE) {
+                return Err(nfs3::nfsstat3::NFS3ERR_NOENT);
+            }
+            stream = &stream[..suffix];
+        }
+        if self.streams == StreamPolicy::Hide || file.is_empty() || stream.is_empty() {
+            return Err(nfs3::nfsstat3::NFS3ERR_NOENT);
+        }
+        Ok(NtfsName::Stream { file, stream })
+    }
+
+    /// Returns the name a stream of a file is listed under, `None` if it is
+    /// not listed
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - Name of the file
+    /// * `stream` - Name of the stream as enumerated by Windows, e.g.
+    ///   `:stream:$DATA`; the unnamed stream `::$DATA` is never listed
+    pub fn stream_entry(&self, file: &[u8], stream: &[u8]) -> Option<Vec<u8>> {
+        if self.streams == StreamPolicy::Hide {
+            return None;
+        }
+        let stream = stream.strip_prefix(b":").unwrap_or(stream);
+        let (name, kind) = match stream.iter().position(|byte| *byte == b':') {
+            Some(colon) => (&stream[..colon], &stream[colon + 1..]),
+            None => (stream, DATA_STREAM_TYPE),
+        };
+        if name.is_empty() || !kind.eq_ignore_ascii_case(DATA_STREAM_TYPE) {
+            return None;
+        }
+        Some([file, b":", name].concat())
+    }
+
+    /// Returns how reparse points with a tag are presented
+    pub fn reparse_action(&self, tag: u32) -> ReparseAction {
+        match tag {
+            IO_REPARSE_TAG_SYMLINK => self.symlinks,
+            IO_REPARSE_TAG_MOUNT_POINT => self.mount_points,
+            _ => self.other_reparse_points,
+        }
+    }
+
+    /// Returns the type an entry is reported as, `None` if it is hidden
+    ///
+    /// # Arguments
+    ///
+    /// * `reparse_tag` - Reparse tag of the entry, if it is a reparse point
+    /// * `target_type` - Type of the entry, or of what a reparse point
+    ///   resolves to
+    pub fn entry_type(
+        &self,
+        reparse_tag: Option<u32>,
+        target_type: nfs3::ftype3,
+    ) -> Option<nfs3::ftype3> {
+        match reparse_tag.map(|tag| self.reparse_action(tag)) {
+            None | Some(ReparseAction::Follow) => Some(target_type),
+            Some(ReparseAction::Symlink) => Some(nfs3::ftype3::NF3LNK),
+            Some(ReparseAction::Hide) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hides_streams_and_maps_reparse_points() {
+        let policy = NtfsPolicy::default();
+        assert_eq!(policy.check_name(b"file.txt").ok(), Some(NtfsName::File(b"file.txt")));
+        assert!(policy.check_name(b"file.txt:secret").is_err());
+        assert!(policy.stream_entry(b"file.txt", b":secret:$DATA").is_none());
+        let dir = nfs3::ftype3::NF3DIR;
+        assert!(matches!(policy.entry_type(None, dir), Some(nfs3::ftype3::NF3DIR)));
+        let junction = policy.entry_type(Some(IO_REPARSE_TAG_MOUNT_POINT), dir);
+        assert!(matches!(junction, Some(nfs3::ftype3::NF3LNK)));
+        assert!(matches!(policy.entry_type(Some(0x9000_001A), dir), Some(nfs3::ftype3::NF3DIR)));
+
+        let policy = NtfsPolicy {
+            streams: StreamPolicy::Expose,
+            mount_points: ReparseAction::Hide,
+            ..NtfsPolicy::default()
+        };
+        let stream = NtfsName::Stream { file: b"a", stream: b"s" };
+        assert_eq!(policy.check_name(b"a:s").ok(), Some(stream));
+        assert_eq!(policy.check_name(b"a:s:$data").ok(), Some(stream));
+        // the unnamed stream aliases the file itself
+        assert!(policy.check_name(b"a::$DATA").is_err());
+        assert!(policy.check_name(b"a:s:$INDEX_ALLOCATION").is_err());
+        assert_eq!(policy.stream_entry(b"a", b":s:$DATA").as_deref(), Some(&b"a:s"[..]));
+        assert!(policy.stream_entry(b"a", b"::$DATA").is_none());
+        assert!(policy.entry_type(Some(IO_REPARSE_TAG_MOUNT_POINT), dir).is_none());
+    }
+}
@@ -0,0 +1,223 @@
+//! POSIX permission evaluation.
+//!
+//! The `ACCESS` procedure and backends that enforce permissions on the server
+//! side both need to decide what a caller may do with a file from its mode,
+//! owner and group. Doing so in one place keeps the answers of `ACCESS`
+//! consistent with what the operations themselves allow.
+//!
+//! The rules are those of POSIX: the caller is checked against the owner bits
+//! if it owns the file, against the group bits if the file's group is its
+//! primary or one of its supplementary groups, and against the other bits
+//! otherwise, without falling back from one class to the next. The superuser
+//! may do anything, except execute a regular file that has no execute bit set.
+
+use crate::protocol::xdr::nfs3;
+use crate::protocol::xdr::rpc::auth_unix;
+
+/// Read permission bit of a permission class
+const READ: u32 = 0o4;
+/// Write permission bit of a permission class
+const WRITE: u32 = 0o2;
+/// Execute or search permission bit of a permission class
+const EXECUTE: u32 = 0o1;
+
+/// Operation whose permission is checked with [`check`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    /// Reading the data of a file, as by `READ`
+    Read,
+    /// Changing the data of a file, as by `WRITE` or a size change in `SETATTR`
+    Write,
+    /// Executing a file
+    Execute,
+    /// Looking up a name in a directory, as by `LOOKUP`
+    Lookup,
+    /// Listing a directory, as by `READDIR`
+    ListDirectory,
+    /// Adding a name to a directory, as by `CREATE`, `MKDIR` or `LINK`
+    AddEntry,
+    /// Removing a name from a directory; see [`check_remove`] for the sticky bit
+    RemoveEntry,
+}
+
+impl Operation {
+    /// Returns the `ACCESS3_*` bits the operation needs
+    pub fn access_bits(self) -> u32 {
+        match self {
+            Operation::Read | Operation::ListDirectory => nfs3::ACCESS3_READ,
+            Operation::Write => nfs3::ACCESS3_MODIFY,
+            Operation::Execute => nfs3::ACCESS3_EXECUTE,
+            Operation::Lookup => nfs3::ACCESS3_LOOKUP,
+            Operation::AddEntry => nfs3::ACCESS3_EXTEND,
+            Operation::RemoveEntry => nfs3::ACCESS3_DELETE,
+        }
+    }
+}
+
+/// Returns the permission bits of the class the caller falls into, as `rwx`
+fn class_bits(attr: &nfs3::fattr3, auth: &auth_unix) -> u32 {
+    if auth.uid == attr.uid {
+        (attr.mode >> 6) & 0o7
+    } else if auth.gid == attr.gid || auth.gids.contains(&attr.gid) {
+        (attr.mode >> 3) & 0o7
+    } else {
+        attr.mode & 0o7
+    }
+}
+
+/// Returns which of the `requested` `ACCESS3_*` bits the caller is granted
+///
+/// Regular files and other non-directories grant `ACCESS3_READ` for read
+/// permission, `ACCESS3_MODIFY` and `ACCESS3_EXTEND` for write permission and
+/// `ACCESS3_EXECUTE` for execute permission. Directories grant `ACCESS3_READ`
+/// for read permission, `ACCESS3_LOOKUP` for search permission, and
+/// `ACCESS3_MODIFY`, `ACCESS3_EXTEND` and `ACCESS3_DELETE` for write and search
+/// permission together. Symbolic links carry no permissions of their own and
+/// grant everything but `ACCESS3_DELETE`.
+///
+/// # Arguments
+///
+/// * `attr` - Attributes of the file
+/// * `auth` - Credentials of the caller
+/// * `requested` - `ACCESS3_*` bits asked for
+pub fn access(attr: &nfs3::fattr3, auth: &auth_unix, requested: u32) -> u32 {
+    let bits = if auth.uid == 0 {
+        // the superuser may execute only what somebody may execute
+        let any_execute = attr.mode & 0o111 != 0;
+        READ | WRITE | if any_execute || is_dir(attr) { EXECUTE } else { 0 }
+    } else {
+        class_bits(attr, auth)
+    };
+    let mut granted = 0;
+    match attr.ftype {
+        nfs3::ftype3::NF3DIR => {
+            if bits & READ != 0 {
+                granted |= nfs3::ACCESS3_READ;
+            }
+            if bits & EXECUTE != 0 {
+                granted |= nfs3::ACCESS3_LOOKUP;
+                if bits & WRITE != 0 {
+                    granted |= nfs3::ACCESS3_MODIFY | nfs3::ACCESS3_EXTEND | nfs3::ACCESS3_DELETE;
+                }
+            }
+        }
+        nfs3::ftype3::NF3LNK => {
+            granted = nfs3::ACCESS3_READ
+                | nfs3::ACCESS3_LOOKUP
+                | nfs3::ACCESS3_MODIFY
+                | nfs3::ACCESS3_EXTEND
+                | nfs3::ACCESS3_EXECUTE;
+        }
+        _ => {
+            if bits & READ != 0 {
+                granted |= nfs3::ACCESS3_READ;
+            }
+            if bits & WRITE != 0 {
+                granted |= nfs3::ACCESS3_MODIFY | nfs3::ACCESS3_EXTEND;
+            }
+            if bits & EXECUTE != 0 {
+                granted |= nfs3::ACCESS3_EXECUTE;
+            }
+        }
+    }
+    granted & requested
+}
+
+/// Returns whether a file is a directory
+fn is_dir(attr: &nfs3::fattr3) -> bool {
+    matches!(attr.ftype, nfs3::ftype3::NF3DIR)
+}
+
+/// Checks whether the caller may perform an operation on a file
+///
+/// Directory operations are checked against the attributes of the directory.
+///
+/// # Returns
+///
+/// * `Result<(), nfsstat3>` - NFS3ERR_ACCES if the operation is not permitted
+pub fn check(attr: &nfs3::fattr3, auth: &auth_unix, op: Operation) -> Result<(), nfs3::nfsstat3> {
+    let needed = op.access_bits();
+    if access(attr, auth, needed) == needed {
+        Ok(())
+    } else {
+        Err(nfs3::nfsstat3::NFS3ERR_ACCES)
+    }
+}
+
+/// Checks whether the caller may remove `child` from `dir`, as by `REMOVE`,
+/// `RMDIR` or `RENAME`
+///
+/// Besides write and search permission on the directory, a directory with the
+/// sticky bit set only lets the owner of the directory or of the entry remove
+/// it.
+///
+/// # Returns
+///
+/// * `Result<(), nfsstat3>` - NFS3ERR_ACCES if the removal is not permitted
+pub fn check_remove(
+    dir: &nfs3::fattr3,
+    child: &nfs3::fattr3,
+    auth: &auth_unix,
+) -> Result<(), nfs3::nfsstat3> {
+    check(dir, auth, Operation::RemoveEntry)?;
+    let sticky = dir.mode & 0o1000 != 0;
+    if sticky && auth.uid != 0 && auth.uid != dir.uid && auth.uid != child.uid {
+        return Err(nfs3::nfsstat3::NFS3ERR_ACCES);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attr(ftype: nfs3::ftype3, mode: u32) -> nfs3::fattr3 {
+        nfs3::fattr3 { ftype, mode, uid: 100, gid: 200, ..Default::default() }
+    }
+
+    fn caller(uid: u32, gid: u32, gids: Vec<u32>) -> auth_unix {
+        auth_unix { uid, gid, gids, ..Default::default() }
+    }
+
+    #[test]
+    fn evaluates_permission_classes() {
+        let all = nfs3::ACCESS3_READ
+            | nfs3::ACCESS3_LOOKUP
+            | nfs3::ACCESS3_MODIFY
+            | nfs3::ACCESS3_EXTEND
+            | nfs3::ACCESS3_DELETE
+            | nfs3::ACCESS3_EXECUTE;
+        let file = attr(nfs3::ftype3::NF3REG, 0o640);
+        let rw = nfs3::ACCESS3_READ | nfs3::ACCESS3_MODIFY | nfs3::ACCESS3_EXTEND;
+        assert_eq!(access(&file, &caller(100, 1, vec![]), all), rw);
+        assert_eq!(access(&file, &caller(1, 1, vec![200]), all), nfs3::ACCESS3_READ);
+        assert_eq!(access(&file, &caller(1, 1, vec![]), all), 0);
+        // the owner class applies even when the group class grants more
+        let file = attr(nfs3::ftype3::NF3REG, 0o070);
+        assert_eq!(access(&file, &caller(100, 200, vec![]), all), 0);
+        assert_eq!(access(&file, &caller(0, 0, vec![]), all), rw | nfs3::ACCESS3_EXECUTE);
+        // the superuser may not execute files nobody may execute
+        let file = attr(nfs3::ftype3::NF3REG, 0o600);
+        assert_eq!(access(&file, &caller(0, 0, vec![]), all), rw);
+
+        let dir = attr(nfs3::ftype3::NF3DIR, 0o755);
+        assert!(check(&dir, &caller(100, 1, vec![]), Operation::AddEntry).is_ok());
+        assert!(check(&dir, &caller(1, 1, vec![]), Operation::Lookup).is_ok());
+        assert!(check(&dir, &caller(1, 1, vec![]), Operation::AddEntry).is_err());
+        // writing a directory needs search permission too
+        let dir = attr(nfs3::ftype3::NF3DIR, 0o600);
+        assert_eq!(access(&dir, &caller(100, 1, vec![]), all), nfs3::ACCESS3_READ);
+    }
+
+    #[test]
+    fn sticky_directories_protect_entries() {
+        let dir = attr(nfs3::ftype3::NF3DIR, 0o1777);
+        let mut child = attr(nfs3::ftype3::NF3REG, 0o644);
+        child.uid = 300;
+        assert!(check_remove(&dir, &child, &caller(300, 1, vec![])).is_ok());
+        assert!(check_remove(&dir, &child, &caller(100, 1, vec![])).is_ok());
+        assert!(check_remove(&dir, &child, &caller(400, 1, vec![])).is_err());
+        let dir = attr(nfs3::ftype3::NF3DIR, 0o777);
+        assert!(check_remove(&dir, &child, &caller(400, 1, vec![])).is_ok());
+    }
+}
@@ -0,0 +1,184 @@
+//! Helpers for paging directory listings.
+//!
+//! A file system that can produce the whole listing of a directory can answer
+//! [`NFSFileSystem::readdir_with_cookie`](super::NFSFileSystem::readdir_with_cookie)
+//! by handing it to one of these helpers:
+//!
+//! - [`page_by_position`] for [`ReadDirCookieKind::FileId`] and
+//!   [`ReadDirCookieKind::Index`] cookies. Both locate the resume point by
+//!   position, so entries added or removed before it between two calls make
+//!   the client skip or repeat entries.
+//! - [`page_by_cookie`] for [`ReadDirCookieKind::Opaque`] cookies. Entries are
+//!   listed in ascending cookie order and a listing resumes after the last
+//!   cookie returned, so concurrent changes only affect the changed entries.
+
+use super::{DirEntry, NFSFileSystem, ReadDirCookie, ReadDirCookieKind, ReadDirResult};
+use crate::protocol::xdr::nfs3;
+
+/// Returns a cookie derived from an entry name, never 0
+///
+/// Uses the 64-bit FNV-1a hash, which is stable across restarts and
+/// platforms. Distinct names sharing a hash would share a cookie, which is
+/// unlikely enough to ignore for directories of realistic size.
+pub fn name_cookie(name: &[u8]) -> nfs3::cookie3 {
+    let hash = name.iter().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    hash.max(1)
+}
+
+/// Returns the cookie continuing a listing of a file system after `last`
+///
+/// For walking whole directories on the server side, where no client hands
+/// back cookies.
+///
+/// # Arguments
+/// * `fs` - The file system being listed
+/// * `dirid` - The directory being listed
+/// * `listed` - Number of entries returned so far, including `last`
+/// * `last` - The last entry returned
+pub fn resume_after(
+    fs: &(dyn NFSFileSystem + Send + Sync),
+    dirid: nfs3::fileid3,
+    listed: usize,
+    last: &DirEntry,
+) -> ReadDirCookie {
+    match fs.readdir_cookie_kind() {
+        ReadDirCookieKind::FileId => ReadDirCookie::AfterFileId(last.fileid),
+        ReadDirCookieKind::Index => ReadDirCookie::Index(listed as u64),
+        ReadDirCookieKind::Opaque => {
+            ReadDirCookie::Opaque(fs.readdir_entry_cookie(dirid, last.fileid, &last.name))
+        }
+    }
+}
+
+/// Returns the page of `entries` resuming from a position-based cookie
+///
+/// # Arguments
+/// * `entries` - All entries of the directory, in listing order
+/// * `cookie` - Where to resume; [`ReadDirCookie::Opaque`] is rejected
+/// * `max_entries` - Maximum number of entries to return
+///
+/// # Returns
+/// * `Result<ReadDirResult, nfsstat3>` - The page, or NFS3ERR_BAD_COOKIE when the
+///   entry to resume after is gone
+pub fn page_by_position(
+    entries: Vec<DirEntry>,
+    cookie: ReadDirCookie,
+    max_entries: usize,
+) -> Result<ReadDirResult, nfs3::nfsstat3> {
+    let first = match cookie {
+        ReadDirCookie::Start => 0,
+        ReadDirCookie::AfterFileId(fileid) => {
+            entries
+                .iter()
+                .position(|entry| entry.fileid == fileid)
+                .ok_or(nfs3::nfsstat3::NFS3ERR_BAD_COOKIE)?
+                + 1
+        }
+        ReadDirCookie::Index(index) => index as usize,
+        ReadDirCookie::Opaque(_) => return Err(nfs3::nfsstat3::NFS3ERR_BAD_COOKIE),
+    };
+    let total = entries.len();
+    let entries: Vec<DirEntry> = entries.into_iter().skip(first).take(max_entries).collect();
+    let end = first + entries.len() >= total;
+    Ok(ReadDirResult { entries, end })
+}
+
+/// Returns the page of `entries` resuming after an opaque cookie
+///
+/// Entries are sorted by cookie, so the listing order is the same on every
+/// call, and a listing resumes after the returned cookie even if that entry
+/// was removed in the meantime.
+///
+/// # Arguments
+/// * `entries` - All entries of the directory, in any order
+/// * `cookie` - Where to resume; position-based cookies are rejected
+/// * `max_entries` - Maximum number of entries to return
+/// * `cookie_of` - Cookie of an entry, as returned by
+///   [`NFSFileSystem::readdir_entry_cookie`](super::NFSFileSystem::readdir_entry_cookie)
+///
+/// # Returns
+/// * `Result<ReadDirResult, nfsstat3>` - The page, or NFS3ERR_BAD_COOKIE for a
+///   cookie of another kind
+pub fn page_by_cookie(
+    entries: Vec<DirEntry>,
+    cookie: ReadDirCookie,
+    max_entries: usize,
+    cookie_of: impl Fn(&DirEntry) -> nfs3::cookie3,
+) -> Result<ReadDirResult, nfs3::nfsstat3> {
+    let after = match cookie {
+        ReadDirCookie::Start => 0,
+        ReadDirCookie::Opaque(cookie) => cookie,
+        _ => return Err(nfs3::nfsstat3::NFS3ERR_BAD_COOKIE),
+    };
+    let mut entries: Vec<(nfs3::cookie3, DirEntry)> = entries
+        .into_iter()
+        .map(|entry| (cookie_of(&entry), entry))
+        .filter(|(cookie, _)| *cookie > after)
+        .collect();
+    entries.sort_by_key(|(cookie, _)| *cookie);
+    let end = entries.len() <= max_entries;
+    let entries = entries.into_iter().take(max_entries).map(|(_, entry)| entry).collect();
+    Ok(ReadDirResult { entries, end })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(names: &[&str]) -> Vec<DirEntry> {
+        names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| DirEntry {
+                fileid: i as u64 + 2,
+                name: name.as_bytes().into(),
+                attr: nfs3::fattr3::default(),
+            })
+            .collect()
+    }
+
+    fn names(result: &ReadDirResult) -> Vec<String> {
+        result.entries.iter().map(|e| String::from_utf8_lossy(&e.name).into_owned()).collect()
+    }
+
+    #[test]
+    fn cookie_pages_survive_changes() {
+        let cookie_of = |entry: &DirEntry| name_cookie(&entry.name);
+        let dir = entries(&["a", "b", "c", "d", "e", "f"]);
+        let first = page_by_cookie(dir.clone(), ReadDirCookie::Start, 3, cookie_of).unwrap();
+        assert!(!first.end);
+        let last = cookie_of(first.entries.last().unwrap());
+
+        // Remove a listed entry, including the one to resume after, and add one
+        let mut changed: Vec<DirEntry> = dir
+            .into_iter()
+            .filter(|e| !first.entries[1..].iter().any(|f| f.name.0 == e.name.0))
+            .collect();
+        changed.extend(entries(&["g"]));
+        let rest = page_by_cookie(changed, ReadDirCookie::Opaque(last), 10, cookie_of).unwrap();
+        assert!(rest.end);
+
+        let mut seen = names(&first);
+        seen.extend(names(&rest));
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), names(&first).len() + names(&rest).len(), "no entry listed twice");
+        for name in ["a", "b", "c", "d", "e", "f"] {
+            assert!(seen.contains(&name.to_string()), "{name} was skipped");
+        }
+    }
+
+    #[test]
+    fn position_pages() {
+        let dir = entries(&["a", "b", "c"]);
+        let page = page_by_position(dir.clone(), ReadDirCookie::AfterFileId(2), 1).unwrap();
+        assert_eq!(names(&page), ["b"]);
+        assert!(!page.end);
+        let page = page_by_position(dir.clone(), ReadDirCookie::Index(2), 5).unwrap();
+        assert_eq!(names(&page), ["c"]);
+        assert!(page.end);
+        assert!(page_by_position(dir, ReadDirCookie::AfterFileId(9), 5).is_err());
+    }
+}
@@ -0,0 +1,161 @@
+//! Information about the call a backend is serving.
+//!
+//! The methods of [`super::NFSFileSystem`] take only what the procedure needs.
+//! Backends calling out to other services, such as databases or object
+//! stores, additionally need to know by when the client expects an answer,
+//! which trace to attach their own requests to, and who is asking. The server
+//! makes that available as a [`RequestContext`] for the duration of every call,
+//! retrieved with [`RequestContext::current`]:
+//!
+//! ```no_run
+//! # use nfs_mamont::vfs::request::RequestContext;
+//! # async fn query(_: Option<std::time::Instant>, _: u64) {}
+//! if let Some(request) = RequestContext::current() {
+//!     query(request.deadline, request.trace_id).await;
+//! }
+//! ```
+//!
+//! The context is task-local: tasks a backend spawns must be given it
+//! explicitly, e.g. by running them in [`RequestContext::scope`]. Calls the
+//! server makes on its own, such as flushing coalesced writes in the background,
+//! run without a context.
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use tracing::Instrument;
+
+use crate::entropy::{Entropy, SystemEntropy};
+use crate::protocol::xdr::rpc::auth_unix;
+
+tokio::task_local! {
+    static CURRENT: RequestContext;
+}
+
+/// The call being served
+#[derive(Clone, Debug)]
+pub struct RequestContext {
+    /// Transaction ID of the call
+    pub xid: u32,
+    /// Identifier of the call unique within the process, for correlating the
+    /// requests of a backend in distributed traces
+    pub trace_id: u64,
+    /// Address of the client
+    pub client_addr: SocketAddr,
+    /// Credentials of the caller, after squashing
+    pub auth: auth_unix,
+    /// When the client stops waiting for the reply, if the listener has a
+    /// request timeout
+    pub deadline: Option<Instant>,
+    /// Span the call is processed in
+    ///
+    /// Events a backend emits while serving the call are recorded in it.
+    pub span: tracing::Span,
+    /// Mapping of backend errors of the listener serving the call, applied by
+    /// [`super::NfsError::from_error`]
+    pub error_mapper: Option<super::ErrorMapper>,
+}
+
+impl RequestContext {
+    /// Creates the context of a call with a new trace ID
+    ///
+    /// # Arguments
+    ///
+    /// * `xid` - Transaction ID of the call
+    /// * `client_addr` - Address of the client
+    /// * `auth` - Credentials of the caller
+    /// * `deadline` - When the client stops waiting for the reply, if known
+    pub fn new(
+        xid: u32,
+        client_addr: SocketAddr,
+        auth: auth_unix,
+        deadline: Option<Instant>,
+    ) -> Self {
+        Self::with_trace_id(xid, next_trace_id(), client_addr, auth, deadline)
+    }
+
+    /// Creates the context of a call with the given trace ID
+    ///
+    /// The server draws trace IDs from the [`crate::entropy::Entropy`] of the
+    /// listener.
+    pub fn with_trace_id(
+        xid: u32,
+        trace_id: u64,
+        client_addr: SocketAddr,
+        auth: auth_unix,
+        deadline: Option<Instant>,
+    ) -> Self {
+        let span = tracing::debug_span!(
+            "rpc",
+            xid,
+            trace_id = %format_args!("{trace_id:016x}"),
+            client = %client_addr,
+            uid = %crate::redaction::id(auth.uid)
+        );
+        Self { xid, trace_id, client_addr, auth, deadline, span, error_mapper: None }
+    }
+
+    /// Returns the context of the call the current task serves, if any
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(Clone::clone).ok()
+    }
+
+    /// Returns the error mapper of the call the current task serves, if any
+    pub(crate) fn current_error_mapper() -> Option<super::ErrorMapper> {
+        CURRENT.try_with(|request| request.error_mapper.clone()).ok().flatten()
+    }
+
+    /// Returns the time left until the deadline, zero once it passed
+    ///
+    /// `None` if the call has no deadline.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Returns whether the deadline passed
+    pub fn is_expired(&self) -> bool {
+        self.remaining() == Some(Duration::ZERO)
+    }
+
+    /// Runs `future` as part of this call, inside its span
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        let span = self.span.clone();
+        CURRENT.scope(self, future.instrument(span)).await
+    }
+}
+
+/// Returns a new trace ID from entropy shared by the process
+///
+/// IDs do not repeat within the process and differ between processes.
+fn next_trace_id() -> u64 {
+    static ENTROPY: OnceLock<SystemEntropy> = OnceLock::new();
+    ENTROPY.get_or_init(SystemEntropy::default).next_u64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn current_within_scope() {
+        assert!(RequestContext::current().is_none());
+        let addr = "10.0.0.1:800".parse().unwrap();
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let request = RequestContext::new(7, addr, auth_unix::default(), None);
+        let other = RequestContext::new(7, addr, auth_unix::default(), None);
+        assert_ne!(request.trace_id, other.trace_id);
+
+        let request = RequestContext { deadline: Some(deadline), ..request };
+        let trace_id = request.trace_id;
+        let seen = request.scope(async { RequestContext::current().unwrap() }).await;
+        assert_eq!((seen.xid, seen.trace_id), (7, trace_id));
+        assert!(seen.remaining().unwrap() > Duration::from_secs(30));
+        assert!(!seen.is_expired());
+        assert!(RequestContext::current().is_none());
+
+        let expired = RequestContext { deadline: Some(Instant::now()), ..seen };
+        assert!(expired.is_expired());
+    }
+}
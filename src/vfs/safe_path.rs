@@ -0,0 +1,261 @@
+//! Composing backend paths from client-supplied names without escaping the
+//! export.
+//!
+//! Backends that keep files under a directory on disk build paths from the
+//! names in `LOOKUP`, `CREATE`, `RENAME` and the like. A client can send any
+//! bytes as a name: `..`, names containing `/`, or names of symbolic links it
+//! created itself pointing outside the export. Joining such names to a path
+//! unchecked gives clients access to the whole file system of the server.
+//!
+//! - [`check_name`] rejects names that are not a single component.
+//! - [`child_path`] and [`join_components`] build paths from checked names.
+//! - [`check_relative`] rejects relative paths leaving their root lexically.
+//! - [`resolve_beneath`] additionally rejects paths whose directories are
+//!   symbolic links leading out of the root.
+//! - `open_beneath` (Linux only) opens a file below a directory with
+//!   `openat2(RESOLVE_BENEATH)`, which the kernel enforces without the race
+//!   between checking and opening that the other helpers leave.
+
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::protocol::xdr::nfs3;
+
+/// Checks that a client-supplied name denotes a single entry of a directory
+///
+/// `.` and `..` are refused as well: clients resolve them themselves, and a
+/// backend must not turn them into paths.
+///
+/// # Returns
+///
+/// * `Result<(), nfsstat3>` - NFS3ERR_INVAL for an empty name, `.`, `..`, or
+///   a name containing `/` or a NUL byte
+pub fn check_name(name: &[u8]) -> Result<(), nfs3::nfsstat3> {
+    if name.is_empty() || name == b"." || name == b".." || name.contains(&b'/') || name.contains(&0)
+    {
+        return Err(nfs3::nfsstat3::NFS3ERR_INVAL);
+    }
+    Ok(())
+}
+
+/// Returns the path of the entry `name` of the directory at `dir`
+///
+/// # Returns
+///
+/// * `Result<PathBuf, nfsstat3>` - The joined path, or the error of
+///   [`check_name`]
+#[cfg(unix)]
+pub fn child_path(dir: &Path, name: &[u8]) -> Result<PathBuf, nfs3::nfsstat3> {
+    use std::os::unix::ffi::OsStrExt;
+
+    check_name(name)?;
+    Ok(dir.join(std::ffi::OsStr::from_bytes(name)))
+}
+
+/// Returns the path below `root` made of `components`, e.g. those of
+/// [`super::inode_map::InodeMap::components`]
+///
+/// # Returns
+///
+/// * `Result<PathBuf, nfsstat3>` - The joined path, or the error of
+///   [`check_name`] for the first invalid component
+#[cfg(unix)]
+pub fn join_components<'a>(
+    root: &Path,
+    components: impl IntoIterator<Item = &'a [u8]>,
+) -> Result<PathBuf, nfs3::nfsstat3> {
+    components.into_iter().try_fold(root.to_path_buf(), |path, name| child_path(&path, name))
+}
+
+/// Checks that a relative path stays below the directory it is relative to
+///
+/// The check is lexical: symbolic links are not looked at, see
+/// [`resolve_beneath`].
+///
+/// # Returns
+///
+/// * `Result<(), nfsstat3>` - NFS3ERR_INVAL if the path is absolute or has a
+///   `..` component
+pub fn check_relative(path: &Path) -> Result<(), nfs3::nfsstat3> {
+    let escapes = path
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir));
+    if escapes {
+        return Err(nfs3::nfsstat3::NFS3ERR_INVAL);
+    }
+    Ok(())
+}
+
+/// Resolves a relative path below `root`, refusing to leave it through
+/// symbolic links
+///
+/// The directories of the path are resolved, following symbolic links, and
+/// must stay below `root`. The last component is appended as is, since NFS
+/// procedures act on links themselves; callers opening it should not follow
+/// links, e.g. by passing `O_NOFOLLOW`. Directories missing on disk are
+/// appended as is too.
+///
+/// A link may be swapped between the check and the use of the path. Backends
+/// exporting directories that others write to should open files with
+/// `open_beneath` where available.
+///
+/// # Returns
+///
+/// * `io::Result<PathBuf>` - The resolved path below the canonical `root`;
+///   an error of kind `InvalidInput` if `relative` leaves `root` lexically,
+///   `PermissionDenied` if a link leads out of it, or the error of resolving
+///   `root`
+pub fn resolve_beneath(root: &Path, relative: &Path) -> io::Result<PathBuf> {
+    if check_relative(relative).is_err() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "path leaves its root"));
+    }
+    let root = root.canonicalize()?;
+    let mut components: Vec<_> = relative.components().map(Component::as_os_str).collect();
+    let Some(last) = components.pop() else {
+        return Ok(root);
+    };
+    let mut resolved = root.clone();
+    let mut missing = false;
+    for component in components {
+        resolved.push(component);
+        if missing {
+            continue;
+        }
+        match resolved.canonicalize() {
+            Ok(canonical) => resolved = canonical,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => missing = true,
+            Err(err) => return Err(err),
+        }
+        if !resolved.starts_with(&root) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "symbolic link leads out of the root",
+            ));
+        }
+    }
+    resolved.push(last);
+    Ok(resolved)
+}
+
+/// Opens a file below the directory `root` with `openat2(RESOLVE_BENEATH)`
+///
+/// The kernel refuses to leave `root` while resolving `relative`, be it by
+/// `..`, an absolute path or a symbolic link, and refuses magic links like
+/// `/proc/self/fd/*`. Kernels older than 5.6 lack `openat2`; there the path
+/// is opened one component at a time with `O_NOFOLLOW`, refusing symbolic
+/// links altogether.
+///
+/// # Arguments
+///
+/// * `root` - Directory the file must be below
+/// * `relative` - Path of the file relative to `root`
+/// * `flags` - Flags of `open(2)`, e.g. `libc::O_RDONLY`; `O_CLOEXEC` is added
+/// * `mode` - Mode of a file created with `O_CREAT`
+#[cfg(target_os = "linux")]
+pub fn open_beneath(
+    root: &std::fs::File,
+    relative: &Path,
+    flags: libc::c_int,
+    mode: libc::mode_t,
+) -> io::Result<std::fs::File> {
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(relative.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let flags = flags | libc::O_CLOEXEC;
+    // SAFETY: `open_how` is plain data, for which all zeroes is valid
+    let mut how: libc::open_how = unsafe { std::mem::zeroed() };
+    how.flags = flags as u64;
+    how.mode = u64::from(mode);
+    how.resolve = libc::RESOLVE_BENEATH | libc::RESOLVE_NO_MAGICLINKS;
+    // SAFETY: the path is NUL terminated and `how` outlives the call
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_openat2,
+            root.as_raw_fd(),
+            path.as_ptr(),
+            &how as *const libc::open_how,
+            std::mem::size_of::<libc::open_how>(),
+        )
+    };
+    if fd >= 0 {
+        // SAFETY: the descriptor was just opened and is owned by nobody else
+        return Ok(unsafe { std::fs::File::from_raw_fd(fd as libc::c_int) });
+    }
+    let err = io::Error::last_os_error();
+    if err.raw_os_error() != Some(libc::ENOSYS) {
+        return Err(err);
+    }
+
+    if check_relative(relative).is_err() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "path leaves its root"));
+    }
+    let openat = |dir: libc::c_int, name: &[u8], flags: libc::c_int| {
+        let name = std::ffi::CString::new(name)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        // SAFETY: the name is NUL terminated
+        let fd = unsafe { libc::openat(dir, name.as_ptr(), flags | libc::O_NOFOLLOW, mode) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the descriptor was just opened and is owned by nobody else
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    };
+    let mut names: Vec<_> = relative
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.as_bytes()),
+            _ => None,
+        })
+        .collect();
+    let last = names.pop().unwrap_or(b".");
+    let mut dir: Option<OwnedFd> = None;
+    for name in names {
+        let parent = dir.as_ref().map_or(root.as_raw_fd(), AsRawFd::as_raw_fd);
+        dir = Some(openat(parent, name, libc::O_PATH | libc::O_DIRECTORY | libc::O_CLOEXEC)?);
+    }
+    let parent = dir.as_ref().map_or(root.as_raw_fd(), AsRawFd::as_raw_fd);
+    Ok(openat(parent, last, flags)?.into())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_names_and_links_leaving_the_root() {
+        assert!(check_name(b"file.txt").is_ok());
+        for name in [&b""[..], b".", b"..", b"a/b", b"a\0"] {
+            assert!(matches!(check_name(name), Err(nfs3::nfsstat3::NFS3ERR_INVAL)));
+        }
+        let root = Path::new("/export");
+        assert_eq!(join_components(root, [&b"a"[..], b"b"]).unwrap(), root.join("a/b"));
+        assert!(join_components(root, [&b"a"[..], b".."]).is_err());
+        assert!(check_relative(Path::new("a/./b")).is_ok());
+        assert!(check_relative(Path::new("a/../../etc")).is_err());
+        assert!(check_relative(Path::new("/etc")).is_err());
+
+        let dir = std::env::temp_dir().join(format!("nfs-mamont-safe-path-{}", std::process::id()));
+        let root = dir.join("root");
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::os::unix::fs::symlink(&dir, root.join("out")).unwrap();
+        std::os::unix::fs::symlink("sub", root.join("in")).unwrap();
+        let canonical = root.canonicalize().unwrap();
+        assert_eq!(resolve_beneath(&root, Path::new("in/f")).unwrap(), canonical.join("sub/f"));
+        assert_eq!(resolve_beneath(&root, Path::new("out")).unwrap(), canonical.join("out"));
+        assert_eq!(resolve_beneath(&root, Path::new("new/f")).unwrap(), canonical.join("new/f"));
+        let err = resolve_beneath(&root, Path::new("out/root")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+
+        #[cfg(target_os = "linux")]
+        {
+            std::fs::write(root.join("sub/f"), b"data").unwrap();
+            let root_dir = std::fs::File::open(&root).unwrap();
+            assert!(open_beneath(&root_dir, Path::new("in/f"), libc::O_RDONLY, 0).is_ok());
+            assert!(open_beneath(&root_dir, Path::new("out/root"), libc::O_RDONLY, 0).is_err());
+            assert!(open_beneath(&root_dir, Path::new("../root"), libc::O_RDONLY, 0).is_err());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
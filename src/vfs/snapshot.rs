@@ -0,0 +1,587 @@
+//! Read-only access to snapshots under a synthetic `.snapshot` directory.
+//!
+//! [`SnapshotFs`] wraps a file system and adds a `.snapshot` directory to its
+//! root, holding one directory per snapshot reported by a [`SnapshotProvider`].
+//! Users restore files by copying them out of `.snapshot/<name>`, as on most NAS
+//! appliances. The `.snapshot` directory can be looked up but is not listed in
+//! the root directory, so tools walking the tree do not descend into every
+//! snapshot.
+//!
+//! File IDs of all file systems share one 64-bit space: the top 16 bits select
+//! the live file system (0), the `.snapshot` directory (1) or a snapshot (2 and
+//! up), and the low 48 bits carry the ID within it. IDs of the wrapped file
+//! systems must therefore fit in 48 bits. Snapshots are numbered in the order
+//! they are first accessed, so handles into snapshots do not survive a restart.
+//!
+//! Snapshots must use the same [`ReadDirCookieKind`] as the live file system.
+
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use tracing::error;
+
+use super::{
+    readdir, Capabilities, DirEntry, NFSFileSystem, ReadDirCookie, ReadDirCookieKind, ReadDirResult,
+};
+use crate::protocol::xdr::nfs3;
+
+/// Name of the synthetic directory
+const SNAPSHOT_DIR: &[u8] = b".snapshot";
+/// Bits of a file ID holding the ID within a namespace
+const LOCAL_BITS: u32 = 48;
+/// Mask of the ID within a namespace
+const LOCAL_MASK: u64 = (1 << LOCAL_BITS) - 1;
+/// Namespace of the `.snapshot` directory
+const SNAPSHOT_DIR_NAMESPACE: u64 = 1;
+/// Namespace of the first snapshot
+const FIRST_SNAPSHOT_NAMESPACE: u64 = 2;
+/// File ID of the `.snapshot` directory
+const SNAPSHOT_DIR_ID: nfs3::fileid3 = SNAPSHOT_DIR_NAMESPACE << LOCAL_BITS | 1;
+
+/// A file system held by a [`SnapshotFs`]
+pub type SharedFileSystem = Arc<dyn NFSFileSystem + Send + Sync>;
+
+/// Source of the snapshots exposed by a [`SnapshotFs`]
+#[async_trait]
+pub trait SnapshotProvider: Send + Sync {
+    /// Returns the names of the available snapshots
+    async fn list(&self) -> Result<Vec<nfs3::filename3>, nfs3::nfsstat3>;
+
+    /// Opens a snapshot as a file system
+    ///
+    /// Returns NFS3ERR_NOENT for unknown names. The returned file system is kept
+    /// for the lifetime of the [`SnapshotFs`] and never written to.
+    async fn open(&self, name: &nfs3::filename3) -> Result<SharedFileSystem, nfs3::nfsstat3>;
+}
+
+/// What a file ID refers to
+enum Target {
+    /// File of the live file system, with its ID there
+    Live(nfs3::fileid3),
+    /// The `.snapshot` directory
+    SnapshotDir,
+    /// File of a snapshot, with the namespace and the ID within the snapshot
+    Snapshot(SharedFileSystem, u64, nfs3::fileid3),
+}
+
+/// File system combinator exposing snapshots under `/.snapshot`
+pub struct SnapshotFs<F, P> {
+    /// Live file system
+    inner: F,
+    /// Source of snapshots
+    provider: P,
+    /// Snapshots opened so far, in namespace order
+    snapshots: Mutex<Vec<(Vec<u8>, SharedFileSystem)>>,
+}
+
+impl<F: NFSFileSystem + Send + Sync, P: SnapshotProvider> SnapshotFs<F, P> {
+    /// Wraps `inner`, exposing the snapshots of `provider`
+    pub fn new(inner: F, provider: P) -> Self {
+        Self { inner, provider, snapshots: Mutex::default() }
+    }
+
+    /// Returns the live file system
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// Resolves a file ID
+    fn resolve(&self, id: nfs3::fileid3) -> Result<Target, nfs3::nfsstat3> {
+        let (namespace, local) = (id >> LOCAL_BITS, id & LOCAL_MASK);
+        match namespace {
+            0 => Ok(Target::Live(local)),
+            SNAPSHOT_DIR_NAMESPACE if id == SNAPSHOT_DIR_ID => Ok(Target::SnapshotDir),
+            SNAPSHOT_DIR_NAMESPACE => Err(nfs3::nfsstat3::NFS3ERR_STALE),
+            _ => {
+                let snapshots = self.snapshots.lock().unwrap();
+                let index = (namespace - FIRST_SNAPSHOT_NAMESPACE) as usize;
+                let (_, fs) = snapshots.get(index).ok_or(nfs3::nfsstat3::NFS3ERR_STALE)?;
+                Ok(Target::Snapshot(fs.clone(), namespace, local))
+            }
+        }
+    }
+
+    /// Resolves the ID of a file of the live file system
+    fn live(&self, id: nfs3::fileid3) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        match self.resolve(id)? {
+            Target::Live(local) => Ok(local),
+            _ => Err(nfs3::nfsstat3::NFS3ERR_ROFS),
+        }
+    }
+
+    /// Resolves the directory of a new entry in the live file system
+    ///
+    /// Refuses to shadow the `.snapshot` directory with an entry of the same name.
+    fn live_entry(
+        &self,
+        dirid: nfs3::fileid3,
+        name: &nfs3::filename3,
+    ) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        let dirid = self.live(dirid)?;
+        if dirid == self.inner.root_dir() && name[..] == *SNAPSHOT_DIR {
+            return Err(nfs3::nfsstat3::NFS3ERR_EXIST);
+        }
+        Ok(dirid)
+    }
+
+    /// Returns a snapshot and its namespace, opening it on first use
+    async fn snapshot(
+        &self,
+        name: &nfs3::filename3,
+    ) -> Result<(u64, SharedFileSystem), nfs3::nfsstat3> {
+        let position = |snapshots: &[(Vec<u8>, SharedFileSystem)]| {
+            snapshots.iter().position(|(known, _)| known[..] == name[..])
+        };
+        {
+            let snapshots = self.snapshots.lock().unwrap();
+            if let Some(index) = position(&snapshots) {
+                return Ok((FIRST_SNAPSHOT_NAMESPACE + index as u64, snapshots[index].1.clone()));
+            }
+        }
+        let fs = self.provider.open(name).await?;
+        let mut snapshots = self.snapshots.lock().unwrap();
+        // another call may have opened it meanwhile
+        let index = match position(&snapshots) {
+            Some(index) => index,
+            None if FIRST_SNAPSHOT_NAMESPACE + snapshots.len() as u64 > u16::MAX.into() => {
+                error!("too many snapshots opened");
+                return Err(nfs3::nfsstat3::NFS3ERR_NOSPC);
+            }
+            None => {
+                snapshots.push((name.to_vec(), fs));
+                snapshots.len() - 1
+            }
+        };
+        Ok((FIRST_SNAPSHOT_NAMESPACE + index as u64, snapshots[index].1.clone()))
+    }
+}
+
+/// Combines a namespace and an ID within it
+fn join(namespace: u64, local: nfs3::fileid3) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+    if local > LOCAL_MASK {
+        error!("file id {} does not fit in {} bits", local, LOCAL_BITS);
+        return Err(nfs3::nfsstat3::NFS3ERR_SERVERFAULT);
+    }
+    Ok(namespace << LOCAL_BITS | local)
+}
+
+/// Moves attributes into a namespace
+fn join_attr(namespace: u64, mut attr: nfs3::fattr3) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+    attr.fileid = join(namespace, attr.fileid)?;
+    Ok(attr)
+}
+
+/// Moves directory entries into a namespace
+fn join_entries(
+    namespace: u64,
+    mut result: ReadDirResult,
+) -> Result<ReadDirResult, nfs3::nfsstat3> {
+    for entry in &mut result.entries {
+        entry.fileid = join(namespace, entry.fileid)?;
+        entry.attr = join_attr(namespace, entry.attr)?;
+    }
+    Ok(result)
+}
+
+#[async_trait]
+impl<F: NFSFileSystem + Send + Sync, P: SnapshotProvider> NFSFileSystem for SnapshotFs<F, P> {
+    fn generation(&self) -> u64 {
+        self.inner.generation()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn root_dir(&self) -> nfs3::fileid3 {
+        self.inner.root_dir()
+    }
+
+    async fn lookup(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        match self.resolve(dirid)? {
+            Target::Live(dirid)
+                if dirid == self.inner.root_dir() && filename[..] == *SNAPSHOT_DIR =>
+            {
+                Ok(SNAPSHOT_DIR_ID)
+            }
+            Target::Live(dirid) => join(0, self.inner.lookup(dirid, filename).await?),
+            Target::SnapshotDir => match &filename[..] {
+                b"." => Ok(SNAPSHOT_DIR_ID),
+                b".." => Ok(self.inner.root_dir()),
+                _ => {
+                    let (namespace, fs) = self.snapshot(filename).await?;
+                    join(namespace, fs.root_dir())
+                }
+            },
+            Target::Snapshot(fs, _, dirid) if dirid == fs.root_dir() && filename[..] == *b".." => {
+                Ok(SNAPSHOT_DIR_ID)
+            }
+            Target::Snapshot(fs, namespace, dirid) => {
+                join(namespace, fs.lookup(dirid, filename).await?)
+            }
+        }
+    }
+
+    async fn getattr(&self, id: nfs3::fileid3) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        match self.resolve(id)? {
+            Target::Live(id) => self.inner.getattr(id).await,
+            Target::SnapshotDir => {
+                let root = self.inner.getattr(self.inner.root_dir()).await?;
+                Ok(nfs3::fattr3 {
+                    ftype: nfs3::ftype3::NF3DIR,
+                    mode: 0o555,
+                    nlink: 2,
+                    size: 0,
+                    used: 0,
+                    fileid: SNAPSHOT_DIR_ID,
+                    ..root
+                })
+            }
+            Target::Snapshot(fs, namespace, id) => join_attr(namespace, fs.getattr(id).await?),
+        }
+    }
+
+    async fn setattr(
+        &self,
+        id: nfs3::fileid3,
+        setattr: nfs3::sattr3,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        self.inner.setattr(self.live(id)?, setattr).await
+    }
+
+    fn reports_pre_op_attrs(&self) -> bool {
+        self.inner.reports_pre_op_attrs()
+    }
+
+    async fn setattr_wcc(
+        &self,
+        id: nfs3::fileid3,
+        setattr: nfs3::sattr3,
+    ) -> Result<(Option<nfs3::wcc_attr>, nfs3::fattr3), nfs3::nfsstat3> {
+        self.inner.setattr_wcc(self.live(id)?, setattr).await
+    }
+
+    async fn read(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        count: u32,
+    ) -> Result<(Vec<u8>, bool), nfs3::nfsstat3> {
+        match self.resolve(id)? {
+            Target::Live(id) => self.inner.read(id, offset, count).await,
+            Target::SnapshotDir => Err(nfs3::nfsstat3::NFS3ERR_ISDIR),
+            Target::Snapshot(fs, _, id) => fs.read(id, offset, count).await,
+        }
+    }
+
+    async fn write(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        self.inner.write(self.live(id)?, offset, data).await
+    }
+
+    async fn write_partial(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(nfs3::fattr3, u32), nfs3::nfsstat3> {
+        self.inner.write_partial(self.live(id)?, offset, data).await
+    }
+
+    async fn write_wcc(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(Option<nfs3::wcc_attr>, nfs3::fattr3, u32), nfs3::nfsstat3> {
+        self.inner.write_wcc(self.live(id)?, offset, data).await
+    }
+
+    async fn create(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        attr: nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        let (id, attr) =
+            self.inner.create(self.live_entry(dirid, filename)?, filename, attr).await?;
+        Ok((join(0, id)?, attr))
+    }
+
+    async fn create_exclusive(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        join(0, self.inner.create_exclusive(self.live_entry(dirid, filename)?, filename).await?)
+    }
+
+    async fn mkdir(
+        &self,
+        dirid: nfs3::fileid3,
+        dirname: &nfs3::filename3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        let (id, attr) = self.inner.mkdir(self.live_entry(dirid, dirname)?, dirname).await?;
+        Ok((join(0, id)?, attr))
+    }
+
+    async fn remove(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> Result<(), nfs3::nfsstat3> {
+        self.inner.remove(self.live(dirid)?, filename).await
+    }
+
+    async fn rename(
+        &self,
+        from_dirid: nfs3::fileid3,
+        from_filename: &nfs3::filename3,
+        to_dirid: nfs3::fileid3,
+        to_filename: &nfs3::filename3,
+    ) -> Result<(), nfs3::nfsstat3> {
+        let from_dirid = self.live(from_dirid)?;
+        let to_dirid = match self.live_entry(to_dirid, to_filename) {
+            Err(nfs3::nfsstat3::NFS3ERR_ROFS) => return Err(nfs3::nfsstat3::NFS3ERR_XDEV),
+            to_dirid => to_dirid?,
+        };
+        self.inner.rename(from_dirid, from_filename, to_dirid, to_filename).await
+    }
+
+    fn readdir_cookie_kind(&self) -> ReadDirCookieKind {
+        self.inner.readdir_cookie_kind()
+    }
+
+    fn readdir_has_attributes(&self) -> bool {
+        self.inner.readdir_has_attributes()
+    }
+
+    fn readdir_entry_cookie(
+        &self,
+        dirid: nfs3::fileid3,
+        fileid: nfs3::fileid3,
+        name: &nfs3::filename3,
+    ) -> nfs3::cookie3 {
+        match self.resolve(dirid) {
+            Ok(Target::Live(dirid)) => {
+                self.inner.readdir_entry_cookie(dirid, fileid & LOCAL_MASK, name)
+            }
+            Ok(Target::Snapshot(fs, _, dirid)) => {
+                fs.readdir_entry_cookie(dirid, fileid & LOCAL_MASK, name)
+            }
+            _ => readdir::name_cookie(name),
+        }
+    }
+
+    async fn readdir_with_cookie(
+        &self,
+        dirid: nfs3::fileid3,
+        cookie: ReadDirCookie,
+        max_entries: usize,
+    ) -> Result<ReadDirResult, nfs3::nfsstat3> {
+        match self.resolve(dirid)? {
+            Target::Live(dirid) => {
+                join_entries(0, self.inner.readdir_with_cookie(dirid, cookie, max_entries).await?)
+            }
+            Target::SnapshotDir => {
+                let mut entries = Vec::new();
+                for name in self.provider.list().await? {
+                    let (namespace, fs) = self.snapshot(&name).await?;
+                    let root = fs.root_dir();
+                    let attr = join_attr(namespace, fs.getattr(root).await?)?;
+                    entries.push(DirEntry { fileid: join(namespace, root)?, name, attr });
+                }
+                if self.inner.readdir_cookie_kind() == ReadDirCookieKind::Opaque {
+                    readdir::page_by_cookie(entries, cookie, max_entries, |entry| {
+                        readdir::name_cookie(&entry.name)
+                    })
+                } else {
+                    readdir::page_by_position(entries, cookie, max_entries)
+                }
+            }
+            Target::Snapshot(fs, namespace, dirid) => {
+                if fs.readdir_cookie_kind() != self.inner.readdir_cookie_kind() {
+                    error!("snapshot uses a different cookie kind than the live file system");
+                    return Err(nfs3::nfsstat3::NFS3ERR_NOTSUPP);
+                }
+                let cookie = match cookie {
+                    ReadDirCookie::AfterFileId(id) if id >> LOCAL_BITS != namespace => {
+                        return Err(nfs3::nfsstat3::NFS3ERR_BAD_COOKIE);
+                    }
+                    ReadDirCookie::AfterFileId(id) => ReadDirCookie::AfterFileId(id & LOCAL_MASK),
+                    cookie => cookie,
+                };
+                join_entries(namespace, fs.readdir_with_cookie(dirid, cookie, max_entries).await?)
+            }
+        }
+    }
+
+    async fn symlink(
+        &self,
+        dirid: nfs3::fileid3,
+        linkname: &nfs3::filename3,
+        symlink: &nfs3::nfspath3,
+        attr: &nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        let dirid = self.live_entry(dirid, linkname)?;
+        let (id, attr) = self.inner.symlink(dirid, linkname, symlink, attr).await?;
+        Ok((join(0, id)?, attr))
+    }
+
+    async fn readlink(&self, id: nfs3::fileid3) -> Result<nfs3::nfspath3, nfs3::nfsstat3> {
+        match self.resolve(id)? {
+            Target::Live(id) => self.inner.readlink(id).await,
+            Target::SnapshotDir => Err(nfs3::nfsstat3::NFS3ERR_INVAL),
+            Target::Snapshot(fs, _, id) => fs.readlink(id).await,
+        }
+    }
+
+    async fn link(
+        &self,
+        file_id: nfs3::fileid3,
+        link_dir_id: nfs3::fileid3,
+        link_name: &nfs3::filename3,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        let link_dir_id = self.live_entry(link_dir_id, link_name)?;
+        let file_id = self.live(file_id).map_err(|_| nfs3::nfsstat3::NFS3ERR_XDEV)?;
+        self.inner.link(file_id, link_dir_id, link_name).await
+    }
+
+    async fn mknod(
+        &self,
+        dir_id: nfs3::fileid3,
+        name: &nfs3::filename3,
+        ftype: nfs3::ftype3,
+        specdata: nfs3::specdata3,
+        attrs: &nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        let dir_id = self.live_entry(dir_id, name)?;
+        let (id, attr) = self.inner.mknod(dir_id, name, ftype, specdata, attrs).await?;
+        Ok((join(0, id)?, attr))
+    }
+
+    async fn commit(
+        &self,
+        file_id: nfs3::fileid3,
+        offset: u64,
+        count: u32,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        self.inner.commit(self.live(file_id)?, offset, count).await
+    }
+
+    async fn allocate(
+        &self,
+        file_id: nfs3::fileid3,
+        offset: u64,
+        length: u64,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        self.inner.allocate(self.live(file_id)?, offset, length).await
+    }
+
+    async fn deallocate(
+        &self,
+        file_id: nfs3::fileid3,
+        offset: u64,
+        length: u64,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        self.inner.deallocate(self.live(file_id)?, offset, length).await
+    }
+
+    async fn readahead(&self, file_id: nfs3::fileid3, offset: u64, length: u64) {
+        match self.resolve(file_id) {
+            Ok(Target::Live(id)) => self.inner.readahead(id, offset, length).await,
+            Ok(Target::Snapshot(fs, _, id)) => fs.readahead(id, offset, length).await,
+            _ => {}
+        }
+    }
+
+    async fn fsinfo(
+        &self,
+        root_fileid: nfs3::fileid3,
+    ) -> Result<nfs3::fs::fsinfo3, nfs3::nfsstat3> {
+        match self.resolve(root_fileid)? {
+            Target::Live(id) => self.inner.fsinfo(id).await,
+            _ => {
+                let mut info = self.inner.fsinfo(self.inner.root_dir()).await?;
+                info.obj_attributes = self.getattr(root_fileid).await.ok();
+                Ok(info)
+            }
+        }
+    }
+
+    async fn fsstat(
+        &self,
+        root_fileid: nfs3::fileid3,
+    ) -> Result<nfs3::fs::FSSTAT3resok, nfs3::nfsstat3> {
+        match self.resolve(root_fileid)? {
+            Target::Live(id) => self.inner.fsstat(id).await,
+            _ => {
+                let mut stat = self.inner.fsstat(self.inner.root_dir()).await?;
+                stat.obj_attributes = self.getattr(root_fileid).await.ok();
+                Ok(stat)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::testing::{Call, MockFs};
+
+    /// Provides a single snapshot named `daily`
+    struct Daily(SharedFileSystem);
+
+    #[async_trait]
+    impl SnapshotProvider for Daily {
+        async fn list(&self) -> Result<Vec<nfs3::filename3>, nfs3::nfsstat3> {
+            Ok(vec![b"daily".to_vec().into()])
+        }
+
+        async fn open(&self, name: &nfs3::filename3) -> Result<SharedFileSystem, nfs3::nfsstat3> {
+            match &name[..] {
+                b"daily" => Ok(self.0.clone()),
+                _ => Err(nfs3::nfsstat3::NFS3ERR_NOENT),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn exposes_snapshots_read_only() {
+        let live = MockFs::new();
+        live.stub(Call::Getattr { id: 1 }, Ok(nfs3::fattr3 { fileid: 1, ..Default::default() }));
+        let daily = Arc::new(MockFs::new());
+        daily.stub(Call::Lookup { dirid: 1, name: b"file".to_vec() }, Ok(5_u64));
+        daily.stub(Call::Getattr { id: 1 }, Ok(nfs3::fattr3 { fileid: 1, ..Default::default() }));
+        daily.stub(Call::Getattr { id: 5 }, Ok(nfs3::fattr3 { fileid: 5, ..Default::default() }));
+        let fs = SnapshotFs::new(live, Daily(daily.clone()));
+
+        let snapdir = fs.lookup(1, &b".snapshot".to_vec().into()).await.unwrap();
+        assert_eq!(fs.getattr(snapdir).await.unwrap().fileid, snapdir);
+        let root = fs.lookup(snapdir, &b"daily".to_vec().into()).await.unwrap();
+        let file = fs.lookup(root, &b"file".to_vec().into()).await.unwrap();
+        assert_eq!(file, 2 << LOCAL_BITS | 5);
+        assert_eq!(fs.getattr(file).await.unwrap().fileid, file);
+        assert_eq!(fs.lookup(root, &b"..".to_vec().into()).await.ok(), Some(snapdir));
+
+        let listing = fs.readdir_with_cookie(snapdir, ReadDirCookie::Start, 10).await.unwrap();
+        assert_eq!(listing.entries.len(), 1);
+        assert_eq!(listing.entries[0].fileid, root);
+        assert!(listing.end);
+
+        assert!(matches!(fs.write(file, 0, b"x").await, Err(nfs3::nfsstat3::NFS3ERR_ROFS)));
+        assert!(matches!(
+            fs.mkdir(1, &b".snapshot".to_vec().into()).await,
+            Err(nfs3::nfsstat3::NFS3ERR_EXIST)
+        ));
+        daily.assert_done();
+    }
+}
@@ -0,0 +1,686 @@
+//! Import and export of file system contents as tar archives.
+//!
+//! [`load_tar`] unpacks an archive into a directory of any [`NFSFileSystem`],
+//! and [`dump_tar`] packs a directory tree back into one. Tests and ephemeral
+//! exports can so be seeded from an archive made with standard tools instead
+//! of building entries by hand, and their contents captured afterwards.
+//!
+//! Archives are written in the POSIX ustar format, with pax extended headers
+//! for names and link targets too long for it, and read in the ustar, pax and
+//! GNU formats. Directories, regular files, symbolic links and hard links are
+//! supported, with their mode, owner and modification time; other entries are
+//! skipped when loading and not written when dumping. Whole archives are held
+//! in memory.
+
+use std::collections::HashMap;
+use std::io;
+
+use tracing::{debug, warn};
+
+use super::{readdir, NFSFileSystem, NfsError, ReadDirCookie};
+use crate::protocol::xdr::nfs3;
+
+/// Size of tar headers and of the blocks data is padded to
+const BLOCK: usize = 512;
+/// Largest number of bytes read or written at once
+const CHUNK: u32 = 1024 * 1024;
+/// Largest number of entries listed at once
+const LIST_BATCH: usize = 1024;
+
+/// Kind of an archive entry
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    File,
+    HardLink,
+    Symlink,
+    Directory,
+}
+
+/// Archive entry, without its data
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Header {
+    /// Path relative to the archive root, without a trailing slash
+    path: Vec<u8>,
+    kind: Kind,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    /// Length of the data, 0 for anything but regular files
+    size: u64,
+    /// Modification time in seconds since the epoch
+    mtime: u64,
+    /// Target of links
+    link: Vec<u8>,
+}
+
+/// Returns an error for a malformed archive
+fn invalid(message: impl Into<String>) -> NfsError {
+    let message = message.into();
+    NfsError::with_source(
+        nfs3::nfsstat3::NFS3ERR_INVAL,
+        io::Error::new(io::ErrorKind::InvalidData, message),
+    )
+}
+
+/// Unpacks an archive into a directory
+///
+/// Missing parent directories are created. Existing directories are reused
+/// and existing regular files are overwritten; other existing names fail with
+/// `NFS3ERR_EXIST`. Paths with `..` components are refused.
+///
+/// # Arguments
+///
+/// * `fs` - File system to unpack into
+/// * `dirid` - Directory the archive root maps to
+/// * `archive` - The archive
+///
+/// # Returns
+///
+/// * `Result<usize, NfsError>` - The number of entries unpacked
+pub async fn load_tar(
+    fs: &(dyn NFSFileSystem + Send + Sync),
+    dirid: nfs3::fileid3,
+    archive: &[u8],
+) -> Result<usize, NfsError> {
+    let mut loader = Loader { fs, root: dirid, ids: HashMap::new(), dirs: Vec::new() };
+    let entries = parse(archive)?;
+    let count = entries.len();
+    for (header, data) in entries {
+        loader.load(&header, data).await?;
+    }
+    // directory times change with every entry added, so they are set last
+    for (id, header) in loader.dirs.iter().rev() {
+        fs.setattr(*id, attributes(header)).await?;
+    }
+    Ok(count)
+}
+
+/// Packs a directory tree into an archive
+///
+/// The directory itself is the archive root and has no entry. Files with
+/// several names are stored once, and as hard links to that entry at their
+/// other names.
+///
+/// # Arguments
+///
+/// * `fs` - File system to pack
+/// * `dirid` - Directory to pack
+///
+/// # Returns
+///
+/// * `Result<Vec<u8>, NfsError>` - The archive
+pub async fn dump_tar(
+    fs: &(dyn NFSFileSystem + Send + Sync),
+    dirid: nfs3::fileid3,
+) -> Result<Vec<u8>, NfsError> {
+    let mut archive = Vec::new();
+    let mut linked: HashMap<nfs3::fileid3, Vec<u8>> = HashMap::new();
+    let mut pending = vec![(dirid, Vec::new())];
+    while let Some((dirid, prefix)) = pending.pop() {
+        let mut subdirectories = Vec::new();
+        for (name, attr) in list(fs, dirid).await? {
+            let mut path = prefix.clone();
+            if !path.is_empty() {
+                path.push(b'/');
+            }
+            path.extend_from_slice(&name);
+            let mut header = Header {
+                path,
+                kind: Kind::File,
+                mode: attr.mode & 0o7777,
+                uid: attr.uid,
+                gid: attr.gid,
+                size: 0,
+                mtime: u64::from(attr.mtime.seconds),
+                link: Vec::new(),
+            };
+            match attr.ftype {
+                nfs3::ftype3::NF3DIR => {
+                    header.kind = Kind::Directory;
+                    write_entry(&mut archive, &header, &[]);
+                    subdirectories.push((attr.fileid, header.path));
+                }
+                nfs3::ftype3::NF3LNK => {
+                    header.kind = Kind::Symlink;
+                    header.link = fs.readlink(attr.fileid).await?.0;
+                    write_entry(&mut archive, &header, &[]);
+                }
+                nfs3::ftype3::NF3REG => {
+                    if let Some(first) = linked.get(&attr.fileid) {
+                        header.kind = Kind::HardLink;
+                        header.link = first.clone();
+                        write_entry(&mut archive, &header, &[]);
+                        continue;
+                    }
+                    if attr.nlink > 1 {
+                        linked.insert(attr.fileid, header.path.clone());
+                    }
+                    let data = read_all(fs, attr.fileid).await?;
+                    header.size = data.len() as u64;
+                    write_entry(&mut archive, &header, &data);
+                }
+                _ => debug!("not archiving {:?} of type {:?}", header.path, attr.ftype),
+            }
+        }
+        // visit subdirectories in listing order
+        pending.extend(subdirectories.into_iter().rev());
+    }
+    archive.resize(archive.len() + 2 * BLOCK, 0);
+    Ok(archive)
+}
+
+/// Unpacks entries into a file system
+struct Loader<'a> {
+    fs: &'a (dyn NFSFileSystem + Send + Sync),
+    /// Directory the archive root maps to
+    root: nfs3::fileid3,
+    /// File IDs of the paths unpacked or looked up so far
+    ids: HashMap<Vec<u8>, nfs3::fileid3>,
+    /// Directories unpacked, whose attributes are set at the end
+    dirs: Vec<(nfs3::fileid3, Header)>,
+}
+
+impl Loader<'_> {
+    /// Unpacks an entry
+    async fn load(&mut self, header: &Header, data: &[u8]) -> Result<(), NfsError> {
+        let (parent, name) = match header.path.iter().rposition(|&b| b == b'/') {
+            Some(at) => (&header.path[..at], &header.path[at + 1..]),
+            None => (&header.path[..0], &header.path[..]),
+        };
+        let dirid = self.directory(parent).await?;
+        let name = nfs3::filename3::from(name);
+        let id = match header.kind {
+            Kind::Directory => {
+                let id = match self.fs.mkdir(dirid, &name).await {
+                    Ok((id, _)) => id,
+                    Err(nfs3::nfsstat3::NFS3ERR_EXIST) => self.fs.lookup(dirid, &name).await?,
+                    Err(stat) => return Err(stat.into()),
+                };
+                self.dirs.push((id, header.clone()));
+                id
+            }
+            Kind::File => {
+                let id = match self.fs.create(dirid, &name, nfs3::sattr3::default()).await {
+                    Ok((id, _)) => id,
+                    Err(nfs3::nfsstat3::NFS3ERR_EXIST) => {
+                        let id = self.fs.lookup(dirid, &name).await?;
+                        let truncate = nfs3::sattr3 { size: Some(0), ..Default::default() };
+                        self.fs.setattr(id, truncate).await?;
+                        id
+                    }
+                    Err(stat) => return Err(stat.into()),
+                };
+                for (index, chunk) in data.chunks(CHUNK as usize).enumerate() {
+                    self.fs.write(id, index as u64 * u64::from(CHUNK), chunk).await?;
+                }
+                self.fs.setattr(id, attributes(header)).await?;
+                id
+            }
+            Kind::Symlink => {
+                let target = nfs3::nfspath3::from(header.link.clone());
+                let attr = nfs3::sattr3 { mode: Some(header.mode), ..Default::default() };
+                self.fs.symlink(dirid, &name, &target, &attr).await?.0
+            }
+            Kind::HardLink => {
+                let target = normalize(&header.link)?;
+                let id = self.resolve(&target).await?;
+                self.fs.link(id, dirid, &name).await?;
+                id
+            }
+        };
+        self.ids.insert(header.path.clone(), id);
+        Ok(())
+    }
+
+    /// Returns the directory at `path`, creating it and its parents if missing
+    async fn directory(&mut self, path: &[u8]) -> Result<nfs3::fileid3, NfsError> {
+        let mut dirid = self.root;
+        for (end, name) in components(path) {
+            if let Some(&id) = self.ids.get(&path[..end]) {
+                dirid = id;
+                continue;
+            }
+            let name = nfs3::filename3::from(name);
+            dirid = match self.fs.lookup(dirid, &name).await {
+                Ok(id) => id,
+                Err(nfs3::nfsstat3::NFS3ERR_NOENT) => self.fs.mkdir(dirid, &name).await?.0,
+                Err(stat) => return Err(stat.into()),
+            };
+            self.ids.insert(path[..end].to_vec(), dirid);
+        }
+        Ok(dirid)
+    }
+
+    /// Returns the file at `path`
+    async fn resolve(&mut self, path: &[u8]) -> Result<nfs3::fileid3, NfsError> {
+        if let Some(&id) = self.ids.get(path) {
+            return Ok(id);
+        }
+        let mut id = self.root;
+        for (_, name) in components(path) {
+            id = self.fs.lookup(id, &nfs3::filename3::from(name)).await?;
+        }
+        Ok(id)
+    }
+}
+
+/// Returns the `/`-separated components of a normalized path, with the length
+/// of the path up to the end of each
+fn components(path: &[u8]) -> impl Iterator<Item = (usize, &[u8])> {
+    let mut end = 0;
+    path.split(|&b| b == b'/').filter(|name| !name.is_empty()).map(move |name| {
+        end += usize::from(end != 0) + name.len();
+        (end, name)
+    })
+}
+
+/// Returns a path without leading `/`, `.` components and duplicate or
+/// trailing slashes
+fn normalize(path: &[u8]) -> Result<Vec<u8>, NfsError> {
+    let mut normalized = Vec::with_capacity(path.len());
+    for name in path.split(|&b| b == b'/') {
+        match name {
+            b"" | b"." => {}
+            b".." => {
+                return Err(invalid(format!(
+                    "path {:?} leaves the archive root",
+                    String::from_utf8_lossy(path)
+                )))
+            }
+            name => {
+                if !normalized.is_empty() {
+                    normalized.push(b'/');
+                }
+                normalized.extend_from_slice(name);
+            }
+        }
+    }
+    Ok(normalized)
+}
+
+/// Returns the attributes of an entry to set after unpacking it
+fn attributes(header: &Header) -> nfs3::sattr3 {
+    let mtime =
+        nfs3::nfstime3 { seconds: header.mtime.min(u64::from(u32::MAX)) as u32, nseconds: 0 };
+    nfs3::sattr3 {
+        mode: Some(header.mode),
+        uid: Some(header.uid),
+        gid: Some(header.gid),
+        mtime: nfs3::set_mtime::SET_TO_CLIENT_TIME(mtime),
+        ..Default::default()
+    }
+}
+
+/// Returns the names and attributes of the entries of a directory
+async fn list(
+    fs: &(dyn NFSFileSystem + Send + Sync),
+    dirid: nfs3::fileid3,
+) -> Result<Vec<(nfs3::filename3, nfs3::fattr3)>, NfsError> {
+    let mut entries = Vec::new();
+    let mut cookie = ReadDirCookie::Start;
+    let mut listed = 0;
+    loop {
+        let result = fs.readdir_with_cookie(dirid, cookie, LIST_BATCH).await?;
+        let Some(last) = result.entries.last() else {
+            break;
+        };
+        listed += result.entries.len();
+        cookie = readdir::resume_after(fs, dirid, listed, last);
+        for entry in result.entries {
+            if entry.name[..] == *b"." || entry.name[..] == *b".." {
+                continue;
+            }
+            let attr = match fs.readdir_has_attributes() {
+                true => entry.attr,
+                false => fs.getattr(entry.fileid).await?,
+            };
+            entries.push((entry.name, attr));
+        }
+        if result.end {
+            break;
+        }
+    }
+    Ok(entries)
+}
+
+/// Returns the whole data of a file
+async fn read_all(
+    fs: &(dyn NFSFileSystem + Send + Sync),
+    id: nfs3::fileid3,
+) -> Result<Vec<u8>, NfsError> {
+    let mut data = Vec::new();
+    loop {
+        let (chunk, eof) = fs.read(id, data.len() as u64, CHUNK).await?;
+        data.extend_from_slice(&chunk);
+        if eof || chunk.is_empty() {
+            return Ok(data);
+        }
+    }
+}
+
+/// Appends an entry and its data, padded to whole blocks, to an archive
+fn write_entry(archive: &mut Vec<u8>, header: &Header, data: &[u8]) {
+    let mut path = header.path.clone();
+    if header.kind == Kind::Directory {
+        path.push(b'/');
+    }
+    let (prefix, name) = split_path(&path).unwrap_or((&[][..], &[][..]));
+    let mut records = Vec::new();
+    if name.is_empty() {
+        pax_record(&mut records, "path", &path);
+    }
+    if header.link.len() > 100 {
+        pax_record(&mut records, "linkpath", &header.link);
+    }
+    if !records.is_empty() {
+        let pax = Header {
+            path: b"PaxHeader".to_vec(),
+            kind: Kind::File,
+            size: records.len() as u64,
+            link: Vec::new(),
+            ..header.clone()
+        };
+        push_block(archive, &encode(&pax, b'x', &[], &pax.path));
+        push_data(archive, &records);
+    }
+    let name = if name.is_empty() { &path[..path.len().min(100)] } else { name };
+    let link = &header.link[..header.link.len().min(100)];
+    let typeflag = match header.kind {
+        Kind::File => b'0',
+        Kind::HardLink => b'1',
+        Kind::Symlink => b'2',
+        Kind::Directory => b'5',
+    };
+    push_block(
+        archive,
+        &encode(&Header { link: link.to_vec(), ..header.clone() }, typeflag, prefix, name),
+    );
+    push_data(archive, data);
+}
+
+/// Splits a path into the ustar prefix and name fields, if it fits
+fn split_path(path: &[u8]) -> Option<(&[u8], &[u8])> {
+    if path.len() <= 100 {
+        return Some((&[], path));
+    }
+    // the separator between prefix and name is implied; a trailing slash of
+    // a directory stays in the name
+    let search = &path[..path.len() - 1];
+    let at = search
+        .iter()
+        .enumerate()
+        .filter(|&(at, &b)| b == b'/' && at <= 155 && path.len() - at - 1 <= 100)
+        .map(|(at, _)| at)
+        .next()?;
+    Some((&path[..at], &path[at + 1..]))
+}
+
+/// Appends a pax extended header record
+fn pax_record(records: &mut Vec<u8>, key: &str, value: &[u8]) {
+    // the length counts its own digits
+    let rest = key.len() + value.len() + 3;
+    let mut len = rest + 1;
+    while len.to_string().len() + rest != len {
+        len = len.to_string().len() + rest;
+    }
+    records.extend_from_slice(format!("{len} {key}=").as_bytes());
+    records.extend_from_slice(value);
+    records.push(b'\n');
+}
+
+/// Encodes a ustar header block
+fn encode(header: &Header, typeflag: u8, prefix: &[u8], name: &[u8]) -> [u8; BLOCK] {
+    let mut block = [0u8; BLOCK];
+    block[..name.len()].copy_from_slice(name);
+    put_number(&mut block[100..108], u64::from(header.mode));
+    put_number(&mut block[108..116], u64::from(header.uid));
+    put_number(&mut block[116..124], u64::from(header.gid));
+    put_number(&mut block[124..136], header.size);
+    put_number(&mut block[136..148], header.mtime);
+    block[156] = typeflag;
+    block[157..157 + header.link.len()].copy_from_slice(&header.link);
+    block[257..263].copy_from_slice(b"ustar\0");
+    block[263..265].copy_from_slice(b"00");
+    block[345..345 + prefix.len()].copy_from_slice(prefix);
+    block[148..156].fill(b' ');
+    let checksum: u32 = block.iter().map(|&b| u32::from(b)).sum();
+    block[148..155].copy_from_slice(format!("{checksum:06o}\0").as_bytes());
+    block
+}
+
+/// Stores a number in a header field, in octal or, if it does not fit, in the
+/// base-256 form of GNU tar
+fn put_number(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    let octal = format!("{value:0digits$o}");
+    if octal.len() <= digits {
+        field[..digits].copy_from_slice(octal.as_bytes());
+        return;
+    }
+    field.fill(0);
+    let len = field.len();
+    field[len - 8..].copy_from_slice(&value.to_be_bytes());
+    field[0] = 0x80;
+}
+
+/// Reads a number from a header field
+fn get_number(field: &[u8]) -> Result<u64, NfsError> {
+    if field.first().is_some_and(|&b| b & 0x80 != 0) {
+        let bytes = &field[field.len() - 8..];
+        return Ok(u64::from_be_bytes(bytes.try_into().unwrap()));
+    }
+    let digits: Vec<u8> = field
+        .iter()
+        .copied()
+        .skip_while(|&b| b == b' ')
+        .take_while(|&b| b != 0 && b != b' ')
+        .collect();
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    let digits = std::str::from_utf8(&digits).map_err(|_| invalid("malformed number"))?;
+    u64::from_str_radix(digits, 8).map_err(|_| invalid(format!("malformed number {digits:?}")))
+}
+
+/// Returns a NUL-terminated header field without its terminator
+fn get_string(field: &[u8]) -> &[u8] {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    &field[..end]
+}
+
+/// Appends a block
+fn push_block(archive: &mut Vec<u8>, block: &[u8; BLOCK]) {
+    archive.extend_from_slice(block);
+}
+
+/// Appends data padded to whole blocks
+fn push_data(archive: &mut Vec<u8>, data: &[u8]) {
+    archive.extend_from_slice(data);
+    archive.resize(archive.len().next_multiple_of(BLOCK), 0);
+}
+
+/// Decodes the entries of an archive, with their data
+fn parse(archive: &[u8]) -> Result<Vec<(Header, &[u8])>, NfsError> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    // overrides from pax and GNU long name headers for the next entry
+    let mut long_path = None;
+    let mut long_link = None;
+    while offset + BLOCK <= archive.len() {
+        let block = &archive[offset..offset + BLOCK];
+        if block.iter().all(|&b| b == 0) {
+            break;
+        }
+        let stored = get_number(&block[148..156])?;
+        let checksum: u64 = block
+            .iter()
+            .enumerate()
+            .map(|(at, &b)| if (148..156).contains(&at) { u64::from(b' ') } else { u64::from(b) })
+            .sum();
+        if stored != checksum {
+            return Err(invalid(format!("bad header checksum at offset {offset}")));
+        }
+        let size = get_number(&block[124..136])?;
+        let start = offset + BLOCK;
+        let end = usize::try_from(size)
+            .ok()
+            .and_then(|size| start.checked_add(size))
+            .filter(|&end| end <= archive.len())
+            .ok_or_else(|| invalid(format!("truncated entry at offset {offset}")))?;
+        let data = &archive[start..end];
+        offset = end.next_multiple_of(BLOCK);
+
+        let typeflag = block[156];
+        let kind = match typeflag {
+            b'0' | 0 | b'7' => Kind::File,
+            b'1' => Kind::HardLink,
+            b'2' => Kind::Symlink,
+            b'5' => Kind::Directory,
+            b'x' => {
+                for (key, value) in pax_records(data)? {
+                    match key {
+                        b"path" => long_path = Some(value.to_vec()),
+                        b"linkpath" => long_link = Some(value.to_vec()),
+                        _ => {}
+                    }
+                }
+                continue;
+            }
+            b'L' => {
+                long_path = Some(get_string(data).to_vec());
+                continue;
+            }
+            b'K' => {
+                long_link = Some(get_string(data).to_vec());
+                continue;
+            }
+            b'g' => continue,
+            _ => {
+                warn!("skipping tar entry of type {:?}", char::from(typeflag));
+                long_path = None;
+                long_link = None;
+                continue;
+            }
+        };
+        let path = long_path.take().unwrap_or_else(|| {
+            let name = get_string(&block[..100]);
+            let prefix = match &block[257..263] {
+                b"ustar\0" => get_string(&block[345..500]),
+                _ => &[],
+            };
+            match prefix {
+                [] => name.to_vec(),
+                prefix => [prefix, b"/", name].concat(),
+            }
+        });
+        let path = normalize(&path)?;
+        if path.is_empty() {
+            // the archive root itself, as in archives made with `tar -C dir .`
+            long_link = None;
+            continue;
+        }
+        let header = Header {
+            path,
+            kind,
+            mode: get_number(&block[100..108])? as u32 & 0o7777,
+            uid: get_number(&block[108..116])? as u32,
+            gid: get_number(&block[116..124])? as u32,
+            size: if kind == Kind::File { size } else { 0 },
+            mtime: get_number(&block[136..148])?,
+            link: long_link.take().unwrap_or_else(|| get_string(&block[157..257]).to_vec()),
+        };
+        entries.push((header, if kind == Kind::File { data } else { &[][..] }));
+    }
+    Ok(entries)
+}
+
+/// Key and value of a pax extended header record
+type PaxRecord<'a> = (&'a [u8], &'a [u8]);
+
+/// Decodes the `<length> <key>=<value>\n` records of a pax extended header
+fn pax_records(mut data: &[u8]) -> Result<Vec<PaxRecord<'_>>, NfsError> {
+    let mut records = Vec::new();
+    while !data.is_empty() && data[0] != 0 {
+        let space =
+            data.iter().position(|&b| b == b' ').ok_or_else(|| invalid("bad pax record"))?;
+        let len = std::str::from_utf8(&data[..space])
+            .ok()
+            .and_then(|len| len.parse::<usize>().ok())
+            .filter(|&len| len > space + 1 && len <= data.len())
+            .ok_or_else(|| invalid("bad pax record length"))?;
+        let record = &data[space + 1..len - 1];
+        let equals =
+            record.iter().position(|&b| b == b'=').ok_or_else(|| invalid("bad pax record"))?;
+        records.push((&record[..equals], &record[equals + 1..]));
+        data = &data[len..];
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::testing::{Call, MockFs};
+
+    fn header(path: &[u8], kind: Kind) -> Header {
+        Header {
+            path: path.to_vec(),
+            kind,
+            mode: 0o644,
+            uid: 1000,
+            gid: 100,
+            size: 0,
+            mtime: 1_700_000_000,
+            link: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn round_trips_entries() {
+        let long = [&[b'd'; 120][..], b"/", &[b'f'; 90][..]].concat();
+        let longer = vec![b'x'; 300];
+        let file = Header { size: 5, ..header(&long, Kind::File) };
+        let dir = header(b"dir", Kind::Directory);
+        let symlink = Header { link: longer.clone(), ..header(&longer, Kind::Symlink) };
+        let hard_link = Header { link: long.clone(), ..header(b"dir/link", Kind::HardLink) };
+
+        let mut archive = Vec::new();
+        write_entry(&mut archive, &dir, &[]);
+        write_entry(&mut archive, &file, b"hello");
+        write_entry(&mut archive, &symlink, &[]);
+        write_entry(&mut archive, &hard_link, &[]);
+        archive.resize(archive.len() + 2 * BLOCK, 0);
+
+        let entries = parse(&archive).unwrap();
+        let headers: Vec<_> = entries.iter().map(|(header, _)| header.clone()).collect();
+        assert_eq!(headers, [dir, file, symlink, hard_link]);
+        assert_eq!(entries[1].1, b"hello");
+        assert!(matches!(
+            normalize(b"./a/../b").map_err(|err| err.status()),
+            Err(nfs3::nfsstat3::NFS3ERR_INVAL)
+        ));
+    }
+
+    #[tokio::test]
+    async fn unpacks_into_the_file_system() {
+        let mut archive = Vec::new();
+        write_entry(&mut archive, &header(b"a", Kind::Directory), &[]);
+        write_entry(&mut archive, &Header { size: 2, ..header(b"a/b/f", Kind::File) }, b"hi");
+        archive.resize(archive.len() + 2 * BLOCK, 0);
+
+        let fs = MockFs::new();
+        let attr = nfs3::fattr3::default();
+        fs.expect(Call::Mkdir { dirid: 1, name: b"a".to_vec() }, Ok((2_u64, attr)));
+        fs.expect(
+            Call::Lookup { dirid: 2, name: b"b".to_vec() },
+            Err::<u64, _>(nfs3::nfsstat3::NFS3ERR_NOENT),
+        );
+        fs.expect(Call::Mkdir { dirid: 2, name: b"b".to_vec() }, Ok((3_u64, attr)));
+        fs.expect(Call::Create { dirid: 3, name: b"f".to_vec() }, Ok((4_u64, attr)));
+        fs.expect(Call::Write { id: 4, offset: 0, data: b"hi".to_vec() }, Ok(attr));
+        fs.expect(Call::Setattr { id: 4 }, Ok(attr));
+        fs.expect(Call::Setattr { id: 2 }, Ok(attr));
+        assert_eq!(load_tar(&fs, 1, &archive).await.unwrap(), 2);
+        fs.assert_done();
+    }
+}
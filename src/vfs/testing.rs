@@ -0,0 +1,611 @@
+//! Utilities for testing file system backends and procedure handlers.
+//!
+//! [`MockFs`] is a scripted [`NFSFileSystem`]: a test declares the calls it
+//! expects together with their results, drives a procedure through the server,
+//! and then checks which calls were made. Calls without a matching expectation
+//! are recorded and fail with [`MockFs::with_default_error`], `NFS3ERR_NOTSUPP`
+//! unless configured otherwise.
+//!
+//! ```ignore
+//! let fs = Arc::new(MockFs::new());
+//! fs.expect(Call::Lookup { dirid: 1, name: b"a".to_vec() }, Ok(2_u64));
+//! fs.stub(Call::Getattr { id: 2 }, Ok(fattr3::default()));
+//!
+//! let context = testing::context(fs.clone());
+//! let args = diropargs3 { dir: context.id_to_fh(1).unwrap(), name: b"a".to_vec().into() };
+//! let mut reply = testing::call_nfs3(&context, NFSProgram::NFSPROC3_LOOKUP, &args).await?;
+//! assert_eq!(testing::status(&mut reply)? as u32, nfsstat3::NFS3_OK as u32);
+//! fs.assert_done();
+//! ```
+//!
+//! The helpers [`context`], [`call_nfs3`] and [`status`] run single calls
+//! through the regular RPC dispatch, so handlers are tested exactly as clients
+//! reach them.
+
+use std::any::Any;
+use std::io::{self, Cursor};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use num_traits::FromPrimitive;
+
+use super::request::RequestContext;
+use super::{Capabilities, NFSFileSystem, ReadDirCookie, ReadDirResult};
+use crate::entropy::SeededEntropy;
+use crate::protocol::rpc;
+use crate::protocol::xdr::{self, deserialize, nfs3, Serialize};
+
+/// A call made to a [`MockFs`], also used to describe expected calls
+///
+/// Names and data are compared byte for byte. Attributes passed to `setattr`,
+/// `create`, `symlink` and `mknod` are not compared.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Call {
+    /// [`NFSFileSystem::lookup`]
+    Lookup { dirid: nfs3::fileid3, name: Vec<u8> },
+    /// [`NFSFileSystem::getattr`]
+    Getattr { id: nfs3::fileid3 },
+    /// [`NFSFileSystem::setattr`], and [`NFSFileSystem::setattr_wcc`],
+    /// answered with `(Option<wcc_attr>, fattr3)` or with `fattr3`
+    Setattr { id: nfs3::fileid3 },
+    /// [`NFSFileSystem::read`]
+    Read { id: nfs3::fileid3, offset: u64, count: u32 },
+    /// [`NFSFileSystem::write`], answered with `fattr3`,
+    /// [`NFSFileSystem::write_partial`], answered with `(fattr3, u32)` or with
+    /// `fattr3` for a complete write, and [`NFSFileSystem::write_wcc`],
+    /// answered with `(Option<wcc_attr>, fattr3, u32)` or like `write_partial`
+    Write { id: nfs3::fileid3, offset: u64, data: Vec<u8> },
+    /// [`NFSFileSystem::create`]
+    Create { dirid: nfs3::fileid3, name: Vec<u8> },
+    /// [`NFSFileSystem::create_exclusive`]
+    CreateExclusive { dirid: nfs3::fileid3, name: Vec<u8> },
+    /// [`NFSFileSystem::mkdir`]
+    Mkdir { dirid: nfs3::fileid3, name: Vec<u8> },
+    /// [`NFSFileSystem::remove`]
+    Remove { dirid: nfs3::fileid3, name: Vec<u8> },
+    /// [`NFSFileSystem::rename`]
+    Rename {
+        from_dirid: nfs3::fileid3,
+        from_name: Vec<u8>,
+        to_dirid: nfs3::fileid3,
+        to_name: Vec<u8>,
+    },
+    /// [`NFSFileSystem::readdir_with_cookie`]
+    ReadDir { dirid: nfs3::fileid3, cookie: ReadDirCookie, max_entries: usize },
+    /// [`NFSFileSystem::symlink`]
+    Symlink { dirid: nfs3::fileid3, name: Vec<u8>, target: Vec<u8> },
+    /// [`NFSFileSystem::readlink`]
+    Readlink { id: nfs3::fileid3 },
+    /// [`NFSFileSystem::link`]
+    Link { id: nfs3::fileid3, dirid: nfs3::fileid3, name: Vec<u8> },
+    /// [`NFSFileSystem::mknod`]
+    Mknod { dirid: nfs3::fileid3, name: Vec<u8> },
+    /// [`NFSFileSystem::commit`]
+    Commit { id: nfs3::fileid3, offset: u64, count: u32 },
+    /// [`NFSFileSystem::rehydrate_handle`]
+    RehydrateHandle { handle: Vec<u8> },
+}
+
+/// Scripted answer to a call
+struct Expectation {
+    /// Call the answer applies to
+    call: Call,
+    /// `Result<R, nfsstat3>` for the result type `R` of the method
+    result: Box<dyn Any + Send>,
+    /// Whether the answer is used once or for every matching call
+    once: bool,
+}
+
+/// Scripted file system for tests
+///
+/// Expectations are matched in the order they were added; an expectation added
+/// with [`MockFs::expect`] answers a single call, one added with
+/// [`MockFs::stub`] answers every matching call.
+pub struct MockFs {
+    /// Root directory file ID
+    root: nfs3::fileid3,
+    /// Reported capabilities
+    capabilities: Capabilities,
+    /// Error returned for calls without a matching expectation
+    default_error: nfs3::nfsstat3,
+    /// Files reported as immutable
+    immutable: Vec<nfs3::fileid3>,
+    /// Whether attributes before changes are reported
+    pre_op_attrs: bool,
+    /// Length of the file handles, at least the 16 bytes of generation and ID
+    handle_len: usize,
+    /// Outstanding expectations
+    expectations: Mutex<Vec<Expectation>>,
+    /// Every call made, in order
+    calls: Mutex<Vec<Call>>,
+    /// Calls that had no matching expectation
+    unexpected: Mutex<Vec<Call>>,
+    /// Context of the call each call was made for, in order
+    requests: Mutex<Vec<Option<RequestContext>>>,
+    /// Attributes passed to `setattr` and `create`, in order
+    attributes: Mutex<Vec<nfs3::sattr3>>,
+}
+
+impl Default for MockFs {
+    fn default() -> Self {
+        Self {
+            root: 1,
+            capabilities: Capabilities::ReadWrite,
+            default_error: nfs3::nfsstat3::NFS3ERR_NOTSUPP,
+            immutable: Vec::new(),
+            pre_op_attrs: false,
+            handle_len: 16,
+            expectations: Mutex::default(),
+            calls: Mutex::default(),
+            unexpected: Mutex::default(),
+            requests: Mutex::default(),
+            attributes: Mutex::default(),
+        }
+    }
+}
+
+impl MockFs {
+    /// Creates a writable mock with root directory 1 and no expectations
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the root directory file ID
+    pub fn with_root(mut self, root: nfs3::fileid3) -> Self {
+        self.root = root;
+        self
+    }
+
+    /// Reports the file system as read-only
+    pub fn read_only(mut self) -> Self {
+        self.capabilities = Capabilities::ReadOnly;
+        self
+    }
+
+    /// Sets the reported capabilities
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// Sets the error returned for calls without a matching expectation
+    pub fn with_default_error(mut self, error: nfs3::nfsstat3) -> Self {
+        self.default_error = error;
+        self
+    }
+
+    /// Reports the given files as immutable
+    pub fn with_immutable_files(mut self, ids: impl IntoIterator<Item = nfs3::fileid3>) -> Self {
+        self.immutable = ids.into_iter().collect();
+        self
+    }
+
+    /// Reports the attributes of files before `SETATTR` and `WRITE`, see
+    /// [`NFSFileSystem::reports_pre_op_attrs`]
+    pub fn with_pre_op_attrs(mut self) -> Self {
+        self.pre_op_attrs = true;
+        self
+    }
+
+    /// Pads file handles with zeros to `len` bytes, e.g. to test handles close
+    /// to `NFS3_FHSIZE`
+    pub fn with_handle_len(mut self, len: usize) -> Self {
+        self.handle_len = len.max(16);
+        self
+    }
+
+    /// Expects `call` once and answers it with `result`
+    ///
+    /// `R` must be the success type of the called method, e.g. `fileid3` for
+    /// [`Call::Lookup`] or `(Vec<u8>, bool)` for [`Call::Read`]; a mismatch panics
+    /// when the call is made.
+    pub fn expect<R: Clone + Send + 'static>(&self, call: Call, result: Result<R, nfs3::nfsstat3>) {
+        self.push(call, result, true);
+    }
+
+    /// Answers every matching `call` with `result`, see [`MockFs::expect`]
+    pub fn stub<R: Clone + Send + 'static>(&self, call: Call, result: Result<R, nfs3::nfsstat3>) {
+        self.push(call, result, false);
+    }
+
+    /// Adds an expectation
+    fn push<R: Clone + Send + 'static>(
+        &self,
+        call: Call,
+        result: Result<R, nfs3::nfsstat3>,
+        once: bool,
+    ) {
+        let result = Box::new(result);
+        self.expectations.lock().unwrap().push(Expectation { call, result, once });
+    }
+
+    /// Returns every call made so far, in order
+    pub fn calls(&self) -> Vec<Call> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Returns the [`RequestContext`] each call was made in, in order of the
+    /// calls
+    pub fn requests(&self) -> Vec<Option<RequestContext>> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Returns the attributes passed to `setattr` and `create` so far, in order
+    pub fn attributes(&self) -> Vec<nfs3::sattr3> {
+        self.attributes.lock().unwrap().clone()
+    }
+
+    /// Returns the calls that had no matching expectation
+    pub fn unexpected_calls(&self) -> Vec<Call> {
+        self.unexpected.lock().unwrap().clone()
+    }
+
+    /// Panics unless every [`MockFs::expect`]ation was used and no unexpected call
+    /// was made
+    pub fn assert_done(&self) {
+        let pending: Vec<Call> = self
+            .expectations
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|e| e.once)
+            .map(|e| e.call.clone())
+            .collect();
+        assert!(pending.is_empty(), "expected calls were not made: {pending:?}");
+        let unexpected = self.unexpected_calls();
+        assert!(unexpected.is_empty(), "unexpected calls: {unexpected:?}");
+    }
+
+    /// Returns whether the first expectation matching `call` answers with `R`
+    fn answers_with<R: 'static>(&self, call: &Call) -> bool {
+        let expectations = self.expectations.lock().unwrap();
+        expectations
+            .iter()
+            .find(|e| e.call == *call)
+            .is_some_and(|e| e.result.is::<Result<R, nfs3::nfsstat3>>())
+    }
+
+    /// Records `call` and returns the scripted result
+    fn answer<R: Clone + 'static>(&self, call: Call) -> Result<R, nfs3::nfsstat3> {
+        self.calls.lock().unwrap().push(call.clone());
+        self.requests.lock().unwrap().push(RequestContext::current());
+        let mut expectations = self.expectations.lock().unwrap();
+        let Some(index) = expectations.iter().position(|e| e.call == call) else {
+            drop(expectations);
+            self.unexpected.lock().unwrap().push(call);
+            return Err(self.default_error);
+        };
+        let result = match expectations[index].result.downcast_ref::<Result<R, nfs3::nfsstat3>>() {
+            Some(result) => result.clone(),
+            None => {
+                panic!("expectation for {:?} does not return {}", call, std::any::type_name::<R>())
+            }
+        };
+        if expectations[index].once {
+            expectations.remove(index);
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl NFSFileSystem for MockFs {
+    fn generation(&self) -> u64 {
+        1
+    }
+
+    fn id_to_fh(&self, id: nfs3::fileid3) -> nfs3::nfs_fh3 {
+        let mut data = Vec::with_capacity(self.handle_len);
+        data.extend_from_slice(&self.generation().to_le_bytes());
+        data.extend_from_slice(&id.to_le_bytes());
+        data.resize(self.handle_len, 0);
+        nfs3::nfs_fh3 { data }
+    }
+
+    fn fh_to_id(&self, fh: &nfs3::nfs_fh3) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        if fh.data.len() != self.handle_len {
+            return Err(nfs3::nfsstat3::NFS3ERR_BADHANDLE);
+        }
+        let gen = u64::from_le_bytes(fh.data[0..8].try_into().unwrap());
+        match gen.cmp(&self.generation()) {
+            std::cmp::Ordering::Less => Err(nfs3::nfsstat3::NFS3ERR_STALE),
+            std::cmp::Ordering::Greater => Err(nfs3::nfsstat3::NFS3ERR_BADHANDLE),
+            std::cmp::Ordering::Equal => Ok(u64::from_le_bytes(fh.data[8..16].try_into().unwrap())),
+        }
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    fn root_dir(&self) -> nfs3::fileid3 {
+        self.root
+    }
+
+    async fn lookup(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        self.answer(Call::Lookup { dirid, name: filename.0.clone() })
+    }
+
+    async fn getattr(&self, id: nfs3::fileid3) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        self.answer(Call::Getattr { id })
+    }
+
+    async fn setattr(
+        &self,
+        id: nfs3::fileid3,
+        setattr: nfs3::sattr3,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        self.attributes.lock().unwrap().push(setattr);
+        self.answer(Call::Setattr { id })
+    }
+
+    fn reports_pre_op_attrs(&self) -> bool {
+        self.pre_op_attrs
+    }
+
+    async fn setattr_wcc(
+        &self,
+        id: nfs3::fileid3,
+        setattr: nfs3::sattr3,
+    ) -> Result<(Option<nfs3::wcc_attr>, nfs3::fattr3), nfs3::nfsstat3> {
+        let call = Call::Setattr { id };
+        if !self.answers_with::<(Option<nfs3::wcc_attr>, nfs3::fattr3)>(&call) {
+            return Ok((None, self.setattr(id, setattr).await?));
+        }
+        self.attributes.lock().unwrap().push(setattr);
+        self.answer(call)
+    }
+
+    async fn read(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        count: u32,
+    ) -> Result<(Vec<u8>, bool), nfs3::nfsstat3> {
+        self.answer(Call::Read { id, offset, count })
+    }
+
+    fn is_immutable(&self, id: nfs3::fileid3) -> bool {
+        self.immutable.contains(&id)
+    }
+
+    async fn write(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        self.answer(Call::Write { id, offset, data: data.to_vec() })
+    }
+
+    async fn write_partial(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(nfs3::fattr3, u32), nfs3::nfsstat3> {
+        let call = Call::Write { id, offset, data: data.to_vec() };
+        if self.answers_with::<(nfs3::fattr3, u32)>(&call) {
+            return self.answer(call);
+        }
+        let attr = self.answer(call)?;
+        Ok((attr, data.len() as u32))
+    }
+
+    async fn write_wcc(
+        &self,
+        id: nfs3::fileid3,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(Option<nfs3::wcc_attr>, nfs3::fattr3, u32), nfs3::nfsstat3> {
+        let call = Call::Write { id, offset, data: data.to_vec() };
+        if self.answers_with::<(Option<nfs3::wcc_attr>, nfs3::fattr3, u32)>(&call) {
+            return self.answer(call);
+        }
+        let (attr, count) = self.write_partial(id, offset, data).await?;
+        Ok((None, attr, count))
+    }
+
+    async fn create(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+        attr: nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        self.attributes.lock().unwrap().push(attr);
+        self.answer(Call::Create { dirid, name: filename.0.clone() })
+    }
+
+    async fn create_exclusive(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        self.answer(Call::CreateExclusive { dirid, name: filename.0.clone() })
+    }
+
+    async fn mkdir(
+        &self,
+        dirid: nfs3::fileid3,
+        dirname: &nfs3::filename3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        self.answer(Call::Mkdir { dirid, name: dirname.0.clone() })
+    }
+
+    async fn remove(
+        &self,
+        dirid: nfs3::fileid3,
+        filename: &nfs3::filename3,
+    ) -> Result<(), nfs3::nfsstat3> {
+        self.answer(Call::Remove { dirid, name: filename.0.clone() })
+    }
+
+    async fn rename(
+        &self,
+        from_dirid: nfs3::fileid3,
+        from_filename: &nfs3::filename3,
+        to_dirid: nfs3::fileid3,
+        to_filename: &nfs3::filename3,
+    ) -> Result<(), nfs3::nfsstat3> {
+        self.answer(Call::Rename {
+            from_dirid,
+            from_name: from_filename.0.clone(),
+            to_dirid,
+            to_name: to_filename.0.clone(),
+        })
+    }
+
+    async fn readdir_with_cookie(
+        &self,
+        dirid: nfs3::fileid3,
+        cookie: ReadDirCookie,
+        max_entries: usize,
+    ) -> Result<ReadDirResult, nfs3::nfsstat3> {
+        self.answer(Call::ReadDir { dirid, cookie, max_entries })
+    }
+
+    async fn symlink(
+        &self,
+        dirid: nfs3::fileid3,
+        linkname: &nfs3::filename3,
+        symlink: &nfs3::nfspath3,
+        _attr: &nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        self.answer(Call::Symlink { dirid, name: linkname.0.clone(), target: symlink.0.clone() })
+    }
+
+    async fn readlink(&self, id: nfs3::fileid3) -> Result<nfs3::nfspath3, nfs3::nfsstat3> {
+        self.answer::<Vec<u8>>(Call::Readlink { id }).map(nfs3::nfspath3::from)
+    }
+
+    async fn link(
+        &self,
+        file_id: nfs3::fileid3,
+        link_dir_id: nfs3::fileid3,
+        link_name: &nfs3::filename3,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        self.answer(Call::Link { id: file_id, dirid: link_dir_id, name: link_name.0.clone() })
+    }
+
+    async fn mknod(
+        &self,
+        dir_id: nfs3::fileid3,
+        name: &nfs3::filename3,
+        _ftype: nfs3::ftype3,
+        _specdata: nfs3::specdata3,
+        _attrs: &nfs3::sattr3,
+    ) -> Result<(nfs3::fileid3, nfs3::fattr3), nfs3::nfsstat3> {
+        self.answer(Call::Mknod { dirid: dir_id, name: name.0.clone() })
+    }
+
+    async fn commit(
+        &self,
+        file_id: nfs3::fileid3,
+        offset: u64,
+        count: u32,
+    ) -> Result<nfs3::fattr3, nfs3::nfsstat3> {
+        self.answer(Call::Commit { id: file_id, offset, count })
+    }
+
+    fn rehydrate_handle(&self, old: &nfs3::nfs_fh3) -> Result<nfs3::fileid3, nfs3::nfsstat3> {
+        self.answer(Call::RehydrateHandle { handle: old.data.clone() })
+    }
+}
+
+/// Builds an RPC context serving `fs` with default settings
+///
+/// The context has no write coalescing, read-ahead, subtree checking or mount
+/// signal, and a transaction tracker that forgets calls immediately, so tests
+/// may reuse transaction IDs. Its entropy is seeded, so replies carrying
+/// random values are the same on every run.
+pub fn context(fs: Arc<dyn NFSFileSystem + Send + Sync>) -> rpc::Context {
+    rpc::Context {
+        local_port: 2049,
+        client_addr: ([127, 0, 0, 1], 700).into(),
+        transaction_tracker: Arc::new(rpc::TransactionTracker::new(Duration::ZERO)),
+        entropy: Arc::new(SeededEntropy::new(0)),
+        ..rpc::Context::new(fs)
+    }
+}
+
+/// Serializes an RPC call with `AUTH_NULL` credentials
+///
+/// # Arguments
+/// * `xid` - Transaction ID
+/// * `prog` - Program number
+/// * `vers` - Program version
+/// * `proc` - Procedure number
+/// * `args` - Procedure arguments
+pub fn call_message(xid: u32, prog: u32, vers: u32, proc: u32, args: &impl Serialize) -> Vec<u8> {
+    let msg = xdr::rpc::rpc_msg {
+        xid,
+        body: xdr::rpc::rpc_body::CALL(xdr::rpc::call_body {
+            rpcvers: 2,
+            prog,
+            vers,
+            proc,
+            cred: xdr::rpc::opaque_auth::default(),
+            verf: xdr::rpc::opaque_auth::default(),
+        }),
+    };
+    let mut out = Vec::new();
+    msg.serialize(&mut out).expect("serializing to a vector cannot fail");
+    args.serialize(&mut out).expect("serializing to a vector cannot fail");
+    out
+}
+
+/// Runs an `NFSv3` call through the RPC dispatch
+///
+/// # Returns
+/// * `io::Result<Cursor<Vec<u8>>>` - The reply positioned after the RPC reply
+///   header, i.e. at the procedure's result, or an error if the call was not
+///   accepted
+pub async fn call_nfs3(
+    context: &rpc::Context,
+    proc: nfs3::NFSProgram,
+    args: &impl Serialize,
+) -> io::Result<Cursor<Vec<u8>>> {
+    let request = call_message(1, nfs3::PROGRAM, nfs3::VERSION, proc as u32, args);
+    let mut reply = Vec::new();
+    rpc::process_message(&request, &mut reply, context.clone()).await.map_err(io::Error::other)?;
+    let mut reply = Cursor::new(reply);
+    match deserialize::<xdr::rpc::rpc_msg>(&mut reply)?.body {
+        xdr::rpc::rpc_body::REPLY(xdr::rpc::reply_body::MSG_ACCEPTED(accepted))
+            if matches!(accepted.reply_data, xdr::rpc::accept_body::SUCCESS) =>
+        {
+            Ok(reply)
+        }
+        body => Err(io::Error::other(format!("call was not accepted: {body:?}"))),
+    }
+}
+
+/// Reads the status code at the start of a procedure result
+pub fn status(reply: &mut Cursor<Vec<u8>>) -> io::Result<nfs3::nfsstat3> {
+    let status = deserialize::<u32>(reply)?;
+    nfs3::nfsstat3::from_u32(status)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid nfsstat3"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn answers_scripted_calls() {
+        let fs = MockFs::new();
+        fs.expect(Call::Lookup { dirid: 1, name: b"a".to_vec() }, Ok(2_u64));
+        fs.stub(Call::Getattr { id: 2 }, Err::<nfs3::fattr3, _>(nfs3::nfsstat3::NFS3ERR_IO));
+
+        assert_eq!(fs.lookup(1, &b"a".to_vec().into()).await.ok(), Some(2));
+        assert!(fs.lookup(1, &b"a".to_vec().into()).await.is_err());
+        assert!(fs.getattr(2).await.is_err());
+        assert!(fs.getattr(2).await.is_err());
+        assert_eq!(fs.calls().len(), 4);
+        assert_eq!(fs.unexpected_calls(), vec![Call::Lookup { dirid: 1, name: b"a".to_vec() }]);
+    }
+}
@@ -0,0 +1,165 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use nfs_mamont::client::{Client, ClientError};
+use nfs_mamont::protocol::rpc::{Algorithm, CompressionConfig};
+use nfs_mamont::tcp::{NFSTcp, NFSTcpListener};
+use nfs_mamont::vfs::testing::{Call, MockFs};
+use nfs_mamont::vfs::{DirEntry, ReadDirCookie, ReadDirResult};
+use nfs_mamont::xdr::nfs3::{self, fattr3, ftype3, nfsstat3};
+
+/// Serves `fs` on a local port and connects a client to it
+async fn connect(fs: Arc<MockFs>) -> Client {
+    let listener = NFSTcpListener::bind_dyn("127.0.0.1:0", fs).await.unwrap();
+    let port = listener.get_listen_port();
+    tokio::spawn(async move { listener.handle_forever().await });
+    Client::connect(&format!("127.0.0.1:{port}")).await.unwrap().with_auth_unix(1000, 1000, vec![])
+}
+
+fn attr(fileid: nfs3::fileid3, ftype: ftype3) -> fattr3 {
+    fattr3 { ftype, fileid, nlink: 1, ..Default::default() }
+}
+
+#[tokio::test]
+async fn mounts_and_looks_up() {
+    let fs = Arc::new(MockFs::new());
+    fs.stub(Call::Getattr { id: 1 }, Ok(attr(1, ftype3::NF3DIR)));
+    fs.stub(Call::Getattr { id: 2 }, Ok(attr(2, ftype3::NF3REG)));
+    fs.expect(Call::Lookup { dirid: 1, name: b"a".to_vec() }, Ok(2_u64));
+    let client = connect(fs.clone()).await;
+
+    let root = client.mount(b"/").await.unwrap();
+    assert_eq!(client.getattr(&root).await.unwrap().fileid, 1);
+    let (file, file_attr) = client.lookup(&root, &b"a"[..].into()).await.unwrap();
+    assert_eq!(file_attr.map(|a| a.fileid), Some(2));
+    assert_eq!(client.getattr(&file).await.unwrap().fileid, 2);
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn reports_failed_procedures() {
+    let fs = Arc::new(MockFs::new());
+    fs.stub(Call::Getattr { id: 1 }, Ok(attr(1, ftype3::NF3DIR)));
+    fs.expect(
+        Call::Lookup { dirid: 1, name: b"missing".to_vec() },
+        Err::<u64, _>(nfsstat3::NFS3ERR_NOENT),
+    );
+    let client = connect(fs.clone()).await;
+
+    let root = client.mount(b"/").await.unwrap();
+    let err = client.lookup(&root, &b"missing"[..].into()).await.unwrap_err();
+    assert!(matches!(err, ClientError::Nfs(nfsstat3::NFS3ERR_NOENT)));
+    assert!(matches!(client.mount(b"/elsewhere").await, Err(ClientError::Mount(_))));
+    // The connection stays usable after errors
+    assert_eq!(client.getattr(&root).await.unwrap().fileid, 1);
+}
+
+#[tokio::test]
+async fn reads_and_writes() {
+    let fs = Arc::new(MockFs::new());
+    fs.stub(Call::Getattr { id: 1 }, Ok(attr(1, ftype3::NF3DIR)));
+    fs.stub(Call::Getattr { id: 2 }, Ok(attr(2, ftype3::NF3REG)));
+    fs.stub(Call::Lookup { dirid: 1, name: b"f".to_vec() }, Ok(2_u64));
+    fs.expect(
+        Call::Write { id: 2, offset: 3, data: b"hello".to_vec() },
+        Ok(attr(2, ftype3::NF3REG)),
+    );
+    fs.expect(Call::Read { id: 2, offset: 0, count: 64 }, Ok((b"abchello".to_vec(), true)));
+    let client = connect(fs.clone()).await;
+
+    let root = client.mount(b"/").await.unwrap();
+    let (file, _) = client.lookup(&root, &b"f"[..].into()).await.unwrap();
+    let written =
+        client.write(&file, 3, b"hello", nfs3::file::stable_how::FILE_SYNC).await.unwrap();
+    assert_eq!(written.count, 5);
+    let read = client.read(&file, 0, 64).await.unwrap();
+    assert_eq!(read.data, b"abchello");
+    assert!(read.eof);
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn lists_directories() {
+    let fs = Arc::new(MockFs::new());
+    fs.stub(Call::Getattr { id: 1 }, Ok(attr(1, ftype3::NF3DIR)));
+    let entries = (2..5)
+        .map(|id| DirEntry {
+            fileid: id,
+            name: format!("file{id}").into_bytes().into(),
+            attr: attr(id, ftype3::NF3REG),
+        })
+        .collect();
+    fs.expect(
+        Call::ReadDir { dirid: 1, cookie: ReadDirCookie::Start, max_entries: 256 },
+        Ok(ReadDirResult { entries, end: true }),
+    );
+    let client = connect(fs.clone()).await;
+
+    let root = client.mount(b"/").await.unwrap();
+    let listing = client.readdirplus(&root, 0, nfs3::cookieverf3::default(), 4096).await.unwrap();
+    assert!(listing.eof);
+    let names: Vec<&[u8]> = listing.entries.iter().map(|e| &e.name.0[..]).collect();
+    assert_eq!(names, [&b"file2"[..], b"file3", b"file4"]);
+    assert!(listing.entries.iter().all(|e| e.name_handle.is_some()));
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn connections_run_where_the_spawner_places_them() {
+    let fs = Arc::new(MockFs::new());
+    fs.stub(Call::Getattr { id: 1 }, Ok(attr(1, ftype3::NF3DIR)));
+    // dropping a runtime within another one panics, so it is leaked
+    let pinned: &'static tokio::runtime::Runtime = Box::leak(Box::new(
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("pinned")
+            .enable_all()
+            .build()
+            .unwrap(),
+    ));
+    let placed = Arc::new(AtomicUsize::new(0));
+    let mut listener = NFSTcpListener::bind_dyn("127.0.0.1:0", fs).await.unwrap();
+    let counter = placed.clone();
+    listener.with_spawner(move |_peer, task| {
+        let counter = counter.clone();
+        pinned.spawn(async move {
+            if std::thread::current().name() == Some("pinned") {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+            task.await
+        });
+    });
+    let port = listener.get_listen_port();
+    tokio::spawn(async move { listener.handle_forever().await });
+    let client = Client::connect(&format!("127.0.0.1:{port}")).await.unwrap();
+
+    let root = client.mount(b"/").await.unwrap();
+    assert_eq!(client.getattr(&root).await.unwrap().fileid, 1);
+    assert_eq!(placed.load(Ordering::Relaxed), 1);
+}
+
+#[tokio::test]
+async fn negotiates_reply_compression() {
+    let fs = Arc::new(MockFs::new());
+    fs.stub(Call::Getattr { id: 1 }, Ok(attr(1, ftype3::NF3DIR)));
+    fs.stub(Call::Getattr { id: 2 }, Ok(attr(2, ftype3::NF3REG)));
+    fs.stub(Call::Lookup { dirid: 1, name: b"f".to_vec() }, Ok(2_u64));
+    fs.stub(Call::Read { id: 2, offset: 0, count: 65536 }, Ok((vec![b'z'; 65536], true)));
+
+    // servers not offering compression send plain replies
+    let client = connect(fs.clone()).await;
+    assert_eq!(client.negotiate_compression().await.unwrap(), None);
+
+    let mut listener = NFSTcpListener::bind_dyn("127.0.0.1:0", fs).await.unwrap();
+    listener.with_reply_compression(Some(CompressionConfig::default()));
+    let port = listener.get_listen_port();
+    tokio::spawn(async move { listener.handle_forever().await });
+    let client = Client::connect(&format!("127.0.0.1:{port}")).await.unwrap();
+    assert_eq!(client.negotiate_compression().await.unwrap(), Some(Algorithm::Lz4));
+
+    let root = client.mount(b"/").await.unwrap();
+    assert_eq!(client.getattr(&root).await.unwrap().fileid, 1);
+    let (file, _) = client.lookup(&root, &b"f"[..].into()).await.unwrap();
+    let read = client.read(&file, 0, 65536).await.unwrap();
+    assert_eq!(read.data, vec![b'z'; 65536]);
+}
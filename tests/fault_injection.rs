@@ -0,0 +1,102 @@
+use std::io::Cursor;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use nfs_mamont::client::Client;
+use nfs_mamont::protocol::rpc::{self, FaultConfig, FaultProxy};
+use nfs_mamont::tcp::{NFSTcp, NFSTcpListener};
+use nfs_mamont::vfs::testing::{self, Call, MockFs};
+use nfs_mamont::xdr::nfs3::{self, fattr3, ftype3, NFSProgram};
+use nfs_mamont::xdr::{self, deserialize};
+
+/// Serves `fs` on a local port behind a proxy injecting `config`
+///
+/// # Returns
+/// * `(String, String)` - Addresses of the listener and of the proxy
+async fn serve(fs: Arc<MockFs>, config: FaultConfig) -> (String, String) {
+    let listener = NFSTcpListener::bind_dyn("127.0.0.1:0", fs).await.unwrap();
+    let addr = format!("127.0.0.1:{}", listener.get_listen_port());
+    tokio::spawn(async move { listener.handle_forever().await });
+    let proxy = FaultProxy::bind(addr.parse().unwrap(), config).await.unwrap();
+    let proxy_addr = proxy.local_addr().unwrap().to_string();
+    tokio::spawn(proxy.run());
+    (addr, proxy_addr)
+}
+
+fn root_fs() -> Arc<MockFs> {
+    let fs = Arc::new(MockFs::new());
+    fs.stub(
+        Call::Getattr { id: 1 },
+        Ok(fattr3 { ftype: ftype3::NF3DIR, fileid: 1, ..Default::default() }),
+    );
+    fs
+}
+
+#[tokio::test]
+async fn calls_survive_split_and_delayed_records() {
+    let config = FaultConfig {
+        latency: Duration::from_micros(100),
+        jitter: Duration::from_millis(1),
+        max_chunk: 3,
+        ..Default::default()
+    };
+    let (_, proxy) = serve(root_fs(), config).await;
+    let client = Client::connect(&proxy).await.unwrap();
+    let root = client.mount(b"/").await.unwrap();
+    for _ in 0..5 {
+        assert_eq!(client.getattr(&root).await.unwrap().fileid, 1);
+    }
+}
+
+#[tokio::test]
+async fn reordered_records_are_all_answered() {
+    let fs = root_fs();
+    let context = testing::context(fs.clone());
+    let (_, proxy) =
+        serve(fs, FaultConfig { reorder: true, max_chunk: 5, ..Default::default() }).await;
+
+    let mut socket = TcpStream::connect(&proxy).await.unwrap();
+    let mut calls = Vec::new();
+    for xid in [1, 2] {
+        let call = testing::call_message(
+            xid,
+            nfs3::PROGRAM,
+            nfs3::VERSION,
+            NFSProgram::NFSPROC3_GETATTR as u32,
+            &context.id_to_fh(1).unwrap(),
+        );
+        rpc::write_fragment(&mut calls, &call).await.unwrap();
+    }
+    socket.write_all(&calls).await.unwrap();
+
+    let mut xids = Vec::new();
+    for _ in 0..2 {
+        let mut reply = Vec::new();
+        assert!(rpc::read_fragment(&mut socket, &mut reply).await.unwrap());
+        xids.push(deserialize::<xdr::rpc::rpc_msg>(&mut Cursor::new(reply)).unwrap().xid);
+    }
+    xids.sort_unstable();
+    assert_eq!(xids, [1, 2]);
+}
+
+#[tokio::test]
+async fn server_survives_mid_record_disconnects() {
+    let (addr, proxy) =
+        serve(root_fs(), FaultConfig { disconnect_after: Some(10), ..Default::default() }).await;
+
+    let mut socket = TcpStream::connect(&proxy).await.unwrap();
+    let fh = testing::context(Arc::new(MockFs::new())).id_to_fh(1).unwrap();
+    let call = testing::call_message(1, nfs3::PROGRAM, nfs3::VERSION, 0, &fh);
+    let mut record = Vec::new();
+    rpc::write_fragment(&mut record, &call).await.unwrap();
+    socket.write_all(&record).await.unwrap();
+    // the connection is closed without a reply
+    assert_eq!(socket.read(&mut [0; 64]).await.unwrap(), 0);
+
+    let client = Client::connect(&addr).await.unwrap();
+    let root = client.mount(b"/").await.unwrap();
+    assert_eq!(client.getattr(&root).await.unwrap().fileid, 1);
+}
@@ -0,0 +1,230 @@
+//! Byte-exact replies to client sessions.
+//!
+//! Every file in `tests/golden` is a session of RPC call records as they go on
+//! the wire (without the record mark), each followed by the reply the server
+//! has to send. `C` lines hold calls and `R` lines replies, both as hex; `#`
+//! lines are comments. Calls are replayed through `handle_rpc` and through
+//! `process_message`, which also covers the fast paths.
+//!
+//! `client.txt` is captured from a TCP connection of [`Client`] to a listener
+//! serving the fixture, by [`capture_client_session`] with
+//! `NFS_MAMONT_CAPTURE=1`. `linux.txt` and `macos.txt` are written by hand in
+//! the shape of those clients' calls; they are not packet captures.
+//!
+//! After an intended change of the encoding, run the tests with
+//! `NFS_MAMONT_BLESS=1` to rewrite the replies and review the diff.
+
+use std::fmt::Write as _;
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::Arc;
+
+use nfs_mamont::client::Client;
+use nfs_mamont::entropy::SeededEntropy;
+use nfs_mamont::protocol::rpc;
+use nfs_mamont::tcp::{NFSTcp, NFSTcpListener};
+use nfs_mamont::vfs::testing::{self, Call, MockFs};
+use nfs_mamont::vfs::{DirEntry, ReadDirCookie, ReadDirResult};
+use nfs_mamont::xdr::nfs3::{self, fattr3, ftype3, nfsstat3, nfstime3};
+
+/// File system every session is replayed against
+///
+/// `/` (1) holds `hello.txt` (2) with `hello world\n` and `link` (3) pointing to it.
+fn fixture() -> Arc<MockFs> {
+    let time = nfstime3 { seconds: 1_700_000_000, nseconds: 0 };
+    let attr = |fileid, ftype, mode, size| fattr3 {
+        ftype,
+        mode,
+        nlink: 1,
+        uid: 0,
+        gid: 0,
+        size,
+        used: size,
+        fileid,
+        atime: time,
+        mtime: time,
+        ctime: time,
+        ..Default::default()
+    };
+    let root = attr(1, ftype3::NF3DIR, 0o755, 4096);
+    let hello = attr(2, ftype3::NF3REG, 0o644, 12);
+    let link = attr(3, ftype3::NF3LNK, 0o777, 9);
+
+    let fs = Arc::new(MockFs::new().read_only());
+    fs.stub(Call::Getattr { id: 1 }, Ok(root));
+    fs.stub(Call::Getattr { id: 2 }, Ok(hello));
+    fs.stub(Call::Getattr { id: 3 }, Ok(link));
+    fs.stub(Call::Lookup { dirid: 1, name: b"hello.txt".to_vec() }, Ok(2_u64));
+    fs.stub(Call::Lookup { dirid: 1, name: b"link".to_vec() }, Ok(3_u64));
+    let missing = Call::Lookup { dirid: 1, name: b"._hello.txt".to_vec() };
+    fs.stub(missing, Err::<u64, _>(nfsstat3::NFS3ERR_NOENT));
+    fs.stub(Call::Read { id: 2, offset: 0, count: 5 }, Ok((b"hello".to_vec(), false)));
+    fs.stub(Call::Read { id: 2, offset: 4096, count: 32768 }, Ok((Vec::<u8>::new(), true)));
+    fs.stub(Call::Readlink { id: 3 }, Ok(b"hello.txt".to_vec()));
+    let listing = ReadDirResult {
+        entries: vec![
+            DirEntry { fileid: 2, name: b"hello.txt".to_vec().into(), attr: hello },
+            DirEntry { fileid: 3, name: b"link".to_vec().into(), attr: link },
+        ],
+        end: true,
+    };
+    // READDIRPLUS with a dircount of 4096 and READDIR with a count of 8192
+    for max_entries in [256, 512] {
+        let call = Call::ReadDir { dirid: 1, cookie: ReadDirCookie::Start, max_entries };
+        fs.stub(call, Ok(listing.clone()));
+    }
+    fs
+}
+
+/// Parses a hex string
+fn from_hex(hex: &str) -> Vec<u8> {
+    assert!(hex.len() % 2 == 0, "odd number of hex digits");
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+}
+
+/// Formats bytes as a hex string
+fn to_hex(data: &[u8]) -> String {
+    data.iter().fold(String::new(), |mut out, b| {
+        write!(out, "{b:02x}").unwrap();
+        out
+    })
+}
+
+/// Replays the session in `path` and compares, or with `bless` rewrites, the replies
+async fn replay(path: &Path, bless: bool) {
+    let session = std::fs::read_to_string(path).unwrap();
+    let fs = fixture();
+    let context = testing::context(fs.clone());
+    let mut blessed = String::new();
+    let mut pending: Option<(usize, Vec<u8>)> = None;
+
+    for (index, line) in session.lines().enumerate() {
+        let lineno = index + 1;
+        if let Some(expected) = line.strip_prefix("R ") {
+            let (call_line, reply) = pending.take().expect("reply without call");
+            assert!(
+                bless || expected == to_hex(&reply),
+                "{}:{lineno}: reply to the call on line {call_line} differs\nexpected {expected}\n  actual {}",
+                path.display(),
+                to_hex(&reply)
+            );
+            continue;
+        }
+        if let Some((call_line, _)) = pending.take() {
+            assert!(bless, "{}:{call_line}: call has no recorded reply", path.display());
+        }
+        blessed.push_str(line);
+        blessed.push('\n');
+
+        let Some(call) = line.strip_prefix("C ") else {
+            continue;
+        };
+        let call = from_hex(call);
+        let mut generic = Vec::new();
+        rpc::handle_rpc(&mut Cursor::new(&call), &mut generic, context.clone()).await.unwrap();
+        let mut dispatched = Vec::new();
+        rpc::process_message(&call, &mut dispatched, context.clone()).await.unwrap();
+        assert_eq!(
+            to_hex(&dispatched),
+            to_hex(&generic),
+            "{}:{lineno}: fast path reply differs from the generic path",
+            path.display()
+        );
+        writeln!(blessed, "R {}", to_hex(&generic)).unwrap();
+        pending = Some((lineno, generic));
+    }
+    if let Some((call_line, _)) = pending {
+        assert!(bless, "{}:{call_line}: call has no recorded reply", path.display());
+    }
+    assert!(fs.unexpected_calls().is_empty(), "unexpected calls: {:?}", fs.unexpected_calls());
+
+    if bless {
+        std::fs::write(path, blessed).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn replies_match_snapshots() {
+    let bless = std::env::var_os("NFS_MAMONT_BLESS").is_some();
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let mut sessions: Vec<_> =
+        std::fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+    sessions.sort();
+    assert!(!sessions.is_empty(), "no sessions");
+    for path in sessions {
+        replay(&path, bless).await;
+    }
+}
+
+/// Returns the value of the string field `key` of a JSON line of a capture
+fn json_field<'a>(line: &'a str, key: &str) -> &'a str {
+    let start = line.find(&format!("\"{key}\":\"")).unwrap() + key.len() + 4;
+    let len = line[start..].find('"').unwrap();
+    &line[start..start + len]
+}
+
+/// Captures `tests/golden/client.txt` from the traffic of [`Client`]
+///
+/// The client runs a session against a listener serving the fixture, which
+/// records the connection with a JSON [`rpc::Capture`]; the records are written
+/// out as they were sent. Only runs with `NFS_MAMONT_CAPTURE=1`.
+#[tokio::test]
+async fn capture_client_session() {
+    if std::env::var_os("NFS_MAMONT_CAPTURE").is_none() {
+        return;
+    }
+    let json = std::env::temp_dir().join(format!("nfs-mamont-golden-{}.json", std::process::id()));
+    let capture = Arc::new(rpc::Capture::create(&json, rpc::CaptureFormat::Json).unwrap());
+    let mut listener = NFSTcpListener::bind_dyn("127.0.0.1:0", fixture()).await.unwrap();
+    listener.with_capture(capture.clone());
+    let addr = format!("127.0.0.1:{}", listener.get_listen_port());
+    tokio::spawn(async move { listener.handle_forever().await });
+
+    let client = Client::connect(&addr)
+        .await
+        .unwrap()
+        .with_entropy(&SeededEntropy::new(0))
+        .with_auth_unix(1000, 1000, vec![1000, 4, 24]);
+    let mut labels = vec!["MOUNT MNT /"];
+    let root = client.mount(b"/").await.unwrap();
+    labels.push("FSINFO root");
+    client.fsinfo(&root).await.unwrap();
+    labels.push("GETATTR root");
+    client.getattr(&root).await.unwrap();
+    labels.push("READDIRPLUS root");
+    let verf = nfs3::cookieverf3::default();
+    client.readdirplus(&root, 0, verf, 4096).await.unwrap();
+    labels.push("READDIR root");
+    client.readdir(&root, 0, verf, 8192).await.unwrap();
+    labels.push("LOOKUP hello.txt");
+    let (file, _) = client.lookup(&root, &b"hello.txt".to_vec().into()).await.unwrap();
+    labels.push("READ 5 bytes of hello.txt");
+    client.read(&file, 0, 5).await.unwrap();
+    labels.push("READ hello.txt past its end");
+    client.read(&file, 4096, 32768).await.unwrap();
+    labels.push("LOOKUP link");
+    let (link, _) = client.lookup(&root, &b"link".to_vec().into()).await.unwrap();
+    labels.push("READLINK link");
+    client.readlink(&link).await.unwrap();
+    drop(client);
+    capture.flush().unwrap();
+
+    let records = std::fs::read_to_string(&json).unwrap();
+    std::fs::remove_file(&json).unwrap();
+    let mut session = String::from(
+        "# Captured from a connection of nfs_mamont::client::Client to a listener\n\
+         # serving the fixture of tests/golden.rs, by capture_client_session with\n\
+         # NFS_MAMONT_CAPTURE=1. Calls carry AUTH_UNIX credentials of a regular user.\n",
+    );
+    let mut labels = labels.into_iter();
+    for line in records.lines() {
+        let data = json_field(line, "data");
+        match json_field(line, "direction") {
+            "call" => write!(session, "\n# {}\nC {data}\n", labels.next().unwrap()).unwrap(),
+            _ => writeln!(session, "R {data}").unwrap(),
+        }
+    }
+    assert!(labels.next().is_none(), "calls missing from the capture");
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/client.txt");
+    std::fs::write(path, session).unwrap();
+}
@@ -0,0 +1,1684 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use nfs_mamont::clock::{Clock, ManualClock};
+use nfs_mamont::protocol::nfs::{self, v3::DirLocks};
+use nfs_mamont::protocol::rpc::{
+    self, CallInfo, DispatchHook, DispatchHooks, HookDecision, ReplyCache, ReplyStream,
+};
+use nfs_mamont::vfs::testing::{self, Call, MockFs};
+use nfs_mamont::vfs::Capabilities;
+use nfs_mamont::write_counter::SpaceCounter;
+use nfs_mamont::xdr::deserialize;
+use nfs_mamont::xdr::mount;
+use nfs_mamont::xdr::nfs3::{self, fattr3, nfsstat3, NFSProgram};
+use nfs_mamont::xdr::rpc::{
+    accept_body, auth_flavor, auth_stat, auth_unix, call_body, opaque_auth, rejected_reply,
+    reply_body, rpc_body, rpc_msg,
+};
+use nfs_mamont::xdr::Serialize;
+
+#[tokio::test]
+async fn lookup_returns_handle_and_attributes() {
+    let fs = Arc::new(MockFs::new());
+    fs.expect(Call::Lookup { dirid: 1, name: b"a".to_vec() }, Ok(2_u64));
+    fs.stub(Call::Getattr { id: 2 }, Ok(fattr3 { fileid: 2, ..Default::default() }));
+    fs.stub(Call::Getattr { id: 1 }, Ok(fattr3 { fileid: 1, ..Default::default() }));
+
+    let context = testing::context(fs.clone());
+    let args = nfs3::diropargs3 { dir: context.id_to_fh(1).unwrap(), name: b"a".to_vec().into() };
+    let mut reply = testing::call_nfs3(&context, NFSProgram::NFSPROC3_LOOKUP, &args).await.unwrap();
+
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+    let handle = deserialize::<nfs3::nfs_fh3>(&mut reply).unwrap();
+    assert_eq!(context.fh_to_id(&handle).ok(), Some(2));
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn lookup_reports_scripted_error() {
+    let fs = Arc::new(MockFs::new());
+    fs.expect(
+        Call::Lookup { dirid: 1, name: b"missing".to_vec() },
+        Err::<u64, _>(nfsstat3::NFS3ERR_NOENT),
+    );
+    fs.stub(Call::Getattr { id: 1 }, Ok(fattr3::default()));
+
+    let context = testing::context(fs.clone());
+    let args =
+        nfs3::diropargs3 { dir: context.id_to_fh(1).unwrap(), name: b"missing".to_vec().into() };
+    let mut reply = testing::call_nfs3(&context, NFSProgram::NFSPROC3_LOOKUP, &args).await.unwrap();
+
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3ERR_NOENT as u32);
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn missing_names_are_cached_until_created() {
+    let fs = Arc::new(MockFs::new().with_capabilities(Capabilities::ReadWrite));
+    fs.stub(Call::Getattr { id: 1 }, Ok(fattr3 { fileid: 1, ..Default::default() }));
+    fs.stub(Call::Getattr { id: 2 }, Ok(fattr3 { fileid: 2, ..Default::default() }));
+    fs.expect(
+        Call::Lookup { dirid: 1, name: b"d".to_vec() },
+        Err::<u64, _>(nfsstat3::NFS3ERR_NOENT),
+    );
+
+    let mut context = testing::context(fs.clone());
+    let config = nfs::v3::NegativeLookupConfig::default();
+    context.negative_lookups = Some(Arc::new(nfs::v3::NegativeLookupCache::new(config)));
+    let lookup = nfs3::diropargs3 { dir: context.id_to_fh(1).unwrap(), name: b"d".to_vec().into() };
+    for _ in 0..2 {
+        let mut reply =
+            testing::call_nfs3(&context, NFSProgram::NFSPROC3_LOOKUP, &lookup).await.unwrap();
+        assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3ERR_NOENT as u32);
+    }
+    let stats = context.stats.snapshot();
+    assert_eq!((stats.negative_lookups.hits, stats.negative_lookups.misses), (1, 1));
+
+    fs.expect(Call::Mkdir { dirid: 1, name: b"d".to_vec() }, Ok((2_u64, fattr3::default())));
+    fs.expect(Call::Lookup { dirid: 1, name: b"d".to_vec() }, Ok(2_u64));
+    let mkdir = nfs3::dir::MKDIR3args { dirops: lookup.clone(), attributes: Default::default() };
+    let mut reply = testing::call_nfs3(&context, NFSProgram::NFSPROC3_MKDIR, &mkdir).await.unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+    let mut reply =
+        testing::call_nfs3(&context, NFSProgram::NFSPROC3_LOOKUP, &lookup).await.unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn missing_capability_is_refused_before_backend() {
+    let fs =
+        Arc::new(MockFs::new().with_capabilities(Capabilities::WRITE | Capabilities::SYMLINKS));
+
+    let context = testing::context(fs.clone());
+    let args = nfs3::file::LINK3args {
+        file: context.id_to_fh(2).unwrap(),
+        link: nfs3::diropargs3 { dir: context.id_to_fh(1).unwrap(), name: b"b".to_vec().into() },
+    };
+    let mut reply = testing::call_nfs3(&context, NFSProgram::NFSPROC3_LINK, &args).await.unwrap();
+
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3ERR_NOTSUPP as u32);
+    assert!(fs.calls().is_empty());
+}
+
+#[tokio::test]
+async fn stale_handles_are_rehydrated_during_grace() {
+    let fs = Arc::new(MockFs::new());
+    // A handle of generation 0, issued before the mock's generation 1
+    let old = nfs3::nfs_fh3 { data: [0_u64.to_le_bytes(), 7_u64.to_le_bytes()].concat() };
+    fs.stub(Call::RehydrateHandle { handle: old.data.clone() }, Ok(7_u64));
+
+    let mut context = testing::context(fs.clone());
+    assert_eq!(context.fh_to_id(&old).unwrap_err() as u32, nfsstat3::NFS3ERR_STALE as u32);
+    assert!(fs.calls().is_empty());
+
+    context.handle_grace_until = Some(Instant::now() + Duration::from_secs(60));
+    assert_eq!(context.fh_to_id(&old).ok(), Some(7));
+
+    context.handle_grace_until = Some(Instant::now() - Duration::from_secs(1));
+    assert_eq!(context.fh_to_id(&old).unwrap_err() as u32, nfsstat3::NFS3ERR_STALE as u32);
+    assert_eq!(fs.calls().len(), 1);
+}
+
+#[tokio::test]
+async fn directory_changes_wait_for_the_directory_lock() {
+    let fs = Arc::new(MockFs::new());
+    fs.stub(Call::Getattr { id: 1 }, Ok(fattr3 { fileid: 1, ..Default::default() }));
+    fs.expect(Call::Remove { dirid: 1, name: b"a".to_vec() }, Ok(()));
+
+    let mut context = testing::context(fs.clone());
+    let locks = Arc::new(DirLocks::default());
+    context.dir_locks = Some(locks.clone());
+    let guard = locks.lock(1, None).await;
+
+    let args = nfs3::diropargs3 { dir: context.id_to_fh(1).unwrap(), name: b"a".to_vec().into() };
+    let remove = tokio::spawn(async move {
+        testing::call_nfs3(&context, NFSProgram::NFSPROC3_REMOVE, &args).await.unwrap()
+    });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(fs.calls().is_empty(), "REMOVE must wait for the directory lock");
+
+    drop(guard);
+    let mut reply = remove.await.unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn server_time_comes_from_the_context_clock() {
+    let fs = Arc::new(MockFs::new().with_capabilities(Capabilities::ReadWrite));
+    fs.stub(Call::Getattr { id: 2 }, Ok(fattr3 { fileid: 2, ..Default::default() }));
+    fs.expect(Call::Setattr { id: 2 }, Ok(fattr3 { fileid: 2, ..Default::default() }));
+
+    let mut context = testing::context(fs.clone());
+    context.clock = Arc::new(ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_000)));
+    let args = nfs3::SETATTR3args {
+        object: context.id_to_fh(2).unwrap(),
+        new_attribute: nfs3::sattr3 {
+            atime: nfs3::set_atime::SET_TO_SERVER_TIME,
+            mtime: nfs3::set_mtime::SET_TO_SERVER_TIME,
+            ..Default::default()
+        },
+        guard: None,
+    };
+    let mut reply =
+        testing::call_nfs3(&context, NFSProgram::NFSPROC3_SETATTR, &args).await.unwrap();
+
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+    let attr = fs.attributes()[0];
+    let (nfs3::set_atime::SET_TO_CLIENT_TIME(atime), nfs3::set_mtime::SET_TO_CLIENT_TIME(mtime)) =
+        (attr.atime, attr.mtime)
+    else {
+        panic!("server time passed to the file system: {attr:?}");
+    };
+    assert_eq!((atime.seconds, mtime.seconds), (1_000, 1_000));
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn backend_sees_the_request_context() {
+    let fs = Arc::new(MockFs::new());
+    fs.expect(Call::Lookup { dirid: 1, name: b"a".to_vec() }, Ok(2_u64));
+    fs.stub(Call::Getattr { id: 1 }, Ok(fattr3 { fileid: 1, ..Default::default() }));
+    fs.stub(Call::Getattr { id: 2 }, Ok(fattr3 { fileid: 2, ..Default::default() }));
+
+    let mut context = testing::context(fs.clone());
+    context.request_timeout = Some(Duration::from_secs(30));
+    let started = Instant::now();
+    // GETATTR takes the fast path, LOOKUP the generic one
+    testing::call_nfs3(&context, NFSProgram::NFSPROC3_GETATTR, &context.id_to_fh(1).unwrap())
+        .await
+        .unwrap();
+    let args = nfs3::diropargs3 { dir: context.id_to_fh(1).unwrap(), name: b"a".to_vec().into() };
+    testing::call_nfs3(&context, NFSProgram::NFSPROC3_LOOKUP, &args).await.unwrap();
+
+    let requests: Vec<_> = fs.requests().into_iter().map(Option::unwrap).collect();
+    assert!(requests.len() >= 2);
+    for request in &requests {
+        assert_eq!((request.xid, request.client_addr), (1, ([127, 0, 0, 1], 700).into()));
+        let deadline = request.deadline.unwrap();
+        assert!(deadline >= started + Duration::from_secs(30));
+        assert!(deadline <= Instant::now() + Duration::from_secs(30));
+    }
+    assert_ne!(requests[0].trace_id, requests.last().unwrap().trace_id);
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn retransmitted_datagram_is_answered_from_cache() {
+    let fs = Arc::new(MockFs::new());
+    fs.expect(Call::Remove { dirid: 1, name: b"a".to_vec() }, Ok(()));
+    fs.stub(Call::Getattr { id: 1 }, Ok(fattr3::default()));
+
+    let context = testing::context(fs.clone());
+    let cache = ReplyCache::default();
+    let args = nfs3::diropargs3 { dir: context.id_to_fh(1).unwrap(), name: b"a".to_vec().into() };
+    let call = testing::call_message(
+        7,
+        nfs3::PROGRAM,
+        nfs3::VERSION,
+        NFSProgram::NFSPROC3_REMOVE as u32,
+        &args,
+    );
+
+    let mut first = Vec::new();
+    assert!(rpc::process_datagram(&call, &mut first, context.clone(), &cache).await.unwrap());
+    let mut second = Vec::new();
+    assert!(rpc::process_datagram(&call, &mut second, context.clone(), &cache).await.unwrap());
+
+    assert_eq!(first, second);
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn streamed_read_matches_buffered_reply() {
+    let fs = Arc::new(MockFs::new());
+    let attr = fattr3 { ftype: nfs3::ftype3::NF3REG, size: 10, fileid: 2, ..Default::default() };
+    fs.stub(Call::Getattr { id: 2 }, Ok(attr));
+    fs.stub(Call::Read { id: 2, offset: 0, count: 32 }, Ok((b"0123456789".to_vec(), true)));
+    fs.expect(Call::Read { id: 2, offset: 0, count: 4 }, Ok((b"0123".to_vec(), false)));
+    fs.expect(Call::Read { id: 2, offset: 4, count: 4 }, Ok((b"4567".to_vec(), false)));
+    fs.expect(Call::Read { id: 2, offset: 8, count: 2 }, Ok((b"89".to_vec(), true)));
+
+    let mut context = testing::context(fs.clone());
+    context.read_ahead = None;
+    let args = nfs3::file::READ3args { file: context.id_to_fh(2).unwrap(), offset: 0, count: 32 };
+    let call = testing::call_message(
+        1,
+        nfs3::PROGRAM,
+        nfs3::VERSION,
+        NFSProgram::NFSPROC3_READ as u32,
+        &args,
+    );
+    let mut buffered = Vec::new();
+    rpc::process_message(&call, &mut buffered, context.clone()).await.unwrap();
+
+    let stream = ReplyStream::new(4);
+    context.reply_stream = Some(stream.clone());
+    let mut data = Vec::new();
+    rpc::process_message(&call, &mut data, context).await.unwrap();
+    let mut socket = Vec::new();
+    rpc::write_reply(&mut socket, rpc::Reply { data, body: stream.take() }).await.unwrap();
+
+    // reassemble the record from its fragments
+    let mut record = Vec::new();
+    let mut fragments = Vec::new();
+    let mut rest = &socket[..];
+    loop {
+        let header = u32::from_be_bytes(rest[..4].try_into().unwrap());
+        let len = (header & !(1 << 31)) as usize;
+        record.extend_from_slice(&rest[4..4 + len]);
+        fragments.push(len);
+        rest = &rest[4 + len..];
+        if header & (1 << 31) != 0 {
+            break;
+        }
+    }
+    assert!(rest.is_empty());
+    assert_eq!(fragments[1..], [4, 4, 4]);
+    assert_eq!(record, buffered);
+    fs.assert_done();
+}
+
+/// Refuses `MKNOD`
+struct NoDevices;
+
+impl DispatchHook for NoDevices {
+    fn before(
+        &self,
+        call: &CallInfo,
+        _context: &rpc::Context,
+        _args: &mut Vec<u8>,
+    ) -> HookDecision {
+        if call.procedure == NFSProgram::NFSPROC3_MKNOD as u32 {
+            HookDecision::Fail(nfsstat3::NFS3ERR_NOTSUPP)
+        } else {
+            HookDecision::Continue
+        }
+    }
+}
+
+#[tokio::test]
+async fn dispatch_hook_refuses_calls() {
+    let fs = Arc::new(MockFs::new());
+    fs.stub(Call::Getattr { id: 1 }, Ok(fattr3::default()));
+    let mut context = testing::context(fs.clone());
+    let mut hooks = DispatchHooks::default();
+    hooks.push(Arc::new(NoDevices));
+    context.hooks = Arc::new(hooks);
+
+    let args = nfs3::dir::MKNOD3args {
+        where_dir: nfs3::diropargs3 {
+            dir: context.id_to_fh(1).unwrap(),
+            name: b"dev".to_vec().into(),
+        },
+        what: nfs3::dir::mknoddata3::default(),
+    };
+    let mut reply = testing::call_nfs3(&context, NFSProgram::NFSPROC3_MKNOD, &args).await.unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3ERR_NOTSUPP as u32);
+    // empty wcc_data
+    assert!(deserialize::<nfs3::wcc_data>(&mut reply).unwrap().after.is_none());
+    assert_eq!(reply.position() as usize, reply.get_ref().len());
+
+    let mut reply =
+        testing::call_nfs3(&context, NFSProgram::NFSPROC3_GETATTR, &context.id_to_fh(1).unwrap())
+            .await
+            .unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+    fs.assert_done();
+}
+
+/// Redirects `GETATTR` of file 2 to file 3, answers `ACCESS` itself, drops
+/// `NULL` and refuses every call of the mount program
+struct Rules {
+    redirected: Vec<u8>,
+}
+
+impl DispatchHook for Rules {
+    fn before(&self, call: &CallInfo, _context: &rpc::Context, args: &mut Vec<u8>) -> HookDecision {
+        match (call.program, call.procedure) {
+            (mount::PROGRAM, _) => HookDecision::Fail(nfsstat3::NFS3ERR_ACCES),
+            (_, 0) => HookDecision::Drop,
+            (_, proc) if proc == NFSProgram::NFSPROC3_ACCESS as u32 => {
+                HookDecision::Reply(call.xid.to_be_bytes().to_vec())
+            }
+            (_, proc) if proc == NFSProgram::NFSPROC3_GETATTR as u32 => {
+                *args = self.redirected.clone();
+                HookDecision::Continue
+            }
+            _ => HookDecision::Continue,
+        }
+    }
+}
+
+#[tokio::test]
+async fn dispatch_hooks_rewrite_answer_and_drop_calls() {
+    let fs = Arc::new(MockFs::new());
+    fs.expect(Call::Getattr { id: 3 }, Ok(fattr3 { fileid: 3, ..Default::default() }));
+    let mut context = testing::context(fs.clone());
+    let mut redirected = Vec::new();
+    context.id_to_fh(3).unwrap().serialize(&mut redirected).unwrap();
+    let mut hooks = DispatchHooks::default();
+    hooks.push(Arc::new(NoDevices));
+    hooks.push(Arc::new(Rules { redirected }));
+    context.hooks = Arc::new(hooks);
+
+    let file = context.id_to_fh(2).unwrap();
+    let mut reply =
+        testing::call_nfs3(&context, NFSProgram::NFSPROC3_GETATTR, &file).await.unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+    assert_eq!(deserialize::<fattr3>(&mut reply).unwrap().fileid, 3);
+
+    let access = nfs3::ACCESS3args { object: file, access: 1 };
+    let call = testing::call_message(9, nfs3::PROGRAM, nfs3::VERSION, 4, &access);
+    let mut reply = Vec::new();
+    assert!(rpc::process_message(&call, &mut reply, context.clone()).await.unwrap());
+    assert_eq!(reply, 9_u32.to_be_bytes());
+
+    let call = testing::call_message(10, nfs3::PROGRAM, nfs3::VERSION, 0, &());
+    let mut reply = Vec::new();
+    assert!(!rpc::process_message(&call, &mut reply, context.clone()).await.unwrap());
+    assert!(reply.is_empty());
+
+    // calls of other programs cannot carry an NFS status
+    let call = testing::call_message(11, mount::PROGRAM, mount::VERSION, 0, &());
+    let mut reply = Vec::new();
+    rpc::process_message(&call, &mut reply, context).await.unwrap();
+    assert!(matches!(
+        deserialize::<rpc_msg>(&mut &reply[..]).unwrap().body,
+        rpc_body::REPLY(reply_body::MSG_DENIED(rejected_reply::AUTH_ERROR(
+            auth_stat::AUTH_TOOWEAK
+        )))
+    ));
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn short_write_reports_count_and_wcc() {
+    let fs = Arc::new(MockFs::new());
+    let before = fattr3 { ftype: nfs3::ftype3::NF3REG, size: 0, fileid: 2, ..Default::default() };
+    let after = fattr3 { size: 3, ..before };
+    fs.expect(Call::Getattr { id: 2 }, Ok(before));
+    fs.expect(Call::Write { id: 2, offset: 0, data: b"hello".to_vec() }, Ok((after, 3_u32)));
+
+    let context = testing::context(fs.clone());
+    let args = nfs3::file::WRITE3args {
+        file: context.id_to_fh(2).unwrap(),
+        offset: 0,
+        count: 5,
+        stable: nfs3::file::stable_how::FILE_SYNC as u32,
+        data: b"hello".to_vec(),
+    };
+    let mut reply = testing::call_nfs3(&context, NFSProgram::NFSPROC3_WRITE, &args).await.unwrap();
+
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+    let res = deserialize::<nfs3::file::WRITE3resok>(&mut reply).unwrap();
+    assert_eq!(res.count, 3);
+    assert_eq!(res.file_wcc.before.map(|attr| attr.size), Some(0));
+    assert_eq!(res.file_wcc.after.map(|attr| attr.size), Some(3));
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn checksums_detect_data_changed_by_the_backend() {
+    let fs = Arc::new(MockFs::new());
+    let attr = fattr3 { ftype: nfs3::ftype3::NF3REG, size: 8, fileid: 2, ..Default::default() };
+    fs.stub(Call::Getattr { id: 2 }, Ok(attr));
+    fs.expect(Call::Write { id: 2, offset: 4, data: b"data".to_vec() }, Ok(attr));
+    fs.expect(Call::Read { id: 2, offset: 0, count: 8 }, Ok((b"....data".to_vec(), true)));
+    fs.expect(Call::Read { id: 2, offset: 0, count: 8 }, Ok((b"....dada".to_vec(), true)));
+
+    let mut context = testing::context(fs.clone());
+    context.read_ahead = None;
+    let checksums = Arc::new(nfs::v3::Checksums::new(nfs::v3::ChecksumConfig::default()));
+    context.checksums = Some(checksums.clone());
+    let write = nfs3::file::WRITE3args {
+        file: context.id_to_fh(2).unwrap(),
+        offset: 4,
+        count: 4,
+        stable: nfs3::file::stable_how::FILE_SYNC as u32,
+        data: b"data".to_vec(),
+    };
+    testing::call_nfs3(&context, NFSProgram::NFSPROC3_WRITE, &write).await.unwrap();
+    assert_eq!(checksums.written(2, 4, 4), Some(nfs::v3::crc32c(b"data")));
+
+    let read = nfs3::file::READ3args { file: context.id_to_fh(2).unwrap(), offset: 0, count: 8 };
+    testing::call_nfs3(&context, NFSProgram::NFSPROC3_READ, &read).await.unwrap();
+    assert_eq!(checksums.mismatches(), 0);
+    // the reply still carries the data, the mismatch is only reported
+    let mut reply = testing::call_nfs3(&context, NFSProgram::NFSPROC3_READ, &read).await.unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+    assert_eq!(checksums.mismatches(), 1);
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn bytes_and_calls_are_counted_per_export() {
+    let fs = Arc::new(MockFs::new());
+    let attr = fattr3 { ftype: nfs3::ftype3::NF3REG, size: 8, fileid: 2, ..Default::default() };
+    fs.stub(Call::Getattr { id: 2 }, Ok(attr));
+    fs.expect(Call::Write { id: 2, offset: 0, data: b"data".to_vec() }, Ok(attr));
+    fs.expect(Call::Read { id: 2, offset: 0, count: 8 }, Ok((b"datadata".to_vec(), true)));
+
+    let mut context = testing::context(fs.clone());
+    context.read_ahead = None;
+    context.export_name = Arc::new("/tenant".to_string());
+    let write = nfs3::file::WRITE3args {
+        file: context.id_to_fh(2).unwrap(),
+        offset: 0,
+        count: 4,
+        stable: nfs3::file::stable_how::FILE_SYNC as u32,
+        data: b"data".to_vec(),
+    };
+    testing::call_nfs3(&context, NFSProgram::NFSPROC3_WRITE, &write).await.unwrap();
+    let read = nfs3::file::READ3args { file: context.id_to_fh(2).unwrap(), offset: 0, count: 8 };
+    testing::call_nfs3(&context, NFSProgram::NFSPROC3_READ, &read).await.unwrap();
+
+    let stats = context.stats.snapshot();
+    assert_eq!(stats.exports.len(), 1);
+    let export = &stats.exports[0];
+    assert_eq!(export.name, "/tenant");
+    assert_eq!((export.bytes_read, export.bytes_written), (8, 4));
+    assert_eq!((export.calls["NFSPROC3_READ"], export.calls["NFSPROC3_WRITE"]), (1, 1));
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn new_files_get_default_mode_and_owner() {
+    let fs = Arc::new(MockFs::new().with_capabilities(Capabilities::ReadWrite));
+    fs.stub(Call::Getattr { id: 1 }, Ok(fattr3 { fileid: 1, ..Default::default() }));
+    fs.expect(
+        Call::Lookup { dirid: 1, name: b"f".to_vec() },
+        Err::<u64, _>(nfsstat3::NFS3ERR_NOENT),
+    );
+    fs.expect(Call::Create { dirid: 1, name: b"f".to_vec() }, Ok((2_u64, fattr3::default())));
+    fs.expect(Call::Mkdir { dirid: 1, name: b"d".to_vec() }, Ok((3_u64, fattr3::default())));
+    fs.expect(Call::Setattr { id: 3 }, Ok(fattr3 { mode: 0o750, ..Default::default() }));
+
+    let mut context = testing::context(fs.clone());
+    context.auth.uid = 1000;
+    context.auth.gid = 100;
+    let owner = nfs::v3::DefaultOwner::Caller;
+    let defaults = nfs::v3::CreationDefaults { umask: 0o027, owner, ..Default::default() };
+    context.creation_defaults = Some(defaults);
+    let create = nfs3::dir::CREATE3args {
+        dirops: nfs3::diropargs3 { dir: context.id_to_fh(1).unwrap(), name: b"f".to_vec().into() },
+        how: nfs3::createhow3::GUARDED(nfs3::sattr3::default()),
+    };
+    let mut reply =
+        testing::call_nfs3(&context, NFSProgram::NFSPROC3_CREATE, &create).await.unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+    let mkdir = nfs3::dir::MKDIR3args {
+        dirops: nfs3::diropargs3 { dir: context.id_to_fh(1).unwrap(), name: b"d".to_vec().into() },
+        attributes: nfs3::sattr3 { gid: Some(5), ..Default::default() },
+    };
+    let mut reply = testing::call_nfs3(&context, NFSProgram::NFSPROC3_MKDIR, &mkdir).await.unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+
+    let attributes = fs.attributes();
+    assert_eq!(
+        (attributes[0].mode, attributes[0].uid, attributes[0].gid),
+        (Some(0o640), Some(1000), Some(100))
+    );
+    assert_eq!(
+        (attributes[1].mode, attributes[1].uid, attributes[1].gid),
+        (Some(0o750), Some(1000), Some(5))
+    );
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn pre_op_attributes_reported_by_backend_spare_getattr() {
+    let fs = Arc::new(MockFs::new().with_pre_op_attrs());
+    let before = nfs3::wcc_attr { size: 3, ..Default::default() };
+    let after = fattr3 { ftype: nfs3::ftype3::NF3REG, size: 4, fileid: 2, ..Default::default() };
+    let call = Call::Write { id: 2, offset: 0, data: b"data".to_vec() };
+    fs.expect(call, Ok((Some(before), after, 4_u32)));
+    fs.expect(Call::Setattr { id: 2 }, Ok((Some(nfs3::wcc_attr::from(after)), after)));
+
+    let context = testing::context(fs.clone());
+    let write = nfs3::file::WRITE3args {
+        file: context.id_to_fh(2).unwrap(),
+        offset: 0,
+        count: 4,
+        stable: nfs3::file::stable_how::FILE_SYNC as u32,
+        data: b"data".to_vec(),
+    };
+    let mut reply = testing::call_nfs3(&context, NFSProgram::NFSPROC3_WRITE, &write).await.unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+    let res = deserialize::<nfs3::file::WRITE3resok>(&mut reply).unwrap();
+    assert_eq!(res.file_wcc.before.map(|attr| attr.size), Some(3));
+
+    let setattr = nfs3::SETATTR3args {
+        object: context.id_to_fh(2).unwrap(),
+        new_attribute: nfs3::sattr3 { mode: Some(0o600), ..Default::default() },
+        guard: None,
+    };
+    let mut reply =
+        testing::call_nfs3(&context, NFSProgram::NFSPROC3_SETATTR, &setattr).await.unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+    let wcc = deserialize::<nfs3::wcc_data>(&mut reply).unwrap();
+    assert_eq!(wcc.before.map(|attr| attr.size), Some(4));
+    assert!(!fs.calls().contains(&Call::Getattr { id: 2 }));
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn reads_of_immutable_files_are_cached() {
+    let fs = Arc::new(MockFs::new().with_immutable_files([2]));
+    for id in [2, 3] {
+        let attr =
+            fattr3 { ftype: nfs3::ftype3::NF3REG, size: 4, fileid: id, ..Default::default() };
+        fs.stub(Call::Getattr { id }, Ok(attr));
+        fs.expect(Call::Read { id, offset: 0, count: 4 }, Ok((b"data".to_vec(), true)));
+    }
+    fs.expect(Call::Read { id: 3, offset: 0, count: 4 }, Ok((b"data".to_vec(), true)));
+
+    let mut context = testing::context(fs.clone());
+    context.read_ahead = None;
+    let config = nfs::v3::ReadCacheConfig::default();
+    context.read_cache = Some(Arc::new(nfs::v3::ReadCache::new(config)));
+    for id in [2, 3] {
+        let read =
+            nfs3::file::READ3args { file: context.id_to_fh(id).unwrap(), offset: 0, count: 4 };
+        for _ in 0..2 {
+            let mut reply =
+                testing::call_nfs3(&context, NFSProgram::NFSPROC3_READ, &read).await.unwrap();
+            assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+            let res = deserialize::<nfs3::file::READ3resok>(&mut reply).unwrap();
+            assert_eq!((res.data.as_slice(), res.eof), (&b"data"[..], true));
+        }
+    }
+    let stats = context.stats.snapshot();
+    assert_eq!((stats.read_cache.hits, stats.read_cache.misses), (1, 1));
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn export_limits_refuse_large_files_and_full_directories() {
+    let fs = Arc::new(MockFs::new());
+    let attr = fattr3 { ftype: nfs3::ftype3::NF3REG, size: 8, fileid: 2, ..Default::default() };
+    fs.stub(Call::Getattr { id: 2 }, Ok(attr));
+    fs.stub(Call::Getattr { id: 1 }, Ok(fattr3 { fileid: 1, ..Default::default() }));
+    fs.expect(
+        Call::Lookup { dirid: 1, name: b"d".to_vec() },
+        Err::<u64, _>(nfsstat3::NFS3ERR_NOENT),
+    );
+    let entry = nfs_mamont::vfs::DirEntry { fileid: 2, ..Default::default() };
+    let listing = nfs_mamont::vfs::ReadDirResult { entries: vec![entry], end: true };
+    let start = nfs_mamont::vfs::ReadDirCookie::Start;
+    fs.expect(Call::ReadDir { dirid: 1, cookie: start, max_entries: 1 }, Ok(listing));
+
+    let mut context = testing::context(fs.clone());
+    context.export_limits = nfs::v3::ExportLimits {
+        max_file_size: Some(8),
+        max_dir_entries: Some(1),
+        max_entries: None,
+    };
+    let write = nfs3::file::WRITE3args {
+        file: context.id_to_fh(2).unwrap(),
+        offset: 4,
+        count: 5,
+        stable: nfs3::file::stable_how::FILE_SYNC as u32,
+        data: b"data!".to_vec(),
+    };
+    let mut reply = testing::call_nfs3(&context, NFSProgram::NFSPROC3_WRITE, &write).await.unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3ERR_FBIG as u32);
+
+    let mkdir = nfs3::dir::MKDIR3args {
+        dirops: nfs3::diropargs3 { dir: context.id_to_fh(1).unwrap(), name: b"d".to_vec().into() },
+        attributes: nfs3::sattr3::default(),
+    };
+    let mut reply = testing::call_nfs3(&context, NFSProgram::NFSPROC3_MKDIR, &mkdir).await.unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3ERR_NOSPC as u32);
+    fs.assert_done();
+}
+
+/// Quarantines files written to with data starting with "EICAR"
+struct Scanner;
+
+#[async_trait::async_trait]
+impl nfs::v3::ContentInspector for Scanner {
+    async fn inspect_write(&self, _id: u64, _offset: u64, data: &[u8]) -> nfs::v3::Verdict {
+        if data.starts_with(b"EICAR") {
+            nfs::v3::Verdict::Quarantine
+        } else {
+            nfs::v3::Verdict::Allow
+        }
+    }
+}
+
+#[tokio::test]
+async fn content_inspection_quarantines_files() {
+    let fs = Arc::new(MockFs::new());
+    let attr = fattr3 { ftype: nfs3::ftype3::NF3REG, size: 8, fileid: 2, ..Default::default() };
+    fs.stub(Call::Getattr { id: 2 }, Ok(attr));
+
+    let mut context = testing::context(fs.clone());
+    let inspection = Arc::new(nfs::v3::ContentInspection::new(Scanner));
+    context.content_inspection = Some(inspection.clone());
+    let write = nfs3::file::WRITE3args {
+        file: context.id_to_fh(2).unwrap(),
+        offset: 0,
+        count: 5,
+        stable: nfs3::file::stable_how::FILE_SYNC as u32,
+        data: b"EICAR".to_vec(),
+    };
+    let mut reply = testing::call_nfs3(&context, NFSProgram::NFSPROC3_WRITE, &write).await.unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3ERR_ACCES as u32);
+    assert!(inspection.is_quarantined(2));
+
+    // the file cannot be read either, the file system never sees the calls
+    let read = nfs3::file::READ3args { file: context.id_to_fh(2).unwrap(), offset: 0, count: 8 };
+    let mut reply = testing::call_nfs3(&context, NFSProgram::NFSPROC3_READ, &read).await.unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3ERR_ACCES as u32);
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn events_publish_changes() {
+    let fs = Arc::new(MockFs::new());
+    let dir = fattr3 { ftype: nfs3::ftype3::NF3DIR, fileid: 3, ..Default::default() };
+    fs.stub(Call::Getattr { id: 1 }, Ok(fattr3 { fileid: 1, ..Default::default() }));
+    fs.expect(Call::Mkdir { dirid: 1, name: b"d".to_vec() }, Ok((3_u64, dir)));
+    fs.expect(
+        Call::Rename {
+            from_dirid: 1,
+            from_name: b"d".to_vec(),
+            to_dirid: 1,
+            to_name: b"e".to_vec(),
+        },
+        Ok(()),
+    );
+
+    let mut context = testing::context(fs.clone());
+    let events = Arc::new(nfs::events::Events::default());
+    context.events = Some(events.clone());
+    let mut receiver = events.subscribe();
+    let mkdir = nfs3::dir::MKDIR3args {
+        dirops: nfs3::diropargs3 { dir: context.id_to_fh(1).unwrap(), name: b"d".to_vec().into() },
+        attributes: nfs3::sattr3::default(),
+    };
+    testing::call_nfs3(&context, NFSProgram::NFSPROC3_MKDIR, &mkdir).await.unwrap();
+    let rename = nfs3::dir::RENAME3args {
+        from: nfs3::diropargs3 { dir: context.id_to_fh(1).unwrap(), name: b"d".to_vec().into() },
+        to: nfs3::diropargs3 { dir: context.id_to_fh(1).unwrap(), name: b"e".to_vec().into() },
+    };
+    testing::call_nfs3(&context, NFSProgram::NFSPROC3_RENAME, &rename).await.unwrap();
+
+    let created = receiver.recv().await.unwrap();
+    assert_eq!(created.client, context.client_addr);
+    let expected = nfs::events::EventKind::Created { dirid: 1, name: b"d".to_vec(), id: 3 };
+    assert_eq!(created.kind, expected);
+    assert!(matches!(
+        receiver.recv().await.unwrap().kind,
+        nfs::events::EventKind::Renamed { to_dirid: 1, .. }
+    ));
+    assert!(receiver.try_recv().is_err());
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn events_publish_writes_removals_and_mounts() {
+    use nfs::events::EventKind;
+
+    let fs = Arc::new(MockFs::new());
+    let attr = fattr3 { ftype: nfs3::ftype3::NF3REG, size: 5, fileid: 2, ..Default::default() };
+    fs.stub(Call::Getattr { id: 1 }, Ok(fattr3 { fileid: 1, ..Default::default() }));
+    fs.stub(Call::Getattr { id: 2 }, Ok(attr));
+    fs.expect(Call::Write { id: 2, offset: 0, data: b"hello".to_vec() }, Ok((attr, 5_u32)));
+    fs.expect(
+        Call::Remove { dirid: 1, name: b"a".to_vec() },
+        Err::<(), _>(nfsstat3::NFS3ERR_NOENT),
+    );
+    fs.expect(Call::Remove { dirid: 1, name: b"a".to_vec() }, Ok(()));
+
+    let mut context = testing::context(fs.clone());
+    let events = Arc::new(nfs::events::Events::default());
+    context.events = Some(events.clone());
+    let mut receiver = events.subscribe();
+
+    let write = nfs3::file::WRITE3args {
+        file: context.id_to_fh(2).unwrap(),
+        offset: 0,
+        count: 5,
+        stable: nfs3::file::stable_how::FILE_SYNC as u32,
+        data: b"hello".to_vec(),
+    };
+    testing::call_nfs3(&context, NFSProgram::NFSPROC3_WRITE, &write).await.unwrap();
+    let name = nfs3::diropargs3 { dir: context.id_to_fh(1).unwrap(), name: b"a".to_vec().into() };
+    // a failed call publishes nothing
+    for _ in 0..2 {
+        testing::call_nfs3(&context, NFSProgram::NFSPROC3_REMOVE, &name).await.unwrap();
+    }
+    for (proc, path) in [
+        (mount::MountProgram::MOUNTPROC3_MNT, Some(&b"/"[..])),
+        (mount::MountProgram::MOUNTPROC3_UMNT, Some(&b"/"[..])),
+        (mount::MountProgram::MOUNTPROC3_UMNTALL, None),
+    ] {
+        let mut call = testing::call_message(3, mount::PROGRAM, mount::VERSION, proc as u32, &());
+        if let Some(path) = path {
+            path.serialize(&mut call).unwrap();
+        }
+        rpc::process_message(&call, &mut Vec::new(), context.clone()).await.unwrap();
+    }
+
+    let mut published = Vec::new();
+    while let Ok(event) = receiver.try_recv() {
+        assert_eq!(event.client, context.client_addr);
+        published.push(event.kind);
+    }
+    assert_eq!(
+        published,
+        [
+            EventKind::Written { id: 2, offset: 0, count: 5 },
+            EventKind::Removed { dirid: 1, name: b"a".to_vec() },
+            EventKind::Mounted { path: b"/".to_vec() },
+            EventKind::Unmounted { path: Some(b"/".to_vec()) },
+            EventKind::Unmounted { path: None },
+        ]
+    );
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn replay_maps_recorded_handles() {
+    let fs = Arc::new(MockFs::new());
+    fs.expect(Call::Lookup { dirid: 1, name: b"a".to_vec() }, Ok(2_u64));
+    fs.stub(Call::Getattr { id: 1 }, Ok(fattr3 { fileid: 1, ..Default::default() }));
+    fs.stub(Call::Getattr { id: 2 }, Ok(fattr3 { fileid: 2, ..Default::default() }));
+    let context = testing::context(fs.clone());
+
+    // the recording server handed out another handle for the looked up file
+    let recorded = nfs3::nfs_fh3 { data: vec![0xaa; 8] };
+    let lookup = nfs3::diropargs3 { dir: context.id_to_fh(1).unwrap(), name: b"a".to_vec().into() };
+    let proc = |proc: NFSProgram| proc as u32;
+    let calls = [
+        testing::call_message(10, nfs3::PROGRAM, 3, proc(NFSProgram::NFSPROC3_LOOKUP), &lookup),
+        testing::call_message(11, nfs3::PROGRAM, 3, proc(NFSProgram::NFSPROC3_GETATTR), &recorded),
+    ];
+    let reply = |xid: u32, body: &[u8]| {
+        let header = [xid, 1, 0, 0, 0, 0, 0].into_iter().flat_map(|w| w.to_be_bytes());
+        header.chain(body.iter().copied()).collect::<Vec<u8>>()
+    };
+    let replies = [reply(10, &[&[0, 0, 0, 8][..], &recorded.data].concat()), reply(11, &[])];
+    let line = |direction: &str, data: &[u8]| {
+        let hex: String = data.iter().map(|b| format!("{b:02x}")).collect();
+        format!(
+            "{{\"time\":5.0,\"connection\":0,\"client\":\"127.0.0.1:700\",\
+             \"direction\":\"{direction}\",\"data\":\"{hex}\"}}\n"
+        )
+    };
+    let capture: String = calls
+        .iter()
+        .zip(&replies)
+        .flat_map(|(call, reply)| [line("call", call), line("reply", reply)])
+        .collect();
+
+    let records = rpc::read_capture(capture.as_bytes()).unwrap();
+    let mut replay = rpc::Replay::new(100);
+    let replayed = replay.run(&records, &context).await.unwrap();
+    assert_eq!(replayed.iter().map(|call| call.xid).collect::<Vec<_>>(), [100, 101]);
+    assert!(replayed.iter().all(|call| call.status == Some(0) && !call.diverged()));
+    assert_eq!(replay.clock().now(), UNIX_EPOCH + Duration::from_secs(5));
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn streamed_read_arrives_in_fragments_over_tcp() {
+    use nfs_mamont::tcp::{NFSTcp, NFSTcpListener};
+    use nfs_mamont::vfs::NFSFileSystem;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let fs = Arc::new(MockFs::new());
+    let attr = fattr3 { ftype: nfs3::ftype3::NF3REG, size: 10, fileid: 2, ..Default::default() };
+    fs.stub(Call::Getattr { id: 2 }, Ok(attr));
+    fs.expect(Call::Read { id: 2, offset: 0, count: 4 }, Ok((b"0123".to_vec(), false)));
+    fs.expect(Call::Read { id: 2, offset: 4, count: 4 }, Ok((b"4567".to_vec(), false)));
+    fs.expect(Call::Read { id: 2, offset: 8, count: 2 }, Ok((b"89".to_vec(), true)));
+    let mut listener = NFSTcpListener::bind_dyn("127.0.0.1:0", fs.clone()).await.unwrap();
+    listener.with_read_streaming(Some(4));
+    let port = listener.get_listen_port();
+    tokio::spawn(async move { listener.handle_forever().await });
+
+    let args = nfs3::file::READ3args { file: fs.id_to_fh(2), offset: 0, count: 32 };
+    let call = testing::call_message(
+        6,
+        nfs3::PROGRAM,
+        nfs3::VERSION,
+        NFSProgram::NFSPROC3_READ as u32,
+        &args,
+    );
+    let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    stream.write_all(&((1_u32 << 31) | call.len() as u32).to_be_bytes()).await.unwrap();
+    stream.write_all(&call).await.unwrap();
+
+    let mut record = Vec::new();
+    let mut fragments = 0;
+    loop {
+        let mark = stream.read_u32().await.unwrap();
+        let mut fragment = vec![0; (mark & !(1 << 31)) as usize];
+        stream.read_exact(&mut fragment).await.unwrap();
+        record.extend(fragment);
+        fragments += 1;
+        if mark & (1 << 31) != 0 {
+            break;
+        }
+    }
+    assert_eq!(fragments, 4);
+    let mut reply = &record[..];
+    assert_eq!(deserialize::<rpc_msg>(&mut reply).unwrap().xid, 6);
+    assert_eq!(deserialize::<u32>(&mut reply).unwrap(), nfsstat3::NFS3_OK as u32);
+    let read = deserialize::<nfs3::file::READ3resok>(&mut reply).unwrap();
+    assert_eq!((read.count, read.eof), (10, true));
+    assert_eq!(read.data, b"0123456789");
+    assert!(reply.is_empty());
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn client_round_trips_against_the_server() {
+    use nfs_mamont::client::Client;
+    use nfs_mamont::tcp::{NFSTcp, NFSTcpListener};
+
+    let fs = Arc::new(MockFs::new());
+    let dir = fattr3 { ftype: nfs3::ftype3::NF3DIR, fileid: 2, ..Default::default() };
+    let file = fattr3 { ftype: nfs3::ftype3::NF3REG, size: 10, fileid: 3, ..Default::default() };
+    fs.stub(Call::Getattr { id: 1 }, Ok(fattr3 { fileid: 1, ..dir }));
+    fs.stub(Call::Getattr { id: 2 }, Ok(dir));
+    fs.stub(Call::Getattr { id: 3 }, Ok(file));
+    fs.expect(Call::Mkdir { dirid: 1, name: b"d".to_vec() }, Ok((2_u64, dir)));
+    fs.expect(Call::Create { dirid: 2, name: b"f".to_vec() }, Ok((3_u64, file)));
+    fs.expect(Call::Write { id: 3, offset: 0, data: b"0123456789".to_vec() }, Ok(file));
+    fs.expect(Call::Commit { id: 3, offset: 0, count: 0 }, Ok(file));
+    fs.expect(Call::Read { id: 3, offset: 0, count: 4 }, Ok((b"0123".to_vec(), false)));
+    fs.expect(Call::Read { id: 3, offset: 4, count: 4 }, Ok((b"4567".to_vec(), false)));
+    fs.expect(Call::Read { id: 3, offset: 8, count: 2 }, Ok((b"89".to_vec(), true)));
+    let rename = Call::Rename {
+        from_dirid: 2,
+        from_name: b"f".to_vec(),
+        to_dirid: 1,
+        to_name: b"g".to_vec(),
+    };
+    fs.expect(rename, Ok(()));
+    fs.expect(Call::Remove { dirid: 1, name: b"g".to_vec() }, Ok(()));
+    fs.expect(Call::Remove { dirid: 1, name: b"d".to_vec() }, Ok(()));
+    let mut listener = NFSTcpListener::bind_dyn("127.0.0.1:0", fs.clone()).await.unwrap();
+    // the read below is streamed, so the client reassembles its fragments
+    listener.with_read_streaming(Some(4));
+    let port = listener.get_listen_port();
+    tokio::spawn(async move { listener.handle_forever().await });
+    let client = Client::connect(&format!("127.0.0.1:{port}")).await.unwrap();
+
+    let root = client.mount(b"/").await.unwrap();
+    let (d, _) = client.mkdir(&root, &b"d"[..].into(), nfs3::sattr3::default()).await.unwrap();
+    let d = d.unwrap();
+    let (f, attr) =
+        client.create(&d, &b"f"[..].into(), nfs3::sattr3::default(), None).await.unwrap();
+    assert_eq!(attr.map(|attr| attr.fileid), Some(3));
+    let f = f.unwrap();
+    let unstable = nfs3::file::stable_how::UNSTABLE;
+    let written = client.write(&f, 0, b"0123456789", unstable).await.unwrap();
+    let committed = client.commit(&f, 0, 0).await.unwrap();
+    assert_eq!(committed.verf, written.verf);
+    let read = client.read(&f, 0, 32).await.unwrap();
+    assert_eq!((&read.data[..], read.eof), (&b"0123456789"[..], true));
+    client.rename(&d, &b"f"[..].into(), &root, &b"g"[..].into()).await.unwrap();
+    client.remove(&root, &b"g"[..].into()).await.unwrap();
+    client.rmdir(&root, &b"d"[..].into()).await.unwrap();
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn streamed_read_continues_short_reads() {
+    let fs = Arc::new(MockFs::new());
+    let attr = fattr3 { ftype: nfs3::ftype3::NF3REG, size: 8, fileid: 2, ..Default::default() };
+    fs.stub(Call::Getattr { id: 2 }, Ok(attr));
+    fs.expect(Call::Read { id: 2, offset: 0, count: 4 }, Ok((b"01".to_vec(), false)));
+    fs.expect(Call::Read { id: 2, offset: 2, count: 2 }, Ok((b"23".to_vec(), false)));
+    fs.expect(Call::Read { id: 2, offset: 4, count: 4 }, Ok((b"4567".to_vec(), true)));
+
+    let mut context = testing::context(fs.clone());
+    context.read_ahead = None;
+    let stream = ReplyStream::new(4);
+    context.reply_stream = Some(stream.clone());
+    let args = nfs3::file::READ3args { file: context.id_to_fh(2).unwrap(), offset: 0, count: 8 };
+    let call = testing::call_message(
+        1,
+        nfs3::PROGRAM,
+        nfs3::VERSION,
+        NFSProgram::NFSPROC3_READ as u32,
+        &args,
+    );
+    let mut data = Vec::new();
+    rpc::process_message(&call, &mut data, context).await.unwrap();
+    let mut body = stream.take().unwrap();
+    let mut streamed = Vec::new();
+    while let Some(chunk) = body.next_chunk().await.unwrap() {
+        streamed.extend(chunk);
+    }
+    assert_eq!(streamed, b"01234567");
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn failed_commit_changes_the_write_verifier() {
+    let fs = Arc::new(MockFs::new());
+    let attr = fattr3 { ftype: nfs3::ftype3::NF3REG, size: 5, fileid: 2, ..Default::default() };
+    fs.stub(Call::Getattr { id: 2 }, Ok(attr));
+    fs.stub(Call::Write { id: 2, offset: 0, data: b"hello".to_vec() }, Ok((attr, 5_u32)));
+    fs.expect(Call::Commit { id: 2, offset: 0, count: 0 }, Ok(attr));
+    fs.expect(Call::Commit { id: 2, offset: 0, count: 0 }, Err::<fattr3, _>(nfsstat3::NFS3ERR_IO));
+
+    let context = testing::context(fs.clone());
+    let write = nfs3::file::WRITE3args {
+        file: context.id_to_fh(2).unwrap(),
+        offset: 0,
+        count: 5,
+        stable: nfs3::file::stable_how::UNSTABLE as u32,
+        data: b"hello".to_vec(),
+    };
+    let commit =
+        nfs3::file::COMMIT3args { file: context.id_to_fh(2).unwrap(), offset: 0, count: 0 };
+
+    let mut reply = testing::call_nfs3(&context, NFSProgram::NFSPROC3_WRITE, &write).await.unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+    let written = deserialize::<nfs3::file::WRITE3resok>(&mut reply).unwrap();
+    assert_eq!(written.committed as u32, nfs3::file::stable_how::UNSTABLE as u32);
+    assert_eq!(context.unstable_writes.uncommitted(2), [(0, 5)]);
+
+    let mut reply =
+        testing::call_nfs3(&context, NFSProgram::NFSPROC3_COMMIT, &commit).await.unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+    let committed = deserialize::<nfs3::file::COMMIT3resok>(&mut reply).unwrap();
+    assert_eq!(committed.verf, written.verf);
+    assert!(context.unstable_writes.uncommitted(2).is_empty());
+
+    testing::call_nfs3(&context, NFSProgram::NFSPROC3_WRITE, &write).await.unwrap();
+    let mut reply =
+        testing::call_nfs3(&context, NFSProgram::NFSPROC3_COMMIT, &commit).await.unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3ERR_IO as u32);
+    assert_ne!(context.unstable_writes.verifier(context.vfs.server_id()), written.verf);
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn remove_forgets_uncommitted_ranges() {
+    let fs = Arc::new(MockFs::new());
+    let attr =
+        fattr3 { ftype: nfs3::ftype3::NF3REG, size: 5, fileid: 2, nlink: 1, ..Default::default() };
+    fs.stub(Call::Getattr { id: 1 }, Ok(fattr3 { fileid: 1, ..Default::default() }));
+    fs.stub(Call::Getattr { id: 2 }, Ok(attr));
+    fs.stub(Call::Lookup { dirid: 1, name: b"a".to_vec() }, Ok(2_u64));
+    fs.stub(Call::Write { id: 2, offset: 0, data: b"hello".to_vec() }, Ok((attr, 5_u32)));
+    fs.expect(Call::Remove { dirid: 1, name: b"a".to_vec() }, Ok(()));
+
+    let context = testing::context(fs.clone());
+    let write = nfs3::file::WRITE3args {
+        file: context.id_to_fh(2).unwrap(),
+        offset: 0,
+        count: 5,
+        stable: nfs3::file::stable_how::UNSTABLE as u32,
+        data: b"hello".to_vec(),
+    };
+    testing::call_nfs3(&context, NFSProgram::NFSPROC3_WRITE, &write).await.unwrap();
+    assert_eq!(context.unstable_writes.uncommitted(2), [(0, 5)]);
+    let verf = context.unstable_writes.verifier(context.vfs.server_id());
+
+    let name = nfs3::diropargs3 { dir: context.id_to_fh(1).unwrap(), name: b"a".to_vec().into() };
+    let mut reply = testing::call_nfs3(&context, NFSProgram::NFSPROC3_REMOVE, &name).await.unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+    assert!(context.unstable_writes.is_empty());
+    assert_eq!(context.unstable_writes.verifier(context.vfs.server_id()), verf);
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn fsstat_reports_accounted_space() {
+    let fs = Arc::new(MockFs::new());
+    let before = fattr3 { ftype: nfs3::ftype3::NF3REG, size: 0, fileid: 2, ..Default::default() };
+    let after = fattr3 { size: 5, ..before };
+    fs.expect(Call::Getattr { id: 2 }, Ok(before));
+    fs.expect(Call::Write { id: 2, offset: 0, data: b"hello".to_vec() }, Ok((after, 5_u32)));
+    fs.stub(Call::Getattr { id: 1 }, Ok(fattr3 { fileid: 1, ..Default::default() }));
+
+    let mut context = testing::context(fs.clone());
+    context.space_counter = Some(Arc::new(SpaceCounter::new(100, 10).with_usage(20, 3)));
+    let args = nfs3::file::WRITE3args {
+        file: context.id_to_fh(2).unwrap(),
+        offset: 0,
+        count: 5,
+        stable: nfs3::file::stable_how::FILE_SYNC as u32,
+        data: b"hello".to_vec(),
+    };
+    testing::call_nfs3(&context, NFSProgram::NFSPROC3_WRITE, &args).await.unwrap();
+
+    let root = context.id_to_fh(1).unwrap();
+    let mut reply = testing::call_nfs3(&context, NFSProgram::NFSPROC3_FSSTAT, &root).await.unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+    let res = deserialize::<nfs3::fs::FSSTAT3resok>(&mut reply).unwrap();
+    assert_eq!((res.tbytes, res.fbytes, res.abytes), (100, 75, 75));
+    assert_eq!((res.tfiles, res.ffiles, res.afiles), (10, 7, 7));
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn client_quirks_cap_announced_transfer_sizes() {
+    let fs = Arc::new(MockFs::new());
+    fs.stub(Call::Getattr { id: 1 }, Ok(fattr3 { fileid: 1, ..Default::default() }));
+    let mut context = testing::context(fs.clone());
+    let root = context.id_to_fh(1).unwrap();
+
+    let mut reply = testing::call_nfs3(&context, NFSProgram::NFSPROC3_FSINFO, &root).await.unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+    let regular = deserialize::<nfs3::fs::fsinfo3>(&mut reply).unwrap();
+    assert_eq!(regular.rtmax, 1024 * 1024);
+
+    context.quirks = rpc::ClientQuirks::SMALL_TRANSFERS;
+    let mut reply = testing::call_nfs3(&context, NFSProgram::NFSPROC3_FSINFO, &root).await.unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+    let capped = deserialize::<nfs3::fs::fsinfo3>(&mut reply).unwrap();
+    assert_eq!((capped.rtmax, capped.wtmax, capped.dtpref), (64 * 1024, 64 * 1024, 64 * 1024));
+    assert_eq!(capped.rtpref, regular.rtpref.min(64 * 1024));
+}
+
+#[tokio::test]
+async fn oversized_records_get_garbage_args() {
+    let fs = Arc::new(MockFs::new());
+    fs.stub(Call::Getattr { id: 1 }, Ok(fattr3 { fileid: 1, ..Default::default() }));
+    let mut context = testing::context(fs.clone());
+    context.max_record_length = 64 * 1024 + rpc::RECORD_OVERHEAD;
+    let root = context.id_to_fh(1).unwrap();
+
+    let mut reply = testing::call_nfs3(&context, NFSProgram::NFSPROC3_FSINFO, &root).await.unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+    let fsinfo = deserialize::<nfs3::fs::fsinfo3>(&mut reply).unwrap();
+    assert_eq!((fsinfo.rtmax, fsinfo.wtmax), (64 * 1024, 64 * 1024));
+
+    let args = nfs3::file::WRITE3args {
+        file: root,
+        offset: 0,
+        count: 128 * 1024,
+        stable: nfs3::file::stable_how::UNSTABLE as u32,
+        data: vec![0; 128 * 1024],
+    };
+    let call = testing::call_message(9, nfs3::PROGRAM, nfs3::VERSION, 7, &args);
+    let mut reply = Vec::new();
+    assert!(rpc::process_message(&call, &mut reply, context).await.unwrap());
+    let reply = deserialize::<rpc_msg>(&mut &reply[..]).unwrap();
+    assert_eq!(reply.xid, 9);
+    let rpc_body::REPLY(reply_body::MSG_ACCEPTED(accepted)) = reply.body else {
+        panic!("call was not accepted: {reply:?}");
+    };
+    assert!(matches!(accepted.reply_data, accept_body::GARBAGE_ARGS));
+    assert!(fs.calls().iter().all(|call| matches!(call, Call::Getattr { .. })));
+}
+
+/// Encodes an `NFSv3` call with the given credentials
+fn call_with_credentials(proc: NFSProgram, cred: opaque_auth, args: &impl Serialize) -> Vec<u8> {
+    let body = rpc_body::CALL(call_body {
+        rpcvers: 2,
+        prog: nfs3::PROGRAM,
+        vers: nfs3::VERSION,
+        proc: proc as u32,
+        cred,
+        verf: opaque_auth::default(),
+    });
+    let mut call = Vec::new();
+    rpc_msg { xid: 3, body }.serialize(&mut call).unwrap();
+    args.serialize(&mut call).unwrap();
+    call
+}
+
+/// Passes every call on, which keeps calls off the fast path
+struct PassThrough;
+
+impl DispatchHook for PassThrough {}
+
+#[tokio::test]
+async fn fast_path_getattr_replies_like_the_generic_path() {
+    let fs = Arc::new(MockFs::new());
+    let attr = fattr3 { ftype: nfs3::ftype3::NF3DIR, fileid: 1, size: 4096, ..Default::default() };
+    fs.stub(Call::Getattr { id: 1 }, Ok(attr));
+    fs.stub(Call::Getattr { id: 2 }, Err::<fattr3, _>(nfsstat3::NFS3ERR_IO));
+    let mut credentials = Vec::new();
+    auth_unix { uid: 1000, gid: 100, ..Default::default() }.serialize(&mut credentials).unwrap();
+    let unix = opaque_auth { flavor: auth_flavor::AUTH_UNIX, body: credentials };
+
+    for subtree_check in [false, true] {
+        // fresh contexts, so neither call is taken for a retransmission
+        let contexts = || {
+            let mut fast = testing::context(fs.clone());
+            fast.subtree_check = subtree_check;
+            let mut generic = testing::context(fs.clone());
+            generic.subtree_check = subtree_check;
+            let mut hooks = DispatchHooks::default();
+            hooks.push(Arc::new(PassThrough));
+            generic.hooks = Arc::new(hooks);
+            (fast, generic)
+        };
+
+        let context = contexts().0;
+        let mut forged = context.id_to_fh(1).unwrap();
+        *forged.data.last_mut().unwrap() ^= 1;
+        let handles = [context.id_to_fh(1).unwrap(), context.id_to_fh(2).unwrap(), forged];
+        for handle in handles {
+            for call in [
+                testing::call_message(7, nfs3::PROGRAM, nfs3::VERSION, 1, &handle),
+                call_with_credentials(NFSProgram::NFSPROC3_GETATTR, unix.clone(), &handle),
+            ] {
+                let (fast, generic) = contexts();
+                let (mut fast_reply, mut generic_reply) = (Vec::new(), Vec::new());
+                assert!(rpc::process_message(&call, &mut fast_reply, fast).await.unwrap());
+                assert!(rpc::process_message(&call, &mut generic_reply, generic).await.unwrap());
+                assert_eq!(fast_reply, generic_reply, "subtree_check: {subtree_check}");
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn short_auth_handles_stand_for_unix_credentials() {
+    let fs = Arc::new(MockFs::new());
+    fs.stub(Call::Getattr { id: 1 }, Ok(fattr3 { fileid: 1, ..Default::default() }));
+    let mut context = testing::context(fs.clone());
+    context.short_auth = Some(Arc::default());
+    let root = context.id_to_fh(1).unwrap();
+
+    let mut credentials = Vec::new();
+    auth_unix { uid: 1000, gid: 100, ..Default::default() }.serialize(&mut credentials).unwrap();
+    let unix = opaque_auth { flavor: auth_flavor::AUTH_UNIX, body: credentials };
+    let call = call_with_credentials(NFSProgram::NFSPROC3_GETATTR, unix, &root);
+    let mut reply = Vec::new();
+    rpc::process_message(&call, &mut reply, context.clone()).await.unwrap();
+    let rpc_body::REPLY(reply_body::MSG_ACCEPTED(accepted)) =
+        deserialize::<rpc_msg>(&mut &reply[..]).unwrap().body
+    else {
+        panic!("call was not accepted");
+    };
+    assert!(matches!(accepted.verf.flavor, auth_flavor::AUTH_SHORT));
+
+    let short = opaque_auth { flavor: auth_flavor::AUTH_SHORT, body: accepted.verf.body };
+    let call = call_with_credentials(NFSProgram::NFSPROC3_GETATTR, short, &root);
+    let mut reply = Vec::new();
+    rpc::process_message(&call, &mut reply, context.clone()).await.unwrap();
+    let request = fs.requests().pop().unwrap().unwrap();
+    assert_eq!((request.auth.uid, request.auth.gid), (1000, 100));
+
+    let unknown = opaque_auth { flavor: auth_flavor::AUTH_SHORT, body: vec![0; 8] };
+    let call = call_with_credentials(NFSProgram::NFSPROC3_GETATTR, unknown, &root);
+    let mut reply = Vec::new();
+    rpc::process_message(&call, &mut reply, context).await.unwrap();
+    assert!(matches!(
+        deserialize::<rpc_msg>(&mut &reply[..]).unwrap().body,
+        rpc_body::REPLY(reply_body::MSG_DENIED(rejected_reply::AUTH_ERROR(
+            auth_stat::AUTH_REJECTEDCRED
+        )))
+    ));
+    assert_eq!(fs.calls().len(), 2);
+}
+
+#[tokio::test]
+async fn scheduled_calls_wait_for_a_slot() {
+    let fs = Arc::new(MockFs::new());
+    fs.stub(Call::Getattr { id: 1 }, Ok(fattr3 { fileid: 1, ..Default::default() }));
+    let scheduler = Arc::new(rpc::Scheduler::new(1));
+    let mut context = testing::context(fs.clone());
+    context.lane = Some(Arc::new(scheduler.lane(1)));
+    let root = context.id_to_fh(1).unwrap();
+
+    let permit = scheduler.lane(1).acquire().await;
+    let getattr = tokio::spawn({
+        let context = context.clone();
+        async move { testing::call_nfs3(&context, NFSProgram::NFSPROC3_GETATTR, &root).await }
+    });
+    tokio::task::yield_now().await;
+    assert!(fs.calls().is_empty(), "GETATTR must wait for a slot");
+    assert_eq!(scheduler.waiting(), 1);
+
+    drop(permit);
+    let mut reply = getattr.await.unwrap().unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+    assert_eq!(scheduler.running(), 0);
+}
+
+#[tokio::test]
+async fn disabled_readdirplus_is_refused_before_backend() {
+    let fs = Arc::new(MockFs::new());
+    let mut context = testing::context(fs.clone());
+    context.readdirplus = false;
+    let args = nfs3::dir::READDIRPLUS3args {
+        dir: context.id_to_fh(1).unwrap(),
+        cookie: 0,
+        cookieverf: nfs3::cookieverf3::default(),
+        dircount: 4096,
+        maxcount: 16384,
+    };
+    let mut reply =
+        testing::call_nfs3(&context, NFSProgram::NFSPROC3_READDIRPLUS, &args).await.unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3ERR_NOTSUPP as u32);
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn removing_a_file_in_use_hides_it_until_idle() {
+    let fs = Arc::new(MockFs::new().with_capabilities(Capabilities::ReadWrite));
+    let file = fattr3 {
+        ftype: nfs3::ftype3::NF3REG,
+        fileid: 2,
+        nlink: 1,
+        size: 100,
+        ..Default::default()
+    };
+    let hidden = b".nfs-mamont-deleted.2.0".to_vec();
+    fs.stub(Call::Getattr { id: 1 }, Ok(fattr3 { fileid: 1, ..Default::default() }));
+    fs.stub(Call::Getattr { id: 2 }, Ok(file));
+    fs.expect(Call::Lookup { dirid: 1, name: b"tmp".to_vec() }, Ok(2_u64));
+    let rename = Call::Rename {
+        from_dirid: 1,
+        from_name: b"tmp".to_vec(),
+        to_dirid: 1,
+        to_name: hidden.clone(),
+    };
+    fs.expect(rename, Ok(()));
+
+    let clock = Arc::new(ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_000)));
+    let mut context = testing::context(fs.clone());
+    context.clock = clock.clone();
+    let soft_delete = Arc::new(nfs::v3::SoftDelete::new(nfs::v3::SoftDeleteConfig {
+        idle: Duration::from_secs(10),
+        ..Default::default()
+    }));
+    context.soft_delete = Some(soft_delete.clone());
+    let counter = Arc::new(SpaceCounter::new(1_000, 10).with_usage(200, 2));
+    context.space_counter = Some(counter.clone());
+    soft_delete.record_io(2, clock.now());
+
+    let args = nfs3::diropargs3 { dir: context.id_to_fh(1).unwrap(), name: b"tmp".to_vec().into() };
+    let mut reply = testing::call_nfs3(&context, NFSProgram::NFSPROC3_REMOVE, &args).await.unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+    assert_eq!(soft_delete.hidden(), 1);
+    // the data of the hidden file still takes up space
+    assert_eq!((counter.used(), counter.files()), (200, 2));
+    fs.assert_done();
+
+    // the next removal purges the hidden file once it is idle
+    clock.advance(Duration::from_secs(10));
+    fs.expect(Call::Remove { dirid: 1, name: hidden }, Ok(()));
+    fs.expect(Call::Lookup { dirid: 1, name: b"old".to_vec() }, Ok(3_u64));
+    fs.expect(Call::Getattr { id: 3 }, Ok(fattr3 { fileid: 3, ..file }));
+    fs.expect(Call::Remove { dirid: 1, name: b"old".to_vec() }, Ok(()));
+    let args = nfs3::diropargs3 { dir: context.id_to_fh(1).unwrap(), name: b"old".to_vec().into() };
+    let mut reply = testing::call_nfs3(&context, NFSProgram::NFSPROC3_REMOVE, &args).await.unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+    assert_eq!(soft_delete.hidden(), 0);
+    assert_eq!((counter.used(), counter.files()), (0, 0));
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn listings_skip_batches_of_files_hidden_instead_of_removed() {
+    use nfs_mamont::vfs::{DirEntry, ReadDirCookie, ReadDirResult};
+
+    let fs = Arc::new(MockFs::new());
+    let mut context = testing::context(fs.clone());
+    context.soft_delete = Some(Arc::default());
+    let entry = |fileid, name: Vec<u8>| DirEntry {
+        fileid,
+        name: name.into(),
+        attr: fattr3 { fileid, ..Default::default() },
+    };
+    fs.stub(Call::Getattr { id: 1 }, Ok(fattr3 { fileid: 1, ..Default::default() }));
+    let hidden: Vec<_> = (10..42)
+        .map(|id| entry(id, format!(".nfs-mamont-deleted.{id:x}.0").into_bytes()))
+        .collect();
+    let batches = [
+        (ReadDirCookie::Start, ReadDirResult { entries: hidden, end: false }),
+        (ReadDirCookie::AfterFileId(41), ReadDirResult { entries: vec![], end: true }),
+    ];
+    for (cookie, batch) in batches {
+        fs.stub(Call::ReadDir { dirid: 1, cookie, max_entries: 32 }, Ok(batch));
+    }
+
+    let args = nfs3::dir::READDIR3args {
+        dir: context.id_to_fh(1).unwrap(),
+        cookie: 0,
+        cookieverf: nfs3::cookieverf3::default(),
+        dircount: 512,
+    };
+    let mut reply =
+        testing::call_nfs3(&context, NFSProgram::NFSPROC3_READDIR, &args).await.unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+    let listing = deserialize::<nfs3::dir::READDIR3resok>(&mut reply).unwrap().reply;
+    assert!(listing.entries.is_empty());
+    assert!(listing.eof, "a listing of hidden files only must end");
+}
+
+#[tokio::test]
+async fn rmdir_removes_hidden_files_left_in_the_directory() {
+    let fs = Arc::new(MockFs::new());
+    let dir = fattr3 { ftype: nfs3::ftype3::NF3DIR, fileid: 5, ..Default::default() };
+    fs.stub(Call::Getattr { id: 1 }, Ok(fattr3 { fileid: 1, ..Default::default() }));
+    fs.stub(Call::Getattr { id: 5 }, Ok(dir));
+    fs.expect(Call::Lookup { dirid: 1, name: b"dir".to_vec() }, Ok(5_u64));
+    fs.expect(
+        Call::Remove { dirid: 1, name: b"dir".to_vec() },
+        Err::<(), _>(nfsstat3::NFS3ERR_NOTEMPTY),
+    );
+    // hidden before a restart, so not tracked by this server
+    let hidden = b".nfs-mamont-deleted.6.0".to_vec();
+    let listing = nfs_mamont::vfs::ReadDirResult {
+        entries: vec![nfs_mamont::vfs::DirEntry {
+            fileid: 6,
+            name: hidden.clone().into(),
+            attr: fattr3 { fileid: 6, ..Default::default() },
+        }],
+        end: true,
+    };
+    let start = nfs_mamont::vfs::ReadDirCookie::Start;
+    fs.expect(Call::ReadDir { dirid: 5, cookie: start, max_entries: 1024 }, Ok(listing));
+    fs.expect(Call::Remove { dirid: 5, name: hidden }, Ok(()));
+    fs.expect(Call::Remove { dirid: 1, name: b"dir".to_vec() }, Ok(()));
+
+    let mut context = testing::context(fs.clone());
+    context.soft_delete = Some(Arc::default());
+    let args = nfs3::diropargs3 { dir: context.id_to_fh(1).unwrap(), name: b"dir".to_vec().into() };
+    let mut reply = testing::call_nfs3(&context, NFSProgram::NFSPROC3_RMDIR, &args).await.unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn public_filehandle_looks_up_paths() {
+    let fs = Arc::new(MockFs::new().with_root(1));
+    fs.stub(Call::Getattr { id: 1 }, Ok(fattr3 { fileid: 1, ..Default::default() }));
+    fs.stub(Call::Getattr { id: 5 }, Ok(fattr3 { fileid: 5, ..Default::default() }));
+    fs.stub(Call::Getattr { id: 6 }, Ok(fattr3 { fileid: 6, ..Default::default() }));
+    fs.expect(Call::Lookup { dirid: 1, name: b"docs".to_vec() }, Ok(5_u64));
+    fs.expect(Call::Lookup { dirid: 5, name: b"a".to_vec() }, Ok(6_u64));
+
+    let mut context = testing::context(fs.clone());
+    let public = nfs3::nfs_fh3::default();
+    let lookup = |name: &[u8]| nfs3::diropargs3 { dir: public.clone(), name: name.to_vec().into() };
+    let mut reply =
+        testing::call_nfs3(&context, NFSProgram::NFSPROC3_LOOKUP, &lookup(b"docs")).await.unwrap();
+    assert_ne!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+
+    context.public_fh = true;
+    let mut reply = testing::call_nfs3(&context, NFSProgram::NFSPROC3_LOOKUP, &lookup(b"/docs//a"))
+        .await
+        .unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+    let handle = deserialize::<nfs3::nfs_fh3>(&mut reply).unwrap();
+    assert_eq!(context.fh_to_id(&handle).ok(), Some(6));
+    fs.assert_done();
+
+    // an empty path names the public directory itself
+    let mut reply =
+        testing::call_nfs3(&context, NFSProgram::NFSPROC3_LOOKUP, &lookup(b"/")).await.unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+    let handle = deserialize::<nfs3::nfs_fh3>(&mut reply).unwrap();
+    assert_eq!(context.fh_to_id(&handle).ok(), Some(1));
+}
+
+#[tokio::test]
+async fn mount_advertises_and_enforces_nfs_flavors() {
+    let fs = Arc::new(MockFs::new());
+    let mut context = testing::context(fs.clone());
+    let mut policy = rpc::AuthPolicy::new();
+    policy.require(nfs3::PROGRAM, &[auth_flavor::RPCSEC_GSS_KRB5I, auth_flavor::AUTH_UNIX]);
+    context.auth_policy = Arc::new(policy);
+
+    let mount = |cred: opaque_auth| {
+        let context = context.clone();
+        async move {
+            let body = rpc_body::CALL(call_body {
+                rpcvers: 2,
+                prog: mount::PROGRAM,
+                vers: mount::VERSION,
+                proc: mount::MountProgram::MOUNTPROC3_MNT as u32,
+                cred,
+                verf: opaque_auth::default(),
+            });
+            let mut call = Vec::new();
+            rpc_msg { xid: 4, body }.serialize(&mut call).unwrap();
+            b"/".to_vec().serialize(&mut call).unwrap();
+            let mut reply = Vec::new();
+            rpc::process_message(&call, &mut reply, context).await.unwrap();
+            let mut reply = &reply[..];
+            deserialize::<rpc_msg>(&mut reply).unwrap();
+            let status = deserialize::<u32>(&mut reply).unwrap();
+            (status, reply.to_vec())
+        }
+    };
+
+    let mut credentials = Vec::new();
+    auth_unix::default().serialize(&mut credentials).unwrap();
+    let (status, rest) =
+        mount(opaque_auth { flavor: auth_flavor::AUTH_UNIX, body: credentials }).await;
+    assert_eq!(status, mount::mountstat3::MNT3_OK as u32);
+    let mut rest = rest.as_slice();
+    deserialize::<Vec<u8>>(&mut rest).unwrap();
+    let flavors = deserialize::<Vec<u32>>(&mut rest).unwrap();
+    assert_eq!(flavors, [390004, auth_flavor::AUTH_UNIX as u32]);
+
+    let (status, _) = mount(opaque_auth::default()).await;
+    assert_eq!(status, mount::mountstat3::MNT3ERR_ACCES as u32);
+}
+
+#[tokio::test]
+async fn dump_lists_mounts_until_unmounted() {
+    let fs = Arc::new(MockFs::new());
+    let mut context = testing::context(fs.clone());
+    let (signal, mut signals) = tokio::sync::mpsc::channel(8);
+    context.mount_signal = Some(signal);
+
+    let call = |proc: mount::MountProgram, path: Option<&[u8]>| {
+        let context = context.clone();
+        let mut credentials = Vec::new();
+        auth_unix::default().serialize(&mut credentials).unwrap();
+        let body = rpc_body::CALL(call_body {
+            rpcvers: 2,
+            prog: mount::PROGRAM,
+            vers: mount::VERSION,
+            proc: proc as u32,
+            cred: opaque_auth { flavor: auth_flavor::AUTH_UNIX, body: credentials },
+            verf: opaque_auth::default(),
+        });
+        let mut call = Vec::new();
+        rpc_msg { xid: 4, body }.serialize(&mut call).unwrap();
+        if let Some(path) = path {
+            path.serialize(&mut call).unwrap();
+        }
+        async move {
+            let mut reply = Vec::new();
+            rpc::process_message(&call, &mut reply, context).await.unwrap();
+            let mut reply = &reply[..];
+            deserialize::<rpc_msg>(&mut reply).unwrap();
+            reply.to_vec()
+        }
+    };
+    let dump = || async {
+        let reply = call(mount::MountProgram::MOUNTPROC3_DUMP, None).await;
+        let mut reply = reply.as_slice();
+        let mut mounts = Vec::new();
+        while deserialize::<bool>(&mut reply).unwrap() {
+            let host = deserialize::<Vec<u8>>(&mut reply).unwrap();
+            mounts.push((host, deserialize::<Vec<u8>>(&mut reply).unwrap()));
+        }
+        mounts
+    };
+
+    assert!(dump().await.is_empty());
+    for _ in 0..2 {
+        call(mount::MountProgram::MOUNTPROC3_MNT, Some(b"/")).await;
+    }
+    assert_eq!(dump().await, [(b"127.0.0.1".to_vec(), b"/".to_vec())]);
+    // a repeated mount is no new mount
+    assert_eq!(signals.recv().await, Some(true));
+    assert!(signals.try_recv().is_err());
+
+    call(mount::MountProgram::MOUNTPROC3_UMNT, Some(b"/")).await;
+    assert!(dump().await.is_empty());
+    assert_eq!(signals.recv().await, Some(false));
+    call(mount::MountProgram::MOUNTPROC3_UMNT, Some(b"/")).await;
+    assert!(signals.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn fast_path_getattr_keeps_mounts_alive() {
+    let fs = Arc::new(MockFs::new());
+    fs.stub(Call::Getattr { id: 1 }, Ok(fattr3 { fileid: 1, ..Default::default() }));
+    let mut context = testing::context(fs.clone());
+    let liveness = nfs::mount_table::MountLivenessConfig {
+        idle_timeout: Duration::from_secs(60),
+        check_interval: Duration::from_secs(1),
+    };
+    context.mount_table = Arc::new(nfs::mount_table::MountTable::new(Some(liveness)));
+    let mounted_at = context.clock.now();
+    context.mount_table.mounted(context.client_addr, b"/", mounted_at);
+
+    let call =
+        testing::call_message(5, nfs3::PROGRAM, nfs3::VERSION, 1, &context.id_to_fh(1).unwrap());
+    let mut reply = Vec::new();
+    let clock = ManualClock::new(mounted_at + Duration::from_secs(50));
+    context.clock = Arc::new(clock);
+    rpc::process_message(&call, &mut reply, context.clone()).await.unwrap();
+    assert!(context.mount_table.expire(mounted_at + Duration::from_secs(100)).is_empty());
+    assert_eq!(context.mount_table.len(), 1);
+}
+
+#[tokio::test]
+async fn subtree_check_rejects_forged_handles() {
+    let mut context = testing::context(Arc::new(MockFs::new()));
+    context.subtree_check = true;
+    let issued = context.id_to_fh(2).unwrap();
+    assert_eq!(context.fh_to_id(&issued).ok(), Some(2));
+
+    // a client knows the export name and the handle layout, but not the key
+    let mut forged = context.vfs.id_to_fh(3);
+    let unkeyed = context.export_name.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    forged.data.extend_from_slice(&unkeyed.to_be_bytes());
+    assert!(matches!(context.fh_to_id(&forged), Err(nfsstat3::NFS3ERR_STALE)));
+
+    let other_export = rpc::Context { export_name: Arc::new("/other".to_string()), ..context };
+    assert!(matches!(other_export.fh_to_id(&issued), Err(nfsstat3::NFS3ERR_STALE)));
+}
+
+#[tokio::test]
+async fn subtree_check_refuses_handles_beyond_fhsize() {
+    let fs = Arc::new(MockFs::new().with_handle_len(60));
+    fs.stub(Call::Getattr { id: 1 }, Ok(fattr3 { fileid: 1, ..Default::default() }));
+    fs.stub(Call::Getattr { id: 2 }, Ok(fattr3 { fileid: 2, ..Default::default() }));
+    fs.stub(Call::Lookup { dirid: 1, name: b"a".to_vec() }, Ok(2_u64));
+    let mut context = testing::context(fs.clone());
+    assert_eq!(context.id_to_fh(2).unwrap().data.len(), 60);
+    context.subtree_check = true;
+    assert!(matches!(context.id_to_fh(2), Err(nfsstat3::NFS3ERR_SERVERFAULT)));
+
+    // the client is never handed a handle it could not send back; the public
+    // handle reaches the root without one
+    context.public_fh = true;
+    let name = nfs3::diropargs3 { dir: nfs3::nfs_fh3::default(), name: b"a".to_vec().into() };
+    let mut reply = testing::call_nfs3(&context, NFSProgram::NFSPROC3_LOOKUP, &name).await.unwrap();
+    let status = testing::status(&mut reply).unwrap();
+    assert_eq!(status as u32, nfsstat3::NFS3ERR_SERVERFAULT as u32);
+}
+
+#[tokio::test]
+async fn coalesced_writes_reach_files_before_lookup_and_remove() {
+    let fs = Arc::new(MockFs::new());
+    fs.stub(Call::Getattr { id: 1 }, Ok(fattr3 { fileid: 1, ..Default::default() }));
+    fs.stub(Call::Getattr { id: 2 }, Ok(fattr3 { fileid: 2, ..Default::default() }));
+    fs.stub(Call::Lookup { dirid: 1, name: b"a".to_vec() }, Ok(2_u64));
+    let written = Call::Write { id: 2, offset: 0, data: b"data".to_vec() };
+    fs.stub(written.clone(), Ok(fattr3 { fileid: 2, size: 4, ..Default::default() }));
+    fs.expect(Call::Remove { dirid: 1, name: b"a".to_vec() }, Ok(()));
+    let mut context = testing::context(fs.clone());
+    context.write_coalescer = Some(Arc::new(nfs::v3::WriteCoalescer::default()));
+
+    let write = nfs3::file::WRITE3args {
+        file: context.id_to_fh(2).unwrap(),
+        offset: 0,
+        count: 4,
+        stable: nfs3::file::stable_how::UNSTABLE as u32,
+        data: b"data".to_vec(),
+    };
+    let name = nfs3::diropargs3 { dir: context.id_to_fh(1).unwrap(), name: b"a".to_vec().into() };
+    for (proc, then) in [
+        (NFSProgram::NFSPROC3_LOOKUP, Call::Getattr { id: 2 }),
+        (NFSProgram::NFSPROC3_REMOVE, Call::Remove { dirid: 1, name: b"a".to_vec() }),
+    ] {
+        let before = fs.calls().len();
+        testing::call_nfs3(&context, NFSProgram::NFSPROC3_WRITE, &write).await.unwrap();
+        assert!(!fs.calls()[before..].contains(&written), "the write is buffered");
+        let mut reply = testing::call_nfs3(&context, proc, &name).await.unwrap();
+        assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+        let calls = &fs.calls()[before..];
+        let flushed = calls.iter().position(|call| *call == written).expect("write flushed");
+        assert!(calls[flushed..].contains(&then), "{proc:?} ran before the flush: {calls:?}");
+    }
+    fs.assert_done();
+}
+
+#[tokio::test]
+async fn listings_skip_batches_of_hidden_entries() {
+    use nfs_mamont::vfs::{DirEntry, ReadDirCookie, ReadDirResult};
+
+    let fs = Arc::new(MockFs::new());
+    let mut context = testing::context(fs.clone());
+    let mut filter = nfs::v3::NameFilter::new();
+    filter.hide_dotfiles();
+    context.name_filter = Some(Arc::new(filter));
+    let entry = |fileid, name: &[u8]| DirEntry {
+        fileid,
+        name: name.to_vec().into(),
+        attr: fattr3 { fileid, ..Default::default() },
+    };
+    fs.stub(Call::Getattr { id: 1 }, Ok(fattr3 { fileid: 1, ..Default::default() }));
+    let hidden = ReadDirResult {
+        entries: vec![entry(10, b".a"), entry(11, b".b"), entry(12, b".c")],
+        end: false,
+    };
+    let visible = ReadDirResult { entries: vec![entry(13, b"visible")], end: true };
+    for max_entries in [32, 256] {
+        let first = Call::ReadDir { dirid: 1, cookie: ReadDirCookie::Start, max_entries };
+        fs.stub(first, Ok(hidden.clone()));
+        let next = Call::ReadDir { dirid: 1, cookie: ReadDirCookie::AfterFileId(12), max_entries };
+        fs.stub(next, Ok(visible.clone()));
+    }
+
+    let args = nfs3::dir::READDIR3args {
+        dir: context.id_to_fh(1).unwrap(),
+        cookie: 0,
+        cookieverf: nfs3::cookieverf3::default(),
+        dircount: 512,
+    };
+    let mut reply =
+        testing::call_nfs3(&context, NFSProgram::NFSPROC3_READDIR, &args).await.unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+    let listing = deserialize::<nfs3::dir::READDIR3resok>(&mut reply).unwrap().reply;
+    let names: Vec<_> =
+        listing.entries.iter().map(|entry| (entry.cookie, &entry.name[..])).collect();
+    assert_eq!(names, [(13, &b"visible"[..])]);
+    assert!(listing.eof);
+
+    let args = nfs3::dir::READDIRPLUS3args {
+        dir: context.id_to_fh(1).unwrap(),
+        cookie: 0,
+        cookieverf: nfs3::cookieverf3::default(),
+        dircount: 4096,
+        maxcount: 16384,
+    };
+    let mut reply =
+        testing::call_nfs3(&context, NFSProgram::NFSPROC3_READDIRPLUS, &args).await.unwrap();
+    assert_eq!(testing::status(&mut reply).unwrap() as u32, nfsstat3::NFS3_OK as u32);
+    let listing = deserialize::<nfs3::dir::READDIRPLUS3resok>(&mut reply).unwrap().reply;
+    let names: Vec<_> =
+        listing.entries.iter().map(|entry| (entry.cookie, &entry.name[..])).collect();
+    assert_eq!(names, [(13, &b"visible"[..])]);
+    assert!(listing.eof);
+}
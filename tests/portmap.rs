@@ -6,9 +6,9 @@ use std::time::Duration;
 use async_trait::async_trait;
 use num_traits::ToPrimitive;
 
-use nfs_mamont::protocol::nfs::portmap::PortmapTable;
+use nfs_mamont::protocol::nfs::portmap::{PortmapTable, RegistrationPolicy};
 use nfs_mamont::protocol::rpc;
-use nfs_mamont::protocol::rpc::Context;
+use nfs_mamont::protocol::rpc::{Context, ServerError};
 use nfs_mamont::vfs::{Capabilities, ReadDirResult};
 use nfs_mamont::xdr::nfs3::{
     fattr3, fileid3, filename3, ftype3, nfspath3, nfsstat3, sattr3, specdata3,
@@ -176,13 +176,13 @@ fn multiple_contexts(amount: u32) -> Vec<Context> {
     for i in 1..=amount {
         result.push(Context {
             local_port: DEFAULT_PROG,
-            client_addr: format!("0.0.0.0:{}", i),
+            client_addr: ([0, 0, 0, 0], i as u16).into(),
             auth: xdr::rpc::auth_unix::default(),
-            vfs: Arc::new(DemoFS { _root: String::default() }),
             mount_signal: None,
             export_name: Arc::from(DEFAULT_EXPORT_NAME.to_string()),
             transaction_tracker: Arc::new(rpc::TransactionTracker::new(Duration::from_secs(60))),
             portmap_table: table.clone(),
+            ..Context::new(Arc::new(DemoFS { _root: String::default() }))
         });
     }
     result
@@ -193,7 +193,7 @@ fn send_get_port(
     input: &mut Cursor<Vec<u8>>,
     output: &mut Cursor<Vec<u8>>,
     mapping_args: mapping,
-) -> Result<(), anyhow::Error> {
+) -> Result<(), ServerError> {
     let body = call_body {
         rpcvers: DEFAULT_VERSION,
         prog: xdr::portmap::PROGRAM,
@@ -219,7 +219,7 @@ fn send_set_port(
     input: &mut Cursor<Vec<u8>>,
     output: &mut Cursor<Vec<u8>>,
     mapping_args: mapping,
-) -> Result<(), anyhow::Error> {
+) -> Result<(), ServerError> {
     let body = call_body {
         rpcvers: DEFAULT_VERSION,
         prog: xdr::portmap::PROGRAM,
@@ -244,7 +244,7 @@ fn send_dump(
     context: &mut Context,
     input: &mut Cursor<Vec<u8>>,
     output: &mut Cursor<Vec<u8>>,
-) -> Result<(), anyhow::Error> {
+) -> Result<(), ServerError> {
     let body = call_body {
         rpcvers: 2,
         prog: xdr::portmap::PROGRAM,
@@ -266,7 +266,7 @@ fn send_unset_port(
     input: &mut Cursor<Vec<u8>>,
     output: &mut Cursor<Vec<u8>>,
     mapping_args: mapping,
-) -> Result<(), anyhow::Error> {
+) -> Result<(), ServerError> {
     let body = call_body {
         rpcvers: DEFAULT_VERSION,
         prog: xdr::portmap::PROGRAM,
@@ -319,7 +319,7 @@ fn call_assert<F, T>(
         &mut Cursor<Vec<u8>>,
         &mut Cursor<Vec<u8>>,
         mapping,
-    ) -> Result<(), anyhow::Error>,
+    ) -> Result<(), ServerError>,
     T: PartialEq + Default + xdr::Deserialize + std::fmt::Debug,
 {
     input.set_position(0);
@@ -340,13 +340,13 @@ mod tests {
     fn get_port_zero_reply(port: u16) {
         let mut context = Context {
             local_port: DEFAULT_PORT,
-            client_addr: DEFAULT_ADDRESS.to_string(),
+            client_addr: DEFAULT_ADDRESS.parse().unwrap(),
             auth: xdr::rpc::auth_unix::default(),
-            vfs: Arc::new(DemoFS { _root: String::default() }),
             mount_signal: None,
             export_name: Arc::from(DEFAULT_EXPORT_NAME.to_string()),
             transaction_tracker: Arc::new(rpc::TransactionTracker::new(Duration::from_secs(60))),
             portmap_table: Arc::from(RwLock::from(PortmapTable::default())),
+            ..Context::new(Arc::new(DemoFS { _root: String::default() }))
         };
         let mut input = Cursor::new(Vec::with_capacity(INPUT_SIZE));
         let mut output = Cursor::new(Vec::with_capacity(OUTPUT_SIZE));
@@ -364,13 +364,13 @@ mod tests {
     fn set_port_ok_reply(port: u16) {
         let mut context = Context {
             local_port: DEFAULT_PORT,
-            client_addr: DEFAULT_ADDRESS.to_string(),
+            client_addr: DEFAULT_ADDRESS.parse().unwrap(),
             auth: xdr::rpc::auth_unix::default(),
-            vfs: Arc::new(DemoFS { _root: String::default() }),
             mount_signal: None,
             export_name: Arc::from(DEFAULT_EXPORT_NAME.to_string()),
             transaction_tracker: Arc::new(rpc::TransactionTracker::new(Duration::from_secs(60))),
             portmap_table: Arc::from(RwLock::from(PortmapTable::default())),
+            ..Context::new(Arc::new(DemoFS { _root: String::default() }))
         };
         let mut input = Cursor::new(Vec::with_capacity(INPUT_SIZE));
         let mut output = Cursor::new(Vec::with_capacity(OUTPUT_SIZE));
@@ -394,13 +394,13 @@ mod tests {
         };
         let mut context = Context {
             local_port: DEFAULT_PORT,
-            client_addr: DEFAULT_ADDRESS.to_string(),
+            client_addr: DEFAULT_ADDRESS.parse().unwrap(),
             auth: xdr::rpc::auth_unix::default(),
-            vfs: Arc::new(DemoFS { _root: String::default() }),
             mount_signal: None,
             export_name: Arc::from(DEFAULT_EXPORT_NAME.to_string()),
             transaction_tracker: Arc::new(rpc::TransactionTracker::new(Duration::from_secs(60))),
             portmap_table: Arc::from(RwLock::from(PortmapTable::default())),
+            ..Context::new(Arc::new(DemoFS { _root: String::default() }))
         };
         let mut input = Cursor::new(Vec::with_capacity(INPUT_SIZE));
         let mut output = Cursor::new(Vec::with_capacity(OUTPUT_SIZE));
@@ -420,13 +420,13 @@ mod tests {
         let maps = multiple_mappings(amount, IPPROTO_TCP);
         let mut context = Context {
             local_port: DEFAULT_PORT,
-            client_addr: DEFAULT_ADDRESS.to_string(),
+            client_addr: DEFAULT_ADDRESS.parse().unwrap(),
             auth: xdr::rpc::auth_unix::default(),
-            vfs: Arc::new(DemoFS { _root: String::default() }),
             mount_signal: None,
             export_name: Arc::from(DEFAULT_EXPORT_NAME.to_string()),
             transaction_tracker: Arc::new(rpc::TransactionTracker::new(Duration::from_secs(60))),
             portmap_table: Arc::from(RwLock::from(PortmapTable::default())),
+            ..Context::new(Arc::new(DemoFS { _root: String::default() }))
         };
         let mut input = Cursor::new(Vec::with_capacity(INPUT_SIZE));
         let mut output = Cursor::new(Vec::with_capacity(OUTPUT_SIZE));
@@ -524,13 +524,13 @@ mod tests {
     fn unset_empty_table(amount: u32) {
         let mut context = Context {
             local_port: DEFAULT_PORT,
-            client_addr: DEFAULT_ADDRESS.to_string(),
+            client_addr: DEFAULT_ADDRESS.parse().unwrap(),
             auth: xdr::rpc::auth_unix::default(),
-            vfs: Arc::new(DemoFS { _root: String::default() }),
             mount_signal: None,
             export_name: Arc::from(DEFAULT_EXPORT_NAME.to_string()),
             transaction_tracker: Arc::new(rpc::TransactionTracker::new(Duration::from_secs(60))),
             portmap_table: Arc::from(RwLock::from(PortmapTable::default())),
+            ..Context::new(Arc::new(DemoFS { _root: String::default() }))
         };
         let mut input = Cursor::new(Vec::with_capacity(INPUT_SIZE));
         let mut output = Cursor::new(Vec::with_capacity(OUTPUT_SIZE));
@@ -546,13 +546,13 @@ mod tests {
     fn unset_single_protocol(amount: u32) {
         let mut context = Context {
             local_port: DEFAULT_PORT,
-            client_addr: DEFAULT_ADDRESS.to_string(),
+            client_addr: DEFAULT_ADDRESS.parse().unwrap(),
             auth: xdr::rpc::auth_unix::default(),
-            vfs: Arc::new(DemoFS { _root: String::default() }),
             mount_signal: None,
             export_name: Arc::from(DEFAULT_EXPORT_NAME.to_string()),
             transaction_tracker: Arc::new(rpc::TransactionTracker::new(Duration::from_secs(60))),
             portmap_table: Arc::from(RwLock::from(PortmapTable::default())),
+            ..Context::new(Arc::new(DemoFS { _root: String::default() }))
         };
         let mut input = Cursor::new(Vec::with_capacity(INPUT_SIZE));
         let mut output = Cursor::new(Vec::with_capacity(OUTPUT_SIZE));
@@ -572,13 +572,13 @@ mod tests {
     fn unset_both_protocols(amount: u32) {
         let mut context = Context {
             local_port: DEFAULT_PORT,
-            client_addr: DEFAULT_ADDRESS.to_string(),
+            client_addr: DEFAULT_ADDRESS.parse().unwrap(),
             auth: xdr::rpc::auth_unix::default(),
-            vfs: Arc::new(DemoFS { _root: String::default() }),
             mount_signal: None,
             export_name: Arc::from(DEFAULT_EXPORT_NAME.to_string()),
             transaction_tracker: Arc::new(rpc::TransactionTracker::new(Duration::from_secs(60))),
             portmap_table: Arc::from(RwLock::from(PortmapTable::default())),
+            ..Context::new(Arc::new(DemoFS { _root: String::default() }))
         };
         let mut input = Cursor::new(Vec::with_capacity(INPUT_SIZE));
         let mut output = Cursor::new(Vec::with_capacity(OUTPUT_SIZE));
@@ -681,13 +681,13 @@ mod tests {
         let mappings = multiple_mappings(entries_amount, IPPROTO_TCP);
         let mut context = Context {
             local_port: DEFAULT_PORT,
-            client_addr: DEFAULT_ADDRESS.to_string(),
+            client_addr: DEFAULT_ADDRESS.parse().unwrap(),
             auth: xdr::rpc::auth_unix::default(),
-            vfs: Arc::new(DemoFS { _root: String::default() }),
             mount_signal: None,
             export_name: Arc::from(DEFAULT_EXPORT_NAME.to_string()),
             transaction_tracker: Arc::new(rpc::TransactionTracker::new(Duration::from_secs(60))),
             portmap_table: Arc::from(RwLock::from(PortmapTable::default())),
+            ..Context::new(Arc::new(DemoFS { _root: String::default() }))
         };
         let mut input = Cursor::new(Vec::with_capacity(INPUT_SIZE));
         let mut output = Cursor::new(Vec::with_capacity(OUTPUT_SIZE));
@@ -819,4 +819,138 @@ mod tests {
         unset_several_threads(0);
         unset_several_threads(100);
     }
+
+    ///test that SET and UNSET are refused for callers the registration policy rejects
+    #[test]
+    fn registration_policy_rejects_remote_callers() {
+        let table = Arc::new(RwLock::new(PortmapTable::with_policy(RegistrationPolicy::STRICT)));
+        let context_from = |client_addr: &str| Context {
+            local_port: DEFAULT_PORT,
+            client_addr: client_addr.parse().unwrap(),
+            auth: xdr::rpc::auth_unix::default(),
+            mount_signal: None,
+            export_name: Arc::from(DEFAULT_EXPORT_NAME.to_string()),
+            transaction_tracker: Arc::new(rpc::TransactionTracker::new(Duration::from_secs(60))),
+            portmap_table: table.clone(),
+            ..Context::new(Arc::new(DemoFS { _root: String::default() }))
+        };
+        let mapping_args =
+            mapping { prog: nfs3::PROGRAM, vers: DEFAULT_VERSION, prot: IPPROTO_TCP, port: 2049 };
+        let mut input = Cursor::new(Vec::with_capacity(INPUT_SIZE));
+        let mut output = Cursor::new(Vec::with_capacity(OUTPUT_SIZE));
+
+        for rejected in ["10.0.0.1:700", "127.0.0.1:40000", "[::1]:40000"] {
+            let mut context = context_from(rejected);
+            call_assert(send_set_port, &mut context, &mut input, &mut output, mapping_args, false);
+            call_assert(send_get_port, &mut context, &mut input, &mut output, mapping_args, 0);
+        }
+
+        let mut local = context_from("127.0.0.1:700");
+        call_assert(send_set_port, &mut local, &mut input, &mut output, mapping_args, true);
+        let mut remote = context_from("10.0.0.1:700");
+        call_assert(send_unset_port, &mut remote, &mut input, &mut output, mapping_args, false);
+        call_assert(send_get_port, &mut remote, &mut input, &mut output, mapping_args, 2049);
+        let mut local = context_from("[::ffff:127.0.0.1]:700");
+        call_assert(send_unset_port, &mut local, &mut input, &mut output, mapping_args, true);
+    }
+
+    ///test that mappings set by clients can be listed and removed by the server
+    #[test]
+    fn table_lists_and_unregisters_mappings() {
+        let mut contexts = multiple_contexts(1);
+        let context = &mut contexts[0];
+        let mut input = Cursor::new(Vec::with_capacity(INPUT_SIZE));
+        let mut output = Cursor::new(Vec::with_capacity(OUTPUT_SIZE));
+        let mappings = multiple_mappings(4, IPPROTO_TCP);
+        for mapping_args in &mappings {
+            call_assert(send_set_port, context, &mut input, &mut output, *mapping_args, true);
+        }
+
+        let table = context.portmap_table.clone();
+        let fields = |entry: &mapping| (entry.prog, entry.vers, entry.prot, entry.port);
+        let mut listed: Vec<_> = table.read().unwrap().mappings().iter().map(fields).collect();
+        listed.sort();
+        assert_eq!(listed, mappings.iter().map(fields).collect::<Vec<_>>());
+        let stale = mappings[0];
+        let port = table.write().unwrap().unregister(stale.prog, stale.vers, stale.prot);
+        assert_eq!(port, Some(stale.port as u16));
+        assert_eq!(table.read().unwrap().len(), 3);
+        call_assert(send_get_port, context, &mut input, &mut output, stale, 0);
+    }
+}
+
+///test that the built-in portmapper announces the programs of its listener over UDP
+#[tokio::test]
+async fn portmapper_answers_getport_over_udp() {
+    use nfs_mamont::tcp::{NFSTcp, NFSTcpListener};
+    use nfs_mamont::vfs::testing::{self, MockFs};
+
+    let mut listener =
+        NFSTcpListener::bind_dyn("127.0.0.1:0", Arc::new(MockFs::new())).await.unwrap();
+    listener.bind_portmapper(0).await.unwrap();
+    let nfs_port = listener.get_listen_port();
+    let portmapper_port = listener.portmapper_port().unwrap();
+    tokio::spawn(async move { listener.handle_forever().await });
+
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket.connect(("127.0.0.1", portmapper_port)).await.unwrap();
+    let get_port = |xid, prog, vers| {
+        let args = mapping { prog, vers, prot: IPPROTO_TCP, port: 0 };
+        let proc = xdr::portmap::PortmapProgram::PMAPPROC_GETPORT.to_u32().unwrap();
+        testing::call_message(xid, xdr::portmap::PROGRAM, xdr::portmap::VERSION, proc, &args)
+    };
+    for (xid, prog, vers, expected) in [
+        (1, nfs3::PROGRAM, nfs3::VERSION, u32::from(nfs_port)),
+        (2, xdr::mount::PROGRAM, xdr::mount::VERSION, u32::from(nfs_port)),
+        (3, xdr::portmap::PROGRAM, xdr::portmap::VERSION, u32::from(portmapper_port)),
+        (4, nfs3::PROGRAM, 4, 0),
+    ] {
+        socket.send(&get_port(xid, prog, vers)).await.unwrap();
+        let mut reply = vec![0; 512];
+        let len = socket.recv(&mut reply).await.unwrap();
+        let mut reply = Cursor::new(&reply[..len]);
+        assert_eq!(deserialize::<xdr::rpc::rpc_msg>(&mut reply).unwrap().xid, xid);
+        assert_eq!(deserialize::<u32>(&mut reply).unwrap(), expected);
+    }
+}
+
+///test that the built-in portmapper does not answer NFS calls on its port
+#[tokio::test]
+async fn portmapper_refuses_other_programs() {
+    use nfs_mamont::tcp::{NFSTcp, NFSTcpListener};
+    use nfs_mamont::vfs::testing::{self, MockFs};
+    use nfs_mamont::xdr::rpc::{accept_body, reply_body, rpc_body};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut listener =
+        NFSTcpListener::bind_dyn("127.0.0.1:0", Arc::new(MockFs::new())).await.unwrap();
+    listener.bind_portmapper(0).await.unwrap();
+    let portmapper_port = listener.portmapper_port().unwrap();
+    tokio::spawn(async move { listener.handle_forever().await });
+
+    let null = testing::call_message(7, nfs3::PROGRAM, nfs3::VERSION, 0, &());
+    let unavailable = |reply: &[u8]| {
+        let reply = deserialize::<xdr::rpc::rpc_msg>(&mut Cursor::new(reply)).unwrap();
+        assert_eq!(reply.xid, 7);
+        let rpc_body::REPLY(reply_body::MSG_ACCEPTED(accepted)) = reply.body else {
+            panic!("call was not accepted: {reply:?}");
+        };
+        matches!(accepted.reply_data, accept_body::PROG_UNAVAIL)
+    };
+
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket.connect(("127.0.0.1", portmapper_port)).await.unwrap();
+    socket.send(&null).await.unwrap();
+    let mut reply = vec![0; 512];
+    let len = socket.recv(&mut reply).await.unwrap();
+    assert!(unavailable(&reply[..len]));
+
+    let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", portmapper_port)).await.unwrap();
+    let mark = (1_u32 << 31) | null.len() as u32;
+    stream.write_all(&mark.to_be_bytes()).await.unwrap();
+    stream.write_all(&null).await.unwrap();
+    let mark = stream.read_u32().await.unwrap();
+    let mut reply = vec![0; (mark & !(1 << 31)) as usize];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert!(unavailable(&reply));
 }
@@ -0,0 +1,492 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Cursor;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use nfs_mamont::protocol::rpc::{self, Context};
+use nfs_mamont::vfs::{
+    self, readdir, testing, Capabilities, DirEntry, NFSFileSystem, ReadDirCookie,
+    ReadDirCookieKind, ReadDirResult,
+};
+use nfs_mamont::xdr::nfs3::{
+    self, cookie3, fattr3, fileid3, filename3, ftype3, nfspath3, nfsstat3, sattr3, specdata3,
+};
+use nfs_mamont::xdr::{self, deserialize};
+
+const ROOT: fileid3 = 1;
+const ENTRIES: u64 = 10_000;
+const NFSPROC3_READDIR: u32 = 16;
+const NFSPROC3_READDIRPLUS: u32 = 17;
+
+/// Directory of `ENTRIES` files listed in name order, whose file ids are
+/// deliberately not in listing order
+struct BigDirFs {
+    kind: ReadDirCookieKind,
+    /// File ids in listing order
+    order: Vec<fileid3>,
+    /// Position of every file id in `order`
+    positions: HashMap<fileid3, usize>,
+    /// Whether listed entries carry their attributes
+    listed_attributes: bool,
+    /// Number of `getattr_bulk` calls
+    bulk_calls: Arc<AtomicUsize>,
+    /// Whether `getattr_bulk` falls back to one slow `getattr` per file, as
+    /// without a batch lookup
+    slow_getattr: bool,
+    /// Number of `getattr` calls in flight, and the most seen at once
+    getattrs: Arc<(AtomicUsize, AtomicUsize)>,
+}
+
+impl BigDirFs {
+    fn new(kind: ReadDirCookieKind) -> Self {
+        let order: Vec<fileid3> = (0..ENTRIES).map(|i| ROOT + 1 + (i * 7919) % ENTRIES).collect();
+        let positions = order.iter().enumerate().map(|(pos, id)| (*id, pos)).collect();
+        BigDirFs {
+            kind,
+            order,
+            positions,
+            listed_attributes: true,
+            bulk_calls: Arc::default(),
+            slow_getattr: false,
+            getattrs: Arc::default(),
+        }
+    }
+}
+
+fn name(fileid: fileid3) -> filename3 {
+    format!("file-{fileid:08}").into_bytes().into()
+}
+
+fn attr(fileid: fileid3) -> fattr3 {
+    fattr3 {
+        ftype: if fileid == ROOT { ftype3::NF3DIR } else { ftype3::NF3REG },
+        mode: 0o755,
+        nlink: 1,
+        fileid,
+        ..Default::default()
+    }
+}
+
+#[async_trait]
+impl NFSFileSystem for BigDirFs {
+    fn generation(&self) -> u64 {
+        1
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::ReadOnly
+    }
+
+    fn root_dir(&self) -> fileid3 {
+        ROOT
+    }
+
+    async fn lookup(&self, _dirid: fileid3, _filename: &filename3) -> Result<fileid3, nfsstat3> {
+        Err(nfsstat3::NFS3ERR_NOENT)
+    }
+
+    async fn getattr(&self, id: fileid3) -> Result<fattr3, nfsstat3> {
+        if self.slow_getattr {
+            let (in_flight, most) = &*self.getattrs;
+            most.fetch_max(in_flight.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+        }
+        Ok(attr(id))
+    }
+
+    async fn getattr_bulk(&self, ids: &[fileid3]) -> Vec<Result<fattr3, nfsstat3>> {
+        self.bulk_calls.fetch_add(1, Ordering::Relaxed);
+        if self.slow_getattr {
+            return vfs::getattr_concurrently(self, ids, self.getattr_concurrency()).await;
+        }
+        ids.iter().map(|&id| Ok(attr(id))).collect()
+    }
+
+    async fn setattr(&self, _id: fileid3, _setattr: sattr3) -> Result<fattr3, nfsstat3> {
+        Err(nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn read(
+        &self,
+        _id: fileid3,
+        _offset: u64,
+        _count: u32,
+    ) -> Result<(Vec<u8>, bool), nfsstat3> {
+        Ok((Vec::new(), true))
+    }
+
+    async fn write(&self, _id: fileid3, _offset: u64, _data: &[u8]) -> Result<fattr3, nfsstat3> {
+        Err(nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn create(
+        &self,
+        _dirid: fileid3,
+        _filename: &filename3,
+        _attr: sattr3,
+    ) -> Result<(fileid3, fattr3), nfsstat3> {
+        Err(nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn create_exclusive(
+        &self,
+        _dirid: fileid3,
+        _filename: &filename3,
+    ) -> Result<fileid3, nfsstat3> {
+        Err(nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn mkdir(
+        &self,
+        _dirid: fileid3,
+        _dirname: &filename3,
+    ) -> Result<(fileid3, fattr3), nfsstat3> {
+        Err(nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn remove(&self, _dirid: fileid3, _filename: &filename3) -> Result<(), nfsstat3> {
+        Err(nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn rename(
+        &self,
+        _from_dirid: fileid3,
+        _from_filename: &filename3,
+        _to_dirid: fileid3,
+        _to_filename: &filename3,
+    ) -> Result<(), nfsstat3> {
+        Err(nfsstat3::NFS3ERR_ROFS)
+    }
+
+    fn readdir_cookie_kind(&self) -> ReadDirCookieKind {
+        self.kind
+    }
+
+    fn readdir_has_attributes(&self) -> bool {
+        self.listed_attributes
+    }
+
+    async fn readdir_with_cookie(
+        &self,
+        dirid: fileid3,
+        cookie: ReadDirCookie,
+        max_entries: usize,
+    ) -> Result<ReadDirResult, nfsstat3> {
+        if dirid != ROOT {
+            return Err(nfsstat3::NFS3ERR_NOTDIR);
+        }
+        if let ReadDirCookieKind::Opaque = self.kind {
+            let entries = self
+                .order
+                .iter()
+                .map(|&fileid| DirEntry { fileid, name: name(fileid), attr: attr(fileid) })
+                .collect();
+            return readdir::page_by_cookie(entries, cookie, max_entries, |entry| {
+                readdir::name_cookie(&entry.name)
+            });
+        }
+        let first = match cookie {
+            ReadDirCookie::Start => 0,
+            ReadDirCookie::AfterFileId(id) => {
+                self.positions.get(&id).ok_or(nfsstat3::NFS3ERR_BAD_COOKIE)? + 1
+            }
+            ReadDirCookie::Index(index) => index as usize,
+            ReadDirCookie::Opaque(_) => return Err(nfsstat3::NFS3ERR_BAD_COOKIE),
+        };
+        let entries: Vec<DirEntry> = self
+            .order
+            .iter()
+            .skip(first)
+            .take(max_entries)
+            .map(|&fileid| DirEntry {
+                fileid,
+                name: name(fileid),
+                attr: if self.listed_attributes { attr(fileid) } else { fattr3::default() },
+            })
+            .collect();
+        let end = first + entries.len() >= self.order.len();
+        Ok(ReadDirResult { entries, end })
+    }
+
+    async fn symlink(
+        &self,
+        _dirid: fileid3,
+        _linkname: &filename3,
+        _symlink: &nfspath3,
+        _attr: &sattr3,
+    ) -> Result<(fileid3, fattr3), nfsstat3> {
+        Err(nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn readlink(&self, _id: fileid3) -> Result<nfspath3, nfsstat3> {
+        Err(nfsstat3::NFS3ERR_INVAL)
+    }
+
+    async fn link(
+        &self,
+        _file_id: fileid3,
+        _link_dir_id: fileid3,
+        _link_name: &filename3,
+    ) -> Result<fattr3, nfsstat3> {
+        Err(nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn mknod(
+        &self,
+        _dir_id: fileid3,
+        _name: &filename3,
+        _ftype: ftype3,
+        _specdata: specdata3,
+        _attrs: &sattr3,
+    ) -> Result<(fileid3, fattr3), nfsstat3> {
+        Err(nfsstat3::NFS3ERR_ROFS)
+    }
+
+    async fn commit(
+        &self,
+        _file_id: fileid3,
+        _offset: u64,
+        _count: u32,
+    ) -> Result<fattr3, nfsstat3> {
+        Err(nfsstat3::NFS3ERR_ROFS)
+    }
+}
+
+fn context(fs: BigDirFs) -> Context {
+    testing::context(Arc::new(fs))
+}
+
+/// Lists the root directory page by page and returns every
+/// (fileid, name, cookie) triple in order, plus the number of pages
+async fn list(context: &Context, plus: bool, size: u32) -> (Vec<(fileid3, Vec<u8>, cookie3)>, u32) {
+    let dir = context.id_to_fh(ROOT).unwrap();
+    let mut entries = Vec::new();
+    let mut cookie = 0;
+    let mut cookieverf = nfs3::cookieverf3::default();
+    for page in 1.. {
+        let request = if plus {
+            let args = nfs3::dir::READDIRPLUS3args {
+                dir: dir.clone(),
+                cookie,
+                cookieverf,
+                dircount: size,
+                maxcount: size * 4,
+            };
+            testing::call_message(page, nfs3::PROGRAM, nfs3::VERSION, NFSPROC3_READDIRPLUS, &args)
+        } else {
+            let args =
+                nfs3::dir::READDIR3args { dir: dir.clone(), cookie, cookieverf, dircount: size };
+            testing::call_message(page, nfs3::PROGRAM, nfs3::VERSION, NFSPROC3_READDIR, &args)
+        };
+        let mut reply = Vec::new();
+        rpc::process_message(&request, &mut reply, context.clone()).await.unwrap();
+
+        let mut reply = Cursor::new(reply);
+        deserialize::<xdr::rpc::rpc_msg>(&mut reply).unwrap();
+        let stat = deserialize::<u32>(&mut reply).unwrap();
+        assert_eq!(stat, nfsstat3::NFS3_OK as u32, "page {page}");
+        deserialize::<nfs3::post_op_attr>(&mut reply).unwrap();
+        cookieverf = deserialize::<nfs3::cookieverf3>(&mut reply).unwrap();
+
+        let before = entries.len();
+        while deserialize::<bool>(&mut reply).unwrap() {
+            if plus {
+                let entry = deserialize::<nfs3::dir::entryplus3>(&mut reply).unwrap();
+                let handle = entry.name_handle.expect("entry without handle");
+                assert_eq!(context.fh_to_id(&handle).ok(), Some(entry.fileid));
+                entries.push((entry.fileid, entry.name.to_vec(), entry.cookie));
+            } else {
+                let entry = deserialize::<nfs3::dir::entry3>(&mut reply).unwrap();
+                entries.push((entry.fileid, entry.name.to_vec(), entry.cookie));
+            }
+        }
+        let eof = deserialize::<bool>(&mut reply).unwrap();
+        if eof {
+            return (entries, page);
+        }
+        assert!(entries.len() > before, "page {page} returned no entries without eof");
+        cookie = entries.last().unwrap().2;
+    }
+    unreachable!()
+}
+
+async fn assert_complete_listing(kind: ReadDirCookieKind, plus: bool) {
+    let fs = BigDirFs::new(kind);
+    let order = fs.order.clone();
+    let context = context(fs);
+    let (entries, pages) = list(&context, plus, 1024).await;
+
+    assert!(pages > 10, "listing should span many pages, got {pages}");
+    let mut fileids: Vec<fileid3> = entries.iter().map(|e| e.0).collect();
+    if kind == ReadDirCookieKind::Opaque {
+        // Listed in cookie order rather than in the file system's order
+        let mut order = order;
+        order.sort();
+        fileids.sort();
+        assert_eq!(fileids, order);
+    } else {
+        assert_eq!(fileids, order);
+    }
+    for (fileid, name_bytes, _) in &entries {
+        assert_eq!(name_bytes[..], name(*fileid)[..]);
+    }
+    let cookies: HashSet<cookie3> = entries.iter().map(|e| e.2).collect();
+    assert_eq!(cookies.len(), entries.len(), "cookies must be unique");
+    assert!(!cookies.contains(&0), "cookie 0 is reserved for the start of a listing");
+}
+
+#[tokio::test]
+async fn readdir_paginates_with_fileid_cookies() {
+    assert_complete_listing(ReadDirCookieKind::FileId, false).await;
+}
+
+#[tokio::test]
+async fn readdir_paginates_with_index_cookies() {
+    assert_complete_listing(ReadDirCookieKind::Index, false).await;
+}
+
+#[tokio::test]
+async fn readdirplus_paginates_with_fileid_cookies() {
+    assert_complete_listing(ReadDirCookieKind::FileId, true).await;
+}
+
+#[tokio::test]
+async fn readdirplus_paginates_with_index_cookies() {
+    assert_complete_listing(ReadDirCookieKind::Index, true).await;
+}
+
+#[tokio::test]
+async fn readdir_paginates_with_opaque_cookies() {
+    assert_complete_listing(ReadDirCookieKind::Opaque, false).await;
+}
+
+#[tokio::test]
+async fn readdirplus_paginates_with_opaque_cookies() {
+    assert_complete_listing(ReadDirCookieKind::Opaque, true).await;
+}
+
+#[tokio::test]
+async fn opaque_cookies_come_from_the_file_system() {
+    let context = context(BigDirFs::new(ReadDirCookieKind::Opaque));
+    let (entries, _) = list(&context, false, 1024).await;
+    for (_, name_bytes, cookie) in &entries {
+        assert_eq!(*cookie, readdir::name_cookie(name_bytes));
+    }
+}
+
+#[tokio::test]
+async fn index_cookies_are_positions() {
+    let context = context(BigDirFs::new(ReadDirCookieKind::Index));
+    let (entries, _) = list(&context, true, 1024).await;
+    for (position, entry) in entries.iter().enumerate() {
+        assert_eq!(entry.2, position as u64 + 1);
+    }
+}
+
+/// Sends a single `READDIRPLUS` call from the start of the root directory and
+/// returns its status and the number of entries in the reply
+async fn readdirplus_once(context: &Context, dircount: u32, maxcount: u32) -> (u32, usize) {
+    let args = nfs3::dir::READDIRPLUS3args {
+        dir: context.id_to_fh(ROOT).unwrap(),
+        cookie: 0,
+        cookieverf: nfs3::cookieverf3::default(),
+        dircount,
+        maxcount,
+    };
+    let request =
+        testing::call_message(1, nfs3::PROGRAM, nfs3::VERSION, NFSPROC3_READDIRPLUS, &args);
+    let mut reply = Vec::new();
+    rpc::process_message(&request, &mut reply, context.clone()).await.unwrap();
+
+    let mut reply = Cursor::new(reply);
+    deserialize::<xdr::rpc::rpc_msg>(&mut reply).unwrap();
+    let stat = deserialize::<u32>(&mut reply).unwrap();
+    deserialize::<nfs3::post_op_attr>(&mut reply).unwrap();
+    if stat != nfsstat3::NFS3_OK as u32 {
+        return (stat, 0);
+    }
+    deserialize::<nfs3::cookieverf3>(&mut reply).unwrap();
+    let mut entries = 0;
+    while deserialize::<bool>(&mut reply).unwrap() {
+        deserialize::<nfs3::dir::entryplus3>(&mut reply).unwrap();
+        entries += 1;
+    }
+    (stat, entries)
+}
+
+#[tokio::test]
+async fn readdirplus_too_small_for_one_entry() {
+    let context = context(BigDirFs::new(ReadDirCookieKind::FileId));
+    assert_eq!(readdirplus_once(&context, 1024, 64).await.0, nfsstat3::NFS3ERR_TOOSMALL as u32);
+    assert_eq!(readdirplus_once(&context, 8, 4096).await.0, nfsstat3::NFS3ERR_TOOSMALL as u32);
+}
+
+#[tokio::test]
+async fn readdirplus_sizes_are_clamped() {
+    let unclamped =
+        readdirplus_once(&context(BigDirFs::new(ReadDirCookieKind::FileId)), 1 << 20, 1 << 24)
+            .await;
+    let mut context = context(BigDirFs::new(ReadDirCookieKind::FileId));
+    context.readdirplus_limits =
+        Arc::new(nfs_mamont::protocol::nfs::v3::ReadDirPlusLimits::new(1024, 1 << 20));
+    let clamped = readdirplus_once(&context, 1 << 20, 1 << 24).await;
+    assert_eq!(clamped.0, nfsstat3::NFS3_OK as u32);
+    assert!(clamped.1 > 0 && clamped.1 < unclamped.1, "{clamped:?} vs {unclamped:?}");
+    assert!(clamped.1 <= 1024 / 16);
+}
+
+/// Lists the first page of the root with `READDIRPLUS`, checking that every
+/// entry carries its own attributes, and returns the number of entries
+async fn readdirplus_attributes(fs: BigDirFs) -> usize {
+    let context = context(fs);
+    let args = nfs3::dir::READDIRPLUS3args {
+        dir: context.id_to_fh(ROOT).unwrap(),
+        cookie: 0,
+        cookieverf: nfs3::cookieverf3::default(),
+        dircount: 1024,
+        maxcount: 4096,
+    };
+    let request =
+        testing::call_message(1, nfs3::PROGRAM, nfs3::VERSION, NFSPROC3_READDIRPLUS, &args);
+    let mut reply = Vec::new();
+    rpc::process_message(&request, &mut reply, context.clone()).await.unwrap();
+
+    let mut reply = Cursor::new(reply);
+    deserialize::<xdr::rpc::rpc_msg>(&mut reply).unwrap();
+    assert_eq!(deserialize::<u32>(&mut reply).unwrap(), nfsstat3::NFS3_OK as u32);
+    deserialize::<nfs3::post_op_attr>(&mut reply).unwrap();
+    deserialize::<nfs3::cookieverf3>(&mut reply).unwrap();
+    let mut entries = 0;
+    while deserialize::<bool>(&mut reply).unwrap() {
+        let entry = deserialize::<nfs3::dir::entryplus3>(&mut reply).unwrap();
+        assert_eq!(entry.name_attributes.map(|attr| attr.fileid), Some(entry.fileid));
+        entries += 1;
+    }
+    entries
+}
+
+#[tokio::test]
+async fn readdirplus_fetches_attributes_in_bulk() {
+    let fs = BigDirFs { listed_attributes: false, ..BigDirFs::new(ReadDirCookieKind::FileId) };
+    let bulk_calls = fs.bulk_calls.clone();
+    assert!(readdirplus_attributes(fs).await > 0);
+    assert_eq!(bulk_calls.load(Ordering::Relaxed), 1);
+}
+
+#[tokio::test]
+async fn readdirplus_overlaps_getattr_calls() {
+    let fs = BigDirFs {
+        listed_attributes: false,
+        slow_getattr: true,
+        ..BigDirFs::new(ReadDirCookieKind::FileId)
+    };
+    let getattrs = fs.getattrs.clone();
+    let entries = readdirplus_attributes(fs).await;
+    assert!(entries > vfs::DEFAULT_GETATTR_CONCURRENCY, "{entries} entries");
+    // as many calls as allowed overlap, but no more
+    assert_eq!(getattrs.1.load(Ordering::SeqCst), vfs::DEFAULT_GETATTR_CONCURRENCY);
+}
@@ -0,0 +1,79 @@
+/*
+ * Port Mapper Protocol Specification, RFC 1833 section 3.1.
+ *
+ * Generate src/protocol/xdr/portmap.rs with `cargo xtask xdrgen`.
+ * Comments directly before a definition, or after it on the same line,
+ * become its documentation; `%` lines are copied to the output verbatim.
+ */
+
+%//! This module implements the Portmap protocol (RFC 1833, previously RFC 1057 Appendix A) data structures
+%//! for XDR serialization and deserialization.
+%//!
+%//! Portmap (also known as Portmapper) is a service that maps RPC program numbers
+%//! to network port numbers. Clients use the Portmapper to locate the port number
+%//! for a specific RPC service they wish to use.
+%
+%// Allow unused code since we implement the complete RFC specification
+%#![allow(dead_code)]
+%// Keep original RFC naming conventions for consistency with the specification
+%#![allow(non_camel_case_types)]
+
+const PMAP_PORT = 111;      /* Portmapper port number */
+
+/* Represents a mapping between an RPC program and a network port. */
+struct mapping {
+   unsigned int prog;       /* The RPC program number */
+   unsigned int vers;       /* The RPC program version number */
+   unsigned int prot;       /* The transport protocol (TCP or UDP, see IPPROTO_* constants) */
+   unsigned int port;       /* The port number where the service is listening */
+};
+
+const IPPROTO_TCP = 6;      /* Protocol number for TCP/IP */
+const IPPROTO_UDP = 17;     /* Protocol number for UDP/IP */
+
+/*
+ * A linked list node for port mapper entries.
+ * `next` is an XDR optional: `None` terminates the list.
+ */
+struct pmaplist {
+   mapping map;             /* Current program mapping */
+   pmaplist *next;          /* Next element in the linked list */
+};
+
+/* Arguments to callit */
+struct call_args {
+   unsigned int prog;       /* The RPC program number */
+   unsigned int vers;       /* The RPC program version number */
+   unsigned int proc;       /* The procedure number to call */
+   opaque args<>;           /* The encoded procedure arguments */
+};
+
+/* Results of callit */
+struct call_result {
+   unsigned int port;       /* The port of the called program */
+   opaque res<>;            /* The encoded procedure results */
+};
+
+/* Portmap RPC program number */
+program PMAP_PROG {
+   /* Portmap RPC version number */
+   version PMAP_VERS {
+      void
+      PMAPPROC_NULL(void)         = 0;  /* Null procedure for service availability testing */
+
+      bool
+      PMAPPROC_SET(mapping)       = 1;  /* Register a new program-to-port mapping */
+
+      bool
+      PMAPPROC_UNSET(mapping)     = 2;  /* Remove a program-to-port mapping */
+
+      unsigned int
+      PMAPPROC_GETPORT(mapping)   = 3;  /* Look up the port for a program */
+
+      pmaplist
+      PMAPPROC_DUMP(void)         = 4;  /* List all registered program-to-port mappings */
+
+      call_result
+      PMAPPROC_CALLIT(call_args)  = 5;  /* Call another registered procedure */
+   } = 2;
+} = 100000;
@@ -0,0 +1,50 @@
+//! Development tasks for nfs-mamont, run with `cargo xtask <task>`.
+//!
+//! Tasks:
+//! - `xdrgen [--check]`: regenerate the XDR modules in `src/protocol/xdr` from the
+//!   `.x` specifications in `xdr/`, or with `--check` fail if they are out of date.
+
+mod xdrgen;
+
+use std::path::Path;
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("xdrgen") => xdrgen_task(args.get(1).map(String::as_str) == Some("--check")),
+        _ => Err("usage: cargo xtask xdrgen [--check]".to_string()),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Regenerates, or checks, every module listed in [`xdrgen::SPECS`]
+fn xdrgen_task(check: bool) -> Result<(), String> {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).parent().expect("xtask is in the workspace");
+    let mut stale = Vec::new();
+    for spec in xdrgen::SPECS {
+        let code = xdrgen::generate(root, spec)?;
+        let path = root.join(spec.output);
+        let current = std::fs::read_to_string(&path).unwrap_or_default();
+        if current == code {
+            continue;
+        }
+        if check {
+            stale.push(spec.output);
+        } else {
+            std::fs::write(&path, code).map_err(|e| format!("{}: {e}", spec.output))?;
+            println!("generated {} from {}", spec.output, spec.input);
+        }
+    }
+    if stale.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("out of date, run `cargo xtask xdrgen`: {}", stale.join(", ")))
+    }
+}
@@ -0,0 +1,545 @@
+//! Rust code generation for parsed XDR definitions.
+//!
+//! The output follows the conventions of the hand-written modules in
+//! `src/protocol/xdr`: RFC names are kept as is, structs use the
+//! `SerializeStruct!`/`DeserializeStruct!` macros, enums derive
+//! `FromPrimitive`/`ToPrimitive` and implement the `SerializeEnum` marker traits,
+//! and unions become Rust enums with one variant per case.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use super::parser::{Arm, Definition, Field, Item, Program, Shape, Type, Union};
+
+/// Largest array length for which the standard library implements `Default`
+const MAX_DEFAULT_ARRAY: u64 = 32;
+
+/// Rust keywords that cannot be used as field names without `r#`
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "static", "struct", "trait", "true", "type", "unsafe", "use", "where",
+    "while",
+];
+
+/// Options controlling the generated code
+pub struct Options<'a> {
+    /// Path of the specification, mentioned in the header comment
+    pub source: &'a str,
+    /// Name of the procedure enum generated for each program, by program name
+    pub program_enums: &'a [(&'a str, &'a str)],
+}
+
+/// Generates a Rust module for a parsed specification
+///
+/// # Parameters
+/// * `items` - Output of [`super::parser::parse`]
+/// * `options` - Naming and header options
+///
+/// # Returns
+/// * `Result<String, String>` - Unformatted Rust source or a message describing
+///   a definition that cannot be represented
+pub fn generate(items: &[Item], options: &Options) -> Result<String, String> {
+    let gen = Generator::new(items);
+    let mut body = String::new();
+    let mut header = String::new();
+    let mut in_header = true;
+
+    for item in items {
+        if let Definition::Passthrough(text) = &item.def {
+            let out = if in_header { &mut header } else { &mut body };
+            writeln!(out, "{text}").unwrap();
+            continue;
+        }
+        in_header = false;
+        gen.item(&mut body, item, options)?;
+    }
+
+    let mut out = String::new();
+    writeln!(out, "// Generated by `cargo xtask xdrgen` from `{}`. Do not edit.", options.source)
+        .unwrap();
+    out.push_str(&header);
+    out.push('\n');
+    out.push_str(&gen.imports(items));
+    out.push('\n');
+    out.push_str(&body);
+    Ok(out)
+}
+
+struct Generator {
+    consts: HashMap<String, String>,
+    enums: HashMap<String, Vec<String>>,
+    structs: HashMap<String, Vec<Field>>,
+    typedefs: HashMap<String, Shape>,
+}
+
+impl Generator {
+    fn new(items: &[Item]) -> Self {
+        let mut gen = Generator {
+            consts: HashMap::new(),
+            enums: HashMap::new(),
+            structs: HashMap::new(),
+            typedefs: HashMap::new(),
+        };
+        for item in items {
+            match &item.def {
+                Definition::Const { name, value } => {
+                    gen.consts.insert(name.clone(), value.clone());
+                }
+                Definition::Enum { name, variants } => {
+                    gen.enums
+                        .insert(name.clone(), variants.iter().map(|v| v.name.clone()).collect());
+                }
+                Definition::Struct { name, fields } => {
+                    gen.structs.insert(name.clone(), fields.clone());
+                }
+                Definition::Typedef(field) => {
+                    gen.typedefs.insert(field.name.clone(), field.shape.clone());
+                }
+                _ => {}
+            }
+        }
+        gen
+    }
+
+    fn imports(&self, items: &[Item]) -> String {
+        let has = |f: fn(&Definition) -> bool| items.iter().any(|i| f(&i.def));
+        let structs = has(|d| matches!(d, Definition::Struct { .. }));
+        let unions = has(|d| matches!(d, Definition::Union { .. }));
+        let enums = has(|d| matches!(d, Definition::Enum { .. } | Definition::Program(_)));
+
+        let mut out = String::new();
+        if structs || unions {
+            out.push_str("use std::io::{Read, Write};\n\n");
+        }
+        if enums {
+            out.push_str("use num_derive::{FromPrimitive, ToPrimitive};\n\n");
+        }
+        let mut xdr = Vec::new();
+        if unions {
+            xdr.push("deserialize");
+        }
+        if structs || unions {
+            xdr.extend(["Deserialize", "Serialize"]);
+        }
+        if enums {
+            xdr.extend(["DeserializeEnum", "SerializeEnum"]);
+        }
+        if !xdr.is_empty() {
+            writeln!(out, "use crate::xdr::{{{}}};", xdr.join(", ")).unwrap();
+        }
+        if structs {
+            out.push_str("use crate::{DeserializeStruct, SerializeStruct};\n");
+        }
+        out
+    }
+
+    fn item(&self, out: &mut String, item: &Item, options: &Options) -> Result<(), String> {
+        match &item.def {
+            Definition::Const { name, value } => {
+                doc(out, &item.doc, "");
+                let ty = if value.starts_with('-') { "i32" } else { "u32" };
+                writeln!(out, "pub const {name}: {ty} = {};\n", self.literal(value)).unwrap();
+            }
+            Definition::Typedef(field) => {
+                doc(out, &item.doc, "");
+                writeln!(out, "pub type {} = {};\n", field.name, self.rust_type(&field.shape, ""))
+                    .unwrap();
+            }
+            Definition::Enum { name, variants } => {
+                doc(out, &item.doc, "");
+                out.push_str("#[allow(clippy::upper_case_acronyms)]\n");
+                out.push_str(
+                    "#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, FromPrimitive, ToPrimitive)]\n",
+                );
+                writeln!(out, "pub enum {name} {{").unwrap();
+                for (i, v) in variants.iter().enumerate() {
+                    doc(out, &v.doc, "    ");
+                    if i == 0 {
+                        out.push_str("    #[default]\n");
+                    }
+                    writeln!(out, "    {} = {},", v.name, self.discriminant(&v.value)).unwrap();
+                }
+                writeln!(out, "}}").unwrap();
+                writeln!(out, "impl SerializeEnum for {name} {{}}").unwrap();
+                writeln!(out, "impl DeserializeEnum for {name} {{}}\n").unwrap();
+            }
+            Definition::Struct { name, fields } => self.structure(out, item, name, fields),
+            Definition::Union { name, body } => self.union(out, item, name, body)?,
+            Definition::Program(program) => self.program(out, item, program, options)?,
+            Definition::Passthrough(_) => unreachable!("handled by the caller"),
+        }
+        Ok(())
+    }
+
+    fn structure(&self, out: &mut String, item: &Item, name: &str, fields: &[Field]) {
+        let copy = self.is_copy_struct(name, &mut HashSet::new());
+        let default = fields.iter().all(|f| self.derives_default(&f.shape));
+        let derives = match (copy, default) {
+            (true, true) => "Copy, Clone, Debug, Default",
+            (true, false) => "Copy, Clone, Debug",
+            (false, true) => "Clone, Debug, Default",
+            (false, false) => "Clone, Debug",
+        };
+
+        doc(out, &item.doc, "");
+        writeln!(out, "#[derive({derives})]\npub struct {name} {{").unwrap();
+        for f in fields {
+            doc(out, &f.doc, "    ");
+            writeln!(out, "    pub {}: {},", field_name(&f.name), self.rust_type(&f.shape, name))
+                .unwrap();
+        }
+        writeln!(out, "}}").unwrap();
+
+        let names = fields.iter().map(|f| field_name(&f.name)).collect::<Vec<_>>().join(", ");
+        writeln!(out, "DeserializeStruct!({name}, {names});").unwrap();
+        writeln!(out, "SerializeStruct!({name}, {names});\n").unwrap();
+
+        if !default {
+            writeln!(out, "impl Default for {name} {{").unwrap();
+            writeln!(out, "    fn default() -> Self {{\n        Self {{").unwrap();
+            for f in fields {
+                let value = if self.derives_default(&f.shape) {
+                    "Default::default()"
+                } else {
+                    "std::array::from_fn(|_| Default::default())"
+                };
+                writeln!(out, "            {}: {value},", field_name(&f.name)).unwrap();
+            }
+            writeln!(out, "        }}\n    }}\n}}\n").unwrap();
+        }
+    }
+
+    fn union(&self, out: &mut String, item: &Item, name: &str, body: &Union) -> Result<(), String> {
+        let Shape::Scalar(disc_ty) = &body.discriminant.shape else {
+            return Err(format!("union {name}: discriminant must be a scalar"));
+        };
+        let disc_rust = self.scalar(disc_ty);
+        let enum_variants = match disc_ty {
+            Type::Named(n) => self.enums.get(n),
+            _ => None,
+        };
+
+        // one Rust variant per case label
+        let mut variants: Vec<(String, String, &Arm)> = Vec::new();
+        for arm in &body.arms {
+            for label in &arm.labels {
+                let variant = variant_name(label);
+                let pattern = match (disc_ty, enum_variants) {
+                    (Type::Named(n), Some(_)) => format!("{n}::{label}"),
+                    (Type::Bool, _) => label.to_lowercase(),
+                    _ => self.literal(label),
+                };
+                variants.push((variant, pattern, arm));
+            }
+        }
+        let covered = match (disc_ty, enum_variants) {
+            (Type::Bool, _) => variants.len() == 2,
+            (_, Some(all)) => all.iter().all(|v| body.arms.iter().any(|a| a.labels.contains(v))),
+            _ => false,
+        };
+
+        doc(out, &item.doc, "");
+        out.push_str("#[allow(clippy::upper_case_acronyms)]\n#[derive(Clone, Debug)]\n");
+        writeln!(out, "pub enum {name} {{").unwrap();
+        for (variant, _, arm) in &variants {
+            doc(out, &arm.doc, "    ");
+            match &arm.field {
+                Some(f) => writeln!(out, "    {variant}({}),", self.rust_type(&f.shape, name)),
+                None => writeln!(out, "    {variant},"),
+            }
+            .unwrap();
+        }
+        if let Some(default) = &body.default {
+            out.push_str("    /// Any other discriminant value\n");
+            match default {
+                Some(f) => {
+                    writeln!(out, "    default({disc_rust}, {}),", self.rust_type(&f.shape, name))
+                }
+                None => writeln!(out, "    default({disc_rust}),"),
+            }
+            .unwrap();
+        }
+        writeln!(out, "}}\n").unwrap();
+
+        // Default is the first case, as for enums
+        let (first, _, first_arm) = variants
+            .first()
+            .ok_or_else(|| format!("union {name}: at least one case is required"))?;
+        writeln!(out, "impl Default for {name} {{\n    fn default() -> Self {{").unwrap();
+        match first_arm.field {
+            Some(_) => writeln!(out, "        {name}::{first}(Default::default())"),
+            None => writeln!(out, "        {name}::{first}"),
+        }
+        .unwrap();
+        writeln!(out, "    }}\n}}\n").unwrap();
+
+        writeln!(out, "impl Serialize for {name} {{").unwrap();
+        out.push_str(
+            "    fn serialize<W: Write>(&self, dest: &mut W) -> std::io::Result<()> {\n        match self {\n",
+        );
+        for (variant, pattern, arm) in &variants {
+            let disc = self.typed_pattern(pattern, disc_ty);
+            match arm.field {
+                Some(_) => writeln!(
+                    out,
+                    "            {name}::{variant}(v) => {{\n                {disc}.serialize(dest)?;\n                v.serialize(dest)\n            }}"
+                ),
+                None => writeln!(out, "            {name}::{variant} => {disc}.serialize(dest),"),
+            }
+            .unwrap();
+        }
+        match &body.default {
+            Some(Some(_)) => writeln!(
+                out,
+                "            {name}::default(d, v) => {{\n                d.serialize(dest)?;\n                v.serialize(dest)\n            }}"
+            )
+            .unwrap(),
+            Some(None) => writeln!(out, "            {name}::default(d) => d.serialize(dest),").unwrap(),
+            None => {}
+        }
+        out.push_str("        }\n    }\n}\n\n");
+
+        writeln!(out, "impl Deserialize for {name} {{").unwrap();
+        out.push_str(
+            "    fn deserialize<R: Read>(&mut self, src: &mut R) -> std::io::Result<()> {\n",
+        );
+        writeln!(out, "        *self = match deserialize::<{disc_rust}>(src)? {{").unwrap();
+        for (variant, pattern, arm) in &variants {
+            match arm.field {
+                Some(_) => {
+                    writeln!(out, "            {pattern} => {name}::{variant}(deserialize(src)?),")
+                }
+                None => writeln!(out, "            {pattern} => {name}::{variant},"),
+            }
+            .unwrap();
+        }
+        match &body.default {
+            Some(Some(_)) => {
+                writeln!(out, "            d => {name}::default(d, deserialize(src)?),").unwrap()
+            }
+            Some(None) => writeln!(out, "            d => {name}::default(d),").unwrap(),
+            None if !covered => out.push_str(
+                "            _ => {\n                return Err(std::io::Error::new(\n                    std::io::ErrorKind::InvalidData,\n                    \"Invalid union discriminant\",\n                ))\n            }\n",
+            ),
+            None => {}
+        }
+        out.push_str("        };\n        Ok(())\n    }\n}\n\n");
+        Ok(())
+    }
+
+    fn program(
+        &self,
+        out: &mut String,
+        item: &Item,
+        program: &Program,
+        options: &Options,
+    ) -> Result<(), String> {
+        let single = program.versions.len() == 1;
+        doc(out, &item.doc, "");
+        let program_const = if single { "PROGRAM" } else { program.name.as_str() };
+        writeln!(out, "pub const {program_const}: u32 = {};\n", self.literal(&program.value))
+            .unwrap();
+
+        let enum_name = options
+            .program_enums
+            .iter()
+            .find(|(p, _)| *p == program.name)
+            .map(|(_, e)| e.to_string())
+            .ok_or_else(|| format!("no procedure enum name configured for {}", program.name))?;
+
+        for version in &program.versions {
+            doc(out, &version.doc, "");
+            let version_const = if single { "VERSION" } else { version.name.as_str() };
+            writeln!(out, "pub const {version_const}: u32 = {};\n", self.literal(&version.value))
+                .unwrap();
+
+            let enum_name =
+                if single { enum_name.clone() } else { format!("{enum_name}{}", version.value) };
+            writeln!(out, "/// Procedure numbers of `{}` version {}", program.name, version.value)
+                .unwrap();
+            out.push_str("#[allow(clippy::upper_case_acronyms)]\n");
+            out.push_str("#[derive(Copy, Clone, Debug, FromPrimitive, ToPrimitive)]\n");
+            writeln!(out, "pub enum {enum_name} {{").unwrap();
+            for proc in &version.procedures {
+                doc(out, &proc.doc, "    ");
+                writeln!(out, "    {} = {},", proc.name, self.discriminant(&proc.value)).unwrap();
+            }
+            out.push_str("    /// Invalid procedure number\n    INVALID,\n}\n");
+            writeln!(out, "impl SerializeEnum for {enum_name} {{}}").unwrap();
+            writeln!(out, "impl DeserializeEnum for {enum_name} {{}}\n").unwrap();
+        }
+        Ok(())
+    }
+
+    fn scalar(&self, ty: &Type) -> String {
+        match ty {
+            Type::Int => "i32".into(),
+            Type::UInt => "u32".into(),
+            Type::Hyper => "i64".into(),
+            Type::UHyper => "u64".into(),
+            Type::Float => "f32".into(),
+            Type::Double => "f64".into(),
+            Type::Bool => "bool".into(),
+            Type::Named(n) => n.clone(),
+        }
+    }
+
+    fn rust_type(&self, shape: &Shape, owner: &str) -> String {
+        match shape {
+            Shape::Scalar(t) => self.scalar(t),
+            Shape::FixedOpaque(size) => format!("[u8; {}]", self.size(size)),
+            Shape::VarOpaque => "Vec<u8>".into(),
+            Shape::String => "String".into(),
+            Shape::FixedArray(t, size) => format!("[{}; {}]", self.scalar(t), self.size(size)),
+            Shape::VarArray(t) => format!("Vec<{}>", self.scalar(t)),
+            // a struct that refers to itself needs an indirection to have a finite size
+            Shape::Optional(Type::Named(n)) if n == owner => format!("Box<Option<{n}>>"),
+            Shape::Optional(t) => format!("Option<{}>", self.scalar(t)),
+        }
+    }
+
+    fn size(&self, size: &str) -> String {
+        if size.chars().next().is_some_and(|c| c.is_ascii_digit()) {
+            self.literal(size)
+        } else {
+            format!("{size} as usize")
+        }
+    }
+
+    fn literal(&self, value: &str) -> String {
+        if let Some(hex) = value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+            format!("0x{hex}")
+        } else if value.len() > 1 && value.starts_with('0') {
+            format!("0o{}", &value[1..])
+        } else {
+            value.to_string()
+        }
+    }
+
+    fn discriminant(&self, value: &str) -> String {
+        if value.chars().next().is_some_and(|c| c.is_ascii_digit() || c == '-') {
+            self.literal(value)
+        } else {
+            format!("{value} as isize")
+        }
+    }
+
+    fn typed_pattern(&self, pattern: &str, ty: &Type) -> String {
+        match ty {
+            Type::Int if pattern.chars().next().is_some_and(|c| c.is_ascii_digit() || c == '-') => {
+                format!("{pattern}i32")
+            }
+            Type::UInt if pattern.chars().next().is_some_and(|c| c.is_ascii_digit()) => {
+                format!("{pattern}u32")
+            }
+            _ => pattern.to_string(),
+        }
+    }
+
+    fn const_value(&self, size: &str) -> Option<u64> {
+        let value = self.consts.get(size).map_or(size, String::as_str);
+        if let Some(hex) = value.strip_prefix("0x") {
+            u64::from_str_radix(hex, 16).ok()
+        } else {
+            value.parse().ok()
+        }
+    }
+
+    fn derives_default(&self, shape: &Shape) -> bool {
+        match shape {
+            Shape::FixedOpaque(size) | Shape::FixedArray(_, size) => {
+                self.const_value(size).is_some_and(|n| n <= MAX_DEFAULT_ARRAY)
+            }
+            Shape::Scalar(Type::Named(n)) => match self.typedefs.get(n) {
+                Some(inner) => self.derives_default(inner),
+                None => true,
+            },
+            _ => true,
+        }
+    }
+
+    fn is_copy_type(&self, ty: &Type, seen: &mut HashSet<String>) -> bool {
+        match ty {
+            Type::Named(n) if self.enums.contains_key(n) => true,
+            Type::Named(n) if self.structs.contains_key(n) => self.is_copy_struct(n, seen),
+            Type::Named(n) => self.typedefs.get(n).is_some_and(|s| self.is_copy_shape(s, seen)),
+            _ => true,
+        }
+    }
+
+    fn is_copy_shape(&self, shape: &Shape, seen: &mut HashSet<String>) -> bool {
+        match shape {
+            Shape::Scalar(t) | Shape::FixedArray(t, _) => self.is_copy_type(t, seen),
+            Shape::FixedOpaque(_) => true,
+            _ => false,
+        }
+    }
+
+    fn is_copy_struct(&self, name: &str, seen: &mut HashSet<String>) -> bool {
+        if !seen.insert(name.to_string()) {
+            return false;
+        }
+        self.structs
+            .get(name)
+            .is_some_and(|fields| fields.iter().all(|f| self.is_copy_shape(&f.shape, seen)))
+    }
+}
+
+fn doc(out: &mut String, lines: &[String], indent: &str) {
+    for line in lines {
+        if line.is_empty() {
+            writeln!(out, "{indent}///").unwrap();
+        } else {
+            writeln!(out, "{indent}/// {line}").unwrap();
+        }
+    }
+}
+
+fn field_name(name: &str) -> String {
+    if KEYWORDS.contains(&name) {
+        format!("r#{name}")
+    } else {
+        name.to_string()
+    }
+}
+
+fn variant_name(label: &str) -> String {
+    if label.chars().next().is_some_and(|c| c.is_ascii_digit() || c == '-') {
+        format!("CASE_{}", label.replace('-', "MINUS_"))
+    } else {
+        label.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{lexer, parser};
+    use super::*;
+
+    fn generate_str(spec: &str) -> String {
+        let items = parser::parse(lexer::tokenize(spec).unwrap()).unwrap();
+        generate(&items, &Options { source: "test.x", program_enums: &[] }).unwrap()
+    }
+
+    #[test]
+    fn union_with_default_arm() {
+        let code = generate_str(
+            "enum stat { OK = 0, ERR = 1, ERR2 = 2 };
+             union res switch (stat status) {
+             case OK: opaque data<>; /* payload */
+             default: void;
+             };",
+        );
+        assert!(code.contains("/// payload\n    OK(Vec<u8>),"));
+        assert!(code.contains("stat::OK => res::OK(deserialize(src)?),"));
+        assert!(code.contains("d => res::default(d),"));
+    }
+
+    #[test]
+    fn large_fixed_array_gets_manual_default() {
+        let code = generate_str("const SIZE = 64; struct fh { opaque data[SIZE]; };");
+        assert!(code.contains("pub data: [u8; SIZE as usize],"));
+        assert!(code.contains("impl Default for fh"));
+    }
+}
@@ -0,0 +1,104 @@
+//! Tokenizer for the XDR language (RFC 4506 section 6) and the RPC language
+//! extensions of RFC 5531 section 12.
+
+/// Kind of a lexical token
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TokenKind {
+    /// Identifier or keyword
+    Ident(String),
+    /// Decimal, hexadecimal or octal constant, kept as written
+    Number(String),
+    /// Single punctuation character
+    Punct(char),
+    /// Contents of a `/* ... */` comment
+    Comment {
+        /// Comment text with the delimiters stripped
+        text: String,
+        /// Whether the comment is the first token on its line
+        own_line: bool,
+    },
+    /// `%` line copied verbatim into the generated file (rpcgen convention)
+    Passthrough(String),
+}
+
+/// A token together with the line it starts on
+#[derive(Clone, Debug)]
+pub struct Token {
+    /// What the token is
+    pub kind: TokenKind,
+    /// 1-based line number
+    pub line: usize,
+}
+
+/// Splits an XDR specification into tokens
+///
+/// # Parameters
+/// * `src` - Contents of a `.x` file
+///
+/// # Returns
+/// * `Result<Vec<Token>, String>` - The tokens or a message describing the first lexical error
+pub fn tokenize(src: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut line_has_token = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\n' {
+            line += 1;
+            line_has_token = false;
+            i += 1;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if c == '%' && !line_has_token {
+            let end = chars[i..].iter().position(|&c| c == '\n').map_or(chars.len(), |p| i + p);
+            let text: String = chars[i + 1..end].iter().collect();
+            tokens.push(Token { kind: TokenKind::Passthrough(text), line });
+            i = end;
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            let start_line = line;
+            let mut j = i + 2;
+            while j + 1 < chars.len() && !(chars[j] == '*' && chars[j + 1] == '/') {
+                if chars[j] == '\n' {
+                    line += 1;
+                }
+                j += 1;
+            }
+            if j + 1 >= chars.len() {
+                return Err(format!("line {start_line}: unterminated comment"));
+            }
+            let text: String = chars[i + 2..j].iter().collect();
+            tokens.push(Token {
+                kind: TokenKind::Comment { text, own_line: !line_has_token },
+                line: start_line,
+            });
+            i = j + 2;
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token { kind: TokenKind::Ident(chars[start..i].iter().collect()), line });
+            line_has_token = true;
+        } else if c.is_ascii_digit()
+            || (c == '-' && chars.get(i + 1).is_some_and(char::is_ascii_digit))
+        {
+            let start = i;
+            i += 1;
+            while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                i += 1;
+            }
+            tokens.push(Token { kind: TokenKind::Number(chars[start..i].iter().collect()), line });
+            line_has_token = true;
+        } else if "{}[]<>()=;,:*".contains(c) {
+            tokens.push(Token { kind: TokenKind::Punct(c), line });
+            line_has_token = true;
+            i += 1;
+        } else {
+            return Err(format!("line {line}: unexpected character {c:?}"));
+        }
+    }
+    Ok(tokens)
+}
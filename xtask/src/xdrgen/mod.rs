@@ -0,0 +1,75 @@
+//! Generation of the `protocol/xdr` modules from XDR language specifications.
+//!
+//! Specifications live in the `xdr/` directory of the repository as `.x` files,
+//! copied from the RFCs that define them. Each one listed in [`SPECS`] is turned
+//! into a Rust module by [`generate`]; `--check` verifies that the committed
+//! modules are up to date.
+
+mod codegen;
+mod lexer;
+mod parser;
+
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// A specification and the module generated from it
+pub struct Spec {
+    /// `.x` file, relative to the repository root
+    pub input: &'static str,
+    /// Generated Rust module, relative to the repository root
+    pub output: &'static str,
+    /// Name of the procedure enum of each program in the specification
+    pub program_enums: &'static [(&'static str, &'static str)],
+}
+
+/// Specifications known to `cargo xtask xdrgen`
+pub const SPECS: &[Spec] = &[Spec {
+    input: "xdr/portmap.x",
+    output: "src/protocol/xdr/portmap.rs",
+    program_enums: &[("PMAP_PROG", "PortmapProgram")],
+}];
+
+/// Generates the formatted Rust module for one specification
+///
+/// # Parameters
+/// * `root` - Repository root
+/// * `spec` - Specification to generate
+///
+/// # Returns
+/// * `Result<String, String>` - Formatted module source or an error message
+pub fn generate(root: &Path, spec: &Spec) -> Result<String, String> {
+    let src = std::fs::read_to_string(root.join(spec.input))
+        .map_err(|e| format!("{}: {e}", spec.input))?;
+    let tokens = lexer::tokenize(&src).map_err(|e| format!("{}: {e}", spec.input))?;
+    let items = parser::parse(tokens).map_err(|e| format!("{}: {e}", spec.input))?;
+    let options = codegen::Options { source: spec.input, program_enums: spec.program_enums };
+    let code = codegen::generate(&items, &options).map_err(|e| format!("{}: {e}", spec.input))?;
+    rustfmt(root, &code)
+}
+
+/// Formats generated code with the repository's rustfmt configuration
+fn rustfmt(root: &Path, code: &str) -> Result<String, String> {
+    let mut child = Command::new("rustfmt")
+        .args(["--edition", "2021", "--emit", "stdout"])
+        .current_dir(root)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run rustfmt: {e}"))?;
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(code.as_bytes())
+        .map_err(|e| format!("failed to write to rustfmt: {e}"))?;
+    let output = child.wait_with_output().map_err(|e| format!("rustfmt failed: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "rustfmt rejected the generated code:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    String::from_utf8(output.stdout).map_err(|e| format!("rustfmt output is not UTF-8: {e}"))
+}
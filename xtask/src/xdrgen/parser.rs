@@ -0,0 +1,542 @@
+//! Parser building a small syntax tree from the tokens of an XDR specification.
+//!
+//! Supports the complete XDR language of RFC 4506 section 6.3 except nested
+//! anonymous struct and union type specifiers, and the `program` definitions of
+//! RFC 5531 section 12.2. Comments directly before a definition, or after it on the
+//! same line, are kept as its documentation.
+
+use super::lexer::{Token, TokenKind};
+
+/// Base type of a declaration
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Type {
+    /// `int`
+    Int,
+    /// `unsigned int`
+    UInt,
+    /// `hyper`
+    Hyper,
+    /// `unsigned hyper`
+    UHyper,
+    /// `float`
+    Float,
+    /// `double`
+    Double,
+    /// `bool`
+    Bool,
+    /// Type defined by name
+    Named(String),
+}
+
+/// Shape of a declaration
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Shape {
+    /// `type name`
+    Scalar(Type),
+    /// `opaque name[size]`
+    FixedOpaque(String),
+    /// `opaque name<size>`
+    VarOpaque,
+    /// `string name<size>`
+    String,
+    /// `type name[size]`
+    FixedArray(Type, String),
+    /// `type name<size>`
+    VarArray(Type),
+    /// `type *name`
+    Optional(Type),
+}
+
+/// A named declaration: struct field, typedef or union arm
+#[derive(Clone, Debug)]
+pub struct Field {
+    /// Documentation lines
+    pub doc: Vec<String>,
+    /// Declared name
+    pub name: String,
+    /// Declared shape
+    pub shape: Shape,
+}
+
+/// Enumerator of an `enum` definition
+#[derive(Clone, Debug)]
+pub struct Variant {
+    /// Documentation lines
+    pub doc: Vec<String>,
+    /// Enumerator name
+    pub name: String,
+    /// Value as written: a number or a constant name
+    pub value: String,
+}
+
+/// One `case` of a union; several labels may share an arm
+#[derive(Clone, Debug)]
+pub struct Arm {
+    /// Documentation lines
+    pub doc: Vec<String>,
+    /// Case values as written
+    pub labels: Vec<String>,
+    /// Payload, `None` for `void`
+    pub field: Option<Field>,
+}
+
+/// Discriminated union
+#[derive(Clone, Debug)]
+pub struct Union {
+    /// Discriminant declaration
+    pub discriminant: Field,
+    /// Explicit cases
+    pub arms: Vec<Arm>,
+    /// `default` arm: `None` if absent, `Some(None)` for `default: void`
+    pub default: Option<Option<Field>>,
+}
+
+/// Remote procedure of a program version
+#[derive(Clone, Debug)]
+pub struct Procedure {
+    /// Documentation lines
+    pub doc: Vec<String>,
+    /// Procedure name
+    pub name: String,
+    /// Procedure number
+    pub value: String,
+}
+
+/// Version of an RPC program
+#[derive(Clone, Debug)]
+pub struct Version {
+    /// Documentation lines
+    pub doc: Vec<String>,
+    /// Version name
+    pub name: String,
+    /// Version number
+    pub value: String,
+    /// Procedures of the version
+    pub procedures: Vec<Procedure>,
+}
+
+/// RPC program definition
+#[derive(Clone, Debug)]
+pub struct Program {
+    /// Program name
+    pub name: String,
+    /// Program number
+    pub value: String,
+    /// Versions of the program
+    pub versions: Vec<Version>,
+}
+
+/// Top-level definition
+#[derive(Clone, Debug)]
+pub enum Definition {
+    /// `const NAME = value;`
+    Const { name: String, value: String },
+    /// `typedef declaration;`
+    Typedef(Field),
+    /// `enum NAME { ... };`
+    Enum { name: String, variants: Vec<Variant> },
+    /// `struct NAME { ... };`
+    Struct { name: String, fields: Vec<Field> },
+    /// `union NAME switch (...) { ... };`
+    Union { name: String, body: Union },
+    /// `program NAME { ... } = value;`
+    Program(Program),
+    /// `%` line
+    Passthrough(String),
+}
+
+/// A definition with its documentation
+#[derive(Clone, Debug)]
+pub struct Item {
+    /// Documentation lines
+    pub doc: Vec<String>,
+    /// The definition
+    pub def: Definition,
+}
+
+/// Parses a token stream into a list of definitions
+///
+/// # Parameters
+/// * `tokens` - Output of [`super::lexer::tokenize`]
+///
+/// # Returns
+/// * `Result<Vec<Item>, String>` - The definitions or a message describing the first syntax error
+pub fn parse(tokens: Vec<Token>) -> Result<Vec<Item>, String> {
+    let mut parser = Parser { tokens, pos: 0, pending: Vec::new() };
+    let mut items = Vec::new();
+    loop {
+        parser.skip_comments();
+        let Some(token) = parser.tokens.get(parser.pos).cloned() else {
+            break;
+        };
+        if let TokenKind::Passthrough(text) = token.kind {
+            parser.pos += 1;
+            parser.pending.clear();
+            items.push(Item { doc: Vec::new(), def: Definition::Passthrough(text) });
+            continue;
+        }
+        items.push(parser.definition()?);
+    }
+    Ok(items)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    pending: Vec<String>,
+}
+
+impl Parser {
+    fn skip_comments(&mut self) {
+        while let Some(Token { kind: TokenKind::Comment { text, .. }, .. }) =
+            self.tokens.get(self.pos)
+        {
+            self.pending.extend(comment_lines(text));
+            self.pos += 1;
+        }
+    }
+
+    fn take_doc(&mut self) -> Vec<String> {
+        self.skip_comments();
+        std::mem::take(&mut self.pending)
+    }
+
+    /// Attaches a comment on the same line as the token just consumed
+    fn trailing_doc(&mut self, doc: &mut Vec<String>, line: usize) {
+        if let Some(Token { kind: TokenKind::Comment { text, own_line: false }, line: l }) =
+            self.tokens.get(self.pos)
+        {
+            if *l == line {
+                if doc.is_empty() {
+                    *doc = comment_lines(text);
+                }
+                self.pos += 1;
+            }
+        }
+    }
+
+    fn peek(&mut self) -> Option<&TokenKind> {
+        self.skip_comments();
+        self.tokens.get(self.pos).map(|t| &t.kind)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens.get(self.pos).or(self.tokens.last()).map_or(0, |t| t.line)
+    }
+
+    fn error<T>(&self, msg: &str) -> Result<T, String> {
+        Err(format!("line {}: {msg}", self.line()))
+    }
+
+    fn next(&mut self) -> Result<TokenKind, String> {
+        self.skip_comments();
+        match self.tokens.get(self.pos) {
+            Some(t) => {
+                self.pos += 1;
+                Ok(t.kind.clone())
+            }
+            None => self.error("unexpected end of file"),
+        }
+    }
+
+    /// Consumes the expected punctuation and returns its line
+    fn expect(&mut self, c: char) -> Result<usize, String> {
+        self.skip_comments();
+        let line = self.line();
+        match self.next()? {
+            TokenKind::Punct(p) if p == c => Ok(line),
+            other => Err(format!("line {line}: expected '{c}', found {other:?}")),
+        }
+    }
+
+    fn is_punct(&mut self, c: char) -> bool {
+        matches!(self.peek(), Some(TokenKind::Punct(p)) if *p == c)
+    }
+
+    fn is_keyword(&mut self, kw: &str) -> bool {
+        matches!(self.peek(), Some(TokenKind::Ident(i)) if i == kw)
+    }
+
+    fn ident(&mut self) -> Result<String, String> {
+        match self.next()? {
+            TokenKind::Ident(i) => Ok(i),
+            other => self.error(&format!("expected identifier, found {other:?}")),
+        }
+    }
+
+    fn value(&mut self) -> Result<String, String> {
+        match self.next()? {
+            TokenKind::Ident(i) | TokenKind::Number(i) => Ok(i),
+            other => self.error(&format!("expected value, found {other:?}")),
+        }
+    }
+
+    fn definition(&mut self) -> Result<Item, String> {
+        let mut doc = self.take_doc();
+        let def = match self.ident()?.as_str() {
+            "const" => {
+                let name = self.ident()?;
+                self.expect('=')?;
+                let value = self.value()?;
+                let line = self.expect(';')?;
+                self.trailing_doc(&mut doc, line);
+                Definition::Const { name, value }
+            }
+            "typedef" => {
+                let field = self.declaration(Vec::new())?;
+                let line = self.expect(';')?;
+                self.trailing_doc(&mut doc, line);
+                Definition::Typedef(field)
+            }
+            "enum" => {
+                let name = self.ident()?;
+                let variants = self.enum_body()?;
+                self.expect(';')?;
+                Definition::Enum { name, variants }
+            }
+            "struct" => {
+                let name = self.ident()?;
+                let fields = self.struct_body()?;
+                self.expect(';')?;
+                Definition::Struct { name, fields }
+            }
+            "union" => {
+                let name = self.ident()?;
+                let body = self.union_body()?;
+                self.expect(';')?;
+                Definition::Union { name, body }
+            }
+            "program" => Definition::Program(self.program()?),
+            other => return self.error(&format!("unknown definition '{other}'")),
+        };
+        Ok(Item { doc, def })
+    }
+
+    fn enum_body(&mut self) -> Result<Vec<Variant>, String> {
+        self.expect('{')?;
+        let mut variants = Vec::new();
+        loop {
+            let mut doc = self.take_doc();
+            let name = self.ident()?;
+            self.expect('=')?;
+            let value = self.value()?;
+            let line = self.line();
+            let last = !self.is_punct(',');
+            if !last {
+                self.expect(',')?;
+            }
+            self.trailing_doc(&mut doc, line);
+            variants.push(Variant { doc, name, value });
+            if last {
+                break;
+            }
+        }
+        self.expect('}')?;
+        self.pending.clear();
+        Ok(variants)
+    }
+
+    fn struct_body(&mut self) -> Result<Vec<Field>, String> {
+        self.expect('{')?;
+        let mut fields = Vec::new();
+        while !self.is_punct('}') {
+            let doc = self.take_doc();
+            let mut field = self.declaration(doc)?;
+            let line = self.expect(';')?;
+            self.trailing_doc(&mut field.doc, line);
+            fields.push(field);
+        }
+        self.expect('}')?;
+        self.pending.clear();
+        Ok(fields)
+    }
+
+    fn union_body(&mut self) -> Result<Union, String> {
+        if self.ident()? != "switch" {
+            return self.error("expected 'switch'");
+        }
+        self.expect('(')?;
+        let discriminant = self.declaration(Vec::new())?;
+        self.expect(')')?;
+        self.expect('{')?;
+
+        let mut arms = Vec::new();
+        let mut default = None;
+        while !self.is_punct('}') {
+            let mut doc = self.take_doc();
+            if self.is_keyword("default") {
+                self.ident()?;
+                self.expect(':')?;
+                default = Some(self.arm_field(&mut doc)?);
+                continue;
+            }
+            let mut labels = Vec::new();
+            while self.is_keyword("case") {
+                self.ident()?;
+                labels.push(self.value()?);
+                self.expect(':')?;
+            }
+            if labels.is_empty() {
+                return self.error("expected 'case' or 'default'");
+            }
+            let field = self.arm_field(&mut doc)?;
+            arms.push(Arm { doc, labels, field });
+        }
+        self.expect('}')?;
+        self.pending.clear();
+        Ok(Union { discriminant, arms, default })
+    }
+
+    fn arm_field(&mut self, doc: &mut Vec<String>) -> Result<Option<Field>, String> {
+        let field = if self.is_keyword("void") {
+            self.ident()?;
+            None
+        } else {
+            Some(self.declaration(Vec::new())?)
+        };
+        let line = self.expect(';')?;
+        self.trailing_doc(doc, line);
+        Ok(field)
+    }
+
+    fn declaration(&mut self, doc: Vec<String>) -> Result<Field, String> {
+        let first = self.ident()?;
+        if first == "opaque" || first == "string" {
+            let name = self.ident()?;
+            let shape = if self.is_punct('[') && first == "opaque" {
+                self.expect('[')?;
+                let size = self.value()?;
+                self.expect(']')?;
+                Shape::FixedOpaque(size)
+            } else {
+                self.variable_size()?;
+                if first == "opaque" {
+                    Shape::VarOpaque
+                } else {
+                    Shape::String
+                }
+            };
+            return Ok(Field { doc, name, shape });
+        }
+
+        let ty = self.type_spec(first)?;
+        if self.is_punct('*') {
+            self.expect('*')?;
+            let name = self.ident()?;
+            return Ok(Field { doc, name, shape: Shape::Optional(ty) });
+        }
+        let name = self.ident()?;
+        let shape = if self.is_punct('[') {
+            self.expect('[')?;
+            let size = self.value()?;
+            self.expect(']')?;
+            Shape::FixedArray(ty, size)
+        } else if self.is_punct('<') {
+            self.variable_size()?;
+            Shape::VarArray(ty)
+        } else {
+            Shape::Scalar(ty)
+        };
+        Ok(Field { doc, name, shape })
+    }
+
+    fn variable_size(&mut self) -> Result<(), String> {
+        self.expect('<')?;
+        if !self.is_punct('>') {
+            self.value()?;
+        }
+        self.expect('>')?;
+        Ok(())
+    }
+
+    fn type_spec(&mut self, first: String) -> Result<Type, String> {
+        Ok(match first.as_str() {
+            "unsigned" => {
+                if self.is_keyword("hyper") {
+                    self.ident()?;
+                    Type::UHyper
+                } else {
+                    if self.is_keyword("int") {
+                        self.ident()?;
+                    }
+                    Type::UInt
+                }
+            }
+            "int" => Type::Int,
+            "hyper" => Type::Hyper,
+            "float" => Type::Float,
+            "double" => Type::Double,
+            "bool" => Type::Bool,
+            "enum" | "struct" | "union" => {
+                let name = self.ident()?;
+                if self.is_punct('{') {
+                    return self.error("anonymous type specifiers are not supported");
+                }
+                Type::Named(name)
+            }
+            _ => Type::Named(first),
+        })
+    }
+
+    fn program(&mut self) -> Result<Program, String> {
+        let name = self.ident()?;
+        self.expect('{')?;
+        let mut versions = Vec::new();
+        while !self.is_punct('}') {
+            let mut doc = self.take_doc();
+            if self.ident()? != "version" {
+                return self.error("expected 'version'");
+            }
+            let version_name = self.ident()?;
+            self.expect('{')?;
+            let mut procedures = Vec::new();
+            while !self.is_punct('}') {
+                let mut proc_doc = self.take_doc();
+                // result type, possibly `unsigned int`
+                let result = self.ident()?;
+                if result == "unsigned" && self.is_keyword("int") {
+                    self.ident()?;
+                }
+                let proc_name = self.ident()?;
+                self.expect('(')?;
+                while !self.is_punct(')') {
+                    self.next()?;
+                }
+                self.expect(')')?;
+                self.expect('=')?;
+                let value = self.value()?;
+                let line = self.expect(';')?;
+                self.trailing_doc(&mut proc_doc, line);
+                procedures.push(Procedure { doc: proc_doc, name: proc_name, value });
+            }
+            self.expect('}')?;
+            self.pending.clear();
+            self.expect('=')?;
+            let value = self.value()?;
+            let line = self.expect(';')?;
+            self.trailing_doc(&mut doc, line);
+            versions.push(Version { doc, name: version_name, value, procedures });
+        }
+        self.expect('}')?;
+        self.pending.clear();
+        self.expect('=')?;
+        let value = self.value()?;
+        self.expect(';')?;
+        Ok(Program { name, value, versions })
+    }
+}
+
+/// Splits a comment into documentation lines, dropping decoration
+fn comment_lines(text: &str) -> Vec<String> {
+    text.lines()
+        .map(|l| l.trim().trim_start_matches('*').trim().to_string())
+        .skip_while(String::is_empty)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .skip_while(String::is_empty)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .collect()
+}